@@ -0,0 +1,43 @@
+//! Times a `FrameDiff` comparing a frame where everything moves in full, then with the early
+//! exit, which stops after the first band of rows.
+//!
+//!     cargo bench --bench early_exit
+
+use std::{ hint::black_box, time::Instant };
+
+use motion_detect::{ diff::{ DiffStrategy, FrameDiff }, thumbnail::Thumbnail };
+
+// The moving frame: a 1080p frame downsampled by 1, compared this many times.
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+const ROUNDS: u32 = 20;
+
+
+// Milliseconds per frame over alternating frames, each moving entirely against the other.
+fn bench(mut strategy: FrameDiff, frames: &[Thumbnail; 2]) -> (f64, usize) {
+    strategy.process(&frames[0]);
+    let start = Instant::now();
+    let mut skipped = 0;
+    for round in 0 .. ROUNDS {
+        skipped = black_box(strategy.process(&frames[(round as usize + 1) % 2])).skipped_pixels;
+    }
+    (start.elapsed().as_secs_f64() * 1000.0 / ROUNDS as f64, skipped)
+}
+
+
+fn main() {
+    // A dark frame and a bright one, both with a little noise.
+    let mut frames = [Thumbnail::with_channels(WIDTH, HEIGHT, 3), Thumbnail::with_channels(WIDTH, HEIGHT, 3)];
+    for (frame, level) in frames.iter_mut().zip([40, 200]) {
+        for (index, value) in frame.pixels.iter_mut().enumerate() {
+            *value = level + (index * 7 % 8) as u8;
+        }
+    }
+    let update_count = (WIDTH * HEIGHT / 50) as i32;
+    let (full, _) = bench(FrameDiff::new(25, update_count), &frames);
+    let (early, skipped) = bench(FrameDiff::new(25, update_count).with_early_exit(), &frames);
+    println!(
+        "{WIDTH}x{HEIGHT} moving frame: {full:.2}ms compared in full, {early:.2}ms with the early exit, {:.0}% skipped, {:.1}x faster",
+        skipped as f64 * 100.0 / (WIDTH * HEIGHT) as f64, full / early.max(f64::EPSILON),
+    );
+}
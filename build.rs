@@ -0,0 +1,19 @@
+//! Records the git commit the binary is built from, reported by --version and GET /capabilities.
+//! Packagers building from a tarball can set MOTION_DETECT_GIT_COMMIT themselves.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=MOTION_DETECT_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let commit = std::env::var("MOTION_DETECT_GIT_COMMIT").ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| {
+            let output = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output().ok()?;
+            let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+            (output.status.success() && !commit.is_empty()).then_some(commit)
+        })
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=MOTION_DETECT_GIT_COMMIT={commit}");
+}
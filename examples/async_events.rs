@@ -0,0 +1,99 @@
+//! Embeds motion-detect in a tokio application: an `AsyncMotionDetector` runs detection on a
+//! synthetic source of its own thread, and the application `select!`s over its events, Ctrl-C and
+//! a deadline, pausing it, changing its thresholds and shutting it down in between.
+//!
+//!     cargo run --example async_events --features async
+//!
+//! A bar sweeps the gray scene for 10 frames in every 40, a frame every 5ms. The movements are
+//! printed for 5s or until Ctrl-C, the detector paused after the first and given a threshold the bar
+//! stays below after the second.
+
+use std::time::Duration;
+
+use motion_detect::{
+    async_detector::AsyncMotionDetector,
+    control::Setting,
+    motion::MotionEvent,
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::PixelLayout,
+};
+use tokio::{ signal, time };
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const BACKGROUND: u8 = 90;
+const PACE: Duration = Duration::from_millis(5);
+const PERIOD: u64 = 40;
+const MOVING: std::ops::Range<u64> = 20 .. 30;
+
+
+/// The scene, with the bar on the moving frames of each period.
+struct Sweeps {
+    frame: Vec<u8>,
+    index: u64,
+}
+
+
+impl FrameSource for Sweeps {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        std::thread::sleep(PACE);
+        self.frame.clear();
+        self.frame.resize(WIDTH * HEIGHT * 3, BACKGROUND);
+        if MOVING.contains(&(self.index % PERIOD)) {
+            let left = (self.index as usize * 4) % (WIDTH - 16);
+            for y in 0 .. HEIGHT {
+                self.frame[(y * WIDTH + left) * 3 .. (y * WIDTH + left + 16) * 3].fill(250);
+            }
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+}
+
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let settings = Settings {
+        frame_capture_interval: Duration::from_millis(100),
+        camera_warm_up: Duration::from_secs(1),
+        motion_tail_length: Duration::from_millis(500),
+        downsample: 4,
+        ..Settings::default()
+    };
+    let source = Sweeps { frame: Vec::new(), index: 0 };
+    let mut detector = AsyncMotionDetector::spawn(settings, WIDTH, HEIGHT, PixelLayout::Rgb, move || Ok(source));
+    let deadline = time::sleep(Duration::from_secs(5));
+    tokio::pin!(deadline);
+    let mut stops = 0;
+    loop {
+        tokio::select! {
+            event = detector.next_event() => match event {
+                Some(Ok(event)) => {
+                    println!("{event:?}");
+                    if !matches!(event, MotionEvent::Stop { .. }) {
+                        continue;
+                    }
+                    // Paused after the first movement, with a threshold above what the bar covers
+                    // after the second, a quarter of the image.
+                    stops += 1;
+                    match stops {
+                        1 => {
+                            println!("{:?}", detector.pause().await);
+                            time::sleep(Duration::from_millis(500)).await;
+                            println!("{:?}", detector.resume().await);
+                        }
+                        2 => println!("{:?}", detector.set_thresholds(vec![(Setting::ImageThreshold, 50.0)]).await),
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => println!("{err:?}"),
+                None => break,
+            },
+            _ = signal::ctrl_c() => break,
+            _ = &mut deadline => break,
+        }
+    }
+    detector.shutdown().await;
+}
@@ -0,0 +1,107 @@
+//! Implements `FrameSource` for a scene drawn on the fly: a ball that crosses an empty room twice,
+//! a minute apart. The detector follows it like any camera, two movements come out.
+//!
+//!     cargo run --example custom_source
+//!
+//! Time is the scene's own, one interval per frame, so it runs as fast as frames are drawn.
+
+use std::time::{ Duration, Instant };
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    motion::{ MotionEvent, MotionTracker },
+    source::FrameSource,
+    thumbnail::{ PixelLayout, Thumbnail },
+};
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+const INTERVAL: Duration = Duration::from_millis(100);
+const DOWNSAMPLE: usize = 8;
+const PIXEL_THRESHOLD: i32 = 25;
+const IMAGE_THRESHOLD: f32 = 0.02;
+const TAIL: Duration = Duration::from_secs(1);
+
+// The ball, in frame pixels and frame pixels per frame.
+const RADIUS: i64 = 32;
+const SPEED: i64 = 64;
+
+// Frames the ball crosses at, first and length, and when the scene ends.
+const CROSSINGS: [(u64, u64); 2] = [(50, 8), (650, 8)];
+const FRAMES: u64 = 800;
+
+
+/// A gray room with a lighter floor, and the ball while it crosses.
+struct BallScene {
+    frame: Vec<u8>,
+    index: u64,
+}
+
+
+impl BallScene {
+
+    fn new() -> Self {
+        Self { frame: vec![0; WIDTH * HEIGHT * 3], index: 0 }
+    }
+
+    // The ball's center, None while it's out of the room.
+    fn ball(&self) -> Option<(i64, i64)> {
+        CROSSINGS.iter()
+            .find(|(first, length)| (*first .. first + length).contains(&self.index))
+            .map(|(first, _)| ((self.index - first) as i64 * SPEED, HEIGHT as i64 / 2))
+    }
+}
+
+
+impl FrameSource for BallScene {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        if self.index == FRAMES {
+            return Ok(None);
+        }
+        let ball = self.ball();
+        for (index, pixel) in self.frame.chunks_exact_mut(3).enumerate() {
+            let (x, y) = ((index % WIDTH) as i64, (index / WIDTH) as i64);
+            let inside = ball.is_some_and(|(cx, cy)| (x - cx).pow(2) + (y - cy).pow(2) <= RADIUS.pow(2));
+            pixel.fill(match (inside, y > HEIGHT as i64 * 2 / 3) {
+                (true, _) => 230,
+                (false, true) => 120,
+                (false, false) => 80,
+            });
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+}
+
+
+fn main() {
+    let mut source = BallScene::new();
+    let mut thumb = Thumbnail::new(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE);
+    let start_count = (thumb.len() as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy = FrameDiff::new(PIXEL_THRESHOLD, start_count);
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let mut events = Vec::new();
+    let mut frames = 0;
+    while let Some(frame) = source.next_frame().expect("The scene never fails") {
+        thumb.downsample(frame, WIDTH, DOWNSAMPLE, PixelLayout::Rgb);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        events.extend(motion.update(result.changed_pixels, result.score, start + INTERVAL * frames));
+        frames += 1;
+    }
+    events.extend(motion.finish(start + INTERVAL * frames));
+
+    for event in &events {
+        match event {
+            MotionEvent::Start { id, at, .. } => println!("{} {id} at {:.1?}", event.text(), at.duration_since(start)),
+            MotionEvent::Stop { id, duration, .. } => println!("{} {id} after {duration:.1?}", event.text()),
+            _ => println!("{}", event.text()),
+        }
+    }
+    let starts = events.iter().filter(|event| matches!(event, MotionEvent::Start { .. })).count();
+    println!("{starts} movements in {frames} frames");
+}
@@ -0,0 +1,85 @@
+//! Polls the motion score from a host's own loop, as firmware or a game engine would: no tracker,
+//! no events, no threads, only a `DiffStrategy` handed a frame per tick, whose score the host acts
+//! on as it likes. Here the host is a grayscale sensor watching a door with some noise, lighting
+//! an indicator while the score is high.
+//!
+//!     cargo run --example embedded_no_events
+
+use std::ops::Range;
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    thumbnail::{ PixelLayout, Thumbnail },
+};
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 96;
+const DOWNSAMPLE: usize = 4;
+const PIXEL_THRESHOLD: i32 = 20;
+const TICKS: u32 = 300;
+
+// Ticks the door swings open at, and its width fully open, in pixels.
+const SWING: Range<u32> = 120 .. 150;
+const DOOR_WIDTH: usize = 48;
+
+// Score in percent above which the host lights its indicator.
+const INDICATOR: f32 = 2.0;
+const NOISE: u8 = 6;
+
+
+/// The host's sensor: a wall that's a little noisy, with the door opening in it.
+struct Sensor {
+    frame: Vec<u8>,
+    noise: u64,
+}
+
+
+impl Sensor {
+
+    fn capture(&mut self, tick: u32) -> &[u8] {
+        let open = match tick {
+            _ if tick < SWING.start => 0,
+            _ if tick >= SWING.end => DOOR_WIDTH,
+            _ => DOOR_WIDTH * (tick - SWING.start) as usize / SWING.len(),
+        };
+        for (index, pixel) in self.frame.iter_mut().enumerate() {
+            let (x, y) = (index % WIDTH, index / WIDTH);
+            let base = if x >= 40 && x < 40 + open && y >= 16 { 20 } else { 140 };
+            // xorshift64
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 7;
+            self.noise ^= self.noise << 17;
+            *pixel = base + (self.noise % NOISE as u64) as u8;
+        }
+        &self.frame
+    }
+}
+
+
+fn main() {
+    let mut sensor = Sensor { frame: vec![0; WIDTH * HEIGHT], noise: 0x2545f491 };
+    let mut thumb = Thumbnail::with_channels(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE, 1);
+    // Every frame becomes the reference, the score is the change from one tick to the next.
+    let mut strategy = FrameDiff::new(PIXEL_THRESHOLD, -1);
+
+    let mut indicator = false;
+    let (mut quiet_peak, mut swing_peak) = (0.0f32, 0.0f32);
+    for tick in 0 .. TICKS {
+        let frame = sensor.capture(tick);
+        thumb.downsample(frame, WIDTH, DOWNSAMPLE, PixelLayout::Gray);
+        let score = strategy.process(&thumb).score;
+
+        if indicator != (score > INDICATOR) {
+            indicator = score > INDICATOR;
+            println!("tick {tick}: score {score:.1}%, indicator {}", if indicator { "on" } else { "off" });
+        }
+        // The first frame after the swing still differs from the last one of it.
+        if SWING.contains(&tick) || tick == SWING.end {
+            swing_peak = swing_peak.max(score);
+        } else {
+            quiet_peak = quiet_peak.max(score);
+        }
+    }
+
+    println!("peak score {quiet_peak:.1}% while shut, {swing_peak:.1}% while swinging");
+}
@@ -0,0 +1,90 @@
+//! Runs detection over a folder of images, in the order of their names, as frames taken one
+//! interval apart, and prints the file each movement starts and stops at.
+//!
+//!     cargo run --example from_images -- frames/ 5
+//!
+//! The second argument is the frame rate the images were taken at, 5 by default. Images are
+//! binary PPM or PGM, as `--snapshot-dir` saves them, all of one size. Others convert with
+//! e.g. `ffmpeg -i frame%04d.png frame%04d.ppm`. Exits with 1 if the folder has none.
+
+use std::{
+    fs,
+    path::PathBuf,
+    process,
+    time::{ Duration, Instant },
+};
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    motion::{ MotionEvent, MotionTracker },
+    thumbnail::{ PixelLayout, Thumbnail },
+};
+
+const DOWNSAMPLE: usize = 8;
+const PIXEL_THRESHOLD: i32 = 25;
+const IMAGE_THRESHOLD: f32 = 0.2;
+const TAIL: Duration = Duration::from_secs(1);
+
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let directory = PathBuf::from(args.next().unwrap_or_else(|| fail("Usage: from_images <directory> [fps]")));
+    let fps: u32 = args.next().map_or(Ok(5), |fps| fps.parse()).unwrap_or_else(|_| fail("The frame rate is a whole number"));
+    let interval = Duration::from_secs(1) / fps.max(1);
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+        .unwrap_or_else(|err| fail(&format!("Can't read {}: {err}", directory.display())))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ppm" || extension == "pgm"))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        fail(&format!("No PPM or PGM images in {}", directory.display()));
+    }
+
+    // Set up from the first image, every other one must be alike.
+    let first = Thumbnail::read_pnm(&paths[0]).unwrap_or_else(|err| fail(&err));
+    let layout = if first.channels == 1 { PixelLayout::Gray } else { PixelLayout::Rgb };
+    let mut thumb = Thumbnail::with_channels(first.width / DOWNSAMPLE, first.height / DOWNSAMPLE, first.channels);
+    let start_count = (thumb.len() as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy = FrameDiff::new(PIXEL_THRESHOLD, start_count);
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(interval);
+
+    // Time is the images' own, one interval each, however fast they're read.
+    let start = Instant::now();
+    motion.ready(start);
+    let mut movements = 0;
+    for (index, path) in paths.iter().enumerate() {
+        let image = Thumbnail::read_pnm(path).unwrap_or_else(|err| fail(&err));
+        if (image.width, image.height, image.channels) != (first.width, first.height, first.channels) {
+            fail(&format!("{} isn't like {}", path.display(), paths[0].display()));
+        }
+        thumb.downsample(&image.pixels, image.width, DOWNSAMPLE, layout);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        let now = start + interval * index as u32;
+        for event in motion.update(result.changed_pixels, result.score, now) {
+            movements += matches!(event, MotionEvent::Start { .. }) as u32;
+            print_event(&event, path);
+        }
+    }
+    if let Some(event) = motion.finish(start + interval * paths.len() as u32) {
+        print_event(&event, paths.last().expect("There are images"));
+    }
+    println!("{movements} movements in {} images", paths.len());
+}
+
+
+fn print_event(event: &MotionEvent, path: &std::path::Path) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match event {
+        MotionEvent::Stop { duration, .. } => println!("{name}: {} after {duration:.1?}", event.text()),
+        _ => println!("{name}: {}", event.text()),
+    }
+}
+
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
@@ -0,0 +1,42 @@
+//! Lists the motion-detect instances announced on the LAN with --mdns, one line per instance with
+//! its address, port and TXT records.
+//!
+//!     cargo run --features mdns --example mdns_browse -- 3
+//!
+//! The argument is how many seconds to wait for answers. With `--loopback` it announces an instance
+//! of its own first and exits with 1 unless browsing finds it, which checks the announcer and the
+//! browser against each other without a detector running.
+
+use std::{ net::SocketAddr, time::Duration };
+
+use motion_detect::mdns::{ browse, Announcer, Service };
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let loopback = args.iter().any(|arg| arg == "--loopback");
+    let seconds = args.iter().find_map(|arg| arg.parse::<f64>().ok()).unwrap_or(2.0);
+
+    let announcer = loopback.then(|| {
+        let address: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        Announcer::start(Service::new("mdns_browse loopback", address, false, false)).unwrap_or_else(|err| {
+            eprintln!("Can't announce: {err}");
+            std::process::exit(1);
+        })
+    });
+    let found = browse(Duration::from_secs_f64(seconds)).unwrap_or_else(|err| {
+        eprintln!("Can't browse: {err}");
+        std::process::exit(1);
+    });
+    for instance in &found {
+        let address = instance.address.map_or(String::from("?"), |address| address.to_string());
+        println!("{} at {address}:{} ({}) {}", instance.instance, instance.port, instance.host, instance.txt.join(" "));
+    }
+    if let Some(announcer) = announcer {
+        announcer.withdraw();
+        let seen = found.iter().any(|instance| instance.instance == "mdns_browse loopback" && instance.port == 8080);
+        if !seen {
+            eprintln!("The loopback instance wasn't found");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Opens the first camera and prints its movements as JSON lines, the smallest program that does
+//! with the library what the binary does by default.
+//!
+//!     cargo run --example minimal_camera
+//!
+//! Frames are downsampled to thumbnails, compared by a `FrameDiff` and followed by a
+//! `MotionTracker`, which decides when a movement starts and stops. Stop with Ctrl+C.
+
+use std::{
+    process,
+    time::{ Duration, Instant, SystemTime },
+};
+use eye::hal::PlatformContext;
+
+use motion_detect::{
+    camera::{ self, Camera },
+    clock::TimeSource,
+    diff::{ DiffStrategy, FrameDiff },
+    motion::MotionTracker,
+    output::{ Format, Output },
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+const INTERVAL: Duration = Duration::from_millis(200);
+const WARM_UP: Duration = Duration::from_secs(2);
+const DOWNSAMPLE: usize = 8;
+const PIXEL_THRESHOLD: i32 = 25;    // Of 255, a pixel changing less is noise.
+const IMAGE_THRESHOLD: f32 = 0.2;   // Share of the thumbnail changing that starts a movement.
+const TAIL: Duration = Duration::from_secs(1);
+
+
+fn main() {
+    // Only tells which stream the camera settled on.
+    let output = Output::new(Format::Text);
+    let ctx = PlatformContext::default();
+    let mut camera = Camera::open(&ctx, WIDTH, HEIGHT, INTERVAL, &output).unwrap_or_else(|err| {
+        eprintln!("Can't open the camera: {err}");
+        process::exit(1);
+    });
+    let layout = camera::layout(&camera.descriptor.pixfmt, None);
+    let frame_width = camera.descriptor.width as usize;
+    let frame_height = camera.descriptor.height as usize;
+
+    let mut thumb = Thumbnail::with_channels(frame_width / DOWNSAMPLE, frame_height / DOWNSAMPLE, layout.channels());
+    let start_count = (thumb.len() as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy = FrameDiff::new(PIXEL_THRESHOLD, start_count);
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+
+    // Auto exposure needs a moment, movements seen from then on started then.
+    std::thread::sleep(WARM_UP);
+    motion.ready(Instant::now());
+    loop {
+        let frame = camera.next_frame().unwrap_or_else(|err| {
+            eprintln!("Capture failed: {err}");
+            process::exit(1);
+        });
+        let now = Instant::now();
+        thumb.downsample(frame, frame_width, DOWNSAMPLE, layout);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        for event in motion.update(result.changed_pixels, result.score, now) {
+            println!("{}", event.to_json(SystemTime::now(), TimeSource::Arrival));
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Runs the detection pipeline for days of virtual time in a few minutes, to catch what only shows
+//! up after a long run: counts drifting, buffers creeping, frames going missing.
+//!
+//!     cargo run --release --features soak-test --example soak_test -- --days 30
+//!
+//! Frames come from a synthetic source through `RawVideoSource`, timed by a virtual clock one
+//! capture interval per frame, so nothing waits. A script injects motion bursts, source outages
+//! with their reconnects, and threshold changes like `set` control commands, spaced 10 to 90
+//! minutes apart. At the end the invariants are checked: one movement per burst, no more, no less,
+//! and lasting as long as the burst plus the tail; activity reports adding up to the events;
+//! every capture slot numbered, the outages' accounted to capture errors; memory after the first
+//! hour growing by no more than --max-growth bytes. Exits with 1 if any doesn't hold.
+
+use std::{
+    cell::Cell,
+    io::{ self, Read },
+    process,
+    rc::Rc,
+    time::{ Duration, Instant, UNIX_EPOCH },
+};
+
+use motion_detect::{
+    activity::ActivitySummary,
+    camera,
+    control::{ ControlCommand, ControlState },
+    diff::{ self, DiffStrategy },
+    memory::{ self, MemoryUsage },
+    motion::{ MotionEvent, MotionTracker },
+    sequence::{ DropReason, FrameCounter },
+    settings::Settings,
+    source::{ FrameSource, RawFormat, RawVideoSource },
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 24;
+const DOWNSAMPLE: usize = 4;
+const BACKGROUND: u8 = 64;
+
+// Left edges of the burst's box, one per frame: it moves a quarter of the frame each time.
+const BOX_POSITIONS: [usize; 4] = [0, 8, 16, 8];
+const BOX_WIDTH: usize = 16;
+const BOX_HEIGHT: usize = 16;
+
+const OUTAGE: Duration = Duration::from_secs(20);
+const OUTAGE_EVERY: Duration = Duration::from_secs(6 * 60 * 60);
+const RELOAD_EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+const ACTIVITY_EVERY: Duration = Duration::from_secs(60 * 60);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Injection {
+    Burst,          // The box moves for the step's length.
+    Outage,         // No frames for the step's length, then the source delivers again.
+    Reload,         // Thresholds change, the detector starts over from a fresh baseline.
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    slot: u64,      // Capture slot it starts at.
+    length: u64,    // In slots.
+    injection: Injection,
+}
+
+
+/// Frames of a still background, with the box drawn where the current burst has it. The soak
+/// loop tells which frame comes next through `scene`.
+struct SyntheticSource {
+    scene: Rc<Cell<Option<usize>>>,     // Frame of the burst in progress.
+    frame: Vec<u8>,
+    position: usize,                    // Into the frame being read.
+}
+
+
+impl Read for SyntheticSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == 0 {
+            self.frame.fill(BACKGROUND);
+            if let Some(frame) = self.scene.get() {
+                let left = BOX_POSITIONS[frame % BOX_POSITIONS.len()];
+                for y in 0 .. BOX_HEIGHT {
+                    let row = (y * WIDTH + left) * 3;
+                    self.frame[row .. row + BOX_WIDTH * 3].fill(255);
+                }
+            }
+        }
+        let count = buf.len().min(self.frame.len() - self.position);
+        buf[.. count].copy_from_slice(&self.frame[self.position .. self.position + count]);
+        self.position = (self.position + count) % self.frame.len();
+        Ok(count)
+    }
+}
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+struct Options {
+    days: u64,
+    fps: u32,
+    max_growth: usize,
+}
+
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options { days: 30, fps: 5, max_growth: 4096 };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{arg} needs a value"))?;
+        let invalid = |_| format!("Invalid value '{value}' for {arg}");
+        match arg.as_str() {
+            "--days" => options.days = value.parse().map_err(invalid)?,
+            "--fps" => options.fps = value.parse().map_err(invalid)?,
+            "--max-growth" => options.max_growth = value.parse().map_err(invalid)?,
+            _ => return Err(format!("Unknown option '{arg}', use --days, --fps or --max-growth")),
+        }
+    }
+    if options.days == 0 || options.fps == 0 {
+        return Err("--days and --fps must be at least 1".to_string());
+    }
+    Ok(options)
+}
+
+
+// Bursts of 3 to 30 seconds, with an outage every 6 hours and a reload every day in their place.
+fn script(slots: u64, fps: u32, random: &mut Random) -> Vec<Step> {
+    let seconds = |duration: Duration| duration.as_secs() * fps as u64;
+    let mut steps = Vec::new();
+    let (mut next_outage, mut next_reload) = (seconds(OUTAGE_EVERY), seconds(RELOAD_EVERY));
+    let mut slot = seconds(Duration::from_secs(10 * 60));
+    while slot < slots {
+        let step = if slot >= next_reload {
+            next_reload += seconds(RELOAD_EVERY);
+            Step { slot, length: 0, injection: Injection::Reload }
+        } else if slot >= next_outage {
+            next_outage += seconds(OUTAGE_EVERY);
+            Step { slot, length: seconds(OUTAGE), injection: Injection::Outage }
+        } else {
+            Step { slot, length: (3 + random.below(28)) * fps as u64, injection: Injection::Burst }
+        };
+        steps.push(step);
+        slot += step.length + (10 + random.below(81)) * 60 * fps as u64;
+    }
+    // The run ends after a gap, never in the middle of a step.
+    steps.retain(|step| step.slot + step.length < slots);
+    steps
+}
+
+
+fn detector(settings: &Settings, control: &ControlState) -> (Box<dyn DiffStrategy>, i32, i32) {
+    let thumb_len = (WIDTH / DOWNSAMPLE * HEIGHT / DOWNSAMPLE) as f32;
+    let pixel_threshold = ((control.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let start_count = (thumb_len * (control.image_threshold / 100.0).clamp(0.0, 1.0)) as i32;
+    let sustain_count = (thumb_len * (control.sustain_percent() / 100.0).clamp(0.0, 1.0)) as i32;
+    let strategy = diff::from_name(&settings.algorithm, pixel_threshold, start_count, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("The default algorithm exists");
+    (strategy, start_count, sustain_count)
+}
+
+
+struct Check {
+    failed: bool,
+}
+
+
+impl Check {
+
+    fn expect(&mut self, ok: bool, what: &str, detail: String) {
+        println!("{} {what}: {detail}", if ok { "ok  " } else { "FAIL" });
+        self.failed |= !ok;
+    }
+}
+
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(2);
+    });
+    let settings = Settings::default();
+    let interval = Duration::from_secs(1) / options.fps;
+    let slots = options.days * 24 * 60 * 60 * options.fps as u64;
+    let steps = script(slots, options.fps, &mut Random(0x9e37_79b9_7f4a_7c15));
+    let bursts: Vec<&Step> = steps.iter().filter(|step| step.injection == Injection::Burst).collect();
+    let outage_slots: u64 = steps.iter().filter(|step| step.injection == Injection::Outage).map(|step| step.length).sum();
+    println!(
+        "Soaking {} days at {} fps: {slots} frames, {} bursts, {} outages, {} reloads",
+        options.days,
+        options.fps,
+        bursts.len(),
+        steps.iter().filter(|step| step.injection == Injection::Outage).count(),
+        steps.iter().filter(|step| step.injection == Injection::Reload).count(),
+    );
+
+    let scene = Rc::new(Cell::new(None));
+    let reader = SyntheticSource { scene: scene.clone(), frame: vec![0; RawFormat::Rgb24.frame_len(WIDTH, HEIGHT)], position: 0 };
+    let mut source = RawVideoSource::new(reader, RawFormat::Rgb24, WIDTH, HEIGHT);
+    let layout = camera::layout(&RawFormat::Rgb24.pixel_format(), None);
+    let mut thumb = Thumbnail::new(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE);
+    let mut control = ControlState::new(&settings);
+    let (mut strategy, start_count, sustain_count) = detector(&settings, &control);
+    let mut motion = MotionTracker::new(settings.motion_tail_length, start_count, sustain_count).with_frame_interval(interval);
+    let mut counter = FrameCounter::new(interval);
+
+    // Virtual time: slot n is captured n intervals after the start, whatever the wall clock says.
+    let start = Instant::now();
+    let slot_time = |slot: u64| start + interval.mul_f64(slot as f64);
+    let mut activity = ActivitySummary::new(ACTIVITY_EVERY, start, UNIX_EPOCH + Duration::from_secs(1_700_000_000), 0);
+    let (mut report_movements, mut report_stopped, mut report_frames) = (0, 0, 0);
+    motion.ready(start);
+
+    let (mut starts, mut stops, mut unexpected) = (0usize, 0usize, Vec::new());
+    let mut durations = Duration::ZERO;
+    let (mut baseline, mut peak): (Option<MemoryUsage>, MemoryUsage) = (None, MemoryUsage::default());
+    let (mut frames, mut last_capture) = (0u64, start);
+    let mut steps = steps.iter().peekable();
+    let mut burst: Option<(u64, u64)> = None;     // First slot and slots left.
+    let wall = Instant::now();
+    let mut slot = 0;
+    while slot < slots {
+        if let Some(step) = steps.next_if(|step| step.slot == slot) {
+            match step.injection {
+                Injection::Burst => burst = Some((slot, step.length)),
+                Injection::Outage => {
+                    counter.source_failed();
+                    slot += step.length;
+                    continue;
+                }
+                Injection::Reload => {
+                    let pixel_threshold = if control.pixel_threshold == settings.pixel_threshold { settings.pixel_threshold * 1.5 } else { settings.pixel_threshold };
+                    let command = ControlCommand::parse(&format!("set pixel_threshold={pixel_threshold}")).expect("The command is valid");
+                    if control.apply(&command) {
+                        let (start_count, sustain_count);
+                        (strategy, start_count, sustain_count) = detector(&settings, &control);
+                        motion.set_thresholds(start_count, sustain_count);
+                    }
+                }
+            }
+        }
+        scene.set(burst.filter(|(_, left)| *left > 0).map(|(first, _)| (slot - first) as usize));
+        if let Some((_, left)) = &mut burst {
+            *left = left.saturating_sub(1);
+        }
+
+        let now = slot_time(slot);
+        let frame = match source.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) | Err(_) => {
+                eprintln!("The synthetic source stopped at frame {frames}");
+                process::exit(1);
+            }
+        };
+        counter.captured(now);
+        if let Some((report, _)) = activity.due(now, counter.total_dropped()) {
+            (report_movements, report_stopped, report_frames) = (report_movements + report.movements, report_stopped + report.stopped, report_frames + report.frames);
+        }
+        activity.captured(now);
+        frames += 1;
+        last_capture = now;
+
+        thumb.downsample(frame, WIDTH, DOWNSAMPLE, layout);
+        let result = strategy.process(&thumb);
+        let (changed_pixels, score) = (result.changed_pixels, result.score);
+        activity.compared(score);
+        strategy.set_motion_active(motion.is_active());
+        for event in motion.update(changed_pixels, score, now) {
+            match event {
+                MotionEvent::Start { at, pre_existing: false, continued_from: None, .. } => {
+                    starts += 1;
+                    // Each start belongs to the burst that began with or before it.
+                    let expected = bursts.get(starts - 1).map(|step| slot_time(step.slot));
+                    if expected != Some(at) {
+                        unexpected.push(format!("start at {:.1?}, expected {}", at - start, expected.map_or("none".to_string(), |at| format!("{:.1?}", at - start))));
+                    }
+                }
+                MotionEvent::Stop { duration, .. } => {
+                    stops += 1;
+                    durations += duration;
+                }
+                event => unexpected.push(format!("{event:?}")),
+            }
+            activity.event(event);
+        }
+
+        let usage = MemoryUsage {
+            source: source.buffer_bytes(),
+            thumbnails: thumb.buffer_bytes(),
+            detector: strategy.buffer_bytes(),
+            ..MemoryUsage::default()
+        };
+        if usage.total() > peak.total() {
+            peak = usage;
+        }
+        if baseline.is_none() && now - start >= ACTIVITY_EVERY {
+            baseline = Some(peak);
+        }
+        slot += 1;
+    }
+    if let Some(event) = motion.finish(last_capture) {
+        unexpected.push(format!("{event:?} still in progress at the end"));
+    }
+    let (report, _) = activity.finish(counter.total_dropped());
+    (report_movements, report_stopped, report_frames) = (report_movements + report.movements, report_stopped + report.stopped, report_frames + report.frames);
+    println!("Took {:.1?} of wall time", wall.elapsed());
+
+    let mut check = Check { failed: false };
+    check.expect(starts == bursts.len() && stops == bursts.len(), "movements", format!("{starts} started and {stops} stopped for {} bursts", bursts.len()));
+    check.expect(unexpected.is_empty(), "unexpected events", match unexpected.first() {
+        Some(first) => format!("{}, the first {first}", unexpected.len()),
+        None => "none".to_string(),
+    });
+    // A movement lasts its burst and the tail, give or take a frame at either end.
+    let expected: Duration = bursts.iter().map(|step| interval * step.length as u32 + settings.motion_tail_length).sum();
+    let slack = interval * 2 * bursts.len() as u32;
+    check.expect(durations.abs_diff(expected) <= slack, "motion", format!("{durations:.1?}, expected {expected:.1?} within {slack:.1?}"));
+    check.expect(
+        (report_movements, report_stopped, report_frames) == (starts as u64, stops as u64, frames),
+        "activity reports",
+        format!("{report_movements} movements, {report_stopped} stopped, {report_frames} frames"),
+    );
+    // Numbered slots include those of the outages, within 0.1%.
+    let sequence = counter.last_sequence();
+    check.expect(sequence.abs_diff(slots) <= slots / 1000, "frame count", format!("{sequence} numbered, {slots} scheduled, {frames} captured"));
+    let (capture_errors, overload) = (counter.dropped(DropReason::CaptureError), counter.dropped(DropReason::Overload));
+    check.expect(
+        capture_errors.abs_diff(outage_slots) <= outage_slots / 1000 + 1 && overload == 0,
+        "drops",
+        format!("{capture_errors} capture errors for {outage_slots} outage slots, {overload} overload"),
+    );
+    let baseline = baseline.unwrap_or(peak);
+    let growth = peak.total().saturating_sub(baseline.total());
+    check.expect(growth <= options.max_growth, "memory", format!(
+        "{} after the first hour, peak {} ({}), grew {}", memory::format_bytes(baseline.total()), memory::format_bytes(peak.total()), peak.text(), memory::format_bytes(growth)
+    ));
+    if check.failed {
+        process::exit(1);
+    }
+}
+
@@ -0,0 +1,74 @@
+//! Embeds motion-detect the simple way: a `MotionDetector` reading a synthetic source, blocked in
+//! `wait_for_motion` until a bar sweeps across the gray scene, and in `wait_for_still` until it
+//! has been gone for a while.
+//!
+//!     cargo run --example wait_for_motion
+//!
+//! A thread cancels the last wait, on a scene where nothing moves any more.
+
+use std::{ thread, time::Duration };
+
+use motion_detect::{
+    detector::MotionDetector,
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::PixelLayout,
+};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const BACKGROUND: u8 = 90;
+
+
+/// Frames of the gray scene, with a bright bar somewhere else on each of frames 30 to 40.
+struct Script {
+    frame: Vec<u8>,
+    index: u64,
+}
+
+
+impl FrameSource for Script {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        self.frame.clear();
+        self.frame.resize(WIDTH * HEIGHT * 3, BACKGROUND);
+        if (30 .. 40).contains(&self.index) {
+            let left = (self.index as usize * 16) % WIDTH;
+            for y in 0 .. HEIGHT {
+                self.frame[(y * WIDTH + left) * 3 .. (y * WIDTH + (left + 16).min(WIDTH)) * 3].fill(250);
+            }
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+}
+
+
+fn main() {
+    let settings = Settings {
+        frame_capture_interval: Duration::from_millis(100),
+        camera_warm_up: Duration::from_secs(1),
+        motion_tail_length: Duration::from_millis(500),
+        confirm_frames: 3,
+        downsample: 4,
+        ..Settings::default()
+    };
+    let mut source = Script { frame: Vec::new(), index: 0 };
+    let mut detector = MotionDetector::new(&settings, WIDTH, HEIGHT, PixelLayout::Rgb);
+
+    match detector.wait_for_motion(&mut source, None) {
+        Ok(Some(event)) => println!("{event:?} after {} frames", detector.frames()),
+        result => println!("no movement: {result:?}"),
+    }
+    match detector.wait_for_still(&mut source, Duration::from_secs(2)) {
+        Ok(()) => println!("still after {} frames", detector.frames()),
+        Err(error) => println!("{error:?}"),
+    }
+
+    let token = detector.cancel_token();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        token.cancel();
+    });
+    println!("{:?}", detector.wait_for_motion(&mut source, None));
+}
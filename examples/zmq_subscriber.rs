@@ -0,0 +1,32 @@
+//! Prints what motion-detect publishes with --zmq-pub, one "topic payload" line per message.
+//!
+//!     cargo run --features zmq --example zmq_subscriber -- tcp://localhost:5556 motion.garden.start
+//!
+//! The second argument is the topic prefix to subscribe to, every message without it. Any ZeroMQ
+//! SUB socket works the same, e.g. pyzmq's `socket.setsockopt_string(zmq.SUBSCRIBE, "motion.")`.
+
+use motion_detect::zmq::Subscriber;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let endpoint = args.next().unwrap_or_else(|| String::from("tcp://localhost:5556"));
+    let prefix = args.next().unwrap_or_default();
+
+    let mut subscriber = Subscriber::connect(&endpoint).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    if let Err(err) = subscriber.subscribe(&prefix) {
+        eprintln!("Can't subscribe: {err}");
+        std::process::exit(1);
+    }
+    loop {
+        match subscriber.receive() {
+            Ok((topic, payload)) => println!("{topic} {payload}"),
+            Err(err) => {
+                eprintln!("Connection lost: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
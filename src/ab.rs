@@ -0,0 +1,129 @@
+use std::time::{ Instant, SystemTime };
+
+use crate::{ json, motion::MotionEvent };
+
+/// Which detector of an A/B comparison an event comes from. A is the primary one, configured by
+/// the command line, B the one from --ab-config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    A,
+    B,
+}
+
+
+impl Variant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::A => "a",
+            Variant::B => "b",
+        }
+    }
+}
+
+
+/// Movements of one variant that the other variant may still overlap.
+#[derive(Default)]
+struct Movements {
+    active: Option<Instant>,        // Start of the movement in progress.
+    closed: Vec<(Instant, Instant)>,// Start and end of those the other variant's active one may overlap.
+    overlapping: u64,               // Since the last report.
+    alone: u64,
+}
+
+
+/// Tells how often both variants agree. Every movement is classified when it stops: it
+/// overlapped a movement of the other variant, or it was alone.
+#[derive(Default)]
+pub struct Agreement {
+    a: Movements,
+    b: Movements,
+}
+
+
+impl Agreement {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follows the movements of a variant through its events.
+    pub fn record(&mut self, variant: Variant, event: MotionEvent) {
+        match event {
+            MotionEvent::Start { at, .. } => self.movements(variant).0.active = Some(at),
+            MotionEvent::Stop { at, .. } => self.stopped(variant, at),
+            MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+        }
+    }
+
+    fn stopped(&mut self, variant: Variant, at: Instant) {
+        let (this, other) = self.movements(variant);
+        let Some(start) = this.active.take() else {
+            return;
+        };
+        let overlaps = other.active.is_some_and(|other_start| other_start <= at)
+            || other.closed.iter().any(|(other_start, other_end)| *other_start <= at && *other_end >= start);
+        if overlaps {
+            this.overlapping += 1;
+        } else {
+            this.alone += 1;
+        }
+        // Only the other variant's active movement can still overlap this one, and nothing of
+        // this variant is in progress to overlap the other's closed ones.
+        if other.active.is_some_and(|other_start| other_start <= at) {
+            this.closed.push((start, at));
+        }
+        other.closed.clear();
+    }
+
+    /// Counts since the last report, which start over.
+    pub fn report(&mut self) -> AgreementReport {
+        let report = AgreementReport {
+            both: self.a.overlapping,
+            only_a: self.a.alone,
+            only_b: self.b.alone,
+            b_overlapping: self.b.overlapping,
+        };
+        for movements in [&mut self.a, &mut self.b] {
+            movements.overlapping = 0;
+            movements.alone = 0;
+        }
+        report
+    }
+
+    fn movements(&mut self, variant: Variant) -> (&mut Movements, &mut Movements) {
+        match variant {
+            Variant::A => (&mut self.a, &mut self.b),
+            Variant::B => (&mut self.b, &mut self.a),
+        }
+    }
+}
+
+
+/// Movements that stopped since the previous report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgreementReport {
+    pub both: u64,          // Movements of A overlapping one of B.
+    pub only_a: u64,
+    pub only_b: u64,
+    pub b_overlapping: u64, // Movements of B overlapping one of A, differs from `both` when one spans several.
+}
+
+
+impl AgreementReport {
+
+    pub fn to_json(&self, time: SystemTime) -> String {
+        json::Object::new()
+            .field("type", "ab_agreement")
+            .field("both", self.both)
+            .field("only_a", self.only_a)
+            .field("only_b", self.only_b)
+            .field("b_overlapping", self.b_overlapping)
+            .field("time", json::unix_time(time))
+            .finish()
+    }
+
+    /// The line printed in text mode.
+    pub fn text(&self) -> String {
+        format!("ab agreement: {} in both, {} only in a, {} only in b", self.both, self.only_a, self.only_b)
+    }
+}
@@ -0,0 +1,124 @@
+use std::net::TcpListener;
+
+// The first descriptor passed by systemd, see sd_listen_fds(3).
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: i32 = 3;
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    use std::os::raw::{ c_int, c_void };
+
+    pub const F_SETFD: c_int = 2;
+    pub const FD_CLOEXEC: c_int = 1;
+    pub const SOL_SOCKET: c_int = 1;
+    pub const SO_TYPE: c_int = 3;
+    pub const SO_ACCEPTCONN: c_int = 30;
+    pub const SOCK_STREAM: c_int = 1;
+    pub const AF_INET: u16 = 2;
+    pub const AF_INET6: u16 = 10;
+
+    extern "C" {
+        pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+        pub fn getsockopt(fd: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32) -> c_int;
+        pub fn getsockname(fd: c_int, address: *mut c_void, len: *mut u32) -> c_int;
+    }
+}
+
+
+/// Listening sockets handed over by systemd socket activation (LISTEN_FDS and LISTEN_FDNAMES),
+/// so the service can be started on the first connection and restarted without closing them.
+#[derive(Debug, Default)]
+pub struct ListenFds {
+    fds: Vec<(String, i32)>,    // Names and descriptors not claimed yet.
+}
+
+
+impl ListenFds {
+
+    /// Takes the sockets passed to this process, none if it wasn't socket activated. The variables
+    /// are removed, and the descriptors marked close-on-exec, so ffmpeg doesn't inherit them.
+    pub fn take() -> Self {
+        Self { fds: passed_fds() }
+    }
+
+    /// Names of the sockets nobody claimed.
+    pub fn unclaimed(&self) -> Vec<String> {
+        self.fds.iter().map(|(name, fd)| format!("{name} (fd {fd})")).collect()
+    }
+
+    /// Adopts the TCP listener called `name`, or the only socket if it's unnamed ("unknown", as
+    /// systemd calls sockets without FileDescriptorName=). Fails if that descriptor isn't a
+    /// listening TCP socket.
+    pub fn tcp_listener(&mut self, name: &str) -> Result<Option<TcpListener>, String> {
+        let index = match self.fds.iter().position(|(fd_name, _)| fd_name == name) {
+            Some(index) => index,
+            None if self.fds.len() == 1 && self.fds[0].0 == "unknown" => 0,
+            None => return Ok(None),
+        };
+        let (fd_name, fd) = self.fds.remove(index);
+        adopt_tcp_listener(fd).map(Some).map_err(|err| format!("passed socket '{fd_name}' (fd {fd}) {err}"))
+    }
+}
+
+
+#[cfg(target_os = "linux")]
+fn passed_fds() -> Vec<(String, i32)> {
+    let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let count = std::env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<i32>().ok());
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    // Variables meant for another process, e.g. inherited from a socket activated shell.
+    let (Some(count), true) = (count, pid == Some(std::process::id())) else {
+        return Vec::new();
+    };
+    let mut names = names.split(':').filter(|name| !name.is_empty());
+    (LISTEN_FDS_START .. LISTEN_FDS_START + count.max(0)).map(|fd| {
+        unsafe { ffi::fcntl(fd, ffi::F_SETFD, ffi::FD_CLOEXEC); }
+        (names.next().unwrap_or("unknown").to_string(), fd)
+    }).collect()
+}
+
+
+#[cfg(not(target_os = "linux"))]
+fn passed_fds() -> Vec<(String, i32)> {
+    Vec::new()
+}
+
+
+#[cfg(target_os = "linux")]
+fn adopt_tcp_listener(fd: i32) -> Result<TcpListener, String> {
+    use std::{ io, os::unix::io::FromRawFd };
+
+    let option = |name| {
+        let (mut value, mut len) = (0i32, std::mem::size_of::<i32>() as u32);
+        match unsafe { ffi::getsockopt(fd, ffi::SOL_SOCKET, name, &mut value as *mut i32 as *mut _, &mut len) } {
+            0 => Ok(value),
+            _ => Err(io::Error::last_os_error()),
+        }
+    };
+    let socket_type = option(ffi::SO_TYPE).map_err(|err| format!("isn't a socket: {err}"))?;
+    if socket_type != ffi::SOCK_STREAM || option(ffi::SO_ACCEPTCONN).unwrap_or(0) == 0 {
+        return Err("isn't a listening stream socket".to_string());
+    }
+    // Large enough for any sockaddr, the family comes first.
+    let (mut address, mut len) = ([0u16; 64], 128u32);
+    if unsafe { ffi::getsockname(fd, address.as_mut_ptr() as *mut _, &mut len) } != 0 {
+        return Err(format!("has no address: {}", io::Error::last_os_error()));
+    }
+    if !matches!(address[0], ffi::AF_INET | ffi::AF_INET6) {
+        return Err("isn't a TCP socket, use ListenStream=<port> or <address:port>".to_string());
+    }
+    // From here on the listener owns the descriptor. systemd may have made it non blocking,
+    // the accept loop expects to wait.
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(false).map_err(|err| format!("can't be made blocking: {err}"))?;
+    Ok(listener)
+}
+
+
+#[cfg(not(target_os = "linux"))]
+fn adopt_tcp_listener(_fd: i32) -> Result<TcpListener, String> {
+    Err("can't be adopted on this platform".to_string())
+}
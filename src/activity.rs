@@ -0,0 +1,365 @@
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use crate::{
+    clock::LocalTime,
+    json,
+    motion::MotionEvent,
+    output::Format,
+    overlay::utc_timestamp,
+    settings::Settings,
+    state::SavedState,
+};
+
+const WEEKDAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+const EXIT_FAILED: i32 = 5;         // I/O error
+
+/// What happened during one --print-interval period.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActivityReport {
+    pub period: Duration,       // Shorter than the interval for the first and last ones.
+    pub movements: u64,         // Starts, continuations of split movements included.
+    pub stopped: u64,
+    pub motion: Duration,       // Summed durations of the movements that stopped.
+    pub peak: f32,              // Highest percentage of changed pixels of any compared frame.
+    pub frames: u64,            // Captured frames.
+    pub dropped: u64,           // Frames that never made it to the detector, see sequence::DropReason.
+}
+
+
+impl ActivityReport {
+
+    pub fn to_json(&self, time: SystemTime) -> String {
+        json::Object::new()
+            .field("type", "activity")
+            .field("period", self.period.as_secs_f64())
+            .field("movements", self.movements)
+            .field("stopped", self.stopped)
+            .field("motion", self.motion.as_secs_f64())
+            .field("peak", self.peak)
+            .field("frames", self.frames)
+            .field("dropped", self.dropped)
+            .field("time", json::unix_time(time))
+            .finish()
+    }
+
+    /// The line printed in text mode.
+    pub fn text(&self) -> String {
+        format!(
+            "activity over {:.1}s: {} movements, {} stopped, {:.3}s of motion, peak {:.1}%, {} frames, {} dropped",
+            self.period.as_secs_f64(), self.movements, self.stopped, self.motion.as_secs_f64(), self.peak, self.frames, self.dropped
+        )
+    }
+}
+
+
+/// Sums up activity every --print-interval, on the detector's frame clock. Periods end on wall
+/// clock multiples of the interval, so the lines of several cameras line up, and the first one
+/// covers the partial period since the detector became ready.
+///
+/// Events count in the period whose report follows them in the output, so adding up the start and
+/// stop lines printed between two reports gives that report's numbers exactly. The motion of a
+/// movement counts once, when it stops.
+pub struct ActivitySummary {
+    every: Duration,
+    period_start: Instant,
+    period_end: Instant,
+    last_capture: Instant,
+    dropped_before: u64,        // Drops counted before this period.
+    current: ActivityReport,
+}
+
+
+impl ActivitySummary {
+
+    /// Starts the first period at `ready`, which is `ready_time` on the wall clock.
+    pub fn new(every: Duration, ready: Instant, ready_time: SystemTime, dropped: u64) -> Self {
+        let since_epoch = ready_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let into_period = Duration::from_nanos((since_epoch % every.as_nanos().max(1)) as u64);
+        Self {
+            every,
+            period_start: ready,
+            period_end: ready + (every - into_period),
+            last_capture: ready,
+            dropped_before: dropped,
+            current: Self::empty(),
+        }
+    }
+
+    fn empty() -> ActivityReport {
+        ActivityReport {
+            period: Duration::ZERO,
+            movements: 0,
+            stopped: 0,
+            motion: Duration::ZERO,
+            peak: 0.0,
+            frames: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Ends the period once a frame is captured at or past its end, before that frame counts.
+    /// Periods without a single frame, e.g. while the camera was lost, are folded into this one.
+    /// Returns the report and the time its period ended.
+    pub fn due(&mut self, captured_at: Instant, dropped: u64) -> Option<(ActivityReport, Instant)> {
+        if captured_at < self.period_end {
+            return None;
+        }
+        let missed = (captured_at - self.period_end).as_nanos() / self.every.as_nanos().max(1);
+        let end = self.period_end + self.every * missed as u32;
+        let report = self.close(end, dropped);
+        self.period_end = end + self.every;
+        Some((report, end))
+    }
+
+    /// The partial period up to the last captured frame, on shutdown, and the time it ended.
+    pub fn finish(&mut self, dropped: u64) -> (ActivityReport, Instant) {
+        let end = self.last_capture.max(self.period_start);
+        (self.close(end, dropped), end)
+    }
+
+    fn close(&mut self, end: Instant, dropped: u64) -> ActivityReport {
+        let mut report = std::mem::replace(&mut self.current, Self::empty());
+        report.period = end.saturating_duration_since(self.period_start);
+        report.dropped = dropped - self.dropped_before;
+        self.period_start = end;
+        self.dropped_before = dropped;
+        report
+    }
+
+    pub fn captured(&mut self, at: Instant) {
+        self.current.frames += 1;
+        self.last_capture = at;
+    }
+
+    pub fn compared(&mut self, score: f32) {
+        self.current.peak = self.current.peak.max(score);
+    }
+
+    pub fn event(&mut self, event: MotionEvent) {
+        match event {
+            MotionEvent::Start { .. } => self.current.movements += 1,
+            MotionEvent::Stop { duration, .. } => {
+                // To the millisecond, as stop events print it, so the sums add up.
+                self.current.stopped += 1;
+                self.current.motion += Duration::from_millis((duration.as_secs_f64() * 1000.0).round() as u64);
+            }
+            MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+        }
+    }
+}
+
+
+/// Seconds of motion per weekday and hour, summed over the movements that stopped since it was
+/// started or reset, to tell when the scene is actually in use. Kept in --state-file over restarts.
+///
+/// Hours are those of the local wall clock, see clock::LocalTime, so a movement is split over the
+/// hours it spans in proportion to the time it spent in each. When daylight saving time ends, the
+/// hour that repeats gets both, and the hour skipped when it begins gets nothing: every second of
+/// motion counts exactly once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivityHistogram {
+    pub seconds: [[f64; 24]; 7],    // Monday first.
+    pub since: SystemTime,          // When it was started or reset.
+}
+
+
+impl ActivityHistogram {
+
+    pub fn new(since: SystemTime) -> Self {
+        Self { seconds: [[0.0; 24]; 7], since }
+    }
+
+    /// Adds a movement that started at `start` and lasted `duration`.
+    pub fn add(&mut self, start: SystemTime, duration: Duration) {
+        let (mut start, mut left) = (start, duration);
+        while !left.is_zero() {
+            let local = LocalTime::from(start);
+            let subsecond = start.duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+            let into_hour = Duration::new((local.minute * 60 + local.second) as u64, subsecond);
+            // A leap second is past the end of its hour, it counts in the hour it's in all the same.
+            let in_hour = Duration::from_secs(3600).checked_sub(into_hour).filter(|in_hour| !in_hour.is_zero())
+                .unwrap_or(Duration::from_secs(1))
+                .min(left);
+            self.seconds[local.weekday as usize][local.hour as usize] += in_hour.as_secs_f64();
+            start += in_hour;
+            left -= in_hour;
+        }
+    }
+
+    /// Starts over from nothing at `now`.
+    pub fn reset(&mut self, now: SystemTime) {
+        *self = Self::new(now);
+    }
+
+    pub fn total(&self) -> f64 {
+        self.seconds.iter().flatten().sum()
+    }
+
+    pub fn to_json(&self, time: SystemTime) -> String {
+        json::Object::new()
+            .field("type", "activity_histogram")
+            .field("since", json::unix_time(self.since))
+            .field("utc_offset", LocalTime::from(time).utc_offset as f64 / 3600.0)
+            .field("weekdays", WEEKDAYS.to_vec())
+            .field("seconds", self.seconds.iter().map(|hours| hours.to_vec()).collect::<Vec<_>>())
+            .field("total", self.total())
+            .field("time", json::unix_time(time))
+            .finish()
+    }
+
+    /// The histogram as a table of weekdays by hours, each hour a digit from 1 for the least motion
+    /// to 9 for as much as the busiest hour had, or a dot without any, and the total of each day.
+    pub fn table(&self, time: SystemTime) -> String {
+        let offset = LocalTime::from(time).utc_offset;
+        let mut text = format!(
+            "Motion per weekday and hour, local time (UTC{}{:02}:{:02}), since {}\n",
+            if offset < 0 { '-' } else { '+' }, offset.abs() / 3600, offset.abs() / 60 % 60, utc_timestamp(self.since)
+        );
+        let busiest = self.seconds.iter().enumerate()
+            .flat_map(|(weekday, hours)| hours.iter().enumerate().map(move |(hour, seconds)| (weekday, hour, *seconds)))
+            .fold((0, 0, 0.0), |busiest, hour| if hour.2 > busiest.2 { hour } else { busiest });
+        if busiest.2 == 0.0 {
+            text += "No motion yet\n";
+            return text;
+        }
+        text += &format!("The busiest hour, {} {:02}:00, had {}\n\n", capitalized(WEEKDAYS[busiest.0]), busiest.1, duration_text(busiest.2));
+        text += "    ";
+        for hour in 0 .. 24 {
+            text += &format!(" {hour:02}");
+        }
+        text += "      total\n";
+        for (weekday, hours) in self.seconds.iter().enumerate() {
+            text += &capitalized(WEEKDAYS[weekday])[.. 3];
+            text += " ";
+            for seconds in hours {
+                let level = (seconds / busiest.2 * 9.0).ceil().clamp(1.0, 9.0);
+                text += &if *seconds > 0.0 { format!("  {level}") } else { String::from("  .") };
+            }
+            text += &format!(" {:>10}\n", duration_text(hours.iter().sum()));
+        }
+        text += &format!("All {:>83}\n", duration_text(self.total()));
+        text
+    }
+}
+
+
+fn capitalized(weekday: &str) -> String {
+    weekday[.. 1].to_uppercase() + &weekday[1 ..]
+}
+
+
+// As hours and minutes, or minutes and seconds below an hour, or seconds below a minute.
+fn duration_text(seconds: f64) -> String {
+    match seconds.round() as u64 {
+        0 .. 60 => format!("{seconds:.1}s"),
+        whole @ 60 .. 3600 => format!("{}m{:02}s", whole / 60, whole % 60),
+        whole => format!("{}h{:02}m", whole / 3600, whole / 60 % 60),
+    }
+}
+
+
+/// Prints the activity histogram saved in --state-file, as a table or, with --format json, as the
+/// object GET /activity serves. Returns the process exit code.
+pub fn run(settings: &Settings) -> i32 {
+    let path = settings.state_file.as_deref().expect("Activity settings have a state file");
+    let state = match SavedState::load(path) {
+        Ok(state) => state,
+        Err(err) => {
+            println!("\nError, {err}");
+            return EXIT_FAILED;
+        }
+    };
+    match settings.format {
+        Format::Text => print!("{}", state.activity.table(SystemTime::now())),
+        Format::Json => println!("{}", state.activity.to_json(SystemTime::now())),
+    }
+    0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion::{ MotionStats, StopReason };
+
+    const EVERY: Duration = Duration::from_secs(10);
+
+
+    // Ready 3s into a period of the wall clock, which ends 7s later.
+    fn summary(ready: Instant) -> ActivitySummary {
+        ActivitySummary::new(EVERY, ready, UNIX_EPOCH + Duration::from_secs(1_003), 0)
+    }
+
+
+    fn stop(duration: Duration, at: Instant) -> MotionEvent {
+        MotionEvent::Stop { id: 1, reason: StopReason::Tail, duration, at, stats: MotionStats::default(), between: None }
+    }
+
+
+    #[test]
+    fn the_first_period_ends_on_a_multiple_of_the_interval() {
+        let ready = Instant::now();
+        let mut summary = summary(ready);
+        summary.captured(ready + Duration::from_secs(1));
+        assert_eq!(summary.due(ready + Duration::from_secs(6), 0), None);
+        let (report, end) = summary.due(ready + Duration::from_secs(7), 0).unwrap();
+        assert_eq!((report.period, report.frames, end), (Duration::from_secs(7), 1, ready + Duration::from_secs(7)));
+        // The frame that ended it counts in the next one, which is a whole interval.
+        summary.captured(ready + Duration::from_secs(7));
+        let (report, _) = summary.due(ready + Duration::from_secs(17), 0).unwrap();
+        assert_eq!((report.period, report.frames), (EVERY, 1));
+    }
+
+
+    #[test]
+    fn periods_without_a_frame_are_folded_into_one() {
+        let ready = Instant::now();
+        let mut summary = summary(ready);
+        summary.due(ready + Duration::from_secs(7), 0).unwrap();
+        summary.captured(ready + Duration::from_secs(8));
+        // The camera was lost from 8s to 45s, with 4 frames dropped: the periods ending at 17s,
+        // 27s and 37s are reported as one, ending at 37s.
+        let (report, end) = summary.due(ready + Duration::from_secs(45), 4).unwrap();
+        assert_eq!(end, ready + Duration::from_secs(37));
+        assert_eq!((report.period, report.frames, report.dropped), (Duration::from_secs(30), 1, 4));
+        // The next one ends on the interval after.
+        assert_eq!(summary.due(ready + Duration::from_secs(46), 4), None);
+        assert!(summary.due(ready + Duration::from_secs(47), 4).is_some());
+    }
+
+
+    #[test]
+    fn finishing_reports_the_partial_period_up_to_the_last_frame() {
+        let ready = Instant::now();
+        let mut summary = summary(ready);
+        summary.due(ready + Duration::from_secs(7), 2).unwrap();
+        summary.captured(ready + Duration::from_secs(8));
+        summary.event(stop(Duration::from_millis(1500), ready + Duration::from_secs(8)));
+        summary.captured(ready + Duration::from_secs(9));
+        let (report, end) = summary.finish(3);
+        assert_eq!(end, ready + Duration::from_secs(9));
+        assert_eq!((report.period, report.frames, report.dropped), (Duration::from_secs(2), 2, 1));
+        assert_eq!((report.stopped, report.motion), (1, Duration::from_millis(1500)));
+    }
+
+
+    #[test]
+    fn finishing_without_a_frame_since_the_last_report_is_an_empty_period() {
+        let ready = Instant::now();
+        let mut summary = summary(ready);
+        summary.captured(ready + Duration::from_secs(2));
+        summary.due(ready + Duration::from_secs(7), 0).unwrap();
+        let (report, end) = summary.finish(0);
+        assert_eq!((report, end), (ActivitySummary::empty(), ready + Duration::from_secs(7)));
+    }
+
+
+    #[test]
+    fn motion_counts_to_the_millisecond_as_stop_records_print_it() {
+        let ready = Instant::now();
+        let mut summary = summary(ready);
+        summary.event(stop(Duration::from_micros(1_200_400), ready));
+        summary.event(stop(Duration::from_micros(800_600), ready));
+        assert_eq!(summary.finish(0).0.motion, Duration::from_millis(2001));
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    fs::OpenOptions,
+    io::{ self, Write },
+    path::{ Path, PathBuf },
+    process::{ Child, Command, Stdio },
+    time::{ Duration, Instant },
+};
+
+use crate::{
+    bus::{ Event, Sink, SinkError },
+    motion::MotionEvent,
+};
+
+// Tried in this order when no --sound-player is given.
+const PLAYERS: [&str; 3] = ["paplay", "aplay -q", "afplay"];
+
+
+/// Something to hear on motion, for a desk without any integration: the terminal bell and a sound
+/// played when a movement starts, and optionally another one when it stops. Each plays at most
+/// once per cooldown, so a scene that keeps moving doesn't loop it.
+///
+/// Sounds are played by a player command, e.g. `aplay -q`, given the file as its last argument
+/// and never waited for. A sound that can't be played, no player installed or no audio device,
+/// rings the bell instead, or is only reported without a terminal. As a sink of the bus, it
+/// sounds for the motion events that notify: movements held during quiet hours stay silent.
+pub struct Alert {
+    bell: bool,
+    start_sound: Option<PathBuf>,
+    stop_sound: Option<PathBuf>,
+    player: Option<Vec<String>>,    // Program then arguments, None if no player was found.
+    cooldown: Duration,
+    last_start: Option<Instant>,
+    last_stop: Option<Instant>,
+    playing: Vec<(PathBuf, Child)>,
+    no_terminal: bool,              // The bell couldn't ring, already reported.
+    unplayable: bool,               // A sound couldn't be played nor the bell ring instead, already reported.
+}
+
+
+impl Alert {
+
+    pub fn new(bell: bool, start_sound: Option<PathBuf>, stop_sound: Option<PathBuf>, player: Option<&str>, cooldown: Duration) -> Self {
+        let player = match player {
+            Some(player) => Some(player),
+            None => PLAYERS.into_iter().find(|player| player.split_whitespace().next().is_some_and(installed)),
+        };
+        Self {
+            bell,
+            start_sound,
+            stop_sound,
+            player: player.map(|player| player.split_whitespace().map(String::from).collect()),
+            cooldown,
+            last_start: None,
+            last_stop: None,
+            playing: Vec::new(),
+            no_terminal: false,
+            unplayable: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.bell && self.start_sound.is_none() && self.stop_sound.is_none()
+    }
+
+    /// The sounds are to be played, but neither a player was given nor one of the usual ones found.
+    pub fn missing_player(&self) -> bool {
+        self.player.is_none() && (self.start_sound.is_some() || self.stop_sound.is_some())
+    }
+
+    /// Sounds the alert of an event, if it has one and its cooldown is over.
+    pub fn event(&mut self, event: MotionEvent, now: Instant) -> Result<(), SinkError> {
+        let (last, sound, bell) = match event {
+            MotionEvent::Start { .. } => (&mut self.last_start, self.start_sound.clone(), self.bell),
+            MotionEvent::Stop { .. } => (&mut self.last_stop, self.stop_sound.clone(), false),
+            _ => return Ok(()),
+        };
+        if (sound.is_none() && !bell) || last.is_some_and(|last| now.saturating_duration_since(last) < self.cooldown) {
+            return Ok(());
+        }
+        *last = Some(now);
+        let rung = match bell {
+            true => self.ring(),
+            false => Ok(()),
+        };
+        let played = match sound {
+            Some(sound) => self.play(&sound),
+            None => Ok(()),
+        };
+        match (rung, played) {
+            (Err(SinkError::Failed(bell)), Err(SinkError::Failed(sound))) => Err(SinkError::Failed(format!("{bell}, {sound}"))),
+            (Err(SinkError::Failed(message)), _) | (_, Err(SinkError::Failed(message))) => Err(SinkError::Failed(message)),
+            (rung, played) => rung.and(played),
+        }
+    }
+
+    fn ring(&mut self) -> Result<(), SinkError> {
+        match ring() {
+            Ok(()) => Ok(()),
+            Err(_) if self.no_terminal => Err(SinkError::Lost),
+            Err(err) => {
+                self.no_terminal = true;
+                Err(SinkError::Failed(format!("can't ring the terminal bell: {err}")))
+            }
+        }
+    }
+
+    fn play(&mut self, sound: &Path) -> Result<(), SinkError> {
+        let Some((program, arguments)) = self.player.as_ref().and_then(|player| player.split_first()) else {
+            return self.instead(sound, "no sound player");
+        };
+        let child = Command::new(program)
+            .args(arguments)
+            .arg(sound)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match child {
+            Ok(child) => {
+                self.playing.push((sound.to_path_buf(), child));
+                Ok(())
+            }
+            Err(err) => self.instead(sound, &format!("can't run {program}: {err}")),
+        }
+    }
+
+    // A sound couldn't be played: rings the bell instead, or reports it once if that fails too.
+    fn instead(&mut self, sound: &Path, reason: &str) -> Result<(), SinkError> {
+        match ring() {
+            Ok(()) => Ok(()),
+            Err(_) if self.unplayable => Err(SinkError::Lost),
+            Err(err) => {
+                self.unplayable = true;
+                Err(SinkError::Failed(format!("can't play {}, {reason}, nor ring the terminal bell instead: {err}", sound.display())))
+            }
+        }
+    }
+
+    /// Collects the sounds that finished, ringing the bell for each that couldn't be played.
+    pub fn reap(&mut self) -> Vec<SinkError> {
+        let mut failed = Vec::new();
+        self.playing.retain_mut(|(sound, child)| match child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                if !status.success() {
+                    failed.push((sound.clone(), format!("the player failed: {status}")));
+                }
+                false
+            }
+            Err(err) => {
+                failed.push((sound.clone(), format!("can't wait for the player: {err}")));
+                false
+            }
+        });
+        failed.into_iter().filter_map(|(sound, reason)| self.instead(&sound, &reason).err()).collect()
+    }
+}
+
+
+impl Sink for Alert {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::Motion { event, at, notify: true, .. } => self.event(*event, *at),
+            _ => Ok(()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<(), SinkError> {
+        self.reap().into_iter().find(|failure| *failure != SinkError::Lost).map_or(Ok(()), Err)
+    }
+}
+
+
+// Writes the BEL character to the controlling terminal.
+fn ring() -> io::Result<()> {
+    OpenOptions::new().write(true).open("/dev/tty")?.write_all(b"\x07")
+}
+
+
+// Whether a program is found in the PATH.
+fn installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|directory| directory.join(program).is_file()))
+}
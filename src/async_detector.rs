@@ -0,0 +1,156 @@
+use std::{ pin::Pin, task::{ Context, Poll }, thread };
+
+use futures_core::Stream;
+use tokio::sync::{ mpsc, oneshot };
+
+use crate::{
+    control::{ ControlCommand, ControlState, Setting },
+    detector::{ CancelToken, MotionDetector, MotionError },
+    motion::MotionEvent,
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::PixelLayout,
+};
+
+
+/// A command for the worker, with the way back for what came of it.
+struct Request {
+    command: ControlCommand,
+    reply: oneshot::Sender<Result<ControlState, String>>,
+}
+
+
+/// A `MotionDetector` for tokio applications: capture and detection run on a thread of their own,
+/// its events come out as a `Stream`, and control commands go in with async methods that wait for
+/// the worker to apply them, between two frames.
+///
+/// The stream yields every event of the tracker, provisional ones included. It ends once the
+/// source did, after the stop of the movement in progress and an `EndOfInput` error, or after the
+/// error that stopped the worker. Dropping the handle cancels the worker, which lets go of the
+/// source after the frame it's on, `shutdown` also waits for it.
+pub struct AsyncMotionDetector {
+    events: mpsc::UnboundedReceiver<Result<MotionEvent, MotionError>>,
+    commands: mpsc::UnboundedSender<Request>,
+    cancel: CancelToken,
+    stopped: Option<oneshot::Receiver<()>>, // Closed once the worker returned.
+}
+
+
+impl AsyncMotionDetector {
+
+    /// Starts the worker: `open` gives it its source, on its own thread so sources that can't move
+    /// between threads work too, e.g. a camera. The detector is made as `MotionDetector::new` does,
+    /// for frames of `width` by `height` pixels in `layout`. A source that can't be opened ends the
+    /// stream with its reason.
+    pub fn spawn<S, F>(settings: Settings, width: usize, height: usize, layout: PixelLayout, open: F) -> Self
+    where
+        S: FrameSource,
+        F: FnOnce() -> Result<S, String> + Send + 'static,
+    {
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let (commands, requests) = mpsc::unbounded_channel();
+        let (stopped_sender, stopped) = oneshot::channel();
+        let cancel = CancelToken::default();
+        let token = cancel.clone();
+        thread::spawn(move || {
+            let _stopped = stopped_sender;
+            let source = match open() {
+                Ok(source) => source,
+                Err(reason) => {
+                    let _ = event_sender.send(Err(MotionError::Source(reason)));
+                    return;
+                }
+            };
+            let detector = MotionDetector::new(&settings, width, height, layout).with_cancel_token(token);
+            work(detector, source, event_sender, requests);
+        });
+        Self { events, commands, cancel, stopped: Some(stopped) }
+    }
+
+    /// The next event, None once the stream ended. Cancel safe, e.g. in a `tokio::select!`.
+    pub async fn next_event(&mut self) -> Option<Result<MotionEvent, MotionError>> {
+        self.events.recv().await
+    }
+
+    /// Sends a command and waits for the worker to apply it, see `MotionDetector::apply`.
+    pub async fn send(&self, command: ControlCommand) -> Result<ControlState, String> {
+        let (reply, state) = oneshot::channel();
+        self.commands.send(Request { command, reply }).map_err(|_| "the detector is gone".to_string())?;
+        state.await.map_err(|_| "the detector is gone".to_string())?
+    }
+
+    pub async fn pause(&self) -> Result<ControlState, String> {
+        self.send(ControlCommand::Pause).await
+    }
+
+    /// Resumes from a fresh baseline.
+    pub async fn resume(&self) -> Result<ControlState, String> {
+        self.send(ControlCommand::Resume).await
+    }
+
+    pub async fn reset_baseline(&self) -> Result<ControlState, String> {
+        self.send(ControlCommand::ResetBaseline).await
+    }
+
+    /// Sets thresholds in percent, refused as a whole if one is out of the --control-bounds.
+    pub async fn set_thresholds(&self, values: Vec<(Setting, f32)>) -> Result<ControlState, String> {
+        self.send(ControlCommand::Set(values)).await
+    }
+
+    /// Cancels the worker and waits for it to return, which it does after the frame it's on.
+    pub async fn shutdown(mut self) {
+        self.cancel.cancel();
+        if let Some(stopped) = self.stopped.take() {
+            let _ = stopped.await;
+        }
+    }
+}
+
+
+impl Stream for AsyncMotionDetector {
+    type Item = Result<MotionEvent, MotionError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+
+impl Drop for AsyncMotionDetector {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+
+// Reads frames until the source ends, the worker is cancelled or nobody listens anymore, applying
+// the commands that came in before each frame.
+fn work(
+    mut detector: MotionDetector,
+    mut source: impl FrameSource,
+    events: mpsc::UnboundedSender<Result<MotionEvent, MotionError>>,
+    mut requests: mpsc::UnboundedReceiver<Request>,
+) {
+    loop {
+        while let Ok(request) = requests.try_recv() {
+            let _ = request.reply.send(detector.apply(&request.command));
+        }
+        match detector.next_events(&mut source) {
+            Ok(found) => {
+                if found.into_iter().any(|event| events.send(Ok(event)).is_err()) {
+                    return;
+                }
+            }
+            Err(MotionError::Cancelled) => return,
+            Err(err) => {
+                if err == MotionError::EndOfInput {
+                    if let Some(stop) = detector.finish() {
+                        let _ = events.send(Ok(stop));
+                    }
+                }
+                let _ = events.send(Err(err));
+                return;
+            }
+        }
+    }
+}
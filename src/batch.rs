@@ -0,0 +1,340 @@
+use std::{
+    fs,
+    path::{ Path, PathBuf },
+    sync::{ atomic::{ AtomicUsize, Ordering }, mpsc },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use crate::{
+    diff::{ self, Blur, FlickerRejection, Masked, Normalize, WhiteBalance },
+    ffmpeg::FfmpegSource,
+    json,
+    motion::{ MotionEvent, MotionTracker, StopReason },
+    padding::{ Clip, ClipPadding },
+    settings::{ ReportFormat, Settings },
+    signals,
+    source::FrameSource,
+    supervisor,
+    thumbnail::{ PixelLayout, TemporalAverage, Thumbnail },
+};
+
+const EXIT_ARGUMENT: i32 = 22;      // Invalid argument
+const EXIT_FAILED: i32 = 5;         // I/O error
+const EXIT_INTERRUPTED: i32 = 4;    // Interrupted system call
+
+// Subdirectory of the input directory the reports go to without --report-dir.
+const REPORT_DIR: &str = "motion-reports";
+
+
+/// A movement, timed from the start of its file.
+struct Segment {
+    id: u64,
+    start: Duration,
+    end: Duration,
+    reason: StopReason,
+    peak: f32,
+    mean: f32,
+    frames_above_threshold: u32,
+    clip: Option<Clip>,     // With --report-padding.
+}
+
+
+/// The movements found in one file.
+struct FileReport {
+    frames: u64,
+    duration: Duration,     // Of the decoded frames, at the capture interval.
+    segments: Vec<Segment>,
+}
+
+
+impl FileReport {
+
+    fn motion_time(&self) -> Duration {
+        self.segments.iter().map(|segment| segment.end - segment.start).sum()
+    }
+
+    fn to_json(&self, file: &Path) -> String {
+        let segments = self.segments.iter().map(|segment| {
+            let object = json::Object::new()
+                .field("id", segment.id)
+                .field("start", segment.start.as_secs_f64())
+                .field("end", segment.end.as_secs_f64())
+                .field("duration", (segment.end - segment.start).as_secs_f64())
+                .field("reason", segment.reason.name())
+                .field("peak", segment.peak)
+                .field("mean", segment.mean)
+                .field("frames_above_threshold", segment.frames_above_threshold as u64);
+            match segment.clip {
+                Some(clip) => object
+                    .field("clip", clip.id)
+                    .field("padded_start", clip.start.as_secs_f64())
+                    .field("padded_end", clip.end.as_secs_f64()),
+                None => object,
+            }
+        }).collect::<Vec<_>>();
+        json::Object::new()
+            .field("file", file.display().to_string())
+            .field("frames", self.frames)
+            .field("duration", self.duration.as_secs_f64())
+            .field("movements", self.segments.len())
+            .field("motion_time", self.motion_time().as_secs_f64())
+            .field("segments", segments)
+            .finish()
+    }
+
+    fn to_csv(&self) -> String {
+        // Padded columns only with --report-padding, which pads every segment or none.
+        let padded = self.segments.first().is_some_and(|segment| segment.clip.is_some());
+        let mut csv = String::from("id,start,end,duration,reason,peak,mean,frames_above_threshold");
+        csv.push_str(if padded { ",clip,padded_start,padded_end\n" } else { "\n" });
+        for segment in &self.segments {
+            csv.push_str(&format!(
+                "{},{:.3},{:.3},{:.3},{},{:.3},{:.3},{}",
+                segment.id, segment.start.as_secs_f64(), segment.end.as_secs_f64(), (segment.end - segment.start).as_secs_f64(),
+                segment.reason.name(), segment.peak, segment.mean, segment.frames_above_threshold
+            ));
+            if let Some(clip) = segment.clip {
+                csv.push_str(&format!(",{},{:.3},{:.3}", clip.id, clip.start.as_secs_f64(), clip.end.as_secs_f64()));
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+
+/// Runs detection over every file of the input directory matching the glob, --jobs files at a
+/// time, each with a detector of its own. Writes a report per file, prints progress as files
+/// complete and a summary at the end. Returns the process exit code: 5 if any file failed.
+pub fn run(settings: &Settings) -> i32 {
+    let input_dir = settings.input_dir.as_deref().expect("Batch settings have an input directory");
+    let files = match matching_files(input_dir, &settings.batch_glob) {
+        Ok(files) if files.is_empty() => {
+            println!("\nError, no file in {} matches {}", input_dir.display(), settings.batch_glob);
+            return EXIT_ARGUMENT;
+        }
+        Ok(files) => files,
+        Err(err) => {
+            println!("\nError, can't read {}: {err}", input_dir.display());
+            return EXIT_ARGUMENT;
+        }
+    };
+    let report_dir = settings.report_dir.clone().unwrap_or_else(|| input_dir.join(REPORT_DIR));
+    if let Err(err) = fs::create_dir_all(&report_dir) {
+        println!("\nError, can't create {}: {err}", report_dir.display());
+        return EXIT_FAILED;
+    }
+    signals::install_shutdown_handler();
+    println!("Processing {} files, {} at a time, reports go to {}", files.len(), settings.jobs.min(files.len()), report_dir.display());
+
+    // Workers take the next file until there are none left, results come back in completion order.
+    let next_file = AtomicUsize::new(0);
+    let (sender, results) = mpsc::channel();
+    let (mut completed, mut with_motion, mut failed) = (0, 0, 0);
+    let mut motion_time = Duration::ZERO;
+    thread::scope(|scope| {
+        for _ in 0 .. settings.jobs.min(files.len()) {
+            let (sender, files, next_file, report_dir) = (sender.clone(), &files, &next_file, &report_dir);
+            scope.spawn(move || loop {
+                let index = next_file.fetch_add(1, Ordering::Relaxed);
+                if index >= files.len() || signals::shutdown_requested() {
+                    break;
+                }
+                let result = analyze(&files[index], settings).and_then(|report| {
+                    write_report(&report, &files[index], report_dir, settings.report_format).map(|()| report)
+                });
+                // A file cut short by the shutdown was not processed rather than failed.
+                if signals::shutdown_requested() || sender.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        for (index, result) in results {
+            completed += 1;
+            let name = files[index].file_name().unwrap_or_default().to_string_lossy();
+            match result {
+                Ok(report) => {
+                    with_motion += !report.segments.is_empty() as usize;
+                    motion_time += report.motion_time();
+                    println!("[{completed}/{}] {name}: {} movements, {:.1}s of motion in {:.1}s",
+                        files.len(), report.segments.len(), report.motion_time().as_secs_f64(), report.duration.as_secs_f64());
+                }
+                Err(err) => {
+                    failed += 1;
+                    println!("[{completed}/{}] {name}: failed, {err}", files.len());
+                }
+            }
+        }
+    });
+
+    println!("\n{completed} files, {with_motion} with motion, {:.1}s of motion in total, {failed} failed", motion_time.as_secs_f64());
+    if failed > 0 {
+        EXIT_FAILED
+    } else if completed < files.len() {
+        println!("Interrupted, {} files were not processed", files.len() - completed);
+        EXIT_INTERRUPTED
+    } else {
+        0
+    }
+}
+
+
+/// Decodes a file at the capture size and interval and follows its movements. Times are those
+/// of the decoded frames, not of processing, which runs as fast as ffmpeg decodes.
+fn analyze(path: &Path, settings: &Settings) -> Result<FileReport, String> {
+    let (width, height) = (settings.capture_width as usize, settings.capture_height as usize);
+    let interval = settings.frame_capture_interval;
+    let mut source = FfmpegSource::start(
+        &settings.ffmpeg,
+        &settings.ffmpeg_args,
+        &path.to_string_lossy(),
+        width,
+        height,
+        interval,
+        settings.input_watchdog,
+    )?.finite();
+
+    let thumb_width = width / settings.downsample;
+    let thumb_height = height / settings.downsample;
+    let thumb_len = thumb_width * thumb_height;
+    let pixel_threshold = ((settings.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let start_count = (thumb_len as f32 * (settings.image_threshold / 100.0).clamp(0.0, 1.0)) as i32;
+    let sustain_count = (thumb_len as f32 * (settings.sustain_threshold() / 100.0).clamp(0.0, 1.0)) as i32;
+    let mut strategy = diff::from_name(&settings.algorithm, pixel_threshold, start_count, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("Algorithm names are validated with the settings");
+    if let Some(mode) = settings.normalize {
+        strategy = Box::new(Normalize::new(strategy, mode));
+    }
+    if let Some(tolerance) = settings.wb_compensation {
+        strategy = Box::new(WhiteBalance::new(strategy, tolerance));
+    }
+    if settings.flicker_rejection {
+        strategy = Box::new(FlickerRejection::new(strategy, pixel_threshold));
+    }
+    if settings.blur > 0 {
+        strategy = Box::new(Blur::new(strategy, settings.blur));
+    }
+    if let Some(mask) = &settings.mask {
+        strategy = Box::new(Masked::new(strategy, mask.clone()));
+    }
+    let mut thumb = Thumbnail::new(thumb_width, thumb_height).with_stats(settings.thumb_stats);
+    let mut averager = TemporalAverage::new(settings.temporal_average);
+
+    let start = Instant::now();
+    let mut motion = MotionTracker::new(settings.motion_tail_length, start_count, sustain_count)
+        .with_tail_frames(settings.tail_frames)
+        .with_max_duration(settings.max_event_duration)
+        .with_confirm_frames(settings.confirm_frames)
+        .with_frame_interval(interval * settings.temporal_average as u32);
+    motion.ready(start);
+    let mut frames = 0u64;
+    let mut last_time = start;
+    let mut segments = Vec::new();
+    while let Some(frame) = source.next_frame()? {
+        if signals::shutdown_requested() {
+            return Err("interrupted".to_string());
+        }
+        let time = start + interval * frames as u32;
+        frames += 1;
+        thumb.downsample(frame, width, settings.downsample, PixelLayout::Rgb);
+        let Some((averaged, now)) = averager.push(&thumb, time) else {
+            continue;
+        };
+        strategy.set_motion_active(motion.is_active());
+        let result = supervisor::guard(|| strategy.process(averaged))
+            .map_err(|panic| format!("processing panicked at frame {frames}: {panic}"))?;
+        segments.extend(motion.update(result.changed_pixels, result.score, now).into_iter().filter_map(|event| segment(event, start)));
+        last_time = now;
+    }
+    if frames == 0 {
+        return Err("no frames decoded".to_string());
+    }
+    segments.extend(motion.finish(last_time).and_then(|event| segment(event, start)));
+    let duration = interval * frames as u32;
+    if let Some(padding) = settings.report_padding {
+        pad(&mut segments, padding, duration);
+    }
+    Ok(FileReport { frames, duration, segments })
+}
+
+
+// Movements are only complete once they stop.
+fn segment(event: MotionEvent, start: Instant) -> Option<Segment> {
+    let MotionEvent::Stop { id, reason, duration, at, stats, .. } = event else {
+        return None;
+    };
+    let end = at.saturating_duration_since(start);
+    Some(Segment {
+        id,
+        start: end.saturating_sub(duration),
+        end,
+        reason,
+        peak: stats.peak,
+        mean: stats.mean(),
+        frames_above_threshold: stats.frames_above_threshold,
+        clip: None,
+    })
+}
+
+
+// Every segment of a clip gets the clip as it ends with its last one, within the file.
+fn pad(segments: &mut [Segment], padding: Duration, duration: Duration) {
+    let mut clips = ClipPadding::new(padding);
+    for segment in segments.iter_mut() {
+        clips.start(segment.id, segment.start);
+        segment.clip = Some(clips.stop(segment.id, segment.end));
+    }
+    let mut last: Option<Clip> = None;
+    for clip in segments.iter_mut().rev().filter_map(|segment| segment.clip.as_mut()) {
+        match last {
+            Some(last) if last.id == clip.id => clip.end = last.end,
+            _ => {
+                clip.end = clip.end.min(duration);
+                last = Some(*clip);
+            }
+        }
+    }
+}
+
+
+// "clip.mp4" is reported in "clip.mp4.json", clips differing only by extension don't collide.
+fn write_report(report: &FileReport, file: &Path, report_dir: &Path, format: ReportFormat) -> Result<(), String> {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(format.extension());
+    let path = report_dir.join(name);
+    let text = match format {
+        ReportFormat::Json => report.to_json(file) + "\n",
+        ReportFormat::Csv => report.to_csv(),
+    };
+    fs::write(&path, text).map_err(|err| format!("can't write {}: {err}", path.display()))
+}
+
+
+/// The regular files of `dir` whose name matches `glob`, sorted by name.
+fn matching_files(dir: &Path, glob: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if entry.file_type()?.is_file() && matches(glob.as_bytes(), name.to_string_lossy().as_bytes()) {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+
+// Shell style wildcards: * for any run of characters, ? for a single one.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => matches(&pattern[1 ..], name) || (!name.is_empty() && matches(pattern, &name[1 ..])),
+        (Some(b'?'), Some(_)) => matches(&pattern[1 ..], &name[1 ..]),
+        (Some(expected), Some(actual)) if expected == actual => matches(&pattern[1 ..], &name[1 ..]),
+        _ => false,
+    }
+}
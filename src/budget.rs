@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+// Capture interval and downsample multipliers for each degradation level. Going up, frames are
+// first captured less often, then processed as smaller thumbnails. Recovery goes back down.
+//
+// There is deliberately no step to luma thumbnails after these. By then the thumbnail has 16 times
+// fewer pixels and the block average over the whole frame is most of the cost, which luma would
+// still pay for every byte. It would also change what is compared under --channels hsv,
+// --wb-compensation, --thumb-stats mean-minmax and --ab-config, and the GPU downsampler.
+const LEVELS: [(u32, usize); 5] = [(1, 1), (2, 1), (4, 1), (4, 2), (4, 4)];
+
+// Frames to wait after an adjustment before judging it, so the average reflects the new settings.
+const SETTLE_FRAMES: u32 = 25;
+
+// Weight of the latest frame in the rolling average.
+const SMOOTHING: f32 = 0.1;
+
+// Only recovers once the load is well below the budget, so it doesn't bounce between two levels.
+const RECOVERY_MARGIN: f32 = 0.4;
+
+
+/// Keeps processing within a share of one core by capturing less often and downsampling more
+/// when the rolling average processing time gets too high, and undoing that when it drops.
+pub struct CpuBudget {
+    budget: f32,
+    base_interval: Duration,
+    base_downsample: usize,
+    level: usize,
+    load: Option<f32>,
+    frames_since_change: u32,
+}
+
+
+impl CpuBudget {
+
+    pub fn new(budget_percent: f32, base_interval: Duration, base_downsample: usize) -> Self {
+        Self {
+            budget: budget_percent / 100.0,
+            base_interval,
+            base_downsample,
+            level: 0,
+            load: None,
+            frames_since_change: 0,
+        }
+    }
+
+    pub fn capture_interval(&self) -> Duration {
+        self.base_interval * LEVELS[self.level].0
+    }
+
+    pub fn downsample(&self) -> usize {
+        self.base_downsample * LEVELS[self.level].1
+    }
+
+    /// Averaged share of the capture interval spent processing, as a percentage.
+    pub fn load(&self) -> f32 {
+        self.load.unwrap_or(0.0) * 100.0
+    }
+
+    /// Records how long a frame took to process. Returns true if the capture interval or the
+    /// downsample factor changed as a result.
+    pub fn record(&mut self, processing_time: Duration) -> bool {
+        let sample = processing_time.as_secs_f32() / self.capture_interval().as_secs_f32();
+        let load = match self.load {
+            Some(load) => load + (sample - load) * SMOOTHING,
+            None => sample,
+        };
+        self.load = Some(load);
+
+        self.frames_since_change += 1;
+        if self.frames_since_change < SETTLE_FRAMES {
+            return false;
+        }
+        if load > self.budget && self.level + 1 < LEVELS.len() {
+            self.level += 1;
+        } else if load < self.budget * RECOVERY_MARGIN && self.level > 0 {
+            self.level -= 1;
+        } else {
+            return false;
+        }
+        // Start averaging over, the new settings change what each frame costs.
+        self.load = None;
+        self.frames_since_change = 0;
+        true
+    }
+}
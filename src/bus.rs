@@ -0,0 +1,341 @@
+use std::{
+    sync::{ mpsc::{ self, Receiver, RecvTimeoutError, SyncSender, TrySendError }, Arc, Condvar, Mutex },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use crate::{ identity::EventIdentity, json, motion::MotionEvent };
+
+// Events waiting for a sink before it's considered unable to keep up.
+const QUEUE_CAPACITY: usize = 1024;
+// How often a sink is polled while no event comes.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// What the detector publishes, the same value for every sink, each taking what it's interested in.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A message of the event stream: its text line, None for messages that have none, and its JSON object.
+    Message { text: Option<String>, json: String },
+    /// A motion event seen at `at`, with what consumers dedupe it on. Unless `notify`, quiet hours
+    /// hold it for the digest.
+    Motion { event: MotionEvent, identity: EventIdentity, at: Instant, notify: bool, text: String, json: String },
+    /// A movement held during quiet hours, now that they're over, and whether it stopped.
+    Held { id: u64, stopped: bool },
+    /// The effective configuration, for the event output alone.
+    Config { json: String },
+}
+
+
+/// Why a sink couldn't deliver an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkError {
+    Failed(String),     // Reported as a warning, the next events are delivered as usual.
+    Lost,               // Again for a reason the sink already reported, only counted.
+    Closed(String),     // The sink takes no more events.
+}
+
+
+/// Something events are delivered to, the event output or the commands for instance. Each sink
+/// runs on a thread of its own, so one that's slow or hangs holds up nothing but itself.
+pub trait Sink: Send {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError>;
+
+    /// Called after every event, and every POLL_INTERVAL while none comes, for the sink's own
+    /// housekeeping.
+    fn poll(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+
+/// Delivery counters of a sink, since launch.
+#[derive(Debug, Clone, Default)]
+pub struct SinkStats {
+    pub delivered: u64,
+    pub failed: u64,            // The sink returned an error.
+    pub dropped: u64,           // Published while its queue was full.
+    pub queued: usize,          // Waiting for the sink.
+    pub slowest: Duration,      // Longest delivery.
+    pub closed: Option<String>, // Why the sink takes no more events.
+}
+
+
+impl SinkStats {
+
+    pub fn to_object(&self, name: &str) -> json::Object {
+        json::Object::new()
+            .field("name", name)
+            .field("delivered_total", self.delivered)
+            .field("failed_total", self.failed)
+            .field("dropped_total", self.dropped)
+            .field("queued", self.queued)
+            .field("slowest", self.slowest.as_secs_f64())
+            .field("closed", self.closed.as_deref())
+    }
+}
+
+
+struct Shared {
+    stats: Mutex<SinkStats>,
+    delivered: Condvar,         // Notified whenever the queue gets shorter or the sink closes.
+}
+
+
+struct Subscription {
+    name: &'static str,
+    lossy: bool,
+    overflowing: bool,          // Dropping events since the last warning.
+    sender: SyncSender<Arc<Event>>,
+    shared: Arc<Shared>,
+}
+
+
+/// Hands what the detector publishes to every registered sink. Publishing never waits: each sink
+/// has a bounded queue, and a lossy sink whose queue is full misses the event, counted and warned
+/// about once per overflow, while any other sink is closed, as it can't be relied on anymore.
+/// Clones publish to the same sinks.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscriptions: Arc<Mutex<Vec<Subscription>>>,
+    warnings: Arc<Mutex<Vec<String>>>,
+}
+
+
+impl EventBus {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts delivering events to a sink, those published from now on.
+    pub fn register(&self, name: &'static str, sink: impl Sink + 'static, lossy: bool) {
+        let (sender, events) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let shared = Arc::new(Shared { stats: Mutex::new(SinkStats::default()), delivered: Condvar::new() });
+        let (thread_shared, warnings) = (shared.clone(), self.warnings.clone());
+        thread::spawn(move || deliver(name, Box::new(sink), events, lossy, &thread_shared, &warnings));
+        self.subscriptions.lock().unwrap().push(Subscription { name, lossy, overflowing: false, sender, shared });
+    }
+
+    /// Queues an event for every sink.
+    pub fn publish(&self, event: Event) {
+        let event = Arc::new(event);
+        let mut warnings = Vec::new();
+        for subscription in self.subscriptions.lock().unwrap().iter_mut() {
+            let mut stats = subscription.shared.stats.lock().unwrap();
+            if stats.closed.is_some() {
+                continue;
+            }
+            // Still locked, the sink can't count the event delivered before it's counted queued.
+            match subscription.sender.try_send(event.clone()) {
+                Ok(()) => {
+                    stats.queued += 1;
+                    subscription.overflowing = false;
+                }
+                Err(TrySendError::Full(_)) if subscription.lossy => {
+                    stats.dropped += 1;
+                    if !subscription.overflowing {
+                        warnings.push(format!("the {} output can't keep up, dropping events", subscription.name));
+                    }
+                    subscription.overflowing = true;
+                }
+                Err(TrySendError::Full(_)) => {
+                    stats.dropped += 1;
+                    stats.closed = Some(format!("can't keep up, {QUEUE_CAPACITY} events waiting"));
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    stats.closed = Some(String::from("its thread panicked"));
+                    subscription.shared.delivered.notify_all();
+                }
+            }
+        }
+        self.warnings.lock().unwrap().extend(warnings);
+    }
+
+    /// What the sinks reported since the last call, to print as warnings.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+
+    /// Why a sink takes no more events, if it doesn't. Lossy sinks are warned about when they
+    /// close, the others are for whoever registered them to check.
+    pub fn closed(&self, name: &str) -> Option<String> {
+        self.subscriptions.lock().unwrap().iter()
+            .find(|subscription| subscription.name == name)
+            .and_then(|subscription| subscription.shared.stats.lock().unwrap().closed.clone())
+    }
+
+    /// The counters of every sink, in the order they were registered.
+    pub fn stats(&self) -> Vec<(&'static str, SinkStats)> {
+        self.subscriptions.lock().unwrap().iter()
+            .map(|subscription| (subscription.name, subscription.shared.stats.lock().unwrap().clone()))
+            .collect()
+    }
+
+    /// Waits until every sink delivered what's queued for it, for at most `timeout` overall.
+    /// Returns false if it ran out.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let shared: Vec<Arc<Shared>> = self.subscriptions.lock().unwrap().iter().map(|subscription| subscription.shared.clone()).collect();
+        shared.iter().all(|shared| {
+            let stats = shared.stats.lock().unwrap();
+            let left = deadline.saturating_duration_since(Instant::now());
+            let (_stats, wait) = shared.delivered
+                .wait_timeout_while(stats, left, |stats| stats.queued > 0 && stats.closed.is_none())
+                .unwrap();
+            !wait.timed_out()
+        })
+    }
+}
+
+
+// The thread of a sink, until the bus is gone or the sink closes.
+fn deliver(name: &str, mut sink: Box<dyn Sink>, events: Receiver<Arc<Event>>, lossy: bool, shared: &Shared, warnings: &Mutex<Vec<String>>) {
+    loop {
+        let delivered = match events.recv_timeout(POLL_INTERVAL) {
+            Ok(event) => {
+                let started = Instant::now();
+                let result = sink.deliver(&event);
+                let mut stats = shared.stats.lock().unwrap();
+                stats.queued -= 1;
+                stats.slowest = stats.slowest.max(started.elapsed());
+                match result {
+                    Ok(()) => stats.delivered += 1,
+                    Err(_) => stats.failed += 1,
+                }
+                drop(stats);
+                shared.delivered.notify_all();
+                result
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        for result in [delivered, sink.poll()] {
+            match result {
+                Ok(()) | Err(SinkError::Lost) => {}
+                Err(SinkError::Failed(message)) => warnings.lock().unwrap().push(message),
+                Err(SinkError::Closed(reason)) => {
+                    if lossy {
+                        warnings.lock().unwrap().push(format!("the {name} output stopped: {reason}"));
+                    }
+                    let mut stats = shared.stats.lock().unwrap();
+                    stats.closed = Some(reason);
+                    stats.queued = 0;
+                    drop(stats);
+                    shared.delivered.notify_all();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps the JSON of the messages it's given.
+    struct Keep(Arc<Mutex<Vec<String>>>);
+
+
+    impl Sink for Keep {
+
+        fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+            if let Event::Message { json, .. } = event {
+                self.0.lock().unwrap().push(json.clone());
+            }
+            Ok(())
+        }
+    }
+
+
+    // Hangs in its first delivery until the sender goes away.
+    struct Hang(Receiver<()>);
+
+
+    impl Sink for Hang {
+
+        fn deliver(&mut self, _event: &Event) -> Result<(), SinkError> {
+            let _ = self.0.recv();
+            Ok(())
+        }
+    }
+
+
+    fn message(index: usize) -> Event {
+        Event::Message { text: None, json: format!("{{\"index\":{index}}}") }
+    }
+
+
+    #[test]
+    fn a_hanging_sink_holds_up_nothing_but_itself() {
+        let bus = EventBus::new();
+        let (release, hang) = mpsc::channel();
+        let kept = Arc::new(Mutex::new(Vec::new()));
+        bus.register("hanging", Hang(hang), false);
+        bus.register("kept", Keep(kept.clone()), false);
+        let started = Instant::now();
+        for index in 0 .. 10 {
+            bus.publish(message(index));
+        }
+        assert!(started.elapsed() < Duration::from_secs(1), "Publishing waited");
+        assert!(!bus.drain(Duration::from_millis(500)), "The hanging sink delivered everything");
+        assert_eq!(kept.lock().unwrap().len(), 10);
+        let stats = bus.stats();
+        assert_eq!((stats[0].1.delivered, stats[0].1.queued), (0, 10));
+        assert_eq!((stats[1].1.delivered, stats[1].1.queued), (10, 0));
+        drop(release);
+        assert!(bus.drain(Duration::from_secs(5)));
+    }
+
+
+    #[test]
+    fn a_full_queue_drops_for_a_lossy_sink_and_closes_any_other() {
+        let bus = EventBus::new();
+        let (_release_lossy, lossy) = mpsc::channel();
+        let (_release, strict) = mpsc::channel();
+        bus.register("lossy", Hang(lossy), true);
+        bus.register("strict", Hang(strict), false);
+        // The first event is taken off each queue, hanging in its delivery.
+        bus.publish(message(0));
+        thread::sleep(Duration::from_millis(100));
+        for index in 1 .. QUEUE_CAPACITY + 3 {
+            bus.publish(message(index));
+        }
+        assert_eq!(bus.take_warnings(), ["the lossy output can't keep up, dropping events"]);
+        assert_eq!(bus.closed("lossy"), None);
+        assert_eq!(bus.stats()[0].1.dropped, 2);
+        assert_eq!(bus.closed("strict").as_deref(), Some("can't keep up, 1024 events waiting"));
+        assert_eq!(bus.stats()[1].1.dropped, 1);
+    }
+
+
+    #[test]
+    fn failures_are_warnings_and_closing_stops_delivery() {
+        struct Refuse(u32);
+
+        impl Sink for Refuse {
+
+            fn deliver(&mut self, _event: &Event) -> Result<(), SinkError> {
+                self.0 += 1;
+                match self.0 {
+                    1 => Err(SinkError::Failed(String::from("the disk is full"))),
+                    2 => Err(SinkError::Lost),
+                    _ => Err(SinkError::Closed(String::from("the pipe broke"))),
+                }
+            }
+        }
+
+        let bus = EventBus::new();
+        bus.register("refusing", Refuse(0), true);
+        for index in 0 .. 4 {
+            bus.publish(message(index));
+        }
+        bus.drain(Duration::from_secs(5));
+        assert_eq!(bus.take_warnings(), ["the disk is full", "the refusing output stopped: the pipe broke"]);
+        assert_eq!(bus.closed("refusing").as_deref(), Some("the pipe broke"));
+        assert_eq!(bus.stats()[0].1.failed, 3);
+    }
+}
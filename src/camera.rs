@@ -0,0 +1,542 @@
+use std::{
+    fmt,
+    path::Path,
+    time::{ Duration, Instant },
+};
+use eye::hal::{
+    control,
+    device::Description,
+    format::PixelFormat,
+    platform::{ Device as PlatformDevice, Stream as PlatformStream },
+    stream::Descriptor,
+    traits::{ Context, Device, Stream },
+    PlatformContext,
+};
+
+use crate::{
+    output::Output,
+    probe_cache::{ CachedDevice, ProbeCache },
+    source::FrameMetadata,
+    startup::{ Phase, Progress },
+    thumbnail::PixelLayout,
+};
+
+
+/// Why a camera couldn't be opened.
+#[derive(Debug)]
+pub enum OpenError {
+    NoDevice,
+    NoUsableFormat,     // None of the advertised pixel formats could be started.
+    Hal(eye::hal::Error),
+}
+
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenError::NoDevice => write!(f, "no device detected"),
+            OpenError::NoUsableFormat => write!(f, "none of the advertised pixel formats could be started"),
+            OpenError::Hal(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+
+impl std::error::Error for OpenError {}
+
+
+impl From<eye::hal::Error> for OpenError {
+    fn from(err: eye::hal::Error) -> Self {
+        OpenError::Hal(err)
+    }
+}
+
+
+/// Layout of the frames a source in the given format hands to the downsampler: as delivered for
+/// RGB and BGR with or without a fourth byte, luma for formats compared by luma only (8 bit
+/// grayscale, e.g. IR cameras exposing GREY, and YUYV). `forced` overrides it, for drivers
+/// misreporting their format.
+pub fn layout(pixfmt: &PixelFormat, forced: Option<PixelLayout>) -> PixelLayout {
+    match (forced, Conversion::for_format(pixfmt)) {
+        (Some(layout), _) => layout,
+        (None, Some(Conversion::Native(layout))) => layout,
+        (None, Some(Conversion::Yuyv)) => PixelLayout::Gray,
+        (None, None) => PixelLayout::Rgb,
+    }
+}
+
+
+/// Channels of the thumbnails made from a stream in the given format, see `layout`.
+pub fn channels(pixfmt: &PixelFormat, forced: Option<PixelLayout>) -> usize {
+    layout(pixfmt, forced).channels()
+}
+
+
+/// The pixel format of a stream and the layout its frames are read in, see `layout`. A device may
+/// come back from a reconnect in another format than it first negotiated, YUYV instead of RGB3
+/// for instance, so it's derived again from the descriptor every time the stream starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamFormat {
+    pub pixfmt: PixelFormat,
+    pub layout: PixelLayout,
+}
+
+
+impl StreamFormat {
+
+    pub fn new(descriptor: &Descriptor, forced: Option<PixelLayout>) -> Self {
+        Self { pixfmt: descriptor.pixfmt.clone(), layout: layout(&descriptor.pixfmt, forced) }
+    }
+
+    /// Follows a (re)started stream. Returns the format it had if that changed: whatever was
+    /// learned from frames in the old one, a baseline or a noise map, means nothing anymore.
+    pub fn follow(&mut self, descriptor: &Descriptor, forced: Option<PixelLayout>) -> Option<StreamFormat> {
+        let current = Self::new(descriptor, forced);
+        (current != *self).then(|| std::mem::replace(self, current))
+    }
+}
+
+
+/// Camera pixel formats by their V4L2 FourCC, those `layout` knows. Compressed formats like MJPG
+/// aren't among them.
+pub const PIXEL_FORMATS: [&str; 8] = ["RGB3", "BGR3", "XB24", "AB24", "XR24", "AR24", "YUYV", "GREY"];
+
+
+/// How frames of a native pixel format are turned into frames the downsampler takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+    Native(PixelLayout),// Used as is.
+    Yuyv,               // Packed 4:2:2, reduced to its luma samples.
+}
+
+
+impl Conversion {
+
+    fn for_format(pixfmt: &PixelFormat) -> Option<Self> {
+        match pixfmt {
+            PixelFormat::Rgb(24) => Some(Conversion::Native(PixelLayout::Rgb)),
+            PixelFormat::Bgr(24) => Some(Conversion::Native(PixelLayout::Bgr)),
+            PixelFormat::Rgb(32) => Some(Conversion::Native(PixelLayout::Rgba)),
+            PixelFormat::Bgr(32) => Some(Conversion::Native(PixelLayout::Bgra)),
+            // V4L2 names 32 bit formats by their little endian words: XB24 is R, G, B, X in memory.
+            PixelFormat::Custom(fourcc) if fourcc == "XB24" || fourcc == "AB24" => Some(Conversion::Native(PixelLayout::Rgba)),
+            PixelFormat::Custom(fourcc) if fourcc == "XR24" || fourcc == "AR24" => Some(Conversion::Native(PixelLayout::Bgra)),
+            PixelFormat::Custom(fourcc) if fourcc == "YUYV" => Some(Conversion::Yuyv),
+            PixelFormat::Gray(8) => Some(Conversion::Native(PixelLayout::Gray)),
+            _ => None,
+        }
+    }
+
+    /// Bytes of a native frame of `width` by `height`.
+    fn frame_len(&self, width: usize, height: usize) -> usize {
+        match self {
+            Conversion::Native(layout) => width * height * layout.bytes_per_pixel(),
+            Conversion::Yuyv => width * height * 2,
+        }
+    }
+
+    /// Refuses a native frame shorter than `width` by `height` take.
+    fn check(&self, frame: &[u8], width: usize, height: usize) -> Result<(), String> {
+        let expected = self.frame_len(width, height);
+        let name = match self {
+            Conversion::Native(layout) => layout.name(),
+            Conversion::Yuyv => "yuyv",
+        };
+        match frame.len() < expected {
+            true => Err(format!("{} byte frame, {width}x{height} {name} needs {expected}", frame.len())),
+            false => Ok(()),
+        }
+    }
+
+    /// Converts a native frame into `converted`, unless it can be used as is.
+    fn convert<'f>(&self, frame: &'f [u8], converted: &'f mut Vec<u8>) -> &'f [u8] {
+        match self {
+            Conversion::Native(_) => frame,
+            Conversion::Yuyv => {
+                converted.clear();
+                converted.extend(frame.iter().step_by(2));
+                converted
+            }
+        }
+    }
+}
+
+
+/// Preference order of the advertised pixel formats, lowest first: by how much of the image we
+/// get to work with, and how cheaply.
+fn format_rank(pixfmt: &PixelFormat) -> u32 {
+    match pixfmt {
+        PixelFormat::Rgb(24) | PixelFormat::Bgr(24) => 0,
+        PixelFormat::Rgb(32) | PixelFormat::Bgr(32) => 0,
+        PixelFormat::Custom(fourcc) if fourcc == "YUYV" => 1,
+        PixelFormat::Jpeg => 2,
+        PixelFormat::Gray(8) => 3,
+        _ => 4,
+    }
+}
+
+
+/// A capture device with its stream running, unless it was released.
+pub struct Camera<'a, 'c> {
+    pub description: Description,
+    pub descriptor: Descriptor,
+    pub modes: Vec<Descriptor>,     // As advertised by the device when it was opened.
+    stream: Option<PlatformStream<'a>>,
+    conversion: Conversion,
+    forced: Option<PixelLayout>,    // From --force-input-layout, applied again on reopening.
+    converted: Vec<u8>,
+    requested: (u32, u32, Duration),// Size and interval negotiated again on reopening.
+    ctx: &'c PlatformContext<'a>,
+    // Kept alive for as long as its stream.
+    device: Option<PlatformDevice<'a>>,
+    metadata_controls: MetadataControls,
+    read_metadata: bool,            // From --exposure-jump, reading the controls being an ioctl each.
+    metadata: FrameMetadata,        // Of the latest frame.
+}
+
+
+impl<'a, 'c> Camera<'a, 'c> {
+
+    /// Opens the first device that has video streams and starts capturing at the requested size
+    /// and interval, reporting what it finds along the way.
+    pub fn open(ctx: &'c PlatformContext<'a>, width: u32, height: u32, interval: Duration, output: &Output) -> Result<Self, OpenError> {
+        Self::open_cached(ctx, width, height, interval, None, output, &|_| {})
+    }
+
+    /// Like `open`, skipping the stream probe with what the probe cache at `cache` remembers, see
+    /// `ProbeCache`. The cache is refreshed by a full probe whenever it's stale or its stream
+    /// doesn't start. Each device probed and pixel format tried is passed to `progress`.
+    pub fn open_cached(
+        ctx: &'c PlatformContext<'a>, width: u32, height: u32, interval: Duration, cache: Option<&Path>, output: &Output,
+        progress: &dyn Fn(Progress),
+    ) -> Result<Self, OpenError> {
+        let begin = Instant::now();
+        // Query for available devices.
+        let devices = ctx.devices()?;
+        if devices.is_empty() {
+            return Err(OpenError::NoDevice);
+        }
+        output.info("Available devices:");
+        for dev in &devices {
+            output.info(&format!("    {:?}", dev));
+        }
+
+        if let Some(path) = cache {
+            match ProbeCache::load(path).and_then(|cache| cache.select(&devices).cloned()) {
+                Ok(cached) => match Self::start_cached(ctx, &cached, width, height, interval, progress) {
+                    Ok(camera) => {
+                        output.info(&format!(
+                            "Started stream with pixel format {} from the probe cache in {:.2}s, probing took {:.2}s",
+                            camera.descriptor.pixfmt, begin.elapsed().as_secs_f64(), cached.probe_time.as_secs_f64()
+                        ));
+                        return Ok(camera);
+                    }
+                    Err(err) => output.info(&format!("Can't start the cached stream of {}: {err}, probing again", cached.uri)),
+                },
+                Err(reason) => output.info(&format!("Probing the devices, the probe cache can't be used: {reason}")),
+            }
+        }
+
+        // Query for available streams and choose the first index with available streams.
+        let mut probed = Vec::new();
+        let mut device_index = 0;
+        for (n,device) in devices.iter().enumerate() {
+            device_index = n;
+            progress(Progress::new(Phase::Probing, device.uri.as_str()));
+            let candidate = ctx.open_device(&device.uri)?;
+            let streams = candidate.streams()?;
+            let found = !streams.is_empty();
+            probed.push(CachedDevice {
+                uri: device.uri.clone(),
+                product: device.product.clone(),
+                streams,
+                started: None,
+                probe_time: Duration::ZERO,
+            });
+            if found {
+                output.info(&format!("Detected video stream on device {n}:"));
+                break;
+            } else {
+                output.info(&format!("Device {n} has no video streams. Checking next one..."));
+            }
+        };
+
+        let device = ctx.open_device(&devices[device_index].uri)?;
+        let (descriptor, stream, conversion) = start(&device, width, height, interval, &|line| output.info(line), progress)?;
+        if let (Some(path), Some(opened)) = (cache, probed.last_mut()) {
+            opened.started = Some(descriptor.pixfmt.clone());
+            opened.probe_time = begin.elapsed();
+            if let Err(err) = (ProbeCache { devices: probed }).save(path) {
+                output.info(&format!("Warning, can't write the probe cache {}: {err}", path.display()));
+            }
+        }
+        Ok(Self {
+            description: devices[device_index].clone(),
+            descriptor,
+            modes: device.streams().unwrap_or_default(),
+            stream: Some(stream),
+            conversion,
+            forced: None,
+            converted: Vec::new(),
+            requested: (width, height, interval),
+            ctx,
+            metadata_controls: MetadataControls::find(&device),
+            read_metadata: false,
+            metadata: FrameMetadata::default(),
+            device: Some(device),
+        })
+    }
+
+    // Opens the cached device and starts its stream in the cached pixel format, without probing.
+    fn start_cached(
+        ctx: &'c PlatformContext<'a>, cached: &CachedDevice, width: u32, height: u32, interval: Duration, progress: &dyn Fn(Progress)
+    ) -> Result<Self, OpenError> {
+        let pixfmt = cached.started.clone().ok_or(OpenError::NoUsableFormat)?;
+        let conversion = Conversion::for_format(&pixfmt).ok_or(OpenError::NoUsableFormat)?;
+        progress(Progress::new(Phase::Probing, format!("{} (cached)", cached.uri)));
+        let device = ctx.open_device(&cached.uri)?;
+        progress(Progress::new(Phase::Negotiating, pixfmt.to_string()));
+        let descriptor = Descriptor { width, height, interval, pixfmt };
+        let stream = device.start_stream(&descriptor)?;
+        Ok(Self {
+            description: Description { uri: cached.uri.clone(), product: cached.product.clone() },
+            descriptor,
+            modes: cached.streams.clone(),
+            stream: Some(stream),
+            conversion,
+            forced: None,
+            converted: Vec::new(),
+            requested: (width, height, interval),
+            ctx,
+            metadata_controls: MetadataControls::find(&device),
+            read_metadata: false,
+            metadata: FrameMetadata::default(),
+            device: Some(device),
+        })
+    }
+
+    /// Stops the stream and closes the device, e.g. to save power while paused.
+    pub fn release(&mut self) {
+        self.stream = None;
+        self.device = None;
+    }
+
+    /// Opens the same device again and negotiates its stream from scratch, which may come back in
+    /// another format or at another interval. The descriptor follows.
+    pub fn reopen(&mut self) -> Result<(), OpenError> {
+        self.release();
+        let device = self.ctx.open_device(&self.description.uri)?;
+        let (width, height, interval) = self.requested;
+        let (descriptor, stream, conversion) = start(&device, width, height, interval, &|_| {}, &|_| {})?;
+        self.descriptor = descriptor;
+        self.conversion = match self.forced {
+            Some(layout) => Conversion::Native(layout),
+            None => conversion,
+        };
+        self.stream = Some(stream);
+        self.metadata_controls = MetadataControls::find(&device);
+        self.device = Some(device);
+        Ok(())
+    }
+
+    /// The devices there are now, by URI.
+    pub fn device_uris(&self) -> Vec<String> {
+        self.ctx.devices().map(|devices| devices.into_iter().map(|device| device.uri).collect()).unwrap_or_default()
+    }
+
+    /// Stops the stream and opens the device at `uri` instead, negotiated like this one, e.g. for
+    /// --stream-rotation. The description and descriptor follow.
+    pub fn switch(&mut self, uri: &str) -> Result<(), OpenError> {
+        let description = self.ctx.devices()?.into_iter().find(|device| device.uri == uri).ok_or(OpenError::NoDevice)?;
+        self.release();
+        self.description = description;
+        self.reopen()?;
+        self.modes = self.device.as_ref().and_then(|device| device.streams().ok()).unwrap_or_default();
+        Ok(())
+    }
+
+    /// Hands frames over as the driver delivers them, in a layout forced with --force-input-layout.
+    pub fn force_layout(&mut self, layout: PixelLayout) {
+        self.conversion = Conversion::Native(layout);
+        self.forced = Some(layout);
+    }
+
+    /// Reads the exposure and gain controls with every frame from now on, for --exposure-jump.
+    /// Without it `metadata` stays empty and frames cost no more ioctls than their own.
+    pub fn read_metadata(&mut self) {
+        self.read_metadata = true;
+    }
+
+    /// Bytes of the conversion buffer. The driver's own buffers are mapped, not allocated here.
+    pub fn buffer_bytes(&self) -> usize {
+        self.converted.capacity()
+    }
+
+    /// Exposure and gain of the latest frame, where the device has controls for them and
+    /// `read_metadata` was called.
+    pub fn metadata(&self) -> FrameMetadata {
+        self.metadata
+    }
+
+    /// The next frame, in the layout given by `layout` for the stream's pixel format.
+    pub fn next_frame(&mut self) -> Result<&[u8], String> {
+        let frame = self.stream
+            .as_mut()
+            .ok_or("stream is released")?
+            .next()
+            .ok_or("stream is dead")?                                       // Unwraps option.
+            .map_err(|err| format!("failed to capture frame: {err}"))?;     // Unwraps result.
+        if let Some(device) = self.device.as_ref().filter(|_| self.read_metadata) {
+            self.metadata = self.metadata_controls.read(device);
+        }
+        // A forced layout may not match what the driver sends at all, and a driver may hand over
+        // a short frame after an error. Downsampling needs all of it.
+        self.conversion.check(frame, self.descriptor.width as usize, self.descriptor.height as usize)?;
+        Ok(self.conversion.convert(frame, &mut self.converted))
+    }
+
+    /// Dequeues a frame without converting or even looking at it, keeping the driver queue
+    /// drained between processed frames.
+    pub fn skip_frame(&mut self) -> Result<(), String> {
+        self.stream
+            .as_mut()
+            .ok_or("stream is released")?
+            .next()
+            .ok_or("stream is dead")?
+            .map(|_| ())
+            .map_err(|err| format!("failed to capture frame: {err}"))
+    }
+}
+
+
+/// The controls a device reports its exposure and gain in, by id. V4L2 marks the exposure
+/// inactive while auto exposure is on, but reading it still tells what the camera picked, so
+/// controls are taken if they can be read at all rather than by their flags.
+#[derive(Debug, Clone, Copy, Default)]
+struct MetadataControls {
+    exposure: Option<u32>,
+    gain: Option<u32>,
+}
+
+
+impl MetadataControls {
+
+    // The number controls named like exposure, e.g. "Exposure Time, Absolute", and gain, e.g.
+    // "Gain" or "Analogue Gain", that answer. Their automatic switches are booleans and menus.
+    fn find(device: &PlatformDevice) -> Self {
+        let controls = device.controls().unwrap_or_default();
+        let find = |wanted: &str| controls.iter()
+            .filter(|control| matches!(control.typ, control::Type::Number { .. }))
+            .filter(|control| {
+                let name = control.name.to_lowercase();
+                name.contains(wanted) && !name.contains("auto")
+            })
+            .map(|control| control.id)
+            .find(|id| matches!(device.control(*id), Ok(control::State::Number(_))));
+        Self { exposure: find("exposure"), gain: find("gain") }
+    }
+
+    fn read(&self, device: &PlatformDevice) -> FrameMetadata {
+        let read = |id: Option<u32>| match id.map(|id| device.control(id)) {
+            Some(Ok(control::State::Number(value))) => Some(value),
+            _ => None,
+        };
+        FrameMetadata { exposure: read(self.exposure), gain: read(self.gain) }
+    }
+}
+
+
+/// Starts a stream on the device at the requested size and interval, in the most useful of the
+/// pixel formats it advertises that can be started. Progress goes to `log`.
+fn start<'a>(
+    device: &PlatformDevice<'a>, width: u32, height: u32, interval: Duration, log: &dyn Fn(&str), progress: &dyn Fn(Progress)
+) -> Result<(Descriptor, PlatformStream<'a>, Conversion), OpenError> {
+    let streams = device.streams()?;
+    if streams.is_empty() {
+        log("\nWarning, no video streams detected.");
+    }
+
+    // // TODO: Only pick a stream if it satisfies the required video specs (resolution, frame rate)
+    // Try the formats the device advertises from the most to the least useful, since the first
+    // stream isn't necessarily a video one (e.g. depth or metadata). Without streams, hope for RGB.
+    let mut formats: Vec<PixelFormat> = Vec::new();
+    for stream in &streams {
+        if !formats.contains(&stream.pixfmt) {
+            formats.push(stream.pixfmt.clone());
+        }
+    }
+    if formats.is_empty() {
+        formats.push(PixelFormat::Rgb(24));
+    }
+    formats.sort_by_key(format_rank);
+
+    // Since we want to capture images, we need to access the native image stream of the device.
+    // The backend will internally select a suitable implementation for the platform stream. On
+    // Linux for example, most devices support memory-mapped buffers.
+    for pixfmt in formats {
+        let Some(conversion) = Conversion::for_format(&pixfmt) else {
+            log(&format!("Skipping pixel format {pixfmt}, it can't be converted"));
+            continue;
+        };
+        progress(Progress::new(Phase::Negotiating, pixfmt.to_string()));
+        let descriptor = Descriptor{ width, height, interval, pixfmt };
+        match device.start_stream(&descriptor) {
+            Ok(stream) => {
+                log(&format!("Started stream with pixel format {}", descriptor.pixfmt));
+                return Ok((descriptor, stream, conversion));
+            }
+            Err(err) => log(&format!("Can't start stream with pixel format {}: {err}", descriptor.pixfmt)),
+        }
+    }
+    log("\nError, no usable pixel format. Advertised streams:");
+    for stream in &streams {
+        log(&format!("    {} {}x{} {:.1?}", stream.pixfmt, stream.width, stream.height, stream.interval));
+    }
+    Err(OpenError::NoUsableFormat)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yuyv_frames_are_reduced_to_luma() {
+        let conversion = Conversion::for_format(&PixelFormat::Custom("YUYV".to_string())).unwrap();
+        assert_eq!(layout(&PixelFormat::Custom("YUYV".to_string()), None), PixelLayout::Gray);
+        let mut converted = Vec::new();
+        assert_eq!(conversion.convert(&[10, 128, 20, 130, 30, 128, 40, 130], &mut converted), [10, 20, 30, 40]);
+    }
+
+
+    #[test]
+    fn grey_frames_are_used_as_they_are() {
+        assert_eq!(Conversion::for_format(&PixelFormat::Gray(8)), Some(Conversion::Native(PixelLayout::Gray)));
+        assert_eq!(channels(&PixelFormat::Gray(8), None), 1);
+        assert_eq!(channels(&PixelFormat::Gray(8), Some(PixelLayout::Rgb)), 3);
+    }
+
+
+    #[test]
+    fn short_frames_are_refused_in_every_format() {
+        let frame = vec![0; 4 * 2 * 2 - 1];
+        assert!(Conversion::Yuyv.check(&frame, 4, 2).is_err());
+        assert!(Conversion::Yuyv.check(&frame[.. 4 * 2], 2, 2).is_ok());
+        assert!(Conversion::Native(PixelLayout::Gray).check(&frame, 4, 2).is_ok());
+        assert!(Conversion::Native(PixelLayout::Rgb).check(&frame, 4, 2).is_err());
+    }
+
+
+    #[test]
+    fn following_a_stream_tells_when_its_format_changed() {
+        let descriptor = |pixfmt| Descriptor { width: 8, height: 6, interval: Duration::from_millis(100), pixfmt };
+        let mut format = StreamFormat::new(&descriptor(PixelFormat::Rgb(24)), None);
+        assert_eq!(format.follow(&descriptor(PixelFormat::Rgb(24)), None), None);
+        let previous = format.follow(&descriptor(PixelFormat::Custom("YUYV".to_string())), None).unwrap();
+        assert_eq!((previous.pixfmt, previous.layout), (PixelFormat::Rgb(24), PixelLayout::Rgb));
+        assert_eq!(format.layout, PixelLayout::Gray);
+        // Forced, the layout stays, only the format changed.
+        let previous = format.follow(&descriptor(PixelFormat::Bgr(32)), Some(PixelLayout::Gray)).unwrap();
+        assert_eq!((previous.layout, format.layout), (PixelLayout::Gray, PixelLayout::Gray));
+    }
+}
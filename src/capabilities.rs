@@ -0,0 +1,146 @@
+use crate::{ camera, json, source::RawFormat };
+
+/// The crate version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The commit the binary was built from, set by build.rs, "unknown" outside of a git checkout.
+pub const GIT_COMMIT: &str = env!("MOTION_DETECT_GIT_COMMIT");
+
+/// Every optional cargo feature, and whether this binary was built with it. The Linux only ones
+/// count as missing elsewhere, their modules aren't built.
+pub const FEATURES: [(&str, bool); 10] = [
+    ("async", cfg!(feature = "async")),
+    ("desktop-notify", cfg!(feature = "desktop-notify")),
+    ("gpio", cfg!(all(feature = "gpio", target_os = "linux"))),
+    ("gpu", cfg!(feature = "gpu")),
+    ("mdns", cfg!(feature = "mdns")),
+    ("serde", cfg!(feature = "serde")),
+    ("smtp", cfg!(feature = "smtp")),
+    ("soak-test", cfg!(feature = "soak-test")),
+    ("uinput", cfg!(all(feature = "uinput", target_os = "linux"))),
+    ("zmq", cfg!(feature = "zmq")),
+];
+
+/// Input sources, as given to --input. Network cameras need ffmpeg at runtime.
+pub const INPUTS: [&str; 3] = ["camera", "stdin", "url"];
+
+// Output sinks and the feature each needs, if any.
+const OUTPUTS: [(&str, Option<&str>); 12] = [
+    ("events", None),               // --event-output
+    ("commands", None),             // --on-start and the other hooks
+    ("snapshots", None),
+    ("timelapse", None),
+    ("http", None),                 // GET /status, /config and /capabilities
+    ("websocket", None),
+    ("exit-report", None),
+    ("desktop-notify", Some("desktop-notify")),
+    ("email", Some("smtp")),
+    ("gpio", Some("gpio")),
+    ("uinput", Some("uinput")),
+    ("zmq", Some("zmq")),
+];
+
+
+/// True if this binary was built with the cargo feature.
+pub fn has(feature: &str) -> bool {
+    FEATURES.iter().any(|(name, enabled)| *name == feature && *enabled)
+}
+
+
+/// The features this binary was built with.
+pub fn features() -> Vec<&'static str> {
+    FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect()
+}
+
+
+/// The output sinks this binary can write to.
+pub fn outputs() -> Vec<&'static str> {
+    OUTPUTS.iter()
+        .filter(|(_, feature)| feature.is_none_or(has))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+
+/// Fails unless this binary was built with `feature`, which `option` needs.
+pub fn require(feature: &str, option: &str) -> Result<(), String> {
+    if has(feature) {
+        return Ok(());
+    }
+    let linux = if matches!(feature, "gpio" | "uinput") { ", which is only available on Linux" } else { "" };
+    Err(format!("{option} requested but this binary was built without the {feature} feature{linux}"))
+}
+
+
+/// E.g. "motion-detect 0.1.0 (3f2a9c1d04be)".
+pub fn version() -> String {
+    format!("motion-detect {VERSION} ({GIT_COMMIT})")
+}
+
+
+/// The object printed by --version --json and served at GET /capabilities.
+pub fn to_json() -> String {
+    json::Object::new()
+        .field("type", "capabilities")
+        .field("version", VERSION)
+        .field("git_commit", GIT_COMMIT)
+        .field("features", features())
+        .field("inputs", INPUTS.to_vec())
+        .field("input_formats", RawFormat::ALL.iter().map(RawFormat::name).collect::<Vec<_>>())
+        .field("camera_formats", camera::PIXEL_FORMATS.to_vec())
+        .field("outputs", outputs())
+        .finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each feature as the compiler saw it, apart from FEATURES.
+    fn compiled() -> Vec<&'static str> {
+        let mut compiled = Vec::new();
+        if cfg!(feature = "async") { compiled.push("async"); }
+        if cfg!(feature = "desktop-notify") { compiled.push("desktop-notify"); }
+        if cfg!(all(feature = "gpio", target_os = "linux")) { compiled.push("gpio"); }
+        if cfg!(feature = "gpu") { compiled.push("gpu"); }
+        if cfg!(feature = "mdns") { compiled.push("mdns"); }
+        if cfg!(feature = "serde") { compiled.push("serde"); }
+        if cfg!(feature = "smtp") { compiled.push("smtp"); }
+        if cfg!(feature = "soak-test") { compiled.push("soak-test"); }
+        if cfg!(all(feature = "uinput", target_os = "linux")) { compiled.push("uinput"); }
+        if cfg!(feature = "zmq") { compiled.push("zmq"); }
+        compiled
+    }
+
+
+    #[test]
+    fn the_features_reported_are_those_compiled_in() {
+        // Run with the default features and e.g. --features async,serde,zmq,mdns.
+        let compiled = compiled();
+        assert_eq!(features(), compiled);
+        let listed: Vec<String> = compiled.iter().map(|feature| format!("\"{feature}\"")).collect();
+        assert!(to_json().contains(&format!("\"features\":[{}]", listed.join(","))), "{}", to_json());
+    }
+
+
+    #[test]
+    fn outputs_needing_a_feature_are_reported_with_it() {
+        let outputs = outputs();
+        assert_eq!(outputs.contains(&"email"), cfg!(feature = "smtp"));
+        assert_eq!(outputs.contains(&"zmq"), cfg!(feature = "zmq"));
+        assert_eq!(outputs.contains(&"desktop-notify"), cfg!(feature = "desktop-notify"));
+        assert!(outputs.contains(&"events") && outputs.contains(&"http"));
+    }
+
+
+    #[test]
+    fn a_missing_feature_is_named_when_required() {
+        let required = require("zmq", "--zmq");
+        if cfg!(feature = "zmq") {
+            assert_eq!(required, Ok(()));
+        } else {
+            assert_eq!(required, Err("--zmq requested but this binary was built without the zmq feature".to_string()));
+        }
+    }
+}
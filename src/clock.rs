@@ -0,0 +1,392 @@
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use crate::sequence::FrameCounter;
+
+/// Differences between the system and monotonic clocks up to this are slewing, not a step.
+pub const DEFAULT_STEP_THRESHOLD: Duration = Duration::from_secs(2);
+
+// Offsets kept for times before the latest resumes.
+const MAX_ANCHORS: usize = 64;
+
+/// Where a frame's capture time came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TimeSource {
+    /// Capture timestamp provided by the driver along with the buffer.
+    Driver,
+    /// Time the frame was handed to us, the best we can do when the backend provides no timestamp.
+    Arrival,
+}
+
+
+impl TimeSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeSource::Driver => "driver",
+            TimeSource::Arrival => "arrival",
+        }
+    }
+}
+
+
+/// When a frame was captured, on the monotonic clock.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTime {
+    pub instant: Instant,
+    pub source: TimeSource,
+    pub sequence: u64,      // Capture sequence number, see sequence::FrameCounter.
+}
+
+
+impl FrameTime {
+
+    /// Stamps a frame that just arrived. The eye HAL doesn't expose buffer timestamps, so this is
+    /// currently the only source. The frame is numbered by `counter`.
+    pub fn arrival(counter: &mut FrameCounter) -> Self {
+        let instant = Instant::now();
+        Self { instant, source: TimeSource::Arrival, sequence: counter.captured(instant) }
+    }
+}
+
+
+/// Converts monotonic times to wall-clock times using an offset captured at startup, so that all
+/// events of a run stay consistently spaced even if the system clock is slewed.
+///
+/// Boards without a real-time clock boot in 1970 and step forward once NTP syncs. A step larger
+/// than the threshold between two `check`s replaces the offset, for times before it too: they
+/// are recomputed from their monotonic distance to the sane clock. A resume from suspend only
+/// changes the offset from then on, the monotonic clock stood still while the wall clock didn't.
+pub struct WallClock {
+    anchors: Vec<(Instant, SystemTime)>,    // Where the offset changed, oldest first, never empty.
+    step_threshold: Duration,
+    stepped_at: Option<Instant>,            // When the latest step was noticed.
+}
+
+
+/// A step of the system clock, noticed at `at`, by `offset` seconds, negative when back in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockStep {
+    pub at: Instant,
+    pub offset: f64,
+}
+
+
+impl WallClock {
+
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now(), SystemTime::now())
+    }
+
+    /// As if started with the system clock reading `system` at `instant`, e.g. in a simulation.
+    pub fn starting_at(instant: Instant, system: SystemTime) -> Self {
+        Self { anchors: vec![(instant, system)], step_threshold: DEFAULT_STEP_THRESHOLD, stepped_at: None }
+    }
+
+    /// Differences between the clocks up to this are drift, not steps.
+    pub fn with_step_threshold(mut self, threshold: Duration) -> Self {
+        self.step_threshold = threshold;
+        self
+    }
+
+    pub fn to_system(&self, instant: Instant) -> SystemTime {
+        let (anchor, system) = self.anchors.iter().rev()
+            .find(|(anchor, _)| *anchor <= instant)
+            .unwrap_or(&self.anchors[0]);
+        if instant >= *anchor {
+            *system + (instant - *anchor)
+        } else {
+            *system - (*anchor - instant)
+        }
+    }
+
+    /// Compares the system clock, reading `system` at `now`, with where the offset puts it. Call
+    /// once per frame, with `check_now` outside of simulations.
+    pub fn check(&mut self, now: Instant, system: SystemTime) -> Option<ClockStep> {
+        let expected = self.to_system(now);
+        let offset = match system.duration_since(expected) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(behind) => -behind.duration().as_secs_f64(),
+        };
+        if offset.abs() <= self.step_threshold.as_secs_f64() {
+            return None;
+        }
+        self.anchors = vec![(now, system)];
+        self.stepped_at = Some(now);
+        Some(ClockStep { at: now, offset })
+    }
+
+    pub fn check_now(&mut self) -> Option<ClockStep> {
+        self.check(Instant::now(), SystemTime::now())
+    }
+
+    /// The system was suspended until `now`, when the system clock read `system`. Times before
+    /// keep their offset.
+    pub fn resumed(&mut self, now: Instant, system: SystemTime) {
+        self.anchors.push((now, system));
+        if self.anchors.len() > MAX_ANCHORS {
+            self.anchors.remove(0);
+        }
+    }
+
+    /// True if the clock stepped after `instant`, so what was reported as of then had the old time.
+    pub fn stepped_since(&self, instant: Instant) -> bool {
+        self.stepped_at.is_some_and(|stepped_at| stepped_at > instant)
+    }
+}
+
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Notices when the system was suspended, from the time the boot clock counts and the monotonic
+/// clock `Instant` uses doesn't. Linux only, nothing is noticed elsewhere.
+pub struct SuspendWatch {
+    asleep: Duration,
+}
+
+
+impl SuspendWatch {
+
+    pub fn new() -> Self {
+        Self { asleep: time_asleep().unwrap_or_default() }
+    }
+
+    /// How long the system was suspended since the previous call, if longer than `threshold`.
+    pub fn check(&mut self, threshold: Duration) -> Option<Duration> {
+        self.update(time_asleep()?, threshold)
+    }
+
+    /// Like `check`, with the time asleep since boot given, e.g. in a simulation.
+    pub fn update(&mut self, asleep: Duration, threshold: Duration) -> Option<Duration> {
+        let slept = asleep.saturating_sub(self.asleep);
+        self.asleep = asleep;
+        (slept > threshold).then_some(slept)
+    }
+}
+
+
+impl Default for SuspendWatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    use std::os::raw::{ c_int, c_long };
+
+    pub const CLOCK_MONOTONIC: c_int = 1;
+    pub const CLOCK_BOOTTIME: c_int = 7;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Timespec {
+        pub tv_sec: c_long,
+        pub tv_nsec: c_long,
+    }
+
+    extern "C" {
+        pub fn clock_gettime(clock: c_int, time: *mut Timespec) -> c_int;
+    }
+}
+
+
+// Time spent suspended since boot: what the boot clock counts beyond the monotonic clock.
+#[cfg(target_os = "linux")]
+fn time_asleep() -> Option<Duration> {
+    let read = |clock| {
+        let mut time = ffi::Timespec::default();
+        match unsafe { ffi::clock_gettime(clock, &mut time) } {
+            0 => Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32)),
+            _ => None,
+        }
+    };
+    Some(read(ffi::CLOCK_BOOTTIME)?.saturating_sub(read(ffi::CLOCK_MONOTONIC)?))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn time_asleep() -> Option<Duration> {
+    None
+}
+
+
+/// A wall-clock time broken down into its calendar fields, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+
+impl From<SystemTime> for UtcTime {
+    fn from(time: SystemTime) -> Self {
+        let seconds = time.duration_since(UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+        let (days, seconds) = ((seconds / 86400) as i64, (seconds % 86400) as u32);
+
+        // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Self { year, month, day, hour: seconds / 3600, minute: seconds / 60 % 60, second: seconds % 60 }
+    }
+}
+
+
+/// A wall-clock time in the local time zone, as the C library has it from TZ or the system's zone
+/// files. Systems other than unix ones have no such thing here, their local time is UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub weekday: u32,       // From 0 for Monday to 6 for Sunday.
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,        // Up to 60 during a leap second.
+    pub utc_offset: i32,    // Seconds ahead of UTC, daylight saving time included.
+}
+
+
+impl From<SystemTime> for LocalTime {
+    fn from(time: SystemTime) -> Self {
+        let seconds = time.duration_since(UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+        local_time(seconds).unwrap_or_else(|| {
+            let utc = UtcTime::from(time);
+            // The epoch was a Thursday.
+            Self { weekday: ((seconds / 86400 + 3) % 7) as u32, hour: utc.hour, minute: utc.minute, second: utc.second, utc_offset: 0 }
+        })
+    }
+}
+
+
+#[cfg(unix)]
+mod tz {
+    use std::os::raw::{ c_char, c_int, c_long };
+
+    #[repr(C)]
+    pub struct Tm {
+        pub tm_sec: c_int,
+        pub tm_min: c_int,
+        pub tm_hour: c_int,
+        pub tm_mday: c_int,
+        pub tm_mon: c_int,
+        pub tm_year: c_int,
+        pub tm_wday: c_int,     // From 0 for Sunday.
+        pub tm_yday: c_int,
+        pub tm_isdst: c_int,
+        pub tm_gmtoff: c_long,
+        pub tm_zone: *const c_char,
+    }
+
+    extern "C" {
+        pub fn tzset();
+        // time_t is a C long on the systems motion-detect runs on.
+        pub fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+    }
+}
+
+
+#[cfg(unix)]
+fn local_time(seconds: u64) -> Option<LocalTime> {
+    static TZSET: std::sync::Once = std::sync::Once::new();
+    TZSET.call_once(|| unsafe { tz::tzset() });
+    let time = std::os::raw::c_long::try_from(seconds).ok()?;
+    let mut tm = std::mem::MaybeUninit::<tz::Tm>::uninit();
+    if unsafe { tz::localtime_r(&time, tm.as_mut_ptr()) }.is_null() {
+        return None;
+    }
+    let tm = unsafe { tm.assume_init() };
+    Some(LocalTime {
+        weekday: ((tm.tm_wday + 6) % 7) as u32,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+        second: tm.tm_sec as u32,
+        utc_offset: tm.tm_gmtoff as i32,
+    })
+}
+
+#[cfg(not(unix))]
+fn local_time(_seconds: u64) -> Option<LocalTime> {
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A board without a real-time clock, booted in 1970.
+    fn booted() -> (Instant, SystemTime, WallClock) {
+        let (instant, system) = (Instant::now(), UNIX_EPOCH + Duration::from_secs(30));
+        (instant, system, WallClock::starting_at(instant, system))
+    }
+
+
+    #[test]
+    fn drift_within_the_threshold_is_no_step() {
+        let (start, system, mut clock) = booted();
+        let later = start + Duration::from_secs(60);
+        assert_eq!(clock.check(later, system + Duration::from_millis(61_500)), None);
+        assert_eq!(clock.to_system(later), system + Duration::from_secs(60));
+        assert!(!clock.stepped_since(start));
+    }
+
+
+    #[test]
+    fn an_ntp_step_corrects_the_times_before_it() {
+        let (start, _, mut clock) = booted();
+        let event = start + Duration::from_secs(10);
+        let synced = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let step = clock.check(start + Duration::from_secs(90), synced).unwrap();
+        assert_eq!(step.at, start + Duration::from_secs(90));
+        assert!(step.offset > 1_700_000_000.0);
+        // An event 80s before the step happened 80s before the synced time.
+        assert_eq!(clock.to_system(event), synced - Duration::from_secs(80));
+        assert!(clock.stepped_since(event));
+        assert!(!clock.stepped_since(start + Duration::from_secs(90)));
+    }
+
+
+    #[test]
+    fn a_step_back_is_negative() {
+        let (start, system, mut clock) = booted();
+        let step = clock.check(start + Duration::from_secs(10), system).unwrap();
+        assert_eq!(step.offset, -10.0);
+    }
+
+
+    #[test]
+    fn a_resume_keeps_the_times_before_it() {
+        let (start, system, mut clock) = booted();
+        // Suspended for an hour after 10s, which the monotonic clock didn't count.
+        let resumed = start + Duration::from_secs(10);
+        let awake = system + Duration::from_secs(3610);
+        clock.resumed(resumed, awake);
+        assert_eq!(clock.to_system(start + Duration::from_secs(5)), system + Duration::from_secs(5));
+        assert_eq!(clock.to_system(resumed + Duration::from_secs(5)), awake + Duration::from_secs(5));
+        assert_eq!(clock.check(resumed + Duration::from_secs(5), awake + Duration::from_secs(5)), None);
+    }
+
+
+    #[test]
+    fn time_asleep_beyond_the_threshold_is_a_suspend() {
+        let mut watch = SuspendWatch { asleep: Duration::from_secs(100) };
+        let threshold = Duration::from_millis(500);
+        assert_eq!(watch.update(Duration::from_millis(100_200), threshold), None);
+        assert_eq!(watch.update(Duration::from_secs(700), threshold), Some(Duration::from_millis(599_800)));
+        assert_eq!(watch.update(Duration::from_secs(700), threshold), None);
+    }
+}
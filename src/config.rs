@@ -0,0 +1,361 @@
+use std::{ path::PathBuf, time::{ Duration, SystemTime } };
+use eye::hal::{ device::Description, stream::Descriptor };
+
+use crate::{
+    camera,
+    control::ControlBounds,
+    decimation::Decimation,
+    exit_report,
+    ffmpeg,
+    json::{ self, Object },
+    output::Format,
+    settings::{ Input, InputEvent, Settings },
+    zones::Zone,
+};
+
+/// The configuration that actually took effect: the settings after the source negotiated its stream
+/// and the thresholds were converted to thumbnail units. Reported at startup, at GET /config and by
+/// --dump-config, so a run can be understood (and reproduced) from its output alone.
+pub struct EffectiveConfig<'a> {
+    pub settings: &'a Settings,
+    pub device: &'a Description,
+    pub stream: &'a Descriptor,
+    pub thumb_width: usize,
+    pub thumb_height: usize,
+    pub channels: usize,
+    pub downsample: usize,              // May differ from the settings under a CPU budget.
+    pub capture_interval: Duration,     // Same.
+    pub pixel_percent: f32,             // The thresholds as set, which may have changed since startup...
+    pub image_percent: f32,
+    pub sustain_percent: f32,
+    pub pixel_threshold: i32,           // ...converted, from 0 to 255.
+    pub start_pixels: i32,              // Changed thumbnail pixels that start a movement...
+    pub sustain_pixels: i32,            // ...and that keep it going.
+}
+
+
+impl EffectiveConfig<'_> {
+
+    pub fn to_json(&self) -> String {
+        self.to_object().field("time", json::unix_time(SystemTime::now())).finish()
+    }
+
+    /// Identifies the configuration, as the exit report's config_hash.
+    pub fn hash(&self) -> u64 {
+        exit_report::fnv1a(self.to_object().finish().as_bytes())
+    }
+
+    /// The configuration object without its time, the same for the same configuration.
+    pub fn to_object(&self) -> Object {
+        let settings = self.settings;
+        let stream = Object::new()
+            .field("width", self.stream.width as u64)
+            .field("height", self.stream.height as u64)
+            .field("pixel_format", self.stream.pixfmt.to_string())
+            .field("layout", camera::layout(&self.stream.pixfmt, settings.force_input_layout).name())
+            .field("deinterlace", settings.deinterlace.map(|mode| mode.name()))
+            .field("interval", self.stream.interval.as_secs_f64());
+        let thumbnail = Object::new()
+            .field("width", self.thumb_width)
+            .field("height", self.thumb_height)
+            .field("channels", self.channels)
+            .field("downsample", self.downsample)
+            .field("temporal_average", settings.temporal_average)
+            .field("stats", settings.thumb_stats.name());
+        let thresholds = Object::new()
+            .field("pixel_percent", self.pixel_percent)
+            .field("pixel", self.pixel_threshold)
+            .field("image_percent", self.image_percent)
+            .field("image_pixels", self.start_pixels)
+            .field("sustain_percent", self.sustain_percent)
+            .field("sustain_pixels", self.sustain_pixels)
+            .field("confirm_frames", settings.confirm_frames as u64)
+            .field("edge_percent", (settings.algorithm == "edges").then_some(settings.edge_threshold))
+            .field("edge", (settings.algorithm == "edges").then(|| settings.edge_level()))
+            .field("control_bounds", settings.control_bounds.specs())
+            .field("storm_revert", settings.storm_movements.map(|movements| Object::new()
+                .field("movements", movements)
+                .field("window", settings.storm_window.as_secs_f64())));
+        let noise = (settings.algorithm == "adaptive").then(|| Object::new()
+            .field("k", settings.noise_k)
+            .field("floor", settings.noise_floor)
+            .field("ceiling", settings.noise_ceiling));
+        let saturated = Object::new()
+            .field("policy", settings.saturated_policy.name())
+            .field("floor", settings.saturated_floor)
+            .field("ceiling", settings.saturated_ceiling);
+        let exposure = settings.exposure_jump.map(|jump| Object::new()
+            .field("jump", jump)
+            .field("raise", settings.exposure_raise));
+        let timing = Object::new()
+            .field("warm_up", settings.camera_warm_up.as_secs_f64())
+            .field("motion_tail", settings.motion_tail_length.as_secs_f64())
+            .field("tail_frames", settings.tail_frames.map(|frames| frames as u64))
+            .field("warm_up_frames", settings.warm_up_frames.map(|frames| frames as u64))
+            .field("capture_interval", self.capture_interval.as_secs_f64())
+            .field("max_event_duration", settings.max_event_duration.map(|duration| duration.as_secs_f64()))
+            .field("cpu_budget", settings.cpu_budget)
+            .field("max_memory", settings.max_memory)
+            .field("print_interval", settings.print_interval.map(|every| every.as_secs_f64()))
+            .field("pause_mode", settings.pause_mode.name())
+            .field("resume_policy", settings.resume_policy.name())
+            .field("clock_step_threshold", settings.clock_step_threshold.as_secs_f64())
+            .field("process_every", match settings.decimation { Some(Decimation::Every(every)) => Some(every as u64), _ => None })
+            .field("detect_fps", match settings.decimation { Some(Decimation::Fps(fps)) => Some(fps), _ => None })
+            .field("stream_dwell", (!settings.stream_rotation.is_empty()).then_some(settings.stream_dwell.as_secs_f64()))
+            .field("stream_warm_up", (!settings.stream_rotation.is_empty()).then_some(settings.stream_warm_up.as_secs_f64()))
+            .field("scene_settle", settings.expect_scene_change.then_some(settings.scene_settle.as_secs_f64()));
+        let outputs = Object::new()
+            .field("format", format_name(settings.format))
+            .field("event_output", settings.event_output.to_string())
+            .field("event_output_lossy", settings.event_output_lossy)
+            .field("log_dedup_window", settings.log_dedup_window.as_secs_f64())
+            .field("log_dedup_first", settings.log_dedup_first as u64)
+            .field("events_include_source", settings.events_include_source)
+            .field("report_padding", settings.report_padding.map(|padding| padding.as_secs_f64()))
+            .field("http", settings.http_address.clone())
+            .field("http_control", settings.http_token.is_some())
+            .field("mdns", settings.mdns)
+            .field("publish_mask", settings.publish_mask)
+            .field("state_file", path(&settings.state_file))
+            .field("state_dir", path(&settings.state_dir))
+            .field("reference_file", path(&settings.reference_file))
+            .field("snapshot_dir", path(&settings.snapshot_dir))
+            .field("snapshot_privacy", settings.snapshot_privacy.map(|privacy| privacy.name()))
+            .field("timelapse_dir", path(&settings.timelapse_dir))
+            .field("media_queue", settings.media_queue)
+            .field("media_overflow", settings.media_overflow.name())
+            .field("media_wait", settings.media_wait.as_secs_f64())
+            .field("sidecar", !settings.no_sidecar)
+            .field("overlay", settings.overlay.is_some())
+            .field("notify", settings.notify)
+            .field("smtp_server", settings.smtp_server.clone())
+            .field("smtp_to", settings.smtp_server.as_ref().map(|_| settings.smtp_to.clone()))
+            .field("email_spool", path(&settings.email_spool))
+            .field("uinput", settings.uinput.map(|event| input_event_name(&event)))
+            .field("gpio_pin", settings.gpio_pin.map(|pin| pin as u64))
+            .field("gpio_chip", settings.gpio_pin.map(|_| settings.gpio_chip.as_str()))
+            .field("hooks", self.hooks())
+            .field("bell", settings.bell)
+            .field("play_sound", path(&settings.play_sound))
+            .field("stop_sound", path(&settings.stop_sound))
+            .field("verify_command", settings.verify_command.clone())
+            .field("verify_timeout", settings.verify_command.as_ref().map(|_| settings.verify_timeout.as_secs_f64()))
+            .field("verify_timeout_action", settings.verify_command.as_ref().map(|_| settings.verify_timeout_action.name()))
+            .field("quiet_hours", settings.quiet_hours.as_ref().map(|hours| hours.to_string()));
+
+        json::Object::new()
+            .field("type", "config")
+            .field("version", env!("CARGO_PKG_VERSION"))
+            .field("input", input_name(&settings.input))
+            .field("uri", self.device.uri.as_str())
+            .field("product", self.device.product.as_str())
+            .field("name", self.settings.name.as_deref().unwrap_or(&self.device.product))
+            .field("stream", stream)
+            .field("thumbnail", thumbnail)
+            .field("thresholds", thresholds)
+            .field("algorithm", settings.algorithm.as_str())
+            .field("blur", settings.blur)
+            .field("normalize", settings.normalize.map(|mode| mode.name()))
+            .field("stabilize", settings.stabilize.then_some(settings.stabilize_max_shift))
+            .field("wb_compensation", settings.wb_compensation)
+            .field("channels", settings.channels.name())
+            .field("flicker_rejection", settings.flicker_rejection)
+            .field("diff_early_exit", settings.early_exit())
+            .field("mask", path(&settings.mask_file))
+            .field("weight_map", path(&settings.weight_map_file))
+            .field("noise", noise)
+            .field("saturated", saturated)
+            .field("exposure", exposure)
+            .field("zones", self.zones())
+            .field("zone_outlines", self.zone_outlines())
+            .field("zone_schedules", self.settings.zones.iter()
+                .filter_map(|zone| zone.schedule.as_ref().map(|schedule| format!("{}={schedule}", zone.name)))
+                .collect::<Vec<_>>())
+            .field("stream_rotation", (!settings.stream_rotation.is_empty()).then(|| settings.stream_rotation.iter()
+                .map(|stream| format!("{}={}", stream.name, stream.uri))
+                .collect::<Vec<_>>()))
+            .field("timing", timing)
+            .field("outputs", outputs)
+    }
+
+    /// The same information as a few lines for text mode.
+    pub fn text(&self) -> Vec<String> {
+        let settings = self.settings;
+        let zones = self.zones();
+        let mut outputs = vec![format!("{} events to {}", format_name(settings.format), settings.event_output)];
+        if let Some(address) = &settings.http_address {
+            outputs.push(format!("http {address}{}{}",
+                if settings.http_token.is_some() { " with control" } else { "" },
+                if settings.mdns { ", announced over mDNS" } else { "" }));
+        }
+        if let Some(rate) = settings.publish_mask {
+            outputs.push(format!("masks at /mask, up to {rate}/s"));
+        }
+        if let Some(state_file) = path(&settings.state_file) {
+            outputs.push(format!("state file {state_file}"));
+        }
+        if let Some(state_dir) = path(&settings.state_dir) {
+            outputs.push(format!("installation id and event sequence in {state_dir}"));
+        }
+        if let Some(reference_file) = path(&settings.reference_file) {
+            outputs.push(format!("scene reference {reference_file} every {:.0?}", settings.reference_check_interval));
+        }
+        if let Some(snapshot_dir) = path(&settings.snapshot_dir) {
+            outputs.push(match settings.snapshot_privacy {
+                Some(privacy) => format!("snapshots to {snapshot_dir} ({} outside movements)", privacy.name()),
+                None => format!("snapshots to {snapshot_dir}"),
+            });
+        }
+        if let Some(timelapse_dir) = path(&settings.timelapse_dir) {
+            outputs.push(format!("timelapse to {timelapse_dir}"));
+        }
+        if settings.notify {
+            outputs.push(String::from("desktop notifications"));
+        }
+        if let Some(server) = &settings.smtp_server {
+            outputs.push(format!("email to {} via {server}", settings.smtp_to.join(", ")));
+        }
+        if let Some(event) = &settings.uinput {
+            outputs.push(format!("uinput {}", input_event_name(event)));
+        }
+        if let Some(pin) = settings.gpio_pin {
+            outputs.push(format!("gpio {} line {pin}", settings.gpio_chip));
+        }
+        let hooks = self.hooks();
+        if !hooks.is_empty() {
+            outputs.push(format!("commands on {}", hooks.join(", ")));
+        }
+        let mut sounds = Vec::new();
+        if settings.bell {
+            sounds.push(String::from("bell"));
+        }
+        if let Some(sound) = path(&settings.play_sound) {
+            sounds.push(format!("sound {sound}"));
+        }
+        if let Some(sound) = path(&settings.stop_sound) {
+            sounds.push(format!("stop sound {sound}"));
+        }
+        if !sounds.is_empty() {
+            outputs.push(sounds.join(", "));
+        }
+        if let Some(command) = &settings.verify_command {
+            outputs.push(format!(
+                "verified by {command}, {:?} timeout then {}",
+                settings.verify_timeout,
+                settings.verify_timeout_action.name(),
+            ));
+        }
+        if let Some(hours) = &settings.quiet_hours {
+            outputs.push(format!("quiet hours {hours} UTC"));
+        }
+        let algorithm = match settings.algorithm.as_str() {
+            "edges" => format!("edges, edge {}% ({}/255)", settings.edge_threshold, settings.edge_level()),
+            name => name.to_string(),
+        };
+        let mask = match (path(&settings.mask_file), &settings.mask) {
+            (Some(mask_file), Some(mask)) => format!(", mask {mask_file} ({:.1}% ignored)", mask.coverage() * 100.0),
+            _ => match (path(&settings.weight_map_file), &settings.mask) {
+                (Some(weight_map_file), Some(weights)) => format!(
+                    ", weight map {weight_map_file} (mean weight {:.0}%, {:.1}% ignored)", weights.mean_weight() * 100.0, weights.coverage() * 100.0
+                ),
+                _ => String::new(),
+            },
+        };
+        vec![
+            format!(
+                "Stream: {}x{} {} ({}) every {:.0?} from {} ({}){}",
+                self.stream.width, self.stream.height, self.stream.pixfmt,
+                camera::layout(&self.stream.pixfmt, settings.force_input_layout).name(), self.stream.interval,
+                self.device.uri, self.device.product,
+                settings.deinterlace.map_or(String::new(), |mode| format!(", deinterlaced by {}", mode.name())),
+            ),
+            format!(
+                "Thumbnail: {}x{}, {} channels, downsample {} ({}), temporal average {}, capture interval {:.0?}",
+                self.thumb_width, self.thumb_height, self.channels, self.downsample, settings.thumb_stats.name(),
+                settings.temporal_average, self.capture_interval,
+            ),
+            format!(
+                "Thresholds: pixel {}% ({}/255), image {}% ({} pixels), sustain {}% ({} pixels){}{}{}",
+                self.pixel_percent, self.pixel_threshold, self.image_percent, self.start_pixels,
+                self.sustain_percent, self.sustain_pixels,
+                settings.exposure_jump.map_or(String::new(), |jump| format!(
+                    ", exposure jumps of {jump}% raise the pixel one by {}%", settings.exposure_raise,
+                )),
+                match settings.control_bounds == ControlBounds::default() {
+                    true => String::new(),
+                    false => format!(", controlled within {}", settings.control_bounds.specs().join(" ")),
+                },
+                settings.storm_movements.map_or(String::new(), |movements| format!(
+                    ", changes reverted on {movements} movements within {:.0?}", settings.storm_window,
+                )),
+            ),
+            format!(
+                "Algorithm: {}, blur {}, normalize {}, channels {}{}{}{}{}{}",
+                algorithm, settings.blur, settings.normalize.map_or("none", |mode| mode.name()), settings.channels.name(),
+                if settings.stabilize { format!(", stabilized up to {} pixel(s)", settings.stabilize_max_shift) } else { String::new() },
+                settings.wb_compensation.map_or(String::new(), |tolerance| format!(", white balance compensated within {tolerance}%")),
+                if settings.flicker_rejection { ", flicker rejection" } else { "" }, mask,
+                if settings.early_exit() { ", early exit" } else { "" },
+            ),
+            format!("Zones: {}", if zones.is_empty() { String::from("none") } else { zones.join(" ") }),
+            format!("Outputs: {}", outputs.join(", ")),
+        ]
+    }
+
+    // Zones in the --zone syntax, so they can be pasted back.
+    fn zones(&self) -> Vec<String> {
+        self.settings.zones.iter().map(Zone::spec).collect()
+    }
+
+    // Vertices of each zone as [x, y] fractions of the frame, for drawing them over a preview.
+    fn zone_outlines(&self) -> Vec<Object> {
+        self.settings.zones.iter()
+            .map(|zone| Object::new()
+                .field("name", zone.name.clone())
+                .field("points", zone.outline().iter().map(|(x, y)| vec![*x, *y]).collect::<Vec<_>>()))
+            .collect()
+    }
+
+    // Events with a command, the commands themselves may hold secrets.
+    fn hooks(&self) -> Vec<String> {
+        let settings = self.settings;
+        [
+            ("provisional", &settings.on_provisional),
+            ("provisional_cancel", &settings.on_provisional_cancel),
+            ("start", &settings.on_start),
+            ("stop", &settings.on_stop),
+        ].iter().filter(|(_, command)| command.is_some()).map(|(name, _)| name.to_string()).collect()
+    }
+}
+
+
+// Network camera URLs are shown without their credentials.
+fn input_name(input: &Input) -> String {
+    match input {
+        Input::Camera => String::from("camera"),
+        Input::Stdin => String::from("stdin"),
+        Input::Url(url) => ffmpeg::redact_url(url),
+    }
+}
+
+
+fn input_event_name(event: &InputEvent) -> String {
+    match event {
+        InputEvent::Key(code) => format!("key:{code}"),
+        InputEvent::Switch(code) => format!("switch:{code}"),
+    }
+}
+
+
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::Text => "text",
+        Format::Json => "json",
+    }
+}
+
+
+fn path(path: &Option<PathBuf>) -> Option<String> {
+    path.as_ref().map(|path| path.display().to_string())
+}
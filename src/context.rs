@@ -0,0 +1,178 @@
+use std::{
+    fs,
+    path::{ Path, PathBuf },
+    time::SystemTime,
+};
+
+use crate::{ json::{ self, Object, Scalar }, thumbnail::Thumbnail };
+
+/// Version of the context directories, in the "format" field of their sidecar, bumped whenever
+/// the layout changes.
+pub const CONTEXT_FORMAT_VERSION: u64 = 1;
+
+/// Name of the sidecar, which marks a directory as an event's context.
+pub const SIDECAR: &str = "context.json";
+
+
+/// The thumbnails around a movement's start, saved by --capture-context-on-event to label false
+/// positives later and replayed by `motion-detect review`. Each start gets a directory of its own,
+/// named "<unix seconds>-<id>", so they sort by time:
+///
+/// | File           | Content                                                                    |
+/// |----------------|----------------------------------------------------------------------------|
+/// | previous.ppm   | The thumbnail the triggering one was compared against, PGM for luma (.pgm) |
+/// | current.ppm    | The thumbnail that started the movement, likewise                          |
+/// | mask.pgm       | The diff mask: 255 where a pixel changed, 0 elsewhere                      |
+/// | config.json    | The effective configuration, as at GET /config                             |
+/// | context.json   | The sidecar, see below                                                     |
+///
+/// Images are binary PPM (P6) or PGM (P5) with 8-bit samples and a header of single spaces or
+/// newlines, no comments. The sidecar is one flat JSON object: "format" (currently 1), "id",
+/// "time" (unix seconds), "frame", "width", "height" and "channels" of the thumbnails,
+/// "changed_pixels", "score" (percent changed) and "start_pixels" (changed pixels needed to start)
+/// as detected, "algorithm", "pixel_threshold" and "image_threshold" (percent) as configured, and
+/// "previous", "current", "mask" and "config", the names of the other files.
+///
+/// The thumbnails are those the detector compared, after temporal averaging and before any blur,
+/// normalization or mask, so replaying them with other options means something. Like the diff
+/// strategies, the capture keeps the last thumbnail that changed enough to count as movement as
+/// the reference, or the first one after the detector started over.
+pub struct ContextCapture {
+    directory: PathBuf,
+    reference: Option<Thumbnail>,
+}
+
+
+impl ContextCapture {
+
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory, reference: None }
+    }
+
+    /// The detector started over, its next thumbnail is the new reference.
+    pub fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    /// Called for every compared thumbnail, after its events. `movement` tells whether it changed
+    /// more than a movement's start needs, which makes it the reference.
+    pub fn compared(&mut self, thumb: &Thumbnail, movement: bool) {
+        match &mut self.reference {
+            Some(reference) if reference.same_shape(thumb) => if movement {
+                reference.pixels.copy_from_slice(&thumb.pixels);
+            },
+            _ => self.reference = Some(thumb.clone()),
+        }
+    }
+
+    /// Saves the context of movement `id`, started by `current` with the diff `mask`. The
+    /// `sidecar` gets the common fields added, `config` is the effective configuration as JSON.
+    /// Returns the directory it was saved in.
+    pub fn save(&self, id: u64, time: SystemTime, current: &Thumbnail, mask: &[u8], sidecar: Object, config: &str) -> Result<PathBuf, String> {
+        let reference = self.reference.as_ref()
+            .filter(|reference| reference.same_shape(current))
+            .ok_or("no reference thumbnail yet")?;
+        let directory = self.directory.join(format!("{:.0}-{id}", json::unix_time(time).floor()));
+        let extension = if current.channels == 1 { "pgm" } else { "ppm" };
+        let (previous_name, current_name) = (format!("previous.{extension}"), format!("current.{extension}"));
+        let mask = Thumbnail {
+            width: current.width,
+            height: current.height,
+            channels: 1,
+            pixels: mask[.. current.len()].iter().map(|changed| if *changed != 0 { 255 } else { 0 }).collect(),
+            extremes: Vec::new(),
+        };
+        let sidecar = sidecar
+            .field("format", CONTEXT_FORMAT_VERSION)
+            .field("id", id)
+            .field("time", json::unix_time(time))
+            .field("width", current.width)
+            .field("height", current.height)
+            .field("channels", current.channels)
+            .field("previous", previous_name.as_str())
+            .field("current", current_name.as_str())
+            .field("mask", "mask.pgm")
+            .field("config", "config.json");
+
+        let write = || -> std::io::Result<()> {
+            fs::create_dir_all(&directory)?;
+            fs::write(directory.join(&previous_name), reference.to_pnm())?;
+            fs::write(directory.join(&current_name), current.to_pnm())?;
+            fs::write(directory.join("mask.pgm"), mask.to_pnm())?;
+            fs::write(directory.join("config.json"), format!("{config}\n"))?;
+            // Last, so a directory with a sidecar is complete.
+            fs::write(directory.join(SIDECAR), sidecar.finish() + "\n")
+        };
+        write().map_err(|err| format!("{}: {err}", directory.display()))?;
+        Ok(directory)
+    }
+}
+
+
+/// An event's context as saved by `ContextCapture`.
+pub struct SavedContext {
+    pub directory: PathBuf,
+    pub id: u64,
+    pub previous: Thumbnail,
+    pub current: Thumbnail,
+    pub mask: Thumbnail,        // 255 where a pixel changed.
+    pub changed_pixels: u64,
+    pub start_pixels: u64,
+}
+
+
+impl SavedContext {
+
+    pub fn load(directory: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(directory.join(SIDECAR)).map_err(|err| format!("can't read {SIDECAR}: {err}"))?;
+        let fields = json::parse_flat(&text).map_err(|err| format!("invalid {SIDECAR}: {err}"))?;
+        let field = |name: &str| fields.iter().find(|(field, _)| field == name).map(|(_, value)| value);
+        let number = |name: &str| match field(name) {
+            Some(Scalar::Number(number)) if *number >= 0.0 => Ok(*number as u64),
+            _ => Err(format!("{SIDECAR} has no {name}")),
+        };
+        let file = |name: &str| match field(name) {
+            Some(Scalar::String(file)) if !file.contains(['/', '\\']) => Ok(directory.join(file)),
+            _ => Err(format!("{SIDECAR} has no {name} file")),
+        };
+        if number("format")? != CONTEXT_FORMAT_VERSION {
+            return Err(format!("unsupported context format {}", number("format")?));
+        }
+        let previous = Thumbnail::read_pnm(&file("previous")?)?;
+        let current = Thumbnail::read_pnm(&file("current")?)?;
+        if !previous.same_shape(&current) {
+            return Err("the previous and current thumbnails differ in size".to_string());
+        }
+        let mask = Thumbnail::read_pnm(&file("mask")?)?;
+        if (mask.width, mask.height, mask.channels) != (current.width, current.height, 1) {
+            return Err("the mask isn't a PGM of the thumbnails' size".to_string());
+        }
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            id: number("id")?,
+            previous,
+            current,
+            mask,
+            changed_pixels: number("changed_pixels")?,
+            start_pixels: number("start_pixels")?,
+        })
+    }
+
+    /// The context directories in `directory`, oldest first.
+    pub fn list(directory: &Path) -> Result<Vec<PathBuf>, String> {
+        let entries = fs::read_dir(directory).map_err(|err| format!("Can't read {}: {err}", directory.display()))?;
+        let mut directories: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.join(SIDECAR).is_file())
+            .collect();
+        // Numerically by time and id, so 9 sorts before 10.
+        let key = |path: &PathBuf| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let mut numbers = name.split('-').map(|number| number.parse::<u64>().unwrap_or(u64::MAX));
+            (numbers.next(), numbers.next(), name)
+        };
+        directories.sort_by_cached_key(key);
+        Ok(directories)
+    }
+}
+
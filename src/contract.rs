@@ -0,0 +1,284 @@
+use std::time::{ Duration, SystemTime };
+
+use crate::{
+    clock::TimeSource,
+    diff,
+    json::{ self, Object },
+    motion::{ MotionEvent, StopReason },
+    settings::Settings,
+};
+
+/// Version of the motion records, written in each as `schema_version`. Records written before it
+/// was, without the field, are version 1.
+pub const SCHEMA_VERSION: u32 = 2;
+
+
+/// The detection settings an embedder keeps, e.g. in its own config file: what a `MotionDetector`
+/// is made from, times in seconds. With the `serde` feature, fields left out keep their defaults
+/// and unknown ones are refused, so a misspelled threshold doesn't go unnoticed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct Config {
+    pub frame_capture_interval: f64,
+    pub camera_warm_up: f64,
+    pub warm_up_frames: Option<u32>,
+    pub motion_tail_length: f64,
+    pub tail_frames: Option<u32>,
+    pub max_event_duration: Option<f64>,
+    pub confirm_frames: u32,
+    pub downsample: usize,
+    pub temporal_average: usize,
+    pub pixel_threshold: f32,           // Percentages, as in Settings.
+    pub image_threshold: f32,
+    pub sustain_threshold: Option<f32>,
+    pub algorithm: String,
+    pub blur: usize,
+    pub stabilize: bool,
+    pub stabilize_max_shift: usize,
+    pub wb_compensation: Option<f32>,
+    pub flicker_rejection: bool,
+    pub diff_early_exit: bool,
+    pub noise_k: f32,
+    pub noise_floor: f32,
+    pub noise_ceiling: f32,
+    pub edge_threshold: f32,
+}
+
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::from(&Settings::default())
+    }
+}
+
+
+impl From<&Settings> for Config {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            frame_capture_interval: settings.frame_capture_interval.as_secs_f64(),
+            camera_warm_up: settings.camera_warm_up.as_secs_f64(),
+            warm_up_frames: settings.warm_up_frames,
+            motion_tail_length: settings.motion_tail_length.as_secs_f64(),
+            tail_frames: settings.tail_frames,
+            max_event_duration: settings.max_event_duration.map(|duration| duration.as_secs_f64()),
+            confirm_frames: settings.confirm_frames,
+            downsample: settings.downsample,
+            temporal_average: settings.temporal_average,
+            pixel_threshold: settings.pixel_threshold,
+            image_threshold: settings.image_threshold,
+            sustain_threshold: settings.sustain_threshold,
+            algorithm: settings.algorithm.clone(),
+            blur: settings.blur,
+            stabilize: settings.stabilize,
+            stabilize_max_shift: settings.stabilize_max_shift,
+            wb_compensation: settings.wb_compensation,
+            flicker_rejection: settings.flicker_rejection,
+            diff_early_exit: settings.diff_early_exit,
+            noise_k: settings.noise_k,
+            noise_floor: settings.noise_floor,
+            noise_ceiling: settings.noise_ceiling,
+            edge_threshold: settings.edge_threshold,
+        }
+    }
+}
+
+
+impl Config {
+
+    /// Sets these fields of `settings`, leaving the others. Nothing is set if a value is invalid.
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        let seconds = |name: &str, value: f64| Duration::try_from_secs_f64(value)
+            .map_err(|_| format!("{name} must be a number of seconds, got {value}"));
+        let percent = |name: &str, value: f32| match (0.0 ..= 100.0).contains(&value) {
+            true => Ok(value),
+            false => Err(format!("{name} must be a percentage from 0 to 100, got {value}")),
+        };
+        if !diff::STRATEGY_NAMES.contains(&self.algorithm.as_str()) {
+            return Err(format!("algorithm must be one of {}, got {}", diff::STRATEGY_NAMES.join(", "), self.algorithm));
+        }
+        if self.downsample == 0 || self.temporal_average == 0 {
+            return Err("downsample and temporal_average must be at least 1".to_string());
+        }
+        let frame_capture_interval = seconds("frame_capture_interval", self.frame_capture_interval)?;
+        let camera_warm_up = seconds("camera_warm_up", self.camera_warm_up)?;
+        let motion_tail_length = seconds("motion_tail_length", self.motion_tail_length)?;
+        let max_event_duration = self.max_event_duration.map(|duration| seconds("max_event_duration", duration)).transpose()?;
+        let pixel_threshold = percent("pixel_threshold", self.pixel_threshold)?;
+        let image_threshold = percent("image_threshold", self.image_threshold)?;
+        let sustain_threshold = self.sustain_threshold.map(|threshold| percent("sustain_threshold", threshold)).transpose()?;
+        *settings = Settings {
+            frame_capture_interval,
+            camera_warm_up,
+            warm_up_frames: self.warm_up_frames,
+            motion_tail_length,
+            tail_frames: self.tail_frames,
+            max_event_duration,
+            confirm_frames: self.confirm_frames,
+            downsample: self.downsample,
+            temporal_average: self.temporal_average,
+            pixel_threshold,
+            image_threshold,
+            sustain_threshold,
+            algorithm: self.algorithm.clone(),
+            blur: self.blur,
+            stabilize: self.stabilize,
+            stabilize_max_shift: self.stabilize_max_shift,
+            wb_compensation: self.wb_compensation,
+            flicker_rejection: self.flicker_rejection,
+            diff_early_exit: self.diff_early_exit,
+            noise_k: self.noise_k,
+            noise_floor: self.noise_floor,
+            noise_ceiling: self.noise_ceiling,
+            edge_threshold: self.edge_threshold,
+            ..std::mem::take(settings)
+        };
+        Ok(())
+    }
+}
+
+
+/// A motion record as the binary writes it, see `MotionEvent::to_object`: the event with its
+/// wall-clock time, in seconds since the unix epoch. The binary adds fields of its own after
+/// these, e.g. the installation and sequence, which reading one back ignores.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub kind: EventKind,
+    pub time: f64,
+    pub time_source: TimeSource,
+    #[cfg_attr(feature = "serde", serde(default = "first_version"))]
+    pub schema_version: u32,
+}
+
+
+/// What happened, named by the record's `type`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "type", rename_all = "snake_case"))]
+pub enum EventKind {
+    Provisional { id: u64 },
+    ProvisionalCancel { id: u64 },
+    Start { id: u64, continued_from: Option<u64>, provisional: bool, pre_existing: bool },
+    Stop { id: u64, reason: StopReason, duration: f64, peak: f32, mean: f32, frames_above_threshold: u64 },
+}
+
+
+impl Event {
+
+    /// The record of `event`, which happened at `time`.
+    pub fn new(event: MotionEvent, time: SystemTime, source: TimeSource) -> Self {
+        let kind = match event {
+            MotionEvent::Provisional { id } => EventKind::Provisional { id },
+            MotionEvent::ProvisionalCancel { id } => EventKind::ProvisionalCancel { id },
+            MotionEvent::Start { id, continued_from, provisional, pre_existing, .. } => {
+                EventKind::Start { id, continued_from, provisional, pre_existing }
+            }
+            MotionEvent::Stop { id, reason, duration, stats, .. } => EventKind::Stop {
+                id,
+                reason,
+                duration: duration.as_secs_f64(),
+                peak: stats.peak,
+                mean: stats.mean(),
+                frames_above_threshold: stats.frames_above_threshold as u64,
+            },
+        };
+        Self { kind, time: json::unix_time(time), time_source: source, schema_version: SCHEMA_VERSION }
+    }
+
+    /// The record's JSON, with the `serde` feature the same fields serde writes.
+    pub fn to_object(&self) -> Object {
+        let object = match &self.kind {
+            EventKind::Provisional { id } => Object::new()
+                .field("type", "provisional")
+                .field("id", *id),
+            EventKind::ProvisionalCancel { id } => Object::new()
+                .field("type", "provisional_cancel")
+                .field("id", *id),
+            EventKind::Start { id, continued_from, provisional, pre_existing } => Object::new()
+                .field("type", "start")
+                .field("id", *id)
+                .field("continued_from", *continued_from)
+                .field("provisional", *provisional)
+                .field("pre_existing", *pre_existing),
+            EventKind::Stop { id, reason, duration, peak, mean, frames_above_threshold } => Object::new()
+                .field("type", "stop")
+                .field("id", *id)
+                .field("reason", reason.name())
+                .field("duration", *duration)
+                .field("peak", *peak)
+                .field("mean", *mean)
+                .field("frames_above_threshold", *frames_above_threshold),
+        };
+        object
+            .field("time", self.time)
+            .field("time_source", self.time_source.name())
+            .field("schema_version", self.schema_version as u64)
+    }
+}
+
+
+#[cfg(feature = "serde")]
+fn first_version() -> u32 {
+    1
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion::MotionStats;
+    use std::time::{ Instant, UNIX_EPOCH };
+
+
+    #[test]
+    fn a_config_applied_gives_back_the_same_config() {
+        let config = Config {
+            motion_tail_length: 0.5,
+            max_event_duration: Some(60.0),
+            image_threshold: 2.5,
+            algorithm: "adaptive".to_string(),
+            ..Config::default()
+        };
+        let mut settings = Settings { downsample: 8, verbose: true, ..Settings::default() };
+        config.apply(&mut settings).unwrap();
+        assert_eq!(settings.motion_tail_length, Duration::from_millis(500));
+        assert_eq!(settings.max_event_duration, Some(Duration::from_secs(60)));
+        assert!(settings.verbose, "Settings the config doesn't hold are kept");
+        assert_eq!(Config::from(&settings), config);
+    }
+
+
+    #[test]
+    fn an_invalid_config_changes_nothing() {
+        let mut settings = Settings::default();
+        for config in [
+            Config { algorithm: "optical-flow".to_string(), ..Config::default() },
+            Config { motion_tail_length: -1.0, ..Config::default() },
+            Config { image_threshold: 250.0, ..Config::default() },
+            Config { downsample: 0, ..Config::default() },
+        ] {
+            assert!(config.apply(&mut settings).is_err(), "{config:?}");
+        }
+        assert_eq!(Config::from(&settings), Config::default());
+    }
+
+
+    #[test]
+    fn the_record_of_a_stop_carries_its_stats_and_version() {
+        let mut stats = MotionStats::default();
+        (stats.peak, stats.frames, stats.frames_above_threshold) = (25.0, 4, 2);
+        let stop = MotionEvent::Stop {
+            id: 3,
+            reason: StopReason::Tail,
+            duration: Duration::from_millis(1500),
+            at: Instant::now(),
+            stats,
+            between: None,
+        };
+        let record = Event::new(stop, UNIX_EPOCH + Duration::from_secs(1_700_000_000), TimeSource::Driver);
+        assert_eq!(
+            record.to_object().finish(),
+            r#"{"type":"stop","id":3,"reason":"tail","duration":1.500,"peak":25.000,"mean":0.000,"frames_above_threshold":2,"time":1700000000.000,"time_source":"driver","schema_version":2}"#,
+        );
+    }
+}
@@ -0,0 +1,621 @@
+use std::{ sync::mpsc::{ self, Receiver, RecvTimeoutError, Sender }, time::{ Duration, Instant, SystemTime } };
+
+use crate::{ json::{ self, Object, Scalar }, settings::{ PauseMode, Settings } };
+
+/// Settings that can be changed while running, in percent like their options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    PixelThreshold,
+    ImageThreshold,
+    SustainThreshold,
+}
+
+
+impl Setting {
+
+    pub const ALL: [Setting; 3] = [Setting::PixelThreshold, Setting::ImageThreshold, Setting::SustainThreshold];
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "pixel_threshold" => Ok(Setting::PixelThreshold),
+            "image_threshold" => Ok(Setting::ImageThreshold),
+            "sustain_threshold" => Ok(Setting::SustainThreshold),
+            _ => Err(format!("Unknown setting '{name}', use pixel_threshold, image_threshold or sustain_threshold")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::PixelThreshold => "pixel_threshold",
+            Setting::ImageThreshold => "image_threshold",
+            Setting::SustainThreshold => "sustain_threshold",
+        }
+    }
+}
+
+
+/// A request to change what the detector does while it runs. Every way of controlling it, signals
+/// included, is turned into these and applied by `ControlState::apply`, so none behaves differently.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    ResetBaseline,               // Takes a fresh reference, as after resuming.
+    SetReference,                // The next thumbnail becomes the scene reference of --reference-file.
+    ResetActivity,               // The activity histogram starts over.
+    Arm,                         // Movements are reported, the default...
+    Disarm,                      // ...or only detected.
+    ArmZone(String),             // Changes in the zone count again...
+    DisarmZone(String),          // ...or not, see zones::ZoneArming.
+    Set(Vec<(Setting, f32)>),    // Already validated, but not against the bounds, see `ControlBounds::check`.
+    Adjust(Vec<(Setting, f32)>), // Steps up or down, kept within the bounds.
+    Revert,                      // Back to the thresholds the run started with.
+    SaveScene(String),           // What the detector learned is kept under the name...
+    ActivateScene(String),       // ...and taken back, see profile::SceneProfiles.
+}
+
+
+impl ControlCommand {
+
+    /// As given in command lines and control URLs.
+    pub const NAMES: [&'static str; 11] = [
+        "pause", "resume", "reset-baseline", "set-reference", "activity-reset", "arm", "disarm", "set", "adjust", "revert", "scene",
+    ];
+
+    /// Parses a command line: "pause", "resume", "reset-baseline", "set-reference", "activity-reset",
+    /// "arm", "disarm", "revert", or "set" followed by name=value pairs, e.g. "set pixel_threshold=8
+    /// image_threshold=15", and "adjust" by steps, e.g. "adjust pixel_threshold=+2". A value may also
+    /// follow its name as a word of its own, e.g. "adjust pixel_threshold +2". "arm" and "disarm" may
+    /// name a zone, e.g. "disarm zone=living_room", and "scene" saves or activates one, e.g. "scene
+    /// save kitchen" or "scene activate kitchen".
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        if name == "scene" {
+            return match (words.next(), words.next(), words.next()) {
+                (Some(action), Some(scene), None) => Self::scene(action, scene),
+                _ => Err("Expected scene save <name> or scene activate <name>".to_string()),
+            };
+        }
+        if name == "arm" || name == "disarm" {
+            let zone = match (words.next(), words.next()) {
+                (None, _) => None,
+                (Some(pair), None) => Some(pair.strip_prefix("zone=").filter(|zone| !zone.is_empty())
+                    .ok_or_else(|| format!("Expected zone=<name>, got '{pair}'"))?),
+                (Some(_), Some(_)) => return Err(format!("{name} takes at most a zone")),
+            };
+            return Ok(Self::arming(name == "arm", zone));
+        }
+        if name != "set" && name != "adjust" {
+            return match words.next() {
+                None => Self::named(name),
+                Some(_) => Err(format!("{name} takes no arguments")),
+            };
+        }
+        let mut values = Vec::new();
+        while let Some(word) = words.next() {
+            let (setting, value) = match word.split_once('=') {
+                Some(pair) => pair,
+                None => (word, words.next().ok_or_else(|| format!("Expected name=value, got '{word}'"))?),
+            };
+            let value = value.parse().map_err(|_| format!("Invalid value '{value}' for {setting}"))?;
+            values.push((setting.to_string(), value));
+        }
+        Self::values(name, values)
+    }
+
+    /// The command of POST /control/<name>. The body of set is a JSON object of the new values,
+    /// e.g. {"pixel_threshold": 8}, that of adjust one of steps, e.g. {"pixel_threshold": -2}, that
+    /// of arm and disarm may name a zone, e.g. {"zone": "living_room"}, that of scene has the scene to
+    /// save or activate, e.g. {"save": "kitchen"}. The other commands ignore theirs.
+    pub fn from_request(name: &str, body: &str) -> Result<Self, String> {
+        if name == "scene" {
+            let fields = json::parse_flat(body).map_err(|err| format!("Invalid JSON body: {err}"))?;
+            return match fields.as_slice() {
+                [(action, Scalar::String(scene))] => Self::scene(action, scene),
+                _ => Err("Expected {\"save\": <name>} or {\"activate\": <name>}".to_string()),
+            };
+        }
+        if (name == "arm" || name == "disarm") && !body.trim().is_empty() {
+            let fields = json::parse_flat(body).map_err(|err| format!("Invalid JSON body: {err}"))?;
+            let zone = match fields.into_iter().find(|(field, _)| field == "zone") {
+                Some((_, Scalar::String(zone))) if !zone.is_empty() => Some(zone),
+                Some(_) => return Err("zone must be a zone name".to_string()),
+                None => None,
+            };
+            return Ok(Self::arming(name == "arm", zone.as_deref()));
+        }
+        if name != "set" && name != "adjust" {
+            return Self::named(name);
+        }
+        let values = json::parse_flat(body).map_err(|err| format!("Invalid JSON body: {err}"))?.into_iter().map(|(name, value)| {
+            match value {
+                Scalar::Number(number) => Ok((name, number)),
+                _ => Err(format!("{name} must be a number")),
+            }
+        }).collect::<Result<Vec<_>, String>>()?;
+        Self::values(name, values)
+    }
+
+    /// As in NAMES, for the commands changing thresholds.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControlCommand::Pause => "pause",
+            ControlCommand::Resume => "resume",
+            ControlCommand::ResetBaseline => "reset-baseline",
+            ControlCommand::SetReference => "set-reference",
+            ControlCommand::ResetActivity => "activity-reset",
+            ControlCommand::Arm | ControlCommand::ArmZone(_) => "arm",
+            ControlCommand::Disarm | ControlCommand::DisarmZone(_) => "disarm",
+            ControlCommand::Set(_) => "set",
+            ControlCommand::Adjust(_) => "adjust",
+            ControlCommand::Revert => "revert",
+            ControlCommand::SaveScene(_) | ControlCommand::ActivateScene(_) => "scene",
+        }
+    }
+
+    /// A pause on true, a resume on false, as SIGUSR1 and SIGUSR2 ask for.
+    pub fn pause(pause: bool) -> Self {
+        if pause { ControlCommand::Pause } else { ControlCommand::Resume }
+    }
+
+    fn arming(armed: bool, zone: Option<&str>) -> Self {
+        match (armed, zone) {
+            (true, None) => ControlCommand::Arm,
+            (false, None) => ControlCommand::Disarm,
+            (true, Some(zone)) => ControlCommand::ArmZone(zone.to_string()),
+            (false, Some(zone)) => ControlCommand::DisarmZone(zone.to_string()),
+        }
+    }
+
+    // Names are kept in the state file, and given in command lines.
+    fn scene(action: &str, scene: &str) -> Result<Self, String> {
+        if scene.is_empty() || scene.len() > 64 || !scene.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid scene name '{scene}', use up to 64 letters, digits, '-' and '_'"));
+        }
+        match action {
+            "save" => Ok(ControlCommand::SaveScene(scene.to_string())),
+            "activate" => Ok(ControlCommand::ActivateScene(scene.to_string())),
+            _ => Err(format!("Unknown scene action '{action}', use save or activate")),
+        }
+    }
+
+    fn named(name: &str) -> Result<Self, String> {
+        match name {
+            "pause" => Ok(ControlCommand::Pause),
+            "resume" => Ok(ControlCommand::Resume),
+            "reset-baseline" => Ok(ControlCommand::ResetBaseline),
+            "set-reference" => Ok(ControlCommand::SetReference),
+            "activity-reset" => Ok(ControlCommand::ResetActivity),
+            "arm" => Ok(ControlCommand::Arm),
+            "disarm" => Ok(ControlCommand::Disarm),
+            "revert" => Ok(ControlCommand::Revert),
+            _ => Err(format!(
+                "Unknown command '{name}', use pause, resume, reset-baseline, set-reference, activity-reset, arm, disarm, set, adjust, revert or scene"
+            )),
+        }
+    }
+
+    // Percentages for set, steps of up to 100 either way for adjust, each given once, so a request
+    // is refused as a whole rather than half applied.
+    fn values(command: &str, values: Vec<(String, f64)>) -> Result<Self, String> {
+        if values.is_empty() {
+            return Err(format!("{command} needs at least one value"));
+        }
+        let range = if command == "adjust" { -100.0 ..= 100.0 } else { 0.0 ..= 100.0 };
+        let mut settings: Vec<(Setting, f32)> = Vec::new();
+        for (name, value) in values {
+            let setting = Setting::parse(&name)?;
+            if !range.contains(&value) {
+                return Err(match command {
+                    "adjust" => format!("{name} must be a step from -100 to +100"),
+                    _ => format!("{name} must be a percentage from 0 to 100"),
+                });
+            }
+            if settings.iter().any(|(other, _)| *other == setting) {
+                return Err(format!("{name} is given twice"));
+            }
+            settings.push((setting, value as f32));
+        }
+        Ok(match command {
+            "adjust" => ControlCommand::Adjust(settings),
+            _ => ControlCommand::Set(settings),
+        })
+    }
+}
+
+
+/// The smallest and largest value control commands may give each setting, from --control-bounds,
+/// so a typo on a remote box can't make every frame a movement or none.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlBounds {
+    ranges: [(f32, f32); 3],    // In the order of Setting::ALL.
+}
+
+
+impl Default for ControlBounds {
+    fn default() -> Self {
+        Self { ranges: [(0.0, 100.0); 3] }
+    }
+}
+
+
+impl ControlBounds {
+
+    /// Parses the --control-bounds syntax, "<setting>=<min>-<max>", e.g. "image_threshold=1-50".
+    pub fn parse(spec: &str) -> Result<(Setting, f32, f32), String> {
+        let invalid = || format!("Invalid value '{spec}' for --control-bounds, expected <setting>=<min>-<max>");
+        let (name, range) = spec.split_once('=').ok_or_else(invalid)?;
+        let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+        let (min, max): (f32, f32) = (min.parse().map_err(|_| invalid())?, max.parse().map_err(|_| invalid())?);
+        if !(0.0 ..= 100.0).contains(&min) || !(0.0 ..= 100.0).contains(&max) || min > max {
+            return Err(format!("--control-bounds of {name} must be percentages from 0 to 100, the smallest first"));
+        }
+        Ok((Setting::parse(name)?, min, max))
+    }
+
+    pub fn set(&mut self, setting: Setting, min: f32, max: f32) {
+        self.ranges[setting as usize] = (min, max);
+    }
+
+    pub fn range(&self, setting: Setting) -> (f32, f32) {
+        self.ranges[setting as usize]
+    }
+
+    pub fn clamp(&self, setting: Setting, value: f32) -> f32 {
+        let (min, max) = self.range(setting);
+        value.clamp(min, max)
+    }
+
+    /// Refuses a set with a value out of bounds, as a whole. Adjustments are clamped instead, they
+    /// only ask for more or less.
+    pub fn check(&self, command: &ControlCommand) -> Result<(), String> {
+        let ControlCommand::Set(values) = command else { return Ok(()) };
+        for (setting, value) in values {
+            let (min, max) = self.range(*setting);
+            if !(min ..= max).contains(value) {
+                return Err(format!("{} must be from {min} to {max}, see --control-bounds", setting.name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// In the --control-bounds syntax, every setting's.
+    pub fn specs(&self) -> Vec<String> {
+        Setting::ALL.iter().map(|setting| {
+            let (min, max) = self.range(*setting);
+            format!("{}={min}-{max}", setting.name())
+        }).collect()
+    }
+}
+
+
+/// The thresholds control commands change, in percent. The sustain threshold is None while it
+/// follows the image threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub pixel: f32,
+    pub image: f32,
+    pub sustain: Option<f32>,
+}
+
+
+/// A setting a command changed, echoed with both values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThresholdChange {
+    pub setting: Setting,
+    pub from: f32,
+    pub to: f32,
+}
+
+
+impl ThresholdChange {
+
+    pub fn text(&self) -> String {
+        format!("{} {}% to {}%", self.setting.name(), self.from, self.to)
+    }
+
+    pub fn to_object(&self) -> Object {
+        Object::new()
+            .field("setting", self.setting.name())
+            .field("from", self.from)
+            .field("to", self.to)
+    }
+}
+
+
+/// What the commands act on, reported back after each one. The detector follows it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlState {
+    pub paused: bool,
+    pub pause_mode: PauseMode,
+    pub armed: bool,
+    pub pixel_threshold: f32,   // Percentages, as the options take them.
+    pub image_threshold: f32,
+    pub sustain_threshold: Option<f32>, // Follows the image threshold until set, see `sustain_percent`.
+    pub bounds: ControlBounds,
+    pub startup: Thresholds,    // What revert goes back to.
+}
+
+
+impl ControlState {
+
+    /// Running and armed, with the thresholds and bounds of `settings`.
+    pub fn new(settings: &Settings) -> Self {
+        let startup = Thresholds {
+            pixel: settings.pixel_threshold,
+            image: settings.image_threshold,
+            sustain: settings.sustain_threshold,
+        };
+        let mut state = Self {
+            paused: false,
+            pause_mode: settings.pause_mode,
+            armed: true,
+            pixel_threshold: 0.0,
+            image_threshold: 0.0,
+            sustain_threshold: None,
+            bounds: settings.control_bounds,
+            startup,
+        };
+        state.restore(startup);
+        state
+    }
+
+    /// Applies a command. Returns true if the detector needs a fresh reference for it.
+    pub fn apply(&mut self, command: &ControlCommand) -> bool {
+        match command {
+            ControlCommand::Pause => self.paused = true,
+            ControlCommand::Resume => self.paused = false,
+            ControlCommand::Arm => self.armed = true,
+            ControlCommand::Disarm => self.armed = false,
+            ControlCommand::ResetBaseline => return true,
+            ControlCommand::SetReference | ControlCommand::ResetActivity | ControlCommand::ArmZone(_) | ControlCommand::DisarmZone(_) => {}
+            ControlCommand::SaveScene(_) | ControlCommand::ActivateScene(_) => {}
+            ControlCommand::Set(values) => {
+                for (setting, value) in values {
+                    self.set(*setting, *value);
+                }
+                return true;
+            }
+            ControlCommand::Adjust(steps) => {
+                for (setting, step) in steps {
+                    self.set(*setting, self.value(*setting) + step);
+                }
+                return true;
+            }
+            ControlCommand::Revert => {
+                self.restore(self.startup);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds { pixel: self.pixel_threshold, image: self.image_threshold, sustain: self.sustain_threshold }
+    }
+
+    /// Puts back thresholds saved before, bounds or not.
+    pub fn restore(&mut self, thresholds: Thresholds) {
+        self.pixel_threshold = thresholds.pixel;
+        self.image_threshold = thresholds.image;
+        self.sustain_threshold = thresholds.sustain;
+    }
+
+    /// What changed since `before`, the sustain threshold as it applies.
+    pub fn changes(&self, before: &ControlState) -> Vec<ThresholdChange> {
+        Setting::ALL.iter()
+            .map(|setting| ThresholdChange { setting: *setting, from: before.value(*setting), to: self.value(*setting) })
+            .filter(|change| change.from != change.to)
+            .collect()
+    }
+
+    // Within the bounds, whichever way the command came.
+    fn set(&mut self, setting: Setting, value: f32) {
+        let value = self.bounds.clamp(setting, value);
+        match setting {
+            Setting::PixelThreshold => self.pixel_threshold = value,
+            Setting::ImageThreshold => self.image_threshold = value,
+            Setting::SustainThreshold => self.sustain_threshold = Some(value),
+        }
+    }
+
+    fn value(&self, setting: Setting) -> f32 {
+        match setting {
+            Setting::PixelThreshold => self.pixel_threshold,
+            Setting::ImageThreshold => self.image_threshold,
+            Setting::SustainThreshold => self.sustain_percent(),
+        }
+    }
+
+    /// The sustain threshold as in `Settings::sustain_threshold`: the image threshold unless set, and
+    /// never above it.
+    pub fn sustain_percent(&self) -> f32 {
+        self.sustain_threshold.unwrap_or(self.image_threshold).min(self.image_threshold)
+    }
+
+    pub fn to_json(&self) -> String {
+        json::Object::new()
+            .field("type", "control")
+            .field("paused", self.paused)
+            .field("pause_mode", self.pause_mode.name())
+            .field("armed", self.armed)
+            .field("pixel_threshold", self.pixel_threshold)
+            .field("image_threshold", self.image_threshold)
+            .field("sustain_threshold", self.sustain_percent())
+            .field("time", json::unix_time(SystemTime::now()))
+            .finish()
+    }
+}
+
+
+/// A threshold change that set off a storm of movements, to be reverted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Storm {
+    pub movements: usize,
+    pub within: Duration,       // Since the change.
+    pub before: Thresholds,     // What to go back to.
+}
+
+
+/// Watches the movements after a threshold change, from --storm-movements and --storm-window: as
+/// many starting within the window of the change are a storm. Changes made within the window of
+/// each other are watched as one, and a storm goes back to the thresholds before the first.
+pub struct StormGuard {
+    movements: usize,
+    window: Duration,
+    watching: Option<(Instant, Thresholds, usize)>, // Since, before, movements so far.
+}
+
+
+impl StormGuard {
+
+    pub fn new(movements: usize, window: Duration) -> Self {
+        Self { movements: movements.max(1), window, watching: None }
+    }
+
+    /// Thresholds changed from `before` at `now`.
+    pub fn changed(&mut self, before: Thresholds, now: Instant) {
+        let before = match self.watching {
+            Some((since, first, _)) if now.saturating_duration_since(since) < self.window => first,
+            _ => before,
+        };
+        self.watching = Some((now, before, 0));
+    }
+
+    /// The change was taken back by other means, e.g. a revert command.
+    pub fn cancel(&mut self) {
+        self.watching = None;
+    }
+
+    /// A movement started at `now`. Returns the storm it completes, if any, and watches no more.
+    pub fn movement(&mut self, now: Instant) -> Option<Storm> {
+        let (since, before, movements) = self.watching.as_mut()?;
+        let within = now.saturating_duration_since(*since);
+        if within >= self.window {
+            self.watching = None;
+            return None;
+        }
+        *movements += 1;
+        let storm = (*movements >= self.movements).then_some(Storm { movements: *movements, within, before: *before });
+        if storm.is_some() {
+            self.watching = None;
+        }
+        storm
+    }
+}
+
+
+/// A command waiting for the main loop, with the way back for the state it resulted in.
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<ControlState>,
+}
+
+
+impl ControlRequest {
+
+    pub fn reply(self, state: ControlState) {
+        let _ = self.reply.send(state);
+    }
+}
+
+
+/// The sending end of the command channel, handed to whatever takes commands.
+#[derive(Clone)]
+pub struct ControlSender {
+    requests: Sender<ControlRequest>,
+    bounds: ControlBounds,
+}
+
+
+impl ControlSender {
+
+    /// Of the settings, to refuse commands before they're sent.
+    pub fn bounds(&self) -> &ControlBounds {
+        &self.bounds
+    }
+
+    /// Sends a command and waits up to `timeout` for the main loop to apply it, e.g. while it's
+    /// warming up after resuming.
+    pub fn send(&self, command: ControlCommand, timeout: Duration) -> Result<ControlState, String> {
+        let (reply, state) = mpsc::channel();
+        self.requests.send(ControlRequest { command, reply }).map_err(|_| "the detector is gone".to_string())?;
+        state.recv_timeout(timeout).map_err(|err| match err {
+            RecvTimeoutError::Timeout => format!("the detector didn't answer within {timeout:.0?}"),
+            RecvTimeoutError::Disconnected => "the detector is gone".to_string(),
+        })
+    }
+}
+
+
+/// The command channel: commands are sent from any thread and drained by the main loop once per
+/// frame, which applies them in order.
+pub fn channel(bounds: ControlBounds) -> (ControlSender, Receiver<ControlRequest>) {
+    let (requests, receiver) = mpsc::channel();
+    (ControlSender { requests, bounds }, receiver)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn steps_are_taken_as_a_pair_or_as_two_words() {
+        let expected = ControlCommand::Adjust(vec![(Setting::PixelThreshold, 2.0)]);
+        assert_eq!(ControlCommand::parse("adjust pixel_threshold=+2"), Ok(expected.clone()));
+        assert_eq!(ControlCommand::parse("adjust pixel_threshold +2"), Ok(expected.clone()));
+        assert_eq!(ControlCommand::from_request("adjust", "{\"pixel_threshold\": 2}"), Ok(expected));
+        assert_eq!(ControlCommand::parse("revert"), Ok(ControlCommand::Revert));
+        assert!(ControlCommand::parse("adjust pixel_threshold").is_err());
+        assert!(ControlCommand::parse("revert now").is_err());
+    }
+
+
+    #[test]
+    fn a_change_is_echoed_with_both_values() {
+        let mut settings = Settings { pixel_threshold: 10.0, image_threshold: 2.0, ..Settings::default() };
+        settings.control_bounds.set(Setting::PixelThreshold, 5.0, 20.0);
+        let mut control = ControlState::new(&settings);
+        let before = control;
+        assert!(control.apply(&ControlCommand::parse("adjust pixel_threshold=+15").unwrap()));
+        let changes = control.changes(&before);
+        assert_eq!(changes, [ThresholdChange { setting: Setting::PixelThreshold, from: 10.0, to: 20.0 }]);
+        assert_eq!(changes[0].text(), "pixel_threshold 10% to 20%");
+        control.apply(&ControlCommand::Revert);
+        assert!(control.changes(&before).is_empty());
+    }
+
+
+    #[test]
+    fn a_storm_goes_back_to_before_the_first_of_changes_in_a_row() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let first = Thresholds { pixel: 10.0, image: 2.0, sustain: None };
+        let second = Thresholds { image: 1.0, ..first };
+        let mut guard = StormGuard::new(2, Duration::from_secs(60));
+        assert_eq!(guard.movement(at(0)), None);
+        guard.changed(first, at(0));
+        guard.changed(second, at(10));
+        assert_eq!(guard.movement(at(20)), None);
+        let storm = guard.movement(at(30));
+        assert_eq!(storm, Some(Storm { movements: 2, within: Duration::from_secs(20), before: first }));
+        // Watched no more once reverted.
+        assert_eq!(guard.movement(at(31)), None);
+    }
+
+
+    #[test]
+    fn movements_after_the_window_or_a_cancel_are_no_storm() {
+        let start = Instant::now();
+        let thresholds = Thresholds { pixel: 10.0, image: 2.0, sustain: None };
+        let mut guard = StormGuard::new(1, Duration::from_secs(60));
+        guard.changed(thresholds, start);
+        assert_eq!(guard.movement(start + Duration::from_secs(60)), None);
+        guard.changed(thresholds, start);
+        guard.cancel();
+        assert_eq!(guard.movement(start + Duration::from_secs(1)), None);
+    }
+}
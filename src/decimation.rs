@@ -0,0 +1,74 @@
+use std::time::{ Duration, Instant };
+
+// How long the delivery rate is measured for at least, before deciding how many frames to skip.
+const MEASURE_WINDOW: Duration = Duration::from_secs(2);
+
+
+/// Which of the source's frames are processed, the others are read and thrown away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decimation {
+    Every(u32),     // --process-every: one frame out of this many.
+    Fps(f32),       // --detect-fps: about this many frames per second, whatever the source delivers.
+}
+
+
+/// Decides how many frames to skip between two processed ones. With a target rate, the source's
+/// delivery rate is measured from the sequence numbers of the processed frames, missed slots
+/// included, and the count follows it.
+pub struct Decimator {
+    target: Decimation,
+    every: u32,
+    window: Option<(Instant, u64)>,     // Start of the measurement, and the sequence number then.
+}
+
+
+impl Decimator {
+
+    pub fn new(target: Decimation) -> Self {
+        let every = match target {
+            Decimation::Every(every) => every.max(1),
+            Decimation::Fps(_) => 1,    // Until the rate is known.
+        };
+        Self { target, every, window: None }
+    }
+
+    /// Every how many frames one is processed.
+    pub fn every(&self) -> u32 {
+        self.every
+    }
+
+    /// Frames to throw away before the next processed one.
+    pub fn skip(&self) -> u32 {
+        self.every - 1
+    }
+
+    /// A frame numbered `sequence` was processed, captured `at`. Returns the measured delivery
+    /// rate when that changed how many frames are skipped.
+    pub fn processed(&mut self, at: Instant, sequence: u64) -> Option<f32> {
+        let Decimation::Fps(fps) = self.target else {
+            return None;
+        };
+        let Some((start, start_sequence)) = self.window else {
+            self.window = Some((at, sequence));
+            return None;
+        };
+        // Long enough for a couple of processed frames at low rates.
+        let window = MEASURE_WINDOW.max(Duration::from_secs_f32(2.0 / fps));
+        let elapsed = at.saturating_duration_since(start);
+        if elapsed < window {
+            return None;
+        }
+        self.window = Some((at, sequence));
+        // A window with a gap, e.g. a pause or a reconnect, says nothing about the source.
+        if elapsed > window * 2 {
+            return None;
+        }
+        let delivered = (sequence - start_sequence) as f32 / elapsed.as_secs_f32();
+        let every = ((delivered / fps).round() as u32).max(1);
+        if every == self.every {
+            return None;
+        }
+        self.every = every;
+        Some(delivered)
+    }
+}
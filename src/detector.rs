@@ -0,0 +1,369 @@
+use std::{
+    fmt,
+    sync::{ atomic::{ AtomicBool, Ordering }, Arc },
+    time::{ Duration, Instant },
+};
+
+use crate::{
+    control::{ ControlCommand, ControlState },
+    diff::{ self, Blur, Channels, DiffStrategy, FlickerRejection, Masked, Normalization, Normalize, Stabilize, WhiteBalance },
+    mask::MaskImage,
+    motion::{ MotionEvent, MotionTracker },
+    noise::AdaptiveThreshold,
+    resume::{ ResumeCause, ResumeDelta, ResumeGuard },
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::{ PixelLayout, TemporalAverage, Thumbnail },
+};
+
+
+/// Why a wait of `MotionDetector` ended without an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MotionError {
+    Source(String),     // The source failed to deliver a frame.
+    FrameSize(usize),   // A frame of this many bytes, too short for the size the detector was made for.
+    EndOfInput,         // The source has no more frames.
+    Cancelled,          // See `CancelToken`.
+}
+
+
+impl fmt::Display for MotionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MotionError::Source(reason) => write!(f, "{reason}"),
+            MotionError::FrameSize(length) => write!(f, "frame of {length} bytes, too short"),
+            MotionError::EndOfInput => write!(f, "input ended"),
+            MotionError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+
+impl std::error::Error for MotionError {}
+
+
+/// Ends a wait of `MotionDetector` from another thread, after the frame it's on. A cancel that
+/// comes between two waits ends the next one. Clones cancel the same detector.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+
+impl CancelToken {
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    // Taken by the wait it ends.
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+
+/// What the strategy chain is made of, kept to make it again with other thresholds.
+struct Chain {
+    algorithm: String,
+    adaptive: AdaptiveThreshold,
+    edge_level: i32,
+    channels: Channels,
+    stabilize: Option<usize>,       // The largest shift, if stabilizing.
+    normalize: Option<Normalization>,
+    wb_compensation: Option<f32>,   // The uniformity tolerance, if compensating color casts.
+    flicker_rejection: bool,
+    blur: usize,
+    mask: Option<MaskImage>,
+    early_exit: bool,
+}
+
+
+impl Chain {
+
+    fn new(settings: &Settings) -> Self {
+        Self {
+            algorithm: settings.algorithm.clone(),
+            adaptive: settings.adaptive_threshold(),
+            edge_level: settings.edge_level(),
+            channels: settings.channels,
+            stabilize: settings.stabilize.then_some(settings.stabilize_max_shift),
+            normalize: settings.normalize,
+            wb_compensation: settings.wb_compensation,
+            flicker_rejection: settings.flicker_rejection,
+            blur: settings.blur,
+            mask: settings.mask.clone(),
+            early_exit: settings.early_exit(),
+        }
+    }
+
+    // The same chain as the binary's, see diff::DiffStrategy.
+    fn build(&self, pixel_threshold: i32, start_count: i32) -> Box<dyn DiffStrategy> {
+        let mut strategy = diff::from_name(&self.algorithm, pixel_threshold, start_count, self.adaptive, self.edge_level, self.channels)
+            .expect("Algorithm names are validated with the settings");
+        if let Some(max_shift) = self.stabilize {
+            strategy = Box::new(Stabilize::new(strategy, max_shift));
+        }
+        if let Some(mode) = self.normalize {
+            strategy = Box::new(Normalize::new(strategy, mode));
+        }
+        if let Some(tolerance) = self.wb_compensation {
+            strategy = Box::new(WhiteBalance::new(strategy, tolerance));
+        }
+        if self.flicker_rejection {
+            strategy = Box::new(FlickerRejection::new(strategy, pixel_threshold));
+        }
+        if self.blur > 0 {
+            strategy = Box::new(Blur::new(strategy, self.blur));
+        }
+        if let Some(mask) = &self.mask {
+            strategy = Box::new(Masked::new(strategy, mask.clone()));
+        }
+        strategy.set_early_exit(self.early_exit);
+        strategy
+    }
+}
+
+
+/// The capture loop of motion-detect for programs that only want to block until something moves,
+/// or until nothing did for a while: frames are downsampled, compared by the strategy the options
+/// pick, and movements followed as the binary does, warm-up and confirmation frames included.
+///
+/// Frames are timed by the capture interval, as they come from sources that deliver at that rate
+/// (--input-fps for raw video), so timeouts and stillness are measured in frames. What the
+/// detector learned is kept between waits, each one picks up where the last left off. A source
+/// that blocks blocks the wait, a cancel is only seen once it delivers.
+///
+/// Control commands are applied by `apply`, between waits or from the `async_detector` worker.
+pub struct MotionDetector {
+    frame_width: usize,
+    frame_len: usize,               // Bytes a frame needs at least.
+    layout: PixelLayout,
+    downsample: usize,
+    interval: Duration,
+    warm_up_frames: u64,
+    thumb: Thumbnail,
+    chain: Chain,
+    strategy: Box<dyn DiffStrategy>,
+    temporal_average: usize,
+    averager: TemporalAverage,
+    baseline: bool,                 // The next thumbnail compared is the reference, it tells no movement.
+    motion: MotionTracker,
+    control: ControlState,          // Paused or not, and the thresholds in percent.
+    resume: ResumeGuard,
+    resume_delta: Option<ResumeDelta>,  // What changed while paused, until it's taken.
+    start: Instant,                 // The time of frame 0.
+    frames: u64,                    // Read so far, warm-up included.
+    still_since: Option<Instant>,   // None while something moves, or before the first comparison.
+    cancel: CancelToken,
+}
+
+
+impl MotionDetector {
+
+    /// A detector for frames of `width` by `height` pixels in `layout`, with the thresholds,
+    /// algorithm, warm-up and everything else the options set in `settings`.
+    pub fn new(settings: &Settings, width: usize, height: usize, layout: PixelLayout) -> Self {
+        let thumb = Thumbnail::with_channels(width / settings.downsample, height / settings.downsample, layout.channels())
+            .with_stats(settings.thumb_stats);
+        let control = ControlState::new(settings);
+        let (pixel_threshold, start_count, sustain_count) = counts(&control, &thumb);
+        let chain = Chain::new(settings);
+        let interval = settings.frame_capture_interval;
+        let motion = MotionTracker::new(settings.motion_tail_length, start_count, sustain_count)
+            .with_tail_frames(settings.tail_frames)
+            .with_max_duration(settings.max_event_duration)
+            .with_confirm_frames(settings.confirm_frames)
+            .with_frame_interval(interval * settings.temporal_average as u32);
+        Self {
+            frame_width: width,
+            frame_len: width * height * layout.bytes_per_pixel(),
+            layout,
+            downsample: settings.downsample,
+            interval,
+            warm_up_frames: match settings.warm_up_frames {
+                Some(frames) => frames as u64,
+                None => settings.camera_warm_up.as_nanos().div_ceil(interval.as_nanos().max(1)) as u64,
+            },
+            thumb,
+            strategy: chain.build(pixel_threshold, start_count),
+            chain,
+            temporal_average: settings.temporal_average,
+            averager: TemporalAverage::new(settings.temporal_average),
+            baseline: true,
+            motion,
+            control,
+            resume: ResumeGuard::new(settings.resume_policy),
+            resume_delta: None,
+            start: Instant::now(),
+            frames: 0,
+            still_since: None,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// Cancelled by `token` instead of a token of its own, e.g. one made before the detector.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Cancels the waits of this detector.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Frames read so far, the warm-up's included.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// True while a movement is in progress, e.g. after `wait_for_motion` returned its start.
+    pub fn is_moving(&self) -> bool {
+        self.motion.is_active()
+    }
+
+    /// Paused or not, and the thresholds commands set.
+    pub fn state(&self) -> ControlState {
+        self.control
+    }
+
+    /// Applies a control command as the binary does, within the --control-bounds of the settings,
+    /// and returns the state it resulted in: pause, resume, reset-baseline, set, adjust and revert.
+    /// The others act on what only the binary has, and are refused. A paused detector still reads
+    /// frames, but compares none. A reset or a threshold change start over from a fresh baseline,
+    /// resuming does as the --resume-policy says, see `take_resume_delta`.
+    pub fn apply(&mut self, command: &ControlCommand) -> Result<ControlState, String> {
+        match command {
+            ControlCommand::Pause | ControlCommand::Resume | ControlCommand::ResetBaseline
+            | ControlCommand::Set(_) | ControlCommand::Adjust(_) | ControlCommand::Revert => {}
+            _ => return Err(format!("{} needs the motion-detect binary", command.name())),
+        }
+        self.control.bounds.check(command)?;
+        let paused = self.control.paused;
+        let changed = self.control.apply(command);
+        if self.control.paused && !paused {
+            self.resume.away(self.now());
+        }
+        let resumed = paused && !self.control.paused && self.resume.back(ResumeCause::Resume, self.now());
+        if changed || resumed {
+            self.rebaseline();
+        }
+        Ok(self.control)
+    }
+
+    /// With --resume-policy report-delta, what changed while the detector was paused, once the
+    /// first frame after resuming was compared and if it's enough to start a movement. That frame
+    /// starts none, the next one is the fresh baseline.
+    pub fn take_resume_delta(&mut self) -> Option<ResumeDelta> {
+        self.resume_delta.take()
+    }
+
+    fn rebaseline(&mut self) {
+        let (pixel_threshold, start_count, sustain_count) = counts(&self.control, &self.thumb);
+        self.strategy = self.chain.build(pixel_threshold, start_count);
+        self.motion.set_thresholds(start_count, sustain_count);
+        self.averager = TemporalAverage::new(self.temporal_average);
+        self.baseline = true;
+    }
+
+    /// Reads frames until a movement starts, and returns its start. A movement already in
+    /// progress doesn't count, nor the continuation of one split by --max-event-duration. Returns
+    /// None once `timeout` worth of frames were read without any.
+    pub fn wait_for_motion(&mut self, source: &mut impl FrameSource, timeout: Option<Duration>) -> Result<Option<MotionEvent>, MotionError> {
+        let deadline = timeout.map(|timeout| self.now() + timeout);
+        loop {
+            if deadline.is_some_and(|deadline| self.now() >= deadline) {
+                return Ok(None);
+            }
+            for event in self.next_events(source)? {
+                if let MotionEvent::Start { continued_from: None, .. } = event {
+                    return Ok(Some(event));
+                }
+            }
+        }
+    }
+
+    /// Reads frames until nothing moved for `duration`, counting from the end of the last
+    /// movement, or from the first frame compared if nothing moved since. Returns right away if
+    /// that's already the case, but never while a movement waits for its confirmation frames.
+    pub fn wait_for_still(&mut self, source: &mut impl FrameSource, duration: Duration) -> Result<(), MotionError> {
+        loop {
+            let still = self.still_since.is_some_and(|since| self.now().saturating_duration_since(since) >= duration);
+            if still && !self.motion.is_busy() {
+                return Ok(());
+            }
+            self.next_events(source)?;
+        }
+    }
+
+    /// Stops the movement in progress once the source ended, with the last frame read.
+    pub fn finish(&mut self) -> Option<MotionEvent> {
+        self.motion.finish(self.now())
+    }
+
+    // The time of the last frame read.
+    fn now(&self) -> Instant {
+        self.start + self.interval * self.frames.saturating_sub(1) as u32
+    }
+
+    /// Reads and compares the next frame, and returns the events it caused, for programs that want
+    /// every one of them. Nothing is compared during the warm-up or while paused.
+    pub fn next_events(&mut self, source: &mut impl FrameSource) -> Result<Vec<MotionEvent>, MotionError> {
+        if self.cancel.take() {
+            return Err(MotionError::Cancelled);
+        }
+        let frame = source.next_frame().map_err(MotionError::Source)?.ok_or(MotionError::EndOfInput)?;
+        if frame.len() < self.frame_len {
+            return Err(MotionError::FrameSize(frame.len()));
+        }
+        self.frames += 1;
+        let now = self.now();
+        // The camera settles, nothing is compared yet. The first frame after it is the baseline.
+        if self.frames <= self.warm_up_frames {
+            return Ok(Vec::new());
+        }
+        if self.frames == self.warm_up_frames + 1 {
+            self.motion.ready(now);
+            self.still_since = Some(now);
+        }
+        if self.control.paused {
+            return Ok(Vec::new());
+        }
+        self.thumb.downsample(frame, self.frame_width, self.downsample, self.layout);
+        let Some((averaged, now)) = self.averager.push(&self.thumb, now) else {
+            return Ok(Vec::new());
+        };
+        self.strategy.set_motion_active(self.motion.is_active());
+        let result = self.strategy.process(averaged);
+        if self.resume.is_pending() {
+            let (_, start_count, _) = counts(&self.control, &self.thumb);
+            self.resume_delta = self.resume.compared(result.changed_pixels, start_count, result.mask, averaged.width, averaged.height);
+            self.rebaseline();
+            return Ok(Vec::new());
+        }
+        // Compared with nothing, the baseline would be a still frame to the tracker, and a movement
+        // in progress at the ready time would no longer be one.
+        if std::mem::take(&mut self.baseline) {
+            return Ok(Vec::new());
+        }
+        let events = self.motion.update(result.changed_pixels, result.score, now);
+        for event in &events {
+            match event {
+                MotionEvent::Start { .. } => self.still_since = None,
+                MotionEvent::Stop { at, .. } => self.still_since = Some(*at),
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+
+// The pixel threshold in 0 - 255 units, and the changed pixels of `thumb` starting and sustaining a
+// movement, from the percentages of `control`.
+fn counts(control: &ControlState, thumb: &Thumbnail) -> (i32, i32, i32) {
+    let thumb_len = (thumb.width * thumb.height) as f32;
+    let pixel_threshold = ((control.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let start_count = (thumb_len * (control.image_threshold / 100.0).clamp(0.0, 1.0)) as i32;
+    let sustain_count = (thumb_len * (control.sustain_percent() / 100.0).clamp(0.0, 1.0)) as i32;
+    (pixel_threshold, start_count, sustain_count)
+}
@@ -0,0 +1,1480 @@
+use crate::{ mask::MaskImage, noise::{ AdaptiveThreshold, NoiseMap }, thumbnail::{ ReferencePolicy, ThumbPair, Thumbnail } };
+
+/// Names accepted by `from_name`, also listed in the --help text.
+pub const STRATEGY_NAMES: &[&str] = &["frame-diff", "adaptive", "edges"];
+
+// Rows `FrameDiff` compares between looks at whether it can stop early.
+const BAND_ROWS: usize = 8;
+
+/// Thumbnail pixels `Stabilize` looks for the picture's shift within, in each direction.
+pub const SEARCH_SHIFT: usize = 2;
+
+// Tiles across and down `WhiteBalance` checks a color cast is uniform over, and the smallest cast
+// it compensates, in thumbnail values.
+const CAST_TILES: usize = 4;
+const MIN_CAST: i64 = 4;
+
+
+/// The outcome of comparing a thumbnail against a strategy's reference.
+pub struct DiffResult<'a> {
+    pub changed_pixels: i32,
+    pub mask: &'a [u8],           // One byte per thumbnail pixel, 1 where the pixel changed.
+    pub score: f32,               // Percentage of changed pixels.
+    pub skipped_pixels: usize,    // Left uncompared by an early exit, unchanged in the mask.
+    pub shift: Option<Shift>,     // How much `Stabilize` found the camera shook, if it did.
+    pub cast: Option<ColorCast>,  // The color cast `WhiteBalance` found, if it did.
+}
+
+
+/// How far the whole picture moved since the previous thumbnail, in thumbnail pixels, with the
+/// content at x, y then found at x + `x`, y + `y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shift {
+    pub x: i32,
+    pub y: i32,
+    pub compensated: bool,  // Within the max shift and compared realigned, otherwise as it came.
+}
+
+
+/// How far the color of the whole picture moved from the reference's, as a camera's auto white
+/// balance hunting makes it, per channel in red, green, blue order and in thumbnail values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorCast {
+    pub shifts: [i32; 3],
+    pub compensated: bool,  // Uniform enough and taken out before comparing, otherwise compared as it came.
+}
+
+
+/// A way to tell how much of a thumbnail changed. Strategies own whatever reference they compare
+/// against, so the event logic only ever sees a `DiffResult`.
+pub trait DiffStrategy {
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_>;
+
+    /// The thumbnail new frames are compared against, if the strategy keeps one.
+    fn reference(&self) -> Option<&Thumbnail> {
+        None
+    }
+
+    /// Replaces the reference, e.g. with one restored from a state file.
+    fn set_reference(&mut self, _reference: Thumbnail) {}
+
+    /// The learned per-pixel noise, if the strategy learns one.
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        None
+    }
+
+    /// Replaces the learned noise, e.g. with one restored from a state file.
+    fn set_noise_map(&mut self, _noise_map: NoiseMap) {}
+
+    /// Tells the strategy whether a movement is in progress, so it can stop learning from frames
+    /// that contain it.
+    fn set_motion_active(&mut self, _active: bool) {}
+
+    /// Lets the strategy stop comparing a thumbnail as soon as it changed enough for a movement.
+    /// Only for callers that need nothing but the changed count's side of the threshold: the mask
+    /// and score then miss whatever wasn't compared. Strategies that can't, and wrappers that need
+    /// the whole mask of the strategy they wrap, ignore it.
+    fn set_early_exit(&mut self, _enabled: bool) {}
+
+    /// Raises the pixel threshold by `raise`, from 0 to 255, for the thumbnails processed until
+    /// it's set again, e.g. while the camera's exposure settles. Strategies without a pixel
+    /// threshold ignore it.
+    fn raise_pixel_threshold(&mut self, _raise: i32) {}
+
+    /// Bytes of the buffers the strategy holds, wrapped strategies included.
+    fn buffer_bytes(&self) -> usize {
+        0
+    }
+}
+
+
+/// Creates a strategy from its configuration name.
+///
+/// * `pixel_threshold` - How much a channel must change to count, from 0 to 255.
+/// * `update_count` - Changed pixels needed for a frame to become the new reference.
+/// * `adaptive` - How learned thresholds are derived, only used by "adaptive".
+/// * `edge_threshold` - Gradient that makes a pixel an edge, from 0 to 255, only used by "edges".
+/// * `channels` - What of each pixel is compared, "edges" always compares luma.
+pub fn from_name(name: &str, pixel_threshold: i32, update_count: i32, adaptive: AdaptiveThreshold, edge_threshold: i32, channels: Channels) -> Option<Box<dyn DiffStrategy>> {
+    let frame_diff = FrameDiff::new(pixel_threshold, update_count).with_channels(channels);
+    let strategy: Box<dyn DiffStrategy> = match name {
+        "frame-diff" => Box::new(frame_diff),
+        "adaptive" => Box::new(frame_diff.with_adaptive_threshold(adaptive)),
+        "edges" => return Some(Box::new(EdgeDiff::new(edge_threshold, update_count))),
+        _ => return None,
+    };
+    Some(match channels {
+        Channels::Rgb => strategy,
+        Channels::HueSaturation | Channels::Value => Box::new(HsvConvert::new(strategy)),
+    })
+}
+
+
+/// What `FrameDiff` compares of each pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channels {
+    Rgb,            // The largest change of red, green and blue.
+    HueSaturation,  // Of thumbnails converted to HSV by `HsvConvert`: ignores brightness alone...
+    Value,          // ...or only sees it.
+}
+
+
+impl Channels {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "rgb" => Ok(Channels::Rgb),
+            "hsv:hs" => Ok(Channels::HueSaturation),
+            "hsv:v" => Ok(Channels::Value),
+            _ => Err(format!("Unknown channels '{text}', use rgb, hsv:hs or hsv:v")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channels::Rgb => "rgb",
+            Channels::HueSaturation => "hsv:hs",
+            Channels::Value => "hsv:v",
+        }
+    }
+}
+
+
+/// Compares each frame against a reference frame, counting pixels where any channel changed by at
+/// least the pixel threshold. The reference is only replaced by frames that changed enough to count
+/// as movement, so slow changes still add up against it.
+///
+/// With an adaptive threshold, each pixel is instead compared against a multiple of its own noise,
+/// learned from quiet frames, so a noisy dark corner doesn't need the same threshold as a bright
+/// static wall. The global threshold is used until enough frames were learned.
+///
+/// Pixels are compared in bands of rows. With an early exit, the rest of the thumbnail is skipped
+/// once a band ends with more than the update count changed: the frame becomes the reference and
+/// teaches no noise whatever the remaining pixels do, so only its mask and score are cut short.
+pub struct FrameDiff {
+    pixel_threshold: i32,
+    update_count: i32,
+    thumbs: ThumbPair,      // The frame compared and the reference, replaced on motion.
+    mask: Vec<u8>,
+    adaptive: Option<AdaptiveThreshold>,
+    noise_map: Option<NoiseMap>,
+    differences: Vec<u8>,
+    motion_active: bool,
+    channels: Channels,
+    early_exit: bool,
+    threshold_raise: i32,
+}
+
+
+impl FrameDiff {
+    pub fn new(pixel_threshold: i32, update_count: i32) -> Self {
+        Self {
+            pixel_threshold,
+            update_count,
+            thumbs: ThumbPair::new(0, 0, 3, ReferencePolicy::OnMotion),
+            mask: Vec::new(),
+            adaptive: None,
+            noise_map: None,
+            differences: Vec::new(),
+            motion_active: false,
+            channels: Channels::Rgb,
+            early_exit: false,
+            threshold_raise: 0,
+        }
+    }
+
+    /// Compares HSV components instead, of thumbnails already converted by `HsvConvert`.
+    pub fn with_channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_adaptive_threshold(mut self, adaptive: AdaptiveThreshold) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    /// Stops comparing once the changed count is over the update count, see `set_early_exit`.
+    pub fn with_early_exit(mut self) -> Self {
+        self.early_exit = true;
+        self
+    }
+}
+
+
+impl DiffStrategy for FrameDiff {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        self.mask.resize(thumb.len(), 0);
+        self.thumbs.set_current(thumb);
+
+        // The first frame (or a size change) only sets the reference.
+        if !self.thumbs.previous().is_some_and(|reference| reference.same_shape(thumb)) {
+            self.thumbs.swap();
+            self.mask.fill(0);
+            return DiffResult { changed_pixels: 0, mask: &self.mask, score: 0.0, skipped_pixels: 0, shift: None, cast: None };
+        }
+        // A reference restored without extremes takes them from the first frame.
+        if let Some(reference) = self.thumbs.previous_mut().filter(|reference| reference.extremes.len() != thumb.extremes.len()) {
+            reference.extremes.clone_from(&thumb.extremes);
+        }
+        let reference = self.thumbs.previous().expect("Compared against a reference");
+
+        // Learned thresholds, once there are enough of them for this thumbnail size.
+        let noise_map = match (&self.adaptive, &mut self.noise_map) {
+            (Some(_), Some(noise_map)) if noise_map.width == thumb.width && noise_map.height == thumb.height => Some(noise_map),
+            (Some(_), noise_map) => Some(noise_map.insert(NoiseMap::new(thumb.width, thumb.height))),
+            (None, _) => None,
+        };
+        self.differences.resize(thumb.len(), 0);
+
+        // Pixel change detection, a band of rows at a time
+        let band_len = thumb.width.max(1) * BAND_ROWS;
+        let mut changed_pixels = 0;
+        let mut compared = 0;
+        while compared < thumb.len() && !(self.early_exit && changed_pixels > self.update_count) {
+            let band_end = (compared + band_len).min(thumb.len());
+            for index in compared .. band_end {
+                let previous_pixel = reference.pixel(index);
+                let pixel = thumb.pixel(index);
+
+                // Largest channel difference
+                let difference = if thumb.channels == 1 {
+                    // Luma thumbnails from grayscale cameras
+                    (pixel[0] as i32 - previous_pixel[0] as i32).abs()
+                } else if self.channels == Channels::Value {
+                    (pixel[2] as i32 - previous_pixel[2] as i32).abs()
+                } else if self.channels == Channels::HueSaturation {
+                    // Half a turn of hue is as far as it gets, and counts like a full scale change.
+                    // The hue of nearly gray pixels is mostly noise, so it only counts as much as
+                    // they are saturated.
+                    let saturation = pixel[1].min(previous_pixel[1]) as i32;
+                    let diff_h = (hue_distance(pixel[0], previous_pixel[0]) as i32 * 2).min(255) * saturation / 255;
+                    let diff_s = (pixel[1] as i32 - previous_pixel[1] as i32).abs();
+                    diff_h.max(diff_s)
+                } else {
+                    let diff_r = (pixel[0] as i32 - previous_pixel[0] as i32).abs();
+                    let diff_g = (pixel[1] as i32 - previous_pixel[1] as i32).abs();
+                    let diff_b = (pixel[2] as i32 - previous_pixel[2] as i32).abs();
+
+                    diff_r.max(diff_g).max(diff_b)
+                };
+                // A small object moving inside a block barely moves its mean, but moves its extremes.
+                let difference = match (thumb.extremes(index), reference.extremes(index)) {
+                    (Some(extremes), Some(previous_extremes)) => extremes.iter().zip(previous_extremes)
+                        .fold(difference, |difference, (value, previous)| difference.max(value.abs_diff(*previous) as i32)),
+                    _ => difference,
+                };
+                let threshold = self.threshold_raise + match (&noise_map, &self.adaptive) {
+                    (Some(noise_map), Some(adaptive)) if noise_map.is_ready() => noise_map.threshold(index, adaptive),
+                    _ => self.pixel_threshold,
+                };
+
+                let changed = difference >= threshold;
+                self.differences[index] = difference as u8;
+                self.mask[index] = changed as u8;
+                changed_pixels += changed as i32;
+            }
+            compared = band_end;
+        }
+        let skipped_pixels = thumb.len() - compared;
+        self.mask[compared ..].fill(0);
+
+        // Only quiet frames teach the noise map, so moving objects don't inflate their own thresholds.
+        if let Some(noise_map) = noise_map {
+            if !self.motion_active && changed_pixels <= self.update_count {
+                noise_map.learn(&self.differences);
+            }
+        }
+
+        self.thumbs.advance(changed_pixels > self.update_count);
+
+        let score = changed_pixels as f32 * 100.0 / thumb.len().max(1) as f32;
+        DiffResult { changed_pixels, mask: &self.mask, score, skipped_pixels, shift: None, cast: None }
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.thumbs.previous()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.thumbs.set_previous(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.noise_map.as_ref()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        if self.adaptive.is_some() {
+            self.noise_map = Some(noise_map);
+        }
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.motion_active = active;
+    }
+
+    fn set_early_exit(&mut self, enabled: bool) {
+        self.early_exit = enabled;
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.threshold_raise = raise;
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.thumbs.buffer_bytes()
+            + self.mask.capacity()
+            + self.differences.capacity()
+            + self.noise_map.as_ref().map_or(0, NoiseMap::buffer_bytes)
+    }
+}
+
+
+/// Compares edge maps instead of intensities: each thumbnail is reduced to luma, its Sobel gradient
+/// is binarized with the edge threshold, and pixels whose edge state flipped against the reference
+/// count as changed. A passing cloud dims the whole scene but barely moves its edges. As with
+/// `FrameDiff`, the reference is only replaced by frames that changed enough to count as movement.
+pub struct EdgeDiff {
+    edge_threshold: i32,
+    update_count: i32,
+    reference: Option<Thumbnail>,   // As given, the edges are derived from it.
+    reference_edges: Vec<u8>,
+    luma: Vec<u8>,
+    edges: Vec<u8>,
+    mask: Vec<u8>,
+}
+
+
+impl EdgeDiff {
+
+    pub fn new(edge_threshold: i32, update_count: i32) -> Self {
+        Self {
+            edge_threshold,
+            update_count,
+            reference: None,
+            reference_edges: Vec::new(),
+            luma: Vec::new(),
+            edges: Vec::new(),
+            mask: Vec::new(),
+        }
+    }
+
+    // Fills `edges` with the edge map of `thumb`, one byte per pixel, 1 on an edge.
+    fn detect_edges(thumb: &Thumbnail, edge_threshold: i32, luma: &mut Vec<u8>, edges: &mut Vec<u8>) {
+        luma.resize(thumb.len(), 0);
+        edges.resize(thumb.len(), 0);
+        if thumb.channels == 1 {
+            luma.copy_from_slice(&thumb.pixels);
+        } else {
+            // BT.601, in fixed point.
+            for (value, pixel) in luma.iter_mut().zip(thumb.pixels.chunks_exact(thumb.channels)) {
+                *value = ((pixel[0] as u32 * 77 + pixel[1] as u32 * 150 + pixel[2] as u32 * 29) >> 8) as u8;
+            }
+        }
+
+        // Sobel with the border pixels repeated outward. A step of n levels between two flat areas
+        // gives a magnitude of n, so the threshold reads like the pixel threshold.
+        let (width, height) = (thumb.width, thumb.height);
+        let at = |x: usize, y: usize| luma[y * width + x] as i32;
+        for y in 0 .. height {
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+            for x in 0 .. width {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+                let gx = (at(right, up) + 2 * at(right, y) + at(right, down)) - (at(left, up) + 2 * at(left, y) + at(left, down));
+                let gy = (at(left, down) + 2 * at(x, down) + at(right, down)) - (at(left, up) + 2 * at(x, up) + at(right, up));
+                let magnitude = (gx.abs() + gy.abs()) / 4;
+                edges[y * width + x] = (magnitude >= edge_threshold) as u8;
+            }
+        }
+    }
+}
+
+
+impl DiffStrategy for EdgeDiff {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        self.mask.resize(thumb.len(), 0);
+        Self::detect_edges(thumb, self.edge_threshold, &mut self.luma, &mut self.edges);
+
+        // The first frame (or a size change) only sets the reference.
+        let reference = match &mut self.reference {
+            Some(reference) if reference.same_shape(thumb) && self.reference_edges.len() == thumb.len() => reference,
+            _ => {
+                self.reference = Some(thumb.clone());
+                self.reference_edges.clone_from(&self.edges);
+                self.mask.fill(0);
+                return DiffResult { changed_pixels: 0, mask: &self.mask, score: 0.0, skipped_pixels: 0, shift: None, cast: None };
+            }
+        };
+
+        let mut changed_pixels = 0;
+        for ((changed, edge), reference_edge) in self.mask.iter_mut().zip(&self.edges).zip(&self.reference_edges) {
+            *changed = (edge != reference_edge) as u8;
+            changed_pixels += *changed as i32;
+        }
+
+        if changed_pixels > self.update_count {
+            reference.pixels.copy_from_slice(&thumb.pixels);
+            self.reference_edges.copy_from_slice(&self.edges);
+        }
+
+        let score = changed_pixels as f32 * 100.0 / thumb.len().max(1) as f32;
+        DiffResult { changed_pixels, mask: &self.mask, score, skipped_pixels: 0, shift: None, cast: None }
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.reference.as_ref()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        Self::detect_edges(&reference, self.edge_threshold, &mut self.luma, &mut self.reference_edges);
+        self.reference = Some(reference);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.reference.as_ref().map_or(0, Thumbnail::buffer_bytes)
+            + self.reference_edges.capacity()
+            + self.luma.capacity()
+            + self.edges.capacity()
+            + self.mask.capacity()
+    }
+}
+
+
+/// Decorator that box blurs each thumbnail before handing it to the wrapped strategy,
+/// which evens out sensor noise at the cost of small details.
+pub struct Blur {
+    inner: Box<dyn DiffStrategy>,
+    radius: usize,
+    horizontal: Thumbnail,
+    blurred: Thumbnail,
+}
+
+
+impl Blur {
+    pub fn new(inner: Box<dyn DiffStrategy>, radius: usize) -> Self {
+        Self { inner, radius, horizontal: Thumbnail::new(0, 0), blurred: Thumbnail::new(0, 0) }
+    }
+}
+
+
+impl DiffStrategy for Blur {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        if !self.blurred.same_shape(thumb) {
+            self.horizontal = Thumbnail::with_channels(thumb.width, thumb.height, thumb.channels);
+            self.blurred = Thumbnail::with_channels(thumb.width, thumb.height, thumb.channels);
+        }
+        // Separable box blur, rows first then columns, clamped at the borders.
+        box_blur_pass(thumb, &mut self.horizontal, self.radius, true);
+        box_blur_pass(&self.horizontal, &mut self.blurred, self.radius, false);
+        // Blurring the extremes would average away what they're kept for.
+        self.blurred.extremes.clone_from(&thumb.extremes);
+        self.inner.process(&self.blurred)
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn set_early_exit(&mut self, enabled: bool) {
+        self.inner.set_early_exit(enabled);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.horizontal.buffer_bytes() + self.blurred.buffer_bytes()
+    }
+}
+
+
+/// Alternations in a row after which a pixel counts as flickering: A, B, A, B.
+pub const FLICKER_STEPS: u8 = 2;
+
+
+/// Ignores the pixels that flicker, for --flicker-rejection: an LED matrix or a PWM-dimmed lamp
+/// whose frequency beats with the capture interval jumps between two values every frame, and
+/// would otherwise count as changed all the time. A pixel flickers once it went back and forth
+/// between the same two values, each within the pixel threshold of itself and at least that far
+/// from the other, for FLICKER_STEPS frames in a row. It counts again as soon as it settles or
+/// takes a third value, as something moving in front of it does. Flicker can't be told from
+/// change before that many frames were seen, so nothing counts as changed until then, after
+/// every (re)start.
+///
+/// Each pixel's brightest channel is followed in three bytes: its last two values and how many
+/// alternations in a row led to them.
+pub struct FlickerRejection {
+    inner: Box<dyn DiffStrategy>,
+    pixel_threshold: i32,
+    history: Vec<[u8; 3]>,  // Value one frame ago, two frames ago, alternations in a row.
+    frames: u8,             // Seen since the start, up to the first one that can tell.
+    mask: Vec<u8>,
+}
+
+
+impl FlickerRejection {
+    pub fn new(inner: Box<dyn DiffStrategy>, pixel_threshold: i32) -> Self {
+        Self { inner, pixel_threshold, history: Vec::new(), frames: 0, mask: Vec::new() }
+    }
+}
+
+
+impl DiffStrategy for FlickerRejection {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        // A new size starts over, with every pixel its own last two values.
+        if self.history.len() != thumb.len() {
+            self.history = (0 .. thumb.len()).map(|index| {
+                let value = *thumb.pixel(index).iter().max().unwrap_or(&0);
+                [value, value, 0]
+            }).collect();
+            self.mask = vec![0; thumb.len()];
+            self.frames = 0;
+        }
+        let learning = self.frames < FLICKER_STEPS + 1;
+        self.frames = (self.frames + 1).min(FLICKER_STEPS + 1);
+        let threshold = self.pixel_threshold.max(1);
+        let result = self.inner.process(thumb);
+        let mut changed_pixels = 0;
+        for (index, (history, masked)) in self.history.iter_mut().zip(self.mask.iter_mut()).enumerate() {
+            let value = *thumb.pixel(index).iter().max().unwrap_or(&0);
+            let [previous, before, steps] = *history;
+            let alternated = (value as i32 - previous as i32).abs() >= threshold && (value as i32 - before as i32).abs() < threshold;
+            let steps = if alternated { steps.saturating_add(1) } else { 0 };
+            *history = [value, previous, steps];
+            *masked = result.mask[index] & (steps < FLICKER_STEPS && !learning) as u8;
+            changed_pixels += *masked as i32;
+        }
+        DiffResult {
+            changed_pixels,
+            mask: &self.mask,
+            score: changed_pixels as f32 * 100.0 / thumb.len().max(1) as f32,
+            skipped_pixels: 0,
+            shift: result.shift,
+            cast: result.cast,
+        }
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.history.capacity() * 3 + self.mask.capacity()
+    }
+}
+
+
+/// Ignores the pixels a --mask image blacks out: they never count as changed, whatever the inner
+/// strategy found. With a --weight-map, changed pixels count as much as their weight, and the
+/// changed count is the weighted share of the total weight, in thumbnail pixels: a change where the
+/// weight is half needs twice the area to reach the same count. A mask counts its watched pixels
+/// as before. The image is scaled to the thumbnail grid on the first frame of each size.
+pub struct Masked {
+    inner: Box<dyn DiffStrategy>,
+    image: MaskImage,
+    weights: Vec<u8>,       // The image fitted to the thumbnail grid.
+    total_weight: u64,      // What all pixels changing scores, the thumbnail size in 255ths for a mask.
+    masked: Vec<u8>,
+    shape: (usize, usize),
+}
+
+
+impl Masked {
+    pub fn new(inner: Box<dyn DiffStrategy>, image: MaskImage) -> Self {
+        Self { inner, image, weights: Vec::new(), total_weight: 0, masked: Vec::new(), shape: (0, 0) }
+    }
+}
+
+
+impl DiffStrategy for Masked {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        if self.shape != (thumb.width, thumb.height) {
+            self.shape = (thumb.width, thumb.height);
+            self.weights = self.image.fit(thumb.width, thumb.height);
+            self.total_weight = match self.image.weighted {
+                true => self.weights.iter().map(|weight| *weight as u64).sum(),
+                false => thumb.len() as u64 * 255,
+            };
+            self.masked = vec![0; thumb.len()];
+        }
+        let result = self.inner.process(thumb);
+        let mut changed_weight = 0u64;
+        for ((masked, changed), weight) in self.masked.iter_mut().zip(result.mask).zip(&self.weights) {
+            *masked = *changed & (*weight != 0) as u8;
+            changed_weight += *masked as u64 * *weight as u64;
+        }
+        let changed_pixels = (changed_weight * thumb.len() as u64 / self.total_weight.max(1)) as i32;
+        DiffResult {
+            changed_pixels,
+            mask: &self.masked,
+            score: changed_pixels as f32 * 100.0 / thumb.len().max(1) as f32,
+            skipped_pixels: 0,
+            shift: result.shift,
+            cast: result.cast,
+        }
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.image.weights.capacity() + self.weights.capacity() + self.masked.capacity()
+    }
+}
+
+
+/// Converts thumbnails to HSV before the inner strategy compares them, into a plane allocated once
+/// per thumbnail size. Components are bytes: hue in 256ths of a turn, saturation and value from 0
+/// to 255. Single channel thumbnails have no hue and go through as they are.
+pub struct HsvConvert {
+    inner: Box<dyn DiffStrategy>,
+    hsv: Thumbnail,
+}
+
+
+impl HsvConvert {
+    pub fn new(inner: Box<dyn DiffStrategy>) -> Self {
+        Self { inner, hsv: Thumbnail::new(0, 0) }
+    }
+}
+
+
+impl DiffStrategy for HsvConvert {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        if thumb.channels == 1 {
+            return self.inner.process(thumb);
+        }
+        if !self.hsv.same_shape(thumb) {
+            self.hsv = Thumbnail::with_channels(thumb.width, thumb.height, 3);
+        }
+        for (hsv, rgb) in self.hsv.pixels.chunks_exact_mut(3).zip(thumb.pixels.chunks_exact(thumb.channels)) {
+            hsv.copy_from_slice(&rgb_to_hsv(rgb[0], rgb[1], rgb[2]));
+        }
+        self.inner.process(&self.hsv)
+    }
+
+    // In HSV, saved and restored as such.
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn set_early_exit(&mut self, enabled: bool) {
+        self.inner.set_early_exit(enabled);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.hsv.buffer_bytes()
+    }
+}
+
+
+/// Hue, saturation and value of an RGB pixel, in integers. Gray has a hue of 0.
+pub fn rgb_to_hsv(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta == 0 {
+        return [0, 0, max as u8];
+    }
+    // In degrees first, each primary a third of a turn from the next.
+    let degrees = if max == r {
+        (60 * (g - b) / delta).rem_euclid(360)
+    } else if max == g {
+        120 + 60 * (b - r) / delta
+    } else {
+        240 + 60 * (r - g) / delta
+    };
+    [(degrees * 256 / 360) as u8, (255 * delta / max) as u8, max as u8]
+}
+
+
+/// How far apart two hues are, the short way around: from 0 to 128, half a turn. 250° and 10° are
+/// a third of a turn apart, not two thirds.
+pub fn hue_distance(a: u8, b: u8) -> u8 {
+    let distance = a.abs_diff(b) as u16;
+    distance.min(256 - distance) as u8
+}
+
+
+/// How `Normalize` matches thumbnails to the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    Gain,       // Same mean and standard deviation, undoes a global gain and offset.
+    Histogram,  // Same brightness distribution, also undoes non linear changes like gamma.
+}
+
+
+impl Normalization {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Normalization::Gain => "gain",
+            Normalization::Histogram => "histogram",
+        }
+    }
+}
+
+
+/// Remaps each thumbnail's brightness to match the inner strategy's reference before comparing,
+/// so a camera's slow auto gain drift doesn't count as change. Pixels that changed locally are left
+/// out when estimating the remap, so they still stand out after it. Works on integers with a lookup
+/// table built per frame, without allocating after the first frame.
+pub struct Normalize {
+    inner: Box<dyn DiffStrategy>,
+    mode: Normalization,
+    normalized: Thumbnail,
+}
+
+
+impl Normalize {
+    pub fn new(inner: Box<dyn DiffStrategy>, mode: Normalization) -> Self {
+        Self { inner, mode, normalized: Thumbnail::new(0, 0) }
+    }
+}
+
+
+impl DiffStrategy for Normalize {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        let Some(reference) = self.inner.reference().filter(|reference| reference.same_shape(thumb)) else {
+            return self.inner.process(thumb); // Nothing to match yet.
+        };
+        let table = matching_table(self.mode, &thumb.pixels, &reference.pixels);
+        if !self.normalized.same_shape(thumb) {
+            self.normalized = Thumbnail::with_channels(thumb.width, thumb.height, thumb.channels);
+        }
+        for (normalized, value) in self.normalized.pixels.iter_mut().zip(&thumb.pixels) {
+            *normalized = table[*value as usize];
+        }
+        self.normalized.extremes.clear();
+        self.normalized.extremes.extend(thumb.extremes.iter().map(|value| table[*value as usize]));
+        self.inner.process(&self.normalized)
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn set_early_exit(&mut self, enabled: bool) {
+        self.inner.set_early_exit(enabled);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.normalized.buffer_bytes()
+    }
+}
+
+
+/// Compensates small camera vibrations, for --stabilize: a camera swaying a pixel moves every edge
+/// of the scene at once, which counts as change all over. Before the inner strategy compares a
+/// thumbnail, the shift of the whole picture since the previous one is estimated: of the shifts of
+/// up to SEARCH_SHIFT pixels each way, the one with the smallest sum of absolute differences. If it
+/// explains most of the difference, removing at least half of it, and is no larger than the max
+/// shift, the thumbnail is compared moved back by it, the strip this leaves at the border taken
+/// from the previous thumbnail. A larger shift is the camera itself moving, compared as it came.
+///
+/// The previous thumbnail is kept realigned, so a camera swaying back and forth stays aligned with
+/// where it was. Both buffers are allocated once per thumbnail size.
+pub struct Stabilize {
+    inner: Box<dyn DiffStrategy>,
+    max_shift: usize,
+    previous: Thumbnail,
+    shifted: Thumbnail,
+}
+
+
+impl Stabilize {
+    pub fn new(inner: Box<dyn DiffStrategy>, max_shift: usize) -> Self {
+        Self { inner, max_shift, previous: Thumbnail::new(0, 0), shifted: Thumbnail::new(0, 0) }
+    }
+}
+
+
+impl DiffStrategy for Stabilize {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        if !self.previous.same_shape(thumb) || self.previous.extremes.len() != thumb.extremes.len() {
+            self.previous = thumb.clone();
+            self.shifted = thumb.clone();
+            return self.inner.process(thumb); // Nothing to align with yet.
+        }
+        let shift = estimate_shift(thumb, &self.previous)
+            .map(|(x, y)| Shift { x, y, compensated: x.unsigned_abs().max(y.unsigned_abs()) as usize <= self.max_shift });
+        let compared = match shift {
+            Some(shift) if shift.compensated => {
+                realign(thumb, &self.previous, shift, &mut self.shifted);
+                &self.shifted
+            }
+            _ => thumb,
+        };
+        self.previous.pixels.copy_from_slice(&compared.pixels);
+        self.previous.extremes.copy_from_slice(&compared.extremes);
+        let mut result = self.inner.process(compared);
+        result.shift = shift;
+        result
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn set_early_exit(&mut self, enabled: bool) {
+        self.inner.set_early_exit(enabled);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.previous.buffer_bytes() + self.shifted.buffer_bytes()
+    }
+}
+
+
+/// Takes out the color casts of a camera's auto white balance hunting, for --wb-compensation: the
+/// whole scene turning bluer or more amber moves every pixel's channels, though luma barely moves.
+/// Before the inner strategy compares a thumbnail, the mean shift of each channel from its
+/// reference is taken in each of CAST_TILES by CAST_TILES tiles, and the picture's cast is their median. A
+/// cast of at least MIN_CAST, with a channel going up and another down, is compensated when three
+/// quarters of the tiles shifted within the tolerance of it: the thumbnail is compared with the
+/// cast subtracted, so a local change still stands out. A cast that isn't uniform is the light in
+/// part of the scene changing, compared as it came. RGB thumbnails only, others pass through.
+pub struct WhiteBalance {
+    inner: Box<dyn DiffStrategy>,
+    tolerance: i64,         // In thumbnail values, with 8 fractional bits.
+    corrected: Thumbnail,
+}
+
+
+impl WhiteBalance {
+
+    /// `tolerance` is how far, in percent, each tile's shift may be from the picture's.
+    pub fn new(inner: Box<dyn DiffStrategy>, tolerance: f32) -> Self {
+        let tolerance = (tolerance.clamp(0.0, 100.0) * (255.0 / 100.0) * 256.0) as i64;
+        Self { inner, tolerance, corrected: Thumbnail::new(0, 0) }
+    }
+}
+
+
+impl DiffStrategy for WhiteBalance {
+
+    fn process(&mut self, thumb: &Thumbnail) -> DiffResult<'_> {
+        let Some(reference) = self.inner.reference().filter(|reference| reference.same_shape(thumb)) else {
+            return self.inner.process(thumb); // Nothing to match yet.
+        };
+        let Some(cast) = estimate_cast(thumb, reference, self.tolerance) else {
+            return self.inner.process(thumb);
+        };
+        let compared = match cast.compensated {
+            true => {
+                if !self.corrected.same_shape(thumb) {
+                    self.corrected = Thumbnail::with_channels(thumb.width, thumb.height, thumb.channels);
+                }
+                let correct = |(index, value): (usize, &u8)| (*value as i32 - cast.shifts[index % 3]).clamp(0, 255) as u8;
+                for (corrected, value) in self.corrected.pixels.iter_mut().zip(thumb.pixels.iter().enumerate().map(correct)) {
+                    *corrected = value;
+                }
+                // The extremes of each pixel are its channels' minimums, then their maximums.
+                self.corrected.extremes.clear();
+                self.corrected.extremes.extend(thumb.extremes.iter().enumerate().map(correct));
+                &self.corrected
+            }
+            false => thumb,
+        };
+        let mut result = self.inner.process(compared);
+        result.cast = Some(cast);
+        result
+    }
+
+    fn reference(&self) -> Option<&Thumbnail> {
+        self.inner.reference()
+    }
+
+    fn set_reference(&mut self, reference: Thumbnail) {
+        self.inner.set_reference(reference);
+    }
+
+    fn noise_map(&self) -> Option<&NoiseMap> {
+        self.inner.noise_map()
+    }
+
+    fn set_noise_map(&mut self, noise_map: NoiseMap) {
+        self.inner.set_noise_map(noise_map);
+    }
+
+    fn set_motion_active(&mut self, active: bool) {
+        self.inner.set_motion_active(active);
+    }
+
+    fn raise_pixel_threshold(&mut self, raise: i32) {
+        self.inner.raise_pixel_threshold(raise);
+    }
+
+    fn set_early_exit(&mut self, enabled: bool) {
+        self.inner.set_early_exit(enabled);
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.inner.buffer_bytes() + self.corrected.buffer_bytes()
+    }
+}
+
+
+// Builds the table in two passes: the second one only learns from pixels the first table already
+// matched well, so a moving object doesn't skew the statistics of the rest of the frame.
+fn matching_table(mode: Normalization, current: &[u8], reference: &[u8]) -> [u8; 256] {
+    const OUTLIER: u8 = 24;
+    let build = |first: Option<&[u8; 256]>| {
+        let pairs = current.iter().zip(reference).map(|(current, reference)| (*current, *reference))
+            .filter(move |(current, reference)| first.is_none_or(|table| table[*current as usize].abs_diff(*reference) <= OUTLIER));
+        match mode {
+            Normalization::Gain => gain_table(pairs),
+            Normalization::Histogram => histogram_table(pairs),
+        }
+    };
+    let first = build(None);
+    build(Some(&first))
+}
+
+
+// Maps values so their mean and deviation match the reference's. The gain is limited, so a frame
+// that lost all contrast (covered lens, lights off) isn't blown up into noise. Statistics are in
+// fixed point with 8 fractional bits.
+fn gain_table(pairs: impl Iterator<Item = (u8, u8)>) -> [u8; 256] {
+    const MAX_GAIN: i64 = 2;
+    let mut sums = [0u64; 5]; // Count, then sum and sum of squares of current and reference.
+    for (current, reference) in pairs {
+        let (current, reference) = (current as u64, reference as u64);
+        sums[0] += 1;
+        sums[1] += current;
+        sums[2] += current * current;
+        sums[3] += reference;
+        sums[4] += reference * reference;
+    }
+    let count = sums[0].max(1);
+    let statistics = |sum: u64, sum_of_squares: u64| {
+        let mean = (sum << 8) / count;
+        let variance = ((sum_of_squares << 16) / count).saturating_sub(mean * mean);
+        (mean as i64, variance.isqrt() as i64)
+    };
+    let (current_mean, current_deviation) = statistics(sums[1], sums[2]);
+    let (reference_mean, reference_deviation) = statistics(sums[3], sums[4]);
+    let reference_deviation = reference_deviation.clamp(current_deviation / MAX_GAIN, current_deviation * MAX_GAIN);
+
+    let mut table = [0u8; 256];
+    for (value, mapped) in table.iter_mut().enumerate() {
+        let centered = ((value as i64) << 8) - current_mean;
+        let scaled = if current_deviation > 0 { centered * reference_deviation / current_deviation } else { centered };
+        *mapped = ((reference_mean + scaled + 128) >> 8).clamp(0, 255) as u8;
+    }
+    table
+}
+
+
+// Maps each value to the reference value at the same position in the cumulative histogram.
+fn histogram_table(pairs: impl Iterator<Item = (u8, u8)>) -> [u8; 256] {
+    let mut current_histogram = [0u64; 256];
+    let mut reference_histogram = [0u64; 256];
+    for (current, reference) in pairs {
+        current_histogram[current as usize] += 1;
+        reference_histogram[reference as usize] += 1;
+    }
+    let mut table = [0u8; 256];
+    let (mut current_total, mut reference_total, mut target) = (0u64, reference_histogram[0], 0usize);
+    for (value, mapped) in table.iter_mut().enumerate() {
+        current_total += current_histogram[value];
+        while target < 255 && reference_total < current_total {
+            target += 1;
+            reference_total += reference_histogram[target];
+        }
+        *mapped = target as u8;
+    }
+    table
+}
+
+
+// Averages each pixel with its neighbours up to `radius` pixels away, along rows or columns.
+fn box_blur_pass(source: &Thumbnail, dest: &mut Thumbnail, radius: usize, along_rows: bool) {
+    let (width, height, channels) = (source.width, source.height, source.channels);
+    let length = if along_rows { width } else { height };
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let position = if along_rows { x } else { y };
+            let first = position.saturating_sub(radius);
+            let last = (position + radius).min(length - 1);
+            let count = (last - first + 1) as u32;
+            for channel in 0 .. channels {
+                let mut sum = 0u32;
+                for step in first ..= last {
+                    let index = if along_rows { y * width + step } else { step * width + x };
+                    sum += source.pixels[index * channels + channel] as u32;
+                }
+                dest.pixels[(y * width + x) * channels + channel] = (sum / count) as u8;
+            }
+        }
+    }
+}
+
+
+// The shift the content of `previous` is found at in `current`, compared over the middle of the
+// thumbnail so that every shift compares the same pixels. None if no shift removes at least half
+// the difference, or the thumbnail is too small to tell.
+fn estimate_shift(current: &Thumbnail, previous: &Thumbnail) -> Option<(i32, i32)> {
+    let (width, height, channels) = (current.width, current.height, current.channels);
+    if width <= 4 * SEARCH_SHIFT || height <= 4 * SEARCH_SHIFT {
+        return None;
+    }
+    let row_len = (width - 2 * SEARCH_SHIFT) * channels;
+    let difference = |x: i32, y: i32| -> u64 {
+        let mut sum = 0;
+        for row in SEARCH_SHIFT .. height - SEARCH_SHIFT {
+            let start = (row * width + SEARCH_SHIFT) * channels;
+            let shifted = (start as isize + (y as isize * width as isize + x as isize) * channels as isize) as usize;
+            sum += current.pixels[shifted .. shifted + row_len].iter()
+                .zip(&previous.pixels[start .. start + row_len])
+                .map(|(value, previous)| value.abs_diff(*previous) as u64)
+                .sum::<u64>();
+        }
+        sum
+    };
+    let search = SEARCH_SHIFT as i32;
+    let unshifted = difference(0, 0);
+    let (smallest, x, y) = (-search ..= search)
+        .flat_map(|y| (-search ..= search).map(move |x| (x, y)))
+        .filter(|shift| *shift != (0, 0))
+        .map(|(x, y)| (difference(x, y), x, y))
+        // Of equal differences, the smallest shift.
+        .min_by_key(|(sum, x, y)| (*sum, x.unsigned_abs().max(y.unsigned_abs())))?;
+    (smallest * 2 <= unshifted && smallest < unshifted).then_some((x, y))
+}
+
+
+// Moves `current` back by `shift` into `out`, pixels and extremes, the pixels it brings in from
+// beyond the border taken from `previous`.
+fn realign(current: &Thumbnail, previous: &Thumbnail, shift: Shift, out: &mut Thumbnail) {
+    let (width, height, channels) = (current.width as i32, current.height as i32, current.channels);
+    let extremes = current.extremes.len() / current.len().max(1);
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let index = (y * width + x) as usize;
+            let (source_x, source_y) = (x + shift.x, y + shift.y);
+            let (source, from) = match (0 .. width).contains(&source_x) && (0 .. height).contains(&source_y) {
+                true => (current, (source_y * width + source_x) as usize),
+                false => (previous, index),
+            };
+            out.pixels[index * channels .. (index + 1) * channels].copy_from_slice(&source.pixels[from * channels .. (from + 1) * channels]);
+            out.extremes[index * extremes .. (index + 1) * extremes].copy_from_slice(&source.extremes[from * extremes .. (from + 1) * extremes]);
+        }
+    }
+}
+
+
+// The cast of `current` from `reference`, the median of each channel's mean shift over the tiles,
+// so a local change doesn't skew it. None unless it's large, a channel up and another down by
+// MIN_CAST or more. Compensated when three quarters of the tiles shifted within `tolerance` of it.
+// Means are in fixed point with 8 fractional bits, like the tolerance.
+fn estimate_cast(current: &Thumbnail, reference: &Thumbnail, tolerance: i64) -> Option<ColorCast> {
+    const TILE_COUNT: usize = CAST_TILES * CAST_TILES;
+    let (width, height) = (current.width, current.height);
+    if current.channels != 3 || width < CAST_TILES || height < CAST_TILES {
+        return None;
+    }
+    let mut tiles = [[0i64; 3]; TILE_COUNT];
+    for (tile, shifts) in tiles.iter_mut().enumerate() {
+        let (column, row) = (tile % CAST_TILES, tile / CAST_TILES);
+        let (left, right) = (column * width / CAST_TILES, (column + 1) * width / CAST_TILES);
+        let (top, bottom) = (row * height / CAST_TILES, (row + 1) * height / CAST_TILES);
+        let mut sums = [0i64; 3];
+        for y in top .. bottom {
+            let span = (y * width + left) * 3 .. (y * width + right) * 3;
+            for (index, (value, previous)) in current.pixels[span.clone()].iter().zip(&reference.pixels[span]).enumerate() {
+                sums[index % 3] += *value as i64 - *previous as i64;
+            }
+        }
+        let count = ((right - left) * (bottom - top)) as i64;
+        *shifts = sums.map(|sum| (sum << 8) / count);
+    }
+    let cast: [i64; 3] = std::array::from_fn(|channel| {
+        let mut shifts = tiles.map(|shifts| shifts[channel]);
+        shifts.sort_unstable();
+        (shifts[TILE_COUNT / 2 - 1] + shifts[TILE_COUNT / 2]) / 2
+    });
+    if *cast.iter().max()? < MIN_CAST << 8 || *cast.iter().min()? > -(MIN_CAST << 8) {
+        return None;
+    }
+    let uniform = tiles.iter()
+        .filter(|shifts| shifts.iter().zip(&cast).all(|(shift, cast)| (shift - cast).abs() <= tolerance))
+        .count();
+    Some(ColorCast { shifts: cast.map(|shift| ((shift + 128) >> 8) as i32), compensated: uniform * 4 >= TILE_COUNT * 3 })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An 8 by 8 RGB thumbnail of `value`.
+    fn flat(value: u8) -> Thumbnail {
+        let mut thumb = Thumbnail::new(8, 8);
+        thumb.pixels.fill(value);
+        thumb
+    }
+
+
+    // The thumbnail of `value` with its first `count` pixels' `channel` at `changed`.
+    fn changed(value: u8, count: usize, channel: usize, changed: u8) -> Thumbnail {
+        let mut thumb = flat(value);
+        for index in 0 .. count {
+            thumb.pixels[index * 3 + channel] = changed;
+        }
+        thumb
+    }
+
+
+    #[test]
+    fn the_first_frame_is_the_reference() {
+        let mut diff = FrameDiff::new(25, 4);
+        let result = diff.process(&flat(100));
+        assert_eq!((result.changed_pixels, result.score), (0, 0.0));
+        assert!(diff.reference().is_some_and(|reference| reference.pixels == flat(100).pixels));
+    }
+
+
+    #[test]
+    fn a_pixel_changes_from_the_pixel_threshold_on_in_any_channel() {
+        let mut diff = FrameDiff::new(25, 64);
+        diff.process(&flat(100));
+        for channel in 0 .. 3 {
+            assert_eq!(diff.process(&changed(100, 10, channel, 124)).changed_pixels, 0);
+            let result = diff.process(&changed(100, 10, channel, 125));
+            assert_eq!(result.changed_pixels, 10);
+            assert_eq!(result.score, 10.0 * 100.0 / 64.0);
+            assert_eq!(&result.mask[.. 11], &[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0]);
+        }
+    }
+
+
+    #[test]
+    fn only_a_movement_replaces_the_reference() {
+        // Slow changes add up against the reference until they're a movement.
+        let mut diff = FrameDiff::new(25, 8);
+        diff.process(&flat(100));
+        assert_eq!(diff.process(&flat(115)).changed_pixels, 0);
+        assert_eq!(diff.process(&flat(130)).changed_pixels, 64);
+        assert_eq!(diff.process(&flat(130)).changed_pixels, 0);
+        // At the update count, it isn't one yet.
+        assert_eq!(diff.process(&changed(130, 8, 0, 200)).changed_pixels, 8);
+        assert_eq!(diff.process(&changed(130, 8, 0, 200)).changed_pixels, 8);
+    }
+
+
+    // A 16 by 16 RGB thumbnail of a scene of random grays, its window `offset` pixels to the right.
+    fn textured(offset: usize) -> Thumbnail {
+        let mut thumb = Thumbnail::new(16, 16);
+        for (index, pixel) in thumb.pixels.chunks_mut(3).enumerate() {
+            let (x, y) = ((index % 16 + offset) as u64, (index / 16) as u64);
+            pixel.fill(((x * 7919 + y * 104_729).wrapping_mul(2_654_435_761) >> 8) as u8 % 200 + 20);
+        }
+        thumb
+    }
+
+
+    #[test]
+    fn a_shift_within_the_max_is_compared_moved_back() {
+        let mut stabilized = Stabilize::new(Box::new(FrameDiff::new(25, 256)), 1);
+        stabilized.process(&textured(2));
+        let result = stabilized.process(&textured(3));
+        let shift = result.shift.expect("The shift was found");
+        assert_eq!((shift.x.abs(), shift.y, shift.compensated), (1, 0, true));
+        assert_eq!(result.changed_pixels, 0);
+        // Unstabilized, the same shift changes most of the picture.
+        let mut plain = FrameDiff::new(25, 256);
+        plain.process(&textured(2));
+        assert!(plain.process(&textured(3)).changed_pixels > 128);
+    }
+
+
+    #[test]
+    fn a_shift_beyond_the_max_is_compared_as_it_came() {
+        let mut stabilized = Stabilize::new(Box::new(FrameDiff::new(25, 256)), 1);
+        stabilized.process(&textured(0));
+        let result = stabilized.process(&textured(2));
+        let shift = result.shift.expect("The shift was found");
+        assert_eq!((shift.x.abs(), shift.y, shift.compensated), (2, 0, false));
+        assert!(result.changed_pixels > 128, "{}", result.changed_pixels);
+    }
+
+
+    #[test]
+    fn a_new_size_starts_over() {
+        let mut diff = FrameDiff::new(25, 4);
+        diff.process(&flat(100));
+        let result = diff.process(&Thumbnail::new(4, 4));
+        assert_eq!((result.changed_pixels, result.mask.len()), (0, 16));
+    }
+
+
+    #[test]
+    fn an_early_exit_stops_after_the_band_that_moved() {
+        let mut thumb = Thumbnail::new(8, BAND_ROWS * 2);
+        let mut diff = FrameDiff::new(25, 4).with_early_exit();
+        diff.process(&thumb);
+        thumb.pixels.fill(200);
+        let result = diff.process(&thumb);
+        assert_eq!((result.changed_pixels, result.skipped_pixels), (8 * BAND_ROWS as i32, 8 * BAND_ROWS));
+    }
+
+
+    // An 8 by 8 gray ramp, through `value(index)` for every pixel.
+    fn ramp(value: impl Fn(usize) -> usize) -> Thumbnail {
+        let mut thumb = Thumbnail::new(8, 8);
+        for (index, pixel) in thumb.pixels.chunks_mut(3).enumerate() {
+            pixel.fill(value(50 + index * 2).min(255) as u8);
+        }
+        thumb
+    }
+
+
+    #[test]
+    fn normalizing_undoes_a_global_brightness_change() {
+        // The camera's gain went up by a fifth, and a small object came in on top of that.
+        let brighter = |value: usize| value * 6 / 5 + 10;
+        let mut moved = ramp(brighter);
+        for pixel in moved.pixels[.. 8 * 3].iter_mut() {
+            *pixel = pixel.saturating_add(80);
+        }
+        let mut plain = FrameDiff::new(25, 64);
+        plain.process(&ramp(|value| value));
+        assert!(plain.process(&ramp(brighter)).changed_pixels > 32);
+        for mode in [Normalization::Gain, Normalization::Histogram] {
+            let mut normalized = Normalize::new(Box::new(FrameDiff::new(25, 64)), mode);
+            normalized.process(&ramp(|value| value));
+            assert_eq!(normalized.process(&ramp(brighter)).changed_pixels, 0, "{}", mode.name());
+            assert_eq!(normalized.process(&moved).changed_pixels, 8, "{}", mode.name());
+        }
+    }
+
+
+    #[test]
+    fn hsv_components_are_bytes() {
+        assert_eq!(rgb_to_hsv(255, 0, 0), [0, 255, 255]);
+        assert_eq!(rgb_to_hsv(0, 255, 0), [85, 255, 255]);
+        assert_eq!(rgb_to_hsv(0, 0, 128), [170, 255, 128]);
+        assert_eq!(rgb_to_hsv(90, 90, 90), [0, 0, 90]);
+        // 250° and 10° are 120° apart the short way around.
+        assert_eq!(hue_distance(178, 7), 85);
+    }
+
+
+    #[test]
+    fn hue_and_saturation_ignore_brightness_alone() {
+        let thumb = |rgb: [u8; 3]| {
+            let mut thumb = Thumbnail::new(8, 8);
+            thumb.pixels.chunks_mut(3).for_each(|pixel| pixel.copy_from_slice(&rgb));
+            thumb
+        };
+        let (orange, dimmer, blue) = (thumb([200, 100, 50]), thumb([100, 50, 25]), thumb([50, 100, 200]));
+        let adaptive = crate::settings::Settings::default().adaptive_threshold();
+        let changed = |channels: Channels, next: &Thumbnail| {
+            let mut diff = from_name("frame-diff", 25, 64, adaptive, 0, channels).unwrap();
+            diff.process(&orange);
+            diff.process(next).changed_pixels
+        };
+        assert_eq!((changed(Channels::Rgb, &dimmer), changed(Channels::Rgb, &blue)), (64, 64));
+        assert_eq!((changed(Channels::HueSaturation, &dimmer), changed(Channels::HueSaturation, &blue)), (0, 64));
+        assert_eq!((changed(Channels::Value, &dimmer), changed(Channels::Value, &blue)), (64, 0));
+    }
+
+
+    // The changed count for the pixels from `first`, `count` of them, changing, seen through `image`.
+    fn masked_count(image: &MaskImage, first: usize, count: usize) -> i32 {
+        let mut masked = Masked::new(Box::new(FrameDiff::new(25, 64)), image.clone());
+        masked.process(&flat(100));
+        let mut thumb = flat(100);
+        thumb.pixels[first * 3 .. (first + count) * 3].fill(200);
+        masked.process(&thumb).changed_pixels
+    }
+
+
+    #[test]
+    fn half_the_weight_takes_twice_the_area() {
+        // The top half at full weight, the bottom half at half of it.
+        let weights = [vec![255; 32], vec![128; 32]].concat();
+        let image = MaskImage { width: 8, height: 8, weights, weighted: true };
+        assert_eq!(masked_count(&image, 0, 8), 10);
+        assert_eq!(masked_count(&image, 32, 8), 5);
+        assert_eq!(masked_count(&image, 32, 16), 10);
+    }
+
+
+    #[test]
+    fn a_binary_mask_counts_the_watched_pixels() {
+        let image = MaskImage { width: 4, height: 4, weights: [vec![255; 8], vec![0; 8]].concat(), weighted: false };
+        assert_eq!(masked_count(&image, 0, 8), 8);
+        assert_eq!(masked_count(&image, 32, 16), 0);
+        assert_eq!(masked_count(&image, 24, 16), 8);
+    }
+
+
+    #[test]
+    fn alternating_pixels_stop_counting_until_they_take_a_third_value() {
+        // With the inner strategy's reference kept on the first frame, every other one differs.
+        let mut flicker = FlickerRejection::new(Box::new(FrameDiff::new(25, 64)), 25);
+        let counts: Vec<i32> = [100, 200, 100, 200, 100, 200, 30, 30, 200]
+            .into_iter()
+            .map(|value| {
+                let mut thumb = flat(100);
+                thumb.pixels[.. 10 * 3].fill(value);
+                flicker.process(&thumb).changed_pixels
+            })
+            .collect();
+        // Learning, then alternated twice, then the third value and the one after count.
+        assert_eq!(counts, [0, 0, 0, 0, 0, 0, 10, 10, 10]);
+    }
+
+
+    #[test]
+    fn a_uniform_cast_is_taken_out_before_comparing() {
+        let mut diff = WhiteBalance::new(Box::new(FrameDiff::new(8, 4)), 2.0);
+        diff.process(&flat(100));
+        let mut cast = flat(100);
+        for pixel in cast.pixels.chunks_mut(3) {
+            (pixel[0], pixel[2]) = (90, 115);
+        }
+        let result = diff.process(&cast);
+        assert_eq!(result.cast, Some(ColorCast { shifts: [-10, 0, 15], compensated: true }));
+        assert_eq!(result.changed_pixels, 0);
+    }
+
+
+    #[test]
+    fn a_change_of_brightness_is_no_cast() {
+        // Every channel went the same way.
+        let mut diff = WhiteBalance::new(Box::new(FrameDiff::new(8, 4)), 2.0);
+        diff.process(&flat(100));
+        let result = diff.process(&flat(115));
+        assert!(result.cast.is_none_or(|cast| !cast.compensated));
+        assert_eq!(result.changed_pixels, 64);
+    }
+}
@@ -0,0 +1,481 @@
+use std::{
+    fs,
+    path::{ Path, PathBuf },
+    sync::{ atomic::{ AtomicBool, AtomicU64, Ordering }, mpsc::{ self, RecvTimeoutError }, Arc },
+    thread,
+    time::{ Duration, Instant, SystemTime, UNIX_EPOCH },
+};
+use lettre::{
+    message::{ header::ContentType, Attachment, Mailbox, MultiPart, SinglePart },
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+
+use crate::{ identity::EventIdentity, output::Format, overlay, settings::{ Settings, SmtpTls }, spool::Spool };
+
+// Emails waiting for the server before new ones are dropped.
+const QUEUE_LIMIT: usize = 16;
+const SERVER_TIMEOUT: Duration = Duration::from_secs(30);
+// Pauses before sending a failed email again, it's spooled or dropped after the last.
+const RETRY_DELAYS: [Duration; 2] = [Duration::from_secs(2), Duration::from_secs(10)];
+// Between attempts at delivering the spooled emails while the server is unreachable.
+const REPLAY_INTERVAL: Duration = Duration::from_secs(60);
+// How long a new email waits for its snapshots, which the media queue writes in the background.
+const SNAPSHOT_WAIT: Duration = Duration::from_secs(5);
+
+
+// Spooled by an earlier version, an email may have no identity.
+enum Email {
+    Start { to: Mailbox, id: u64, identity: Option<EventIdentity>, snapshots: Vec<PathBuf> },
+    Stop { to: Mailbox, id: u64, identity: Option<EventIdentity>, duration: Duration },
+    Digest { to: Mailbox, summary: String, lines: Vec<String> },
+}
+
+
+impl Email {
+
+    fn to(&self) -> &Mailbox {
+        match self {
+            Email::Start { to, .. } | Email::Stop { to, .. } | Email::Digest { to, .. } => to,
+        }
+    }
+}
+
+
+/// An email with the time it was queued at, which a delayed email mentions.
+struct Queued {
+    email: Email,
+    time: SystemTime,
+}
+
+
+impl Queued {
+
+    fn new(email: Email) -> Self {
+        Self { email, time: SystemTime::now() }
+    }
+
+    // The spool record: kind, unix milliseconds, recipient, then the fields of the kind. The
+    // identity key follows the id of a start, and the duration of a stop.
+    fn to_record(&self) -> Vec<String> {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string();
+        let mut record = match &self.email {
+            Email::Start { .. } => vec![String::from("start")],
+            Email::Stop { .. } => vec![String::from("stop")],
+            Email::Digest { .. } => vec![String::from("digest")],
+        };
+        record.extend([time, self.email.to().to_string()]);
+        match &self.email {
+            Email::Start { id, identity, snapshots, .. } => {
+                record.push(id.to_string());
+                record.extend(identity.as_ref().map(EventIdentity::key));
+                record.extend(snapshots.iter().map(|path| path.display().to_string()));
+            }
+            Email::Stop { id, identity, duration, .. } => {
+                record.extend([id.to_string(), duration.as_millis().to_string()]);
+                record.extend(identity.as_ref().map(EventIdentity::key));
+            }
+            Email::Digest { summary, lines, .. } => {
+                record.push(summary.clone());
+                record.extend(lines.iter().cloned());
+            }
+        }
+        record
+    }
+
+    fn from_record(record: &[String]) -> Option<Self> {
+        let [kind, time, to, rest @ ..] = record else { return None };
+        let time = UNIX_EPOCH + Duration::from_millis(time.parse().ok()?);
+        let to: Mailbox = to.parse().ok()?;
+        let email = match (kind.as_str(), rest) {
+            ("start", [id, rest @ ..]) => {
+                let id = id.parse().ok()?;
+                let identity = rest.first().and_then(|key| EventIdentity::parse(key, id));
+                let snapshots = &rest[identity.is_some() as usize ..];
+                Email::Start { to, id, identity, snapshots: snapshots.iter().map(PathBuf::from).collect() }
+            }
+            ("stop", [id, duration, key @ ..]) if key.len() <= 1 => {
+                let id = id.parse().ok()?;
+                let identity = match key {
+                    [key] => Some(EventIdentity::parse(key, id)?),
+                    _ => None,
+                };
+                Email::Stop { to, id, identity, duration: Duration::from_millis(duration.parse().ok()?) }
+            }
+            ("digest", [summary, lines @ ..]) => Email::Digest { to, summary: summary.clone(), lines: lines.to_vec() },
+            _ => return None,
+        };
+        Some(Self { email, time })
+    }
+}
+
+
+/// A recipient, rate limited on its own.
+struct Recipient {
+    address: Mailbox,
+    last_email: Option<Instant>,
+    movement: Option<u64>,  // The movement it got a start email for, until it stops.
+}
+
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    failed: AtomicU64,      // Rejected by the server, or dropped with a full queue or spool.
+    spooled: AtomicU64,     // Waiting in the spool now.
+}
+
+
+/// Emails every recipient when a movement starts, at most once per cooldown, and optionally again
+/// when that movement stops. Emails are sent from a separate thread through a bounded queue, so a
+/// slow or unreachable server can't stall detection.
+///
+/// A failed email is tried again twice. With --email-spool it's then written to the spool, and so
+/// is every email after it until the spool could be delivered, in order, marked as delayed. The
+/// Message-ID of a start or stop email comes from the identity of its event and its recipient,
+/// so a spooled email that was in fact delivered before a crash is the same email twice.
+pub struct Mailer {
+    sender: mpsc::SyncSender<Queued>,
+    recipients: Vec<Recipient>,
+    cooldown: Duration,
+    on_stop: bool,
+    counters: Arc<Counters>,
+    closing: Arc<AtomicBool>,
+    done: mpsc::Receiver<()>,
+}
+
+
+impl Mailer {
+
+    /// Checks the addresses and server options, nothing connects before the first email.
+    pub fn new(settings: &Settings, camera_name: String) -> Result<Self, String> {
+        let server = settings.smtp_server.as_deref().ok_or("no --smtp-server")?;
+        let from = parse_address(settings.smtp_from.as_deref().unwrap_or_default())?;
+        let recipients = settings.smtp_to.iter()
+            .map(|address| Ok(Recipient { address: parse_address(address)?, last_email: None, movement: None }))
+            .collect::<Result<Vec<_>, String>>()?;
+        let transport = transport(server, settings)?;
+        let counters = Arc::new(Counters::default());
+        let spool = match &settings.email_spool {
+            Some(path) => {
+                let (spool, dropped) = Spool::open(path.clone(), settings.email_spool_size)?;
+                if dropped > 0 {
+                    log(settings.format, &format!("Warning, dropped {dropped} unreadable or excess email(s) from the spool {}", path.display()));
+                }
+                counters.spooled.store(spool.len() as u64, Ordering::Relaxed);
+                Some(spool)
+            }
+            None => None,
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LIMIT);
+        let (done_sender, done) = mpsc::channel();
+        let closing = Arc::new(AtomicBool::new(false));
+        let worker = Worker {
+            transport,
+            from,
+            camera_name,
+            format: settings.format,
+            spool,
+            counters: counters.clone(),
+            closing: closing.clone(),
+            next_replay: Instant::now(),
+        };
+        thread::spawn(move || {
+            worker.run(receiver);
+            let _ = done_sender.send(());
+        });
+        Ok(Self { sender, recipients, cooldown: settings.email_cooldown, on_stop: settings.email_stop, counters, closing, done })
+    }
+
+    /// Emails the recipients whose cooldown ran out, with the snapshots saved for the movement.
+    pub fn motion_started(&mut self, identity: &EventIdentity, now: Instant, snapshots: &[PathBuf]) -> Result<(), String> {
+        let id = identity.id;
+        let mut dropped = 0;
+        for recipient in &mut self.recipients {
+            if recipient.last_email.is_some_and(|time| now.saturating_duration_since(time) < self.cooldown) {
+                continue;
+            }
+            let email = Email::Start { to: recipient.address.clone(), id, identity: Some(identity.clone()), snapshots: snapshots.to_vec() };
+            if self.sender.try_send(Queued::new(email)).is_ok() {
+                recipient.last_email = Some(now);
+                recipient.movement = Some(id);
+            } else {
+                dropped += 1;
+            }
+        }
+        self.dropped(dropped)
+    }
+
+    /// Follows up with the recipients that got an email for this movement, with --email-stop.
+    pub fn motion_stopped(&mut self, identity: &EventIdentity, duration: Duration) -> Result<(), String> {
+        let id = identity.id;
+        let mut dropped = 0;
+        for recipient in &mut self.recipients {
+            if recipient.movement != Some(id) {
+                continue;
+            }
+            recipient.movement = None;
+            let email = Email::Stop { to: recipient.address.clone(), id, identity: Some(identity.clone()), duration };
+            if self.on_stop && self.sender.try_send(Queued::new(email)).is_err() {
+                dropped += 1;
+            }
+        }
+        self.dropped(dropped)
+    }
+
+    /// Emails every recipient what happened during quiet hours, whatever the cooldown: the
+    /// `summary` and one of the `lines` per movement, with the paths of its snapshots.
+    pub fn digest(&mut self, summary: &str, lines: &[String]) -> Result<(), String> {
+        let mut dropped = 0;
+        for recipient in &self.recipients {
+            let email = Email::Digest { to: recipient.address.clone(), summary: summary.to_string(), lines: lines.to_vec() };
+            if self.sender.try_send(Queued::new(email)).is_err() {
+                dropped += 1;
+            }
+        }
+        self.dropped(dropped)
+    }
+
+    /// Sends the queued emails and, with --email-spool, tries to deliver the spooled ones once
+    /// more, waiting up to `timeout`. What isn't delivered by then stays in the spool for the
+    /// next start. Returns the number of emails left there.
+    pub fn finish(self, timeout: Duration) -> u64 {
+        self.closing.store(true, Ordering::SeqCst);
+        drop(self.sender);
+        let _ = self.done.recv_timeout(timeout);
+        self.counters.spooled.load(Ordering::Relaxed)
+    }
+
+    /// Emails accepted by the server since launch.
+    pub fn sent(&self) -> u64 {
+        self.counters.sent.load(Ordering::Relaxed)
+    }
+
+    /// Emails that failed or were dropped since launch.
+    pub fn failures(&self) -> u64 {
+        self.counters.failed.load(Ordering::Relaxed)
+    }
+
+    /// Emails waiting in the spool for the server, with --email-spool.
+    pub fn spooled(&self) -> u64 {
+        self.counters.spooled.load(Ordering::Relaxed)
+    }
+
+    fn dropped(&self, count: u64) -> Result<(), String> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.counters.failed.fetch_add(count, Ordering::Relaxed);
+        Err(format!("email queue is full, dropped {count} email(s)"))
+    }
+}
+
+
+// Sends the emails from the queue on its own thread, and keeps the spool.
+struct Worker {
+    transport: SmtpTransport,
+    from: Mailbox,
+    camera_name: String,
+    format: Format,
+    spool: Option<Spool>,
+    counters: Arc<Counters>,
+    closing: Arc<AtomicBool>,   // No more retries, the process is about to exit.
+    next_replay: Instant,
+}
+
+
+impl Worker {
+
+    fn run(mut self, receiver: mpsc::Receiver<Queued>) {
+        loop {
+            let received = match self.spool.as_ref().is_some_and(|spool| !spool.is_empty()) {
+                true => receiver.recv_timeout(self.next_replay.saturating_duration_since(Instant::now())),
+                false => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(queued) => self.deliver(queued),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if Instant::now() >= self.next_replay {
+                self.replay();
+            }
+        }
+        // Shutting down, a last attempt at what the spool holds.
+        self.replay();
+    }
+
+    fn deliver(&mut self, queued: Queued) {
+        // Nothing overtakes the spool.
+        if self.spool.as_ref().is_some_and(|spool| !spool.is_empty()) {
+            return self.spool(queued);
+        }
+        let mut result = self.send(&queued, false);
+        for delay in RETRY_DELAYS {
+            // Shutting down cuts the pause short, the email is then spooled right away.
+            let retry_at = Instant::now() + delay;
+            while result.is_err() && Instant::now() < retry_at && !self.closing.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(100));
+            }
+            if result.is_ok() || self.closing.load(Ordering::SeqCst) {
+                break;
+            }
+            result = self.send(&queued, false);
+        }
+        // The error holds the server response, failing is never fatal.
+        match result {
+            Ok(()) => {
+                self.counters.sent.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) if self.spool.is_some() => {
+                log(self.format, &format!("Warning, email to {} failed: {err}, spooled for later", queued.email.to()));
+                self.next_replay = Instant::now() + REPLAY_INTERVAL;
+                self.spool(queued);
+            }
+            Err(err) => {
+                log(self.format, &format!("Warning, email to {} failed: {err}", queued.email.to()));
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn spool(&mut self, queued: Queued) {
+        let Some(spool) = &mut self.spool else { return };
+        match spool.push(&queued.to_record()) {
+            Ok(0) => {}
+            Ok(evicted) => {
+                log(self.format, &format!("Warning, email spool is full, dropped the oldest {evicted} email(s)"));
+                self.counters.failed.fetch_add(evicted as u64, Ordering::Relaxed);
+            }
+            Err(err) => {
+                log(self.format, &format!("Warning, email to {} dropped, {err}", queued.email.to()));
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.counters.spooled.store(spool.len() as u64, Ordering::Relaxed);
+    }
+
+    // Sends the spooled emails oldest first, until the server fails again.
+    fn replay(&mut self) {
+        let mut delivered = 0;
+        while let Some(record) = self.spool.as_ref().and_then(|spool| spool.front().map(<[String]>::to_vec)) {
+            let result = match Queued::from_record(&record) {
+                Some(queued) => self.send(&queued, true),
+                None => {
+                    log(self.format, "Warning, dropped an unreadable email from the spool");
+                    self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                log(self.format, &format!("Warning, email server still unreachable: {err}, {} email(s) spooled", self.counters.spooled.load(Ordering::Relaxed)));
+                self.next_replay = Instant::now() + REPLAY_INTERVAL;
+                break;
+            }
+            let spool = self.spool.as_mut().expect("A record came from the spool");
+            if let Err(err) = spool.pop() {
+                log(self.format, &format!("Warning, {err}"));
+            }
+            self.counters.spooled.store(spool.len() as u64, Ordering::Relaxed);
+            self.counters.sent.fetch_add(1, Ordering::Relaxed);
+            delivered += 1;
+        }
+        if delivered > 0 {
+            log(self.format, &format!("Delivered {delivered} spooled email(s)"));
+        }
+    }
+
+    fn send(&self, queued: &Queued, delayed: bool) -> Result<(), String> {
+        let message = message(&queued.email, &self.from, &self.camera_name, self.format, delayed.then_some(queued.time))?;
+        self.transport.send(&message).map(|_| ()).map_err(|err| err.to_string())
+    }
+}
+
+
+fn parse_address(address: &str) -> Result<Mailbox, String> {
+    address.parse().map_err(|err| format!("Invalid email address '{address}': {err}"))
+}
+
+
+// "host" or "host:port", with the default port of the TLS mode.
+fn transport(server: &str, settings: &Settings) -> Result<SmtpTransport, String> {
+    let (host, port) = match server.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port.parse::<u16>().map_err(|_| format!("Invalid port in --smtp-server {server}"))?)),
+        None => (server, None),
+    };
+    let mut builder = match settings.smtp_tls {
+        SmtpTls::StartTls => SmtpTransport::starttls_relay(host),
+        SmtpTls::Tls => SmtpTransport::relay(host),
+        SmtpTls::None => Ok(SmtpTransport::builder_dangerous(host)),
+    }.map_err(|err| format!("Invalid --smtp-server {server}: {err}"))?;
+    if let Some(port) = port {
+        builder = builder.port(port);
+    }
+    if let (Some(user), Some(path)) = (&settings.smtp_user, &settings.smtp_password_file) {
+        let password = fs::read_to_string(path).map_err(|err| format!("Can't read --smtp-password-file {}: {err}", path.display()))?;
+        builder = builder.credentials(Credentials::new(user.clone(), password.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    Ok(builder.timeout(Some(SERVER_TIMEOUT)).build())
+}
+
+
+// Delayed emails, sent from the spool, say so in their subject and when they were due.
+fn message(email: &Email, from: &Mailbox, camera_name: &str, format: Format, delayed: Option<SystemTime>) -> Result<Message, String> {
+    let mut builder = Message::builder().from(from.clone());
+    if let Email::Start { to, identity: Some(identity), .. } | Email::Stop { to, identity: Some(identity), .. } = email {
+        builder = builder.message_id(Some(identity.message_id(&to.to_string())));
+    }
+    let prefix = if delayed.is_some() { "[delayed] " } else { "" };
+    let note = delayed
+        .map(|time| format!("\n\nDelayed, this email was due at {} but the server couldn't be reached.", overlay::utc_timestamp(time)))
+        .unwrap_or_default();
+    let message = match email {
+        Email::Start { to, id, snapshots, .. } => {
+            let mut parts = MultiPart::mixed().singlepart(SinglePart::plain(format!("Movement {id} started on {camera_name}.{note}")));
+            // A snapshot that can't be read doesn't hold up the email, nor for long one not written
+            // yet, which the media queue may have dropped too.
+            let wait_until = Instant::now() + if delayed.is_none() { SNAPSHOT_WAIT } else { Duration::ZERO };
+            for path in snapshots {
+                while !path.exists() && Instant::now() < wait_until {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                match fs::read(path) {
+                    Ok(image) => parts = parts.singlepart(attachment(path, image)),
+                    Err(err) => log(format, &format!("Warning, can't attach snapshot {}: {err}", path.display())),
+                }
+            }
+            builder.to(to.clone()).subject(format!("{prefix}Motion detected: {camera_name}")).multipart(parts)
+        }
+        Email::Stop { to, id, duration, .. } => builder
+            .to(to.clone())
+            .subject(format!("{prefix}Motion stopped: {camera_name}"))
+            .body(format!("Movement {id} on {camera_name} stopped after {duration:.0?}.{note}")),
+        Email::Digest { to, summary, lines } => builder
+            .to(to.clone())
+            .subject(format!("{prefix}Quiet hours digest: {camera_name}"))
+            .body(format!("{summary} on {camera_name}.\n\n{}{note}\n", lines.join("\n"))),
+    };
+    message.map_err(|err| err.to_string())
+}
+
+
+// Snapshots are PGM or PPM images.
+fn attachment(path: &Path, image: Vec<u8>) -> SinglePart {
+    let content_type = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("pgm") => "image/x-portable-graymap",
+        Some("ppm") => "image/x-portable-pixmap",
+        _ => "application/octet-stream",
+    };
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    Attachment::new(name).body(image, ContentType::parse(content_type).expect("Valid content type"))
+}
+
+
+// As Output::info, which stays on the detection thread.
+fn log(format: Format, message: &str) {
+    match format {
+        Format::Text => println!("{message}"),
+        Format::Json => eprintln!("{message}"),
+    }
+}
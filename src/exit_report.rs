@@ -0,0 +1,217 @@
+use std::{
+    collections::VecDeque,
+    fs::{ self, File },
+    io::{ self, Write },
+    panic,
+    path::{ Path, PathBuf },
+    sync::{ Mutex, MutexGuard },
+    thread,
+    time::{ Instant, SystemTime },
+};
+
+use crate::{ json, supervisor };
+
+// Warnings and errors kept for the report, the oldest go first.
+const RECENT_MESSAGES: usize = 20;
+
+// Exit code of a process ended by a panic.
+const EXIT_PANIC: i32 = 101;
+
+static REPORT: Mutex<Option<Tracker>> = Mutex::new(None);
+static BEFORE_EXIT: Mutex<Option<Box<dyn Fn() + Send>>> = Mutex::new(None);
+
+
+/// Why the process ended, as reported in the exit report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    Signal,         // SIGINT or SIGTERM.
+    EndOfInput,
+    Completed,      // The command ran its course, e.g. learn-mask, self-test or --dump-config.
+    CameraLost,
+    EventsLost,     // The event output stopped accepting events.
+    PanicLimit,     // Processing kept panicking, see --panic-exit.
+    OutOfMemory,    // Above --max-memory.
+    Error,          // Anything else fatal, see the recent messages.
+    Panic,          // An uncaught panic on the main thread.
+}
+
+
+impl ExitReason {
+
+    /// For commands that only return an exit code.
+    pub fn for_code(code: i32) -> Self {
+        match code {
+            0 => ExitReason::Completed,
+            4 => ExitReason::Signal,
+            _ => ExitReason::Error,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExitReason::Signal => "signal",
+            ExitReason::EndOfInput => "end_of_input",
+            ExitReason::Completed => "completed",
+            ExitReason::CameraLost => "camera_lost",
+            ExitReason::EventsLost => "events_lost",
+            ExitReason::PanicLimit => "panic_limit",
+            ExitReason::OutOfMemory => "out_of_memory",
+            ExitReason::Error => "error",
+            ExitReason::Panic => "panic",
+        }
+    }
+}
+
+
+/// What the report says about the run, updated as it goes.
+struct Tracker {
+    path: PathBuf,
+    started: Instant,
+    frames: u64,
+    events: u64,                // Movements started.
+    last_frame: Option<Instant>,
+    recent: VecDeque<String>,
+    config_hash: Option<u64>,   // Of the effective configuration, once the source is open.
+}
+
+
+impl Tracker {
+
+    fn to_json(&self, reason: ExitReason, code: i32, now: Instant) -> String {
+        json::Object::new()
+            .field("type", "exit_report")
+            .field("reason", reason.name())
+            .field("code", code)
+            .field("uptime", now.duration_since(self.started).as_secs_f64())
+            .field("frames", self.frames)
+            .field("events", self.events)
+            .field("last_frame_age", self.last_frame.map(|at| now.saturating_duration_since(at).as_secs_f64()))
+            .field("recent", self.recent.iter().cloned().collect::<Vec<_>>())
+            .field("config_hash", self.config_hash.map(|hash| format!("{hash:016x}")))
+            .field("time", json::unix_time(SystemTime::now()))
+            .finish()
+    }
+}
+
+
+/// Writes a JSON postmortem to `path` whenever the process ends from here on, through `exit`, or
+/// through a panic of the main thread. Panics caught by `supervisor::guard` don't count.
+pub fn install(path: PathBuf) {
+    *lock() = Some(Tracker {
+        path,
+        started: Instant::now(),
+        frames: 0,
+        events: 0,
+        last_frame: None,
+        recent: VecDeque::new(),
+        config_hash: None,
+    });
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") && !supervisor::guarded() {
+            message(&format!("Error, panicked at {}: {}",
+                info.location().map_or(String::from("unknown location"), |location| location.to_string()),
+                supervisor::panic_message(info.payload())));
+            write(ExitReason::Panic, EXIT_PANIC);
+        }
+        default_hook(info);
+    }));
+}
+
+
+/// Counts a frame captured at `at`.
+pub fn frame(at: Instant) {
+    if let Some(tracker) = lock().as_mut() {
+        tracker.frames += 1;
+        tracker.last_frame = Some(at);
+    }
+}
+
+
+/// Counts a movement start.
+pub fn event() {
+    if let Some(tracker) = lock().as_mut() {
+        tracker.events += 1;
+    }
+}
+
+
+/// Keeps a warning or error among the recent ones, other messages are ignored.
+pub fn message(message: &str) {
+    let message = message.trim_start();
+    if !message.starts_with("Warning") && !message.starts_with("Error") {
+        return;
+    }
+    if let Some(tracker) = lock().as_mut() {
+        if tracker.recent.len() == RECENT_MESSAGES {
+            tracker.recent.pop_front();
+        }
+        tracker.recent.push_back(message.to_string());
+    }
+}
+
+
+/// The effective configuration as reported, to tell which one the run had. Its time is left out.
+pub fn config(object: json::Object) {
+    if let Some(tracker) = lock().as_mut() {
+        tracker.config_hash = Some(fnv1a(object.finish().as_bytes()));
+    }
+}
+
+
+/// Runs `finish` on `exit`, before the report is written, replacing what was set before: process::exit
+/// skips destructors, e.g. those delivering the last events.
+pub fn before_exit(finish: impl Fn() + Send + 'static) {
+    *BEFORE_EXIT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(finish));
+}
+
+
+/// Writes the report, then ends the process with `code`.
+pub fn exit(reason: ExitReason, code: i32) -> ! {
+    if let Some(finish) = BEFORE_EXIT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).as_ref() {
+        finish();
+    }
+    write(reason, code);
+    std::process::exit(code);
+}
+
+
+/// Writes the report, if one was asked for. Failing to is only printed, there's nothing else left to do.
+pub fn write(reason: ExitReason, code: i32) {
+    let guard = lock();
+    let Some(tracker) = guard.as_ref() else {
+        return;
+    };
+    let report = tracker.to_json(reason, code, Instant::now());
+    if let Err(err) = save(&tracker.path, &report) {
+        eprintln!("Warning, can't write the exit report to {}: {err}", tracker.path.display());
+    }
+}
+
+
+// Through a temporary file, so a crash while writing leaves the previous report rather than half of
+// this one. Synced to disk as far as it goes, the machine may be going down as well.
+fn save(path: &Path, report: &str) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(report.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+
+// A poisoned tracker still has what happened until then.
+fn lock() -> MutexGuard<'static, Option<Tracker>> {
+    REPORT.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+
+/// 64 bit FNV-1a, stable across builds unlike the standard library's hasher.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
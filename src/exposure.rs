@@ -0,0 +1,63 @@
+use crate::source::FrameMetadata;
+
+/// Frames the pixel threshold stays raised for after an exposure jump: the frame it's seen on
+/// and the next, while the picture overshoots and the camera corrects it.
+pub const RAISED_FRAMES: u32 = 2;
+
+
+/// Illumination compensation from the camera's own metadata, for --exposure-jump: when the
+/// exposure or gain a frame reports moved by at least the jump from the previous frame's, the
+/// whole picture may brighten or darken at once, which would count as change everywhere. The
+/// pixel threshold is then raised for RAISED_FRAMES frames, so only what changes by much more
+/// still counts.
+///
+/// Values the source doesn't report never jump, nor does one appearing or going away. A lasting
+/// change of brightness is the job of --normalize, this covers the frames it takes to settle.
+#[derive(Debug, Clone, Copy)]
+pub struct ExposureCompensation {
+    jump: f64,                  // Share of the previous value.
+    raise: i32,                 // Added to the pixel threshold, from 0 to 255.
+    previous: FrameMetadata,
+    remaining: u32,             // Frames still raised, the current one included.
+}
+
+
+impl ExposureCompensation {
+
+    pub fn new(jump: f64, raise: i32) -> Self {
+        Self { jump, raise, previous: FrameMetadata::default(), remaining: 0 }
+    }
+
+    /// Follows the metadata of the frame about to be compared. Returns the previous frame's if
+    /// this one jumped from it.
+    pub fn update(&mut self, metadata: FrameMetadata) -> Option<FrameMetadata> {
+        let jumped = self.jumped(self.previous.exposure, metadata.exposure) || self.jumped(self.previous.gain, metadata.gain);
+        let previous = std::mem::replace(&mut self.previous, metadata);
+        self.remaining = match jumped {
+            true => RAISED_FRAMES,
+            false => self.remaining.saturating_sub(1),
+        };
+        jumped.then_some(previous)
+    }
+
+    // A gain of 0 is common, a change from it counts against 1.
+    fn jumped(&self, previous: Option<f64>, current: Option<f64>) -> bool {
+        match (previous, current) {
+            (Some(previous), Some(current)) => (current - previous).abs() >= self.jump * previous.abs().max(1.0),
+            _ => false,
+        }
+    }
+
+    /// Whether the frame last updated with is compared with the threshold raised.
+    pub fn is_raised(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// What the pixel threshold is raised by for the frame last updated with, 0 once settled.
+    pub fn raise(&self) -> i32 {
+        match self.is_raised() {
+            true => self.raise,
+            false => 0,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{ fs, path::Path };
+
+use crate::{
+    context::SavedContext,
+    settings::Settings,
+    thumbnail::Thumbnail,
+};
+
+const EXIT_ARGUMENT: i32 = 22;      // Invalid argument
+const EXIT_FAILED: i32 = 5;         // I/O error
+
+
+/// Writes the thumbnails of every event context saved by --capture-context-on-event in the review
+/// directory to the output directory, to look at false positives without a camera: for the
+/// directory "<unix seconds>-<id>" of each, "<unix seconds>-<id>-previous.ppm" and
+/// "<unix seconds>-<id>-current.ppm", the pixels of the diff mask tinted red on the latter. Both
+/// are scaled up --downsample times with nearest neighbor, so each thumbnail pixel stays a block of
+/// the size it was averaged from, whatever the thumbnails' size was when they were saved. Returns
+/// the process exit code: 5 if any context couldn't be read or written.
+pub fn run(settings: &Settings) -> i32 {
+    let review_dir = settings.review_dir.as_deref().expect("Extract settings have a directory");
+    let output = settings.mask_output.as_deref().expect("Extract settings have an output");
+    let directories = match SavedContext::list(review_dir) {
+        Ok(directories) if directories.is_empty() => {
+            println!("\nError, no event context in {}", review_dir.display());
+            return EXIT_ARGUMENT;
+        }
+        Ok(directories) => directories,
+        Err(err) => {
+            println!("\nError, {err}");
+            return EXIT_ARGUMENT;
+        }
+    };
+    if let Err(err) = fs::create_dir_all(output) {
+        println!("\nError, can't create {}: {err}", output.display());
+        return EXIT_FAILED;
+    }
+
+    let mut failed = 0;
+    for directory in &directories {
+        let name = directory.file_name().unwrap_or_default().to_string_lossy();
+        match SavedContext::load(directory).and_then(|context| write(&context, output, &name, settings.downsample)) {
+            Ok((width, height)) => println!("{name}: {width}x{height} images written"),
+            Err(err) => {
+                println!("{name}: failed, {err}");
+                failed += 1;
+            }
+        }
+    }
+    println!("{} of {} events extracted to {}", directories.len() - failed, directories.len(), output.display());
+    if failed > 0 {
+        println!("\nError, {failed} contexts couldn't be extracted");
+        return EXIT_FAILED;
+    }
+    0
+}
+
+
+// Writes both images of the context, returns their size.
+fn write(context: &SavedContext, output: &Path, name: &str, scale: usize) -> Result<(usize, usize), String> {
+    let previous = render(&context.previous, None, scale);
+    let current = render(&context.current, Some(&context.mask), scale);
+    for (image, suffix) in [(&previous, "previous"), (&current, "current")] {
+        let path = output.join(format!("{name}-{suffix}.ppm"));
+        fs::write(&path, image.to_pnm()).map_err(|err| format!("can't write {}: {err}", path.display()))?;
+    }
+    Ok((current.width, current.height))
+}
+
+
+/// The thumbnail in RGB, `scale` times its size, with the pixels set in `mask` tinted red.
+pub fn render(thumb: &Thumbnail, mask: Option<&Thumbnail>, scale: usize) -> Thumbnail {
+    let scale = scale.max(1);
+    let mut image = Thumbnail::with_channels(thumb.width * scale, thumb.height * scale, 3);
+    for (index, pixel) in image.pixels.chunks_exact_mut(3).enumerate() {
+        let source = (index / image.width / scale) * thumb.width + index % image.width / scale;
+        let value = &thumb.pixels[source * thumb.channels ..][.. thumb.channels];
+        pixel.copy_from_slice(&match value {
+            [luma] => [*luma; 3],
+            _ => [value[0], value[1], value[2]],
+        });
+        if mask.is_some_and(|mask| mask.pixels[source] != 0) {
+            pixel[0] = ((pixel[0] as u16 + 255) / 2) as u8;
+            pixel[1] /= 2;
+            pixel[2] /= 2;
+        }
+    }
+    image
+}
@@ -0,0 +1,72 @@
+use std::time::{ Duration, Instant };
+use gpio_cdev::{ Chip, LineHandle, LineRequestFlags };
+
+
+/// A GPIO output line that is asserted while a movement is active and released `hold` after it
+/// stops, e.g. to drive a relay board. The line is deasserted when this is dropped, including
+/// while unwinding from a panic.
+pub struct GpioOutput {
+    handle: LineHandle,
+    hold: Duration,
+    asserted: bool,
+    release_at: Option<Instant>,
+}
+
+
+impl GpioOutput {
+
+    /// Requests a line of a GPIO character device, e.g. "/dev/gpiochip0" and pin 17.
+    /// Active low lines are driven low while asserted.
+    pub fn open(chip_path: &str, pin: u32, active_high: bool, hold: Duration) -> Result<Self, String> {
+        let mut flags = LineRequestFlags::OUTPUT;
+        if !active_high {
+            flags |= LineRequestFlags::ACTIVE_LOW;
+        }
+        let handle = Chip::new(chip_path)
+            .and_then(|mut chip| chip.get_line(pin))
+            .and_then(|line| line.request(flags, 0, "motion-detect"))
+            .map_err(|err| format!("can't request pin {pin} of {chip_path}: {err}"))?;
+        Ok(Self { handle, hold, asserted: false, release_at: None })
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.asserted
+    }
+
+    pub fn motion_started(&mut self) -> Result<(), String> {
+        self.release_at = None;
+        self.set(true)
+    }
+
+    pub fn motion_stopped(&mut self, now: Instant) {
+        self.release_at = Some(now + self.hold);
+    }
+
+    /// Releases the line once the hold time after a stop has passed. Call once per frame.
+    pub fn update(&mut self, now: Instant) -> Result<(), String> {
+        match self.release_at {
+            Some(time) if now >= time => {
+                self.release_at = None;
+                self.set(false)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Drives the line right away, e.g. for the LED of latency-test.
+    pub fn set(&mut self, asserted: bool) -> Result<(), String> {
+        if asserted == self.asserted {
+            return Ok(());
+        }
+        self.handle.set_value(asserted as u8).map_err(|err| format!("can't set GPIO line: {err}"))?;
+        self.asserted = asserted;
+        Ok(())
+    }
+}
+
+
+impl Drop for GpioOutput {
+    fn drop(&mut self) {
+        let _ = self.handle.set_value(0);
+    }
+}
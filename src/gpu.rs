@@ -0,0 +1,237 @@
+use std::{ borrow::Cow, sync::mpsc };
+
+use crate::thumbnail::Thumbnail;
+
+// Same block average as Thumbnail::downsample, integer division included, so both paths give
+// identical thumbnails. Frame bytes are packed four per u32, thumbnail bytes one per u32.
+const SHADER: &str = "
+struct Params {
+    frame_width: u32,
+    thumb_width: u32,
+    thumb_height: u32,
+    channels: u32,
+    factor: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> frame: array<u32>;
+@group(0) @binding(2) var<storage, read_write> thumb: array<u32>;
+
+fn frame_byte(index: u32) -> u32 {
+    return (frame[index / 4u] >> ((index % 4u) * 8u)) & 0xffu;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.thumb_width || id.y >= params.thumb_height) {
+        return;
+    }
+    let source_x = id.x * params.factor;
+    let source_y = id.y * params.factor;
+    let sample_count = params.factor * params.factor;
+    for (var channel = 0u; channel < params.channels; channel++) {
+        var sum = 0u;
+        for (var y = 0u; y < params.factor; y++) {
+            for (var x = 0u; x < params.factor; x++) {
+                let pixel = (source_y + y) * params.frame_width + source_x + x;
+                sum += frame_byte(pixel * params.channels + channel);
+            }
+        }
+        thumb[(id.y * params.thumb_width + id.x) * params.channels + channel] = min(sum / sample_count, 255u);
+    }
+}
+";
+
+// Bytes of the uniform buffer: five u32 parameters, padded to 16 bytes.
+const PARAMS_SIZE: u64 = 32;
+
+
+/// Buffers sized for one frame and thumbnail shape, recreated when either changes.
+struct Buffers {
+    frame_len: usize,
+    thumb_len: usize,
+    frame: wgpu::Buffer,
+    thumb: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+
+/// Downsamples frames with a compute shader, for high resolution inputs where the block average
+/// dominates the processing time. Frames must be RGB or luma, main downsamples other layouts on the CPU.
+pub struct GpuDownsampler {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    params: wgpu::Buffer,
+    buffers: Option<Buffers>,
+    adapter_name: String,
+}
+
+
+impl GpuDownsampler {
+
+    /// Picks the most capable adapter. Fails if there is none, or it can't run compute shaders.
+    pub fn new() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        })).ok_or("no GPU adapter found")?;
+        let info = adapter.get_info();
+        let adapter_name = format!("{} ({:?})", info.name, info.backend);
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("motion-detect"),
+            required_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            memory_hints: wgpu::MemoryHints::Performance,
+        }, None)).map_err(|err| format!("can't open {adapter_name}: {err}"))?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("downsample"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("downsample"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params"),
+            size: PARAMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Ok(Self { device, queue, pipeline, params, buffers: None, adapter_name })
+    }
+
+    /// Name and backend of the adapter in use.
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    /// Resizes a full frame into the thumbnail like `Thumbnail::downsample`, waiting for the result.
+    pub fn downsample(&mut self, thumb: &mut Thumbnail, frame: &[u8], frame_width: usize, factor: usize) -> Result<(), String> {
+        let frame_len = frame_width * thumb.height * factor * thumb.channels;
+        let frame = frame.get(.. frame_len).ok_or("frame smaller than the thumbnail needs")?;
+        let thumb_len = thumb.pixels.len();
+        if thumb_len == 0 {
+            return Ok(());
+        }
+        if self.buffers.as_ref().map(|buffers| (buffers.frame_len, buffers.thumb_len)) != Some((frame_len, thumb_len)) {
+            self.buffers = Some(self.create_buffers(frame_len, thumb_len));
+        }
+        let buffers = self.buffers.as_ref().expect("Buffers were just created");
+
+        let params: Vec<u8> = [frame_width, thumb.width, thumb.height, thumb.channels, factor, 0, 0, 0]
+            .iter()
+            .flat_map(|value| (*value as u32).to_le_bytes())
+            .collect();
+        self.queue.write_buffer(&self.params, 0, &params);
+        // Buffer writes must be a multiple of 4 bytes.
+        let aligned_len = frame_len - frame_len % 4;
+        self.queue.write_buffer(&buffers.frame, 0, &frame[.. aligned_len]);
+        if aligned_len < frame_len {
+            let mut tail = [0u8; 4];
+            tail[.. frame_len - aligned_len].copy_from_slice(&frame[aligned_len ..]);
+            self.queue.write_buffer(&buffers.frame, aligned_len as u64, &tail);
+        }
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.dispatch_workgroups(thumb.width.div_ceil(8) as u32, thumb.height.div_ceil(8) as u32, 1);
+        }
+        encoder.copy_buffer_to_buffer(&buffers.thumb, 0, &buffers.readback, 0, (thumb_len * 4) as u64);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffers.readback.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()
+            .map_err(|_| "GPU readback was dropped".to_string())?
+            .map_err(|err| format!("GPU readback failed: {err}"))?;
+        {
+            let data = slice.get_mapped_range();
+            for (pixel, value) in thumb.pixels.iter_mut().zip(data.chunks_exact(4)) {
+                *pixel = value[0];
+            }
+        }
+        buffers.readback.unmap();
+        Ok(())
+    }
+
+    fn create_buffers(&self, frame_len: usize, thumb_len: usize) -> Buffers {
+        let buffer = |label, size: usize, usage| self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size as u64,
+            usage,
+            mapped_at_creation: false,
+        });
+        let frame = buffer("frame", frame_len.div_ceil(4) * 4, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let thumb = buffer("thumbnail", thumb_len * 4, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let readback = buffer("readback", thumb_len * 4, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: self.params.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: frame.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: thumb.as_entire_binding() },
+            ],
+        });
+        Buffers { frame_len, thumb_len, frame, thumb, readback, bind_group }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Noise rather than flat blocks, so every sum and division is exercised.
+    fn frame(width: usize, height: usize, channels: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0 .. width * height * channels).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect()
+    }
+
+
+    #[test]
+    fn the_gpu_downsamples_as_the_cpu_does() {
+        let mut gpu = match GpuDownsampler::new() {
+            Ok(gpu) => gpu,
+            Err(err) => {
+                println!("Skipped, {err}");
+                return;
+            }
+        };
+        for (channels, factor) in [(3, 4), (3, 3), (1, 8), (1, 5)] {
+            let (width, height) = (160, 120);
+            let frame = frame(width, height, channels);
+            let mut cpu_thumb = Thumbnail::with_channels(width / factor, height / factor, channels);
+            let mut gpu_thumb = cpu_thumb.clone();
+            if channels == 3 {
+                cpu_thumb.downsample_rgb(&frame, width, factor);
+            } else {
+                cpu_thumb.downsample_luma(&frame, width, factor);
+            }
+            gpu.downsample(&mut gpu_thumb, &frame, width, factor).unwrap();
+            let diff = cpu_thumb.pixels.iter().zip(&gpu_thumb.pixels).map(|(cpu, gpu)| cpu.abs_diff(*gpu)).max();
+            assert!(diff <= Some(1), "{} with {channels} channels at {factor}: {diff:?}", gpu.adapter_name());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use crate::mask::MaskImage;
+
+/// How often each thumbnail pixel changed, accumulated over the compared frames. Backs learn-mask
+/// and any view of where motion happens most.
+#[derive(Debug, Clone, Default)]
+pub struct Heatmap {
+    pub width: usize,
+    pub height: usize,
+    pub frames: u64,        // Compared frames recorded.
+    counts: Vec<u64>,       // Frames each pixel changed in, row by row.
+}
+
+
+impl Heatmap {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the changed pixels of a compared frame. A new thumbnail size starts over.
+    pub fn record(&mut self, mask: &[u8], width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) || self.counts.len() != mask.len() {
+            *self = Self { width, height, frames: 0, counts: vec![0; mask.len()] };
+        }
+        self.frames += 1;
+        for (count, changed) in self.counts.iter_mut().zip(mask) {
+            *count += (*changed != 0) as u64;
+        }
+    }
+
+    /// Fraction of the recorded frames the pixel changed in.
+    pub fn frequency(&self, index: usize) -> f32 {
+        self.counts[index] as f32 / self.frames.max(1) as f32
+    }
+
+    /// Pixels that changed in more than `fraction` of the recorded frames.
+    pub fn above(&self, fraction: f32) -> usize {
+        (0 .. self.counts.len()).filter(|index| self.frequency(*index) > fraction).count()
+    }
+
+    /// A mask ignoring the pixels that changed in more than `fraction` of the recorded frames.
+    pub fn mask(&self, fraction: f32) -> MaskImage {
+        MaskImage {
+            width: self.width,
+            height: self.height,
+            weights: (0 .. self.counts.len()).map(|index| if self.frequency(index) <= fraction { 255 } else { 0 }).collect(),
+            weighted: false,
+        }
+    }
+
+    pub fn buffer_bytes(&self) -> usize {
+        self.counts.capacity() * size_of::<u64>()
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    process::{ Child, Command, Stdio },
+    time::{ Duration, Instant },
+};
+
+use crate::{
+    bus::{ Event, Sink, SinkError },
+    identity::EventIdentity,
+    motion::MotionEvent,
+};
+
+/// Shell commands run on motion events, e.g. to switch an IR illuminator on or turn a PTZ head.
+///
+/// Commands are started with `sh -c` in the order of the events, and never waited for: a slow
+/// command doesn't hold up detection. For one movement, the provisional command is always started
+/// before the start or cancel one, and the start command before the stop one, but they may run at
+/// the same time. Each gets MOTION_EVENT (provisional, provisional_cancel, start or stop) and
+/// MOTION_ID in its environment, with MOTION_INSTALLATION and MOTION_SEQUENCE to dedupe on, stdin
+/// and stdout closed, and stderr shared with motion-detect. As a sink of the bus, they run for the
+/// motion events that notify and for the held movements, which get the identities of their events
+/// when they were held, none if they were restored from the state file.
+pub struct Hooks {
+    on_start: Option<String>,
+    on_stop: Option<String>,
+    on_provisional: Option<String>,
+    on_provisional_cancel: Option<String>,
+    provisional_cooldown: Duration,
+    last_provisional: Option<Instant>,
+    provisional_ran: Option<u64>,   // Id of the movement whose provisional command ran, for its cancel.
+    // The identities of the start and stop events of the movements held during quiet hours.
+    held: HashMap<u64, (Option<EventIdentity>, Option<EventIdentity>)>,
+    children: Vec<(String, Child)>,
+}
+
+
+impl Hooks {
+
+    pub fn new(
+        on_start: Option<String>,
+        on_stop: Option<String>,
+        on_provisional: Option<String>,
+        on_provisional_cancel: Option<String>,
+        provisional_cooldown: Duration,
+    ) -> Self {
+        Self {
+            on_start,
+            on_stop,
+            on_provisional,
+            on_provisional_cancel,
+            provisional_cooldown,
+            last_provisional: None,
+            provisional_ran: None,
+            held: HashMap::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.on_start.is_none() && self.on_stop.is_none() && self.on_provisional.is_none() && self.on_provisional_cancel.is_none()
+    }
+
+    /// Starts the command for an event, if there is one. The provisional command runs at most once
+    /// per cooldown, and the cancel command only follows a provisional command that ran.
+    pub fn event(&mut self, event: MotionEvent, identity: &EventIdentity, now: Instant) -> Result<(), String> {
+        let (name, id, command) = match event {
+            MotionEvent::Provisional { id } => {
+                if self.last_provisional.is_some_and(|last| now.saturating_duration_since(last) < self.provisional_cooldown) {
+                    return Ok(());
+                }
+                if self.on_provisional.is_some() {
+                    self.last_provisional = Some(now);
+                    self.provisional_ran = Some(id);
+                }
+                ("provisional", id, &self.on_provisional)
+            }
+            MotionEvent::ProvisionalCancel { id } => {
+                if self.provisional_ran.take() != Some(id) {
+                    return Ok(());
+                }
+                ("provisional_cancel", id, &self.on_provisional_cancel)
+            }
+            MotionEvent::Start { id, .. } => {
+                self.provisional_ran = None;
+                ("start", id, &self.on_start)
+            }
+            MotionEvent::Stop { id, .. } => ("stop", id, &self.on_stop),
+        };
+        let Some(command) = command.clone() else {
+            return Ok(());
+        };
+        self.run(name, id, Some(identity), &command, false)
+    }
+
+    /// Keeps the identity of an event held during quiet hours, for the command run once they're over.
+    pub fn hold(&mut self, event: MotionEvent, identity: &EventIdentity) {
+        match event {
+            MotionEvent::Start { id, .. } => self.held.entry(id).or_default().0 = Some(identity.clone()),
+            MotionEvent::Stop { id, .. } => self.held.entry(id).or_default().1 = Some(identity.clone()),
+            MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+        }
+    }
+
+    /// Starts the commands of a movement held during quiet hours, once they're over: the start
+    /// command, then the stop one if it stopped. Both get MOTION_HELD=1 in their environment.
+    pub fn held(&mut self, id: u64, stopped: bool) -> Result<(), String> {
+        let (start, stop) = self.held.remove(&id).unwrap_or_default();
+        if let Some(command) = self.on_start.clone() {
+            self.run("start", id, start.as_ref(), &command, true)?;
+        }
+        match self.on_stop.clone() {
+            Some(command) if stopped => self.run("stop", id, stop.as_ref(), &command, true),
+            _ => Ok(()),
+        }
+    }
+
+    fn run(&mut self, name: &str, id: u64, identity: Option<&EventIdentity>, command: &str, held: bool) -> Result<(), String> {
+        let mut process = Command::new("sh");
+        process
+            .args(["-c", command])
+            .env("MOTION_EVENT", name)
+            .env("MOTION_ID", id.to_string());
+        if let Some(identity) = identity {
+            process
+                .env("MOTION_INSTALLATION", &identity.installation)
+                .env("MOTION_SEQUENCE", identity.sequence.to_string());
+        }
+        if held {
+            process.env("MOTION_HELD", "1");
+        }
+        let child = process
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("can't run the {name} command: {err}"))?;
+        self.children.push((name.to_string(), child));
+        Ok(())
+    }
+
+    /// Collects the commands that exited, returns a message for each that failed.
+    pub fn reap(&mut self) -> Vec<String> {
+        let mut failures = Vec::new();
+        self.children.retain_mut(|(name, child)| match child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                if !status.success() {
+                    failures.push(format!("the {name} command failed: {status}"));
+                }
+                false
+            }
+            Err(err) => {
+                failures.push(format!("can't wait for the {name} command: {err}"));
+                false
+            }
+        });
+        failures
+    }
+}
+
+
+impl Sink for Hooks {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::Motion { event, identity, at, notify: true, .. } => self.event(*event, identity, *at),
+            Event::Motion { event, identity, notify: false, .. } => {
+                self.hold(*event, identity);
+                Ok(())
+            }
+            Event::Held { id, stopped } => self.held(*id, *stopped),
+            _ => Ok(()),
+        }
+        .map_err(SinkError::Failed)
+    }
+
+    fn poll(&mut self) -> Result<(), SinkError> {
+        let failures = self.reap();
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(SinkError::Failed(failures.join(", "))),
+        }
+    }
+}
@@ -0,0 +1,797 @@
+use std::{
+    collections::VecDeque,
+    io::{ self, BufRead, BufReader, Read, Write },
+    net::{ SocketAddr, TcpListener, TcpStream },
+    sync::{ atomic::{ AtomicBool, Ordering }, Arc, Condvar, Mutex, MutexGuard },
+    thread,
+    time::{ Duration, SystemTime },
+};
+
+use crate::{
+    bus::{ Event, Sink, SinkError },
+    capabilities,
+    control::{ ControlCommand, ControlSender },
+    json,
+    memory::MemoryUsage,
+    sequence::FrameCounter,
+    source::SourceInfo,
+    startup::Progress,
+    timing::PipelineTiming,
+};
+
+// How many messages may wait for a slow WebSocket client before score updates are dropped...
+const CLIENT_QUEUE_LIMIT: usize = 32;
+// ...and how many events, which are never dropped, before the client is disconnected.
+const CLIENT_QUEUE_CAP: usize = 256;
+// How long a client may take to send its request, or to take what's written to it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// The longest request or header line, longer ones are refused.
+const LINE_LIMIT: usize = 8 * 1024;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// Control request bodies are a handful of settings at most.
+const CONTROL_BODY_LIMIT: usize = 4 * 1024;
+// How long a control request waits for the main loop, which may be warming up after a resume.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Detector state reported by GET /status and sent to WebSocket clients when they connect.
+#[derive(Default)]
+pub struct Status {
+    pub active: bool,       // A movement is in progress.
+    pub changed: f32,       // Percentage of changed pixels in the latest frame.
+    pub events: u64,        // Movements started since launch.
+    pub capture_interval: Duration,
+    pub downsample: usize,
+    pub cpu_load: Option<f32>, // Percentage of the capture interval spent processing, with --cpu-budget.
+    pub gpio: Option<bool>,    // Whether the GPIO output is asserted, with --gpio-pin.
+    pub processing_panics: u64, // Panics caught in per-frame processing since launch.
+    pub timing: PipelineTiming, // Time per pipeline stage.
+    pub idle_for: Option<Duration>, // How long nothing moved, with --idle-after.
+    pub frames: FrameCounter,   // Latest capture sequence number and dropped frames.
+    pub emails_sent: Option<u64>,   // Since launch, with --smtp-server.
+    pub email_failures: Option<u64>,
+    pub emails_spooled: Option<u64>,// Waiting for the server, with --email-spool.
+    pub zmq_dropped: Option<u64>,   // Messages dropped at the high water mark, with --zmq-pub.
+    pub media_written: Option<u64>, // Snapshot and timelapse jobs since launch, with either...
+    pub media_dropped: Option<u64>, // ...those dropped by --media-overflow...
+    pub media_queued: Option<usize>,// ...and those waiting now.
+    pub verification: Option<json::Object>, // Verdicts of the verify command, with --verify-command.
+    pub sinks: Vec<json::Object>,   // Delivery counters of each output on the event bus.
+    pub memory: MemoryUsage,    // Bytes held by the pipeline buffers.
+    pub source: Option<SourceInfo>, // Once the source is open.
+    pub startup: Option<Progress>,  // The latest step of the startup, until the detector is ready.
+    pub paused: bool,       // See POST /control/pause and SIGUSR1.
+    pub armed: bool,        // Movements are reported, see POST /control/disarm.
+    pub zones: Vec<json::Object>,   // Each zone's arming, see zones::ZoneArming.
+}
+
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        json::Object::new()
+            .field("type", "status")
+            .field("active", self.active)
+            .field("changed", self.changed)
+            .field("events", self.events)
+            .field("capture_interval", self.capture_interval.as_secs_f64())
+            .field("downsample", self.downsample)
+            .field("cpu_load", self.cpu_load)
+            .field("gpio", self.gpio)
+            .field("processing_panics_total", self.processing_panics)
+            .field("timing", self.timing.to_object())
+            .field("idle_for", self.idle_for.map(|idle_for| idle_for.as_secs_f64()))
+            .field("frames", self.frames.to_object())
+            .field("emails_sent_total", self.emails_sent)
+            .field("email_failures_total", self.email_failures)
+            .field("emails_spooled", self.emails_spooled)
+            .field("zmq_dropped_total", self.zmq_dropped)
+            .field("media_written_total", self.media_written)
+            .field("media_dropped_total", self.media_dropped)
+            .field("media_queued", self.media_queued)
+            .field("verification", self.verification.clone())
+            .field("sinks", self.sinks.clone())
+            .field("memory", self.memory.to_object())
+            .field("source", self.source.as_ref().map(SourceInfo::to_object))
+            .field("startup", self.startup.as_ref().map(Progress::to_object))
+            .field("paused", self.paused)
+            .field("armed", self.armed)
+            .field("zones", self.zones.clone())
+            .field("time", json::unix_time(SystemTime::now()))
+            .finish()
+    }
+}
+
+
+/// Embedded HTTP server. Serves GET /status, GET /config, GET /capabilities, GET /activity, a
+/// WebSocket at GET /ws that pushes every JSON message to all connected clients, and a small test page at GET / that renders that stream. Once
+/// enabled, a second WebSocket at GET /mask sends change masks as binary messages, see `mask::PackedMask`,
+/// and POST /control/<command> takes `control::ControlCommand`s from clients with the bearer token.
+#[derive(Clone)]
+pub struct HttpServer {
+    shared: Arc<Shared>,
+}
+
+
+struct Shared {
+    address: SocketAddr,
+    status: Mutex<Status>,
+    config: Mutex<Option<String>>,
+    activity: Mutex<Option<String>>,
+    clients: Mutex<Vec<Arc<Client>>>,
+    masks: AtomicBool,  // Whether GET /mask is served.
+    control: Mutex<Option<Control>>,    // POST /control/... is only served with a token.
+}
+
+
+struct Control {
+    token: String,
+    sender: ControlSender,
+}
+
+
+/// A connected WebSocket client with its own bounded send queue.
+struct Client {
+    queue: Mutex<ClientQueue>,
+    wake: Condvar,
+    masks: bool,        // Connected to GET /mask, only gets masks.
+}
+
+
+#[derive(Default)]
+struct ClientQueue {
+    messages: VecDeque<Message>,
+    closed: bool,
+}
+
+
+struct Message {
+    data: Vec<u8>,
+    binary: bool,       // Masks, everything else is JSON text.
+    droppable: bool,    // Score updates and heartbeats can be dropped under backpressure, events never.
+}
+
+
+impl HttpServer {
+
+    /// Binds the listening socket and serves connections on background threads.
+    pub fn start(address: &str) -> io::Result<Self> {
+        Self::with_listener(TcpListener::bind(address)?)
+    }
+
+    /// Serves on a listener that is already bound, e.g. one passed by socket activation.
+    pub fn with_listener(listener: TcpListener) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            address: listener.local_addr()?,
+            status: Mutex::new(Status::default()),
+            config: Mutex::new(None),
+            activity: Mutex::new(None),
+            clients: Mutex::new(Vec::new()),
+            masks: AtomicBool::new(false),
+            control: Mutex::new(None),
+        });
+
+        let accept_shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = accept_shared.clone();
+                thread::spawn(move || {
+                    let _ = handle_connection(stream, shared);
+                });
+            }
+        });
+
+        let heartbeat_shared = shared.clone();
+        thread::spawn(move || loop {
+            thread::sleep(HEARTBEAT_INTERVAL);
+            let heartbeat = json::Object::new()
+                .field("type", "heartbeat")
+                .field("time", json::unix_time(SystemTime::now()))
+                .finish();
+            heartbeat_shared.broadcast(heartbeat, true);
+        });
+
+        Ok(Self { shared })
+    }
+
+    /// The address it listens on, with the port chosen if it was bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.shared.address
+    }
+
+    /// The status shared with the server threads, lock it briefly to update it.
+    pub fn status(&self) -> MutexGuard<'_, Status> {
+        self.shared.status.lock().unwrap()
+    }
+
+    /// Sets the effective configuration served at GET /config.
+    pub fn set_config(&self, json: String) {
+        *self.shared.config.lock().unwrap() = Some(json);
+    }
+
+    /// Sets the activity histogram served at GET /activity.
+    pub fn set_activity(&self, json: String) {
+        *self.shared.activity.lock().unwrap() = Some(json);
+    }
+
+    /// Serves GET /mask, for --publish-mask.
+    pub fn enable_masks(&self) {
+        self.shared.masks.store(true, Ordering::SeqCst);
+    }
+
+    /// Serves POST /control/..., for --http-token. Requests need `Authorization: Bearer <token>` and
+    /// are answered with the state the command resulted in.
+    pub fn enable_control(&self, token: String, sender: ControlSender) {
+        *self.shared.control.lock().unwrap() = Some(Control { token, sender });
+    }
+
+    /// True if at least one WebSocket client is connected, so messages are worth formatting.
+    pub fn has_clients(&self) -> bool {
+        self.shared.clients.lock().unwrap().iter().any(|client| !client.masks)
+    }
+
+    /// Bytes of the messages waiting for WebSocket clients.
+    pub fn queued_bytes(&self) -> usize {
+        self.shared.clients.lock().unwrap().iter()
+            .map(|client| client.queue.lock().unwrap().messages.iter().map(|message| message.data.capacity()).sum::<usize>())
+            .sum()
+    }
+
+    /// True if at least one client is connected to GET /mask, so masks are worth packing.
+    pub fn has_mask_clients(&self) -> bool {
+        self.shared.clients.lock().unwrap().iter().any(|client| client.masks)
+    }
+
+    /// Sends a motion event to every client. Events are never dropped.
+    pub fn send_event(&self, json: String) {
+        self.shared.broadcast(json, false);
+    }
+
+    /// Sends a score update to every client, dropping the oldest ones if a client can't keep up.
+    pub fn send_score(&self, json: String) {
+        self.shared.broadcast(json, true);
+    }
+
+    /// Sends a packed mask to the clients of GET /mask, dropped like scores if one can't keep up.
+    pub fn send_mask(&self, frame: Vec<u8>) {
+        let clients = self.shared.clients.lock().unwrap();
+        for client in clients.iter().filter(|client| client.masks) {
+            client.push(Message { data: frame.clone(), binary: true, droppable: true });
+        }
+    }
+}
+
+
+/// Every message of the event stream goes to the WebSocket clients, events held for quiet hours included.
+impl Sink for HttpServer {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+        if let Event::Message { json, .. } | Event::Motion { json, .. } = event {
+            self.send_event(json.clone());
+        }
+        Ok(())
+    }
+}
+
+
+impl Shared {
+    fn broadcast(&self, text: String, droppable: bool) {
+        let clients = self.clients.lock().unwrap();
+        for client in clients.iter().filter(|client| !client.masks) {
+            client.push(Message { data: text.clone().into_bytes(), binary: false, droppable });
+        }
+    }
+}
+
+
+impl Client {
+
+    // Past CLIENT_QUEUE_LIMIT the oldest droppable message makes room, past CLIENT_QUEUE_CAP the
+    // client is too far behind to be worth the memory, and is disconnected.
+    fn push(&self, message: Message) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return;
+        }
+        if queue.messages.len() >= CLIENT_QUEUE_LIMIT {
+            if let Some(oldest) = queue.messages.iter().position(|m| m.droppable) {
+                queue.messages.remove(oldest);
+            } else if message.droppable {
+                return;
+            }
+        }
+        if queue.messages.len() >= CLIENT_QUEUE_CAP {
+            queue.messages.clear();
+            queue.closed = true;
+        } else {
+            queue.messages.push_back(message);
+        }
+        self.wake.notify_one();
+    }
+
+    fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.wake.notify_one();
+    }
+}
+
+
+fn handle_connection(mut stream: TcpStream, shared: Arc<Shared>) -> io::Result<()> {
+    // A client that stops sending or reading holds a thread, but not for long.
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    // Request line and headers, we only care about the path, the WebSocket key and what control
+    // requests need.
+    let Some(request_line) = read_line(&mut reader)? else {
+        return respond(&mut stream, "414 URI Too Long", "text/plain", "Request line is too long\n");
+    };
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let mut websocket_key = None;
+    let mut authorization = None;
+    let mut content_length = None;
+    let mut header_bytes = 0;
+    loop {
+        let Some(line) = read_line(&mut reader)? else {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "text/plain", "Header line is too long\n");
+        };
+        header_bytes += line.len();
+        if line.is_empty() || line.trim().is_empty() || header_bytes > 16 * 1024 {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse::<usize>().unwrap_or(usize::MAX));
+            }
+        }
+    }
+
+    match (method, path) {
+        ("GET", "/") => respond(&mut stream, "200 OK", "text/html", TEST_PAGE),
+        ("GET", "/status") => {
+            let status = shared.status.lock().unwrap().to_json();
+            respond(&mut stream, "200 OK", "application/json", &status)
+        }
+        ("GET", "/capabilities") => respond(&mut stream, "200 OK", "application/json", &capabilities::to_json()),
+        ("GET", "/config") => match shared.config.lock().unwrap().clone() {
+            Some(config) => respond(&mut stream, "200 OK", "application/json", &config),
+            None => respond(&mut stream, "503 Service Unavailable", "text/plain", "Not configured yet\n"),
+        },
+        ("GET", "/activity") => match shared.activity.lock().unwrap().clone() {
+            Some(activity) => respond(&mut stream, "200 OK", "application/json", &activity),
+            None => respond(&mut stream, "503 Service Unavailable", "text/plain", "Not running yet\n"),
+        },
+        ("GET", "/ws") => match websocket_key {
+            Some(key) => websocket(stream, reader, &key, shared, false),
+            None => respond(&mut stream, "400 Bad Request", "text/plain", "Expected a WebSocket upgrade\n"),
+        },
+        ("GET", "/mask") if shared.masks.load(Ordering::SeqCst) => match websocket_key {
+            Some(key) => websocket(stream, reader, &key, shared, true),
+            None => respond(&mut stream, "400 Bad Request", "text/plain", "Expected a WebSocket upgrade\n"),
+        },
+        (method, path) if path.starts_with("/control/") && shared.control.lock().unwrap().is_some() => {
+            let request = ControlRequest { method, command: &path["/control/".len() ..], authorization: authorization.as_deref(), content_length };
+            control(&mut stream, &mut reader, request, &shared)
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found\n"),
+    }
+}
+
+
+// A line of the request, None if it's longer than LINE_LIMIT, empty at the end of the stream.
+fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let count = reader.by_ref().take(LINE_LIMIT as u64).read_line(&mut line)?;
+    Ok((count < LINE_LIMIT || line.ends_with('\n')).then_some(line))
+}
+
+
+/// What a control request asked for, as far as the headers tell.
+struct ControlRequest<'a> {
+    method: &'a str,
+    command: &'a str,
+    authorization: Option<&'a str>,
+    content_length: Option<usize>,
+}
+
+
+// Checks the request before its body is read, and nothing changes unless the command is valid
+// as a whole.
+fn control(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, request: ControlRequest, shared: &Shared) -> io::Result<()> {
+    let (token_matches, sender) = match shared.control.lock().unwrap().as_ref() {
+        Some(control) => {
+            let token = request.authorization.and_then(|value| value.strip_prefix("Bearer ")).unwrap_or_default();
+            (constant_time_eq(token.trim().as_bytes(), control.token.as_bytes()), control.sender.clone())
+        }
+        None => return respond(stream, "404 Not Found", "text/plain", "Not found\n"),
+    };
+    if !ControlCommand::NAMES.contains(&request.command) {
+        return respond(stream, "404 Not Found", "text/plain", "Not found\n");
+    }
+    if request.method != "POST" {
+        return respond_with(stream, "405 Method Not Allowed", "text/plain", "Allow: POST\r\n", "Use POST\n");
+    }
+    if !token_matches {
+        return respond_with(stream, "401 Unauthorized", "text/plain", "WWW-Authenticate: Bearer\r\n", "Missing or wrong bearer token\n");
+    }
+    let length = request.content_length.unwrap_or(0);
+    if length > CONTROL_BODY_LIMIT {
+        return respond(stream, "413 Content Too Large", "text/plain", "Request body is too large\n");
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let Ok(body) = String::from_utf8(body) else {
+        return respond(stream, "400 Bad Request", "text/plain", "Request body is not UTF-8\n");
+    };
+    let command = match ControlCommand::from_request(request.command, &body) {
+        Ok(command) => command,
+        Err(err) => return respond(stream, "400 Bad Request", "text/plain", &format!("{err}\n")),
+    };
+    if let Err(err) = sender.bounds().check(&command) {
+        return respond(stream, "400 Bad Request", "text/plain", &format!("{err}\n"));
+    }
+    match sender.send(command, CONTROL_TIMEOUT) {
+        Ok(state) => respond(stream, "200 OK", "application/json", &state.to_json()),
+        Err(err) => respond(stream, "503 Service Unavailable", "text/plain", &format!("Not applied, {err}\n")),
+    }
+}
+
+
+// Takes as long whatever the token is like, so it can't be guessed byte by byte from the timing.
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    let difference = expected.iter().enumerate()
+        .fold(given.len() ^ expected.len(), |difference, (index, byte)| difference | (*byte ^ given.get(index).copied().unwrap_or(0)) as usize);
+    difference == 0
+}
+
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    respond_with(stream, status, content_type, "", body)
+}
+
+
+// With extra header lines, each ending in \r\n.
+fn respond_with(stream: &mut TcpStream, status: &str, content_type: &str, headers: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n{headers}Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+
+fn websocket(mut stream: TcpStream, mut reader: BufReader<TcpStream>, key: &str, shared: Arc<Shared>, masks: bool) -> io::Result<()> {
+    // Handshake, see RFC 6455 section 4.2.2.
+    let accept = base64(&sha1(format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_bytes()));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+
+    // New clients get the current state first, so they don't need a separate status request.
+    let client = Arc::new(Client { queue: Mutex::new(ClientQueue::default()), wake: Condvar::new(), masks });
+    if !masks {
+        client.push(Message { data: shared.status.lock().unwrap().to_json().into_bytes(), binary: false, droppable: false });
+    }
+    shared.clients.lock().unwrap().push(client.clone());
+
+    // Incoming frames are only watched for the connection closing, clients have nothing to say,
+    // so they may stay silent as long as they like. One that stops reading runs into the write
+    // timeout instead.
+    stream.set_read_timeout(None)?;
+    let reader_client = client.clone();
+    thread::spawn(move || {
+        let mut header = [0u8; 2];
+        while reader.read_exact(&mut header).is_ok() {
+            let opcode = header[0] & 0x0F;
+            let mut length = (header[1] & 0x7F) as u64;
+            if length == 126 {
+                let mut extended = [0u8; 2];
+                if reader.read_exact(&mut extended).is_err() { break }
+                length = u16::from_be_bytes(extended) as u64;
+            } else if length == 127 {
+                let mut extended = [0u8; 8];
+                if reader.read_exact(&mut extended).is_err() { break }
+                length = u64::from_be_bytes(extended);
+            }
+            let masked = header[1] & 0x80 != 0;
+            let payload = length + if masked { 4 } else { 0 };
+            if opcode == 0x8 || io::copy(&mut (&mut reader).take(payload), &mut io::sink()).is_err() {
+                break;
+            }
+        }
+        reader_client.close();
+    });
+
+    // Writes queued messages until the client goes away.
+    let result = loop {
+        let message = {
+            let mut queue = client.queue.lock().unwrap();
+            while queue.messages.is_empty() && !queue.closed {
+                queue = client.wake.wait(queue).unwrap();
+            }
+            if queue.closed {
+                break Ok(());
+            }
+            queue.messages.pop_front()
+        };
+        if let Some(message) = message {
+            if let Err(err) = write_frame(&mut stream, &message.data, message.binary) {
+                break Err(err);
+            }
+        }
+    };
+
+    shared.clients.lock().unwrap().retain(|c| !Arc::ptr_eq(c, &client));
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    result
+}
+
+
+fn write_frame(stream: &mut TcpStream, payload: &[u8], binary: bool) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(if binary { 0x82 } else { 0x81 }); // FIN + binary or text opcode
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+
+/// SHA-1, only used for the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0 .. 16 {
+            w[i] = u32::from_be_bytes([chunk[i*4], chunk[i*4+1], chunk[i*4+2], chunk[i*4+3]]);
+        }
+        for i in 16 .. 80 {
+            w[i] = (w[i-3] ^ w[i-8] ^ w[i-14] ^ w[i-16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0 ..= 19 => ((b & c) | (!b & d), 0x5A827999),
+                20 ..= 39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40 ..= 59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in h.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, value) in h.iter().enumerate() {
+        digest[i*4 .. i*4+4].copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32;
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[((value >> (18 - i * 6)) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+
+// Connects to /ws and lists every message, handy for checking the server by hand. Draws the zone
+// outlines from /config over the change mask, when /mask is published.
+const TEST_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>motion-detect</title></head>
+<body style="font-family: monospace">
+<h3>motion-detect <span id="state">connecting...</span></h3>
+<canvas id="preview" width="320" height="240" style="border: 1px solid silver"></canvas>
+<ul id="events"></ul>
+<script>
+const preview = document.getElementById("preview");
+const context = preview.getContext("2d");
+let outlines = [];
+let mask = null;
+const draw = () => {
+    context.clearRect(0, 0, preview.width, preview.height);
+    if (mask) {
+        const [cellWidth, cellHeight] = [preview.width / mask.width, preview.height / mask.height];
+        context.fillStyle = "tomato";
+        for (let index = 0; index < mask.width * mask.height; index++) {
+            if (mask.bits[index >> 3] & (1 << (index & 7))) {
+                context.fillRect((index % mask.width) * cellWidth, Math.floor(index / mask.width) * cellHeight, cellWidth, cellHeight);
+            }
+        }
+    }
+    context.strokeStyle = context.fillStyle = "royalblue";
+    for (const zone of outlines) {
+        context.beginPath();
+        for (const [x, y] of zone.points) context.lineTo(x * preview.width, y * preview.height);
+        context.closePath();
+        context.stroke();
+        context.fillText(zone.name, zone.points[0][0] * preview.width + 3, zone.points[0][1] * preview.height + 12);
+    }
+};
+fetch("/config").then((response) => response.json()).then((config) => {
+    outlines = config.zone_outlines;
+    preview.height = preview.width * config.stream.height / config.stream.width;
+    draw();
+});
+const masks = new WebSocket(`ws://${location.host}/mask`);
+masks.binaryType = "arraybuffer";
+masks.onmessage = (message) => {
+    const header = new DataView(message.data);
+    mask = { width: header.getUint16(1, true), height: header.getUint16(3, true), bits: new Uint8Array(message.data, 13) };
+    draw();
+};
+const socket = new WebSocket(`ws://${location.host}/ws`);
+const state = document.getElementById("state");
+const events = document.getElementById("events");
+socket.onclose = () => state.textContent = "disconnected";
+socket.onmessage = (message) => {
+    const data = JSON.parse(message.data);
+    if (data.type === "score" || data.type === "status") {
+        state.textContent = `${data.active ? "MOTION" : "idle"} ${data.changed.toFixed(1)}%`;
+    }
+    if (data.type === "score" || data.type === "heartbeat") return;
+    const item = document.createElement("li");
+    item.textContent = `${new Date(data.time * 1000).toLocaleTimeString()} ${message.data}`;
+    events.prepend(item);
+};
+</script>
+</body>
+</html>
+"#;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client { queue: Mutex::new(ClientQueue::default()), wake: Condvar::new(), masks: false }
+    }
+
+
+    fn message(droppable: bool) -> Message {
+        Message { data: b"{}".to_vec(), binary: false, droppable }
+    }
+
+
+    // Sends `request` and reads the answer until the server closes the connection.
+    fn exchange(server: &HttpServer, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(request).unwrap();
+        let mut answer = String::new();
+        let _ = stream.read_to_string(&mut answer);
+        answer
+    }
+
+
+    #[test]
+    fn score_updates_make_room_for_events() {
+        let client = client();
+        for _ in 0 .. CLIENT_QUEUE_LIMIT {
+            client.push(message(true));
+        }
+        client.push(message(true));
+        client.push(message(false));
+        let queue = client.queue.lock().unwrap();
+        assert_eq!(queue.messages.len(), CLIENT_QUEUE_LIMIT);
+        assert!(!queue.messages.back().unwrap().droppable);
+        assert!(!queue.closed);
+    }
+
+
+    #[test]
+    fn a_client_too_far_behind_is_disconnected() {
+        let client = client();
+        for _ in 0 .. CLIENT_QUEUE_CAP {
+            client.push(message(false));
+        }
+        assert!(!client.queue.lock().unwrap().closed);
+        client.push(message(false));
+        let queue = client.queue.lock().unwrap();
+        assert!(queue.closed);
+        assert!(queue.messages.is_empty());
+        drop(queue);
+        client.push(message(false));
+        assert!(client.queue.lock().unwrap().messages.is_empty());
+    }
+
+
+    #[test]
+    fn overlong_lines_are_refused() {
+        let server = HttpServer::start("127.0.0.1:0").unwrap();
+        let answer = exchange(&server, &vec![b'a'; LINE_LIMIT + 1]);
+        assert!(answer.starts_with("HTTP/1.1 414 "), "{answer}");
+        let mut request = b"GET /status HTTP/1.1\r\nX-Long: ".to_vec();
+        request.extend(vec![b'a'; LINE_LIMIT]);
+        let answer = exchange(&server, &request);
+        assert!(answer.starts_with("HTTP/1.1 431 "), "{answer}");
+    }
+
+
+    #[test]
+    fn websocket_clients_get_the_status_then_events() {
+        let server = HttpServer::start("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 101 Switching Protocols\r\n");
+        let mut accepted = false;
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            // The example of RFC 6455 section 1.3.
+            accepted |= line == "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n";
+        }
+        assert!(accepted);
+        let mut frame = || {
+            let mut header = [0u8; 2];
+            reader.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], 0x81);
+            let length = match header[1] {
+                126 => {
+                    let mut extended = [0u8; 2];
+                    reader.read_exact(&mut extended).unwrap();
+                    u16::from_be_bytes(extended) as usize
+                }
+                length => length as usize,
+            };
+            let mut payload = vec![0; length];
+            reader.read_exact(&mut payload).unwrap();
+            String::from_utf8(payload).unwrap()
+        };
+        assert!(frame().starts_with(r#"{"type":"status""#));
+        while !server.has_clients() {
+            thread::yield_now();
+        }
+        server.send_event(r#"{"type":"start","id":1}"#.to_string());
+        assert_eq!(frame(), r#"{"type":"start","id":1}"#);
+    }
+}
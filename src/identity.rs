@@ -0,0 +1,253 @@
+use std::{
+    fs::{ self, File },
+    io::{ self, Read, Write },
+    path::{ Path, PathBuf },
+    time::{ SystemTime, UNIX_EPOCH },
+};
+
+use crate::exit_report::fnv1a;
+
+/// Sequence numbers reserved on disk at a time. A crash skips what's left of the block, never
+/// more, and nothing is ever handed out twice.
+pub const SEQUENCE_BLOCK: u64 = 64;
+
+const INSTALLATION_FILE: &str = "installation-id";
+const SEQUENCE_FILE: &str = "event-sequence";
+
+
+/// What a consumer dedupes an event on: the installation it came from, its place in that
+/// installation's sequence of events, and the movement it belongs to. A replayed or spooled
+/// delivery carries the same identity as the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventIdentity {
+    pub installation: String,
+    pub sequence: u64,
+    pub id: u64,
+}
+
+
+impl EventIdentity {
+
+    /// "installation:sequence", the id is kept apart where it already is, e.g. in spool records.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.installation, self.sequence)
+    }
+
+    /// Reads back a `key` for movement `id`.
+    pub fn parse(key: &str, id: u64) -> Option<Self> {
+        let (installation, sequence) = key.rsplit_once(':')?;
+        is_uuid(installation).then_some(())?;
+        Some(Self { installation: installation.to_string(), sequence: sequence.parse().ok()?, id })
+    }
+
+    /// For email Message-IDs, the same for every delivery of the email.
+    pub fn message_id(&self, recipient: &str) -> String {
+        format!("<{}.{}.{}.{:016x}@motion-detect>", self.installation, self.sequence, self.id, fnv1a(recipient.as_bytes()))
+    }
+}
+
+
+/// This installation's id and its event sequence, kept in the state directory: the id is
+/// generated on the first run, a random UUID, and the sequence goes on from where the last run
+/// left it. The sequence file holds the end of the block last reserved, replaced by an atomic
+/// rename after syncing, so a crash leaves either the old or the new block on disk.
+///
+/// Without a state directory, or if it can't be used, the id is only this run's: different from
+/// every other, so keys never collide, but consumers see a new installation after a restart.
+pub struct Identity {
+    installation: String,
+    next: u64,
+    reserved: u64,          // Numbers below it may have been handed out.
+    dir: Option<PathBuf>,   // None if the sequence isn't kept.
+    warnings: Vec<String>,
+}
+
+
+impl Identity {
+
+    /// Loads the id and sequence from `dir`, creating both on the first run.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|err| format!("can't create the state directory {}: {err}", dir.display()))?;
+        let installation_path = dir.join(INSTALLATION_FILE);
+        let installation = match fs::read_to_string(&installation_path) {
+            Ok(text) if is_uuid(text.trim()) => text.trim().to_string(),
+            Ok(_) => return Err(format!("{} doesn't hold an installation id", installation_path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let installation = uuid();
+                write_atomically(&installation_path, &format!("{installation}\n"))
+                    .map_err(|err| format!("can't write {}: {err}", installation_path.display()))?;
+                installation
+            }
+            Err(err) => return Err(format!("can't read {}: {err}", installation_path.display())),
+        };
+        let sequence_path = dir.join(SEQUENCE_FILE);
+        let reserved = match fs::read_to_string(&sequence_path) {
+            Ok(text) => text.trim().parse().map_err(|_| format!("{} doesn't hold a sequence number", sequence_path.display()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(format!("can't read {}: {err}", sequence_path.display())),
+        };
+        let mut identity = Self { installation, next: reserved, reserved, dir: Some(dir.to_path_buf()), warnings: Vec::new() };
+        identity.reserve().map_err(|err| format!("can't write {}: {err}", sequence_path.display()))?;
+        Ok(identity)
+    }
+
+    /// An id of this run only, with a sequence from 0 that isn't kept.
+    pub fn ephemeral() -> Self {
+        Self { installation: uuid(), next: 0, reserved: u64::MAX, dir: None, warnings: Vec::new() }
+    }
+
+    pub fn installation(&self) -> &str {
+        &self.installation
+    }
+
+    /// The identity of the next event, of movement `id`.
+    pub fn next(&mut self, id: u64) -> EventIdentity {
+        if self.next == self.reserved {
+            // Handing out numbers that weren't reserved could repeat them after a crash, going
+            // on unkept is the lesser evil.
+            if let Err(err) = self.reserve() {
+                let path = self.dir.take().unwrap_or_default().join(SEQUENCE_FILE);
+                self.warnings.push(format!("can't write {}: {err}, the event sequence isn't kept anymore", path.display()));
+                self.reserved = u64::MAX;
+            }
+        }
+        let sequence = self.next;
+        self.next += 1;
+        EventIdentity { installation: self.installation.clone(), sequence, id }
+    }
+
+    /// What went wrong keeping the sequence since last asked.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn reserve(&mut self) -> io::Result<()> {
+        let Some(dir) = &self.dir else { return Ok(()) };
+        let reserved = self.next + SEQUENCE_BLOCK;
+        write_atomically(&dir.join(SEQUENCE_FILE), &format!("{reserved}\n"))?;
+        self.reserved = reserved;
+        Ok(())
+    }
+}
+
+
+/// Where the installation id and event sequence are kept unless --state-dir says otherwise: in
+/// $XDG_STATE_HOME, or ~/.local/state without it.
+pub fn default_dir() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|home| !home.is_empty()).map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(state_home.join("motion-detect"))
+}
+
+
+// Written and synced next to the file, then renamed over it.
+fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+
+// A version 4 UUID, from the system's random source or, without one, the time and process.
+fn uuid() -> String {
+    let mut bytes = [0u8; 16];
+    let random = File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut bytes));
+    if random.is_err() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let seed = [nanos.to_le_bytes().as_slice(), &std::process::id().to_le_bytes()].concat();
+        bytes[.. 8].copy_from_slice(&fnv1a(&seed).to_le_bytes());
+        bytes[8 ..].copy_from_slice(&fnv1a(&[seed.as_slice(), b"motion-detect"].concat()).to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{}-{}-{}-{}-{}", &hex[.. 8], &hex[8 .. 12], &hex[12 .. 16], &hex[16 .. 20], &hex[20 ..])
+}
+
+
+// "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx" in lowercase hex.
+fn is_uuid(text: &str) -> bool {
+    text.len() == 36 && text.char_indices().all(|(index, char)| match index {
+        8 | 13 | 18 | 23 => char == '-',
+        _ => matches!(char, '0' ..= '9' | 'a' ..= 'f'),
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn state_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("motion-detect-identity-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+
+    #[test]
+    fn a_key_reads_back_to_the_same_identity() {
+        let identity = Identity::ephemeral().next(7);
+        assert!(is_uuid(&identity.installation), "{}", identity.installation);
+        assert_eq!(EventIdentity::parse(&identity.key(), 7), Some(identity));
+        assert_eq!(EventIdentity::parse("not-a-uuid:3", 7), None);
+    }
+
+
+    #[test]
+    fn a_restart_goes_on_past_the_block_the_last_run_reserved() {
+        let dir = state_dir("restart");
+        let mut first = Identity::open(&dir).unwrap();
+        let handed_out: Vec<_> = (1 .. 4).map(|id| first.next(id).sequence).collect();
+        assert_eq!(handed_out, [0, 1, 2]);
+        drop(first);
+        let mut second = Identity::open(&dir).unwrap();
+        let next = second.next(4);
+        assert_eq!((next.sequence, next.installation.as_str()), (SEQUENCE_BLOCK, Identity::open(&dir).unwrap().installation()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+
+    #[test]
+    fn a_block_used_up_is_reserved_before_it_is_handed_out() {
+        let dir = state_dir("block");
+        let mut identity = Identity::open(&dir).unwrap();
+        for id in 0 .. SEQUENCE_BLOCK + 1 {
+            identity.next(id);
+        }
+        let reserved = fs::read_to_string(dir.join(SEQUENCE_FILE)).unwrap();
+        assert_eq!(reserved.trim(), (2 * SEQUENCE_BLOCK).to_string());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+
+    #[test]
+    fn a_damaged_state_is_refused_rather_than_started_over() {
+        let dir = state_dir("damaged");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(INSTALLATION_FILE), "cabin\n").unwrap();
+        assert!(Identity::open(&dir).is_err());
+        fs::remove_file(dir.join(INSTALLATION_FILE)).unwrap();
+        fs::write(dir.join(SEQUENCE_FILE), "many\n").unwrap();
+        assert!(Identity::open(&dir).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+
+    #[test]
+    fn a_sequence_that_can_no_longer_be_kept_goes_on_with_a_warning() {
+        let dir = state_dir("unkept");
+        let mut identity = Identity::open(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let sequences: Vec<_> = (0 .. SEQUENCE_BLOCK + 2).map(|id| identity.next(id).sequence).collect();
+        assert_eq!(sequences, (0 .. SEQUENCE_BLOCK + 2).collect::<Vec<_>>());
+        assert_eq!(identity.take_warnings().len(), 1);
+        assert!(identity.take_warnings().is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::{ Duration, Instant, SystemTime };
+
+use crate::{ clock::TimeSource, json };
+
+/// Stillness reported to consumers, so they don't need timers of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleEvent {
+    /// Nothing moved for `idle_for`, which just reached the `after` threshold.
+    Idle { after: Duration, idle_for: Duration },
+    /// A movement started after an `Idle`, ending a stillness that lasted `idle_for`.
+    IdleEnd { idle_for: Duration },
+}
+
+
+impl IdleEvent {
+
+    pub fn to_json(&self, time: SystemTime, source: TimeSource) -> String {
+        let object = match self {
+            IdleEvent::Idle { after, idle_for } => json::Object::new()
+                .field("type", "idle")
+                .field("idle_after", after.as_secs_f64())
+                .field("idle_for", idle_for.as_secs_f64()),
+            IdleEvent::IdleEnd { idle_for } => json::Object::new()
+                .field("type", "idle_end")
+                .field("idle_for", idle_for.as_secs_f64()),
+        };
+        object
+            .field("time", json::unix_time(time))
+            .field("time_source", source.name())
+            .finish()
+    }
+
+    /// The line printed in text mode, e.g. "idle 15m".
+    pub fn text(&self) -> String {
+        match self {
+            IdleEvent::Idle { after, .. } => format!("idle {}", short_duration(*after)),
+            IdleEvent::IdleEnd { idle_for } => format!("idle end after {}", short_duration(*idle_for)),
+        }
+    }
+}
+
+
+// Whole hours, minutes or seconds, the way durations are given on the command line.
+fn short_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0 => format!("{:.0?}", duration),
+        _ if seconds.is_multiple_of(3600) => format!("{}h", seconds / 3600),
+        _ if seconds.is_multiple_of(60) => format!("{}m", seconds / 60),
+        _ if seconds >= 3600 => format!("{}h{}m", seconds / 3600, seconds % 3600 / 60),
+        _ if seconds >= 60 => format!("{}m{}s", seconds / 60, seconds % 60),
+        _ => format!("{seconds}s"),
+    }
+}
+
+
+/// Measures how long nothing has moved, on the detector's frame clock, and reports each
+/// threshold once per still period: an `Idle` when it's reached, and an `IdleEnd` when the next
+/// movement starts.
+pub struct IdleTimer {
+    thresholds: Vec<Duration>,  // Ascending.
+    still_since: Option<Instant>, // None during movements.
+    reported: usize,            // Thresholds already reported for the current still period.
+    assume_idle: bool,          // The first still period counts as having lasted forever.
+}
+
+
+impl IdleTimer {
+
+    /// Starts counting from `now`. With `assume_idle`, every threshold is reported on the first
+    /// update instead of once reached, as if nothing had moved for a long time before the start.
+    pub fn new(mut thresholds: Vec<Duration>, now: Instant, assume_idle: bool) -> Self {
+        thresholds.sort();
+        thresholds.dedup();
+        Self { thresholds, still_since: Some(now), reported: 0, assume_idle }
+    }
+
+    /// How long nothing has moved, None during a movement.
+    pub fn idle_for(&self, now: Instant) -> Option<Duration> {
+        self.still_since.map(|since| now.saturating_duration_since(since))
+    }
+
+    /// Ends the still period. Returns the `IdleEnd` if it was reported as idle.
+    pub fn motion_started(&mut self, now: Instant) -> Option<IdleEvent> {
+        let idle_for = self.idle_for(now)?;
+        let reported = self.reported > 0;
+        self.still_since = None;
+        self.reported = 0;
+        self.assume_idle = false;
+        reported.then_some(IdleEvent::IdleEnd { idle_for })
+    }
+
+    pub fn motion_stopped(&mut self, now: Instant) {
+        self.still_since = Some(now);
+        self.reported = 0;
+    }
+
+    /// The thresholds reached since the last update.
+    pub fn update(&mut self, now: Instant) -> Vec<IdleEvent> {
+        let Some(idle_for) = self.idle_for(now) else {
+            return Vec::new();
+        };
+        let mut events = Vec::new();
+        while let Some(after) = self.thresholds.get(self.reported).copied() {
+            if idle_for < after && !self.assume_idle {
+                break;
+            }
+            events.push(IdleEvent::Idle { after, idle_for });
+            self.reported += 1;
+        }
+        events
+    }
+}
@@ -0,0 +1,264 @@
+use std::{ fmt::Write, time::{ SystemTime, UNIX_EPOCH } };
+
+/// Minimal JSON object writer, enough for the flat messages this tool emits
+/// without pulling in a serialization dependency.
+#[derive(Clone)]
+pub struct Object {
+    text: String,
+}
+
+
+impl Default for Object {
+    fn default() -> Self {
+        Self { text: String::from("{") }
+    }
+}
+
+
+impl Object {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a field. Chained calls keep message construction on one line.
+    pub fn field(mut self, name: &str, value: impl Value) -> Self {
+        if self.text.len() > 1 {
+            self.text.push(',');
+        }
+        write_string(&mut self.text, name);
+        self.text.push(':');
+        value.write(&mut self.text);
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.text.push('}');
+        self.text
+    }
+}
+
+
+/// Anything that can be written as a JSON value.
+pub trait Value {
+    fn write(&self, out: &mut String);
+}
+
+
+impl Value for &str {
+    fn write(&self, out: &mut String) {
+        write_string(out, self);
+    }
+}
+
+impl Value for String {
+    fn write(&self, out: &mut String) {
+        write_string(out, self);
+    }
+}
+
+impl Value for bool {
+    fn write(&self, out: &mut String) {
+        out.push_str(if *self { "true" } else { "false" });
+    }
+}
+
+impl Value for u64 {
+    fn write(&self, out: &mut String) {
+        let _ = write!(out, "{self}");
+    }
+}
+
+impl Value for usize {
+    fn write(&self, out: &mut String) {
+        let _ = write!(out, "{self}");
+    }
+}
+
+impl Value for i32 {
+    fn write(&self, out: &mut String) {
+        let _ = write!(out, "{self}");
+    }
+}
+
+impl Value for f32 {
+    fn write(&self, out: &mut String) {
+        (*self as f64).write(out);
+    }
+}
+
+impl Value for f64 {
+    fn write(&self, out: &mut String) {
+        if self.is_finite() {
+            let _ = write!(out, "{:.3}", self);
+        } else {
+            out.push_str("null");
+        }
+    }
+}
+
+impl<T: Value> Value for Option<T> {
+    fn write(&self, out: &mut String) {
+        match self {
+            Some(value) => value.write(out),
+            None => out.push_str("null"),
+        }
+    }
+}
+
+impl Value for Object {
+    fn write(&self, out: &mut String) {
+        out.push_str(&self.text);
+        out.push('}');
+    }
+}
+
+impl<T: Value> Value for Vec<T> {
+    fn write(&self, out: &mut String) {
+        out.push('[');
+        for (index, value) in self.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            value.write(out);
+        }
+        out.push(']');
+    }
+}
+
+
+/// Timestamps are written as fractional seconds since the unix epoch.
+pub fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map(|t| t.as_secs_f64()).unwrap_or(0.0)
+}
+
+
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+
+/// A value of a flat JSON object, see `parse_flat`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scalar {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+
+/// Reads a JSON object whose values are all scalars, like the bodies of control requests, in the
+/// order of its fields. Nested objects and arrays are refused.
+pub fn parse_flat(text: &str) -> Result<Vec<(String, Scalar)>, String> {
+    let mut reader = Reader { chars: text.char_indices().peekable(), text };
+    reader.expect('{')?;
+    let mut fields = Vec::new();
+    if reader.peek() == Some('}') {
+        reader.next();
+    } else {
+        loop {
+            let name = reader.string()?;
+            reader.expect(':')?;
+            fields.push((name, reader.scalar()?));
+            match reader.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'".to_string()),
+            }
+        }
+    }
+    match reader.next() {
+        None => Ok(fields),
+        Some(_) => Err("unexpected text after the object".to_string()),
+    }
+}
+
+
+struct Reader<'t> {
+    chars: std::iter::Peekable<std::str::CharIndices<'t>>,
+    text: &'t str,
+}
+
+
+impl Reader<'_> {
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.next().map(|(_, c)| c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(format!("expected '{expected}'")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next().map(|(_, c)| c) {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next().map(|(_, c)| c) {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => {
+                        let digits: String = (0 .. 4).filter_map(|_| self.chars.next().map(|(_, c)| c)).collect();
+                        let code = u32::from_str_radix(&digits, 16).map_err(|_| "invalid \\u escape".to_string())?;
+                        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    _ => return Err("invalid escape".to_string()),
+                },
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn scalar(&mut self) -> Result<Scalar, String> {
+        match self.peek() {
+            Some('"') => return self.string().map(Scalar::String),
+            Some('{') | Some('[') => return Err("nested values aren't accepted".to_string()),
+            _ => {}
+        }
+        let start = self.chars.peek().map_or(self.text.len(), |(index, _)| *index);
+        while self.chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || "+-.".contains(*c)).is_some() {}
+        let end = self.chars.peek().map_or(self.text.len(), |(index, _)| *index);
+        match &self.text[start .. end] {
+            "null" => Ok(Scalar::Null),
+            "true" => Ok(Scalar::Bool(true)),
+            "false" => Ok(Scalar::Bool(false)),
+            number => number.parse::<f64>().ok()
+                .filter(|number| number.is_finite())
+                .map(Scalar::Number)
+                .ok_or_else(|| format!("invalid value '{number}'")),
+        }
+    }
+}
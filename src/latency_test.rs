@@ -0,0 +1,477 @@
+use std::{
+    io::Read,
+    sync::mpsc::{ self, Receiver, TryRecvError },
+    time::{ Duration, Instant },
+};
+use eye::hal::PlatformContext;
+
+use crate::{
+    camera::{ self, Camera },
+    diff::{ self, Blur, DiffStrategy, FlickerRejection, Masked, Normalize },
+    ffmpeg::FfmpegSource,
+    json,
+    motion::{ MotionEvent, MotionTracker },
+    output::{ Format, Output },
+    settings::{ Input, Settings },
+    signals,
+    source::{ FrameSource, RawVideoSource },
+    thumbnail::{ PixelLayout, Thumbnail },
+    timing::{ PipelineTiming, Stage },
+};
+
+// Exit codes, as for self-test.
+const EXIT_DEVICE: i32 = 19;        // No such device
+const EXIT_CAPTURE: i32 = 5;        // I/O error
+const EXIT_CONTENT: i32 = 61;       // No data available
+const EXIT_TIMING: i32 = 62;        // Timer expired
+const EXIT_INVALID: i32 = 22;       // Invalid argument
+
+// Quiet frames needed before a trigger, and how long the scene gets to become that quiet.
+const SETTLE: Duration = Duration::from_secs(1);
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A trigger not followed by a movement within this long is counted as missed.
+const DETECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A change not followed by Enter within this long moved before the trigger.
+const KEY_GRACE: Duration = Duration::from_secs(1);
+
+// How long the LED stays on past the detection, so its light reaches the frames for sure.
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+const LED_HOLD: Duration = Duration::from_millis(500);
+
+// What stopped the test, and its exit code.
+type Failure = (String, i32);
+
+
+/// Parts of the time from a trigger to the movement's "start", in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    CaptureWait,    // Until the first frame showing the change was delivered.
+    Processing,     // Downsampling and diffing that frame.
+    Confirmation,   // Until the tracker started the movement, with --confirm-frames.
+    Total,
+}
+
+
+impl Part {
+
+    const ALL: [Part; 4] = [Part::CaptureWait, Part::Processing, Part::Confirmation, Part::Total];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Part::CaptureWait => "capture_wait",
+            Part::Processing => "processing",
+            Part::Confirmation => "confirmation",
+            Part::Total => "total",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Part::CaptureWait => "capture wait",
+            other => other.name(),
+        }
+    }
+}
+
+
+/// One detected trigger, each part in the order of Part::ALL.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    parts: [Duration; 4],
+}
+
+
+impl Sample {
+
+    fn to_object(self) -> json::Object {
+        Part::ALL.iter().fold(json::Object::new(), |object, part| {
+            object.field(&format!("{}_ms", part.name()), milliseconds(self.parts[*part as usize]))
+        })
+    }
+}
+
+
+/// The sharp visual change being timed: an LED on a GPIO line, or the user with a light switch,
+/// pressing Enter as they flip it.
+enum Trigger {
+    Manual(Receiver<Instant>),
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    Led(crate::gpio::GpioOutput),
+}
+
+
+impl Trigger {
+
+    fn name(&self) -> &'static str {
+        match self {
+            Trigger::Manual(_) => "manual",
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            Trigger::Led(_) => "gpio",
+        }
+    }
+}
+
+
+/// What one frame through the pipeline gave.
+struct Frame {
+    delivered: Instant,
+    processed: Instant,
+    over: bool,                 // Above the start threshold.
+    started: Option<Instant>,   // When the tracker started a movement with it.
+}
+
+
+/// The frames of a change so far: the first one above the start threshold, when it was delivered
+/// and processed, and when its movement started.
+#[derive(Default)]
+struct Observed {
+    first_over: Option<(Instant, Instant)>,
+    started: Option<Instant>,
+}
+
+
+impl Observed {
+
+    fn push(&mut self, frame: &Frame) {
+        if frame.over && self.first_over.is_none() {
+            self.first_over = Some((frame.delivered, frame.processed));
+        }
+        self.started = self.started.or(frame.started);
+    }
+
+    /// The latencies once the movement started. A movement started without crossing the start
+    /// threshold first is one that was confirmed right away.
+    fn sample(&self, trigger: Instant) -> Option<Sample> {
+        let started = self.started?;
+        let (delivered, processed) = self.first_over.unwrap_or((started, started));
+        Some(Sample { parts: [
+            delivered.saturating_duration_since(trigger),
+            processed.saturating_duration_since(delivered),
+            started.saturating_duration_since(processed),
+            started.saturating_duration_since(trigger),
+        ] })
+    }
+}
+
+
+/// The detector's pipeline, without the sinks: source, downsampling, diff strategy and tracker,
+/// each frame's stages timed.
+struct Pipeline<'a> {
+    source: Box<dyn FrameSource + 'a>,
+    frame_width: usize,
+    downsample: usize,
+    layout: PixelLayout,
+    thumb: Thumbnail,
+    strategy: Box<dyn DiffStrategy>,
+    motion: MotionTracker,
+    timing: PipelineTiming,
+}
+
+
+impl Pipeline<'_> {
+
+    fn step(&mut self) -> Result<Frame, String> {
+        let capture_start = Instant::now();
+        let frame = self.source.next_frame()?.ok_or("the input ended")?;
+        let delivered = Instant::now();
+        self.timing.record(Stage::Capture, delivered - capture_start);
+        self.thumb.downsample(frame, self.frame_width, self.downsample, self.layout);
+        let downsampled = Instant::now();
+        self.timing.record(Stage::Downsample, downsampled - delivered);
+        self.strategy.set_motion_active(self.motion.is_active());
+        let result = self.strategy.process(&self.thumb);
+        let processed = Instant::now();
+        self.timing.record(Stage::Diff, processed - downsampled);
+        self.timing.end_frame();
+        let over = self.motion.exceeds_start(result.changed_pixels);
+        let started = self.motion.update(result.changed_pixels, result.score, delivered).iter()
+            .any(|event| matches!(event, MotionEvent::Start { .. }))
+            .then(Instant::now);
+        Ok(Frame { delivered, processed, over, started })
+    }
+
+    /// Processes frames until none crossed the start threshold for SETTLE, with no movement left.
+    fn settle(&mut self) -> Result<(), Failure> {
+        let start = Instant::now();
+        let mut quiet_since: Option<Instant> = None;
+        loop {
+            let frame = self.step().map_err(capture_failed)?;
+            if frame.over || self.motion.is_busy() {
+                quiet_since = None;
+            } else if frame.delivered.duration_since(*quiet_since.get_or_insert(frame.delivered)) >= SETTLE {
+                return Ok(());
+            }
+            if frame.delivered.duration_since(start) > SETTLE_TIMEOUT {
+                return Err((format!("the scene didn't settle within {SETTLE_TIMEOUT:?}, keep it still or raise the thresholds"), EXIT_CONTENT));
+            }
+        }
+    }
+
+    /// Processes frames after the trigger until the tracker starts a movement, None if it
+    /// doesn't within DETECT_TIMEOUT.
+    fn measure(&mut self, trigger: Instant, mut observed: Observed) -> Result<Option<Sample>, Failure> {
+        loop {
+            if let Some(sample) = observed.sample(trigger) {
+                return Ok(Some(sample));
+            }
+            let frame = self.step().map_err(capture_failed)?;
+            observed.push(&frame);
+            if observed.started.is_none() && frame.delivered.saturating_duration_since(trigger) > DETECT_TIMEOUT {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+
+/// Times the sharp visual changes of a trigger through the detector, --repetitions times, and
+/// prints the distribution of trigger to "start" latencies, broken down into its parts. Returns
+/// the process exit code.
+pub fn run(settings: &Settings) -> i32 {
+    let output = Output::new(settings.format);
+    let mut trigger = match open_trigger(settings) {
+        Ok(trigger) => trigger,
+        Err(err) => {
+            output.info(&format!("\nError, {err}"));
+            return EXIT_INVALID;
+        }
+    };
+    // Only cameras need the platform's context.
+    let ctx = (settings.input == Input::Camera).then(PlatformContext::default);
+    let mut pipeline = match open_pipeline(settings, ctx.as_ref(), &output) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            output.info(&format!("\nError, {err}"));
+            return EXIT_DEVICE;
+        }
+    };
+    output.info(&format!("Warming up for {:.1?}...", settings.camera_warm_up));
+    std::thread::sleep(settings.camera_warm_up);
+    pipeline.motion.ready(Instant::now());
+    signals::install_shutdown_handler();
+
+    match measure_all(settings, &mut pipeline, &mut trigger, &output) {
+        Ok(samples) => {
+            let missed = settings.repetitions - samples.len() as u32;
+            match settings.format {
+                Format::Text => print_table(&samples, missed, &pipeline.timing),
+                Format::Json => println!("{}", to_json(settings, trigger.name(), &samples, missed, &pipeline.timing)),
+            }
+            if missed > 0 { EXIT_TIMING } else { 0 }
+        }
+        Err((err, code)) => {
+            output.info(&format!("\nError, {err}"));
+            code
+        }
+    }
+}
+
+
+fn measure_all(settings: &Settings, pipeline: &mut Pipeline, trigger: &mut Trigger, output: &Output) -> Result<Vec<Sample>, Failure> {
+    let mut samples = Vec::new();
+    let mut repetition = 0;
+    while repetition < settings.repetitions && !signals::shutdown_requested() {
+        pipeline.settle()?;
+        let label = format!("{} of {}", repetition + 1, settings.repetitions);
+        let fired = match trigger {
+            Trigger::Manual(keys) => {
+                while keys.try_recv().is_ok() {}
+                output.info(&format!("Trigger {label}: press Enter the moment you switch the light on"));
+                wait_for_key(pipeline, keys)?
+            }
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            Trigger::Led(led) => {
+                // Spreads the triggers over the capture interval, as physical motion would be.
+                let phase = (repetition as f64 * 0.618).fract();
+                std::thread::sleep(pipeline.timing.stage(Stage::Capture).mean.mul_f64(phase));
+                led.set(true).map_err(|err| (err, EXIT_DEVICE))?;
+                Some((Instant::now(), Observed::default()))
+            }
+        };
+        let Some((fired, observed)) = fired else {
+            output.info("Warning, movement before the trigger, keep the scene still until then");
+            continue;
+        };
+        let sample = pipeline.measure(fired, observed)?;
+        match sample {
+            Some(sample) => output.info(&format!("Trigger {label}: start after {:.1?}", sample.parts[Part::Total as usize])),
+            None => output.info(&format!("Trigger {label}: no movement within {DETECT_TIMEOUT:?}")),
+        }
+        samples.extend(sample);
+        match trigger {
+            Trigger::Manual(_) => output.info("Switch the light off again"),
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            Trigger::Led(led) => {
+                std::thread::sleep(LED_HOLD);
+                let _ = led.set(false);
+            }
+        }
+        repetition += 1;
+    }
+    Ok(samples)
+}
+
+
+// Keeps the pipeline going until Enter is pressed. The frames showing the change may well be
+// processed before the key is read, they only count if they came after it. None if the scene
+// moved first.
+fn wait_for_key(pipeline: &mut Pipeline, keys: &Receiver<Instant>) -> Result<Option<(Instant, Observed)>, Failure> {
+    let mut observed = Observed::default();
+    loop {
+        let frame = pipeline.step().map_err(capture_failed)?;
+        observed.push(&frame);
+        let early = |time: Instant| observed.first_over.is_some_and(|(delivered, _)| delivered < time);
+        match keys.try_recv() {
+            Ok(time) if early(time) => return Ok(None),
+            Ok(time) => return Ok(Some((time, observed))),
+            Err(TryRecvError::Empty) if early(frame.delivered.checked_sub(KEY_GRACE).unwrap_or(frame.delivered)) => return Ok(None),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                return Err(("stdin closed, a manual trigger needs Enter presses".to_string(), EXIT_INVALID));
+            }
+        }
+    }
+}
+
+
+fn capture_failed(err: String) -> Failure {
+    (err, EXIT_CAPTURE)
+}
+
+
+// The LED with --gpio-pin, Enter presses otherwise, timed as they're read.
+fn open_trigger(settings: &Settings) -> Result<Trigger, String> {
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    if let Some(pin) = settings.gpio_pin {
+        return crate::gpio::GpioOutput::open(&settings.gpio_chip, pin, settings.gpio_active_high, Duration::ZERO).map(Trigger::Led);
+    }
+    if settings.input == Input::Stdin {
+        return Err("latency-test reads Enter presses from stdin, raw video input needs --gpio-pin".to_string());
+    }
+    let (sender, keys) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8; 1];
+        while let Ok(1) = std::io::stdin().read(&mut byte) {
+            if byte[0] == b'\n' && sender.send(Instant::now()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Trigger::Manual(keys))
+}
+
+
+fn open_pipeline<'a>(settings: &Settings, ctx: Option<&'a PlatformContext>, output: &Output) -> Result<Pipeline<'a>, String> {
+    let (width, height) = (settings.capture_width as usize, settings.capture_height as usize);
+    let (source, frame_width, frame_height, layout): (Box<dyn FrameSource + 'a>, usize, usize, PixelLayout) = match &settings.input {
+        Input::Camera => {
+            let ctx = ctx.expect("Cameras come with a context");
+            let mut camera = Camera::open(ctx, settings.capture_width, settings.capture_height, settings.frame_capture_interval, output)
+                .map_err(|err| err.to_string())?;
+            if let Some(layout) = settings.force_input_layout {
+                camera.force_layout(layout);
+            }
+            let layout = camera::layout(&camera.descriptor.pixfmt, settings.force_input_layout);
+            let (frame_width, frame_height) = (camera.descriptor.width as usize, camera.descriptor.height as usize);
+            (Box::new(camera), frame_width, frame_height, layout)
+        }
+        Input::Stdin => {
+            let format = settings.input_format;
+            let layout = camera::layout(&format.pixel_format(), None);
+            (Box::new(RawVideoSource::new(std::io::stdin().lock(), format, width, height)), width, height, layout)
+        }
+        Input::Url(url) => {
+            let source = FfmpegSource::start(&settings.ffmpeg, &settings.ffmpeg_args, url, width, height, settings.frame_capture_interval, settings.input_watchdog)?;
+            (Box::new(source), width, height, PixelLayout::Rgb)
+        }
+    };
+
+    let thumb = Thumbnail::with_channels(frame_width / settings.downsample, frame_height / settings.downsample, layout.channels())
+        .with_stats(settings.thumb_stats);
+    let pixel_threshold = ((settings.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let start_count = (thumb.len() as f32 * (settings.image_threshold / 100.0).clamp(0.0, 1.0)) as i32;
+    let sustain_count = (thumb.len() as f32 * (settings.sustain_threshold() / 100.0).clamp(0.0, 1.0)) as i32;
+    let mut strategy = diff::from_name(&settings.algorithm, pixel_threshold, start_count, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("Algorithm names are validated with the settings");
+    if let Some(mode) = settings.normalize {
+        strategy = Box::new(Normalize::new(strategy, mode));
+    }
+    if settings.flicker_rejection {
+        strategy = Box::new(FlickerRejection::new(strategy, pixel_threshold));
+    }
+    if settings.blur > 0 {
+        strategy = Box::new(Blur::new(strategy, settings.blur));
+    }
+    if let Some(mask) = &settings.mask {
+        strategy = Box::new(Masked::new(strategy, mask.clone()));
+    }
+    let motion = MotionTracker::new(settings.motion_tail_length, start_count, sustain_count)
+        .with_confirm_frames(settings.confirm_frames)
+        .with_frame_interval(settings.frame_capture_interval);
+    Ok(Pipeline {
+        source,
+        frame_width,
+        downsample: settings.downsample,
+        layout,
+        thumb,
+        strategy,
+        motion,
+        timing: PipelineTiming::default(),
+    })
+}
+
+
+/// Minimum, median, 90th percentile and maximum.
+fn distribution(samples: &[Sample], part: Part) -> [Duration; 4] {
+    let mut values: Vec<Duration> = samples.iter().map(|sample| sample.parts[part as usize]).collect();
+    values.sort_unstable();
+    let percentile = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+    [percentile(0.0), percentile(0.5), percentile(0.9), percentile(1.0)]
+}
+
+
+fn print_table(samples: &[Sample], missed: u32, timing: &PipelineTiming) {
+    println!("\n{:<14}{:>10}{:>10}{:>10}{:>10}", "Part", "min", "median", "p90", "max");
+    if !samples.is_empty() {
+        for part in Part::ALL {
+            let values: Vec<String> = distribution(samples, part).iter().map(|value| format!("{:.1?}", value)).collect();
+            println!("{:<14}{:>10}{:>10}{:>10}{:>10}", part.label(), values[0], values[1], values[2], values[3]);
+        }
+    }
+    println!("\n{} detected, {missed} missed", samples.len());
+    println!("{}", timing.summary_line());
+}
+
+
+fn to_json(settings: &Settings, trigger: &str, samples: &[Sample], missed: u32, timing: &PipelineTiming) -> String {
+    let parts = Part::ALL.iter().fold(json::Object::new(), |object, part| {
+        let value = (!samples.is_empty()).then(|| {
+            let [min, median, p90, max] = distribution(samples, *part);
+            json::Object::new()
+                .field("min_ms", milliseconds(min))
+                .field("median_ms", milliseconds(median))
+                .field("p90_ms", milliseconds(p90))
+                .field("max_ms", milliseconds(max))
+        });
+        object.field(part.name(), value)
+    });
+    json::Object::new()
+        .field("type", "latency_test")
+        .field("trigger", trigger)
+        .field("repetitions", settings.repetitions as u64)
+        .field("detected", samples.len())
+        .field("missed", missed as u64)
+        .field("capture_interval_ms", milliseconds(settings.frame_capture_interval))
+        .field("confirm_frames", settings.confirm_frames as u64)
+        .field("latency", parts)
+        .field("samples", samples.iter().map(|sample| sample.to_object()).collect::<Vec<_>>())
+        .field("timing", timing.to_object())
+        .finish()
+}
+
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,83 @@
+//! Motion detection building blocks used by the motion-detect binary: thumbnails, pixel difference
+//! strategies and the start/stop event logic, plus the optional outputs.
+
+pub mod ab;
+pub mod activation;
+pub mod activity;
+pub mod alert;
+#[cfg(feature = "async")]
+pub mod async_detector;
+pub mod batch;
+pub mod budget;
+pub mod bus;
+pub mod camera;
+pub mod capabilities;
+pub mod clock;
+pub mod config;
+pub mod contract;
+pub mod context;
+pub mod control;
+pub mod decimation;
+pub mod detector;
+pub mod diff;
+#[cfg(feature = "smtp")]
+pub mod email;
+pub mod exit_report;
+pub mod exposure;
+pub mod extract;
+pub mod ffmpeg;
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+pub mod gpio;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod heatmap;
+pub mod hooks;
+pub mod http;
+pub mod identity;
+pub mod idle;
+pub mod json;
+pub mod latency_test;
+pub mod limits;
+pub mod mask;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+pub mod media;
+pub mod memory;
+pub mod motion;
+pub mod noise;
+#[cfg(feature = "desktop-notify")]
+pub mod notify;
+pub mod output;
+pub mod overlay;
+pub mod padding;
+pub mod privacy;
+pub mod probe_cache;
+pub mod profile;
+pub mod quiet;
+pub mod resume;
+pub mod review;
+pub mod rotation;
+pub mod saturation;
+pub mod scene;
+pub mod schedule;
+pub mod self_test;
+pub mod sequence;
+pub mod settings;
+pub mod sidecar;
+pub mod signals;
+pub mod snapshot;
+pub mod source;
+pub mod spool;
+pub mod startup;
+pub mod state;
+pub mod supervisor;
+pub mod thumbnail;
+pub mod timelapse;
+pub mod timing;
+pub mod tune;
+#[cfg(all(feature = "uinput", target_os = "linux"))]
+pub mod uinput;
+pub mod verify;
+pub mod zones;
+#[cfg(feature = "zmq")]
+pub mod zmq;
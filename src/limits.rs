@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use crate::{ decimation::Decimation, settings::Settings, thumbnail::ThumbStats };
+
+/// Largest thumbnail compared, in pixels. Beyond it the per pixel work of every frame adds up to
+/// more than a core, and the largest useful thumbnails are far smaller.
+pub const MAX_THUMBNAIL_PIXELS: usize = 1_000_000;
+
+/// Largest source frame, in pixels: 8K.
+pub const MAX_FRAME_PIXELS: usize = 7680 * 4320;
+
+/// Largest blur radius. The box blur costs twice the radius per pixel and pass.
+pub const MAX_BLUR_RADIUS: usize = 64;
+
+/// Shortest capture interval, 200 frames per second.
+pub const MIN_CAPTURE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Largest estimate of the bytes held by frame and thumbnail buffers.
+pub const MAX_BUFFER_BYTES: usize = 1_000_000_000;
+
+/// Estimated byte operations per second above which a warning says to expect a busy core.
+pub const WARN_OPERATIONS_PER_SECOND: f64 = 1e9;
+
+
+/// What a configuration will cost per frame, estimated from the frame size and the options before
+/// anything is allocated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cost {
+    pub frame_width: usize,
+    pub frame_height: usize,
+    pub thumb_width: usize,
+    pub thumb_height: usize,
+    pub buffer_bytes: usize,        // Frame and thumbnail buffers, roughly.
+    pub operations: f64,            // Byte operations per processed frame: downsampling, blur and diff.
+    pub frames_per_second: f64,     // Processed ones.
+}
+
+
+impl Cost {
+
+    /// * `frame_bytes` - Bytes of one source frame.
+    /// * `channels` - Of the thumbnails, 1 or 3.
+    /// * `interval` - Between the frames the source delivers.
+    pub fn estimate(settings: &Settings, frame_width: usize, frame_height: usize, frame_bytes: usize, channels: usize, interval: Duration) -> Self {
+        let downsample = settings.downsample.max(1);
+        let (thumb_width, thumb_height) = (frame_width / downsample, frame_height / downsample);
+        // Block extremes take twice the bytes of the means.
+        let stats = if settings.thumb_stats == ThumbStats::MeanMinMax { 3 } else { 1 };
+        let thumb_bytes = thumb_width * thumb_height * channels * stats;
+
+        // The source's buffer and the kept copy of snapshots, then the thumbnail, the reference,
+        // the averaged, blurred, normalized or converted copies, and the per pixel mask,
+        // differences and noise.
+        let snapshots = settings.snapshot_dir.is_some() || settings.timelapse_dir.is_some() || settings.verify_command.is_some()
+            || settings.zones.iter().any(|zone| zone.snapshot.is_some());
+        let frame_buffers = frame_bytes + if snapshots { frame_width * frame_height * channels } else { 0 };
+        let averaging = if settings.temporal_average > 1 { thumb_bytes * 5 } else { 0 };
+        let buffer_bytes = frame_buffers + thumb_bytes * 6 + averaging + thumb_width * thumb_height * 6;
+
+        // Downsampling reads every byte of the frame. Each blur pass sums up to twice the radius
+        // per byte, no more than the row or column has.
+        let blur_span = (settings.blur * 2 + 1).min(thumb_width.max(thumb_height)) as f64;
+        let blur = if settings.blur > 0 { 2.0 * blur_span } else { 0.0 };
+        let compare = (thumb_bytes as f64 * (4.0 + blur)) / settings.temporal_average.max(1) as f64;
+        let operations = frame_bytes as f64 + compare;
+
+        let interval = interval.as_secs_f64().max(1e-6);
+        let frames_per_second = match settings.decimation {
+            None => 1.0 / settings.frame_capture_interval.as_secs_f64().max(interval),
+            Some(Decimation::Every(every)) => 1.0 / (interval * every.max(1) as f64),
+            Some(Decimation::Fps(fps)) => (fps as f64).min(1.0 / interval),
+        };
+        Self { frame_width, frame_height, thumb_width, thumb_height, buffer_bytes, operations, frames_per_second }
+    }
+
+    /// Checks the cost and the options against the limits. Returns the warnings, or the first
+    /// limit exceeded. With --i-know-what-im-doing exceeded limits are only warned about.
+    pub fn check(&self, settings: &Settings) -> Result<Vec<String>, String> {
+        let mut exceeded = Vec::new();
+        if self.frame_width * self.frame_height > MAX_FRAME_PIXELS {
+            exceeded.push((
+                format!("frame {}x{} exceeds the {} pixel limit (8K)", self.frame_width, self.frame_height, count(MAX_FRAME_PIXELS as f64)),
+                "lower --width and --height",
+            ));
+        }
+        if self.thumb_width * self.thumb_height > MAX_THUMBNAIL_PIXELS {
+            exceeded.push((
+                format!("thumbnail {}x{} exceeds the {} pixel limit", self.thumb_width, self.thumb_height, count(MAX_THUMBNAIL_PIXELS as f64)),
+                "increase --downsample",
+            ));
+        }
+        if settings.blur > MAX_BLUR_RADIUS {
+            exceeded.push((format!("blur radius {} exceeds the {MAX_BLUR_RADIUS} limit", settings.blur), "lower --blur"));
+        }
+        // Decimation reads frames as fast as the source delivers them, whatever the interval.
+        if settings.decimation.is_none() && settings.frame_capture_interval < MIN_CAPTURE_INTERVAL {
+            exceeded.push((
+                format!("capture interval {:?} is below the {MIN_CAPTURE_INTERVAL:?} limit", settings.frame_capture_interval),
+                "increase --capture-interval",
+            ));
+        }
+        if self.buffer_bytes > MAX_BUFFER_BYTES {
+            exceeded.push((
+                format!("buffers of about {}B exceed the {}B limit", count(self.buffer_bytes as f64), count(MAX_BUFFER_BYTES as f64)),
+                "lower the capture size or increase --downsample",
+            ));
+        }
+
+        // Each limit exceeded with what to change about it.
+        let mut warnings = Vec::new();
+        match exceeded.first() {
+            Some((limit, remedy)) if !settings.ignore_limits => return Err(format!("{limit}; {remedy}")),
+            _ => warnings.extend(exceeded.into_iter().map(|(limit, _)| format!("{limit}, allowed by --i-know-what-im-doing"))),
+        }
+        let per_second = self.operations * self.frames_per_second;
+        if per_second > WARN_OPERATIONS_PER_SECOND {
+            warnings.push(format!(
+                "about {} byte operations per second at {:.0} frames per second, expect a busy core; increase --capture-interval or --downsample",
+                count(per_second), self.frames_per_second
+            ));
+        }
+        Ok(warnings)
+    }
+}
+
+
+// Large counts with a unit prefix, e.g. "1M" or "2.1G".
+fn count(value: f64) -> String {
+    let (scaled, prefix) = match value {
+        _ if value >= 1e9 => (value / 1e9, "G"),
+        _ if value >= 1e6 => (value / 1e6, "M"),
+        _ if value >= 1e3 => (value / 1e3, "k"),
+        _ => (value, ""),
+    };
+    let text = format!("{scaled:.1}");
+    format!("{}{prefix}", text.trim_end_matches(".0"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(settings: &Settings, width: usize, height: usize) -> Cost {
+        Cost::estimate(settings, width, height, width * height * 3, 3, settings.frame_capture_interval)
+    }
+
+
+    #[test]
+    fn the_defaults_are_within_every_limit() {
+        let settings = Settings::default();
+        assert_eq!(cost(&settings, 640, 480).check(&settings), Ok(Vec::new()));
+    }
+
+
+    #[test]
+    fn an_absurd_configuration_is_refused_with_what_to_change() {
+        let settings = Settings::default();
+        let err = cost(&settings, 16_000, 9_000).check(&settings).unwrap_err();
+        assert!(err.starts_with("frame 16000x9000 exceeds the 33.2M pixel limit (8K)"), "{err}");
+        assert!(err.ends_with("lower --width and --height"), "{err}");
+        let settings = Settings { blur: 100, ..Settings::default() };
+        assert!(cost(&settings, 640, 480).check(&settings).unwrap_err().contains("lower --blur"));
+        let settings = Settings { frame_capture_interval: Duration::from_millis(1), ..Settings::default() };
+        assert!(cost(&settings, 640, 480).check(&settings).unwrap_err().contains("increase --capture-interval"));
+    }
+
+
+    #[test]
+    fn limits_are_only_warned_about_when_ignored() {
+        let settings = Settings { blur: 100, downsample: 1, ignore_limits: true, ..Settings::default() };
+        let warnings = cost(&settings, 7680, 4320).check(&settings).unwrap();
+        assert!(warnings.iter().any(|warning| warning.starts_with("thumbnail 7680x4320") && warning.ends_with("allowed by --i-know-what-im-doing")), "{warnings:?}");
+        assert!(warnings.iter().any(|warning| warning.starts_with("blur radius 100")), "{warnings:?}");
+        assert!(warnings.iter().any(|warning| warning.contains("expect a busy core")), "{warnings:?}");
+    }
+
+
+    #[test]
+    fn counts_have_a_unit_prefix() {
+        assert_eq!(count(999.0), "999");
+        assert_eq!(count(1_000_000.0), "1M");
+        assert_eq!(count(2_140_000_000.0), "2.1G");
+    }
+}
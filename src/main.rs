@@ -1,211 +1,2195 @@
-use std::{time::{ Duration, Instant }, error::Error};
-use eye::hal::{ format::PixelFormat, stream::Descriptor, traits::{Context, Device, Stream}, PlatformContext };
+use std::{collections::HashMap, time::{ Duration, Instant, SystemTime }, error::Error};
+use eye::hal::{ device::Description, stream::Descriptor, PlatformContext };
 
+use motion_detect::{
+    ab::{ Agreement, Variant },
+    activity::{ self, ActivityHistogram, ActivityReport, ActivitySummary },
+    activation::ListenFds,
+    alert::Alert,
+    batch,
+    camera::{ self, Camera, OpenError, StreamFormat },
+    clock::{ FrameTime, SuspendWatch, WallClock },
+    config::EffectiveConfig,
+    context::ContextCapture,
+    control::{ self, ControlCommand, ControlState, StormGuard },
+    decimation::Decimator,
+    budget::CpuBudget,
+    diff::{ self, Blur, Channels, DiffResult, DiffStrategy, FlickerRejection, Masked, Normalize, Stabilize, WhiteBalance },
+    exit_report::{ self, ExitReason },
+    exposure::{ ExposureCompensation, RAISED_FRAMES },
+    extract,
+    ffmpeg::{ self, FfmpegSource },
+    heatmap::Heatmap,
+    hooks::Hooks,
+    http, json,
+    identity::Identity,
+    idle::{ IdleEvent, IdleTimer },
+    latency_test,
+    limits::Cost,
+    mask::{ self, MaskImage, PackedMask },
+    media::{ self, MediaFile, MediaJob, MediaQueue },
+    memory::{ self, MemoryUsage },
+    motion::{ MotionEvent, MotionTracker, StopReason },
+    padding::ClipPadding,
+    output::{ Format, Lifecycle, Output },
+    probe_cache,
+    profile::SceneProfiles,
+    quiet::Holdover,
+    resume::{ ResumeCause, ResumeGuard },
+    review,
+    rotation::StreamRotation,
+    saturation::{ SaturatedPolicy, SaturationChange, SaturationDetector },
+    scene::{ self, SceneReference },
+    self_test,
+    sequence::{ DropReason, FrameCounter },
+    settings::{ Command, Input, PauseMode, Settings },
+    sidecar::Sidecar,
+    source::{ FrameSource, RawVideoSource, SourceInfo },
+    signals,
+    snapshot::Snapshots,
+    startup::{ Phase, Progress },
+    state::{ SavedState, StreamState },
+    supervisor::{ self, PanicAction, PanicSupervisor },
+    thumbnail::{ TemporalAverage, Thumbnail },
+    timelapse::Timelapse,
+    timing::{ PipelineTiming, Stage },
+    tune,
+    verify::Verifier,
+    zones::{ self, ZoneArming, ZoneMasks, ZoneTracker },
+};
+#[cfg(feature = "desktop-notify")]
+use motion_detect::notify;
+#[cfg(feature = "smtp")]
+use motion_detect::email;
+#[cfg(all(feature = "uinput", target_os = "linux"))]
+use motion_detect::uinput;
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+use motion_detect::gpio;
+#[cfg(feature = "gpu")]
+use motion_detect::gpu;
+#[cfg(feature = "mdns")]
+use motion_detect::mdns;
+#[cfg(feature = "zmq")]
+use motion_detect::zmq;
 
-fn main() -> Result<(), Box<dyn Error>> {
+
+// Baseline captures repeated while the scene keeps changing, before starting anyway.
+const BASELINE_ATTEMPTS: u32 = 10;
+
+// Processed frame intervals of time asleep that make a suspend, as opposed to a slow frame.
+const SUSPEND_FRAMES: u32 = 5;
+
+
+fn main() {
+    if let Err(err) = run() {
+        // As returning it from main would print it.
+        eprintln!("Error: {err:?}");
+        exit_report::message(&format!("Error, {err}"));
+        exit_report::exit(ExitReason::Error, 1);
+    }
+}
+
+
+fn run() -> Result<(), Box<dyn Error>> {
 
     // Settings
-    let camera_warm_up = Duration::from_secs(2);
-    let motion_tail_length = Duration::from_secs(1);
-    let frame_capture_interval = Duration::from_secs_f32(0.2); 
-    
-    let capture_width = 640;
-    let capture_height = 480;
-    let downsample:usize = 8;
-
-    let pixel_threshold = 10.0;   // The percentage a pixel must change for it to count as an actual change.
-    let image_threshold = 20.0;   // The percentage of pixels in an image needed to change to to trigger movement detection.
-
-    // Create a context
-    let ctx = PlatformContext::default();
-
-    // Query for available devices.
-    let devices = ctx.devices()?;
-    if devices.is_empty() {
-        println!("\nError, no device detected.");
-        std::process::exit(19); // No such device
-    }
-    println!("Available devices:");
-    for dev in &devices {
-        println!("    {:?}", dev);
-    }
-    
-    // Query for available streams and choose the first index with available streams.
-    let mut device_index = 0;
-    for (n,device) in devices.iter().enumerate() {
-        device_index = n;
-        let candidate = ctx.open_device(&device.uri)?;
-        if !candidate.streams()?.is_empty(){
-            println!("Detected video stream on device {n}:");
-            break;
-        } else {
-            println!("Device {n} has no video streams. Checking next one...");
-        }
+    let mut settings = Settings::from_args().unwrap_or_else(|err| {
+        println!("\nError, {err}");
+        std::process::exit(22); // Invalid argument
+    });
+    // From here on every exit is reported, with --exit-report.
+    if let Some(path) = &settings.exit_report {
+        exit_report::install(path.clone());
+    }
+    let code = match settings.command {
+        Command::SelfTest => Some(self_test::run(&settings)),
+        Command::Tune => Some(tune::run(&settings)),
+        Command::Batch => Some(batch::run(&settings)),
+        Command::Review => Some(review::run(&settings)),
+        Command::Extract => Some(extract::run(&settings)),
+        Command::LatencyTest => Some(latency_test::run(&settings)),
+        Command::Activity => Some(activity::run(&settings)),
+        Command::MakeMask => Some(make_mask(&settings)),
+        _ => None,
     };
+    if let Some(code) = code {
+        exit_report::exit(ExitReason::for_code(code), code);
+    }
+    let camera_warm_up = settings.camera_warm_up;
+    let motion_tail_length = settings.motion_tail_length;
+    let mut frame_capture_interval = settings.frame_capture_interval;
+    let max_event_duration = settings.max_event_duration;
+
+    let capture_width = settings.capture_width;
+    let capture_height = settings.capture_height;
+    let mut downsample = settings.downsample;
 
-    let device = ctx.open_device(&devices[device_index].uri)?;
-    let streams = device.streams()?;
-    if streams.is_empty() {
-        println!("\nWarning, no video streams detected.");
-        // std::process::exit(19); // No such device
-    }
-
-    // // TODO: Only pick a stream if it satisfies the required video specs (resolution, frame rate)
-    // let mut stream_desc = streams[0].clone();
-    // stream_desc.interval = frame_capture_interval;
-    // stream_desc.width = capture_width;
-    // stream_desc.height = capture_height;
-    // println!("    {:.1?}", stream_desc);
-    let pixfmt = if streams.is_empty(){
-        PixelFormat::Rgb(24)
-    } else {
-        streams[0].pixfmt.clone()
+    // Events go to stdout in the selected format, and to the optional HTTP server's WebSocket clients.
+    // --dump-config keeps stdout for the configuration alone.
+    let dump_config = settings.command == Command::DumpConfig;
+    let output = Output::new(if dump_config { Format::Json } else { settings.format })
+        .with_dedup(settings.log_dedup_window, settings.log_dedup_first)
+        .with_events(settings.event_output.clone(), settings.event_output_lossy)
+        .unwrap_or_else(|err| {
+            println!("\nError, can't open the event output: {err}");
+            exit_report::exit(ExitReason::Error, 5); // I/O error
+        });
+
+    // With socket activation, systemd owns the listening socket and --http isn't needed.
+    let mut listen_fds = ListenFds::take();
+    let http_listener = listen_fds.tcp_listener("http").unwrap_or_else(|err| {
+        output.info(&format!("\nError, {err}"));
+        exit_report::exit(ExitReason::Error, 22); // Invalid argument
+    });
+    for socket in listen_fds.unclaimed() {
+        output.info(&format!("Warning, ignoring passed socket {socket}, only \"http\" is used"));
+    }
+    let early_exit_conflicts = settings.early_exit_conflicts();
+    if settings.diff_early_exit && !early_exit_conflicts.is_empty() {
+        output.info(&format!(
+            "Warning, the diff compares every pixel, {} need{} the whole diff mask",
+            early_exit_conflicts.join(" and "), if early_exit_conflicts.len() == 1 { "s" } else { "" },
+        ));
+    }
+    for warning in settings.interval_warnings() {
+        output.info(&format!("Warning, {warning}"));
+    }
+    let http_server = match (http_listener, &settings.http_address, dump_config) {
+        (_, _, true) => None,
+        (Some(listener), _, false) => {
+            if let Ok(address) = listener.local_addr() {
+                settings.http_address = Some(address.to_string());
+            }
+            Some(http::HttpServer::with_listener(listener)?)
+        }
+        (None, Some(address), false) => Some(http::HttpServer::start(address)?),
+        (None, None, false) => None,
+    };
+    let (control_sender, control_requests) = control::channel(settings.control_bounds);
+    if let (Some(token), false) = (&settings.http_token, dump_config) {
+        match &http_server {
+            Some(server) => server.enable_control(token.clone(), control_sender.clone()),
+            None => {
+                output.info("\nError, --http-token requires --http");
+                exit_report::exit(ExitReason::Error, 22); // Invalid argument
+            }
+        }
+    }
+    if let (Some(_), false) = (settings.publish_mask, dump_config) {
+        match &http_server {
+            Some(server) => server.enable_masks(),
+            None => {
+                output.info("\nError, --publish-mask requires --http");
+                exit_report::exit(ExitReason::Error, 22); // Invalid argument
+            }
+        }
+    }
+    if settings.mdns && http_server.is_none() && !dump_config {
+        output.info("\nError, --mdns requires --http");
+        exit_report::exit(ExitReason::Error, 22); // Invalid argument
+    }
+    // Optional ZeroMQ publisher, for every message the WebSocket clients get.
+    #[cfg(feature = "zmq")]
+    let zmq_publisher = settings.zmq_pub.as_ref().filter(|_| !dump_config).map(|endpoint| {
+        zmq::Publisher::bind(endpoint, settings.name.as_deref().unwrap_or("default"), settings.zmq_hwm).unwrap_or_else(|err| {
+            output.info(&format!("\nError, {err}"));
+            exit_report::exit(ExitReason::Error, 98); // Address in use
+        })
+    });
+    // Every message goes out through the bus, each output taking what's for it on a thread of its own.
+    if let Some(server) = &http_server {
+        output.bus().register("http", server.clone(), true);
+    }
+    #[cfg(feature = "zmq")]
+    if let Some(publisher) = &zmq_publisher {
+        output.bus().register("zmq", publisher.clone(), true);
+    }
+    let announce = |message: Lifecycle| {
+        if !dump_config {
+            output.lifecycle(&message);
+        }
+    };
+    announce(Lifecycle::Starting);
+    // Each step towards ready is published and shown at /status, which reports the startup until then.
+    if let Some(server) = &http_server {
+        server.status().startup = Some(Progress::new(Phase::Starting, ""));
+    }
+    let progress = |progress: Progress| {
+        if let Some(server) = &http_server {
+            server.status().startup = Some(progress.clone());
+        }
+        announce(Lifecycle::Startup { progress });
     };
 
-    let stream_desc = Descriptor{
-        width: capture_width,
-        height: capture_height,
-        interval: frame_capture_interval,
-        pixfmt,
+    // Open the frame source: the first camera with video streams, raw video piped to stdin,
+    // or a network camera decoded by ffmpeg.
+    let ctx;
+    let (mut source, device_description, stream_desc): (Box<dyn FrameSource>, Description, Descriptor) = match &settings.input {
+        Input::Camera => {
+            ctx = PlatformContext::default();
+            // Probing every stream takes seconds on some backends, the cache skips it when it can.
+            let cache = (!settings.no_probe_cache).then(probe_cache::default_path).flatten();
+            let mut camera = match Camera::open_cached(&ctx, capture_width, capture_height, frame_capture_interval, cache.as_deref(), &output, &progress) {
+                Ok(camera) => camera,
+                Err(OpenError::NoDevice) => {
+                    output.info("\nError, no device detected.");
+                    exit_report::exit(ExitReason::Error, 19); // No such device
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if let Some(layout) = settings.force_input_layout {
+                camera.force_layout(layout);
+            }
+            if settings.exposure_jump.is_some() {
+                camera.read_metadata();
+            }
+            // A rotation starts on its first stream, and every one of them has to be there.
+            let uris = camera.device_uris();
+            if let Some(missing) = settings.stream_rotation.iter().find(|stream| !uris.contains(&stream.uri)) {
+                output.info(&format!("\nError, no device {} for stream {}", missing.uri, missing.name));
+                exit_report::exit(ExitReason::Error, 19); // No such device
+            }
+            if let Some(first) = settings.stream_rotation.first().filter(|first| first.uri != camera.description.uri) {
+                if let Err(err) = camera.switch(&first.uri) {
+                    output.info(&format!("\nError, can't open stream {}: {err}", first.name));
+                    exit_report::exit(ExitReason::Error, 19); // No such device
+                }
+            }
+            let (description, descriptor) = (camera.description.clone(), camera.descriptor.clone());
+            (Box::new(camera), description, descriptor)
+        }
+        Input::Stdin => {
+            let format = settings.input_format;
+            progress(Progress::new(Phase::Probing, "stdin"));
+            progress(Progress::new(Phase::Negotiating, format.pixel_format().to_string()));
+            let source = RawVideoSource::new(std::io::stdin().lock(), format, capture_width as usize, capture_height as usize);
+            let description = Description { uri: String::from("stdin"), product: format!("{} raw video", format.name()) };
+            let descriptor = Descriptor {
+                width: capture_width,
+                height: capture_height,
+                interval: frame_capture_interval,
+                pixfmt: format.pixel_format(),
+            };
+            (Box::new(source), description, descriptor)
+        }
+        Input::Url(url) => {
+            progress(Progress::new(Phase::Probing, ffmpeg::redact_url(url)));
+            progress(Progress::new(Phase::Negotiating, eye::hal::format::PixelFormat::Rgb(24).to_string()));
+            let source = FfmpegSource::start(
+                &settings.ffmpeg,
+                &settings.ffmpeg_args,
+                url,
+                capture_width as usize,
+                capture_height as usize,
+                frame_capture_interval,
+                settings.input_watchdog,
+            ).unwrap_or_else(|err| {
+                output.info(&format!("\nError, {err}"));
+                exit_report::exit(ExitReason::Error, 19); // No such device
+            });
+            let description = Description { uri: ffmpeg::redact_url(url), product: String::from("ffmpeg") };
+            let descriptor = Descriptor {
+                width: capture_width,
+                height: capture_height,
+                interval: frame_capture_interval,
+                pixfmt: eye::hal::format::PixelFormat::Rgb(24),
+            };
+            (Box::new(source), description, descriptor)
+        }
     };
+    let mut source_info = SourceInfo::new(&device_description, &stream_desc, settings.name.as_deref(), frame_capture_interval);
+    announce(Lifecycle::DeviceSelected { source: source_info.clone() });
+    if let Some(server) = &http_server {
+        server.status().source = Some(source_info.clone());
+    }
+    // Optional mDNS announcement of the HTTP server, named once the device is known. Without it
+    // detection goes on, only unannounced.
+    #[cfg(feature = "mdns")]
+    let announcer = http_server.as_ref().filter(|_| settings.mdns).and_then(|server| {
+        let service = mdns::Service::new(&source_info.name, server.local_addr(), settings.http_token.is_some(), settings.publish_mask.is_some());
+        mdns::Announcer::start(service)
+            .map_err(|err| output.info(&format!("Warning, no mDNS announcements: {err}")))
+            .ok()
+    });
 
-    // Since we want to capture images, we need to access the native image stream of the device.
-    // The backend will internally select a suitable implementation for the platform stream. On
-    // Linux for example, most devices support memory-mapped buffers.
-    let mut stream = device.start_stream(&stream_desc)?;
-    
-    // Convert pixel_threshold from a percentage to an integer amount with a max value of 255
-    let pixel_threshold = ((pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let (mut pixel_threshold, mut image_threshold, mut sustain_threshold) =
+        thresholds(settings.pixel_threshold, settings.image_threshold, settings.sustain_threshold());
 
-    // Normalize and clamp image threshold from its original percentage value
-    let image_threshold = (image_threshold / 100.0f32).clamp(0.0, 1.0); 
+    // Frames are read in the layout of the stream's pixel format, followed across reconnects, as
+    // are the detectors built on the stream, while the rest of the pipeline keeps its first mode.
+    let stream_format = std::cell::RefCell::new(StreamFormat::new(&stream_desc, settings.force_input_layout));
+    let mut live_stream = stream_desc.clone();
+    let layout = stream_format.borrow().layout;
+    if layout.channels() == 1 {
+        output.info(&format!("Single channel pixel format {}, comparing luma only", stream_desc.pixfmt));
+    }
+    // Again with the size the source negotiated, before anything of that size is allocated.
+    let (frame_width, frame_height) = (stream_desc.width as usize, stream_desc.height as usize);
+    let frame_bytes = frame_width * frame_height * layout.bytes_per_pixel();
+    match Cost::estimate(&settings, frame_width, frame_height, frame_bytes, layout.channels(), stream_desc.interval).check(&settings) {
+        Ok(warnings) => for warning in warnings {
+            output.info(&format!("Warning, {warning}"));
+        },
+        Err(err) => {
+            output.info(&format!("\nError, {err}"));
+            exit_report::exit(ExitReason::Error, 22); // Invalid argument
+        }
+    }
+
+    // Drivers may pick another mode than the one requested without saying so, the first frame
+    // tells. It's dropped, warm-up discards the ones after it anyway.
+    if let Some(requirement) = settings.resolution_requirement() {
+        let result = source.next_frame()
+            .and_then(|frame| frame.map(|frame| frame.len() / layout.bytes_per_pixel()).ok_or(String::from("the input ended before its first frame")))
+            .and_then(|pixels| requirement.check(&stream_desc, pixels, &source.modes()));
+        if let Err(err) = result {
+            output.info(&format!("\nError, {err}"));
+            let modes = source.modes();
+            if !modes.is_empty() {
+                output.info("Supported modes:");
+                for mode in &modes {
+                    output.info(&format!("    {} {}x{} {:.1?}", mode.pixfmt, mode.width, mode.height, mode.interval));
+                }
+            }
+            exit_report::exit(ExitReason::Error, 22); // Invalid argument
+        }
+    }
 
     // Thumbnail management.
-    let thumb_width = stream_desc.width as usize / downsample;
-    let thumb_height = stream_desc.height as usize / downsample;
-    let thumb_len = thumb_width * thumb_height;
-    let sample_count = downsample * downsample;
-    let pixel_count_threshold = (thumb_len as f32 * image_threshold) as i32;
-    let mut previous_thumb = 0;
-    let mut current_thumb = 1;
-    let mut thumbs = [                              // Two thumbnail buffers, previous and current.
-        vec![0; thumb_width * thumb_height * 3],    // 3 bytes per pixel (RGB).
-        vec![0; thumb_width * thumb_height * 3],
-    ];
-    
+    let (mut thumb, mut strategy, pixel_count_threshold, sustain_count_threshold) =
+        detector(&settings, &stream_desc, downsample, pixel_threshold, image_threshold, sustain_threshold);
+
+    // Report what actually took effect, or only print it with --dump-config.
+    let mut effective_config = EffectiveConfig {
+        settings: &settings,
+        device: &device_description,
+        stream: &stream_desc,
+        thumb_width: thumb.width,
+        thumb_height: thumb.height,
+        channels: thumb.channels,
+        downsample,
+        capture_interval: frame_capture_interval,
+        pixel_percent: settings.pixel_threshold,
+        image_percent: settings.image_threshold,
+        sustain_percent: settings.sustain_threshold(),
+        pixel_threshold,
+        start_pixels: pixel_count_threshold,
+        sustain_pixels: sustain_count_threshold,
+    };
+    if dump_config {
+        println!("{}", effective_config.to_json());
+        exit_report::write(ExitReason::Completed, 0);
+        return Ok(());
+    }
+    output.config(&effective_config);
+    exit_report::config(effective_config.to_object());
+    if let Some(server) = &http_server {
+        server.set_config(effective_config.to_json());
+    }
+    let mut averager = TemporalAverage::new(settings.temporal_average);
+
+    // Experimental GPU downsampling, the CPU takes over whenever it isn't available.
+    #[cfg(feature = "gpu")]
+    let gpu = std::cell::RefCell::new(match gpu::GpuDownsampler::new() {
+        // The shader reads the frame in the thumbnail's layout.
+        Ok(_) if !matches!(layout, motion_detect::thumbnail::PixelLayout::Rgb | motion_detect::thumbnail::PixelLayout::Gray) => {
+            output.info(&format!("Downsampling {} frames on the CPU", layout.name()));
+            None
+        }
+        // Nor does it keep the extremes of each block.
+        Ok(_) if settings.thumb_stats == motion_detect::thumbnail::ThumbStats::MeanMinMax => {
+            output.info("Downsampling on the CPU for --thumb-stats mean-minmax");
+            None
+        }
+        Ok(gpu) => {
+            output.info(&format!("Downsampling on GPU {}", gpu.adapter_name()));
+            Some(gpu)
+        }
+        Err(err) => {
+            output.info(&format!("Warning, {err}, downsampling on the CPU"));
+            None
+        }
+    });
+
+    // With --deinterlace, frames are made progressive in a copy of their own before anything else.
+    let progressive = std::cell::RefCell::new(Vec::new());
+
     // Function (OK, closure) to capture single frame and resize it to a thumbnail size,
-    // stored in the "thumb" byte buffer passed as an argument.
-    let mut update_thumbnail = |thumb:&mut Vec<u8>| {
-        let frame = stream
-            .next()
-            .expect("Stream is dead")               // Unwraps result.
-            .expect("Failed to capture frame");     // Unwraps option.
-    
-        let mut source_x = 0;
-        let mut source_y = 0;
-        while source_y < stream_desc.height as usize {
-            while source_x < stream_desc.width as usize {
-                let mut resized_pixel:[u32; 3] = [0, 0, 0];
-                for y in 0 .. downsample {
-                    for x in 0 .. downsample {
-                        let sub_pixel_index = (((source_y + y) * stream_desc.width as usize) + (source_x + x)) * 3;
-                        // Accumulate RGB values
-                        resized_pixel[0] += frame[sub_pixel_index] as u32;
-                        resized_pixel[1] += frame[sub_pixel_index+1] as u32;
-                        resized_pixel[2] += frame[sub_pixel_index+2] as u32;
-                    }
-                }
-
-                let dest_index = (((source_y / downsample) * thumb_width) + (source_x / downsample)) * 3;
-
-                // Averages RGB value and assigns it to thumbnail
-                thumb[dest_index] = (resized_pixel[0] as usize / sample_count).clamp(0, 255) as u8;
-                thumb[dest_index+1] = (resized_pixel[1] as usize / sample_count).clamp(0, 255) as u8;
-                thumb[dest_index+2] = (resized_pixel[2] as usize / sample_count).clamp(0, 255) as u8;
-
-                source_x += downsample; // We increment by as many pixels as "downsample" instead of just 1.
-            }
-            source_x = 0;
-            source_y += downsample;
+    // stored in the thumbnail passed as an argument. Returns when the frame was captured,
+    // or None once the input ended. Fails with a reason if the camera stopped delivering frames.
+    // With snapshots, a copy of the full frame is kept as well. Both steps are timed. The
+    // `skip` frames before it are only taken from the source, numbered and dropped.
+    let update_thumbnail = |source:&mut dyn FrameSource, thumb:&mut Thumbnail, downsample:usize, snapshots:Option<&mut Snapshots>, timing:&mut PipelineTiming, counter:&mut FrameCounter, skip:u32| -> Result<Option<FrameTime>, String> {
+        for _ in 0 .. skip {
+            if !source.skip_frame()? {
+                return Ok(None);
+            }
+            counter.decimated(Instant::now());
         }
+        let capture_start = Instant::now();
+        let Some(frame) = source.next_frame()? else {
+            return Ok(None); // End of input.
+        };
+        let frame_time = FrameTime::arrival(counter);
+        timing.record(Stage::Capture, frame_time.instant.saturating_duration_since(capture_start));
+        let layout = stream_format.borrow().layout;
+        let mut progressive = progressive.borrow_mut();
+        let frame = match settings.deinterlace {
+            Some(mode) => {
+                mode.apply(frame, stream_desc.width as usize * layout.bytes_per_pixel(), &mut progressive);
+                progressive.as_slice()
+            }
+            None => frame,
+        };
+        #[cfg(feature = "gpu")]
+        let mut gpu = gpu.borrow_mut();
+        #[cfg(feature = "gpu")]
+        let on_gpu = match gpu.as_mut() {
+            Some(gpu) => gpu.downsample(thumb, frame, stream_desc.width as usize, downsample)
+                .map_err(|err| output.info(&format!("Warning, {err}, downsampling on the CPU from now on")))
+                .is_ok(),
+            None => false,
+        };
+        #[cfg(feature = "gpu")]
+        if !on_gpu {
+            *gpu = None;
+            thumb.downsample(frame, stream_desc.width as usize, downsample, layout);
+        }
+        #[cfg(not(feature = "gpu"))]
+        thumb.downsample(frame, stream_desc.width as usize, downsample, layout);
+        if let Some(snapshots) = snapshots {
+            snapshots.capture(frame, layout);
+        }
+        timing.record(Stage::Downsample, frame_time.instant.elapsed());
+        Ok(Some(frame_time))
     };
 
-    // Time keeping
+    // Time keeping. Events are timed by frame capture rather than processing, and converted to
+    // wall-clock time for output. The system clock may step, e.g. when NTP syncs, or move on while
+    // the system is suspended, see WallClock.
+    let mut wall_clock = WallClock::new().with_step_threshold(settings.clock_step_threshold);
+    let mut suspend_watch = SuspendWatch::new();
     let app_time = std::time::Instant::now();
     let mut last_frame_time = app_time;
-    let mut latest_movement_time:Option<Instant> = None;
+    // With decimation the source sets the pace, frames are processed its interval times the
+    // decimation apart rather than a capture interval.
+    let mut decimator = settings.decimation.map(Decimator::new);
+    let processed_interval = |decimator: &Option<Decimator>, capture_interval: Duration| match decimator {
+        Some(decimator) => stream_desc.interval * decimator.every() * settings.temporal_average as u32,
+        None => capture_interval * settings.temporal_average as u32,
+    };
+    let mut motion = MotionTracker::new(motion_tail_length, pixel_count_threshold, sustain_count_threshold)
+        .with_tail_frames(settings.tail_frames)
+        .with_max_duration(max_event_duration)
+        .with_confirm_frames(settings.confirm_frames)
+        .with_frame_interval(processed_interval(&decimator, frame_capture_interval));
+    let mut variant_b = settings.ab_variant.as_deref().map(|variant| {
+        output.info("A/B comparison, variant b only prints and sends its events");
+        VariantB::new(variant, &stream_desc, downsample, processed_interval(&decimator, frame_capture_interval), settings.ab_report)
+    });
 
-    // Wait for camera warm up (avoids black frames and false motion positives)
-    println!("warming up");
-    std::thread::sleep(camera_warm_up);
+    // Per-stage timing, optionally printed for every frame or reported periodically.
+    let mut timing = PipelineTiming::default();
+    let mut next_timing_report = settings.timing_report.map(|every| Instant::now() + every);
+
+    // Frames are numbered as they are captured, frames that never were leave gaps. The source
+    // can't deliver faster than its own interval.
+    let mut frame_counter = FrameCounter::new(match decimator {
+        Some(_) => stream_desc.interval,
+        None => frame_capture_interval.max(stream_desc.interval),
+    });
+
+    // Optional adaptive processing budget.
+    let mut cpu_budget = settings.cpu_budget.map(|budget| CpuBudget::new(budget, frame_capture_interval, downsample));
+
+    // Optional virtual input device. Failing to create it is reported, but doesn't stop detection.
+    #[cfg(all(feature = "uinput", target_os = "linux"))]
+    let mut input_device = settings.uinput.and_then(|event| {
+        uinput::VirtualDevice::create(event)
+            .map_err(|err| output.info(&format!("Warning, no virtual input device: {err}")))
+            .ok()
+    });
+
+    // Optional GPIO output. Like the input device, a failure is reported and detection continues.
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    let mut gpio_output = settings.gpio_pin.and_then(|pin| {
+        gpio::GpioOutput::open(&settings.gpio_chip, pin, settings.gpio_active_high, settings.gpio_hold)
+            .map_err(|err| output.info(&format!("Warning, no GPIO output: {err}")))
+            .ok()
+    });
+
+    // Optional commands run on motion events. Like the other outputs, failing ones are reported.
+    let hooks = Hooks::new(
+        settings.on_start.clone(),
+        settings.on_stop.clone(),
+        settings.on_provisional.clone(),
+        settings.on_provisional_cancel.clone(),
+        settings.provisional_cooldown,
+    );
+    if !hooks.is_empty() {
+        output.bus().register("hooks", hooks, true);
+    }
 
-    // Init thumbnails
-    update_thumbnail(&mut thumbs[previous_thumb]);
-    update_thumbnail(&mut thumbs[current_thumb]);
+    // Optional bell and sounds, played off the detection thread like the commands.
+    let alert = Alert::new(
+        settings.bell,
+        settings.play_sound.clone(),
+        settings.stop_sound.clone(),
+        settings.sound_player.as_deref(),
+        settings.alert_cooldown,
+    );
+    if alert.missing_player() {
+        output.info("Warning, no sound player found, ringing the terminal bell instead");
+    }
+    if !alert.is_empty() {
+        output.bus().register("alert", alert, true);
+    }
+
+    // Optional zones, followed by the centroid of the active movement.
+    let mut zone_tracker = (!settings.zones.is_empty())
+        .then(|| ZoneTracker::new(settings.zones.clone(), settings.zone_debounce));
+    // Their pixels on the thumbnail grid, rasterized again whenever the thumbnail size changes.
+    let mut zone_masks = ZoneMasks::default();
+    zone_masks.fit(&settings.zones, thumb.width, thumb.height);
+    // And whether each is armed, on its schedule or by command.
+    let mut zone_arming = ZoneArming::new(&settings.zones, SystemTime::now());
+
+    // Optional snapshots of the full frame and of the zones that ask for one.
+    let snapshot_zones = settings.zones.iter().any(|zone| zone.snapshot.is_some());
+    // Timelapse frames and those to verify go through the same writer.
+    let snapshots_on_start = settings.snapshot_dir.is_some() || snapshot_zones;
+    let mut snapshots = (snapshots_on_start || settings.timelapse_dir.is_some() || settings.verify_command.is_some()).then(|| Snapshots::new(
+        settings.snapshot_dir.clone(),
+        &settings.snapshot_template,
+        !settings.snapshot_zones_only,
+        stream_desc.width as usize,
+        stream_desc.height as usize,
+        layout.channels(),
+    )
+    .with_overlay(settings.overlay, &source_info.name)
+    .with_privacy(settings.snapshot_privacy, settings.snapshot_privacy_margin));
+    // Written on a thread of their own, frames to verify are saved right away.
+    let media = (snapshots_on_start || settings.timelapse_dir.is_some())
+        .then(|| MediaQueue::new(settings.media_queue, settings.media_overflow, settings.media_wait));
+    // The sidecars of the snapshots of active movements, written again once they stop.
+    let mut sidecars: HashMap<u64, (Vec<MediaFile>, Sidecar)> = HashMap::new();
+
+    // Optional external verification of each movement, on the frame its first event comes with.
+    let mut verifier = settings.verify_command.clone()
+        .map(|command| Verifier::new(command, settings.verify_timeout, settings.verify_timeout_action));
+
+    // Optional thumbnails of each start, to review false positives with.
+    let mut context = settings.context_dir.clone().map(ContextCapture::new);
+
+    // Optional fixed reference of the scene, compared every so often.
+    let mut scene = settings.reference_file.clone().map(|path| {
+        let (scene, warning) = SceneReference::new(
+            path,
+            settings.reference_pixel_threshold,
+            settings.reference_image_threshold,
+            settings.reference_check_interval,
+            settings.mask.clone(),
+        );
+        if let Some(warning) = warning {
+            output.info(&format!("Warning, {warning}"));
+        }
+        scene
+    });
+    let mut take_reference = false;
+
+    // Optional timelapse.
+    let mut timelapse = settings.timelapse_dir.clone().map(|directory| Timelapse::new(
+        directory,
+        settings.timelapse_idle_interval,
+        settings.timelapse_active_interval,
+        settings.timelapse_daily,
+    ));
+
+    // Optional desktop notifications, named after the instance.
+    #[cfg(feature = "desktop-notify")]
+    let mut notifier = settings.notify.then(|| {
+        notify::DesktopNotifier::new(source_info.name.clone(), settings.notify_cooldown, settings.notify_stop)
+    });
+
+    // Optional emails, also named after the instance.
+    #[cfg(feature = "smtp")]
+    let mut mailer = settings.smtp_server.is_some().then(|| {
+        email::Mailer::new(&settings, source_info.name.clone()).unwrap_or_else(|err| {
+            output.info(&format!("\nError, {err}"));
+            exit_report::exit(ExitReason::Error, 22); // Invalid argument
+        })
+    });
+
+    // Restore what a previous run learned, as long as it used the same capture configuration.
+    // References are saved in the color space they were compared in.
+    let mut algorithm_id = format!("{} blur={}", settings.algorithm, settings.blur);
+    if settings.channels != Channels::Rgb {
+        algorithm_id.push_str(&format!(" channels={}", settings.channels.name()));
+    }
+    let mut restored = None;
+    let mut held = Vec::new();
+    // Motion per weekday and hour, over restarts with --state-file.
+    let mut hourly = ActivityHistogram::new(SystemTime::now());
+    // With --stream-rotation, the detectors of the streams not being read wait here for their turn.
+    let mut rotation = (!settings.stream_rotation.is_empty())
+        .then(|| StreamRotation::new(settings.stream_rotation.clone(), settings.stream_dwell, Instant::now()));
+    let mut parked: Vec<Option<Box<dyn DiffStrategy>>> = settings.stream_rotation.iter().map(|_| None).collect();
+    // The scenes saved by scene save, each kept as long as its thumbnails are the size of these.
+    let mut scene_profiles = SceneProfiles::new(settings.expect_scene_change.then_some(settings.scene_settle));
+    if let (Some(path), false) = (&settings.state_file, settings.reset_state) {
+        if path.exists() {
+            match SavedState::load(path) {
+                // The movements held for a digest don't depend on the capture configuration.
+                Ok(mut state) => {
+                    held = std::mem::take(&mut state.held);
+                    if !settings.activity_reset {
+                        hourly = state.activity.clone();
+                    }
+                    if state.algorithm == algorithm_id {
+                        for reason in scene_profiles.restore(std::mem::take(&mut state.scenes), &thumb) {
+                            output.info(&format!("Warning, dropping a scene from {}: {reason}", path.display()));
+                        }
+                    }
+                    if state.matches(stream_desc.width, stream_desc.height, downsample as u32, &algorithm_id) {
+                        output.info(&format!("Restored state from {}", path.display()));
+                        restored = Some(state);
+                    } else {
+                        output.info(&format!("Warning, ignoring state file {}: saved with a different capture configuration", path.display()));
+                    }
+                }
+                Err(err) => output.info(&format!("Warning, ignoring state file: {err}")),
+            }
+        }
+    }
+    // With --quiet-hours, the notification outputs get the movements of the quiet hours after them.
+    if !held.is_empty() && settings.quiet_hours.is_none() {
+        output.info(&format!("Warning, dropping {} movements held for a digest, there are no --quiet-hours anymore", held.len()));
+    }
+    let mut holdover = settings.quiet_hours.clone().map(|hours| Holdover::new(hours, held));
+    // What every event is deduped on downstream, the sequence going on from the last run.
+    let mut identity = match settings.state_dir.as_deref().map(Identity::open) {
+        Some(Ok(identity)) => identity,
+        Some(Err(err)) => {
+            output.info(&format!("Warning, {err}, the installation id and event sequence are only this run's"));
+            Identity::ephemeral()
+        }
+        None => {
+            output.info("Warning, no --state-dir nor home directory, the installation id and event sequence are only this run's");
+            Identity::ephemeral()
+        }
+    };
+    if let Some(server) = &http_server {
+        server.set_activity(hourly.to_json(SystemTime::now()));
+        // Known by now, /status shows them while warming up.
+        let mut status = server.status();
+        (status.capture_interval, status.downsample) = (frame_capture_interval, downsample);
+    }
+
+    // Wait for camera warm up (avoids black frames and false motion positives).
+    // With a restored reference the camera only needs to settle, not provide a fresh reference.
+    let warm_up = if restored.is_some() { camera_warm_up / 4 } else { camera_warm_up };
+    match settings.warm_up_frames {
+        Some(frames) => {
+            let frames = if restored.is_some() { frames.div_ceil(4) } else { frames };
+            announce(Lifecycle::WarmupBegin { duration: frame_capture_interval * frames });
+            warm_up_frames(source.as_mut(), frames, frame_capture_interval, &progress);
+        }
+        None => {
+            announce(Lifecycle::WarmupBegin { duration: warm_up });
+            // Reported every second, slow cameras can take a while.
+            let warm_up_begin = Instant::now();
+            loop {
+                let elapsed = warm_up_begin.elapsed();
+                progress(Progress::new(Phase::WarmingUp, "").with_percent(elapsed.as_secs_f64(), warm_up.as_secs_f64()));
+                if elapsed >= warm_up {
+                    break;
+                }
+                std::thread::sleep((warm_up - elapsed).min(Duration::from_secs(1)));
+            }
+        }
+    }
+
+    // Init reference thumbnail
+    match restored {
+        Some(mut state) => {
+            progress(Progress::new(Phase::Baseline, "restored"));
+            // Each stream of a rotation gets back its own, the first one is read first.
+            for stream in std::mem::take(&mut state.streams) {
+                match settings.stream_rotation.iter().position(|rotating| rotating.name == stream.name) {
+                    Some(0) => (state.reference, state.noise_map) = (stream.reference, stream.noise_map),
+                    Some(index) => {
+                        let (_, mut parked_strategy, _, _) =
+                            detector(&settings, &live_stream, downsample, pixel_threshold, image_threshold, sustain_threshold);
+                        parked_strategy.set_reference(stream.reference);
+                        if let Some(noise_map) = stream.noise_map {
+                            parked_strategy.set_noise_map(noise_map);
+                        }
+                        parked[index] = Some(parked_strategy);
+                    }
+                    None => {}
+                }
+            }
+            strategy.set_reference(state.reference);
+            if let Some(noise_map) = state.noise_map {
+                strategy.set_noise_map(noise_map);
+            }
+        }
+        // Two baseline thumbnails that differ as much as a movement mean something moved during
+        // the capture, and the reference would hold it: capture them again, for a while.
+        None => {
+            let mut baseline: Option<Thumbnail> = None;
+            let mut attempts = 0;
+            // Masked pixels moving is what the mask is for, and flickering ones are ignored once
+            // there were enough frames to tell them. Compared the way frames will be.
+            let mut check = diff::from_name("frame-diff", pixel_threshold, i32::MAX, settings.adaptive_threshold(), 0, settings.channels)
+                .expect("frame-diff is a strategy");
+            let mut learning = 0;
+            if settings.flicker_rejection {
+                check = Box::new(FlickerRejection::new(check, pixel_threshold));
+                learning = diff::FLICKER_STEPS + 1;
+            }
+            if let Some(mask) = &settings.mask {
+                check = Box::new(Masked::new(check, mask.clone()));
+            }
+            loop {
+                let frame_time = update_thumbnail(source.as_mut(), &mut thumb, downsample, snapshots.as_mut(), &mut timing, &mut frame_counter, 0)
+                    .and_then(|frame_time| frame_time.ok_or_else(|| "input ended before the first frame".to_string()))
+                    .unwrap_or_else(|reason| {
+                        announce(Lifecycle::CameraLost { reason });
+                        exit_report::exit(ExitReason::CameraLost, 5); // I/O error
+                    });
+                let Some((averaged, _)) = averager.push(&thumb, frame_time.instant) else {
+                    continue;
+                };
+                match &baseline {
+                    Some(previous) if learning == 0 => check.set_reference(previous.clone()),
+                    _ => {
+                        check.process(averaged);
+                        learning = learning.saturating_sub(1);
+                        baseline = Some(averaged.clone());
+                        if settings.flicker_rejection {
+                            let steps = diff::FLICKER_STEPS + 1;
+                            progress(Progress::new(Phase::Calibrating, "flicker").with_percent((steps - learning) as f64, steps as f64));
+                        }
+                        continue;
+                    }
+                }
+                progress(Progress::new(Phase::Baseline, format!("attempt {}", attempts + 1)));
+                let changed = check.process(averaged);
+                if changed.changed_pixels <= pixel_count_threshold {
+                    strategy.process(averaged);
+                    break;
+                }
+                if attempts == BASELINE_ATTEMPTS {
+                    output.info("Warning, the scene kept changing while capturing the baseline, starting anyway");
+                    strategy.process(averaged);
+                    break;
+                }
+                attempts += 1;
+                output.info(&format!("Warning, {:.1}% of the baseline changed while capturing it, capturing it again", changed.score));
+                baseline = Some(averaged.clone());
+            }
+        }
+    }
 
     // // Debug save image. Optional! Comment out if image crate is not available.
-    // let img = image::RgbImage::from_raw(thumb_width as u32, thumb_height as u32, thumbs[0].clone()).unwrap();
+    // let img = image::RgbImage::from_raw(thumb_width as u32, thumb_height as u32, thumb.pixels.clone()).unwrap();
     // if img.save("test.png").is_err(){
     //     println!("Error saving thumbnail image");
     // } else {
     //     println!("First thumbnail saved!");
     // };
 
-    // Loop forever until interrupted.
-    println!("ready");
-    loop {
+    // Loop until interrupted.
+    signals::install_shutdown_handler();
+    signals::install_pause_handler();
+    if let Some(server) = &http_server {
+        server.status().startup = None;
+    }
+    announce(Lifecycle::Ready);
+    let ready_at = Instant::now();
+    motion.ready(ready_at);
+    if let Some(variant_b) = &mut variant_b {
+        variant_b.motion.ready(Instant::now());
+    }
+    let mut idle = (!settings.idle_after.is_empty())
+        .then(|| IdleTimer::new(settings.idle_after.clone(), Instant::now(), settings.assume_idle_at_start));
+    let send_idle = |event: IdleEvent, time: SystemTime, source| {
+        let idle_json = event.to_json(time, source);
+        output.event(&event.text(), &idle_json);
+    };
+    let mut activity = settings.print_interval.map(|every| {
+        ActivitySummary::new(every, ready_at, wall_clock.to_system(ready_at), frame_counter.total_dropped())
+    });
+    let send_activity = |report: ActivityReport, time: SystemTime| {
+        let report_json = report.to_json(time);
+        output.event(&report.text(), &report_json);
+    };
+    let mut camera_lost = false;
+    let mut events_lost = false;
+    let mut gave_up = false;
+    let (mut out_of_memory, mut over_memory) = (false, false);
+    let mut panics = PanicSupervisor::new(settings.panic_restart, settings.panic_exit, settings.panic_window);
+    let mut end_of_input = false;
+    let mut reconnected = false;
+    let (mut paused, mut released) = (false, false);
+    // What control requests change, the detector follows it below. Changing thresholds or
+    // resuming starts over from a fresh baseline.
+    let mut control_state = ControlState::new(&settings);
+    let mut rebaseline = false;
+    // Whether what changed during a pause or a lost camera is reported once detection is back.
+    let mut resume_guard = ResumeGuard::new(settings.resume_policy);
+    // A movement in progress when a scene is activated ends with it.
+    let mut scene_changed = false;
+    // A threshold change followed by a storm of movements is taken back.
+    let mut storm_guard = settings.storm_movements.map(|movements| StormGuard::new(movements, settings.storm_window));
+    // The latest movement and whether it's reported: one that began while disarmed stays silent.
+    let mut latest_movement: Option<(u64, bool)> = None;
+    // With --report-padding, movements are also reported as clips, never before the detector was ready.
+    let mut clips = settings.report_padding.map(ClipPadding::new);
+    if let Some(server) = &http_server {
+        server.status().armed = true;
+    }
+    let mut last_mask: Option<Instant> = None;
+    // Black or white thumbnails, compared as --saturated-policy says.
+    let mut saturation = SaturationDetector::new(settings.saturated_floor, settings.saturated_ceiling);
+    let mut saturated_mask = Vec::new();
+    // Jumps of the exposure or gain the camera reports raise the pixel threshold for a moment.
+    let mut exposure = settings.exposure_jump.map(|jump| ExposureCompensation::new(jump as f64 / 100.0, settings.exposure_level()));
+    // learn-mask counts how often each pixel changes until its duration ran out.
+    let mut heatmap = (settings.command == Command::LearnMask).then(Heatmap::new);
+    let learn_until = Instant::now() + settings.learn_duration;
+    let (mut frames, mut movements) = (0u64, 0u64);
+    while !signals::shutdown_requested() {
+        // Dropping events silently would defeat the purpose, stop unless told otherwise.
+        if let Some(err) = output.event_error() {
+            output.info(&format!("\nError, event output failed: {err}"));
+            events_lost = true;
+            break;
+        }
+        output.expire_repeats();
+
+        // SIGUSR1 and SIGUSR2 are control requests like the others, applied in the order they came.
+        if let Some(pause) = signals::take_pause_request() {
+            rebaseline |= control_state.apply(&ControlCommand::pause(pause));
+        }
+        let mut activate_scene = None;
+        for request in control_requests.try_iter() {
+            let before = control_state;
+            rebaseline |= control_state.apply(&request.command);
+            let changes = control_state.changes(&before);
+            if let Some(storm_guard) = &mut storm_guard {
+                match request.command {
+                    ControlCommand::Revert => storm_guard.cancel(),
+                    _ if !changes.is_empty() => storm_guard.changed(before.thresholds(), Instant::now()),
+                    _ => {}
+                }
+            }
+            if !changes.is_empty() {
+                announce(Lifecycle::ThresholdsChanged { command: request.command.name(), changes });
+            }
+            if request.command == ControlCommand::SetReference {
+                match scene {
+                    Some(_) => take_reference = true,
+                    None => output.info("Warning, set-reference needs --reference-file"),
+                }
+            }
+            if let ControlCommand::SaveScene(scene) = &request.command {
+                let replaced = scene_profiles.profiles().iter().any(|profile| profile.name == *scene);
+                match scene_profiles.save(scene, strategy.as_ref()) {
+                    Ok(()) => announce(Lifecycle::SceneSaved { scene: scene.clone(), replaced }),
+                    Err(err) => output.info(&format!("Warning, {err}")),
+                }
+            }
+            // Activated once the thresholds of these requests applied, the last one asked for.
+            if let ControlCommand::ActivateScene(scene) = &request.command {
+                activate_scene = Some(scene.clone());
+            }
+            if request.command == ControlCommand::ResetActivity {
+                hourly.reset(SystemTime::now());
+                if let Some(server) = &http_server {
+                    server.set_activity(hourly.to_json(SystemTime::now()));
+                }
+            }
+            if let ControlCommand::ArmZone(zone) | ControlCommand::DisarmZone(zone) = &request.command {
+                match zone_arming.set(zone, matches!(request.command, ControlCommand::ArmZone(_)), SystemTime::now()) {
+                    Ok(Some(change)) => announce(Lifecycle::ZoneArming { change }),
+                    Ok(None) => {}
+                    Err(err) => output.info(&format!("Warning, can't arm or disarm, {err}")),
+                }
+            }
+            request.reply(control_state);
+        }
+        if let Some(server) = &http_server {
+            let mut status = server.status();
+            status.paused = control_state.paused;
+            status.armed = control_state.armed;
+            status.zones = zone_arming.to_objects(control_state.armed, SystemTime::now());
+        }
+
+        // Paused until resumed. With stream-off the source is released meanwhile and reopened on
+        // resuming, either way detection starts over from a fresh baseline.
+        if control_state.paused && !paused {
+            paused = true;
+            resume_guard.away(Instant::now());
+            announce(Lifecycle::Paused { mode: settings.pause_mode });
+            if settings.pause_mode == PauseMode::StreamOff {
+                match source.release() {
+                    Ok(()) => {
+                        released = true;
+                        announce(Lifecycle::StreamStopped);
+                    }
+                    Err(err) => output.info(&format!("Warning, {err}, it keeps running while paused")),
+                }
+            }
+        }
+        if !control_state.paused && paused {
+            let resume_start = Instant::now();
+            paused = false;
+            if std::mem::take(&mut released) {
+                // Restored once it delivers, the stream may take a while to start.
+                if let Err(reason) = source.reconnect().and_then(|()| source.next_frame().map(|_| ())) {
+                    announce(Lifecycle::CameraLost { reason });
+                    if !reconnect(source.as_mut(), &output) {
+                        camera_lost = true;
+                        break;
+                    }
+                }
+                announce(Lifecycle::StreamRestored { latency: resume_start.elapsed() });
+                reconnected = true;
+                match settings.warm_up_frames {
+                    Some(frames) => {
+                        announce(Lifecycle::WarmupBegin { duration: frame_capture_interval * frames });
+                        warm_up_frames(source.as_mut(), frames, frame_capture_interval, &|_| {});
+                    }
+                    None => {
+                        announce(Lifecycle::WarmupBegin { duration: camera_warm_up });
+                        std::thread::sleep(camera_warm_up);
+                    }
+                }
+            }
+            frame_counter.paused();
+            rebaseline |= resume_guard.back(ResumeCause::Resume, resume_start);
+            announce(Lifecycle::Resumed { latency: resume_start.elapsed() });
+        }
+        // A released source has nothing to compare yet, the baseline waits for the resume.
+        if rebaseline && !released {
+            rebaseline = false;
+            (pixel_threshold, image_threshold, sustain_threshold) =
+                thresholds(control_state.pixel_threshold, control_state.image_threshold, control_state.sustain_percent());
+            let (start_count, sustain_count);
+            (thumb, strategy, start_count, sustain_count) =
+                detector(&settings, &live_stream, downsample, pixel_threshold, image_threshold, sustain_threshold);
+            motion.set_thresholds(start_count, sustain_count);
+            if let Some(variant_b) = &mut variant_b {
+                variant_b.rebuild(&live_stream, downsample);
+            }
+            // The other streams of a rotation start over as well, on their next turn.
+            parked.iter_mut().for_each(|parked| *parked = None);
+            averager = TemporalAverage::new(settings.temporal_average);
+            if let Some(context) = &mut context {
+                context.reset();
+            }
+            if (control_state.pixel_threshold, control_state.image_threshold, control_state.sustain_percent())
+                != (effective_config.pixel_percent, effective_config.image_percent, effective_config.sustain_percent)
+            {
+                effective_config.pixel_percent = control_state.pixel_threshold;
+                effective_config.image_percent = control_state.image_threshold;
+                effective_config.sustain_percent = control_state.sustain_percent();
+                effective_config.pixel_threshold = pixel_threshold;
+                effective_config.start_pixels = start_count;
+                effective_config.sustain_pixels = sustain_count;
+                exit_report::config(effective_config.to_object());
+                if let Some(server) = &http_server {
+                    server.set_config(effective_config.to_json());
+                }
+                output.info(&format!(
+                    "Thresholds: pixel {}%, image {}%, sustain {}%",
+                    control_state.pixel_threshold, control_state.image_threshold, control_state.sustain_percent(),
+                ));
+            }
+        }
+        // An activated scene takes over from the baseline, there's nothing to warm up. What was in
+        // progress was on the scene before, it ends with the next frame compared.
+        if let Some(scene) = activate_scene {
+            let (activated_thumb, mut activated, _, _) =
+                detector(&settings, &live_stream, downsample, pixel_threshold, image_threshold, sustain_threshold);
+            match scene_profiles.activate(&scene, &activated_thumb, activated.as_mut(), Instant::now()) {
+                Ok(()) => {
+                    (thumb, strategy) = (activated_thumb, activated);
+                    if let Some(variant_b) = &mut variant_b {
+                        variant_b.rebuild(&live_stream, downsample);
+                    }
+                    averager = TemporalAverage::new(settings.temporal_average);
+                    if let Some(context) = &mut context {
+                        context.reset();
+                    }
+                    scene_changed = true;
+                    announce(Lifecycle::SceneActivated { scene, settle: scene_profiles.settle() });
+                }
+                Err(err) => output.info(&format!("Warning, can't activate, {err}")),
+            }
+        }
+        if released {
+            std::thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+
+        // With --stream-rotation, the next stream once this one had its dwell, but never in the
+        // middle of a movement. The detector of the stream left is parked until its next turn,
+        // the next one picks up where it left off, or starts with the first frame it compares.
+        if let Some(rotation) = rotation.as_mut().filter(|rotation| !paused && rotation.due(Instant::now(), motion.is_busy())) {
+            let next = rotation.upcoming().clone();
+            let description = match source.switch(&next.uri) {
+                Ok(description) => description,
+                Err(reason) => {
+                    announce(Lifecycle::CameraLost { reason });
+                    frame_counter.source_failed();
+                    if !reconnect(source.as_mut(), &output) {
+                        camera_lost = true;
+                        break;
+                    }
+                    announce(Lifecycle::CameraRecovered);
+                    Description { uri: next.uri.clone(), product: device_description.product.clone() }
+                }
+            };
+            let stream = source.stream().cloned().unwrap_or_else(|| stream_desc.clone());
+            let current = SourceInfo::new(&description, &stream, settings.name.as_deref(), frame_capture_interval);
+            if !current.same_mode(&source_info) {
+                output.info(&format!("\nError, stream {} came up as {}x{} {}, every stream of the rotation needs the mode of the first",
+                    next.name, current.width, current.height, current.pixel_format));
+                camera_lost = true;
+                break;
+            }
+            source_info = current;
+            if let Some(server) = &http_server {
+                server.status().source = Some(source_info.clone());
+            }
+            let (left, next_index) = (rotation.index(), (rotation.index() + 1) % parked.len());
+            let resumed = parked[next_index].take().unwrap_or_else(|| {
+                detector(&settings, &live_stream, downsample, pixel_threshold, image_threshold, sustain_threshold).1
+            });
+            parked[left] = Some(std::mem::replace(&mut strategy, resumed));
+            averager = TemporalAverage::new(settings.temporal_average);
+            if let Some(context) = &mut context {
+                context.reset();
+            }
+            // The time away isn't a loss.
+            frame_counter.paused();
+            announce(Lifecycle::StreamSwitched { stream: next.name.clone(), source: source_info.clone() });
+            announce(Lifecycle::WarmupBegin { duration: settings.stream_warm_up });
+            std::thread::sleep(settings.stream_warm_up);
+            rotation.advance(Instant::now());
+            // What moves on the new stream right away was there before it was looked at.
+            motion.ready(Instant::now());
+            last_frame_time = Instant::now();
+        }
+
+        // A source may come back from a reconnect in another mode, checked before its first frame
+        // is read. The pipeline is sized for the one it started with, a new size needs a restart.
+        // A new pixel format is followed: its frames are read in their own layout from now on, and
+        // the detectors start over from a fresh baseline, as what they learned doesn't apply.
+        if std::mem::take(&mut reconnected) {
+            if let Some(stream) = source.stream().cloned() {
+                let current = SourceInfo::new(&device_description, &stream, settings.name.as_deref(), frame_capture_interval);
+                if current != source_info {
+                    announce(Lifecycle::SourceChanged { source: current.clone() });
+                    if (current.width, current.height) != (source_info.width, source_info.height) {
+                        output.info(&format!("\nError, the source came back as {}x{} {}, detection needs a restart for it",
+                            current.width, current.height, current.pixel_format));
+                        camera_lost = true;
+                        break;
+                    }
+                    source_info = current;
+                    if let Some(server) = &http_server {
+                        server.status().source = Some(source_info.clone());
+                    }
+                }
+                let previous = stream_format.borrow_mut().follow(&stream, settings.force_input_layout);
+                if let Some(previous) = previous {
+                    let layout = stream_format.borrow().layout;
+                    announce(Lifecycle::FormatChanged {
+                        previous: previous.pixfmt.to_string(),
+                        pixel_format: stream.pixfmt.to_string(),
+                        layout,
+                    });
+                    live_stream = stream;
+                    if let Some(snapshots) = &mut snapshots {
+                        snapshots.set_channels(layout.channels());
+                    }
+                    #[cfg(feature = "gpu")]
+                    if !matches!(layout, motion_detect::thumbnail::PixelLayout::Rgb | motion_detect::thumbnail::PixelLayout::Gray) && gpu.borrow_mut().take().is_some() {
+                        output.info(&format!("Downsampling {} frames on the CPU", layout.name()));
+                    }
+                    rebaseline = true;
+                    last_frame_time = Instant::now();
+                    continue;
+                }
+            }
+        }
+
         // Capture new thumbnail for current frame
-        update_thumbnail(&mut thumbs[current_thumb]);
+        let skip = decimator.as_ref().map_or(0, Decimator::skip);
+        let frame_time = match update_thumbnail(source.as_mut(), &mut thumb, downsample, snapshots.as_mut(), &mut timing, &mut frame_counter, skip) {
+            Ok(Some(frame_time)) => frame_time,
+            Ok(None) => {
+                end_of_input = true;
+                break;
+            }
+            // Stopping the whole process group, as timeout or systemd does, stops ffmpeg as well.
+            // Blocked on the source, as with decimation, that's noticed first.
+            Err(_) if signals::shutdown_requested() => break,
+            Err(reason) => {
+                announce(Lifecycle::CameraLost { reason });
+                frame_counter.source_failed();
+                resume_guard.away(Instant::now());
+                if source.can_reconnect() && reconnect(source.as_mut(), &output) {
+                    announce(Lifecycle::CameraRecovered);
+                    rebaseline |= resume_guard.back(ResumeCause::Reconnect, Instant::now());
+                    reconnected = true;
+                    last_frame_time = Instant::now();
+                    continue;
+                }
+                camera_lost = true;
+                break;
+            }
+        };
+        frames += 1;
+        exit_report::frame(frame_time.instant);
+        let metadata = source.metadata();
+        if let Some(previous) = exposure.as_mut().and_then(|exposure| exposure.update(metadata)) {
+            if settings.verbose {
+                output.info(&format!(
+                    "frame {} {} after {}, raising the pixel threshold for {RAISED_FRAMES} frames",
+                    frame_time.sequence, metadata.text(), previous.text(),
+                ));
+            }
+        }
+        if let Some(delivered) = decimator.as_mut().and_then(|decimator| decimator.processed(frame_time.instant, frame_time.sequence)) {
+            let every = decimator.as_ref().map_or(1, Decimator::every);
+            output.info(&format!("The source delivers {delivered:.1} fps, processing 1 frame in {every}"));
+            motion.set_frame_interval(processed_interval(&decimator, frame_capture_interval));
+            if let Some(variant_b) = &mut variant_b {
+                variant_b.motion.set_frame_interval(processed_interval(&decimator, frame_capture_interval));
+            }
+        }
+
+        if let Some(activity) = &mut activity {
+            if let Some((report, end)) = activity.due(frame_time.instant, frame_counter.total_dropped()) {
+                send_activity(report, wall_clock.to_system(end));
+            }
+            activity.captured(frame_time.instant);
+        }
 
         // Ensures processing will actually wait for the desired capture interval,
-        // since a camera may refuse to record at very low frame rates
+        // since a camera may refuse to record at very low frame rates. Decimation reads the
+        // frames in between instead.
         let processing_time = last_frame_time.elapsed();
-        if processing_time < frame_capture_interval {
+        if processing_time < frame_capture_interval && decimator.is_none() {
             let wait_time = frame_capture_interval - processing_time;
             if wait_time.as_millis() > 1 {
                 std::thread::sleep(wait_time);
             }
         }
+        if paused {
+            last_frame_time = Instant::now();
+            continue;
+        }
+        // What the camera sees on its way to an activated scene isn't movement.
+        if scene_profiles.settling(Instant::now()) {
+            last_frame_time = Instant::now();
+            continue;
+        }
 
-        // Pixel change detection
-        let mut changed_pixels = 0;
-        for y in 0 .. thumb_height {
-            for x in 0 .. thumb_width {
-                let index = ((y * thumb_width) + x) * 3;
+        // With temporal averaging, only complete groups are compared. Their events are timed
+        // at the group's middle frame.
+        let Some((averaged, now)) = averager.push(&thumb, frame_time.instant) else {
+            last_frame_time = Instant::now();
+            continue;
+        };
 
-                let previous_pixel = &thumbs[previous_thumb][index ..= index+2];
-                let pixel = &&thumbs[current_thumb][index ..= index+2];
+        // A suspend comes between two frames that don't follow on from each other: what was in
+        // progress ended with the frame before, and the baseline starts over. Checked before the
+        // clock, which moved on meanwhile without stepping.
+        let suspend_after = processed_interval(&decimator, frame_capture_interval) * SUSPEND_FRAMES;
+        let suspended = suspend_watch.check(suspend_after);
+        let mut interrupted = Vec::new();
+        let mut interrupted_b = Vec::new();
+        if let Some(suspended) = suspended {
+            announce(Lifecycle::SystemResumed { suspended });
+            wall_clock.resumed(Instant::now(), SystemTime::now());
+            interrupted = motion.interrupt(StopReason::Suspend);
+            if let Some(variant_b) = &mut variant_b {
+                interrupted_b = variant_b.motion.interrupt(StopReason::Suspend);
+            }
+            frame_counter.paused();
+            rebaseline = true;
+        }
+        if std::mem::take(&mut scene_changed) {
+            interrupted.extend(motion.interrupt(StopReason::SceneChange));
+            if let Some(variant_b) = &mut variant_b {
+                interrupted_b.extend(variant_b.motion.interrupt(StopReason::SceneChange));
+            }
+        }
+        if let Some(step) = wall_clock.check_now() {
+            announce(Lifecycle::ClockStepped { offset: step.offset });
+        }
 
-                let diff_r = (pixel[0] as i32 - previous_pixel[0] as i32).abs();
-                let diff_g = (pixel[1] as i32 - previous_pixel[1] as i32).abs();
-                let diff_b = (pixel[2] as i32 - previous_pixel[2] as i32).abs();
+        // Saturated thumbnails say nothing about movement: nothing is learned from them, and the
+        // baseline starts over once they're usable again. Until then, with suppress they are
+        // taken as unchanged, with tamper as entirely changed.
+        let recovered = match saturation.update(averaged, now) {
+            Some(SaturationChange::Entered { level, mean }) => {
+                announce(Lifecycle::SignalLost { level, mean, policy: settings.saturated_policy });
+                false
+            }
+            Some(SaturationChange::Left { duration }) => {
+                announce(Lifecycle::SignalRestored { duration });
+                rebaseline = true;
+                true
+            }
+            None => false,
+        };
+        let substitute = match (saturation.level(), settings.saturated_policy) {
+            _ if recovered || suspended.is_some() => Some(0),
+            (Some(_), SaturatedPolicy::Suppress) => Some(0),
+            (Some(_), SaturatedPolicy::Tamper) => Some(1),
+            (Some(_), SaturatedPolicy::Detect) | (None, _) => None,
+        };
+        if let Some(changed) = substitute {
+            saturated_mask.clear();
+            saturated_mask.resize(averaged.len(), changed);
+        }
 
-                if diff_r >= pixel_threshold || diff_g >= pixel_threshold || diff_b >= pixel_threshold{
-                    changed_pixels += 1;
+        // Pixel change detection. A panic only costs the frame, the detector it left behind is
+        // rebuilt from scratch and takes a fresh reference.
+        strategy.set_motion_active(motion.is_active() || saturation.level().is_some());
+        strategy.raise_pixel_threshold(exposure.as_ref().map_or(0, ExposureCompensation::raise));
+        let diff_start = Instant::now();
+        let processed = match substitute {
+            Some(changed) => Ok(DiffResult {
+                changed_pixels: if changed == 0 { 0 } else { averaged.len() as i32 },
+                mask: &saturated_mask,
+                score: changed as f32 * 100.0,
+                skipped_pixels: 0,
+                shift: None,
+                cast: None,
+            }),
+            None => supervisor::guard(|| strategy.process(averaged)),
+        };
+        let result = match processed {
+            Ok(result) => result,
+            Err(panic) => {
+                let action = panics.record(Instant::now());
+                output.info(&format!("Warning, processing panicked at frame {}: {panic}", frame_time.sequence));
+                frame_counter.drop_frames(DropReason::ProcessingPanic, settings.temporal_average as u64);
+                if let Some(server) = &http_server {
+                    server.status().processing_panics = panics.total();
+                }
+                if action == PanicAction::Exit {
+                    output.info(&format!("\nError, {} processing panics, giving up", panics.total()));
+                    gave_up = true;
+                    break;
+                }
+                let (start_count, sustain_count);
+                (thumb, strategy, start_count, sustain_count) =
+                    detector(&settings, &live_stream, downsample, pixel_threshold, image_threshold, sustain_threshold);
+                motion.set_thresholds(start_count, sustain_count);
+                averager = TemporalAverage::new(settings.temporal_average);
+                if let Some(context) = &mut context {
+                    context.reset();
+                }
+                if action == PanicAction::Restart && source.can_reconnect() {
+                    output.info("Restarting the source after repeated panics");
+                    frame_counter.source_failed();
+                    reconnected = true;
+                    if let Err(err) = source.reconnect() {
+                        announce(Lifecycle::CameraLost { reason: err });
+                        if !reconnect(source.as_mut(), &output) {
+                            camera_lost = true;
+                            break;
+                        }
+                        announce(Lifecycle::CameraRecovered);
+                    }
                 }
-            }   
+                last_frame_time = Instant::now();
+                continue;
+            }
+        };
+        // Changes in disarmed zones take no part in movements, everything else still sees them.
+        zone_masks.fit(&settings.zones, averaged.width, averaged.height);
+        for change in zone_arming.update(wall_clock.to_system(now)) {
+            announce(Lifecycle::ZoneArming { change });
+        }
+        let changed_pixels = result.changed_pixels - zone_masks.silenced(result.mask, &zone_arming.armed());
+        if let Some(activity) = &mut activity {
+            activity.compared(result.score);
+        }
+        let output_start = Instant::now();
+        timing.record(Stage::Diff, output_start - diff_start);
+        timing.record_skipped(result.skipped_pixels, averaged.len());
+        // A shake was compared realigned. A larger shift is the camera itself moving, whether the
+        // scene still matches its reference is checked right away.
+        if let Some(shift) = result.shift {
+            if settings.verbose {
+                output.info(&match shift.compensated {
+                    true => format!("frame {} shifted by {},{} pixels, compensated", frame_time.sequence, shift.x, shift.y),
+                    false => format!("frame {} shifted by {},{} pixels, beyond --stabilize-max-shift", frame_time.sequence, shift.x, shift.y),
+                });
+            }
+            if let (false, Some(scene)) = (shift.compensated, &mut scene) {
+                scene.check_now(now);
+            }
+        }
+        if let (Some(cast), true) = (result.cast, settings.verbose) {
+            let [red, green, blue] = cast.shifts;
+            output.info(&match cast.compensated {
+                true => format!("frame {} color cast of {red:+} red, {green:+} green, {blue:+} blue, compensated", frame_time.sequence),
+                false => format!("frame {} color cast of {red:+} red, {green:+} green, {blue:+} blue, not uniform", frame_time.sequence),
+            });
+        }
+        if let Some(heatmap) = &mut heatmap {
+            if saturation.level().is_none() {
+                heatmap.record(result.mask, averaged.width, averaged.height);
+            }
+            if now >= learn_until {
+                break;
+            }
         }
 
         // Outputs messages if sufficient pixels have changed or stopped changing.
-        if changed_pixels > pixel_count_threshold {
-            if latest_movement_time.is_none() {
-                println!("start");
+        let event_time = wall_clock.to_system(now);
+        // The first comparison after a pause or a reconnect is against the baseline from before,
+        // with report-delta. What changed meanwhile is no movement, the baseline starts over.
+        if substitute.is_none() && resume_guard.is_pending() {
+            if let Some(delta) = resume_guard.compared(changed_pixels, effective_config.start_pixels, result.mask, averaged.width, averaged.height) {
+                output.event(&delta.text(), &delta.to_json(event_time, frame_time.source));
+            }
+            rebaseline = true;
+            last_frame_time = Instant::now();
+            continue;
+        }
+        let centroid = zone_tracker.as_ref().and_then(|_| zones::centroid(result.mask, averaged.width));
+        let mut started = false;
+        if let Some(digest) = holdover.as_mut().and_then(|holdover| holdover.update(event_time)) {
+            let digest_json = digest.to_json(frame_time.source);
+            output.event(&digest.text(), &digest_json);
+            // Commands have no digest, they run late for each movement.
+            for movement in &digest.movements {
+                output.held(movement.id, movement.duration.is_some());
+            }
+            #[cfg(feature = "desktop-notify")]
+            if let Some(notifier) = &mut notifier {
+                notifier.digest(digest.headline());
+            }
+            #[cfg(feature = "smtp")]
+            if let Some(mailer) = &mut mailer {
+                if let Err(err) = mailer.digest(&digest.headline(), &digest.lines()) {
+                    output.info(&format!("Warning, {err}"));
+                }
+            }
+        }
+        interrupted.extend(motion.update(changed_pixels, result.score, now));
+        // Movements wait for the verify command, their events are reported once it confirms them.
+        let events = match &mut verifier {
+            Some(verifier) => {
+                let snapshot = |path| snapshots.as_ref().map_or(Err(String::from("no snapshots")), |snapshots| snapshots.save_frame(path, event_time));
+                let events = verifier.pass(interrupted, snapshot);
+                for warning in verifier.take_warnings() {
+                    output.info(&format!("Warning, {warning}"));
+                }
+                events
+            }
+            None => interrupted.into_iter().map(|event| (event, None)).collect(),
+        };
+        let mut storm = None;
+        for (event, verdict) in events {
+            // Detection goes on while disarmed, only what it finds isn't reported.
+            if latest_movement.is_none_or(|(id, _)| id != event.id()) {
+                latest_movement = Some((event.id(), control_state.armed));
+            }
+            if latest_movement.is_some_and(|(_, reported)| !reported) {
+                continue;
+            }
+            if let Some(activity) = &mut activity {
+                activity.event(event);
+            }
+            if let MotionEvent::Stop { at, duration, .. } = event {
+                hourly.add(wall_clock.to_system(at - duration), duration);
+                if let Some(server) = &http_server {
+                    server.set_activity(hourly.to_json(SystemTime::now()));
+                }
+            }
+            if let (MotionEvent::Start { .. }, Some(storm_guard)) = (event, &mut storm_guard) {
+                storm = storm_guard.movement(now).or(storm);
+            }
+            // Stillness ends with a movement's start and begins again at its stop.
+            if let Some(idle) = &mut idle {
+                match event {
+                    MotionEvent::Start { .. } => if let Some(idle_end) = idle.motion_started(now) {
+                        send_idle(idle_end, event_time, frame_time.source);
+                    },
+                    MotionEvent::Stop { at, .. } => idle.motion_stopped(at),
+                    MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+                }
+            }
+            // A stop is timed when its tail ran out, not when the frame noticing it arrived, and a
+            // movement already in progress at startup when the detector became ready.
+            let time = match event {
+                MotionEvent::Start { at, .. } | MotionEvent::Stop { at, .. } => wall_clock.to_system(at),
+                _ => event_time,
+            };
+            // The commands, notifications, emails and ZeroMQ subscribers, unless held for quiet hours.
+            let notify = holdover.as_mut().is_none_or(|holdover| holdover.event(event, time));
+            let event_identity = identity.next(event.id());
+            for warning in identity.take_warnings() {
+                output.info(&format!("Warning, {warning}"));
+            }
+            let mut object = event.to_object(time, frame_time.source)
+                .field("installation", event_identity.installation.as_str())
+                .field("sequence", event_identity.sequence)
+                .field("frame", frame_time.sequence);
+            // At long capture intervals, when the change came is only known to a frame.
+            if settings.tail_frames.is_some() {
+                if let MotionEvent::Start { between: Some((before, after)), .. } | MotionEvent::Stop { between: Some((before, after)), .. } = event {
+                    let between = vec![json::unix_time(wall_clock.to_system(before)), json::unix_time(wall_clock.to_system(after))];
+                    object = object.field("between", between);
+                }
+            }
+            // What was reported of a movement before the clock stepped was on the old clock, its
+            // stop repeats the start on the new one.
+            match event {
+                MotionEvent::Start { at, .. } if wall_clock.stepped_since(at) => object = object.field("clock_stepped", true),
+                MotionEvent::Stop { at, duration, .. } if wall_clock.stepped_since(at - duration) => {
+                    object = object
+                        .field("clock_stepped", true)
+                        .field("start", json::unix_time(wall_clock.to_system(at - duration)));
+                }
+                _ => {}
+            }
+            if let Some(rotation) = &rotation {
+                object = object.field("stream", rotation.current().name.as_str());
+            }
+            if let Some(verdict) = verdict {
+                object = object.field("verification", verdict.name());
+            }
+            if settings.events_include_source && matches!(event, MotionEvent::Start { .. }) {
+                object = object.field("source", source_info.to_object());
+            }
+            if let (MotionEvent::Start { .. }, Some(level)) = (event, saturation.level()) {
+                object = object.field("saturated", level.name());
+            }
+            if let (MotionEvent::Start { .. }, false) = (event, metadata.is_empty()) {
+                object = object.field("metadata", metadata.to_object());
+            }
+            if let (MotionEvent::Start { .. }, true) = (event, exposure.is_some_and(|exposure| exposure.is_raised())) {
+                object = object.field("exposure_jump", true);
+            }
+            if let Some(clips) = &mut clips {
+                match event {
+                    MotionEvent::Start { id, at, .. } => {
+                        let clip = clips.start(id, at.saturating_duration_since(ready_at));
+                        object = object
+                            .field("clip", clip.id)
+                            .field("padded_start", json::unix_time(wall_clock.to_system(ready_at + clip.start)));
+                    }
+                    MotionEvent::Stop { id, at, .. } => {
+                        let clip = clips.stop(id, at.saturating_duration_since(ready_at));
+                        object = object
+                            .field("clip", clip.id)
+                            .field("padded_start", json::unix_time(wall_clock.to_system(ready_at + clip.start)))
+                            .field("padded_end", json::unix_time(wall_clock.to_system(ready_at + clip.end)));
+                    }
+                    MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+                }
+            }
+            if let Some(variant_b) = &mut variant_b {
+                object = object.field("variant", Variant::A.name());
+                variant_b.agreement.record(Variant::A, event);
+            }
+            // The snapshots to be written, none if the media queue dropped them. One it drops
+            // later, pushed out with drop-oldest, is told by the stop.
+            let saved_snapshots = match (event, &snapshots, &media, snapshots_on_start) {
+                (MotionEvent::Start { id, .. }, Some(snapshots), Some(media), true) => {
+                    match snapshots.save(id, event_time, &settings.zones, &zone_masks, result.mask, averaged.width) {
+                        Ok(job) if job.files.is_empty() => Some(Vec::new()),
+                        Ok(job) => {
+                            let files = job.files.clone();
+                            let sidecar = (!settings.no_sidecar).then(|| {
+                                let bbox = result.mask.iter().any(|changed| *changed != 0)
+                                    .then(|| scene::bounding_box(result.mask, averaged.width, averaged.height));
+                                let hash = effective_config.hash();
+                                Sidecar::started(&source_info.name, hash, event_time, event_identity.clone(), time, result.score, bbox)
+                            });
+                            if let Some(sidecar) = &sidecar {
+                                sidecars.insert(id, (files.clone(), sidecar.clone()));
+                            }
+                            match media.push(job.with_sidecar(sidecar)) {
+                                true => Some(files.into_iter().map(|file| file.path).collect()),
+                                false => {
+                                    object = object.field("media_skipped", true);
+                                    Some(Vec::new())
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            output.info(&format!("Warning, failed to save snapshot {err}"));
+                            Some(Vec::new())
+                        }
+                    }
+                }
+                _ => None,
+            };
+            if let Some(saved) = &saved_snapshots {
+                object = object.field("snapshots", saved.iter().map(|path| path.display().to_string()).collect::<Vec<_>>());
+            }
+            if let (MotionEvent::Stop { id, duration, stats, .. }, Some(media)) = (event, &media) {
+                if let Some((files, mut sidecar)) = sidecars.remove(&id) {
+                    sidecar.stopped(time, duration, stats.peak);
+                    media.push(MediaJob::sidecars(Some(id), files, sidecar));
+                }
+                if media.take_skipped(id) {
+                    object = object.field("media_skipped", true);
+                }
+            }
+            if let (MotionEvent::Start { id, .. }, Some(holdover), Some(saved), false) = (event, &mut holdover, &saved_snapshots, notify) {
+                holdover.snapshots(id, saved);
+            }
+            if let (MotionEvent::Start { id, .. }, Some(context)) = (event, &context) {
+                let sidecar = json::Object::new()
+                    .field("frame", frame_time.sequence)
+                    .field("changed_pixels", changed_pixels)
+                    .field("score", result.score)
+                    .field("start_pixels", effective_config.start_pixels)
+                    .field("algorithm", settings.algorithm.as_str())
+                    .field("pixel_threshold", control_state.pixel_threshold)
+                    .field("image_threshold", control_state.image_threshold);
+                match context.save(id, event_time, averaged, result.mask, sidecar, &effective_config.to_json()) {
+                    Ok(directory) => object = object.field("context", directory.display().to_string()),
+                    Err(err) => output.info(&format!("Warning, failed to save the event context {err}")),
+                }
+            }
+            if let Some(tracker) = &mut zone_tracker {
+                match event {
+                    MotionEvent::Start { .. } => tracker.begin(centroid),
+                    MotionEvent::Stop { .. } => object = object.field("zones", tracker.visited()),
+                    MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+                }
+            }
+            if let (Some(server), MotionEvent::Start { .. }) = (&http_server, event) {
+                server.status().events += 1;
+            }
+            output.motion(event, event_identity.clone(), now, notify, object.finish());
+            match event {
+                MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => {}
+                MotionEvent::Start { id, continued_from, pre_existing, .. } => {
+                    started = true;
+                    exit_report::event();
+                    movements += continued_from.is_none() as u64;
+                    if let Some(previous_id) = continued_from {
+                        output.info(&format!("movement {id} continues movement {previous_id}"));
+                    }
+                    if pre_existing {
+                        output.info(&format!("movement {id} was already in progress at startup"));
+                    }
+                    #[cfg(feature = "desktop-notify")]
+                    if let (Some(notifier), true) = (&mut notifier, notify) {
+                        notifier.motion_started(now);
+                    }
+                    #[cfg(feature = "smtp")]
+                    if let (Some(mailer), true) = (&mut mailer, notify) {
+                        if let Err(err) = mailer.motion_started(&event_identity, now, saved_snapshots.as_deref().unwrap_or_default()) {
+                            output.info(&format!("Warning, {err}"));
+                        }
+                    }
+                    #[cfg(all(feature = "uinput", target_os = "linux"))]
+                    if let Some(device) = &mut input_device {
+                        if let Err(err) = device.motion_started() {
+                            output.info(&format!("Warning, virtual input event failed: {err}"));
+                        }
+                    }
+                    #[cfg(all(feature = "gpio", target_os = "linux"))]
+                    if let Some(gpio) = &mut gpio_output {
+                        if let Err(err) = gpio.motion_started() {
+                            output.info(&format!("Warning, {err}"));
+                        }
+                    }
+                }
+                MotionEvent::Stop { id, reason, duration, .. } => {
+                    if reason == StopReason::MaxDuration {
+                        output.info(&format!("movement {id} reached the maximum duration after {duration:.1?}"));
+                    }
+                    #[cfg(feature = "desktop-notify")]
+                    if let (Some(notifier), true) = (&mut notifier, notify) {
+                        notifier.motion_stopped(duration);
+                    }
+                    #[cfg(feature = "smtp")]
+                    if let (Some(mailer), true) = (&mut mailer, notify) {
+                        if let Err(err) = mailer.motion_stopped(&event_identity, duration) {
+                            output.info(&format!("Warning, {err}"));
+                        }
+                    }
+                    #[cfg(all(feature = "uinput", target_os = "linux"))]
+                    if let Some(device) = &mut input_device {
+                        if let Err(err) = device.motion_stopped() {
+                            output.info(&format!("Warning, virtual input event failed: {err}"));
+                        }
+                    }
+                    #[cfg(all(feature = "gpio", target_os = "linux"))]
+                    if let Some(gpio) = &mut gpio_output {
+                        gpio.motion_stopped(now);
+                    }
+                }
+            }
+        }
+
+        // The thresholds before a change that set off a storm come back, from a fresh baseline.
+        if let Some(storm) = storm {
+            let before = control_state;
+            control_state.restore(storm.before);
+            rebaseline = true;
+            announce(Lifecycle::StormReverted { storm, changes: control_state.changes(&before) });
+        }
+        if let (Some(context), None) = (&mut context, substitute) {
+            context.compared(averaged, changed_pixels > effective_config.start_pixels);
+        }
+        if let (Some(scene), None) = (&mut scene, saturation.level()) {
+            let checked = match std::mem::take(&mut take_reference) {
+                true => scene.set(averaged, now).map(|()| {
+                    output.info("Scene reference set");
+                    None
+                }),
+                false => scene.update(averaged, now),
+            };
+            match checked {
+                Ok(Some(delta)) => {
+                    let delta_json = delta.to_json(event_time, frame_time.source);
+                    output.event(&delta.text(), &delta_json);
+                }
+                Ok(None) => {}
+                Err(err) => output.info(&format!("Warning, {err}")),
+            }
+        }
+
+        // Variant B of an A/B comparison only prints and sends its events.
+        if let Some(variant_b) = &mut variant_b {
+            variant_b.strategy.set_motion_active(variant_b.motion.is_active());
+            variant_b.strategy.raise_pixel_threshold(exposure.as_ref().map_or(0, ExposureCompensation::raise));
+            match supervisor::guard(|| variant_b.strategy.process(averaged)) {
+                Ok(result) => interrupted_b.extend(variant_b.motion.update(result.changed_pixels, result.score, now)),
+                Err(panic) => {
+                    output.info(&format!("Warning, variant b processing panicked at frame {}: {panic}", frame_time.sequence));
+                    variant_b.rebuild(&live_stream, downsample);
+                }
+            }
+            for event in interrupted_b {
+                let time = match event {
+                    MotionEvent::Start { at, .. } | MotionEvent::Stop { at, .. } => wall_clock.to_system(at),
+                    _ => event_time,
+                };
+                let event_json = event.to_object(time, frame_time.source)
+                    .field("frame", frame_time.sequence)
+                    .field("variant", Variant::B.name())
+                    .finish();
+                variant_b.agreement.record(Variant::B, event);
+                output.event(&format!("b {}", event.text()), &event_json);
+            }
+            if now >= variant_b.next_report {
+                let report = variant_b.agreement.report();
+                let report_json = report.to_json(event_time);
+                output.event(&report.text(), &report_json);
+                variant_b.next_report += settings.ab_report;
+            }
+        }
+
+        for warning in output.bus().take_warnings() {
+            output.info(&format!("Warning, {warning}"));
+        }
+        for warning in media.as_ref().map(MediaQueue::take_warnings).unwrap_or_default() {
+            output.info(&format!("Warning, {warning}"));
+        }
+        for idle_event in idle.as_mut().map(|idle| idle.update(now)).unwrap_or_default() {
+            send_idle(idle_event, event_time, frame_time.source);
+        }
+
+        // Timelapse frames, more often while a movement is active.
+        if let (Some(timelapse), Some(snapshots), Some(media)) = (&mut timelapse, &snapshots, &media) {
+            if timelapse.due(now, motion.is_active()) {
+                match snapshots.timelapse_job(timelapse.path(event_time), event_time) {
+                    Ok(job) => {
+                        let sidecar = (!settings.no_sidecar).then(|| Sidecar::timelapse(&source_info.name, effective_config.hash(), event_time));
+                        media.push(job.with_sidecar(sidecar));
+                    }
+                    Err(err) => output.info(&format!("Warning, failed to save timelapse frame {err}")),
+                }
+            }
+        }
+
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        if let Some(gpio) = &mut gpio_output {
+            if let Err(err) = gpio.update(now) {
+                output.info(&format!("Warning, {err}"));
+            }
+        }
+
+        // Movements crossing between zones.
+        let reported = latest_movement.is_none_or(|(_, reported)| reported);
+        if let (Some(tracker), Some(id), false, true) = (&mut zone_tracker, motion.active_id(), started, reported) {
+            if let Some(transition) = tracker.update(centroid) {
+                let transition_json = transition.to_json(id, event_time, frame_time.source);
+                output.event(&transition.text(), &transition_json);
+            }
+        }
+        let score = || json::Object::new()
+            .field("type", "score")
+            .field("active", motion.is_active())
+            .field("changed", result.score)
+            .field("time", json::unix_time(SystemTime::now()))
+            .finish();
+        #[cfg(feature = "zmq")]
+        if let (Some(publisher), true) = (&zmq_publisher, settings.zmq_scores) {
+            publisher.publish(&score());
+        }
+        if let Some(server) = &http_server {
+            let mut status = server.status();
+            status.active = motion.is_active();
+            status.changed = result.score;
+            status.capture_interval = frame_capture_interval;
+            status.downsample = downsample;
+            status.cpu_load = cpu_budget.as_ref().map(CpuBudget::load);
+            status.timing = timing;
+            status.idle_for = idle.as_ref().and_then(|idle| idle.idle_for(now));
+            status.frames = frame_counter.clone();
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            {
+                status.gpio = gpio_output.as_ref().map(gpio::GpioOutput::is_asserted);
+            }
+            #[cfg(feature = "smtp")]
+            {
+                status.emails_sent = mailer.as_ref().map(email::Mailer::sent);
+                status.email_failures = mailer.as_ref().map(email::Mailer::failures);
+                status.emails_spooled = settings.email_spool.as_ref().and(mailer.as_ref()).map(email::Mailer::spooled);
+            }
+            #[cfg(feature = "zmq")]
+            {
+                status.zmq_dropped = zmq_publisher.as_ref().map(zmq::Publisher::dropped);
             }
-            latest_movement_time = Some(Instant::now());
-            // "flip" buffers!
-            previous_thumb = 1 - previous_thumb;
-            current_thumb = 1 - current_thumb;            
-        } else {
-            // No movement in current frame, but there is an active movement started.
-            if let Some(time) = latest_movement_time {
-                if time.elapsed() > motion_tail_length {
-                    println!("stop");
-                    latest_movement_time = None;
+            status.media_written = media.as_ref().map(MediaQueue::written);
+            status.media_dropped = media.as_ref().map(MediaQueue::dropped);
+            status.media_queued = media.as_ref().map(MediaQueue::queued);
+            status.verification = verifier.as_ref().map(|verifier| verifier.stats().to_object());
+            status.sinks = output.bus().stats().iter().map(|(name, stats)| stats.to_object(name)).collect();
+            drop(status);
+            if server.has_clients() {
+                server.send_score(score());
+            }
+            // Masks are only packed for someone, and at most at the requested rate.
+            if let Some(rate) = settings.publish_mask {
+                let due = last_mask.is_none_or(|last| now.saturating_duration_since(last).as_secs_f32() >= 1.0 / rate);
+                if due && server.has_mask_clients() {
+                    server.send_mask(PackedMask::encode(result.mask, averaged.width, averaged.height, event_time));
+                    last_mask = Some(now);
                 }
             }
         }
 
+        // Buffers only grow with the frame size, so the first frame tells if they fit. Queued
+        // messages come and go with the clients, exceeding the cap with them only warns.
+        if http_server.is_some() || settings.max_memory.is_some() {
+            let usage = MemoryUsage {
+                source: source.buffer_bytes() + progressive.borrow().capacity(),
+                // As if the media queue were full, so the first frame tells if it fits.
+                snapshots: snapshots.as_ref().map_or(0, Snapshots::buffer_bytes) * (1 + media.as_ref().map_or(0, MediaQueue::capacity)),
+                thumbnails: thumb.buffer_bytes() + averager.buffer_bytes(),
+                detector: strategy.buffer_bytes() + variant_b.as_ref().map_or(0, |variant_b| variant_b.strategy.buffer_bytes()),
+                http: http_server.as_ref().map_or(0, http::HttpServer::queued_bytes),
+            };
+            if let Some(server) = &http_server {
+                server.status().memory = usage;
+            }
+            if let Some(max_memory) = settings.max_memory {
+                if usage.total() - usage.http > max_memory {
+                    output.info(&format!("\nError, the buffers need {}, above --max-memory {} ({})",
+                        memory::format_bytes(usage.total() - usage.http), memory::format_bytes(max_memory), usage.text()));
+                    out_of_memory = true;
+                    break;
+                }
+                if usage.total() > max_memory && !over_memory {
+                    output.info(&format!("Warning, queued messages push the buffers to {}, above --max-memory {} ({})",
+                        memory::format_bytes(usage.total()), memory::format_bytes(max_memory), usage.text()));
+                }
+                over_memory = usage.total() > max_memory;
+            }
+        }
+
+        timing.record(Stage::Output, output_start.elapsed());
+        timing.end_frame();
+        if settings.verbose {
+            match metadata.is_empty() {
+                true => output.info(&format!("frame {} {}", frame_time.sequence, timing.frame_line())),
+                false => output.info(&format!("frame {} {}, {}", frame_time.sequence, timing.frame_line(), metadata.text())),
+            }
+        }
+        if let Some(report_at) = &mut next_timing_report {
+            if Instant::now() >= *report_at {
+                output.info(&timing.summary_line());
+                timing.reset_max();
+                *report_at += settings.timing_report.unwrap_or_default();
+            }
+        }
+
+        // Degrade or recover to stay within the CPU budget.
+        if let Some(budget) = &mut cpu_budget {
+            if budget.record(frame_time.instant.elapsed()) {
+                frame_capture_interval = budget.capture_interval();
+                frame_counter.set_interval(frame_capture_interval.max(stream_desc.interval));
+                motion.set_frame_interval(processed_interval(&decimator, frame_capture_interval));
+                if let Some(variant_b) = &mut variant_b {
+                    variant_b.motion.set_frame_interval(processed_interval(&decimator, frame_capture_interval));
+                }
+                if budget.downsample() != downsample {
+                    // A new thumbnail size starts over with a fresh reference.
+                    downsample = budget.downsample();
+                    let (start_count, sustain_count);
+                    (thumb, strategy, start_count, sustain_count) =
+                        detector(&settings, &live_stream, downsample, pixel_threshold, image_threshold, sustain_threshold);
+                    motion.set_thresholds(start_count, sustain_count);
+                    if let Some(variant_b) = &mut variant_b {
+                        variant_b.rebuild(&live_stream, downsample);
+                    }
+                    if let Some(context) = &mut context {
+                        context.reset();
+                    }
+                    effective_config.thumb_width = thumb.width;
+                    effective_config.thumb_height = thumb.height;
+                    effective_config.downsample = downsample;
+                    effective_config.start_pixels = start_count;
+                    effective_config.sustain_pixels = sustain_count;
+                }
+                effective_config.capture_interval = frame_capture_interval;
+                source_info.capture_interval = frame_capture_interval;
+                exit_report::config(effective_config.to_object());
+                if let Some(server) = &http_server {
+                    server.set_config(effective_config.to_json());
+                    server.status().source = Some(source_info.clone());
+                }
+                output.info(&format!(
+                    "CPU budget: capture interval {:.0?}, downsample {}",
+                    frame_capture_interval, downsample
+                ));
+            }
+        }
         last_frame_time = Instant::now();
     }
+
+    // Clean shutdown, keep what was learned for the next start.
+    if end_of_input {
+        output.info(&format!("End of input after {frames} frames, {movements} movements"));
+    }
+    // The last, partial period ends with the last frame.
+    if let Some(activity) = &mut activity {
+        let (report, end) = activity.finish(frame_counter.total_dropped());
+        send_activity(report, wall_clock.to_system(end));
+    }
+    let dropped: Vec<String> = DropReason::ALL.iter()
+        .filter(|reason| frame_counter.dropped(**reason) > 0)
+        .map(|reason| format!("{} {}", frame_counter.dropped(*reason), reason.name()))
+        .collect();
+    if !dropped.is_empty() {
+        output.info(&format!("Dropped frames: {}, out of {}", dropped.join(", "), frame_counter.last_sequence()));
+    }
+    announce(Lifecycle::ShuttingDown);
+    if let Some(verifier) = verifier {
+        verifier.finish();
+    }
+    #[cfg(feature = "mdns")]
+    if let Some(announcer) = announcer {
+        announcer.withdraw();
+    }
+    // Written before the emails attaching them go.
+    if let Some(media) = media {
+        let left = media.finish(media::DRAIN_TIMEOUT);
+        if left > 0 {
+            output.info(&format!("Warning, {left} snapshot(s) or timelapse frame(s) left unwritten"));
+        }
+    }
+    #[cfg(feature = "smtp")]
+    if let Some(mailer) = mailer.take() {
+        let spooled = mailer.finish(settings.email_drain_timeout);
+        if spooled > 0 {
+            output.info(&format!("Warning, {spooled} email(s) left in the spool for the next start"));
+        }
+    }
+    // Learning cut short by the end of input or a shutdown still writes what it learned.
+    let mask_exit = heatmap.as_ref().and_then(|heatmap| save_learned_mask(heatmap, &settings, &output));
+    // A detector that kept panicking isn't worth keeping.
+    if let (Some(path), false) = (&settings.state_file, gave_up) {
+        if let Some(reference) = strategy.reference() {
+            let state = SavedState {
+                capture_width: stream_desc.width,
+                capture_height: stream_desc.height,
+                downsample: downsample as u32,
+                algorithm: algorithm_id,
+                reference: reference.clone(),
+                noise_map: strategy.noise_map().cloned(),
+                held: holdover.as_ref().map(|holdover| holdover.held().to_vec()).unwrap_or_default(),
+                streams: rotation.as_ref().map(|rotation| {
+                    rotation.streams().iter().enumerate()
+                        .filter_map(|(index, stream)| {
+                            let learned = if index == rotation.index() { Some(&strategy) } else { parked[index].as_ref() };
+                            learned.and_then(|learned| Some(StreamState {
+                                name: stream.name.clone(),
+                                reference: learned.reference()?.clone(),
+                                noise_map: learned.noise_map().cloned(),
+                            }))
+                        })
+                        .collect()
+                }).unwrap_or_default(),
+                scenes: scene_profiles.profiles().to_vec(),
+                activity: hourly,
+            };
+            match state.save(path) {
+                Ok(()) => output.info(&format!("Saved state to {}", path.display())),
+                Err(err) => output.info(&format!("Warning, failed to save state to {}: {err}", path.display())),
+            }
+        }
+    }
+    if let (Some(path), Some(noise_map), false) = (&settings.noise_map_image, strategy.noise_map(), gave_up) {
+        match noise_map.save_image(path, &settings.adaptive_threshold()) {
+            Ok(()) => output.info(&format!("Saved noise map to {}", path.display())),
+            Err(err) => output.info(&format!("Warning, failed to save noise map to {}: {err}", path.display())),
+        }
+    }
+    // process::exit skips destructors, release the GPIO line and print what's pending explicitly.
+    output.flush_repeats();
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    drop(gpio_output);
+    if camera_lost {
+        exit_report::exit(ExitReason::CameraLost, 5); // I/O error
+    }
+    if events_lost {
+        exit_report::exit(ExitReason::EventsLost, 5); // I/O error
+    }
+    if gave_up {
+        exit_report::exit(ExitReason::PanicLimit, 131); // State not recoverable
+    }
+    if out_of_memory {
+        exit_report::exit(ExitReason::OutOfMemory, 12); // Cannot allocate memory
+    }
+    if let Some(code) = mask_exit {
+        exit_report::exit(ExitReason::Error, code);
+    }
+    let reason = match (end_of_input, signals::shutdown_requested()) {
+        (true, _) => ExitReason::EndOfInput,
+        (false, true) => ExitReason::Signal,
+        (false, false) => ExitReason::Completed, // learn-mask's duration is over.
+    };
+    exit_report::write(reason, 0);
+    Ok(())
+}
+
+
+/// Writes the mask make-mask draws from its rectangles, on --size or the thumbnail grid of the
+/// capture size and downsample given, and prints how much of the frame it covers. Returns the exit
+/// code.
+fn make_mask(settings: &Settings) -> i32 {
+    let path = settings.mask_output.as_deref().expect("make-mask settings have an output");
+    let (width, height) = settings.mask_grid();
+    let drawn = match MaskImage::from_rects(width, height, &settings.mask_rects) {
+        Ok(drawn) => drawn,
+        Err(err) => {
+            println!("\nError, {err}");
+            return 22; // Invalid argument
+        }
+    };
+    println!(
+        "Mask covers {:.1}% of the frame: {} of {} pixels of the {width}x{height} grid ignored",
+        drawn.coverage() * 100.0, drawn.weights.iter().filter(|weight| **weight == 0).count(), drawn.weights.len()
+    );
+    match drawn.save(path) {
+        Ok(()) => {
+            println!("Saved mask to {}, use it with --mask", path.display());
+            0
+        }
+        Err(err) => {
+            println!("\nError, failed to save mask to {}: {err}", path.display());
+            5 // I/O error
+        }
+    }
+}
+
+
+/// Writes the mask learn-mask learned, and prints how much of the frame it covers. Returns the exit
+/// code when it couldn't or shouldn't be written.
+fn save_learned_mask(heatmap: &Heatmap, settings: &Settings, output: &Output) -> Option<i32> {
+    let path = settings.mask_output.as_deref().expect("learn-mask settings have an output");
+    if heatmap.frames == 0 {
+        output.info("\nError, no frames were compared, no mask written");
+        return Some(61); // No data available
+    }
+    let threshold = settings.mask_threshold / 100.0;
+    let learned = heatmap.mask(threshold);
+    let coverage = learned.coverage();
+    output.info(&format!(
+        "Mask covers {:.1}% of the frame: {} of {} pixels changed in more than {}% of {} frames",
+        coverage * 100.0, heatmap.above(threshold), learned.weights.len(), settings.mask_threshold, heatmap.frames
+    ));
+    if coverage > mask::MAX_LEARNED_COVERAGE && !settings.force {
+        output.info(&format!(
+            "\nError, a mask covering more than {:.0}% of the frame would blind detection, not written. \
+            Raise --mask-threshold, or pass --force to write it anyway",
+            mask::MAX_LEARNED_COVERAGE * 100.0
+        ));
+        return Some(1); // Operation not permitted
+    }
+    match learned.save(path) {
+        Ok(()) => {
+            output.info(&format!("Saved mask to {}, use it with --mask", path.display()));
+            None
+        }
+        Err(err) => {
+            output.info(&format!("\nError, failed to save mask to {}: {err}", path.display()));
+            Some(5) // I/O error
+        }
+    }
+}
+
+
+/// Drops `frames` frames, one per capture interval, reporting the progress after each. Stops early
+/// at the end of the input or on an error, the next read tells.
+fn warm_up_frames(source: &mut dyn FrameSource, frames: u32, interval: Duration, progress: &dyn Fn(Progress)) {
+    for frame in 0 .. frames {
+        progress(Progress::new(Phase::WarmingUp, "").with_percent(frame as f64, frames as f64));
+        if !matches!(source.skip_frame(), Ok(true)) {
+            return;
+        }
+        std::thread::sleep(interval);
+    }
+    progress(Progress::new(Phase::WarmingUp, "").with_percent(frames as f64, frames as f64));
 }
 
+
+/// Retries a failed source with exponential backoff until it delivers frames again. Returns
+/// false if shutdown was requested first.
+fn reconnect(source: &mut dyn FrameSource, output: &Output) -> bool {
+    const BACKOFF_MIN: Duration = Duration::from_secs(1);
+    const BACKOFF_MAX: Duration = Duration::from_secs(30);
+    let mut backoff = BACKOFF_MIN;
+    loop {
+        let retry_at = Instant::now() + backoff;
+        while Instant::now() < retry_at {
+            if signals::shutdown_requested() {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        match source.reconnect().and_then(|()| source.next_frame().map(|_| ())) {
+            Ok(()) => return true,
+            Err(err) => output.info(&format!("Warning, reconnect failed: {err}, retrying in {:.0?}", (backoff * 2).min(BACKOFF_MAX))),
+        }
+        backoff = (backoff * 2).min(BACKOFF_MAX);
+    }
+}
+
+
+/// The pixel threshold from 0 to 255, and the image and sustain thresholds from 0 to 1, from
+/// their percentages.
+fn thresholds(pixel_percent: f32, image_percent: f32, sustain_percent: f32) -> (i32, f32, f32) {
+    // Convert pixel_threshold from a percentage to an integer amount with a max value of 255
+    let pixel_threshold = ((pixel_percent * (255.0 / 100.0)) as i32).clamp(0, 255);
+
+    // Normalize and clamp image and sustain thresholds from their original percentage values
+    let image_threshold = (image_percent / 100.0f32).clamp(0.0, 1.0);
+    let sustain_threshold = (sustain_percent / 100.0f32).clamp(0.0, 1.0);
+    (pixel_threshold, image_threshold, sustain_threshold)
+}
+
+
+/// Variant B of an A/B comparison: a second detector fed the same thumbnails as the primary one.
+struct VariantB<'a> {
+    settings: &'a Settings,
+    strategy: Box<dyn DiffStrategy>,
+    motion: MotionTracker,
+    agreement: Agreement,
+    next_report: Instant,
+}
+
+
+impl<'a> VariantB<'a> {
+
+    fn new(settings: &'a Settings, stream_desc: &Descriptor, downsample: usize, frame_interval: Duration, report_every: Duration) -> Self {
+        let (pixel_threshold, image_threshold, sustain_threshold) =
+            thresholds(settings.pixel_threshold, settings.image_threshold, settings.sustain_threshold());
+        let (_, strategy, start_count, sustain_count) =
+            detector(settings, stream_desc, downsample, pixel_threshold, image_threshold, sustain_threshold);
+        Self {
+            settings,
+            strategy,
+            motion: MotionTracker::new(settings.motion_tail_length, start_count, sustain_count)
+                .with_tail_frames(settings.tail_frames)
+                .with_max_duration(settings.max_event_duration)
+                .with_confirm_frames(settings.confirm_frames)
+                .with_frame_interval(frame_interval),
+            agreement: Agreement::new(),
+            next_report: Instant::now() + report_every,
+        }
+    }
+
+    /// Starts over with a fresh strategy for the thumbnail size, after a panic or a downsample change.
+    fn rebuild(&mut self, stream_desc: &Descriptor, downsample: usize) {
+        let settings = self.settings;
+        let (pixel_threshold, image_threshold, sustain_threshold) =
+            thresholds(settings.pixel_threshold, settings.image_threshold, settings.sustain_threshold());
+        let (_, strategy, start_count, sustain_count) =
+            detector(self.settings, stream_desc, downsample, pixel_threshold, image_threshold, sustain_threshold);
+        self.strategy = strategy;
+        self.motion.set_thresholds(start_count, sustain_count);
+    }
+}
+
+
+/// Creates the thumbnail and diff strategy for a downsample factor, along with the changed pixel
+/// counts that start and sustain a movement at that size. Thresholds are already normalized.
+fn detector(
+    settings: &Settings,
+    stream_desc: &Descriptor,
+    downsample: usize,
+    pixel_threshold: i32,
+    image_threshold: f32,
+    sustain_threshold: f32,
+) -> (Thumbnail, Box<dyn DiffStrategy>, i32, i32) {
+    let thumb_width = stream_desc.width as usize / downsample;
+    let thumb_height = stream_desc.height as usize / downsample;
+    let thumb_len = thumb_width * thumb_height;
+    let pixel_count_threshold = (thumb_len as f32 * image_threshold) as i32;
+    let sustain_count_threshold = (thumb_len as f32 * sustain_threshold) as i32;
+
+    // The diff strategy keeps its own reference thumbnail, optionally fed through a shake
+    // compensation, a brightness normalization, a white balance compensation and, before that, a
+    // blur. Flickering pixels are dropped in between, as blurred, and the mask then drops the
+    // ignored pixels.
+    let mut strategy = diff::from_name(&settings.algorithm, pixel_threshold, pixel_count_threshold, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("Algorithm names are validated with the settings");
+    if settings.stabilize {
+        strategy = Box::new(Stabilize::new(strategy, settings.stabilize_max_shift));
+    }
+    if let Some(mode) = settings.normalize {
+        strategy = Box::new(Normalize::new(strategy, mode));
+    }
+    if let Some(tolerance) = settings.wb_compensation {
+        strategy = Box::new(WhiteBalance::new(strategy, tolerance));
+    }
+    if settings.flicker_rejection {
+        strategy = Box::new(FlickerRejection::new(strategy, pixel_threshold));
+    }
+    if settings.blur > 0 {
+        strategy = Box::new(Blur::new(strategy, settings.blur));
+    }
+    if let Some(mask) = &settings.mask {
+        strategy = Box::new(Masked::new(strategy, mask.clone()));
+    }
+    strategy.set_early_exit(settings.early_exit());
+    let thumb = Thumbnail::with_channels(thumb_width, thumb_height, camera::channels(&stream_desc.pixfmt, settings.force_input_layout))
+        .with_stats(settings.thumb_stats);
+    (thumb, strategy, pixel_count_threshold, sustain_count_threshold)
+}
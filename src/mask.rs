@@ -0,0 +1,327 @@
+use std::{
+    fmt, fs, io,
+    ops::Range,
+    path::Path,
+    time::{ Duration, SystemTime, UNIX_EPOCH },
+};
+
+/// Version byte of the binary mask frames, bumped whenever the layout changes.
+pub const MASK_FORMAT_VERSION: u8 = 1;
+
+/// Bytes before the packed mask.
+pub const MASK_HEADER_LEN: usize = 13;
+
+
+/// A change mask sent to WebSocket clients at GET /mask, one binary message per frame:
+///
+/// | Offset | Size | Content                                                   |
+/// |--------|------|-----------------------------------------------------------|
+/// | 0      | 1    | Format version, currently 1                               |
+/// | 1      | 2    | Width in thumbnail pixels, little endian                  |
+/// | 3      | 2    | Height in thumbnail pixels, little endian                 |
+/// | 5      | 8    | Capture time in microseconds since the unix epoch, little endian |
+/// | 13     | ⌈width × height / 8⌉ | One bit per pixel, row by row, least significant bit first, 1 where it changed |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedMask {
+    pub width: usize,
+    pub height: usize,
+    pub time: SystemTime,
+    pub bits: Vec<u8>,
+}
+
+
+impl PackedMask {
+
+    /// Serializes a mask with one byte per pixel, as in `DiffResult::mask`.
+    pub fn encode(mask: &[u8], width: usize, height: usize, time: SystemTime) -> Vec<u8> {
+        let pixels = width * height;
+        let mut frame = Vec::with_capacity(MASK_HEADER_LEN + pixels.div_ceil(8));
+        frame.push(MASK_FORMAT_VERSION);
+        frame.extend_from_slice(&(width as u16).to_le_bytes());
+        frame.extend_from_slice(&(height as u16).to_le_bytes());
+        let micros = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        frame.extend_from_slice(&micros.to_le_bytes());
+        frame.extend(mask[.. pixels].chunks(8).map(|chunk| {
+            chunk.iter().enumerate().fold(0u8, |byte, (bit, changed)| byte | (((*changed != 0) as u8) << bit))
+        }));
+        frame
+    }
+
+    /// Parses a frame made by `encode`.
+    pub fn decode(frame: &[u8]) -> Result<Self, String> {
+        let header = frame.get(.. MASK_HEADER_LEN).ok_or("mask frame shorter than its header")?;
+        if header[0] != MASK_FORMAT_VERSION {
+            return Err(format!("unsupported mask format version {}", header[0]));
+        }
+        let width = u16::from_le_bytes([header[1], header[2]]) as usize;
+        let height = u16::from_le_bytes([header[3], header[4]]) as usize;
+        let micros = u64::from_le_bytes(header[5 .. 13].try_into().expect("The header has 8 time bytes"));
+        let bits = &frame[MASK_HEADER_LEN ..];
+        if bits.len() != (width * height).div_ceil(8) {
+            return Err(format!("{} mask bytes for {width}x{height} pixels", bits.len()));
+        }
+        Ok(Self { width, height, time: UNIX_EPOCH + Duration::from_micros(micros), bits: bits.to_vec() })
+    }
+
+    /// Whether the pixel at index (y * width + x) changed.
+    pub fn changed(&self, index: usize) -> bool {
+        self.bits[index / 8] & (1 << (index % 8)) != 0
+    }
+}
+
+
+/// Share of the frame a learned mask may cover without --force. More usually means the camera moved
+/// or the threshold is too low, and the mask would blind detection.
+pub const MAX_LEARNED_COVERAGE: f32 = 0.6;
+
+
+/// The pixels detection ignores, e.g. swaying trees, from a grayscale PGM image (--mask): black
+/// pixels are ignored, any other shade is watched. Or how much each pixel counts, from a weight map
+/// (--weight-map): black is ignored, white counts fully, the shades in between proportionally. The
+/// image can have any size, it is scaled to the thumbnail grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskImage {
+    pub width: usize,
+    pub height: usize,
+    pub weights: Vec<u8>,       // Row by row, 0 for ignored to 255 for full weight, only both in a mask.
+    pub weighted: bool,         // A weight map: scores are shares of the total weight, not of the thumbnail.
+}
+
+
+impl MaskImage {
+
+    /// Reads a binary PGM (P5) image with 8-bit samples as a mask.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let (width, height, _, pixels) = read_pgm(path, "mask")?;
+        Ok(Self { width, height, weights: pixels.iter().map(|pixel| if *pixel != 0 { 255 } else { 0 }).collect(), weighted: false })
+    }
+
+    /// Reads a binary PGM (P5) image with 8-bit samples as a weight map, its maximum value being
+    /// full weight.
+    pub fn load_weights(path: &Path) -> Result<Self, String> {
+        let (width, height, max_value, pixels) = read_pgm(path, "weight map")?;
+        let weights = pixels.iter().map(|pixel| ((*pixel).min(max_value) as usize * 255 / max_value as usize) as u8).collect();
+        Ok(Self { width, height, weights, weighted: true })
+    }
+
+    /// Writes the image as a binary PGM image, black where ignored and white where watched.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        data.extend(&self.weights);
+        fs::write(path, data)
+    }
+
+    /// The weights on a grid of the given size, each taking the image pixel under its centre.
+    pub fn fit(&self, width: usize, height: usize) -> Vec<u8> {
+        (0 .. width * height)
+            .map(|index| {
+                let x = ((index % width) * 2 + 1) * self.width / (width * 2);
+                let y = ((index / width) * 2 + 1) * self.height / (height * 2);
+                self.weights[y * self.width + x]
+            })
+            .collect()
+    }
+
+    /// A mask drawn from rectangles, for make-mask: everything is watched, or ignored if any rectangle
+    /// is watched, and each rectangle in turn is then ignored or watched over what came before.
+    pub fn from_rects(width: usize, height: usize, rects: &[MaskRect]) -> Result<Self, String> {
+        let watching = rects.iter().any(|rect| rect.watch);
+        let mut weights = vec![if watching { 0 } else { 255 }; width * height];
+        for rect in rects {
+            let (columns, rows) = rect.cells(width, height).ok_or(format!("{rect} covers no pixel of the {width}x{height} grid"))?;
+            for y in rows {
+                weights[y * width + columns.start .. y * width + columns.end].fill(if rect.watch { 255 } else { 0 });
+            }
+        }
+        Ok(Self { width, height, weights, weighted: false })
+    }
+
+    /// Fraction of the image that is ignored.
+    pub fn coverage(&self) -> f32 {
+        self.weights.iter().filter(|weight| **weight == 0).count() as f32 / self.weights.len().max(1) as f32
+    }
+
+    /// Mean weight of the image, from 0 to 1.
+    pub fn mean_weight(&self) -> f32 {
+        self.weights.iter().map(|weight| *weight as f32 / 255.0).sum::<f32>() / self.weights.len().max(1) as f32
+    }
+}
+
+
+/// A coordinate or extent of a make-mask rectangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extent {
+    Pixels(usize),  // Thumbnail pixels.
+    Percent(f32),   // Of the grid's width or height, given with a trailing '%'.
+}
+
+
+impl Extent {
+
+    fn parse(text: &str) -> Option<Self> {
+        match text.trim().strip_suffix('%') {
+            Some(percent) => percent.parse().ok().filter(|percent| (0.0 ..= 100.0).contains(percent)).map(Extent::Percent),
+            None => text.trim().parse().ok().map(Extent::Pixels),
+        }
+    }
+
+    /// In thumbnail pixels, on a grid `size` pixels across or down.
+    fn pixels(&self, size: usize) -> usize {
+        match self {
+            Extent::Pixels(pixels) => *pixels,
+            Extent::Percent(percent) => (percent / 100.0 * size as f32).round() as usize,
+        }
+    }
+}
+
+
+impl fmt::Display for Extent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Extent::Pixels(pixels) => write!(f, "{pixels}"),
+            Extent::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+
+/// A rectangle make-mask ignores (--ignore) or watches (--watch), from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaskRect {
+    pub x: Extent,
+    pub y: Extent,
+    pub width: Extent,
+    pub height: Extent,
+    pub watch: bool,
+}
+
+
+impl MaskRect {
+
+    /// Parses "x,y,width,height", each value in thumbnail pixels or, ending in '%', in percent.
+    pub fn parse(text: &str, watch: bool) -> Result<Self, String> {
+        let invalid = || format!("Invalid rectangle '{text}', expected x,y,width,height in pixels or percent, e.g. 0,0,80,10 or 0%,0%,100%,15%");
+        let values: Vec<Extent> = text.split(',').map(Extent::parse).collect::<Option<_>>().ok_or_else(invalid)?;
+        let [x, y, width, height] = values[..] else {
+            return Err(invalid());
+        };
+        if [width, height].iter().any(|extent| matches!(extent, Extent::Pixels(0)) || *extent == Extent::Percent(0.0)) {
+            return Err(format!("Rectangle '{text}' is empty"));
+        }
+        Ok(Self { x, y, width, height, watch })
+    }
+
+    /// The columns and rows it covers on a grid of the given size, cut at its edges, None if it's
+    /// outside of it.
+    fn cells(&self, width: usize, height: usize) -> Option<(Range<usize>, Range<usize>)> {
+        let (left, top) = (self.x.pixels(width), self.y.pixels(height));
+        let right = (left + self.width.pixels(width)).min(width);
+        let bottom = (top + self.height.pixels(height)).min(height);
+        (left < right && top < bottom).then_some((left .. right, top .. bottom))
+    }
+}
+
+
+impl fmt::Display for MaskRect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let option = if self.watch { "--watch" } else { "--ignore" };
+        write!(f, "{option} {},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+
+// The size, maximum value and pixels of a binary PGM image with 8-bit samples, `what` it is
+// naming it in errors.
+fn read_pgm(path: &Path, what: &str) -> Result<(usize, usize, u8, Vec<u8>), String> {
+    let data = fs::read(path).map_err(|err| format!("Can't read {what} {}: {err}", path.display()))?;
+    let invalid = |reason: &str| format!("Invalid {what} {}: {reason}", path.display());
+
+    // Four whitespace separated header fields, comments included, then one whitespace byte.
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while fields.len() < 4 {
+        while data.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+        if data.get(position) == Some(&b'#') {
+            while data.get(position).is_some_and(|byte| *byte != b'\n') {
+                position += 1;
+            }
+            continue;
+        }
+        let start = position;
+        while data.get(position).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+            position += 1;
+        }
+        if start == position {
+            return Err(invalid("truncated header"));
+        }
+        fields.push(String::from_utf8_lossy(&data[start .. position]).into_owned());
+    }
+    if fields[0] != "P5" {
+        return Err(invalid("not a binary PGM image (P5)"));
+    }
+    let number = |field: &str| field.parse::<usize>().map_err(|_| invalid("bad header"));
+    let (width, height, max_value) = (number(&fields[1])?, number(&fields[2])?, number(&fields[3])?);
+    if width == 0 || height == 0 || !(1 ..= 255).contains(&max_value) {
+        return Err(invalid("only 8-bit images of at least one pixel are supported"));
+    }
+    let pixels = data.get(position + 1 .. position + 1 + width * height).ok_or_else(|| invalid("truncated pixels"))?;
+    Ok((width, height, max_value as u8, pixels.to_vec()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_masks_decode_to_what_was_encoded() {
+        // 5 by 3 is 15 bits, the last byte is partly padding.
+        let mask: Vec<u8> = (0 .. 15).map(|index| (index % 3 == 0) as u8 * 255).collect();
+        let time = UNIX_EPOCH + Duration::from_micros(1_714_571_107_123_456);
+        let frame = PackedMask::encode(&mask, 5, 3, time);
+        assert_eq!(frame.len(), MASK_HEADER_LEN + 2);
+        assert_eq!(&frame[.. 5], &[MASK_FORMAT_VERSION, 5, 0, 3, 0]);
+        assert_eq!(frame[MASK_HEADER_LEN ..], [0b0100_1001, 0b0001_0010]);
+        let decoded = PackedMask::decode(&frame).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.time), (5, 3, time));
+        for (index, changed) in mask.iter().enumerate() {
+            assert_eq!(decoded.changed(index), *changed != 0, "{index}");
+        }
+    }
+
+
+    #[test]
+    fn malformed_mask_frames_are_refused() {
+        let frame = PackedMask::encode(&[1; 16], 4, 4, UNIX_EPOCH);
+        assert!(PackedMask::decode(&frame[.. MASK_HEADER_LEN - 1]).is_err());
+        assert!(PackedMask::decode(&frame[.. frame.len() - 1]).is_err());
+        let mut newer = frame.clone();
+        newer[0] = MASK_FORMAT_VERSION + 1;
+        assert!(PackedMask::decode(&newer).unwrap_err().contains("version"));
+    }
+
+
+    #[test]
+    fn rectangles_parse_in_pixels_and_percent() {
+        let rect = MaskRect::parse("0,10%, 80,12.5%", false).unwrap();
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (Extent::Pixels(0), Extent::Percent(10.0), Extent::Pixels(80), Extent::Percent(12.5)));
+        assert_eq!(rect.to_string(), "--ignore 0,10%,80,12.5%");
+        for text in ["0,0,80", "0,0,80,10,5", "0,0,120%,10", "0,0,-1,10", "0,0,0,10", "0,0,80,0%"] {
+            assert!(MaskRect::parse(text, false).is_err(), "{text}");
+        }
+    }
+
+
+    #[test]
+    fn rectangles_are_drawn_in_turn_and_cut_at_the_edges() {
+        // Everything ignored but a watched 2 by 2 square, its top left pixel ignored again.
+        let rects = [MaskRect::parse("1,1,2,2", true).unwrap(), MaskRect::parse("1,1,1,1", false).unwrap()];
+        let mask = MaskImage::from_rects(4, 3, &rects).unwrap();
+        assert_eq!(mask.weights, [0, 0, 0, 0, 0, 0, 255, 0, 0, 255, 255, 0]);
+        let edge = MaskImage::from_rects(4, 3, &[MaskRect::parse("2,0,10,10", false).unwrap()]).unwrap();
+        assert_eq!(edge.coverage(), 0.5);
+        assert!(MaskImage::from_rects(4, 3, &[MaskRect::parse("4,0,1,1", false).unwrap()]).is_err());
+    }
+}
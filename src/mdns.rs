@@ -0,0 +1,639 @@
+use std::{
+    io,
+    net::{ IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket },
+    sync::{ Arc, Mutex, atomic::{ AtomicBool, Ordering } },
+    thread::{ self, JoinHandle },
+    time::{ Duration, Instant },
+};
+
+use crate::capabilities;
+
+/// The DNS-SD service type the HTTP status server is announced as.
+pub const SERVICE_TYPE: &str = "_motion-detect._tcp.local";
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+// Record types and the class, its top bit telling caches to flush older records of a unique name.
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CACHE_FLUSH: u16 = 0x8000;
+
+// Lifetimes RFC 6762 recommends: host names and SRV records expire sooner than the rest.
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+
+// Announcements after each (re)start, as delays since the previous one.
+const ANNOUNCEMENTS: [Duration; 3] = [Duration::ZERO, Duration::from_secs(1), Duration::from_secs(2)];
+// How often the responder checks whether it's withdrawn or has something to announce.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Largest message sent or read, what fits a standard Ethernet frame.
+const MAX_MESSAGE: usize = 1500;
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    use std::os::raw::{ c_int, c_void };
+
+    pub const AF_INET: c_int = 2;
+    pub const SOCK_DGRAM: c_int = 2;
+    pub const SOCK_CLOEXEC: c_int = 0o2000000;
+    pub const SOL_SOCKET: c_int = 1;
+    pub const SO_REUSEADDR: c_int = 2;
+    pub const SO_REUSEPORT: c_int = 15;
+
+    #[repr(C)]
+    pub struct SockaddrIn {
+        pub family: u16,
+        pub port: u16,          // Network byte order, as the address.
+        pub address: [u8; 4],
+        pub zero: [u8; 8],
+    }
+
+    extern "C" {
+        pub fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+        pub fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+        pub fn bind(fd: c_int, address: *const c_void, len: u32) -> c_int;
+        pub fn close(fd: c_int) -> c_int;
+    }
+}
+
+
+/// What is announced: the instance name, --name or the product, the HTTP port and address, and
+/// TXT records as "key=value" strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    pub instance: String,
+    pub port: u16,
+    pub address: Ipv4Addr,
+    pub txt: Vec<String>,
+}
+
+
+impl Service {
+
+    /// The service of the HTTP server listening on `http`, with the version, the features and
+    /// whether /control and /mask are served in its TXT records. A server listening on every address
+    /// is announced at the one multicast goes out from.
+    pub fn new(instance: &str, http: SocketAddr, control: bool, masks: bool) -> Self {
+        let address = match http.ip() {
+            IpAddr::V4(address) if !address.is_unspecified() => address,
+            _ => outgoing_address().unwrap_or(Ipv4Addr::LOCALHOST),
+        };
+        let flag = |enabled: bool| if enabled { "1" } else { "0" };
+        Self {
+            instance: truncate(instance, 63).to_string(),
+            port: http.port(),
+            address,
+            txt: vec![
+                String::from("txtvers=1"),
+                format!("name={instance}"),
+                format!("version={}", capabilities::VERSION),
+                String::from("path=/status"),
+                format!("features={}", capabilities::features().join(",")),
+                format!("control={}", flag(control)),
+                format!("masks={}", flag(masks)),
+            ],
+        }
+    }
+
+    /// The full name of the instance, e.g. "garden._motion-detect._tcp.local".
+    pub fn full_name(&self) -> String {
+        format!("{}.{SERVICE_TYPE}", self.instance)
+    }
+}
+
+
+struct Shared {
+    service: Mutex<Service>,
+    changed: Mutex<Option<Service>>,    // Announced before the next one, then withdrawn.
+    withdrawn: AtomicBool,
+}
+
+
+/// Announces the HTTP status server over multicast DNS, for --mdns, so dashboards on the LAN find
+/// every detector without configuration. DNS-SD browsers asking for `_motion-detect._tcp` get a
+/// PTR record to the instance, its SRV record with the port, its TXT records and the address of
+/// the host. Probing for conflicting names is left out: instances need distinct --name values.
+///
+/// A thread of its own answers queries and announces the service after every (re)start, and
+/// withdraws it, records with a lifetime of 0, on `withdraw`. Failing to send only delays
+/// discovery until the next query, so it's never reported.
+pub struct Announcer {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+
+impl Announcer {
+
+    /// Joins the mDNS group and starts announcing. Fails if port 5353 can't be bound, e.g. when
+    /// another responder holds it on a platform without SO_REUSEPORT.
+    pub fn start(service: Service) -> io::Result<Self> {
+        let socket = bind()?;
+        socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let shared = Arc::new(Shared { service: Mutex::new(service), changed: Mutex::new(None), withdrawn: AtomicBool::new(false) });
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || respond(&socket, &thread_shared));
+        Ok(Self { shared, thread: Some(thread) })
+    }
+
+    /// Announces the service under a new name, port or TXT records, withdrawing the old one.
+    pub fn update(&self, service: Service) {
+        let mut current = self.shared.service.lock().unwrap();
+        if *current != service {
+            let old = std::mem::replace(&mut *current, service);
+            self.shared.changed.lock().unwrap().get_or_insert(old);
+        }
+    }
+
+    /// Withdraws the announcement, for a graceful shutdown. Returns once it's sent.
+    pub fn withdraw(mut self) {
+        self.shared.withdrawn.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+
+// The responder thread, until withdrawn.
+fn respond(socket: &UdpSocket, shared: &Shared) {
+    let group = SocketAddr::V4(SocketAddrV4::new(GROUP, PORT));
+    let mut announcements = 0;
+    let mut next_announcement = Instant::now();
+    let mut buffer = [0u8; MAX_MESSAGE];
+    loop {
+        if shared.withdrawn.load(Ordering::SeqCst) {
+            let service = shared.service.lock().unwrap().clone();
+            let _ = socket.send_to(&response(&service, 0, &[], false, true), group);
+            return;
+        }
+        if let Some(old) = shared.changed.lock().unwrap().take() {
+            let _ = socket.send_to(&response(&old, 0, &[], false, true), group);
+            (announcements, next_announcement) = (0, Instant::now());
+        }
+        if announcements < ANNOUNCEMENTS.len() && Instant::now() >= next_announcement {
+            let service = shared.service.lock().unwrap().clone();
+            let _ = socket.send_to(&response(&service, 0, &[], true, false), group);
+            announcements += 1;
+            next_announcement += ANNOUNCEMENTS.get(announcements).copied().unwrap_or_default();
+        }
+
+        let (length, sender) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(_) => continue, // Timed out, or a message that didn't fit.
+        };
+        let Some(query) = Query::parse(&buffer[.. length]) else {
+            continue;
+        };
+        let service = shared.service.lock().unwrap().clone();
+        let (answers, enumeration) = query.wants(&service);
+        if !answers {
+            continue;
+        }
+        // Queries from ports other than 5353 are one-shot resolvers expecting a direct answer.
+        if sender.port() == PORT {
+            let _ = socket.send_to(&response(&service, 0, &[], enumeration, false), group);
+        } else {
+            let _ = socket.send_to(&response(&service, query.id, query.questions, enumeration, false), sender);
+        }
+    }
+}
+
+
+// The questions of a query, as far as they're needed to answer it.
+struct Query<'a> {
+    id: u16,
+    questions: &'a [u8],            // Echoed in direct answers.
+    names: Vec<(String, u16)>,      // Lowercase names and types asked for.
+}
+
+
+impl<'a> Query<'a> {
+
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        let field = |offset: usize| Some(u16::from_be_bytes([*message.get(offset)?, *message.get(offset + 1)?]));
+        let (id, flags, count) = (field(0)?, field(2)?, field(4)?);
+        // Responses, and queries of any other opcode, aren't for a responder.
+        if flags & 0xf800 != 0 {
+            return None;
+        }
+        let mut offset = 12;
+        let mut names = Vec::new();
+        for _ in 0 .. count {
+            let (name, next) = read_name(message, offset)?;
+            names.push((name.to_lowercase(), field(next)?));
+            offset = next + 4;
+        }
+        Some(Self { id, questions: message.get(12 .. offset)?, names })
+    }
+
+    // Whether the service answers, and whether it's an enumeration of every service type.
+    fn wants(&self, service: &Service) -> (bool, bool) {
+        let (instance, host) = (service.full_name().to_lowercase(), host_name().to_lowercase());
+        let mut answers = false;
+        let mut enumeration = false;
+        for (name, kind) in &self.names {
+            let of = |wanted: u16| *kind == wanted || *kind == TYPE_ANY;
+            if name == SERVICES && of(TYPE_PTR) {
+                enumeration = true;
+            }
+            answers |= (name == SERVICE_TYPE && of(TYPE_PTR))
+                || (*name == instance && (of(TYPE_SRV) || of(TYPE_TXT)))
+                || (*name == host && of(TYPE_A));
+        }
+        (answers || enumeration, enumeration)
+    }
+}
+
+
+/// An instance found by `browse`.
+#[derive(Debug, Clone, Default)]
+pub struct Found {
+    pub instance: String,
+    pub host: String,
+    pub address: Option<Ipv4Addr>,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+
+/// Asks the LAN for every `_motion-detect._tcp` instance, and collects the answers coming within
+/// `timeout`. The query goes out from a port of its own, so responders answer it directly, and it
+/// works alongside any responder on this host, including an `Announcer` of this process.
+pub fn browse(timeout: Duration) -> io::Result<Vec<Found>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_loop_v4(true)?;
+    let mut query = Vec::new();
+    for field in [0x4d44u16, 0, 1, 0, 0, 0] {
+        query.extend_from_slice(&field.to_be_bytes());
+    }
+    query.extend_from_slice(&name_bytes(SERVICE_TYPE));
+    query.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    socket.send_to(&query, (GROUP, PORT))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut found: Vec<Found> = Vec::new();
+    let mut addresses: Vec<(String, Ipv4Addr)> = Vec::new();
+    let mut buffer = [0u8; MAX_MESSAGE];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        socket.set_read_timeout(Some(left))?;
+        let length = match socket.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            Err(err) => return Err(err),
+        };
+        let message = &buffer[.. length];
+        for (name, kind, data) in records(message).unwrap_or_default() {
+            if kind == TYPE_A && data.len() == 4 {
+                let octets = &message[data];
+                addresses.push((name, Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])));
+                continue;
+            }
+            let instance = match kind {
+                TYPE_PTR if name.eq_ignore_ascii_case(SERVICE_TYPE) => read_name(message, data.start).map(|(target, _)| target),
+                TYPE_SRV | TYPE_TXT => Some(name),
+                _ => None,
+            };
+            let Some(instance) = instance.as_deref().and_then(|name| name.strip_suffix(SERVICE_TYPE)).and_then(|instance| instance.strip_suffix('.')) else {
+                continue;
+            };
+            let index = match found.iter().position(|entry| entry.instance == instance) {
+                Some(index) => index,
+                None => {
+                    found.push(Found { instance: instance.to_string(), ..Found::default() });
+                    found.len() - 1
+                }
+            };
+            let entry = &mut found[index];
+            match kind {
+                TYPE_SRV if data.len() > 6 => {
+                    entry.port = u16::from_be_bytes([message[data.start + 4], message[data.start + 5]]);
+                    entry.host = read_name(message, data.start + 6).map(|(host, _)| host).unwrap_or_default();
+                }
+                TYPE_TXT => {
+                    let mut offset = data.start;
+                    entry.txt.clear();
+                    while offset < data.end {
+                        let end = (offset + 1 + message[offset] as usize).min(data.end);
+                        entry.txt.push(String::from_utf8_lossy(&message[offset + 1 .. end]).into_owned());
+                        offset = end;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    for entry in &mut found {
+        entry.address = addresses.iter().find(|(host, _)| host.eq_ignore_ascii_case(&entry.host)).map(|(_, address)| *address);
+    }
+    Ok(found)
+}
+
+
+// The records of every section of a response: their name, type and where their data is.
+fn records(message: &[u8]) -> Option<Vec<(String, u16, std::ops::Range<usize>)>> {
+    let field = |offset: usize| Some(u16::from_be_bytes([*message.get(offset)?, *message.get(offset + 1)?]));
+    if field(2)? & 0x8000 == 0 {
+        return None; // A query.
+    }
+    let mut offset = 12;
+    for _ in 0 .. field(4)? {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+    let count = field(6)? as usize + field(8)? as usize + field(10)? as usize;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0 .. count {
+        let (name, next) = read_name(message, offset)?;
+        let (kind, length) = (field(next)?, field(next + 8)? as usize);
+        let data = next + 10 .. next + 10 + length;
+        if data.end > message.len() {
+            return None;
+        }
+        records.push((name, kind, data.clone()));
+        offset = data.end;
+    }
+    Some(records)
+}
+
+
+// A response with every record of the service, and the PTR record of the service type for
+// enumerations. A goodbye has them expire right away, all but the host's address, which other
+// responders on the host may announce as well.
+fn response(service: &Service, id: u16, questions: &[u8], enumeration: bool, goodbye: bool) -> Vec<u8> {
+    let (host_ttl, other_ttl) = if goodbye { (0, 0) } else { (HOST_TTL, OTHER_TTL) };
+    let question_count = if questions.is_empty() { 0 } else { count_questions(questions) };
+    let answer_count = 3 + enumeration as u16 + !goodbye as u16;
+    let mut message = Vec::with_capacity(MAX_MESSAGE);
+    for field in [id, 0x8400, question_count, answer_count, 0, 0] {
+        message.extend_from_slice(&field.to_be_bytes());
+    }
+    message.extend_from_slice(questions);
+
+    let (instance, host) = (service.full_name(), host_name());
+    if enumeration {
+        record(&mut message, SERVICES, TYPE_PTR, CLASS_IN, other_ttl, &name_bytes(SERVICE_TYPE));
+    }
+    record(&mut message, SERVICE_TYPE, TYPE_PTR, CLASS_IN, other_ttl, &name_bytes(&instance));
+    let mut srv = Vec::new();
+    for field in [0, 0, service.port] {
+        srv.extend_from_slice(&field.to_be_bytes());
+    }
+    srv.extend_from_slice(&name_bytes(&host));
+    record(&mut message, &instance, TYPE_SRV, CLASS_IN | CACHE_FLUSH, host_ttl, &srv);
+    let txt: Vec<u8> = service.txt.iter()
+        .flat_map(|entry| {
+            let entry = truncate(entry, 255);
+            std::iter::once(entry.len() as u8).chain(entry.bytes())
+        })
+        .collect();
+    record(&mut message, &instance, TYPE_TXT, CLASS_IN | CACHE_FLUSH, other_ttl, &txt);
+    if !goodbye {
+        record(&mut message, &host, TYPE_A, CLASS_IN | CACHE_FLUSH, HOST_TTL, &service.address.octets());
+    }
+    message
+}
+
+
+fn record(message: &mut Vec<u8>, name: &str, kind: u16, class: u16, ttl: u32, data: &[u8]) {
+    message.extend_from_slice(&name_bytes(name));
+    message.extend_from_slice(&kind.to_be_bytes());
+    message.extend_from_slice(&class.to_be_bytes());
+    message.extend_from_slice(&ttl.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+}
+
+
+// A name as DNS labels, uncompressed. The instance label may hold dots and spaces, only the
+// service type and "local" are split.
+fn name_bytes(name: &str) -> Vec<u8> {
+    let (first, rest) = match name.strip_suffix(SERVICE_TYPE).and_then(|instance| instance.strip_suffix('.')) {
+        Some(instance) => (Some(instance), SERVICE_TYPE),
+        None => (None, name),
+    };
+    let mut bytes = Vec::new();
+    for label in first.into_iter().chain(rest.split('.')).filter(|label| !label.is_empty()) {
+        let label = truncate(label, 63);
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    bytes.push(0);
+    bytes
+}
+
+
+// A name at `offset`, following compression pointers, and the offset after it.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0 .. 128 {
+        let length = *message.get(offset)? as usize;
+        match length {
+            0 => return Some((labels.join("."), end.unwrap_or(offset + 1))),
+            length if length & 0xc0 == 0xc0 => {
+                end.get_or_insert(offset + 2);
+                offset = (length & 0x3f) << 8 | *message.get(offset + 1)? as usize;
+            }
+            length => {
+                labels.push(String::from_utf8_lossy(message.get(offset + 1 .. offset + 1 + length)?).into_owned());
+                offset += 1 + length;
+            }
+        }
+    }
+    None // A loop of pointers.
+}
+
+
+fn count_questions(questions: &[u8]) -> u16 {
+    let mut offset = 0;
+    let mut count = 0;
+    while let Some((_, next)) = read_name(questions, offset) {
+        offset = next + 4;
+        count += 1;
+        if offset >= questions.len() {
+            break;
+        }
+    }
+    count
+}
+
+
+// The host's own name in .local, from its first label.
+fn host_name() -> String {
+    let name = system_host_name().unwrap_or_default();
+    let label: String = name.split('.').next().unwrap_or_default().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' })
+        .collect();
+    format!("{}.local", if label.is_empty() { "motion-detect" } else { &label })
+}
+
+
+fn system_host_name() -> Option<String> {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn gethostname(name: *mut std::os::raw::c_char, len: usize) -> std::os::raw::c_int;
+        }
+        let mut buffer = [0u8; 256];
+        if unsafe { gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+            return None;
+        }
+        let end = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+        Some(String::from_utf8_lossy(&buffer[.. end]).into_owned())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok()
+    }
+}
+
+
+// The address multicast goes out from, nothing is sent to find it.
+fn outgoing_address() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((GROUP, PORT)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(address) if !address.is_unspecified() => Some(address),
+        _ => None,
+    }
+}
+
+
+// At most `limit` bytes of the text, cut at a character boundary.
+fn truncate(text: &str, limit: usize) -> &str {
+    let mut end = text.len().min(limit);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[.. end]
+}
+
+
+// Port 5353 shared with any other responder on the host, as Avahi or mDNSResponder do.
+#[cfg(target_os = "linux")]
+fn bind() -> io::Result<UdpSocket> {
+    use std::os::fd::FromRawFd;
+
+    unsafe {
+        let fd = ffi::socket(ffi::AF_INET, ffi::SOCK_DGRAM | ffi::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let on: std::os::raw::c_int = 1;
+        let address = ffi::SockaddrIn { family: ffi::AF_INET as u16, port: PORT.to_be(), address: [0; 4], zero: [0; 8] };
+        let bound = [ffi::SO_REUSEADDR, ffi::SO_REUSEPORT].iter()
+            .all(|option| ffi::setsockopt(fd, ffi::SOL_SOCKET, *option, (&on as *const std::os::raw::c_int).cast(), 4) == 0)
+            && ffi::bind(fd, (&address as *const ffi::SockaddrIn).cast(), std::mem::size_of::<ffi::SockaddrIn>() as u32) == 0;
+        if !bound {
+            let err = io::Error::last_os_error();
+            ffi::close(fd);
+            return Err(err);
+        }
+        Ok(UdpSocket::from_raw_fd(fd))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind() -> io::Result<UdpSocket> {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service {
+            instance: String::from("Garden cam"),
+            port: 8080,
+            address: Ipv4Addr::new(192, 168, 1, 20),
+            txt: vec![String::from("txtvers=1"), String::from("path=/status")],
+        }
+    }
+
+
+    // The records of a response as their name, type, lifetime and data.
+    fn decode(message: &[u8]) -> Vec<(String, u16, u32, Vec<u8>)> {
+        records(message).unwrap().into_iter()
+            .map(|(name, kind, data)| {
+                let ttl = u32::from_be_bytes(message[data.start - 6 .. data.start - 2].try_into().unwrap());
+                (name, kind, ttl, message[data].to_vec())
+            })
+            .collect()
+    }
+
+
+    #[test]
+    fn the_announcement_decodes_to_the_service() {
+        let service = service();
+        let message = response(&service, 0, &[], false, false);
+        let host = host_name();
+        let records = decode(&message);
+        let kinds: Vec<_> = records.iter().map(|(name, kind, ..)| (name.as_str(), *kind)).collect();
+        assert_eq!(kinds, [
+            (SERVICE_TYPE, TYPE_PTR),
+            ("Garden cam._motion-detect._tcp.local", TYPE_SRV),
+            ("Garden cam._motion-detect._tcp.local", TYPE_TXT),
+            (host.as_str(), TYPE_A),
+        ]);
+        assert_eq!(read_name(&records[0].3, 0).unwrap().0, service.full_name());
+        // The SRV record's priority, weight, port and host.
+        let srv = &records[1].3;
+        assert_eq!((&srv[.. 4], u16::from_be_bytes([srv[4], srv[5]])), ([0u8, 0, 0, 0].as_slice(), 8080));
+        assert_eq!(read_name(srv, 6).unwrap().0, host);
+        assert_eq!(records[2].3, b"\x09txtvers=1\x0cpath=/status");
+        assert_eq!(records[3].3, [192, 168, 1, 20]);
+        let ttls: Vec<_> = records.iter().map(|(_, _, ttl, _)| *ttl).collect();
+        assert_eq!(ttls, [OTHER_TTL, HOST_TTL, OTHER_TTL, HOST_TTL]);
+    }
+
+
+    #[test]
+    fn a_goodbye_expires_all_but_the_address() {
+        let records = decode(&response(&service(), 0, &[], true, true));
+        let kinds: Vec<_> = records.iter().map(|(_, kind, ttl, _)| (*kind, *ttl)).collect();
+        assert_eq!(kinds, [(TYPE_PTR, 0), (TYPE_PTR, 0), (TYPE_SRV, 0), (TYPE_TXT, 0)]);
+        assert_eq!(records[0].0, SERVICES);
+    }
+
+
+    #[test]
+    fn a_direct_answer_echoes_the_query() {
+        let mut query = Vec::new();
+        for field in [0x1234u16, 0, 1, 0, 0, 0] {
+            query.extend_from_slice(&field.to_be_bytes());
+        }
+        query.extend_from_slice(&name_bytes("Garden cam._motion-detect._tcp.local"));
+        query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        let parsed = Query::parse(&query).unwrap();
+        assert_eq!(parsed.names, [(String::from("garden cam._motion-detect._tcp.local"), TYPE_SRV)]);
+        assert_eq!(parsed.wants(&service()), (true, false));
+        let answer = response(&service(), parsed.id, parsed.questions, false, false);
+        assert_eq!(&answer[.. 4], &[0x12, 0x34, 0x84, 0]);
+        assert_eq!(answer[12 .. query.len()], query[12 ..]);
+        assert_eq!(decode(&answer).len(), 4);
+        // A response is no query.
+        assert!(Query::parse(&answer).is_none());
+    }
+
+
+    #[test]
+    fn compressed_names_follow_their_pointers() {
+        // "local" at 0, then "cam" pointing to it.
+        let message = b"\x05local\x00\x03cam\xc0\x00";
+        assert_eq!(read_name(message, 7), Some((String::from("cam.local"), 13)));
+        assert_eq!(read_name(b"\xc0\x00", 0), None);
+    }
+}
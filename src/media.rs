@@ -0,0 +1,267 @@
+use std::{
+    collections::{ HashSet, VecDeque },
+    path::PathBuf,
+    sync::{ Arc, Condvar, Mutex, MutexGuard },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use crate::{ settings::MediaOverflow, sidecar::Sidecar };
+
+/// How long the queue may take to write what's left on shutdown.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Writes the images of a job from its frame, returning its failures.
+type Write = Box<dyn FnOnce(&[u8]) -> Vec<String> + Send>;
+
+
+/// An image a job writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaFile {
+    pub path: PathBuf,
+    pub zone: Option<String>,   // The zone of a zone snapshot.
+}
+
+
+/// Images waiting to be written from one full frame: the snapshots of a movement, or a timelapse
+/// frame. The frame is shared with the detection thread until the next capture, which then takes
+/// a buffer of its own, so a queued job holds the only copy of its frame. With a sidecar, each
+/// image written gets it next to it once complete.
+pub struct MediaJob {
+    pub id: Option<u64>,        // The movement the snapshots are of, None for timelapse frames.
+    pub files: Vec<MediaFile>,  // What the job writes, if it gets to.
+    frame: Arc<Vec<u8>>,
+    write: Write,
+    sidecar: Option<Sidecar>,
+}
+
+
+impl MediaJob {
+
+    pub fn new(id: Option<u64>, files: Vec<MediaFile>, frame: Arc<Vec<u8>>, write: impl FnOnce(&[u8]) -> Vec<String> + Send + 'static) -> Self {
+        Self { id, files, frame, write: Box::new(write), sidecar: None }
+    }
+
+    /// Rewrites the sidecars of images written before, e.g. once their movement stopped. The
+    /// images that aren't there, dropped or failed, get none.
+    pub fn sidecars(id: Option<u64>, files: Vec<MediaFile>, sidecar: Sidecar) -> Self {
+        Self::new(id, files, Arc::default(), |_| Vec::new()).with_sidecar(Some(sidecar))
+    }
+
+    pub fn with_sidecar(mut self, sidecar: Option<Sidecar>) -> Self {
+        self.sidecar = sidecar;
+        self
+    }
+
+    /// Bytes of its frame.
+    pub fn bytes(&self) -> usize {
+        self.frame.len()
+    }
+}
+
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<MediaJob>,
+    writing: bool,              // The worker holds one more job.
+    closed: bool,
+    written: u64,
+    dropped: u64,
+    skipped: HashSet<u64>,      // Movements that lost a job, until asked about.
+    warnings: Vec<String>,
+}
+
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,           // A job was queued or taken, or the queue closed.
+}
+
+
+impl Queue {
+
+    // Jobs waiting with a frame, those the capacity is of.
+    fn framed(&self) -> usize {
+        self.jobs.iter().filter(|job| job.bytes() > 0).count()
+    }
+}
+
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+/// Writes snapshots and timelapse frames on a thread of its own, so a slow disk doesn't stall
+/// detection, through a queue of at most `capacity` jobs. A full queue drops the oldest job or the
+/// new one, as the `MediaOverflow` policy says, or makes the detection thread wait for room, at
+/// most `wait`, before dropping the new one. Dropped jobs are counted, and the movements they were
+/// of remembered, so their events can tell their snapshots are missing.
+pub struct MediaQueue {
+    shared: Arc<Shared>,
+    capacity: usize,
+    overflow: MediaOverflow,
+    wait: Duration,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+
+impl MediaQueue {
+
+    pub fn new(capacity: usize, overflow: MediaOverflow, wait: Duration) -> Self {
+        let shared = Arc::new(Shared { queue: Mutex::new(Queue::default()), changed: Condvar::new() });
+        let worker = {
+            let shared = shared.clone();
+            thread::spawn(move || work(&shared))
+        };
+        Self { shared, capacity: capacity.max(1), overflow, wait, worker: Some(worker) }
+    }
+
+    /// Queues a job. False if it was dropped, the queue being full, in which case its movement
+    /// counts as skipped. With drop-oldest the new job always gets in, the job it pushed out is
+    /// then the one skipped. A job rewriting sidecars, without a frame, always gets in.
+    pub fn push(&self, job: MediaJob) -> bool {
+        let mut queue = self.shared.lock();
+        if queue.framed() >= self.capacity && job.bytes() > 0 {
+            match self.overflow {
+                MediaOverflow::DropOldest => {
+                    let oldest = queue.jobs.iter().position(|job| job.bytes() > 0);
+                    if let Some(oldest) = oldest.and_then(|oldest| queue.jobs.remove(oldest)) {
+                        drop_job(&mut queue, &oldest);
+                    }
+                }
+                MediaOverflow::DropNewest => {
+                    drop_job(&mut queue, &job);
+                    return false;
+                }
+                MediaOverflow::Block => {
+                    let wait = self.wait;
+                    queue = self.shared.changed
+                        .wait_timeout_while(queue, wait, |queue| queue.framed() >= self.capacity && !queue.closed)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0;
+                    if queue.framed() >= self.capacity {
+                        drop_job(&mut queue, &job);
+                        return false;
+                    }
+                }
+            }
+        }
+        queue.jobs.push_back(job);
+        self.shared.changed.notify_all();
+        true
+    }
+
+    /// Whether movement `id` lost any of its jobs, forgetting it. Asked once it stops.
+    pub fn take_skipped(&self, id: u64) -> bool {
+        self.shared.lock().skipped.remove(&id)
+    }
+
+    /// What went wrong since the last call: failed writes, and jobs pushed out by drop-oldest.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.shared.lock().warnings)
+    }
+
+    /// Jobs written since launch, whether or not each of their files could be, sidecars alone
+    /// not counted.
+    pub fn written(&self) -> u64 {
+        self.shared.lock().written
+    }
+
+    /// Jobs dropped with a full queue since launch.
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    /// Jobs waiting now, the one being written included.
+    pub fn queued(&self) -> usize {
+        let queue = self.shared.lock();
+        queue.jobs.len() + queue.writing as usize
+    }
+
+    /// Bytes of the frames waiting now, the one being written excluded.
+    pub fn queued_bytes(&self) -> usize {
+        self.shared.lock().jobs.iter().map(MediaJob::bytes).sum()
+    }
+
+    /// The most jobs it holds, the one being written included.
+    pub fn capacity(&self) -> usize {
+        self.capacity + 1
+    }
+
+    /// Writes what's queued, waiting up to `timeout`. Returns the jobs left unwritten.
+    pub fn finish(mut self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.shared.lock();
+        queue.closed = true;
+        self.shared.changed.notify_all();
+        while !queue.jobs.is_empty() || queue.writing {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return queue.jobs.len() + queue.writing as usize;
+            }
+            queue = self.shared.changed.wait_timeout(queue, left).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+        }
+        drop(queue);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        0
+    }
+}
+
+
+impl Drop for MediaQueue {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+
+// Counts a job that won't be written, and remembers its movement.
+fn drop_job(queue: &mut Queue, job: &MediaJob) {
+    queue.dropped += 1;
+    if let Some(id) = job.id {
+        queue.skipped.insert(id);
+    }
+    let what = job.id.map_or("a timelapse frame".to_string(), |id| format!("the snapshots of movement {id}"));
+    queue.warnings.push(format!("media queue full, dropped {what}"));
+}
+
+
+// Writes the jobs in order until the queue is closed and empty.
+fn work(shared: &Shared) {
+    loop {
+        let mut queue = shared.lock();
+        let job = loop {
+            match queue.jobs.pop_front() {
+                Some(job) => break job,
+                None if queue.closed => return,
+                None => queue = shared.changed.wait(queue).unwrap_or_else(|poisoned| poisoned.into_inner()),
+            }
+        };
+        queue.writing = true;
+        shared.changed.notify_all();
+        drop(queue);
+
+        let MediaJob { files, frame, write, sidecar, .. } = job;
+        let images = !frame.is_empty();
+        let mut failures = write(&frame);
+        drop(frame);
+        // Only next to complete images, which are renamed into place once written.
+        for file in files.iter().filter(|file| sidecar.is_some() && file.path.exists()) {
+            if let Err(err) = sidecar.as_ref().expect("Filtered on a sidecar").write(file) {
+                failures.push(format!("sidecar {}: {err}", Sidecar::path(&file.path).display()));
+            }
+        }
+
+        let mut queue = shared.lock();
+        queue.writing = false;
+        queue.written += images as u64;
+        queue.warnings.extend(failures.into_iter().map(|failure| format!("failed to save {failure}")));
+        shared.changed.notify_all();
+    }
+}
@@ -0,0 +1,58 @@
+use crate::json;
+
+/// Bytes held by the buffers of each part of the pipeline, reported at /status and checked against
+/// --max-memory. Only buffers that grow with the frame size or with backpressure are counted, not
+/// the fixed size structures around them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub source: usize,      // Read, conversion and queued frames of the source.
+    pub snapshots: usize,   // Copy of the latest full frame, and the frames the media queue can hold.
+    pub thumbnails: usize,  // Thumbnails and temporal average sums.
+    pub detector: usize,    // References, masks and noise maps of the diff strategies, both A/B variants.
+    pub http: usize,        // Messages queued for WebSocket clients.
+}
+
+
+impl MemoryUsage {
+
+    pub fn total(&self) -> usize {
+        self.source + self.snapshots + self.thumbnails + self.detector + self.http
+    }
+
+    pub fn to_object(&self) -> json::Object {
+        json::Object::new()
+            .field("source", self.source)
+            .field("snapshots", self.snapshots)
+            .field("thumbnails", self.thumbnails)
+            .field("detector", self.detector)
+            .field("http", self.http)
+            .field("total", self.total())
+    }
+
+    /// "source 1.2 MB, snapshots 921.6 kB, ...", the parts holding anything.
+    pub fn text(&self) -> String {
+        let parts = [
+            ("source", self.source),
+            ("snapshots", self.snapshots),
+            ("thumbnails", self.thumbnails),
+            ("detector", self.detector),
+            ("http", self.http),
+        ];
+        parts.iter()
+            .filter(|(_, bytes)| *bytes > 0)
+            .map(|(name, bytes)| format!("{name} {}", format_bytes(*bytes)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+
+/// Sizes in decimal units, as --max-memory takes them.
+pub fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0 .. 1_000 => format!("{bytes} B"),
+        1_000 .. 1_000_000 => format!("{:.1} kB", bytes as f64 / 1e3),
+        1_000_000 .. 1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.1} GB", bytes as f64 / 1e9),
+    }
+}
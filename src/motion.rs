@@ -0,0 +1,589 @@
+use std::time::{ Duration, Instant, SystemTime };
+
+use crate::{ clock::TimeSource, contract, json };
+
+/// Motion state changes reported by the tracker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionEvent {
+    /// A first frame crossed the start threshold, the movement starts once enough frames in a row
+    /// confirm it. Only with more than one confirmation frame.
+    Provisional { id: u64 },
+    /// The frames that followed a `Provisional` didn't confirm it.
+    ProvisionalCancel { id: u64 },
+    /// A movement started. If it only exists because the previous one hit the maximum duration,
+    /// `continued_from` holds the id of that previous movement. `provisional` tells whether a
+    /// `Provisional` with the same id came first. It started `at` the capture time of its first
+    /// frame, or, if it was `pre_existing`, already in progress when the detector became ready,
+    /// at the ready time. `between` are the capture times of the last frame before it and of its
+    /// first, the change came in between, unless there's no frame before to tell.
+    Start { id: u64, continued_from: Option<u64>, provisional: bool, pre_existing: bool, at: Instant, between: Option<(Instant, Instant)> },
+    /// A movement stopped after lasting for `duration`. It ended `at` the capture time of its last
+    /// moving frame plus the tail, which can be before the frame that reported it, or with tail
+    /// frames at the frame that ended it. `between` are the capture times of its last moving frame
+    /// and of the frame after, unless it was split by the maximum duration.
+    Stop { id: u64, reason: StopReason, duration: Duration, at: Instant, stats: MotionStats, between: Option<(Instant, Instant)> },
+}
+
+
+/// How big a movement was, over every frame from its start to its stop.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MotionStats {
+    pub peak: f32,                  // Highest percentage of changed pixels.
+    pub frames: u32,
+    pub frames_above_threshold: u32,// Frames with enough changed pixels to keep the movement going.
+    score_sum: f32,
+}
+
+
+impl MotionStats {
+
+    /// Mean percentage of changed pixels.
+    pub fn mean(&self) -> f32 {
+        self.score_sum / self.frames.max(1) as f32
+    }
+
+    fn record(&mut self, score: f32, above_threshold: bool) {
+        self.peak = self.peak.max(score);
+        self.score_sum += score;
+        self.frames += 1;
+        self.frames_above_threshold += above_threshold as u32;
+    }
+}
+
+
+impl MotionEvent {
+
+    /// The movement the event belongs to.
+    pub fn id(&self) -> u64 {
+        match self {
+            MotionEvent::Provisional { id } | MotionEvent::ProvisionalCancel { id } => *id,
+            MotionEvent::Start { id, .. } | MotionEvent::Stop { id, .. } => *id,
+        }
+    }
+
+    /// The line printed in text mode.
+    pub fn text(&self) -> &'static str {
+        match self {
+            MotionEvent::Provisional { .. } => "provisional",
+            MotionEvent::ProvisionalCancel { .. } => "provisional cancel",
+            MotionEvent::Start { .. } => "start",
+            MotionEvent::Stop { .. } => "stop",
+        }
+    }
+
+    /// The event as a single line JSON object, stamped with the given wall-clock time and where
+    /// that time came from.
+    pub fn to_json(self, time: SystemTime, source: TimeSource) -> String {
+        self.to_object(time, source).finish()
+    }
+
+    /// Like `to_json`, but open for more fields.
+    pub fn to_object(self, time: SystemTime, source: TimeSource) -> json::Object {
+        contract::Event::new(self, time, source).to_object()
+    }
+}
+
+
+/// Why a movement stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum StopReason {
+    /// No movement for longer than the motion tail.
+    Tail,
+    /// The movement reached the maximum event duration and was split.
+    MaxDuration,
+    /// The input ended during the movement.
+    EndOfInput,
+    /// The system was suspended during the movement.
+    Suspend,
+    /// Another scene was activated during the movement, see profile::SceneProfiles.
+    SceneChange,
+}
+
+
+impl StopReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::Tail => "tail",
+            StopReason::MaxDuration => "max_duration",
+            StopReason::EndOfInput => "end_of_input",
+            StopReason::Suspend => "suspend",
+            StopReason::SceneChange => "scene_change",
+        }
+    }
+}
+
+
+/// The movement currently in progress.
+struct ActiveMotion {
+    id: u64,
+    start_time: Instant,
+    latest_movement_time: Instant,  // Capture time of the latest frame that kept it going.
+    latest_frame_time: Instant,
+    after_movement: Option<Instant>,    // Capture time of the frame after the latest moving one.
+    still_frames: u32,                  // Since the latest moving frame.
+    stats: MotionStats,
+}
+
+
+impl ActiveMotion {
+
+    // Moving on its frame `now`.
+    fn new(id: u64, start_time: Instant, now: Instant, stats: MotionStats) -> Self {
+        Self { id, start_time, latest_movement_time: now, latest_frame_time: now, after_movement: None, still_frames: 0, stats }
+    }
+
+    // When it ended if still in progress with the frame captured at `last`: a tail after its
+    // latest moving frame if that's sooner, or with that frame when counting tail frames.
+    fn tail_end(&self, tail_length: Duration, tail_frames: Option<u32>, last: Instant) -> Instant {
+        match tail_frames {
+            Some(_) => last,
+            None => (self.latest_movement_time + tail_length).min(last),
+        }
+    }
+}
+
+
+/// A movement waiting for its confirmation frames.
+struct PendingMotion {
+    id: u64,
+    start_time: Instant,
+    before: Option<Instant>,    // Capture time of the frame before the first one.
+    frames: u32,
+    stats: MotionStats,
+}
+
+
+/// Turns per-frame changed pixel counts into "start" and "stop" events.
+///
+/// A movement starts when more than `start_count` pixels change, in `confirm_frames` frames in a
+/// row. The first of them is reported as provisional, and cancelled if a frame in between doesn't
+/// make it. While it is active, any frame with
+/// more than `sustain_count` changed pixels refreshes the tail timer, so a subject that barely moves
+/// isn't chopped into many short movements. The movement stops once the tail length has passed
+/// without such a frame, or when it reaches the optional maximum duration, in which case a new
+/// movement starts right away if there is still motion in the frame.
+///
+/// If every frame since the detector became ready crossed the start threshold, something was
+/// already moving during the baseline capture: the movement is reported as pre-existing, started
+/// at the ready time.
+///
+/// Every time is a frame's capture time, so late processing doesn't stretch the tail. If frames
+/// stop coming for longer than the tail (and than two frame intervals), the source stalled: the
+/// movement ends a tail after its last moving frame, reported by the first frame after the stall.
+pub struct MotionTracker {
+    tail_length: Duration,
+    tail_frames: Option<u32>,   // Counted instead of the tail length if set.
+    max_duration: Option<Duration>,
+    frame_interval: Duration,
+    start_count: i32,
+    sustain_count: i32,
+    confirm_frames: u32,
+    pending: Option<PendingMotion>,
+    active: Option<ActiveMotion>,
+    next_id: u64,
+    ready_at: Option<Instant>,  // Until a frame doesn't cross the start threshold.
+    previous_frame: Option<Instant>,
+}
+
+
+impl MotionTracker {
+
+    pub fn new(tail_length: Duration, start_count: i32, sustain_count: i32) -> Self {
+        Self {
+            tail_length,
+            tail_frames: None,
+            max_duration: None,
+            frame_interval: Duration::ZERO,
+            start_count,
+            sustain_count: sustain_count.min(start_count),
+            confirm_frames: 1,
+            pending: None,
+            active: None,
+            next_id: 1,
+            ready_at: None,
+            previous_frame: None,
+        }
+    }
+
+    /// Splits movements that last longer than `max_duration`.
+    pub fn with_max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Stops movements after this many frames in a row without movement rather than after the
+    /// tail length, for capture intervals too long for one to make sense.
+    pub fn with_tail_frames(mut self, tail_frames: Option<u32>) -> Self {
+        self.tail_frames = tail_frames.map(|frames| frames.max(1));
+        self
+    }
+
+    /// Only starts movements seen in this many consecutive frames, 1 starts them right away.
+    pub fn with_confirm_frames(mut self, confirm_frames: u32) -> Self {
+        self.confirm_frames = confirm_frames.max(1);
+        self
+    }
+
+    /// Sets the expected time between frames, so that slow capture isn't mistaken for a stall when
+    /// it's longer than the tail.
+    pub fn with_frame_interval(mut self, frame_interval: Duration) -> Self {
+        self.frame_interval = frame_interval;
+        self
+    }
+
+    pub fn set_frame_interval(&mut self, frame_interval: Duration) {
+        self.frame_interval = frame_interval;
+    }
+
+    /// The detector is ready, movements seen from the very first frame on started `now`.
+    pub fn ready(&mut self, now: Instant) {
+        self.ready_at = Some(now);
+        self.previous_frame = None;
+    }
+
+    /// Changes the pixel counts that start and sustain a movement, e.g. after the thumbnail size changed.
+    pub fn set_thresholds(&mut self, start_count: i32, sustain_count: i32) {
+        self.start_count = start_count;
+        self.sustain_count = sustain_count.min(start_count);
+    }
+
+    /// True if the changed pixel count is enough to start a movement.
+    pub fn exceeds_start(&self, changed_pixels: i32) -> bool {
+        changed_pixels > self.start_count
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// True while a movement is in progress or waiting for its confirmation frames.
+    pub fn is_busy(&self) -> bool {
+        self.active.is_some() || self.pending.is_some()
+    }
+
+    /// Id of the movement in progress.
+    pub fn active_id(&self) -> Option<u64> {
+        self.active.as_ref().map(|active| active.id)
+    }
+
+    /// Feeds the changed pixel count of a new frame, its percentage and its capture time, returns
+    /// the events caused by it (usually none).
+    pub fn update(&mut self, changed_pixels: i32, score: f32, now: Instant) -> Vec<MotionEvent> {
+        let mut events = Vec::new();
+        if let Some(active) = &self.active {
+            // After a stall, the frame is judged on its own, as the first one after the movement.
+            // Tail frames are frames, a stall is only told by the time between them if it's known.
+            let stall_after = match self.tail_frames {
+                Some(frames) if !self.frame_interval.is_zero() => Some(self.frame_interval * (frames + 1).max(2)),
+                Some(_) => None,
+                None => Some(self.tail_length.max(self.frame_interval * 2)),
+            };
+            if stall_after.is_some_and(|stall_after| now.saturating_duration_since(active.latest_frame_time) > stall_after) {
+                let at = match self.tail_frames {
+                    Some(_) => active.latest_frame_time,
+                    None => active.tail_end(self.tail_length, self.tail_frames, now),
+                };
+                let duration = at.saturating_duration_since(active.start_time);
+                let between = Some((active.latest_movement_time, active.after_movement.unwrap_or(now)));
+                events.push(MotionEvent::Stop { id: active.id, reason: StopReason::Tail, duration, at, stats: active.stats, between });
+                self.active = None;
+            }
+        }
+        let exceeds_start = self.exceeds_start(changed_pixels);
+        match &mut self.active {
+            None if !exceeds_start => {
+                if let Some(pending) = self.pending.take() {
+                    events.push(MotionEvent::ProvisionalCancel { id: pending.id });
+                }
+            }
+            None if self.confirm_frames == 1 => {
+                let start_time = self.ready_at.unwrap_or(now);
+                let between = self.previous_frame.filter(|_| self.ready_at.is_none()).map(|before| (before, now));
+                events.push(self.start(start_time, now, None, score, between));
+            }
+            None => {
+                let pending = match &mut self.pending {
+                    Some(pending) => pending,
+                    None => {
+                        let id = self.next_id;
+                        self.next_id += 1;
+                        events.push(MotionEvent::Provisional { id });
+                        let before = self.previous_frame;
+                        self.pending.insert(PendingMotion { id, start_time: now, before, frames: 0, stats: MotionStats::default() })
+                    }
+                };
+                pending.frames += 1;
+                pending.stats.record(score, true);
+                if pending.frames >= self.confirm_frames {
+                    // The movement started with its first frame, the statistics include all of them.
+                    let PendingMotion { id, start_time: first, before, stats, .. } = self.pending.take().expect("Pending motion was just updated");
+                    let start_time = self.ready_at.unwrap_or(first);
+                    self.active = Some(ActiveMotion::new(id, start_time, now, stats));
+                    events.push(MotionEvent::Start {
+                        id,
+                        continued_from: None,
+                        provisional: true,
+                        pre_existing: self.ready_at.is_some(),
+                        at: start_time,
+                        between: before.filter(|_| self.ready_at.is_none()).map(|before| (before, first)),
+                    });
+                }
+            }
+            Some(active) => {
+                let moving = changed_pixels > self.sustain_count;
+                if moving {
+                    active.latest_movement_time = now;
+                    active.after_movement = None;
+                    active.still_frames = 0;
+                } else {
+                    active.after_movement.get_or_insert(now);
+                    active.still_frames += 1;
+                }
+                active.latest_frame_time = now;
+                active.stats.record(score, moving);
+                let duration = now.saturating_duration_since(active.start_time);
+                let (id, stats) = (active.id, active.stats);
+                let tail_over = match self.tail_frames {
+                    Some(frames) => active.still_frames >= frames,
+                    None => now.saturating_duration_since(active.latest_movement_time) > self.tail_length,
+                };
+                if self.max_duration.is_some_and(|max| duration >= max) {
+                    // Forced segmentation, the next movement picks up where this one left off,
+                    // with statistics of its own.
+                    self.active = None;
+                    events.push(MotionEvent::Stop { id, reason: StopReason::MaxDuration, duration, at: now, stats, between: None });
+                    if moving {
+                        events.push(self.start(now, now, Some(id), score, None));
+                    }
+                } else if !moving && tail_over {
+                    // No movement in current frame, and the tail has run out.
+                    let at = active.tail_end(self.tail_length, self.tail_frames, now);
+                    let duration = at.saturating_duration_since(active.start_time);
+                    let between = active.after_movement.map(|after| (active.latest_movement_time, after));
+                    self.active = None;
+                    events.push(MotionEvent::Stop { id, reason: StopReason::Tail, duration, at, stats, between });
+                }
+            }
+        }
+        if !exceeds_start || self.active.is_some() {
+            self.ready_at = None;
+        }
+        self.previous_frame = Some(now);
+        events
+    }
+
+    /// Stops the movement in progress once the input ended, `now` being the capture time of the
+    /// last frame. It ends a tail after its last moving frame, or with the last frame if sooner.
+    pub fn finish(&mut self, now: Instant) -> Option<MotionEvent> {
+        self.pending = None;
+        let active = self.active.take()?;
+        let at = active.tail_end(self.tail_length, self.tail_frames, now);
+        let duration = at.saturating_duration_since(active.start_time);
+        let between = active.after_movement.map(|after| (active.latest_movement_time, after));
+        Some(MotionEvent::Stop { id: active.id, reason: StopReason::EndOfInput, duration, at, stats: active.stats, between })
+    }
+
+    /// Ends what's in progress when frames can't be trusted to follow on from the previous ones,
+    /// e.g. after a suspend. A movement waiting for confirmation is cancelled, one in progress
+    /// ends a tail after its last moving frame, or with the last frame before if sooner.
+    pub fn interrupt(&mut self, reason: StopReason) -> Vec<MotionEvent> {
+        let mut events = Vec::new();
+        self.previous_frame = None;
+        if let Some(pending) = self.pending.take() {
+            events.push(MotionEvent::ProvisionalCancel { id: pending.id });
+        }
+        if let Some(active) = self.active.take() {
+            let at = active.tail_end(self.tail_length, self.tail_frames, active.latest_frame_time);
+            let duration = at.saturating_duration_since(active.start_time);
+            let between = active.after_movement.map(|after| (active.latest_movement_time, after));
+            events.push(MotionEvent::Stop { id: active.id, reason, duration, at, stats: active.stats, between });
+        }
+        events
+    }
+
+    // The starting frame is the first one of the movement's statistics. It may have started
+    // before it, if it was already in progress when the detector became ready.
+    fn start(&mut self, start_time: Instant, now: Instant, continued_from: Option<u64>, score: f32, between: Option<(Instant, Instant)>) -> MotionEvent {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut stats = MotionStats::default();
+        stats.record(score, true);
+        self.active = Some(ActiveMotion::new(id, start_time, now, stats));
+        MotionEvent::Start { id, continued_from, provisional: false, pre_existing: self.ready_at.is_some(), at: start_time, between }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+
+    // Feeds changed pixel counts, out of 100, one frame per INTERVAL, then ends the input.
+    fn events(tracker: &mut MotionTracker, counts: &[i32]) -> Vec<MotionEvent> {
+        let origin = Instant::now();
+        let mut events = Vec::new();
+        for (frame, count) in counts.iter().enumerate() {
+            events.extend(tracker.update(*count, *count as f32, origin + INTERVAL * frame as u32));
+        }
+        events.extend(tracker.finish(origin + INTERVAL * counts.len() as u32));
+        events
+    }
+
+
+    fn starts(events: &[MotionEvent]) -> usize {
+        events.iter().filter(|event| matches!(event, MotionEvent::Start { .. })).count()
+    }
+
+
+    // A subject moving at 25%, and at 5% for three frames in between, five times, then still.
+    fn oscillating() -> Vec<i32> {
+        let mut counts: Vec<i32> = (0 .. 5).flat_map(|_| [25, 5, 5, 5]).collect();
+        counts.extend([0; 10]);
+        counts
+    }
+
+
+    #[test]
+    fn sustain_threshold_keeps_an_oscillating_subject_in_one_movement() {
+        let mut tracker = MotionTracker::new(Duration::from_millis(250), 20, 4);
+        let events = events(&mut tracker, &oscillating());
+        assert_eq!(starts(&events), 1);
+        let Some(MotionEvent::Stop { reason, stats, .. }) = events.last() else {
+            panic!("The movement stopped: {events:?}");
+        };
+        assert_eq!(*reason, StopReason::Tail);
+        assert_eq!(stats.peak, 25.0);
+    }
+
+
+    #[test]
+    fn without_sustain_threshold_every_peak_is_a_movement() {
+        // The sustain count defaults to the start count, as before there was one.
+        let mut tracker = MotionTracker::new(Duration::from_millis(250), 20, 20);
+        assert_eq!(starts(&events(&mut tracker, &oscillating())), 5);
+    }
+
+
+    #[test]
+    fn sustain_count_is_never_above_start_count() {
+        let mut tracker = MotionTracker::new(Duration::from_millis(250), 20, 50);
+        assert_eq!(starts(&events(&mut tracker, &oscillating())), 5);
+        tracker.set_thresholds(20, 4);
+        assert_eq!(starts(&events(&mut tracker, &oscillating())), 1);
+    }
+
+
+    // Feeds changed pixel counts captured at the given milliseconds from `origin`.
+    fn feed(tracker: &mut MotionTracker, origin: Instant, frames: &[(u64, i32)]) -> Vec<MotionEvent> {
+        frames.iter()
+            .flat_map(|(millis, count)| tracker.update(*count, *count as f32, origin + Duration::from_millis(*millis)))
+            .collect()
+    }
+
+
+    fn stop_time(event: &MotionEvent) -> Option<(Instant, Duration)> {
+        match event {
+            MotionEvent::Stop { at, duration, reason: StopReason::Tail, .. } => Some((*at, *duration)),
+            _ => None,
+        }
+    }
+
+
+    #[test]
+    fn a_stall_ends_the_movement_a_tail_after_its_last_moving_frame() {
+        let origin = Instant::now();
+        let moving = [(0, 50), (100, 50), (200, 50), (300, 50), (400, 50)];
+        for (after_stall, starts_again) in [(0, false), (50, true)] {
+            let mut tracker = MotionTracker::new(Duration::from_millis(500), 20, 20).with_frame_interval(INTERVAL);
+            assert_eq!(starts(&feed(&mut tracker, origin, &moving)), 1);
+            // The camera stalled for 10s, the first frame after it reports the stop.
+            let events = feed(&mut tracker, origin, &[(10_400, after_stall)]);
+            assert_eq!(stop_time(&events[0]), Some((origin + Duration::from_millis(900), Duration::from_millis(900))));
+            // And is judged on its own.
+            assert_eq!(starts(&events) == 1, starts_again);
+            assert_eq!(tracker.is_active(), starts_again);
+        }
+    }
+
+
+    #[test]
+    fn a_burst_of_late_frames_stops_by_their_capture_times() {
+        let origin = Instant::now();
+        let mut tracker = MotionTracker::new(Duration::from_millis(500), 20, 20).with_frame_interval(INTERVAL);
+        feed(&mut tracker, origin, &[(0, 50), (100, 50), (200, 50), (300, 50)]);
+        // Queued while processing hung, the still frames all come at once, well after they were
+        // captured. The first one past the tail reports the stop, timed a tail after the last move.
+        let late: Vec<_> = (4 .. 15).map(|frame| (frame * 100, 0)).collect();
+        let mut stops = Vec::new();
+        for (frame, events) in late.iter().map(|frame| feed(&mut tracker, origin, &[*frame])).enumerate() {
+            stops.extend(events.iter().filter_map(stop_time).map(|stop| (frame + 4, stop)));
+        }
+        assert_eq!(stops, [(9, (origin + Duration::from_millis(800), Duration::from_millis(800)))]);
+    }
+
+
+    #[test]
+    fn slow_capture_is_no_stall() {
+        // Frames every 2s with a 1s tail: the time between two of them is no stall, the movement
+        // goes on until a still frame, and ends a tail after the last moving one.
+        let origin = Instant::now();
+        let mut tracker = MotionTracker::new(Duration::from_secs(1), 20, 20).with_frame_interval(Duration::from_secs(2));
+        let events = feed(&mut tracker, origin, &[(0, 50), (2000, 50), (4000, 50), (6000, 0)]);
+        assert_eq!(starts(&events), 1);
+        assert_eq!(stop_time(events.last().unwrap()), Some((origin + Duration::from_secs(5), Duration::from_secs(5))));
+    }
+
+
+    #[test]
+    fn a_movement_in_progress_when_ready_is_pre_existing() {
+        let origin = Instant::now();
+        for confirm_frames in [1, 3] {
+            let mut tracker = MotionTracker::new(Duration::from_millis(500), 20, 20).with_confirm_frames(confirm_frames);
+            tracker.ready(origin);
+            let events = feed(&mut tracker, origin, &[(0, 50), (100, 50), (200, 50), (300, 50)]);
+            let start = events.iter().find(|event| matches!(event, MotionEvent::Start { .. }));
+            assert!(matches!(start, Some(MotionEvent::Start { pre_existing: true, at, between: None, .. }) if *at == origin), "{events:?}");
+        }
+    }
+
+
+    #[test]
+    fn a_movement_after_a_still_frame_is_not_pre_existing() {
+        let origin = Instant::now();
+        let mut tracker = MotionTracker::new(Duration::from_millis(500), 20, 20);
+        tracker.ready(origin);
+        let events = feed(&mut tracker, origin, &[(0, 0), (100, 50)]);
+        let expected = origin + INTERVAL;
+        assert!(matches!(events[..], [MotionEvent::Start { pre_existing: false, at, between: Some((before, first)), .. }]
+            if at == expected && before == origin && first == expected), "{events:?}");
+    }
+
+
+    #[test]
+    fn a_suspend_stops_the_movement_with_its_last_frame() {
+        let origin = Instant::now();
+        let mut tracker = MotionTracker::new(Duration::from_millis(500), 20, 20).with_frame_interval(INTERVAL);
+        feed(&mut tracker, origin, &[(0, 50), (100, 50), (200, 0)]);
+        let events = tracker.interrupt(StopReason::Suspend);
+        // The tail hadn't run out, the movement ends with the last frame before the suspend.
+        let last_frame = origin + Duration::from_millis(200);
+        assert!(matches!(events[..], [MotionEvent::Stop { reason: StopReason::Suspend, at, duration, .. }]
+            if at == last_frame && duration == Duration::from_millis(200)), "{events:?}");
+        assert!(!tracker.is_active());
+        assert!(tracker.interrupt(StopReason::Suspend).is_empty());
+    }
+
+
+    #[test]
+    fn a_suspend_cancels_a_movement_waiting_for_confirmation() {
+        let origin = Instant::now();
+        let mut tracker = MotionTracker::new(Duration::from_millis(500), 20, 20).with_confirm_frames(3);
+        feed(&mut tracker, origin, &[(0, 50)]);
+        assert!(matches!(tracker.interrupt(StopReason::Suspend)[..], [MotionEvent::ProvisionalCancel { .. }]));
+    }
+}
@@ -0,0 +1,80 @@
+use std::{ fs, io, path::Path };
+
+// Quiet frames needed before the learned thresholds replace the global one.
+const MIN_SAMPLES: u32 = 50;
+
+// The estimate is a running average over the first frames, then a rolling one over about this many.
+const WINDOW: u32 = 200;
+
+
+/// How learned per-pixel thresholds are derived from the noise estimate, all in 0 - 255 units.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveThreshold {
+    pub k: f32,         // Threshold as a multiple of the pixel's noise.
+    pub floor: i32,     // No pixel is ever more sensitive than this.
+    pub ceiling: i32,   // No pixel is ever less sensitive than this.
+}
+
+
+impl AdaptiveThreshold {
+    /// Converts floor and ceiling from percentages, like --pixel-threshold.
+    pub fn from_percent(k: f32, floor: f32, ceiling: f32) -> Self {
+        let to_level = |percent: f32| ((percent * (255.0 / 100.0)) as i32).clamp(0, 255);
+        Self { k, floor: to_level(floor), ceiling: to_level(ceiling).max(to_level(floor)) }
+    }
+}
+
+
+/// Per-pixel noise estimate: the rolling root mean square of each thumbnail pixel's difference
+/// against the reference, learned only from quiet frames.
+#[derive(Debug, Clone)]
+pub struct NoiseMap {
+    pub width: usize,
+    pub height: usize,
+    pub samples: u32,
+    pub variance: Vec<f32>,
+}
+
+
+impl NoiseMap {
+
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, samples: 0, variance: vec![0.0; width * height] }
+    }
+
+    pub fn buffer_bytes(&self) -> usize {
+        self.variance.capacity() * std::mem::size_of::<f32>()
+    }
+
+    /// True once enough quiet frames were seen for the estimate to be used.
+    pub fn is_ready(&self) -> bool {
+        self.samples >= MIN_SAMPLES
+    }
+
+    pub fn sigma(&self, index: usize) -> f32 {
+        self.variance[index].sqrt()
+    }
+
+    /// The threshold for a pixel, clamped to the configured floor and ceiling.
+    pub fn threshold(&self, index: usize, adaptive: &AdaptiveThreshold) -> i32 {
+        ((adaptive.k * self.sigma(index)).ceil() as i32).clamp(adaptive.floor, adaptive.ceiling)
+    }
+
+    /// Adds the per-pixel differences of a quiet frame to the estimate.
+    pub fn learn(&mut self, differences: &[u8]) {
+        self.samples = self.samples.saturating_add(1);
+        let weight = 1.0 / self.samples.min(WINDOW) as f32;
+        for (variance, difference) in self.variance.iter_mut().zip(differences) {
+            let squared = (*difference as f32) * (*difference as f32);
+            *variance += (squared - *variance) * weight;
+        }
+    }
+
+    /// Writes the noise of each pixel as a binary PGM image, black for none and white at the ceiling.
+    pub fn save_image(&self, path: &Path, adaptive: &AdaptiveThreshold) -> io::Result<()> {
+        let mut data = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        let scale = 255.0 / (adaptive.ceiling.max(1) as f32 / adaptive.k.max(f32::EPSILON));
+        data.extend((0 .. self.variance.len()).map(|index| (self.sigma(index) * scale).min(255.0) as u8));
+        fs::write(path, data)
+    }
+}
@@ -0,0 +1,132 @@
+use std::{ sync::mpsc, thread, time::{ Duration, Instant } };
+use notify_rust::Notification;
+
+use crate::settings::NotifyStop;
+
+// Update and close are only available with the freedesktop notification daemons.
+#[cfg(all(unix, not(target_os = "macos")))]
+type Handle = notify_rust::NotificationHandle;
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+type Handle = ();
+
+
+enum Message {
+    Start,
+    Stop { duration: Duration },
+    Digest { summary: String },
+}
+
+
+/// Shows a desktop notification when a movement starts, and optionally updates or closes it when it stops.
+/// Notifications are sent from a separate thread so a slow notification daemon can't stall detection.
+pub struct DesktopNotifier {
+    sender: mpsc::Sender<Message>,
+    cooldown: Duration,
+    last_notification: Option<Instant>,
+    showing: bool,
+}
+
+
+impl DesktopNotifier {
+
+    pub fn new(camera_name: String, cooldown: Duration, on_stop: NotifyStop) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut handle: Option<Handle> = None;
+            let mut failed = false;
+            for message in receiver {
+                let result = match message {
+                    Message::Start => show(&camera_name, "Motion detected").map(|h| handle = Some(h)),
+                    Message::Stop { duration } => stop(&camera_name, handle.take(), duration, on_stop),
+                    Message::Digest { summary } => notification(&camera_name, &summary).show().map(|_| ()),
+                };
+                // Notifications are a convenience, so failing to send one is never fatal and only logged once.
+                if let Err(err) = result {
+                    if !failed {
+                        println!("Warning, desktop notification failed: {err}");
+                        failed = true;
+                    }
+                }
+            }
+        });
+        Self { sender, cooldown, last_notification: None, showing: false }
+    }
+
+    pub fn motion_started(&mut self, now: Instant) {
+        // Rate limit, so a busy scene doesn't spam the desktop.
+        if let Some(time) = self.last_notification {
+            if now.saturating_duration_since(time) < self.cooldown {
+                return;
+            }
+        }
+        self.last_notification = Some(now);
+        self.showing = true;
+        let _ = self.sender.send(Message::Start);
+    }
+
+    pub fn motion_stopped(&mut self, duration: Duration) {
+        // Only follow up on movements that actually got a notification.
+        if self.showing {
+            self.showing = false;
+            let _ = self.sender.send(Message::Stop { duration });
+        }
+    }
+
+    /// Shows what happened during quiet hours, whatever the cooldown.
+    pub fn digest(&mut self, summary: String) {
+        let _ = self.sender.send(Message::Digest { summary });
+    }
+}
+
+
+/// Shows a one-off notification flagged as a test, reporting whether it could be shown.
+pub fn send_test(camera_name: &str) -> Result<(), String> {
+    notification(camera_name, "motion-detect self-test").show().map(|_| ()).map_err(|err| err.to_string())
+}
+
+
+fn notification(camera_name: &str, summary: &str) -> Notification {
+    let mut notification = Notification::new();
+    notification
+        .appname("motion-detect")
+        .summary(summary)
+        .body(camera_name);
+    notification
+}
+
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show(camera_name: &str, summary: &str) -> Result<Handle, notify_rust::error::Error> {
+    notification(camera_name, summary).show()
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn show(camera_name: &str, summary: &str) -> Result<Handle, notify_rust::error::Error> {
+    notification(camera_name, summary).show().map(|_| ())
+}
+
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn stop(camera_name: &str, handle: Option<Handle>, duration: Duration, on_stop: NotifyStop) -> Result<(), notify_rust::error::Error> {
+    match (on_stop, handle) {
+        (NotifyStop::Close, Some(handle)) => handle.close(),
+        (NotifyStop::Summary, Some(mut handle)) => {
+            // Replaces the "Motion detected" popup in place.
+            handle.summary(&format!("Motion stopped after {:.0?}", duration));
+            handle.update()?;
+        }
+        (NotifyStop::Summary, None) => {
+            show(camera_name, &format!("Motion stopped after {:.0?}", duration))?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn stop(camera_name: &str, _handle: Option<Handle>, duration: Duration, on_stop: NotifyStop) -> Result<(), notify_rust::error::Error> {
+    if on_stop == NotifyStop::Summary {
+        show(camera_name, &format!("Motion stopped after {:.0?}", duration))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,694 @@
+use std::{
+    cell::RefCell,
+    fs::OpenOptions,
+    io::{ self, Write },
+    path::PathBuf,
+    time::{ Duration, Instant, SystemTime },
+};
+
+use crate::{
+    bus::{ Event, EventBus, Sink, SinkError },
+    config::EffectiveConfig,
+    control::{ Storm, ThresholdChange },
+    exit_report,
+    identity::EventIdentity,
+    json,
+    motion::MotionEvent,
+    saturation::{ Level, SaturatedPolicy },
+    settings::PauseMode,
+    signals,
+    source::SourceInfo,
+    startup::Progress,
+    thumbnail::PixelLayout,
+    zones::ArmingChange,
+};
+
+// How long exiting waits at most for the sinks to deliver what's queued.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How messages are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The original plain lines: "warming up", "ready", "start", "stop" and free form diagnostics.
+    Text,
+    /// One JSON object per line for every event, diagnostics go to stderr so stdout stays parseable.
+    Json,
+}
+
+
+/// Where the event stream (motion, zone and lifecycle messages) is written, separately from diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventDestination {
+    Stdout,
+    Stderr,
+    Fd(i32),            // A descriptor left open by a supervisor, e.g. systemd's OpenFile=.
+    File(PathBuf),      // Appended to, and reopened on SIGHUP for log rotation.
+    Socket(PathBuf),    // A listening unix stream socket.
+}
+
+
+impl EventDestination {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let destination = match text.split_once(':') {
+            None if text == "stdout" => EventDestination::Stdout,
+            None if text == "stderr" => EventDestination::Stderr,
+            Some(("fd", fd)) => EventDestination::Fd(
+                fd.parse().ok().filter(|fd| *fd >= 0).ok_or(format!("Invalid file descriptor '{fd}'"))?
+            ),
+            Some(("file", path)) if !path.is_empty() => EventDestination::File(PathBuf::from(path)),
+            Some(("socket", path)) if !path.is_empty() => EventDestination::Socket(PathBuf::from(path)),
+            _ => return Err(format!("Invalid event output '{text}', use stdout, stderr, fd:<n>, file:<path> or socket:<path>")),
+        };
+        if matches!(destination, EventDestination::Fd(_) | EventDestination::Socket(_)) && !cfg!(unix) {
+            return Err(format!("Event output '{text}' is only available on unix"));
+        }
+        Ok(destination)
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            EventDestination::Stdout => Box::new(io::stdout()),
+            EventDestination::Stderr => Box::new(io::stderr()),
+            EventDestination::File(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+            #[cfg(unix)]
+            EventDestination::Fd(fd) => {
+                use std::os::unix::io::FromRawFd;
+                // The descriptor is handed over by whoever started the process, and owned from here on.
+                Box::new(unsafe { std::fs::File::from_raw_fd(*fd) })
+            }
+            #[cfg(unix)]
+            EventDestination::Socket(path) => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            EventDestination::Fd(_) | EventDestination::Socket(_) => return Err(io::ErrorKind::Unsupported.into()),
+        })
+    }
+}
+
+
+impl std::fmt::Display for EventDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventDestination::Stdout => write!(f, "stdout"),
+            EventDestination::Stderr => write!(f, "stderr"),
+            EventDestination::Fd(fd) => write!(f, "fd:{fd}"),
+            EventDestination::File(path) => write!(f, "file:{}", path.display()),
+            EventDestination::Socket(path) => write!(f, "socket:{}", path.display()),
+        }
+    }
+}
+
+
+/// The name of the event destination on the bus.
+pub const EVENT_LOG: &str = "events";
+
+
+/// The open event destination, a sink of the bus. Every line is flushed right away, so pipes and
+/// files see events as they happen.
+pub struct EventLog {
+    format: Format,
+    destination: EventDestination,
+    writer: Box<dyn Write + Send>,
+    lossy: bool,
+    failing: bool,
+}
+
+
+impl EventLog {
+
+    /// Unless `lossy`, the first failed write closes it, otherwise writing resumes once it works again.
+    pub fn open(format: Format, destination: EventDestination, lossy: bool) -> io::Result<Self> {
+        let writer = destination.open()?;
+        if let EventDestination::File(_) = destination {
+            signals::install_reopen_handler();
+        }
+        Ok(Self { format, destination, writer, lossy, failing: false })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), SinkError> {
+        if let EventDestination::File(path) = &self.destination {
+            if signals::take_reopen_request() {
+                match self.destination.open() {
+                    Ok(writer) => self.writer = writer,
+                    Err(err) => eprintln!("Warning, can't reopen event output {}: {err}", path.display()),
+                }
+            }
+        }
+        let result = self.writer.write_all(line.as_bytes())
+            .and_then(|()| self.writer.write_all(b"\n"))
+            .and_then(|()| self.writer.flush());
+        match result {
+            Ok(()) => {
+                self.failing = false;
+                Ok(())
+            }
+            // On stderr, as stdout may well be what's failing.
+            Err(err) if self.lossy => {
+                if !self.failing {
+                    eprintln!("Warning, event output failed: {err}, dropping events");
+                }
+                self.failing = true;
+                Err(SinkError::Lost)
+            }
+            Err(err) => Err(SinkError::Closed(err.to_string())),
+        }
+    }
+}
+
+
+impl Sink for EventLog {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+        let line = match (event, self.format) {
+            (Event::Message { text: None, .. }, Format::Text) | (Event::Held { .. }, _) => return Ok(()),
+            (Event::Message { text: Some(text), .. }, Format::Text) | (Event::Motion { text, .. }, Format::Text) => text,
+            (Event::Message { json, .. } | Event::Motion { json, .. } | Event::Config { json }, _) => json,
+        };
+        self.write_line(line)
+    }
+}
+
+
+/// Messages about the detector itself rather than about motion.
+#[derive(Debug, Clone)]
+pub enum Lifecycle {
+    Starting,
+    DeviceSelected { source: SourceInfo },
+    SourceChanged { source: SourceInfo },   // The source came back from a reconnect in another mode.
+    FormatChanged { previous: String, pixel_format: String, layout: PixelLayout }, // Frames are read in another layout from now on.
+    Startup { progress: Progress },         // A step towards ready, see startup::Phase.
+    WarmupBegin { duration: Duration },
+    Ready,
+    CameraLost { reason: String },
+    CameraRecovered,
+    SignalLost { level: Level, mean: f32, policy: SaturatedPolicy },
+    SignalRestored { duration: Duration },  // Thumbnails are usable again after a SignalLost.
+    Paused { mode: PauseMode },
+    StreamStopped,                          // Released while paused with --pause-mode stream-off...
+    StreamRestored { latency: Duration },   // ...and opened again, this long after resuming was asked for.
+    Resumed { latency: Duration },          // Detection is back, warm up and baseline included.
+    ZoneArming { change: ArmingChange },
+    ThresholdsChanged { command: &'static str, changes: Vec<ThresholdChange> }, // By set, adjust or revert.
+    StormReverted { storm: Storm, changes: Vec<ThresholdChange> }, // A change set off too many movements.
+    StreamSwitched { stream: String, source: SourceInfo }, // The next stream of a --stream-rotation.
+    SceneSaved { scene: String, replaced: bool }, // By scene save, see profile::SceneProfiles.
+    SceneActivated { scene: String, settle: Option<Duration> }, // Nothing is compared meanwhile.
+    ClockStepped { offset: f64 },           // The system clock jumped by this many seconds, e.g. on NTP sync.
+    SystemResumed { suspended: Duration },  // Back from a suspend, detection starts over.
+    ShuttingDown,
+}
+
+
+impl Lifecycle {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lifecycle::Starting => "starting",
+            Lifecycle::DeviceSelected { .. } => "device_selected",
+            Lifecycle::SourceChanged { .. } => "source_changed",
+            Lifecycle::FormatChanged { .. } => "format_changed",
+            Lifecycle::Startup { .. } => "startup_progress",
+            Lifecycle::WarmupBegin { .. } => "warmup_begin",
+            Lifecycle::Ready => "ready",
+            Lifecycle::CameraLost { .. } => "camera_lost",
+            Lifecycle::CameraRecovered => "camera_recovered",
+            Lifecycle::SignalLost { .. } => "signal_lost",
+            Lifecycle::SignalRestored { .. } => "signal_restored",
+            Lifecycle::Paused { .. } => "paused",
+            Lifecycle::StreamStopped => "stream_stopped",
+            Lifecycle::StreamRestored { .. } => "stream_restored",
+            Lifecycle::Resumed { .. } => "resumed",
+            Lifecycle::ZoneArming { change } if change.armed => "zone_armed",
+            Lifecycle::ZoneArming { .. } => "zone_disarmed",
+            Lifecycle::ThresholdsChanged { .. } => "thresholds_changed",
+            Lifecycle::StormReverted { .. } => "storm_reverted",
+            Lifecycle::StreamSwitched { .. } => "stream_switched",
+            Lifecycle::SceneSaved { .. } => "scene_saved",
+            Lifecycle::SceneActivated { .. } => "scene_activated",
+            Lifecycle::ClockStepped { .. } => "clock_stepped",
+            Lifecycle::SystemResumed { .. } => "system_resumed",
+            Lifecycle::ShuttingDown => "shutting_down",
+        }
+    }
+
+    /// The line printed in text mode. Messages that didn't exist before JSON mode have none.
+    pub fn text(&self) -> Option<String> {
+        let text = match self {
+            Lifecycle::WarmupBegin { .. } => "warming up",
+            Lifecycle::Ready => "ready",
+            Lifecycle::CameraLost { reason } => return Some(format!("camera lost: {reason}")),
+            Lifecycle::CameraRecovered => "camera recovered",
+            Lifecycle::SignalLost { level, mean, .. } => return Some(format!("signal lost: {} thumbnails, mean {mean:.0}", level.name())),
+            Lifecycle::SignalRestored { duration } => return Some(format!("signal restored after {:.1}s", duration.as_secs_f64())),
+            Lifecycle::Paused { mode } => return Some(format!("paused, {}", mode.name())),
+            Lifecycle::StreamStopped => "stream stopped",
+            Lifecycle::StreamRestored { latency } => return Some(format!("stream restored after {:.3}s", latency.as_secs_f64())),
+            Lifecycle::Resumed { latency } => return Some(format!("resumed after {:.3}s", latency.as_secs_f64())),
+            Lifecycle::SourceChanged { source } => return Some(format!("source changed: {}", source.text())),
+            Lifecycle::FormatChanged { previous, pixel_format, layout } => return Some(format!(
+                "pixel format changed from {previous} to {pixel_format}, reading {} frames from now on", layout.name()
+            )),
+            Lifecycle::ZoneArming { change } => return Some(format!(
+                "zone {} {} by {}", change.zone, if change.armed { "armed" } else { "disarmed" }, change.cause.name()
+            )),
+            Lifecycle::ThresholdsChanged { command, changes } => return Some(format!(
+                "thresholds changed by {command}: {}", changes.iter().map(ThresholdChange::text).collect::<Vec<_>>().join(", ")
+            )),
+            Lifecycle::StormReverted { storm, changes } => return Some(format!(
+                "{} movements within {:.1}s of a threshold change, reverted: {}",
+                storm.movements, storm.within.as_secs_f64(), changes.iter().map(ThresholdChange::text).collect::<Vec<_>>().join(", ")
+            )),
+            Lifecycle::StreamSwitched { stream, source } => return Some(format!("switched to stream {stream}: {}", source.text())),
+            Lifecycle::SceneSaved { scene, replaced } => return Some(match replaced {
+                true => format!("scene {scene} saved again"),
+                false => format!("scene {scene} saved"),
+            }),
+            Lifecycle::SceneActivated { scene, settle } => return Some(match settle {
+                Some(settle) => format!("scene {scene} activated, settling for {:.1}s", settle.as_secs_f64()),
+                None => format!("scene {scene} activated"),
+            }),
+            Lifecycle::ClockStepped { offset } => return Some(format!("clock stepped by {offset:+.1}s")),
+            Lifecycle::SystemResumed { suspended } => return Some(format!("system resumed after {:.1}s", suspended.as_secs_f64())),
+            Lifecycle::ShuttingDown => "shutting down",
+            Lifecycle::Starting | Lifecycle::DeviceSelected { .. } | Lifecycle::Startup { .. } => return None,
+        };
+        Some(text.to_string())
+    }
+
+    pub fn to_json(&self, time: SystemTime) -> String {
+        let object = json::Object::new().field("type", self.name());
+        let object = match self {
+            // The flat fields came first, the source object repeats them for all messages alike.
+            Lifecycle::DeviceSelected { source } => object
+                .field("uri", source.uri.as_str())
+                .field("product", source.product.as_str())
+                .field("width", source.width as u64)
+                .field("height", source.height as u64)
+                .field("pixel_format", source.pixel_format.as_str())
+                .field("interval", source.interval.as_secs_f64())
+                .field("source", source.to_object()),
+            Lifecycle::SourceChanged { source } => object.field("source", source.to_object()),
+            Lifecycle::FormatChanged { previous, pixel_format, layout } => object
+                .field("previous", previous.as_str())
+                .field("pixel_format", pixel_format.as_str())
+                .field("layout", layout.name())
+                .field("channels", layout.channels()),
+            Lifecycle::Startup { progress } => object
+                .field("phase", progress.phase.name())
+                .field("detail", progress.detail.as_str())
+                .field("percent", progress.percent),
+            Lifecycle::WarmupBegin { duration } => object.field("duration", duration.as_secs_f64()),
+            Lifecycle::CameraLost { reason } => object.field("reason", reason.as_str()),
+            Lifecycle::SignalLost { level, mean, policy } => object
+                .field("level", level.name())
+                .field("mean", *mean)
+                .field("policy", policy.name()),
+            Lifecycle::SignalRestored { duration } => object.field("duration", duration.as_secs_f64()),
+            Lifecycle::Paused { mode } => object.field("mode", mode.name()),
+            Lifecycle::StreamRestored { latency } | Lifecycle::Resumed { latency } => object.field("latency", latency.as_secs_f64()),
+            Lifecycle::ZoneArming { change } => object.field("zone", change.zone.as_str()).field("cause", change.cause.name()),
+            Lifecycle::ThresholdsChanged { command, changes } => object
+                .field("command", *command)
+                .field("changes", changes.iter().map(ThresholdChange::to_object).collect::<Vec<_>>()),
+            Lifecycle::StormReverted { storm, changes } => object
+                .field("movements", storm.movements)
+                .field("within", storm.within.as_secs_f64())
+                .field("changes", changes.iter().map(ThresholdChange::to_object).collect::<Vec<_>>()),
+            Lifecycle::StreamSwitched { stream, source } => object.field("stream", stream.as_str()).field("source", source.to_object()),
+            Lifecycle::SceneSaved { scene, replaced } => object.field("scene", scene.as_str()).field("replaced", *replaced),
+            Lifecycle::SceneActivated { scene, settle } => object
+                .field("scene", scene.as_str())
+                .field("settle", settle.map(|settle| settle.as_secs_f64())),
+            Lifecycle::ClockStepped { offset } => object.field("offset", *offset),
+            Lifecycle::SystemResumed { suspended } => object.field("suspended", suspended.as_secs_f64()),
+            _ => object,
+        };
+        object.field("time", json::unix_time(time)).finish()
+    }
+}
+
+/// A warning seen again within the window.
+struct Repeat {
+    key: String,
+    since: Instant,     // First printed.
+    seen: u32,
+    suppressed: u32,
+    last: String,       // Latest suppressed text, numbers included.
+}
+
+
+/// Collapses a warning repeated within a window, e.g. by a camera dropping out all night: the first
+/// occurrences are printed, the rest are counted and summed up in one line once the window is over,
+/// another warning comes, or on shutdown. Warnings are told apart by `repeat_key`, so the numbers
+/// in them don't make each one unique. Other messages come in between without ending the window.
+pub struct WarningDedup {
+    window: Duration,
+    first: u32,         // Occurrences printed in each window.
+    current: Option<Repeat>,
+}
+
+
+impl WarningDedup {
+
+    pub fn new(window: Duration, first: u32) -> Self {
+        Self { window, first: first.max(1), current: None }
+    }
+
+    /// What to print for a message: the summary of the repeats it ends, if any, and whether the
+    /// message itself is printed.
+    pub fn message(&mut self, message: &str, now: Instant) -> (Option<String>, bool) {
+        if !message.trim_start().starts_with("Warning") {
+            // Errors end the run more often than not, the summary comes before them.
+            return match message.trim_start().starts_with("Error") {
+                true => (self.end(now), true),
+                false => (None, true),
+            };
+        }
+        let key = repeat_key(message);
+        let summary = match &self.current {
+            Some(repeat) if repeat.key == key && now.saturating_duration_since(repeat.since) < self.window => None,
+            _ => self.end(now),
+        };
+        let repeat = self.current.get_or_insert_with(|| Repeat { key, since: now, seen: 0, suppressed: 0, last: String::new() });
+        repeat.seen += 1;
+        if repeat.seen <= self.first {
+            return (summary, true);
+        }
+        repeat.suppressed += 1;
+        repeat.last.clear();
+        repeat.last.push_str(message);
+        (summary, false)
+    }
+
+    /// The summary of a window that is over, without waiting for the next warning.
+    pub fn expire(&mut self, now: Instant) -> Option<String> {
+        match &self.current {
+            Some(repeat) if now.saturating_duration_since(repeat.since) >= self.window => self.end(now),
+            _ => None,
+        }
+    }
+
+    /// Ends the current window, with a summary if anything was suppressed in it.
+    pub fn flush(&mut self) -> Option<String> {
+        self.end(Instant::now())
+    }
+
+    fn end(&mut self, now: Instant) -> Option<String> {
+        let repeat = self.current.take()?;
+        (repeat.suppressed > 0).then(|| format!(
+            "Warning, repeated {} more times in {:.0}s, last: {}",
+            repeat.suppressed, now.saturating_duration_since(repeat.since).min(self.window).as_secs_f64(), repeat.last.trim_start()
+        ))
+    }
+}
+
+
+/// What makes warnings the same: their text with every number replaced, so counters, durations and
+/// timestamps don't tell them apart.
+pub fn repeat_key(message: &str) -> String {
+    let mut key = String::with_capacity(message.len());
+    let mut in_number = false;
+    for character in message.trim_start().chars() {
+        let digit = character.is_ascii_digit() || (in_number && character == '.');
+        if digit && !in_number {
+            key.push('#');
+        } else if !digit {
+            key.push(character);
+        }
+        in_number = digit;
+    }
+    key
+}
+
+
+/// Publishes events and lifecycle messages on the bus, whose event log writes them to the event
+/// destination, stdout by default, and writes diagnostics to stdout or stderr, in the selected format.
+pub struct Output {
+    pub format: Format,
+    bus: EventBus,
+    dedup: Option<RefCell<WarningDedup>>,
+}
+
+
+impl Output {
+
+    pub fn new(format: Format) -> Self {
+        let bus = EventBus::new();
+        let events = EventLog { format, destination: EventDestination::Stdout, writer: Box::new(io::stdout()), lossy: false, failing: false };
+        bus.register(EVENT_LOG, events, false);
+        drain_on_exit(&bus);
+        Self { format, bus, dedup: None }
+    }
+
+    /// Collapses warnings repeated within `window`, printing the `first` ones of each window.
+    pub fn with_dedup(mut self, window: Duration, first: u32) -> Self {
+        self.dedup = (!window.is_zero()).then(|| RefCell::new(WarningDedup::new(window, first)));
+        self
+    }
+
+    /// Sends events to another destination, see `EventLog::open`. A bus of its own replaces the
+    /// default one, any other sink is registered afterwards.
+    pub fn with_events(mut self, destination: EventDestination, lossy: bool) -> io::Result<Self> {
+        let events = EventLog::open(self.format, destination, lossy)?;
+        self.bus = EventBus::new();
+        self.bus.register(EVENT_LOG, events, lossy);
+        drain_on_exit(&self.bus);
+        Ok(self)
+    }
+
+    /// The bus events are published on, for the other sinks to register with.
+    pub fn bus(&self) -> &EventBus {
+        &self.bus
+    }
+
+    /// Why the (non lossy) event destination stopped accepting events, if it did.
+    pub fn event_error(&self) -> Option<String> {
+        self.bus.closed(EVENT_LOG)
+    }
+
+    pub fn lifecycle(&self, message: &Lifecycle) {
+        if let Lifecycle::CameraLost { reason } = message {
+            exit_report::message(&format!("Warning, camera lost: {reason}"));
+        }
+        self.bus.publish(Event::Message { text: message.text(), json: message.to_json(SystemTime::now()) });
+    }
+
+    /// Publishes a motion event seen at `now`, given as already serialized JSON.
+    pub fn motion(&self, event: MotionEvent, identity: EventIdentity, now: Instant, notify: bool, json: String) {
+        self.bus.publish(Event::Motion { event, identity, at: now, notify, text: event.text().to_string(), json });
+    }
+
+    /// Publishes a movement held during quiet hours, once they're over, for the commands.
+    pub fn held(&self, id: u64, stopped: bool) {
+        self.bus.publish(Event::Held { id, stopped });
+    }
+
+    /// Publishes a message as its text line and its JSON object, the event log writing either
+    /// depending on the format.
+    pub fn event(&self, text: &str, json: &str) {
+        self.bus.publish(Event::Message { text: Some(text.to_string()), json: json.to_string() });
+    }
+
+    /// Reports the effective configuration: the JSON object as an event, or its lines as diagnostics.
+    pub fn config(&self, config: &EffectiveConfig) {
+        match self.format {
+            Format::Text => config.text().iter().for_each(|line| self.info(line)),
+            Format::Json => self.bus.publish(Event::Config { json: config.to_json() }),
+        }
+    }
+
+    /// Free form diagnostics for humans, kept off stdout in JSON mode. Unlike events, failing to
+    /// write them is ignored.
+    pub fn info(&self, message: &str) {
+        exit_report::message(message);
+        let Some(dedup) = &self.dedup else {
+            return self.write_info(message);
+        };
+        let (summary, print) = dedup.borrow_mut().message(message, Instant::now());
+        if let Some(summary) = summary {
+            self.write_info(&summary);
+        }
+        if print {
+            self.write_info(message);
+        }
+    }
+
+    /// Prints the summary of repeated warnings whose window is over, called once per frame.
+    pub fn expire_repeats(&self) {
+        if let Some(summary) = self.dedup.as_ref().and_then(|dedup| dedup.borrow_mut().expire(Instant::now())) {
+            self.write_info(&summary);
+        }
+    }
+
+    /// Prints the summary of repeated warnings still pending, before exiting.
+    pub fn flush_repeats(&self) {
+        if let Some(summary) = self.dedup.as_ref().and_then(|dedup| dedup.borrow_mut().flush()) {
+            self.write_info(&summary);
+        }
+    }
+
+    fn write_info(&self, message: &str) {
+        let _ = match self.format {
+            Format::Text => writeln!(io::stdout(), "{message}"),
+            Format::Json => writeln!(io::stderr(), "{message}"),
+        };
+    }
+}
+
+
+// process::exit skips this, exits call flush_repeats first and drain the bus.
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.flush_repeats();
+        self.bus.drain(DRAIN_TIMEOUT);
+    }
+}
+
+
+// Events published right before exiting are still delivered, as far as the sinks keep up.
+fn drain_on_exit(bus: &EventBus) {
+    let bus = bus.clone();
+    exit_report::before_exit(move || {
+        bus.drain(DRAIN_TIMEOUT);
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{ Arc, Mutex };
+
+    use super::*;
+    use crate::json::Scalar;
+
+    // Lines written to the event log, shared with the test.
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+
+    impl Write for Lines {
+
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+
+    impl Lines {
+
+
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+
+    fn event_log(format: Format, lines: &Lines) -> EventLog {
+        EventLog { format, destination: EventDestination::Stdout, writer: Box::new(lines.clone()), lossy: false, failing: false }
+    }
+
+
+    fn message(lifecycle: &Lifecycle) -> Event {
+        Event::Message { text: lifecycle.text(), json: lifecycle.to_json(SystemTime::UNIX_EPOCH + Duration::from_secs(10)) }
+    }
+
+
+    #[test]
+    fn lifecycle_messages_are_json_objects_of_their_type() {
+        let messages = [
+            Lifecycle::Starting,
+            Lifecycle::WarmupBegin { duration: Duration::from_millis(1500) },
+            Lifecycle::Ready,
+            Lifecycle::CameraLost { reason: "gone".to_string() },
+            Lifecycle::CameraRecovered,
+            Lifecycle::ShuttingDown,
+        ];
+        for lifecycle in &messages {
+            let fields = json::parse_flat(&lifecycle.to_json(SystemTime::UNIX_EPOCH + Duration::from_secs(10))).unwrap();
+            assert_eq!(fields.first(), Some(&("type".to_string(), Scalar::String(lifecycle.name().to_string()))));
+            assert_eq!(fields.last(), Some(&("time".to_string(), Scalar::Number(10.0))));
+        }
+        let warm_up = json::parse_flat(&messages[1].to_json(SystemTime::UNIX_EPOCH)).unwrap();
+        assert!(warm_up.contains(&("duration".to_string(), Scalar::Number(1.5))));
+    }
+
+
+    #[test]
+    fn text_mode_keeps_the_plain_lines() {
+        let lines = Lines::default();
+        let mut log = event_log(Format::Text, &lines);
+        for lifecycle in [Lifecycle::Starting, Lifecycle::WarmupBegin { duration: Duration::from_secs(1) }, Lifecycle::Ready, Lifecycle::ShuttingDown] {
+            log.deliver(&message(&lifecycle)).unwrap();
+        }
+        // Starting came with JSON mode, it has no line of its own.
+        assert_eq!(lines.text(), "warming up\nready\nshutting down\n");
+    }
+
+
+    #[test]
+    fn json_mode_writes_one_object_per_line() {
+        let lines = Lines::default();
+        let mut log = event_log(Format::Json, &lines);
+        for lifecycle in [Lifecycle::Starting, Lifecycle::Ready, Lifecycle::CameraLost { reason: "unplugged".to_string() }] {
+            log.deliver(&message(&lifecycle)).unwrap();
+        }
+        let types: Vec<_> = lines.text().lines().map(|line| json::parse_flat(line).unwrap().swap_remove(0).1).collect();
+        assert_eq!(types, ["starting", "ready", "camera_lost"].map(|name| Scalar::String(name.to_string())));
+    }
+
+
+    #[test]
+    fn warnings_are_told_apart_without_their_numbers() {
+        assert_eq!(repeat_key("Warning, frame 12 late by 0.35s"), repeat_key("Warning, frame 13 late by 1.5s"));
+        assert_ne!(repeat_key("Warning, frame 12 late"), repeat_key("Warning, frame 12 dropped"));
+    }
+
+
+    #[test]
+    fn repeats_past_the_first_ones_are_summed_up_when_another_warning_comes() {
+        let now = Instant::now();
+        let mut dedup = WarningDedup::new(Duration::from_secs(60), 2);
+        let printed: Vec<_> = (1 ..= 5).map(|attempt| dedup.message(&format!("Warning, reconnect attempt {attempt} failed"), now)).collect();
+        assert_eq!(printed, [(None, true), (None, true), (None, false), (None, false), (None, false)]);
+        // Other messages don't end the window.
+        assert_eq!(dedup.message("ready", now), (None, true));
+        let (summary, printed) = dedup.message("Warning, stale frame", now + Duration::from_secs(5));
+        assert_eq!(summary.as_deref(), Some("Warning, repeated 3 more times in 5s, last: Warning, reconnect attempt 5 failed"));
+        assert!(printed);
+    }
+
+
+    #[test]
+    fn a_window_that_is_over_is_summed_up_without_waiting() {
+        let now = Instant::now();
+        let mut dedup = WarningDedup::new(Duration::from_secs(10), 1);
+        dedup.message("Warning, decode error 1", now);
+        dedup.message("Warning, decode error 2", now);
+        assert_eq!(dedup.expire(now + Duration::from_secs(9)), None);
+        assert!(dedup.expire(now + Duration::from_secs(10)).is_some_and(|summary| summary.contains("1 more times")));
+        // The next one starts a window of its own and is printed.
+        assert_eq!(dedup.message("Warning, decode error 3", now + Duration::from_secs(11)), (None, true));
+    }
+
+
+    #[test]
+    fn nothing_suppressed_is_lost_at_shutdown_or_on_an_error() {
+        let now = Instant::now();
+        let mut dedup = WarningDedup::new(Duration::from_secs(60), 1);
+        dedup.message("Warning, camera gone", now);
+        assert_eq!(dedup.flush(), None);
+        dedup.message("Warning, camera gone", now);
+        dedup.message("Warning, camera gone", now);
+        assert!(dedup.flush().is_some_and(|summary| summary.ends_with("last: Warning, camera gone")));
+        assert_eq!(dedup.flush(), None);
+        dedup.message("Warning, camera gone", now);
+        dedup.message("Warning, camera gone", now);
+        let (summary, printed) = dedup.message("Error, giving up", now);
+        assert!(summary.is_some() && printed);
+    }
+}
@@ -0,0 +1,257 @@
+use std::time::SystemTime;
+
+use crate::clock::UtcTime;
+
+// Glyphs are 5x7, one byte per row with the leftmost pixel in bit 4.
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+const FONT: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    ('0', [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e]),
+    ('1', [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('2', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f]),
+    ('3', [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e]),
+    ('4', [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02]),
+    ('5', [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e]),
+    ('6', [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e]),
+    ('7', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e]),
+    ('9', [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c]),
+    ('A', [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11]),
+    ('B', [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e]),
+    ('C', [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e]),
+    ('D', [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c]),
+    ('E', [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f]),
+    ('F', [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10]),
+    ('G', [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f]),
+    ('H', [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11]),
+    ('I', [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f]),
+    ('M', [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e]),
+    ('P', [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10]),
+    ('Q', [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d]),
+    ('R', [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11]),
+    ('S', [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e]),
+    ('T', [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a]),
+    ('X', [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04]),
+    ('Z', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    (':', [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00]),
+    ('-', [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('?', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+];
+
+
+/// Where the overlay goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+
+impl Corner {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(format!("Invalid corner '{text}', use top-left, top-right, bottom-left or bottom-right")),
+        }
+    }
+}
+
+
+/// A line of text burned into saved images: any of the capture time, the camera name and the zone,
+/// white on a black box so it reads on any background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overlay {
+    pub timestamp: bool,
+    pub name: bool,
+    pub zone: bool,
+    pub corner: Corner,
+}
+
+
+impl Overlay {
+
+    /// Parses the comma separated fields "ts", "name" and "zone", or "none".
+    pub fn parse(text: &str) -> Result<Option<Self>, String> {
+        if text == "none" {
+            return Ok(None);
+        }
+        let mut overlay = Overlay { timestamp: false, name: false, zone: false, corner: Corner::BottomLeft };
+        for field in text.split(',') {
+            match field.trim() {
+                "ts" => overlay.timestamp = true,
+                "name" => overlay.name = true,
+                "zone" => overlay.zone = true,
+                other => return Err(format!("Invalid overlay field '{other}', use ts, name and zone, or none")),
+            }
+        }
+        Ok(Some(overlay))
+    }
+
+    /// The line for an image captured at `time`, `zone` is None for full frames.
+    pub fn text(&self, time: SystemTime, name: &str, zone: Option<&str>) -> String {
+        let mut parts = Vec::new();
+        if self.timestamp {
+            parts.push(utc_timestamp(time));
+        }
+        if self.name {
+            parts.push(name.to_string());
+        }
+        if let (true, Some(zone)) = (self.zone, zone) {
+            parts.push(zone.to_string());
+        }
+        parts.join("  ")
+    }
+
+    /// Draws the text into an image with 1 (gray) or 3 (RGB) channels per pixel. The glyphs grow
+    /// with the image width, and text that doesn't fit even at the smallest size is cut with "..".
+    pub fn draw(&self, pixels: &mut [u8], width: usize, height: usize, channels: usize, text: &str) {
+        let mut glyphs: Vec<&[u8; GLYPH_HEIGHT]> = text.chars().map(glyph).collect();
+        let margin = 2;
+        if glyphs.is_empty() || width < ADVANCE + 1 + 2 * margin || height < GLYPH_HEIGHT + 2 + 2 * margin {
+            return;
+        }
+        let fits = (width - 2 * margin - 1) / ADVANCE;
+        if glyphs.len() > fits {
+            glyphs.truncate(fits.saturating_sub(2));
+            glyphs.extend([glyph('.'), glyph('.')]);
+            glyphs.truncate(fits);
+        }
+        let text_width = glyphs.len() * ADVANCE + 1;
+        let scale = (width / 320).max(1)
+            .min((width - 2 * margin) / text_width)
+            .min((height - 2 * margin) / (GLYPH_HEIGHT + 2))
+            .max(1);
+
+        // Background box with a pixel of padding around the glyphs.
+        let (box_width, box_height) = (text_width * scale, (GLYPH_HEIGHT + 2) * scale);
+        let left = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin,
+            Corner::TopRight | Corner::BottomRight => width - margin - box_width,
+        };
+        let top = match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin,
+            Corner::BottomLeft | Corner::BottomRight => height - margin - box_height,
+        };
+        let mut fill = |x: usize, y: usize, value: u8| {
+            let index = ((top + y) * width + left + x) * channels;
+            pixels[index .. index + channels].fill(value);
+        };
+        for y in 0 .. box_height {
+            for x in 0 .. box_width {
+                fill(x, y, 0);
+            }
+        }
+        for (position, rows) in glyphs.iter().enumerate() {
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0 .. GLYPH_WIDTH {
+                    if bits & (0x10 >> column) == 0 {
+                        continue;
+                    }
+                    let (x, y) = ((1 + position * ADVANCE + column) * scale, (1 + row) * scale);
+                    for dy in 0 .. scale {
+                        for dx in 0 .. scale {
+                            fill(x + dx, y + dy, 255);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+// Lowercase is drawn as uppercase, anything else missing from the font as "?".
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    FONT.iter()
+        .find(|(glyph, _)| *glyph == c)
+        .or_else(|| FONT.iter().find(|(glyph, _)| *glyph == '?'))
+        .map(|(_, rows)| rows)
+        .expect("The font has a question mark")
+}
+
+
+/// Formats a time as "2024-05-01 13:45:07Z", in UTC since there is no time zone database to rely on.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let utc = UtcTime::from(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}Z",
+        utc.year, utc.month, utc.day, utc.hour, utc.minute, utc.second
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn the_line_has_the_fields_asked_for() {
+        let overlay = Overlay::parse("ts,zone").unwrap().unwrap();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_571_107);
+        assert_eq!(overlay.text(time, "porch", Some("door")), "2024-05-01 13:45:07Z  door");
+        assert_eq!(overlay.text(time, "porch", None), "2024-05-01 13:45:07Z");
+        assert_eq!(Overlay::parse("none"), Ok(None));
+        assert!(Overlay::parse("ts,date").is_err());
+    }
+
+
+    #[test]
+    fn text_is_drawn_in_its_corner_white_on_black() {
+        let (width, height) = (40, 20);
+        let mut pixels = vec![128; width * height];
+        let overlay = Overlay { timestamp: true, name: false, zone: false, corner: Corner::BottomRight };
+        overlay.draw(&mut pixels, width, height, 1, "1");
+        // One glyph and its padding, 2 pixels off the bottom right corner.
+        let drawn = |x: usize, y: usize| (width - 2 - ADVANCE - 1 .. width - 2).contains(&x) && (height - 2 - GLYPH_HEIGHT - 2 .. height - 2).contains(&y);
+        for (index, value) in pixels.iter().enumerate() {
+            match drawn(index % width, index / width) {
+                true => assert!(*value == 0 || *value == 255, "{index}: {value}"),
+                false => assert_eq!(*value, 128, "{index}"),
+            }
+        }
+        assert!(pixels.contains(&255));
+    }
+
+
+    #[test]
+    fn text_too_long_for_the_image_is_cut() {
+        let (width, height) = (40, 20);
+        let mut pixels = vec![128; width * height * 3];
+        let overlay = Overlay { timestamp: true, name: false, zone: false, corner: Corner::TopLeft };
+        overlay.draw(&mut pixels, width, height, 3, "2024-05-01 13:45:07Z  porch");
+        // Nothing is drawn past the margin on the right.
+        for y in 0 .. height {
+            assert!(pixels[(y * width + width - 2) * 3 .. (y + 1) * width * 3].iter().all(|value| *value == 128));
+        }
+        // And an image too small for a single glyph is left alone.
+        let mut tiny = vec![128; 6 * 6];
+        overlay.draw(&mut tiny, 6, 6, 1, "1");
+        assert!(tiny.iter().all(|value| *value == 128));
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+/// Movements widened by the padding on either side, ready to cut from a recording. Times are
+/// offsets from the start of the run, which no clip starts before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clip {
+    pub id: u64,            // Of its first movement.
+    pub start: Duration,
+    pub end: Duration,      // As far as known: the padded end of its latest stop.
+}
+
+
+/// Groups movements into clips for --report-padding. A movement whose padded start would overlap
+/// the padded end of the previous clip joins that clip rather than starting an overlapping one,
+/// so clips never overlap.
+pub struct ClipPadding {
+    padding: Duration,
+    clip: Option<Clip>,
+}
+
+
+impl ClipPadding {
+
+    pub fn new(padding: Duration) -> Self {
+        Self { padding, clip: None }
+    }
+
+    /// A movement `id` started `at`. Returns the clip it belongs to.
+    pub fn start(&mut self, id: u64, at: Duration) -> Clip {
+        let start = at.saturating_sub(self.padding);
+        match &mut self.clip {
+            Some(clip) if start <= clip.end => *clip,
+            _ => *self.clip.insert(Clip { id, start, end: at }),
+        }
+    }
+
+    /// Movement `id` stopped `at`. Returns its clip, which now ends the padding later.
+    pub fn stop(&mut self, id: u64, at: Duration) -> Clip {
+        let padding = self.padding;
+        let clip = self.clip.get_or_insert(Clip { id, start: at.saturating_sub(padding), end: at });
+        clip.end = clip.end.max(at + padding);
+        *clip
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+
+    #[test]
+    fn clips_are_padded_on_either_side() {
+        let mut padding = ClipPadding::new(seconds(2));
+        assert_eq!(padding.start(1, seconds(10)), Clip { id: 1, start: seconds(8), end: seconds(10) });
+        assert_eq!(padding.stop(1, seconds(15)), Clip { id: 1, start: seconds(8), end: seconds(17) });
+    }
+
+
+    #[test]
+    fn a_clip_never_starts_before_the_run() {
+        let mut padding = ClipPadding::new(seconds(2));
+        assert_eq!(padding.start(1, seconds(1)).start, Duration::ZERO);
+    }
+
+
+    #[test]
+    fn movements_whose_padding_overlaps_are_merged() {
+        let mut padding = ClipPadding::new(seconds(2));
+        padding.start(1, seconds(10));
+        padding.stop(1, seconds(15));
+        // Padded, the next one would start at 16, before the clip ends at 17.
+        assert_eq!(padding.start(2, seconds(18)), Clip { id: 1, start: seconds(8), end: seconds(17) });
+        assert_eq!(padding.stop(2, seconds(25)), Clip { id: 1, start: seconds(8), end: seconds(27) });
+        // Starting right at the padded end, it still joins. Past it, a clip of its own starts.
+        assert_eq!(padding.start(3, seconds(29)).id, 1);
+        padding.stop(3, seconds(30));
+        assert_eq!(padding.start(4, seconds(35)), Clip { id: 4, start: seconds(33), end: seconds(35) });
+    }
+
+
+    #[test]
+    fn a_stop_without_its_start_is_a_clip_of_its_own() {
+        let mut padding = ClipPadding::new(seconds(2));
+        assert_eq!(padding.stop(7, seconds(5)), Clip { id: 7, start: seconds(3), end: seconds(7) });
+    }
+}
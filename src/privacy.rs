@@ -0,0 +1,213 @@
+/// Left, top, right and bottom (excluded) frame pixels, as snapshots are cropped.
+pub type Rect = (usize, usize, usize, usize);
+
+// Frame pixels averaged together are squares of the frame's larger side over this, at least
+// MIN_BLOCK wide: what's left leaves no faces or documents recognizable.
+const BLOCKS_ACROSS: usize = 48;
+const MIN_BLOCK: usize = 4;
+
+
+/// What --snapshot-privacy does to a snapshot outside the movement's boxes: everything but the
+/// parts that moved is blurred, pixelated or blacked out, so snapshots show what happened without
+/// recording the rest of the scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privacy {
+    Blur,       // Blocks blended into each other.
+    Pixelate,   // Blocks of one shade.
+    Blackout,
+}
+
+
+impl Privacy {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "blur" => Ok(Privacy::Blur),
+            "pixelate" => Ok(Privacy::Pixelate),
+            "blackout" => Ok(Privacy::Blackout),
+            _ => Err(format!("Invalid snapshot privacy '{text}', use blur, pixelate or blackout")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Privacy::Blur => "blur",
+            Privacy::Pixelate => "pixelate",
+            Privacy::Blackout => "blackout",
+        }
+    }
+
+    /// Obscures the frame, row by row with `channels` bytes per pixel, outside of every box in
+    /// `keep`. Where boxes overlap it's their union that stays as it is.
+    pub fn apply(&self, frame: &mut [u8], width: usize, height: usize, channels: usize, keep: &[Rect]) {
+        let kept = |x: usize, y: usize| keep.iter().any(|(left, top, right, bottom)| (*left .. *right).contains(&x) && (*top .. *bottom).contains(&y));
+        if *self == Privacy::Blackout {
+            for (index, pixel) in frame[.. width * height * channels].chunks_exact_mut(channels).enumerate() {
+                if !kept(index % width, index / width) {
+                    pixel.fill(0);
+                }
+            }
+            return;
+        }
+
+        // The mean of each block, over all of its pixels.
+        let block = (width.max(height) / BLOCKS_ACROSS).max(MIN_BLOCK);
+        let (columns, rows) = (width.div_ceil(block), height.div_ceil(block));
+        let mut means = vec![0u8; columns * rows * channels];
+        for row in 0 .. rows {
+            for column in 0 .. columns {
+                let (xs, ys) = (column * block .. ((column + 1) * block).min(width), row * block .. ((row + 1) * block).min(height));
+                let count = (xs.len() * ys.len()) as u32;
+                for channel in 0 .. channels {
+                    let sum: u32 = ys.clone()
+                        .flat_map(|y| xs.clone().map(move |x| (y * width + x) * channels + channel))
+                        .map(|index| frame[index] as u32)
+                        .sum();
+                    means[(row * columns + column) * channels + channel] = (sum / count) as u8;
+                }
+            }
+        }
+
+        // Pixelated pixels take their block's mean, blurred ones blend those of the four blocks
+        // whose centres surround them.
+        let sample = |x: usize, y: usize, channel: usize| -> u8 {
+            match self {
+                Privacy::Pixelate | Privacy::Blackout => means[((y / block) * columns + x / block) * channels + channel],
+                Privacy::Blur => {
+                    let position = |value: usize, last: usize| {
+                        let centred = ((value as f32 + 0.5) / block as f32 - 0.5).clamp(0.0, last as f32);
+                        let first = (centred as usize).min(last.saturating_sub(1));
+                        (first, (first + 1).min(last), centred - first as f32)
+                    };
+                    let (x0, x1, fx) = position(x, columns - 1);
+                    let (y0, y1, fy) = position(y, rows - 1);
+                    let mean = |column: usize, row: usize| means[(row * columns + column) * channels + channel] as f32;
+                    let top = mean(x0, y0) * (1.0 - fx) + mean(x1, y0) * fx;
+                    let bottom = mean(x0, y1) * (1.0 - fx) + mean(x1, y1) * fx;
+                    (top * (1.0 - fy) + bottom * fy).round() as u8
+                }
+            }
+        };
+        for y in 0 .. height {
+            for x in 0 .. width {
+                if kept(x, y) {
+                    continue;
+                }
+                for channel in 0 .. channels {
+                    frame[(y * width + x) * channels + channel] = sample(x, y, channel);
+                }
+            }
+        }
+    }
+}
+
+
+/// The boxes of a frame of the given size a snapshot keeps: one around each group of touching
+/// changed pixels of the diff `mask`, `mask_width` pixels wide, widened by `margin` times the
+/// frame size on each side.
+pub fn motion_boxes(mask: &[u8], mask_width: usize, width: usize, height: usize, margin: f32) -> Vec<Rect> {
+    if mask_width == 0 {
+        return Vec::new();
+    }
+    let mask_height = mask.len() / mask_width;
+    let (pad_x, pad_y) = ((width as f32 * margin).round() as usize, (height as f32 * margin).round() as usize);
+    let mut seen = vec![false; mask.len()];
+    let mut boxes = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0 .. mask_width * mask_height {
+        if mask[start] == 0 || seen[start] {
+            continue;
+        }
+        // Flood fill of the pixels touching it, sides only.
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+        seen[start] = true;
+        stack.push(start);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % mask_width, index / mask_width);
+            (left, top, right, bottom) = (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1));
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < mask_width).then_some(index + 1),
+                (y > 0).then(|| index - mask_width),
+                (y + 1 < mask_height).then_some(index + mask_width),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if mask[neighbour] != 0 && !seen[neighbour] {
+                    seen[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        // Thumbnail pixels cover frame pixels from their first to their last, rounded outwards.
+        boxes.push((
+            (left * width / mask_width).saturating_sub(pad_x),
+            (top * height / mask_height).saturating_sub(pad_y),
+            (right * width).div_ceil(mask_width).saturating_add(pad_x).min(width),
+            (bottom * height).div_ceil(mask_height).saturating_add(pad_y).min(height),
+        ));
+    }
+    boxes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+
+    // A gray RGB frame of fine detail: a checkerboard of one pixel squares.
+    fn checkerboard() -> Vec<u8> {
+        (0 .. WIDTH * HEIGHT).flat_map(|index| [((index % WIDTH + index / WIDTH) % 2 * 200 + 20) as u8; 3]).collect()
+    }
+
+
+    // The mean and the variance of the samples of the pixels inside `keep`, or outside of them.
+    fn statistics(frame: &[u8], keep: &[Rect], inside: bool) -> (f32, f32) {
+        let samples: Vec<f32> = frame.chunks_exact(3).enumerate()
+            .filter(|(index, _)| {
+                let (x, y) = (index % WIDTH, index / WIDTH);
+                keep.iter().any(|(left, top, right, bottom)| (*left .. *right).contains(&x) && (*top .. *bottom).contains(&y)) == inside
+            })
+            .flat_map(|(_, pixel)| pixel.iter().map(|sample| *sample as f32))
+            .collect();
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        (mean, samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f32>() / samples.len() as f32)
+    }
+
+
+    #[test]
+    fn only_the_kept_boxes_keep_their_detail() {
+        // Two movements, one box overlapping the edge of the other.
+        let keep = [(8, 8, 24, 24), (20, 20, 40, 32)];
+        let original = checkerboard();
+        let (mean, variance) = statistics(&original, &keep, false);
+        for privacy in [Privacy::Blur, Privacy::Pixelate, Privacy::Blackout] {
+            let mut frame = original.clone();
+            privacy.apply(&mut frame, WIDTH, HEIGHT, 3, &keep);
+            assert_eq!(statistics(&frame, &keep, true), statistics(&original, &keep, true), "{privacy:?}");
+            let (obscured_mean, obscured_variance) = statistics(&frame, &keep, false);
+            assert!(obscured_variance < variance / 100.0, "{privacy:?}: {obscured_variance} of {variance}");
+            match privacy {
+                Privacy::Blackout => assert_eq!(obscured_mean, 0.0),
+                _ => assert!((obscured_mean - mean).abs() < 5.0, "{privacy:?}: {obscured_mean} for {mean}"),
+            }
+        }
+    }
+
+
+    #[test]
+    fn each_group_of_changes_is_a_box_with_its_margin() {
+        // On a 16 by 12 diff of the 64 by 48 frame, a 2 by 2 group and a single pixel in the corner.
+        let mut mask = vec![0u8; 16 * 12];
+        for index in [2 * 16 + 3, 2 * 16 + 4, 3 * 16 + 3, 3 * 16 + 4, 11 * 16 + 15] {
+            mask[index] = 1;
+        }
+        assert_eq!(motion_boxes(&mask, 16, WIDTH, HEIGHT, 0.0), [(12, 8, 20, 16), (60, 44, 64, 48)]);
+        // Widened by 1/16 of the frame on each side, up to its edges.
+        assert_eq!(motion_boxes(&mask, 16, WIDTH, HEIGHT, 1.0 / 16.0), [(8, 5, 24, 19), (56, 41, 64, 48)]);
+        assert!(motion_boxes(&[0; 16 * 12], 16, WIDTH, HEIGHT, 0.1).is_empty());
+    }
+}
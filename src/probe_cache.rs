@@ -0,0 +1,169 @@
+use std::{
+    fs, io,
+    path::{ Path, PathBuf },
+    time::Duration,
+};
+use eye::hal::{ device::Description, format::PixelFormat, stream::Descriptor };
+
+// First line of the file, bumped whenever the layout below changes, older files are then ignored.
+const HEADER: &str = "motion-detect probe cache 1";
+
+
+/// What probing the cameras found, so the next start can skip it: some backends take seconds per
+/// device to list every stream. The file is text, one tab separated record per line after the
+/// header: `device`, URI, product, the pixel format that started (or `-`) and the probe time in
+/// milliseconds, followed by one `stream` record per advertised stream: pixel format, width,
+/// height and interval in nanoseconds. Pixel formats are written as `rgb24`, `gray8`, `jpeg` or
+/// `custom:YUYV` and so on.
+///
+/// It never stands in for the devices themselves: a cached device gone or replaced by another
+/// product, or one there is now that wasn't probed, makes the whole cache stale.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeCache {
+    pub devices: Vec<CachedDevice>,     // In the order they were probed.
+}
+
+
+/// One probed device.
+#[derive(Debug, Clone)]
+pub struct CachedDevice {
+    pub uri: String,
+    pub product: String,
+    pub streams: Vec<Descriptor>,       // As advertised, no video streams if empty.
+    pub started: Option<PixelFormat>,   // The pixel format the stream started in, on the device that was opened.
+    pub probe_time: Duration,           // How long the full open took, to tell what the cache saved.
+}
+
+
+impl ProbeCache {
+
+    /// Reads the cache, empty if there is none yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("can't read probe cache {}: {err}", path.display())),
+        };
+        let invalid = |line: usize| format!("invalid probe cache {}, line {line}", path.display());
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, header)| header) != Some(HEADER) {
+            return Err(format!("probe cache {} is of another version", path.display()));
+        }
+        let mut devices: Vec<CachedDevice> = Vec::new();
+        for (index, line) in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["device", uri, product, started, probe_ms] => devices.push(CachedDevice {
+                    uri: uri.to_string(),
+                    product: product.to_string(),
+                    streams: Vec::new(),
+                    started: match *started {
+                        "-" => None,
+                        name => Some(parse_format(name).ok_or_else(|| invalid(index + 1))?),
+                    },
+                    probe_time: Duration::from_millis(probe_ms.parse().map_err(|_| invalid(index + 1))?),
+                }),
+                ["stream", pixfmt, width, height, interval] => {
+                    let number = |field: &str| field.parse::<u64>().map_err(|_| invalid(index + 1));
+                    let stream = Descriptor {
+                        pixfmt: parse_format(pixfmt).ok_or_else(|| invalid(index + 1))?,
+                        width: number(width)? as u32,
+                        height: number(height)? as u32,
+                        interval: Duration::from_nanos(number(interval)?),
+                    };
+                    devices.last_mut().ok_or_else(|| invalid(index + 1))?.streams.push(stream);
+                }
+                [""] => {}
+                _ => return Err(invalid(index + 1)),
+            }
+        }
+        Ok(Self { devices })
+    }
+
+    /// Writes the cache to a temporary file first, so a crash never leaves a half written one behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = format!("{HEADER}\n");
+        for device in &self.devices {
+            let started = device.started.as_ref().map_or(String::from("-"), format_name);
+            text += &format!("device\t{}\t{}\t{started}\t{}\n", clean(&device.uri), clean(&device.product), device.probe_time.as_millis());
+            for stream in &device.streams {
+                text += &format!("stream\t{}\t{}\t{}\t{}\n", format_name(&stream.pixfmt), stream.width, stream.height, stream.interval.as_nanos());
+            }
+        }
+        if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            fs::create_dir_all(directory)?;
+        }
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// The device to open among those there are now: the first one with video streams, as a full
+    /// probe would pick. The error says why the cache is stale.
+    pub fn select(&self, devices: &[Description]) -> Result<&CachedDevice, String> {
+        if self.devices.is_empty() {
+            return Err("nothing cached yet".to_string());
+        }
+        for cached in &self.devices {
+            match devices.iter().find(|device| device.uri == cached.uri) {
+                None => return Err(format!("device {} is gone", cached.uri)),
+                Some(device) if device.product != cached.product => return Err(format!("device {} is now {}", cached.uri, device.product)),
+                Some(_) => {}
+            }
+        }
+        for device in devices {
+            let cached = self.devices.iter().find(|cached| cached.uri == device.uri).ok_or_else(|| format!("device {} wasn't probed", device.uri))?;
+            if !cached.streams.is_empty() {
+                return Ok(cached);
+            }
+        }
+        Err("no cached device has video streams".to_string())
+    }
+}
+
+
+/// Where the cache is kept unless --no-probe-cache: in $XDG_CACHE_HOME, or ~/.cache without it.
+pub fn default_path() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|home| !home.is_empty()).map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("motion-detect").join("probe-cache"))
+}
+
+
+fn format_name(pixfmt: &PixelFormat) -> String {
+    match pixfmt {
+        PixelFormat::Custom(fourcc) => format!("custom:{}", clean(fourcc)),
+        PixelFormat::Depth(bits) => format!("depth{bits}"),
+        PixelFormat::Gray(bits) => format!("gray{bits}"),
+        PixelFormat::Bgr(bits) => format!("bgr{bits}"),
+        PixelFormat::Rgb(bits) => format!("rgb{bits}"),
+        PixelFormat::Jpeg => String::from("jpeg"),
+    }
+}
+
+
+fn parse_format(name: &str) -> Option<PixelFormat> {
+    if let Some(fourcc) = name.strip_prefix("custom:") {
+        return Some(PixelFormat::Custom(fourcc.to_string()));
+    }
+    if name == "jpeg" {
+        return Some(PixelFormat::Jpeg);
+    }
+    let split = name.find(|c: char| c.is_ascii_digit())?;
+    let bits = name[split ..].parse().ok()?;
+    match &name[.. split] {
+        "depth" => Some(PixelFormat::Depth(bits)),
+        "gray" => Some(PixelFormat::Gray(bits)),
+        "bgr" => Some(PixelFormat::Bgr(bits)),
+        "rgb" => Some(PixelFormat::Rgb(bits)),
+        _ => None,
+    }
+}
+
+
+// Tabs and line breaks would split records.
+fn clean(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
@@ -0,0 +1,168 @@
+use std::time::{ Duration, Instant };
+
+use crate::{ diff::DiffStrategy, noise::NoiseMap, thumbnail::Thumbnail };
+
+/// What the detector learned on one scene, e.g. a preset of a PTZ camera, saved by `scene save`.
+#[derive(Clone)]
+pub struct SceneProfile {
+    pub name: String,
+    pub reference: Thumbnail,
+    pub noise_map: Option<NoiseMap>,    // Only kept by the adaptive algorithm, same size as the reference.
+}
+
+
+impl SceneProfile {
+
+    /// Why the profile doesn't apply to thumbnails like `thumb`, if it doesn't.
+    pub fn mismatch(&self, thumb: &Thumbnail) -> Option<String> {
+        let size = |thumb: &Thumbnail| format!("{}x{} {}", thumb.width, thumb.height, if thumb.channels == 1 { "luma" } else { "RGB" });
+        let (saved, current) = (size(&self.reference), size(thumb));
+        (saved != current).then(|| format!("scene {} was saved for {saved} thumbnails, these are {current}", self.name))
+    }
+}
+
+
+/// The scenes saved by `scene save` and restored by `scene activate`, by name. With
+/// --expect-scene-change-signal, an activation comes before the camera moves to its scene, and
+/// nothing is compared until it settled there.
+pub struct SceneProfiles {
+    profiles: Vec<SceneProfile>,
+    settle: Option<Duration>,
+    settled_at: Option<Instant>,    // While settling after an activation.
+}
+
+
+impl SceneProfiles {
+
+    pub fn new(settle: Option<Duration>) -> Self {
+        Self { profiles: Vec::new(), settle, settled_at: None }
+    }
+
+    /// Takes the profiles of a state file. Those that don't apply to thumbnails like `thumb` are
+    /// dropped, the reason each is returned.
+    pub fn restore(&mut self, profiles: Vec<SceneProfile>, thumb: &Thumbnail) -> Vec<String> {
+        let mut dropped = Vec::new();
+        for profile in profiles {
+            match profile.mismatch(thumb) {
+                Some(reason) => dropped.push(reason),
+                None => self.profiles.push(profile),
+            }
+        }
+        dropped
+    }
+
+    /// Saves what `strategy` learned as the scene `name`, replacing any scene of that name.
+    pub fn save(&mut self, name: &str, strategy: &dyn DiffStrategy) -> Result<(), String> {
+        let reference = strategy.reference().ok_or_else(|| format!("can't save scene {name}, there's no reference yet"))?;
+        let profile = SceneProfile { name: name.to_string(), reference: reference.clone(), noise_map: strategy.noise_map().cloned() };
+        match self.profiles.iter_mut().find(|other| other.name == name) {
+            Some(other) => *other = profile,
+            None => self.profiles.push(profile),
+        }
+        Ok(())
+    }
+
+    /// Hands the scene `name` over to `strategy`, a fresh detector for thumbnails like `thumb`, and
+    /// settles from `now` if there's a settling time. A scene that doesn't apply to them anymore,
+    /// e.g. after the pixel format changed, is dropped.
+    pub fn activate(&mut self, name: &str, thumb: &Thumbnail, strategy: &mut dyn DiffStrategy, now: Instant) -> Result<(), String> {
+        let index = self.profiles.iter().position(|profile| profile.name == name)
+            .ok_or_else(|| format!("no scene {name}, save it first with scene save {name}"))?;
+        if let Some(reason) = self.profiles[index].mismatch(thumb) {
+            self.profiles.remove(index);
+            return Err(format!("{reason}, dropped it"));
+        }
+        let profile = &self.profiles[index];
+        strategy.set_reference(profile.reference.clone());
+        if let Some(noise_map) = &profile.noise_map {
+            strategy.set_noise_map(noise_map.clone());
+        }
+        self.settled_at = self.settle.map(|settle| now + settle);
+        Ok(())
+    }
+
+    /// True while settling after an activation, until its settling time passed.
+    pub fn settling(&mut self, now: Instant) -> bool {
+        if self.settled_at.is_some_and(|settled_at| now >= settled_at) {
+            self.settled_at = None;
+        }
+        self.settled_at.is_some()
+    }
+
+    pub fn settle(&self) -> Option<Duration> {
+        self.settle
+    }
+
+    pub fn profiles(&self) -> &[SceneProfile] {
+        &self.profiles
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::FrameDiff;
+
+
+    fn thumb(value: u8) -> Thumbnail {
+        let mut thumb = Thumbnail::with_channels(8, 8, 1);
+        thumb.pixels.fill(value);
+        thumb
+    }
+
+
+    #[test]
+    fn an_activated_scene_is_the_reference_to_compare_with() {
+        let mut profiles = SceneProfiles::new(None);
+        let mut kitchen = FrameDiff::new(25, 4);
+        assert!(profiles.save("kitchen", &kitchen).is_err(), "Nothing to save before the first frame");
+        kitchen.process(&thumb(60));
+        profiles.save("kitchen", &kitchen).unwrap();
+        let mut door = FrameDiff::new(25, 4);
+        door.process(&thumb(200));
+        profiles.activate("kitchen", &thumb(60), &mut door, Instant::now()).unwrap();
+        assert_eq!(door.process(&thumb(60)).changed_pixels, 0);
+        assert!(profiles.activate("garage", &thumb(60), &mut door, Instant::now()).is_err());
+    }
+
+
+    #[test]
+    fn saving_again_replaces_the_scene() {
+        let mut profiles = SceneProfiles::new(None);
+        let mut strategy = FrameDiff::new(25, 4);
+        strategy.process(&thumb(60));
+        profiles.save("kitchen", &strategy).unwrap();
+        strategy.set_reference(thumb(120));
+        profiles.save("kitchen", &strategy).unwrap();
+        assert_eq!(profiles.profiles().len(), 1);
+        assert_eq!(profiles.profiles()[0].reference.pixels, thumb(120).pixels);
+    }
+
+
+    #[test]
+    fn a_scene_of_another_size_is_dropped_when_activated() {
+        let mut profiles = SceneProfiles::new(Some(Duration::from_secs(1)));
+        let mut strategy = FrameDiff::new(25, 4);
+        strategy.process(&thumb(60));
+        profiles.save("kitchen", &strategy).unwrap();
+        let rgb = Thumbnail::new(8, 8);
+        let err = profiles.activate("kitchen", &rgb, &mut strategy, Instant::now()).unwrap_err();
+        assert_eq!(err, "scene kitchen was saved for 8x8 luma thumbnails, these are 8x8 RGB, dropped it");
+        assert!(profiles.profiles().is_empty());
+    }
+
+
+    #[test]
+    fn nothing_is_compared_until_the_camera_settled() {
+        let start = Instant::now();
+        let mut profiles = SceneProfiles::new(Some(Duration::from_secs(1)));
+        let mut strategy = FrameDiff::new(25, 4);
+        strategy.process(&thumb(60));
+        profiles.save("kitchen", &strategy).unwrap();
+        assert!(!profiles.settling(start));
+        profiles.activate("kitchen", &thumb(60), &mut strategy, start).unwrap();
+        assert!(profiles.settling(start + Duration::from_millis(900)));
+        assert!(!profiles.settling(start + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    path::PathBuf,
+    time::{ Duration, SystemTime },
+};
+
+use crate::{ clock::TimeSource, json, motion::MotionEvent, overlay, schedule::Schedule };
+
+/// A movement that started during quiet hours, held back from the notification outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldMovement {
+    pub id: u64,
+    pub start: SystemTime,
+    pub duration: Option<Duration>,     // None while it's in progress.
+    pub snapshots: Vec<PathBuf>,
+}
+
+
+/// Holds movements back from the notification outputs during quiet hours, and hands them over as
+/// a digest once the quiet hours end. Detection, the event output and the WebSocket stream go on
+/// as usual, only the outputs asking `event` hold back.
+///
+/// A movement belongs to the time it started in: one that started before the quiet hours still
+/// gets its stop delivered, and one that started during them is in the digest, with no duration
+/// if it's still in progress then. Its stop is delivered as usual afterwards.
+pub struct Holdover {
+    hours: Schedule,
+    held: Vec<HeldMovement>,
+    quiet: bool,
+    delivered: Option<u64>,     // The last movement whose start was delivered, its stop is too.
+}
+
+
+impl Holdover {
+
+    /// `held` are the movements restored from the state file, still waiting for their digest.
+    pub fn new(hours: Schedule, held: Vec<HeldMovement>) -> Self {
+        Self { hours, held, quiet: false, delivered: None }
+    }
+
+    pub fn held(&self) -> &[HeldMovement] {
+        &self.held
+    }
+
+    /// Called for every frame with its time. Returns the digest of the held movements when the
+    /// quiet hours are over, right away after a restart outside them.
+    pub fn update(&mut self, time: SystemTime) -> Option<Digest> {
+        self.quiet = self.hours.contains(time);
+        if self.quiet || self.held.is_empty() {
+            return None;
+        }
+        Some(Digest { time, hours: self.hours.clone(), movements: std::mem::take(&mut self.held) })
+    }
+
+    /// Tells whether the notification outputs get this event now, holding the movements that
+    /// start during quiet hours. `time` is the event's.
+    pub fn event(&mut self, event: MotionEvent, time: SystemTime) -> bool {
+        match event {
+            MotionEvent::Start { id, .. } if !self.quiet => {
+                self.delivered = Some(id);
+                true
+            }
+            MotionEvent::Start { id, .. } => {
+                self.held.push(HeldMovement { id, start: time, duration: None, snapshots: Vec::new() });
+                false
+            }
+            MotionEvent::Stop { id, duration, .. } => {
+                if let Some(movement) = self.held.iter_mut().find(|movement| movement.id == id) {
+                    movement.duration = Some(duration);
+                }
+                !self.quiet || self.delivered == Some(id)
+            }
+            MotionEvent::Provisional { .. } | MotionEvent::ProvisionalCancel { .. } => !self.quiet,
+        }
+    }
+
+    /// Adds the snapshots saved for a held movement.
+    pub fn snapshots(&mut self, id: u64, snapshots: &[PathBuf]) {
+        if let Some(movement) = self.held.iter_mut().find(|movement| movement.id == id) {
+            movement.snapshots.extend_from_slice(snapshots);
+        }
+    }
+}
+
+
+/// What happened during quiet hours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub time: SystemTime,
+    pub hours: Schedule,
+    pub movements: Vec<HeldMovement>,
+}
+
+
+impl Digest {
+
+    /// Total duration of the movements, those in progress up to the digest.
+    pub fn motion_time(&self) -> Duration {
+        self.movements.iter()
+            .map(|movement| movement.duration.unwrap_or_else(|| self.time.duration_since(movement.start).unwrap_or_default()))
+            .sum()
+    }
+
+    pub fn to_json(&self, source: TimeSource) -> String {
+        let movements = self.movements.iter()
+            .map(|movement| json::Object::new()
+                .field("id", movement.id)
+                .field("start", json::unix_time(movement.start))
+                .field("duration", movement.duration.map(|duration| duration.as_secs_f64()))
+                .field("snapshots", movement.snapshots.iter().map(|path| path.display().to_string()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        json::Object::new()
+            .field("type", "digest")
+            .field("time", json::unix_time(self.time))
+            .field("time_source", source.name())
+            .field("quiet_hours", self.hours.to_string())
+            .field("count", self.movements.len())
+            .field("motion_time", self.motion_time().as_secs_f64())
+            .field("movements", movements)
+            .finish()
+    }
+
+    /// The line printed in text mode, e.g. "digest 3 movements during quiet hours 23:00-07:00, 42s of motion".
+    pub fn text(&self) -> String {
+        format!("digest {}, {:.0?} of motion", self.headline(), self.motion_time())
+    }
+
+    /// E.g. "3 movements during quiet hours 23:00-07:00".
+    pub fn headline(&self) -> String {
+        let plural = if self.movements.len() == 1 { "" } else { "s" };
+        format!("{} movement{plural} during quiet hours {}", self.movements.len(), self.hours)
+    }
+
+    /// One line per movement with its start, duration and snapshots, for emails.
+    pub fn lines(&self) -> Vec<String> {
+        self.movements.iter()
+            .map(|movement| {
+                let duration = match movement.duration {
+                    Some(duration) => format!("{duration:.0?}"),
+                    None => "still in progress".to_string(),
+                };
+                let mut line = format!("Movement {} at {}, {duration}", movement.id, overlay::utc_timestamp(movement.start));
+                for path in &movement.snapshots {
+                    line.push_str(&format!("\n    {}", path.display()));
+                }
+                line
+            })
+            .collect()
+    }
+}
+
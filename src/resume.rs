@@ -0,0 +1,176 @@
+use std::time::{ Duration, Instant, SystemTime };
+
+use crate::{
+    clock::TimeSource,
+    json,
+    scene::{ self, BoundingBox },
+    settings::ResumePolicy,
+};
+
+
+/// What brought detection back after a while it compared nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeCause {
+    Resume,     // From a pause, by SIGUSR2 or a control request.
+    Reconnect,  // The camera came back after it was lost.
+}
+
+
+impl ResumeCause {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResumeCause::Resume => "resume",
+            ResumeCause::Reconnect => "reconnect",
+        }
+    }
+}
+
+
+/// What changed while detection was away, with --resume-policy report-delta.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumeDelta {
+    pub cause: ResumeCause,
+    pub away: Duration,         // Since the pause, or since the camera was lost.
+    pub changed_pixels: usize,
+    pub fraction: f32,          // Of the thumbnail's pixels, 0 to 1.
+    pub bbox: BoundingBox,
+}
+
+
+impl ResumeDelta {
+
+    pub fn to_json(&self, time: SystemTime, source: TimeSource) -> String {
+        let bbox = json::Object::new()
+            .field("x", self.bbox.x)
+            .field("y", self.bbox.y)
+            .field("width", self.bbox.width)
+            .field("height", self.bbox.height);
+        json::Object::new()
+            .field("type", "resume_delta")
+            .field("cause", self.cause.name())
+            .field("away", self.away.as_secs_f64())
+            .field("changed_pixels", self.changed_pixels)
+            .field("changed_fraction", self.fraction)
+            .field("bbox", bbox)
+            .field("time", json::unix_time(time))
+            .field("time_source", source.name())
+            .finish()
+    }
+
+    /// The line printed in text mode, e.g. "resume delta 3.2% at 40,55 12x20 after 3600.0s away".
+    pub fn text(&self) -> String {
+        format!(
+            "resume delta {:.1}% at {:.0},{:.0} {:.0}x{:.0} after {:.1}s away",
+            self.fraction * 100.0, self.bbox.x, self.bbox.y, self.bbox.width, self.bbox.height, self.away.as_secs_f64()
+        )
+    }
+}
+
+
+/// Keeps what the scene changed by while detection was away from turning into a movement. Once a
+/// pause or a lost camera is over, the baseline is from before it, and everything that changed
+/// meanwhile would start one big movement on the first frame compared. With
+/// `ResumePolicy::Rebaseline` the detector starts over from a fresh baseline right away, silently.
+/// With `ResumePolicy::ReportDelta` the first frame is still compared against the old baseline,
+/// and what changed, if it's enough to start a movement, is reported as a `ResumeDelta` instead.
+/// The detector starts over after that frame.
+pub struct ResumeGuard {
+    policy: ResumePolicy,
+    away_since: Option<Instant>,
+    pending: Option<(ResumeCause, Duration)>,  // Back, the next comparison tells what changed.
+}
+
+
+impl ResumeGuard {
+
+    pub fn new(policy: ResumePolicy) -> Self {
+        Self { policy, away_since: None, pending: None }
+    }
+
+    /// Detection stopped comparing, e.g. paused or the camera lost. The first of several counts.
+    pub fn away(&mut self, now: Instant) {
+        self.away_since.get_or_insert(now);
+    }
+
+    /// Detection is back. True if the detector is to start over from a fresh baseline right away,
+    /// otherwise the next comparison goes to `compared` first.
+    pub fn back(&mut self, cause: ResumeCause, now: Instant) -> bool {
+        let away = self.away_since.take().map_or(Duration::ZERO, |since| now.saturating_duration_since(since));
+        match self.policy {
+            ResumePolicy::Rebaseline => true,
+            ResumePolicy::ReportDelta => {
+                self.pending = Some((cause, away));
+                false
+            }
+        }
+    }
+
+    /// True while the next comparison is against the baseline from before.
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The first comparison after coming back: what changed while away, if more than
+    /// `start_count` pixels did, as for starting a movement. The detector starts over from a fresh
+    /// baseline after it either way.
+    pub fn compared(&mut self, changed_pixels: i32, start_count: i32, mask: &[u8], width: usize, height: usize) -> Option<ResumeDelta> {
+        let (cause, away) = self.pending.take()?;
+        (changed_pixels > start_count.max(0)).then(|| ResumeDelta {
+            cause,
+            away,
+            changed_pixels: changed_pixels as usize,
+            fraction: changed_pixels as f32 / (width * height).max(1) as f32,
+            bbox: scene::bounding_box(mask, width, height),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+
+    #[test]
+    fn rebaseline_starts_over_right_away() {
+        let mut guard = ResumeGuard::new(ResumePolicy::Rebaseline);
+        guard.away(Instant::now());
+        assert!(guard.back(ResumeCause::Reconnect, Instant::now()));
+        assert!(!guard.is_pending());
+    }
+
+
+    #[test]
+    fn report_delta_tells_what_changed_since_the_first_time_away() {
+        let mut guard = ResumeGuard::new(ResumePolicy::ReportDelta);
+        let since = Instant::now();
+        guard.away(since);
+        guard.away(since + Duration::from_secs(5));
+        assert!(!guard.back(ResumeCause::Reconnect, since + Duration::from_secs(60)));
+        assert!(guard.is_pending());
+        // The top left quarter of a 4 by 4 thumbnail changed.
+        let mut mask = [0; 16];
+        for index in [0, 1, 4, 5] {
+            mask[index] = 1;
+        }
+        let delta = guard.compared(4, 2, &mask, 4, 4).unwrap();
+        assert_eq!((delta.cause, delta.away, delta.fraction), (ResumeCause::Reconnect, Duration::from_secs(60), 0.25));
+        assert_eq!(delta.bbox, BoundingBox { x: 0.0, y: 0.0, width: 50.0, height: 50.0 });
+        assert!(!guard.is_pending());
+        assert_eq!(
+            delta.to_json(UNIX_EPOCH + Duration::from_secs(1_700_000_000), TimeSource::Driver),
+            r#"{"type":"resume_delta","cause":"reconnect","away":60.000,"changed_pixels":4,"changed_fraction":0.250,"bbox":{"x":0.000,"y":0.000,"width":50.000,"height":50.000},"time":1700000000.000,"time_source":"driver"}"#,
+        );
+    }
+
+
+    #[test]
+    fn report_delta_tells_nothing_below_the_start_count() {
+        let mut guard = ResumeGuard::new(ResumePolicy::ReportDelta);
+        guard.back(ResumeCause::Resume, Instant::now());
+        assert_eq!(guard.compared(2, 2, &[1, 1, 0, 0], 2, 2), None);
+        assert!(!guard.is_pending());
+    }
+}
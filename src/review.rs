@@ -0,0 +1,76 @@
+use crate::{
+    context::SavedContext,
+    diff::{ self, Blur, Masked, Normalize },
+    settings::Settings,
+};
+
+const EXIT_ARGUMENT: i32 = 22;      // Invalid argument
+const EXIT_FAILED: i32 = 5;         // I/O error
+
+
+/// Replays every event context saved by --capture-context-on-event in the review directory through
+/// a fresh detector built from the settings: the previous thumbnail, then the triggering one.
+/// Prints for each whether it would still start a movement, then how many would. Returns the
+/// process exit code: 5 if any context couldn't be read.
+pub fn run(settings: &Settings) -> i32 {
+    let review_dir = settings.review_dir.as_deref().expect("Review settings have a directory");
+    let directories = match SavedContext::list(review_dir) {
+        Ok(directories) if directories.is_empty() => {
+            println!("\nError, no event context in {}", review_dir.display());
+            return EXIT_ARGUMENT;
+        }
+        Ok(directories) => directories,
+        Err(err) => {
+            println!("\nError, {err}");
+            return EXIT_ARGUMENT;
+        }
+    };
+
+    let (mut triggered, mut failed) = (0, 0);
+    for directory in &directories {
+        let name = directory.file_name().unwrap_or_default().to_string_lossy();
+        let context = match SavedContext::load(directory) {
+            Ok(context) => context,
+            Err(err) => {
+                println!("{name}: failed, {err}");
+                failed += 1;
+                continue;
+            }
+        };
+        let (changed_pixels, start_count) = replay(settings, &context);
+        let verdict = if changed_pixels > start_count { "still triggers" } else { "no longer triggers" };
+        triggered += (changed_pixels > start_count) as usize;
+        println!(
+            "{name}: {verdict}, {changed_pixels} pixels changed where more than {start_count} start a movement (movement {} had {} for {})",
+            context.id, context.changed_pixels, context.start_pixels
+        );
+    }
+    println!("{triggered} of {} events would still trigger", directories.len() - failed);
+    if failed > 0 {
+        println!("\nError, {failed} contexts couldn't be read");
+        return EXIT_FAILED;
+    }
+    0
+}
+
+
+// Changed pixels of the triggering thumbnail against the previous one, and how many start a
+// movement. As in batch, with the detector of the main loop.
+fn replay(settings: &Settings, context: &SavedContext) -> (i32, i32) {
+    let thumb_len = context.current.len();
+    let pixel_threshold = ((settings.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let start_count = (thumb_len as f32 * (settings.image_threshold / 100.0).clamp(0.0, 1.0)) as i32;
+    let mut strategy = diff::from_name(&settings.algorithm, pixel_threshold, start_count, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("Algorithm names are validated with the settings");
+    if let Some(mode) = settings.normalize {
+        strategy = Box::new(Normalize::new(strategy, mode));
+    }
+    if settings.blur > 0 {
+        strategy = Box::new(Blur::new(strategy, settings.blur));
+    }
+    if let Some(mask) = &settings.mask {
+        strategy = Box::new(Masked::new(strategy, mask.clone()));
+    }
+    strategy.process(&context.previous);
+    (strategy.process(&context.current).changed_pixels, start_count)
+}
@@ -0,0 +1,80 @@
+use std::time::{ Duration, Instant };
+
+/// One stream of a --stream-rotation: the name its events are tagged with, and the video device
+/// it's read from. Streams of one camera that can't run at the same time, e.g. a wide angle and
+/// a telephoto lens sharing a USB link, show up as devices of their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationStream {
+    pub name: String,
+    pub uri: String,
+}
+
+
+impl RotationStream {
+
+    /// Parses "name=uri" pairs separated by commas, e.g. "wide=/dev/video0,tele=/dev/video2".
+    pub fn parse_list(text: &str) -> Result<Vec<Self>, String> {
+        let mut streams: Vec<Self> = Vec::new();
+        for pair in text.split(',') {
+            let (name, uri) = pair.split_once('=')
+                .filter(|(name, uri)| !name.is_empty() && !uri.is_empty())
+                .ok_or_else(|| format!("Invalid stream '{pair}', expected <name>=<device>"))?;
+            if streams.iter().any(|stream| stream.name == name) {
+                return Err(format!("Stream '{name}' is given twice"));
+            }
+            streams.push(Self { name: name.to_string(), uri: uri.to_string() });
+        }
+        if streams.len() < 2 {
+            return Err("--stream-rotation needs at least two streams".to_string());
+        }
+        Ok(streams)
+    }
+}
+
+
+/// Which stream of the rotation is being read, and when to move on to the next one. A stream
+/// keeps going past its dwell while something moves on it, so a movement is never cut off by a
+/// switch, and its dwell starts over once the camera delivers it.
+pub struct StreamRotation {
+    streams: Vec<RotationStream>,
+    dwell: Duration,
+    current: usize,
+    since: Instant,
+}
+
+
+impl StreamRotation {
+
+    /// Starts with the first stream, already open at `now`.
+    pub fn new(streams: Vec<RotationStream>, dwell: Duration, now: Instant) -> Self {
+        Self { streams, dwell, current: 0, since: now }
+    }
+
+    pub fn streams(&self) -> &[RotationStream] {
+        &self.streams
+    }
+
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> &RotationStream {
+        &self.streams[self.current]
+    }
+
+    /// True once the current stream had its dwell, unless `busy` with a movement.
+    pub fn due(&self, now: Instant, busy: bool) -> bool {
+        !busy && now.saturating_duration_since(self.since) >= self.dwell
+    }
+
+    /// The stream after the current one, in order.
+    pub fn upcoming(&self) -> &RotationStream {
+        &self.streams[(self.current + 1) % self.streams.len()]
+    }
+
+    /// Makes the next stream the current one, once it delivers at `now`.
+    pub fn advance(&mut self, now: Instant) {
+        self.current = (self.current + 1) % self.streams.len();
+        self.since = now;
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::{ Duration, Instant };
+
+use crate::thumbnail::Thumbnail;
+
+/// Share of the samples at 0, or at 255, that makes a thumbnail black, or white, whatever its mean.
+pub const CLIPPED_SHARE: f32 = 0.95;
+
+/// Unsaturated thumbnails in a row it takes to leave the saturated state, so a flickering lamp
+/// doesn't start the baseline over with every frame.
+pub const RECOVERY_FRAMES: u32 = 3;
+
+
+/// How a thumbnail is saturated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Black,  // E.g. the IR illuminator is off at night.
+    White,  // E.g. the camera stares into a lamp.
+}
+
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Black => "black",
+            Level::White => "white",
+        }
+    }
+}
+
+
+/// What happens to detection while thumbnails are saturated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaturatedPolicy {
+    Suppress,   // Nothing is compared, no movement starts, one in progress stops after its tail.
+    Detect,     // Compared as usual, only learning is paused.
+    Tamper,     // Every thumbnail counts as entirely changed, so being blinded starts a movement.
+}
+
+
+impl SaturatedPolicy {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "suppress" => Ok(SaturatedPolicy::Suppress),
+            "detect" => Ok(SaturatedPolicy::Detect),
+            "tamper" => Ok(SaturatedPolicy::Tamper),
+            _ => Err(format!("Invalid saturated policy '{text}', use suppress, detect or tamper")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SaturatedPolicy::Suppress => "suppress",
+            SaturatedPolicy::Detect => "detect",
+            SaturatedPolicy::Tamper => "tamper",
+        }
+    }
+}
+
+
+/// Entering or leaving the saturated state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaturationChange {
+    /// The thumbnail is `level`, its samples average `mean` (0 to 255).
+    Entered { level: Level, mean: f32 },
+    /// Thumbnails are back to normal after being saturated for `duration`.
+    Left { duration: Duration },
+}
+
+
+/// Tells saturated thumbnails, whose differences mean nothing, from usable ones: a mean below the
+/// floor or above the ceiling, or nearly every sample clipped at 0 or 255.
+pub struct SaturationDetector {
+    floor: f32,             // Mean sample values, 0 to 255.
+    ceiling: f32,
+    saturated: Option<(Level, Instant)>,
+    unsaturated: u32,       // Unsaturated thumbnails in a row while saturated.
+}
+
+
+impl SaturationDetector {
+
+    /// * `floor_percent`, `ceiling_percent` - Of the full brightness.
+    pub fn new(floor_percent: f32, ceiling_percent: f32) -> Self {
+        Self {
+            floor: floor_percent.clamp(0.0, 100.0) * 2.55,
+            ceiling: ceiling_percent.clamp(0.0, 100.0) * 2.55,
+            saturated: None,
+            unsaturated: 0,
+        }
+    }
+
+    /// How the thumbnail is saturated, if it is, and the mean of its samples.
+    pub fn measure(&self, thumb: &Thumbnail) -> (Option<Level>, f32) {
+        let pixels = &thumb.pixels;
+        if pixels.is_empty() {
+            return (None, 0.0);
+        }
+        let (mut sum, mut black, mut white) = (0u64, 0usize, 0usize);
+        for value in pixels {
+            sum += *value as u64;
+            black += (*value == 0) as usize;
+            white += (*value == 255) as usize;
+        }
+        let mean = sum as f32 / pixels.len() as f32;
+        let clipped = (pixels.len() as f32 * CLIPPED_SHARE) as usize;
+        let level = if mean < self.floor || black > clipped {
+            Some(Level::Black)
+        } else if mean > self.ceiling || white > clipped {
+            Some(Level::White)
+        } else {
+            None
+        };
+        (level, mean)
+    }
+
+    /// Called for every compared thumbnail. A single saturated one enters the state, it takes
+    /// `RECOVERY_FRAMES` usable ones in a row to leave it. Going from black to white straight
+    /// away stays in it.
+    pub fn update(&mut self, thumb: &Thumbnail, now: Instant) -> Option<SaturationChange> {
+        let (level, mean) = self.measure(thumb);
+        match (level, self.saturated) {
+            (Some(level), None) => {
+                self.saturated = Some((level, now));
+                self.unsaturated = 0;
+                Some(SaturationChange::Entered { level, mean })
+            }
+            (Some(_), Some(_)) => {
+                self.unsaturated = 0;
+                None
+            }
+            (None, Some((_, since))) => {
+                self.unsaturated += 1;
+                if self.unsaturated < RECOVERY_FRAMES {
+                    return None;
+                }
+                self.saturated = None;
+                Some(SaturationChange::Left { duration: now.saturating_duration_since(since) })
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// The level thumbnails went saturated with, while they are.
+    pub fn level(&self) -> Option<Level> {
+        self.saturated.map(|(level, _)| level)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumb(value: u8) -> Thumbnail {
+        let mut thumb = Thumbnail::new(16, 12);
+        thumb.pixels.fill(value);
+        thumb
+    }
+
+
+    // Floors and ceilings of 5% and 95%, the defaults.
+    fn detector() -> SaturationDetector {
+        SaturationDetector::new(5.0, 95.0)
+    }
+
+
+    #[test]
+    fn a_black_thumbnail_enters_and_usable_ones_leave() {
+        let (mut detector, start) = (detector(), Instant::now());
+        let at = |frame: u64| start + Duration::from_millis(100 * frame);
+        assert_eq!(detector.update(&thumb(90), at(0)), None);
+        assert_eq!(detector.update(&thumb(4), at(1)), Some(SaturationChange::Entered { level: Level::Black, mean: 4.0 }));
+        assert_eq!(detector.level(), Some(Level::Black));
+        for frame in 2 .. 2 + RECOVERY_FRAMES as u64 - 1 {
+            assert_eq!(detector.update(&thumb(90), at(frame)), None);
+        }
+        let left = detector.update(&thumb(90), at(1 + RECOVERY_FRAMES as u64));
+        assert_eq!(left, Some(SaturationChange::Left { duration: Duration::from_millis(100 * RECOVERY_FRAMES as u64) }));
+        assert_eq!(detector.level(), None);
+    }
+
+
+    #[test]
+    fn a_white_thumbnail_enters_and_usable_ones_leave() {
+        let (mut detector, now) = (detector(), Instant::now());
+        assert_eq!(detector.update(&thumb(250), now), Some(SaturationChange::Entered { level: Level::White, mean: 250.0 }));
+        let changes: Vec<_> = (0 .. RECOVERY_FRAMES).filter_map(|_| detector.update(&thumb(120), now)).collect();
+        assert_eq!(changes, [SaturationChange::Left { duration: Duration::ZERO }]);
+    }
+
+
+    #[test]
+    fn clipped_samples_are_saturated_whatever_the_mean() {
+        let detector = detector();
+        // 96% at 0, the rest bright enough to lift the mean well above the floor.
+        let mut black = thumb(0);
+        let lit = black.pixels.len() * 4 / 100;
+        black.pixels[.. lit].fill(255);
+        assert_eq!(detector.measure(&black).0, Some(Level::Black));
+        let mut white = thumb(255);
+        white.pixels[.. lit].fill(0);
+        assert_eq!(detector.measure(&white).0, Some(Level::White));
+        let mut contrasted = thumb(0);
+        let half = contrasted.pixels.len() / 2;
+        contrasted.pixels[.. half].fill(255);
+        assert_eq!(detector.measure(&contrasted).0, None);
+    }
+
+
+    #[test]
+    fn a_flickering_lamp_stays_saturated() {
+        let (mut detector, now) = (detector(), Instant::now());
+        detector.update(&thumb(0), now);
+        for _ in 0 .. 10 {
+            for value in [90, 90, 255] {
+                assert_eq!(detector.update(&thumb(value), now), None);
+            }
+        }
+        // Straight from black to white stays in the state it entered with.
+        assert_eq!(detector.level(), Some(Level::Black));
+    }
+}
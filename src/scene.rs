@@ -0,0 +1,245 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{ Duration, Instant, SystemTime },
+};
+
+use crate::{
+    clock::TimeSource,
+    diff::{ DiffStrategy, FrameDiff, Masked, Normalization, Normalize },
+    json,
+    mask::MaskImage,
+    thumbnail::Thumbnail,
+};
+
+
+/// The part of the thumbnail that changed, in percent of its width and height like zones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+
+/// The scene changed against the reference by more than the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneDelta {
+    pub changed_pixels: usize,
+    pub fraction: f32,          // Of the thumbnail's pixels, 0 to 1.
+    pub bbox: BoundingBox,
+    pub reference: PathBuf,
+}
+
+
+impl SceneDelta {
+
+    pub fn to_json(&self, time: SystemTime, source: TimeSource) -> String {
+        let bbox = json::Object::new()
+            .field("x", self.bbox.x)
+            .field("y", self.bbox.y)
+            .field("width", self.bbox.width)
+            .field("height", self.bbox.height);
+        json::Object::new()
+            .field("type", "scene_delta")
+            .field("changed_pixels", self.changed_pixels)
+            .field("changed_fraction", self.fraction)
+            .field("bbox", bbox)
+            .field("reference", self.reference.display().to_string())
+            .field("time", json::unix_time(time))
+            .field("time_source", source.name())
+            .finish()
+    }
+
+    /// The line printed in text mode, e.g. "scene delta 3.2% at 40,55 12x20".
+    pub fn text(&self) -> String {
+        format!(
+            "scene delta {:.1}% at {:.0},{:.0} {:.0}x{:.0}",
+            self.fraction * 100.0, self.bbox.x, self.bbox.y, self.bbox.width, self.bbox.height
+        )
+    }
+}
+
+
+/// Compares a thumbnail against a fixed reference every so often, to tell whether anything in the
+/// scene changed since it was taken, however fast it happened: a package that appeared, a door
+/// left open. Unlike live detection the reference never follows the scene, it's saved to a file
+/// and only replaced on request.
+///
+/// Brightness is always matched to the reference with gain normalization before comparing, since
+/// the light of the morning the reference was taken in is long gone by the evening. The --mask
+/// applies as well, nothing else of the live detector does.
+pub struct SceneReference {
+    path: PathBuf,
+    reference: Option<Thumbnail>,
+    pixel_threshold: i32,       // From 0 to 255.
+    image_threshold: f32,       // Fraction of the pixels.
+    interval: Duration,
+    mask: Option<MaskImage>,
+    next_check: Option<Instant>,
+}
+
+
+impl SceneReference {
+
+    /// Loads the reference from `path` if it's there. Returns a warning as well if the file
+    /// couldn't be used, the next thumbnail then replaces it.
+    pub fn new(path: PathBuf, pixel_percent: f32, image_percent: f32, interval: Duration, mask: Option<MaskImage>) -> (Self, Option<String>) {
+        let (reference, warning) = match path.exists() {
+            true => match Thumbnail::read_pnm(&path) {
+                Ok(reference) => (Some(reference), None),
+                Err(err) => (None, Some(format!("ignoring the scene reference, {err}"))),
+            },
+            false => (None, None),
+        };
+        let scene = Self {
+            path,
+            reference,
+            pixel_threshold: ((pixel_percent * (255.0 / 100.0)) as i32).clamp(0, 255),
+            image_threshold: (image_percent / 100.0).clamp(0.0, 1.0),
+            interval,
+            mask,
+            next_check: None,
+        };
+        (scene, warning)
+    }
+
+    /// Makes `thumb` the reference and saves it.
+    pub fn set(&mut self, thumb: &Thumbnail, now: Instant) -> Result<(), String> {
+        self.reference = Some(thumb.clone());
+        self.next_check = Some(now + self.interval);
+        // Renamed into place, a crash never leaves half a reference behind.
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, thumb.to_pnm())
+            .and_then(|()| fs::rename(&temp_path, &self.path))
+            .map_err(|err| format!("can't save the scene reference {}: {err}", self.path.display()))
+    }
+
+    /// Compares the thumbnail of `now` whatever the interval, e.g. after the camera moved.
+    pub fn check_now(&mut self, now: Instant) {
+        self.next_check = Some(now);
+    }
+
+    /// Called for every compared thumbnail. The first one becomes the reference if there is none
+    /// or it's of another size, e.g. after --downsample changed. Once the interval is over,
+    /// compares the thumbnail and returns what changed if that's more than the threshold.
+    pub fn update(&mut self, thumb: &Thumbnail, now: Instant) -> Result<Option<SceneDelta>, String> {
+        let Some(reference) = self.reference.as_ref().filter(|reference| reference.same_shape(thumb)) else {
+            return self.set(thumb, now).map(|()| None);
+        };
+        let next_check = *self.next_check.get_or_insert(now + self.interval);
+        if now < next_check {
+            return Ok(None);
+        }
+        self.next_check = Some(now + self.interval);
+
+        let mut strategy: Box<dyn DiffStrategy> = Box::new(FrameDiff::new(self.pixel_threshold, i32::MAX));
+        strategy = Box::new(Normalize::new(strategy, Normalization::Gain));
+        if let Some(mask) = &self.mask {
+            strategy = Box::new(Masked::new(strategy, mask.clone()));
+        }
+        strategy.set_reference(reference.clone());
+        let result = strategy.process(thumb);
+        let changed_pixels = result.changed_pixels.max(0) as usize;
+        let fraction = changed_pixels as f32 / thumb.len().max(1) as f32;
+        if changed_pixels == 0 || fraction < self.image_threshold {
+            return Ok(None);
+        }
+        Ok(Some(SceneDelta { changed_pixels, fraction, bbox: bounding_box(result.mask, thumb.width, thumb.height), reference: self.path.clone() }))
+    }
+}
+
+
+// Of the pixels set in the mask, which has at least one.
+pub fn bounding_box(mask: &[u8], width: usize, height: usize) -> BoundingBox {
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (index, _) in mask.iter().enumerate().filter(|(_, changed)| **changed != 0) {
+        let (x, y) = (index % width, index / width);
+        left = left.min(x);
+        top = top.min(y);
+        right = right.max(x + 1);
+        bottom = bottom.max(y + 1);
+    }
+    let percent = |value: usize, total: usize| value as f32 * 100.0 / total.max(1) as f32;
+    BoundingBox {
+        x: percent(left, width),
+        y: percent(top, height),
+        width: percent(right.saturating_sub(left), width),
+        height: percent(bottom.saturating_sub(top), height),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ env, process };
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 24;
+    const SECOND: Duration = Duration::from_secs(1);
+
+
+    // A room lit from the left, with a dark box of 8 by 8 at `box_left`, its brightness through
+    // `light`.
+    fn room(box_left: usize, light: impl Fn(f32) -> f32) -> Thumbnail {
+        let mut thumb = Thumbnail::with_channels(WIDTH, HEIGHT, 1);
+        for (index, pixel) in thumb.pixels.iter_mut().enumerate() {
+            let (x, y) = (index % WIDTH, index / WIDTH);
+            let value = if (box_left .. box_left + 8).contains(&x) && (8 .. 16).contains(&y) { 30.0 } else { 180.0 - x as f32 * 3.0 };
+            *pixel = light(value).round().clamp(0.0, 255.0) as u8;
+        }
+        thumb
+    }
+
+
+    fn reference(test: &str) -> (SceneReference, PathBuf) {
+        let path = env::temp_dir().join(format!("motion-detect-scene-{test}-{}.pgm", process::id()));
+        let _ = fs::remove_file(&path);
+        let (scene, warning) = SceneReference::new(path.clone(), 10.0, 2.0, SECOND, None);
+        assert_eq!(warning, None);
+        (scene, path)
+    }
+
+
+    #[test]
+    fn a_change_of_light_is_no_scene_change() {
+        let (mut scene, path) = reference("light");
+        let now = Instant::now();
+        assert_eq!(scene.update(&room(4, |value| value), now), Ok(None));
+        // The evening, dimmer and flatter, then a lamp switched on.
+        assert_eq!(scene.update(&room(4, |value| value * 0.6 + 10.0), now + SECOND), Ok(None));
+        assert_eq!(scene.update(&room(4, |value| value + 40.0), now + SECOND * 2), Ok(None));
+        let _ = fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn a_moved_box_is_a_scene_change_under_any_light() {
+        let (mut scene, path) = reference("layout");
+        let now = Instant::now();
+        scene.update(&room(4, |value| value), now).unwrap();
+        // Not before the interval is over.
+        assert_eq!(scene.update(&room(20, |value| value), now), Ok(None));
+        let delta = scene.update(&room(20, |value| value * 0.6 + 10.0), now + SECOND).unwrap().expect("A scene change");
+        // Where the box was and where it is now, nothing in between.
+        assert_eq!(delta.changed_pixels, 2 * 64);
+        assert_eq!(delta.bbox, BoundingBox { x: 12.5, y: 100.0 / 3.0, width: 75.0, height: 100.0 / 3.0 });
+        assert_eq!(delta.reference, path);
+        let _ = fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn the_reference_is_loaded_back_from_its_file() {
+        let (mut scene, path) = reference("saved");
+        let now = Instant::now();
+        scene.update(&room(4, |value| value), now).unwrap();
+        let (mut scene, warning) = SceneReference::new(path.clone(), 10.0, 2.0, SECOND, None);
+        assert_eq!(warning, None);
+        assert!(scene.update(&room(20, |value| value), now).unwrap().is_none());
+        assert!(scene.update(&room(20, |value| value), now + SECOND).unwrap().is_some());
+        let _ = fs::remove_file(&path);
+    }
+}
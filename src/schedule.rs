@@ -0,0 +1,85 @@
+use std::{ fmt, time::{ Duration, SystemTime, UNIX_EPOCH } };
+
+use crate::clock::UtcTime;
+
+const DAY: u64 = 24 * 60 * 60;
+
+
+/// Daily windows, in UTC like every time motion-detect reports, e.g. "23:00-07:00" or
+/// "07:30-12:00,13:00-18:00". A window may span midnight, its start is included and its end
+/// isn't. Windows may overlap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    windows: Vec<(u32, u32)>,   // Start and end, in minutes since midnight.
+}
+
+
+impl Schedule {
+
+    /// Parses "HH:MM-HH:MM" windows separated by commas.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid schedule '{text}', expected HH:MM-HH:MM windows separated by commas");
+        let minutes = |time: &str| -> Result<u32, String> {
+            let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+            let (hours, minutes) = (hours.parse::<u32>().map_err(|_| invalid())?, minutes.parse::<u32>().map_err(|_| invalid())?);
+            if hours > 23 || minutes > 59 || time.len() != 5 {
+                return Err(invalid());
+            }
+            Ok(hours * 60 + minutes)
+        };
+        let windows = text.split(',')
+            .map(|window| {
+                let (start, end) = window.split_once('-').ok_or_else(invalid)?;
+                let (start, end) = (minutes(start)?, minutes(end)?);
+                if start == end {
+                    return Err(format!("Window '{window}' of schedule '{text}' starts and ends at the same time"));
+                }
+                Ok((start, end))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { windows })
+    }
+
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let utc = UtcTime::from(time);
+        self.contains_minute(utc.hour * 60 + utc.minute)
+    }
+
+    /// The next time after `time` the schedule goes in or out of its windows, None if it never
+    /// does because they cover the whole day.
+    pub fn next_change(&self, time: SystemTime) -> Option<SystemTime> {
+        let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let midnight = seconds - seconds % DAY;
+        let inside = self.contains(time);
+        // Every start and end for today and tomorrow, in order, the first going the other way.
+        let mut boundaries: Vec<u64> = self.windows.iter()
+            .flat_map(|(start, end)| [*start, *end])
+            .flat_map(|minute| [midnight + minute as u64 * 60, midnight + DAY + minute as u64 * 60])
+            .filter(|boundary| *boundary > seconds)
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.into_iter()
+            .find(|boundary| self.contains_minute(((boundary % DAY) / 60) as u32) != inside)
+            .map(|boundary| UNIX_EPOCH + Duration::from_secs(boundary))
+    }
+
+    fn contains_minute(&self, minute: u32) -> bool {
+        self.windows.iter().any(|(start, end)| match start < end {
+            true => (*start .. *end).contains(&minute),
+            false => minute >= *start || minute < *end,
+        })
+    }
+}
+
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (start, end)) in self.windows.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{:02}:{:02}-{:02}:{:02}", start / 60, start % 60, end / 60, end % 60)?;
+        }
+        Ok(())
+    }
+}
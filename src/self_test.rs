@@ -0,0 +1,246 @@
+use std::{
+    fs,
+    io::{ Read, Write },
+    net::{ IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs },
+    time::{ Duration, Instant },
+};
+use eye::hal::PlatformContext;
+
+use crate::{
+    camera::{ self, Camera },
+    diff::{ self, Blur },
+    http::HttpServer,
+    output::{ Format, Output },
+    settings::Settings,
+    state::SavedState,
+    thumbnail::Thumbnail,
+};
+
+// Exit codes, one per stage so scripts can tell a camera problem from a network problem.
+const EXIT_DEVICE: i32 = 19;        // No such device
+const EXIT_CAPTURE: i32 = 5;        // I/O error
+const EXIT_CONTENT: i32 = 61;       // No data available
+const EXIT_TIMING: i32 = 62;        // Timer expired
+const EXIT_STATE: i32 = 13;         // Permission denied
+const EXIT_HTTP: i32 = 98;          // Address in use
+#[cfg(feature = "desktop-notify")]
+const EXIT_NOTIFY: i32 = 6;         // No such device or address
+
+// Frames captured back to back to check content, frame rate and processing time.
+const FRAME_COUNT: usize = 10;
+
+// Frames whose brightest thumbnail pixel stays below this are considered black.
+const BLACK_LEVEL: u8 = 16;
+
+
+struct Stage {
+    name: &'static str,
+    result: &'static str,
+    detail: String,
+    exit_code: i32,
+}
+
+
+#[derive(Default)]
+struct Report {
+    stages: Vec<Stage>,
+}
+
+
+impl Report {
+
+    fn pass(&mut self, name: &'static str, detail: String) {
+        self.stages.push(Stage { name, result: "pass", detail, exit_code: 0 });
+    }
+
+    fn fail(&mut self, name: &'static str, detail: String, exit_code: i32) {
+        self.stages.push(Stage { name, result: "FAIL", detail, exit_code });
+    }
+
+    fn skip(&mut self, name: &'static str, detail: &str) {
+        self.stages.push(Stage { name, result: "skip", detail: detail.to_string(), exit_code: 0 });
+    }
+
+    fn print(&self) {
+        println!("\n{:<12}{:<8}Details", "Stage", "Result");
+        for stage in &self.stages {
+            println!("{:<12}{:<8}{}", stage.name, stage.result, stage.detail);
+        }
+    }
+
+    /// The exit code of the first failed stage, 0 if everything passed.
+    fn exit_code(&self) -> i32 {
+        self.stages.iter().map(|stage| stage.exit_code).find(|code| *code != 0).unwrap_or(0)
+    }
+}
+
+
+/// Runs the whole pipeline once with the given settings, prints a pass/fail table and returns
+/// the process exit code.
+pub fn run(settings: &Settings) -> i32 {
+    let mut report = Report::default();
+    let output = Output::new(Format::Text);
+
+    let ctx = PlatformContext::default();
+    let mut camera_name = String::from("unknown camera");
+    match Camera::open(&ctx, settings.capture_width, settings.capture_height, settings.frame_capture_interval, &output) {
+        Ok(mut camera) => {
+            camera_name = settings.name.clone().unwrap_or_else(|| camera.description.product.clone());
+            report.pass("device", format!(
+                "{} ({}), {}x{} {}",
+                camera.description.product, camera.description.uri,
+                camera.descriptor.width, camera.descriptor.height, camera.descriptor.pixfmt
+            ));
+            if let Some(layout) = settings.force_input_layout {
+                camera.force_layout(layout);
+            }
+            test_pipeline(&mut report, &mut camera, settings);
+        }
+        Err(err) => report.fail("device", err.to_string(), EXIT_DEVICE),
+    }
+
+    test_state_file(&mut report, settings);
+    test_http(&mut report, settings);
+    test_notify(&mut report, settings, &camera_name);
+
+    report.print();
+    report.exit_code()
+}
+
+
+/// Captures a few frames through downsampling and the configured diff strategy.
+fn test_pipeline(report: &mut Report, camera: &mut Camera, settings: &Settings) {
+    println!("Warming up for {:.1?}...", settings.camera_warm_up);
+    std::thread::sleep(settings.camera_warm_up);
+
+    let frame_width = camera.descriptor.width as usize;
+    let layout = camera::layout(&camera.descriptor.pixfmt, settings.force_input_layout);
+    let channels = layout.channels();
+    let thumb_width = frame_width / settings.downsample;
+    let thumb_height = camera.descriptor.height as usize / settings.downsample;
+    let pixel_threshold = ((settings.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let pixel_count_threshold = ((thumb_width * thumb_height) as f32 * settings.image_threshold / 100.0) as i32;
+    let mut strategy = diff::from_name(&settings.algorithm, pixel_threshold, pixel_count_threshold, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("Algorithm names are validated with the settings");
+    if settings.blur > 0 {
+        strategy = Box::new(Blur::new(strategy, settings.blur));
+    }
+
+    let mut thumbs = Vec::with_capacity(FRAME_COUNT);
+    let mut processing_time = Duration::ZERO;
+    let capture_start = Instant::now();
+    for _ in 0 .. FRAME_COUNT {
+        let frame = match camera.next_frame() {
+            Ok(frame) => frame,
+            Err(err) => return report.fail("capture", err, EXIT_CAPTURE),
+        };
+        let processing_start = Instant::now();
+        let mut thumb = Thumbnail::with_channels(thumb_width, thumb_height, channels).with_stats(settings.thumb_stats);
+        thumb.downsample(frame, frame_width, settings.downsample, layout);
+        strategy.process(&thumb);
+        processing_time += processing_start.elapsed();
+        thumbs.push(thumb);
+    }
+    let capture_time = capture_start.elapsed();
+    report.pass("capture", format!("{FRAME_COUNT} frames"));
+
+    let brightest = thumbs.iter().flat_map(|thumb| thumb.pixels.iter().copied()).max().unwrap_or(0);
+    if brightest < BLACK_LEVEL {
+        report.fail("content", format!("all frames are black (brightest value {brightest})"), EXIT_CONTENT);
+    } else if thumbs.iter().all(|thumb| thumb.pixels == thumbs[0].pixels) {
+        report.fail("content", "all frames are identical, the camera may be frozen".to_string(), EXIT_CONTENT);
+    } else {
+        report.pass("content", format!("brightest value {brightest}, frames differ"));
+    }
+
+    // The camera only needs to keep up with the capture interval, frames are dropped otherwise anyway.
+    let achieved_rate = FRAME_COUNT as f32 / capture_time.as_secs_f32();
+    let requested_rate = 1.0 / settings.frame_capture_interval.as_secs_f32();
+    let detail = format!("{achieved_rate:.1} fps, {requested_rate:.1} fps requested");
+    if achieved_rate < requested_rate * 0.5 {
+        report.fail("frame rate", detail, EXIT_TIMING);
+    } else {
+        report.pass("frame rate", detail);
+    }
+
+    let average_processing = processing_time / FRAME_COUNT as u32;
+    let detail = format!("{average_processing:.1?} per frame");
+    if average_processing > settings.frame_capture_interval {
+        report.fail("processing", detail, EXIT_TIMING);
+    } else {
+        report.pass("processing", detail);
+    }
+}
+
+
+/// Checks that a saved state loads and that the state file can be written.
+fn test_state_file(report: &mut Report, settings: &Settings) {
+    let Some(path) = &settings.state_file else {
+        return report.skip("state file", "not configured");
+    };
+    if path.exists() {
+        if let Err(err) = SavedState::load(path) {
+            return report.fail("state file", err, EXIT_STATE);
+        }
+    }
+    let test_path = path.with_extension("selftest");
+    match fs::write(&test_path, b"motion-detect self-test") {
+        Ok(()) => {
+            let _ = fs::remove_file(&test_path);
+            report.pass("state file", format!("{} is writable", path.display()));
+        }
+        Err(err) => report.fail("state file", format!("can't write next to {}: {err}", path.display()), EXIT_STATE),
+    }
+}
+
+
+/// Starts the HTTP server and requests its status page.
+fn test_http(report: &mut Report, settings: &Settings) {
+    let Some(address) = &settings.http_address else {
+        return report.skip("http", "not configured");
+    };
+    if let Err(err) = HttpServer::start(address) {
+        return report.fail("http", format!("can't listen on {address}: {err}"), EXIT_HTTP);
+    }
+    match request_status(address) {
+        Ok(status_line) if status_line.contains(" 200 ") => report.pass("http", format!("GET /status on {address}")),
+        Ok(status_line) => report.fail("http", format!("GET /status answered {status_line}"), EXIT_HTTP),
+        Err(err) => report.fail("http", format!("GET /status failed: {err}"), EXIT_HTTP),
+    }
+}
+
+
+#[cfg(feature = "desktop-notify")]
+fn test_notify(report: &mut Report, settings: &Settings, camera_name: &str) {
+    if !settings.notify {
+        return report.skip("notify", "not enabled");
+    }
+    match crate::notify::send_test(camera_name) {
+        Ok(()) => report.pass("notify", "test notification shown".to_string()),
+        Err(err) => report.fail("notify", err, EXIT_NOTIFY),
+    }
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+fn test_notify(report: &mut Report, _settings: &Settings, _camera_name: &str) {
+    report.skip("notify", "not compiled in");
+}
+
+
+fn request_status(address: &str) -> std::io::Result<String> {
+    let mut target = address.to_socket_addrs()?.next()
+        .ok_or_else(|| std::io::Error::other("address doesn't resolve"))?;
+    // Listening on all interfaces, talk to it through loopback.
+    if target.ip().is_unspecified() {
+        target.set_ip(match target.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    let mut stream = TcpStream::connect_timeout(&target, Duration::from_secs(2))?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.write_all(b"GET /status HTTP/1.1\r\nHost: self-test\r\nConnection: close\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response.lines().next().unwrap_or_default().to_string())
+}
@@ -0,0 +1,184 @@
+use std::time::{ Duration, Instant };
+
+use crate::json;
+
+/// Why frames never made it to the detector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Processing took so long that capture slots went by without a frame.
+    Overload,
+    /// The source failed, frames were lost until it delivered again.
+    CaptureError,
+    /// The frame was captured, but processing it panicked.
+    ProcessingPanic,
+    /// The frame was thrown away on purpose, see decimation::Decimator. Not a loss.
+    Decimated,
+}
+
+
+impl DropReason {
+
+    pub const ALL: [DropReason; 4] = [DropReason::Overload, DropReason::CaptureError, DropReason::ProcessingPanic, DropReason::Decimated];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropReason::Overload => "overload",
+            DropReason::CaptureError => "capture_error",
+            DropReason::ProcessingPanic => "processing_panic",
+            DropReason::Decimated => "decimated",
+        }
+    }
+}
+
+
+/// Numbers frames as they are captured, so that frames that were never captured show up as gaps.
+///
+/// Frames are expected one capture interval apart. A frame arriving later than one and a half
+/// intervals after the previous one skips the sequence numbers of the slots missed in between,
+/// accounted to overload, or to a capture error if the source failed meanwhile. Sequence numbers
+/// start at 1 and only start over with the process, reconnects included.
+#[derive(Debug, Clone)]
+pub struct FrameCounter {
+    interval: Duration,
+    last_sequence: u64,
+    last_capture: Option<Instant>,
+    source_failed: bool,        // Since the last captured frame.
+    dropped: [u64; 4],          // Indexed by DropReason.
+}
+
+
+impl FrameCounter {
+
+    /// Expects a frame every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last_sequence: 0, last_capture: None, source_failed: false, dropped: [0; 4] }
+    }
+
+    /// Changes the expected time between frames, e.g. when the CPU budget slows capture down.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Numbers a frame captured at `time`, after those missed since the previous one.
+    pub fn captured(&mut self, time: Instant) -> u64 {
+        let missed = match self.last_capture {
+            Some(last) if !self.interval.is_zero() => {
+                let slots = time.saturating_duration_since(last).as_secs_f64() / self.interval.as_secs_f64();
+                if slots > 1.5 { (slots.round() as u64).saturating_sub(1) } else { 0 }
+            }
+            _ => 0,
+        };
+        let reason = if self.source_failed { DropReason::CaptureError } else { DropReason::Overload };
+        self.drop_frames(reason, missed);
+        self.last_sequence += missed + 1;
+        self.last_capture = Some(time);
+        self.source_failed = false;
+        self.last_sequence
+    }
+
+    /// Numbers a frame captured at `time` that is thrown away unprocessed.
+    pub fn decimated(&mut self, time: Instant) {
+        self.captured(time);
+        self.drop_frames(DropReason::Decimated, 1);
+    }
+
+    /// The source failed, frames missed until the next capture are accounted to it.
+    pub fn source_failed(&mut self) {
+        self.source_failed = true;
+    }
+
+    /// Detection was paused, frames missed until the next capture weren't meant to be captured.
+    pub fn paused(&mut self) {
+        self.last_capture = None;
+    }
+
+    /// Accounts for frames that were captured but not processed, or missed in a way `captured`
+    /// can't tell from timing.
+    pub fn drop_frames(&mut self, reason: DropReason, count: u64) {
+        self.dropped[reason as usize] += count;
+    }
+
+    /// Sequence number of the latest captured frame, 0 before the first one.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        self.dropped[reason as usize]
+    }
+
+    /// Drops for every reason, but frames decimated on purpose.
+    pub fn total_dropped(&self) -> u64 {
+        self.dropped.iter().sum::<u64>() - self.dropped(DropReason::Decimated)
+    }
+
+    /// The counts reported at /status: the latest sequence number, and drops per reason.
+    pub fn to_object(&self) -> json::Object {
+        let dropped = DropReason::ALL.iter().fold(json::Object::new(), |object, reason| object.field(reason.name(), self.dropped(*reason)));
+        json::Object::new()
+            .field("sequence", self.last_sequence)
+            .field("dropped", dropped)
+    }
+}
+
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+
+    // Captures at the given frame slots, every slot INTERVAL after the one before.
+    fn capture(counter: &mut FrameCounter, origin: Instant, slots: impl IntoIterator<Item = u32>) -> Vec<u64> {
+        slots.into_iter().map(|slot| counter.captured(origin + INTERVAL * slot)).collect()
+    }
+
+
+    #[test]
+    fn missed_slots_are_gaps_in_the_sequence() {
+        let origin = Instant::now();
+        let mut counter = FrameCounter::new(INTERVAL);
+        // Slots 3, 4 and 9 to 11 never came.
+        let sequence = capture(&mut counter, origin, [0, 1, 2, 5, 6, 7, 8, 12]);
+        assert_eq!(sequence, [1, 2, 3, 6, 7, 8, 9, 13]);
+        assert_eq!(counter.dropped(DropReason::Overload), 5);
+        assert_eq!(counter.total_dropped(), 5);
+    }
+
+
+    #[test]
+    fn jitter_isnt_a_drop() {
+        let origin = Instant::now();
+        let mut counter = FrameCounter::new(INTERVAL);
+        let times = [0, 140, 190, 330, 400];
+        let sequence: Vec<_> = times.iter().map(|millis| counter.captured(origin + Duration::from_millis(*millis))).collect();
+        assert_eq!(sequence, [1, 2, 3, 4, 5]);
+        assert_eq!(counter.total_dropped(), 0);
+    }
+
+
+    #[test]
+    fn drops_are_told_apart_by_their_reason() {
+        let origin = Instant::now();
+        let mut counter = FrameCounter::new(INTERVAL);
+        capture(&mut counter, origin, [0, 1]);
+        counter.source_failed();
+        capture(&mut counter, origin, [4, 6]);
+        counter.decimated(origin + INTERVAL * 7);
+        counter.drop_frames(DropReason::ProcessingPanic, 1);
+        // Paused frames weren't meant to be captured.
+        counter.paused();
+        assert_eq!(capture(&mut counter, origin, [20]), [9]);
+        let dropped = DropReason::ALL.map(|reason| counter.dropped(reason));
+        assert_eq!(dropped, [1, 2, 1, 1]);
+        assert_eq!(counter.total_dropped(), 4);
+    }
+}
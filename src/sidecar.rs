@@ -0,0 +1,166 @@
+use std::{
+    fs::{ self, File },
+    io::{ self, Write },
+    path::{ Path, PathBuf },
+    time::{ Duration, SystemTime },
+};
+
+use crate::{
+    identity::EventIdentity,
+    json,
+    media::MediaFile,
+    scene::BoundingBox,
+};
+
+
+/// The movement a snapshot is of, as its events tell it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SidecarMovement {
+    pub identity: EventIdentity,        // Of its start event.
+    pub start: SystemTime,              // The start event's time...
+    pub stop: Option<SystemTime>,       // ...and the stop's, once it stopped.
+    pub duration: Option<Duration>,
+    pub peak: f32,                      // Percentage of changed pixels, of the start's frame until it stopped.
+    pub bbox: Option<BoundingBox>,      // Of the change that started it.
+}
+
+
+/// What a `<basename>.json` next to a saved image says of it, for media managers that ingest the
+/// folders: the camera, the configuration hash as in the exit report, the capture time, and for
+/// a snapshot the movement with the fields of its start and stop events. A snapshot's sidecar is
+/// written with the image, once it's complete, and again once its movement stopped. Each is
+/// written under a temporary name and renamed, so no sidecar is seen without its whole image, nor
+/// half written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sidecar {
+    pub camera: String,
+    pub config_hash: u64,
+    pub time: SystemTime,                   // Capture time of the frame saved.
+    pub movement: Option<SidecarMovement>,  // None for timelapse frames.
+}
+
+
+impl Sidecar {
+
+    /// The sidecar of the snapshots of a movement that just started, at `start`, with `score`
+    /// percent of changed pixels in `bbox`.
+    pub fn started(camera: &str, config_hash: u64, time: SystemTime, identity: EventIdentity, start: SystemTime, score: f32, bbox: Option<BoundingBox>) -> Self {
+        let movement = SidecarMovement { identity, start, stop: None, duration: None, peak: score, bbox };
+        Self { camera: camera.to_string(), config_hash, time, movement: Some(movement) }
+    }
+
+    /// The sidecar of a timelapse frame.
+    pub fn timelapse(camera: &str, config_hash: u64, time: SystemTime) -> Self {
+        Self { camera: camera.to_string(), config_hash, time, movement: None }
+    }
+
+    /// Its movement stopped at `stop`, as the stop event tells.
+    pub fn stopped(&mut self, stop: SystemTime, duration: Duration, peak: f32) {
+        if let Some(movement) = &mut self.movement {
+            movement.stop = Some(stop);
+            movement.duration = Some(duration);
+            movement.peak = peak;
+        }
+    }
+
+    /// Where the sidecar of an image goes: its path with a .json extension.
+    pub fn path(image: &Path) -> PathBuf {
+        image.with_extension("json")
+    }
+
+    pub fn to_json(&self, file: &MediaFile) -> String {
+        let movement = self.movement.as_ref();
+        let bbox = movement.and_then(|movement| movement.bbox).map(|bbox| json::Object::new()
+            .field("x", bbox.x)
+            .field("y", bbox.y)
+            .field("width", bbox.width)
+            .field("height", bbox.height));
+        json::Object::new()
+            .field("type", if movement.is_some() { "snapshot" } else { "timelapse" })
+            .field("file", file.path.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .field("zone", file.zone.as_deref())
+            .field("camera", self.camera.as_str())
+            .field("time", json::unix_time(self.time))
+            .field("id", movement.map(|movement| movement.identity.id))
+            .field("installation", movement.map(|movement| movement.identity.installation.as_str()))
+            .field("sequence", movement.map(|movement| movement.identity.sequence))
+            .field("start", movement.map(|movement| json::unix_time(movement.start)))
+            .field("stop", movement.and_then(|movement| movement.stop).map(json::unix_time))
+            .field("duration", movement.and_then(|movement| movement.duration).map(|duration| duration.as_secs_f64()))
+            .field("peak", movement.map(|movement| movement.peak))
+            .field("bbox", bbox)
+            .field("config_hash", format!("{:016x}", self.config_hash))
+            .finish()
+    }
+
+    /// Writes the sidecar of `file`, flushed to disk under a temporary name, then renamed.
+    pub fn write(&self, file: &MediaFile) -> io::Result<()> {
+        write_atomically(&Self::path(&file.path), self.to_json(file).as_bytes())
+    }
+}
+
+
+/// Writes `data` to `path` through a temporary file next to it, flushed to disk before it's
+/// renamed, so whoever sees the file sees all of it.
+pub fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let mut file = File::create(&partial)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&partial, path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ env, process, time::UNIX_EPOCH };
+
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+
+    #[test]
+    fn a_snapshot_sidecar_tells_its_movement_once_it_stopped() {
+        let identity = EventIdentity { installation: "cabin".to_string(), sequence: 7, id: 3 };
+        let bbox = BoundingBox { x: 10.0, y: 20.0, width: 30.0, height: 40.0 };
+        let mut sidecar = Sidecar::started("porch", 0xabc, at(100), identity, at(100), 5.0, Some(bbox));
+        let file = MediaFile { path: PathBuf::from("/media/3-porch.ppm"), zone: Some("porch".to_string()) };
+        assert_eq!(
+            sidecar.to_json(&file),
+            r#"{"type":"snapshot","file":"3-porch.ppm","zone":"porch","camera":"porch","time":100.000,"id":3,"installation":"cabin","sequence":7,"start":100.000,"stop":null,"duration":null,"peak":5.000,"bbox":{"x":10.000,"y":20.000,"width":30.000,"height":40.000},"config_hash":"0000000000000abc"}"#,
+        );
+        sidecar.stopped(at(103), Duration::from_secs(3), 12.5);
+        let movement = sidecar.movement.as_ref().unwrap();
+        assert_eq!((movement.stop, movement.duration, movement.peak), (Some(at(103)), Some(Duration::from_secs(3)), 12.5));
+        assert_eq!(Sidecar::path(&file.path), PathBuf::from("/media/3-porch.json"));
+    }
+
+
+    #[test]
+    fn a_timelapse_sidecar_has_no_movement() {
+        let mut sidecar = Sidecar::timelapse("porch", 1, at(60));
+        sidecar.stopped(at(61), Duration::from_secs(1), 50.0);
+        let file = MediaFile { path: PathBuf::from("timelapse-60.ppm"), zone: None };
+        assert_eq!(
+            sidecar.to_json(&file),
+            r#"{"type":"timelapse","file":"timelapse-60.ppm","zone":null,"camera":"porch","time":60.000,"id":null,"installation":null,"sequence":null,"start":null,"stop":null,"duration":null,"peak":null,"bbox":null,"config_hash":"0000000000000001"}"#,
+        );
+    }
+
+
+    #[test]
+    fn an_atomic_write_leaves_the_whole_file_and_no_temporary_one() {
+        let path = env::temp_dir().join(format!("motion-detect-sidecar-{}.json", process::id()));
+        write_atomically(&path, b"{\"id\":1}").unwrap();
+        write_atomically(&path, b"{\"id\":2}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{\"id\":2}");
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        assert!(!Path::new(&partial).exists());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{ AtomicBool, AtomicU8, Ordering };
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static REOPEN: AtomicBool = AtomicBool::new(false);
+static PAUSE: AtomicU8 = AtomicU8::new(NO_PAUSE_REQUEST);
+
+// What the last SIGUSR1 or SIGUSR2 asked for, until taken.
+const NO_PAUSE_REQUEST: u8 = 0;
+const PAUSE_REQUEST: u8 = 1;
+const RESUME_REQUEST: u8 = 2;
+
+#[cfg(unix)]
+mod ffi {
+    pub const SIGHUP: i32 = 1;
+    pub const SIGINT: i32 = 2;
+    #[cfg(target_os = "linux")]
+    pub const SIGUSR1: i32 = 10;
+    #[cfg(target_os = "linux")]
+    pub const SIGUSR2: i32 = 12;
+    // The BSDs and macOS number them differently.
+    #[cfg(not(target_os = "linux"))]
+    pub const SIGUSR1: i32 = 30;
+    #[cfg(not(target_os = "linux"))]
+    pub const SIGUSR2: i32 = 31;
+    pub const SIGTERM: i32 = 15;
+    pub const SIG_DFL: usize = 0;
+
+    extern "C" {
+        pub fn signal(signum: i32, handler: usize) -> usize;
+    }
+}
+
+
+#[cfg(unix)]
+extern "C" fn on_signal(signum: i32) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+    // A second Ctrl+C kills the process right away, in case shutting down gets stuck.
+    unsafe { ffi::signal(signum, ffi::SIG_DFL); }
+}
+
+
+#[cfg(unix)]
+extern "C" fn on_hangup(_signum: i32) {
+    REOPEN.store(true, Ordering::SeqCst);
+}
+
+
+#[cfg(unix)]
+extern "C" fn on_pause(signum: i32) {
+    PAUSE.store(if signum == ffi::SIGUSR1 { PAUSE_REQUEST } else { RESUME_REQUEST }, Ordering::SeqCst);
+}
+
+
+/// Turns SIGINT and SIGTERM into a shutdown request that the main loop polls, so it can exit cleanly.
+/// Does nothing on platforms without unix signals.
+pub fn install_shutdown_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(i32) as usize;
+        ffi::signal(ffi::SIGINT, handler);
+        ffi::signal(ffi::SIGTERM, handler);
+    }
+}
+
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+
+/// Turns SIGHUP into a request to reopen output files, as sent by logrotate. Without it SIGHUP
+/// would end the process.
+pub fn install_reopen_handler() {
+    #[cfg(unix)]
+    unsafe {
+        ffi::signal(ffi::SIGHUP, on_hangup as extern "C" fn(i32) as usize);
+    }
+}
+
+
+/// True once after each SIGHUP.
+pub fn take_reopen_request() -> bool {
+    REOPEN.swap(false, Ordering::SeqCst)
+}
+
+
+/// Turns SIGUSR1 into a request to pause detection and SIGUSR2 into one to resume it, e.g. from a
+/// schedule in cron. Without it either signal would end the process.
+pub fn install_pause_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_pause as extern "C" fn(i32) as usize;
+        ffi::signal(ffi::SIGUSR1, handler);
+        ffi::signal(ffi::SIGUSR2, handler);
+    }
+}
+
+
+/// Once after SIGUSR1 or SIGUSR2, true to pause and false to resume. Of several signals since the
+/// last call, the last one counts.
+pub fn take_pause_request() -> Option<bool> {
+    match PAUSE.swap(NO_PAUSE_REQUEST, Ordering::SeqCst) {
+        PAUSE_REQUEST => Some(true),
+        RESUME_REQUEST => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    fs, io,
+    path::{ Path, PathBuf },
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::{
+    json,
+    media::{ MediaFile, MediaJob },
+    overlay::Overlay,
+    privacy::{ self, Privacy },
+    sidecar,
+    thumbnail::PixelLayout,
+    zones::{ Zone, ZoneMasks },
+};
+
+/// Zones with at least this fraction of their pixels changed get their own snapshot.
+const ZONE_ACTIVITY: f32 = 0.02;
+
+
+/// Saves full resolution frames when a movement starts: the whole frame, and one per zone that
+/// asks for it, cropped to the zone. Also saves timelapse frames. Images are written as binary PPM (or PGM for single channel
+/// sources), which any image viewer opens and needs no encoder. Snapshots and timelapse frames
+/// are written by a `MediaJob`, on the thread of a `media::MediaQueue`.
+pub struct Snapshots {
+    directory: Option<PathBuf>,     // Where full frames go, and the default for zones.
+    template: String,
+    full_frame: bool,
+    writer: Writer,
+    frame: Arc<Vec<u8>>,            // Copy of the latest captured frame, shared with the jobs queued for it.
+    privacy: Option<(Privacy, f32)>,    // What becomes of the frame outside the movement, and the margin kept around it.
+}
+
+
+/// What images are written with, on the detection thread or a job's.
+#[derive(Clone)]
+struct Writer {
+    width: usize,
+    height: usize,
+    channels: usize,
+    overlay: Option<(Overlay, String)>, // With the camera name.
+}
+
+
+impl Snapshots {
+
+    /// * `template` - File name without extension, "{time}", "{id}" and "{zone}" are replaced.
+    /// * `full_frame` - Also saves the whole frame, "{zone}" is then "frame".
+    pub fn new(directory: Option<PathBuf>, template: &str, full_frame: bool, width: usize, height: usize, channels: usize) -> Self {
+        let writer = Writer { width, height, channels, overlay: None };
+        Self { directory, template: template.to_string(), full_frame, writer, frame: Arc::default(), privacy: None }
+    }
+
+    /// Burns an overlay into every saved image, `name` is the camera's.
+    pub fn with_overlay(mut self, overlay: Option<Overlay>, name: &str) -> Self {
+        self.writer.overlay = overlay.map(|overlay| (overlay, name.to_string()));
+        self
+    }
+
+    /// Obscures movement snapshots outside the boxes around what moved, grown by `margin` times the
+    /// frame size, see `privacy::motion_boxes`. Timelapse frames are saved as they are.
+    pub fn with_privacy(mut self, privacy: Option<Privacy>, margin: f32) -> Self {
+        self.privacy = privacy.map(|privacy| (privacy, margin));
+        self
+    }
+
+    /// Keeps a copy of the latest full frame, in RGB or luma, called for every captured frame.
+    pub fn capture(&mut self, frame: &[u8], layout: PixelLayout) {
+        let pixels = self.writer.width * self.writer.height;
+        let kept = self.unshared_frame();
+        kept.clear();
+        layout.extend_rgb(&frame[.. frame.len().min(pixels * layout.bytes_per_pixel())], kept);
+    }
+
+    /// Frames come with another number of channels from now on, e.g. after a reconnect in
+    /// another pixel format. The kept one is dropped.
+    pub fn set_channels(&mut self, channels: usize) {
+        self.writer.channels = channels;
+        self.unshared_frame().clear();
+    }
+
+    /// Bytes of the kept frame.
+    pub fn buffer_bytes(&self) -> usize {
+        self.frame.capacity()
+    }
+
+    /// The job saving the latest frame for movement `id`, along with the zones active in the diff
+    /// `mask`, `mask_width` pixels wide, which `zone_masks` were fitted to. It has no files if
+    /// neither the whole frame nor any zone is to be saved.
+    pub fn save(&self, id: u64, time: SystemTime, zones: &[Zone], zone_masks: &ZoneMasks, mask: &[u8], mask_width: usize) -> Result<MediaJob, String> {
+        let (width, height) = (self.writer.width, self.writer.height);
+        if self.frame.len() < width * height * self.writer.channels {
+            return Err(String::from("no frame captured yet"));
+        }
+        // The file, the part of the frame and the zone of each image.
+        let mut images = Vec::new();
+        if let (true, Some(directory)) = (self.full_frame, &self.directory) {
+            images.push((directory.join(self.file_name(&self.template, id, time, "frame")), (0, 0, width, height), None));
+        }
+        for (index, zone) in zones.iter().enumerate() {
+            let Some(options) = &zone.snapshot else {
+                continue;
+            };
+            if zone_masks.activity(index, mask) < ZONE_ACTIVITY {
+                continue;
+            }
+            let Some(directory) = options.directory.as_ref().or(self.directory.as_ref()) else {
+                continue; // Rejected with the settings.
+            };
+            let rect = if options.crop {
+                zone.pixel_rect(width, height, options.padding)
+            } else {
+                (0, 0, width, height)
+            };
+            let template = options.template.as_deref().unwrap_or(&self.template);
+            images.push((directory.join(self.file_name(template, id, time, &zone.name)), rect, Some(zone.name.clone())));
+        }
+        let keep = self.privacy.map(|(privacy, margin)| (privacy, privacy::motion_boxes(mask, mask_width, width, height, margin)));
+        let writer = self.writer.clone();
+        let files = images.iter().map(|(path, _, zone)| MediaFile { path: path.clone(), zone: zone.clone() }).collect();
+        Ok(MediaJob::new(Some(id), files, self.frame.clone(), move |frame| {
+            // Obscured once, before any of the images is cropped from it and gets its overlay.
+            let obscured;
+            let frame = match &keep {
+                Some((privacy, keep)) => {
+                    let mut frame = frame.to_vec();
+                    privacy.apply(&mut frame, width, height, writer.channels, keep);
+                    obscured = frame;
+                    &obscured
+                }
+                None => frame,
+            };
+            images.iter()
+                .filter_map(|(path, rect, zone)| writer.write(frame, path, *rect, time, zone.as_deref()).err().map(|err| format!("snapshot {}: {err}", path.display())))
+                .collect()
+        }))
+    }
+
+    /// Saves the whole latest frame at `path`, with the image extension added, right away. Returns
+    /// the saved file.
+    pub fn save_frame(&self, path: PathBuf, time: SystemTime) -> Result<PathBuf, String> {
+        if self.frame.len() < self.writer.width * self.writer.height * self.writer.channels {
+            return Err(String::from("no frame captured yet"));
+        }
+        let path = path.with_extension(self.writer.extension());
+        self.writer.write(&self.frame, &path, (0, 0, self.writer.width, self.writer.height), time, None)
+            .map(|()| path.clone())
+            .map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// The job saving the whole latest frame as a timelapse frame at `path`, with the image
+    /// extension added.
+    pub fn timelapse_job(&self, path: PathBuf, time: SystemTime) -> Result<MediaJob, String> {
+        let (width, height) = (self.writer.width, self.writer.height);
+        if self.frame.len() < width * height * self.writer.channels {
+            return Err(String::from("no frame captured yet"));
+        }
+        let path = path.with_extension(self.writer.extension());
+        let writer = self.writer.clone();
+        Ok(MediaJob::new(None, vec![MediaFile { path: path.clone(), zone: None }], self.frame.clone(), move |frame| {
+            match writer.write(frame, &path, (0, 0, width, height), time, None) {
+                Ok(()) => Vec::new(),
+                Err(err) => vec![format!("timelapse frame {}: {err}", path.display())],
+            }
+        }))
+    }
+
+    // The kept frame, a new one if queued jobs still share it.
+    fn unshared_frame(&mut self) -> &mut Vec<u8> {
+        if Arc::get_mut(&mut self.frame).is_none() {
+            self.frame = Arc::default();
+        }
+        Arc::get_mut(&mut self.frame).expect("The kept frame was just unshared")
+    }
+
+    fn file_name(&self, template: &str, id: u64, time: SystemTime, zone: &str) -> String {
+        let name = template
+            .replace("{time}", &format!("{:.0}", json::unix_time(time).floor()))
+            .replace("{id}", &id.to_string())
+            .replace("{zone}", zone);
+        format!("{name}.{}", self.writer.extension())
+    }
+}
+
+
+impl Writer {
+
+    fn extension(&self) -> &'static str {
+        if self.channels == 1 { "pgm" } else { "ppm" }
+    }
+
+    // Writes the part of `frame` within left, top, right and bottom (excluded), with the overlay,
+    // atomically so nothing reads half of it.
+    fn write(&self, frame: &[u8], path: &Path, (left, top, right, bottom): (usize, usize, usize, usize), time: SystemTime, zone: Option<&str>) -> io::Result<()> {
+        if right <= left || bottom <= top {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty crop"));
+        }
+        let (width, height) = (right - left, bottom - top);
+        let magic = if self.channels == 1 { "P5" } else { "P6" };
+        let mut data = format!("{magic}\n{width} {height}\n255\n").into_bytes();
+        let header_length = data.len();
+        let row_length = self.width * self.channels;
+        for y in top .. bottom {
+            data.extend_from_slice(&frame[y * row_length + left * self.channels .. y * row_length + right * self.channels]);
+        }
+        if let Some((overlay, name)) = &self.overlay {
+            let text = overlay.text(time, name, zone);
+            overlay.draw(&mut data[header_length ..], width, height, self.channels, &text);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        sidecar::write_atomically(path, &data)
+    }
+}
@@ -0,0 +1,502 @@
+use std::{ io::{ self, Read }, time::Duration };
+use eye::hal::{ device::Description, format::PixelFormat, stream::Descriptor };
+
+use crate::{ camera::{ Camera, OpenError }, json };
+
+/// Where frames come from. Frames are in the layout of the descriptor the source was opened with.
+pub trait FrameSource {
+    /// The next frame, or None once the input has ended. Fails with a reason if the source broke.
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String>;
+
+    /// Takes the next frame and throws it away, as cheaply as the source allows. False once the
+    /// input has ended.
+    fn skip_frame(&mut self) -> Result<bool, String> {
+        self.next_frame().map(|frame| frame.is_some())
+    }
+
+    /// True if `reconnect` may bring a failed source back.
+    fn can_reconnect(&self) -> bool {
+        false
+    }
+
+    /// Tries to restart a source after `next_frame` failed.
+    fn reconnect(&mut self) -> Result<(), String> {
+        Err("this source can't reconnect".to_string())
+    }
+
+    /// True if `release` can stop the stream, for pausing with --pause-mode stream-off.
+    fn can_release(&self) -> bool {
+        false
+    }
+
+    /// Stops the stream and lets go of the device until `reconnect` starts it again.
+    fn release(&mut self) -> Result<(), String> {
+        Err("this source can't release its stream".to_string())
+    }
+
+    /// Bytes of the frame buffers the source holds, see `memory::MemoryUsage`.
+    fn buffer_bytes(&self) -> usize {
+        0
+    }
+
+    /// The stream as last negotiated, for sources that negotiate one and may get another mode
+    /// when reconnecting. None when frames always come as the source was opened with.
+    fn stream(&self) -> Option<&Descriptor> {
+        None
+    }
+
+    /// Stops reading and opens the device at `uri` instead, for sources that have several, and
+    /// returns its description. The stream may come back in another mode, see `stream`.
+    fn switch(&mut self, _uri: &str) -> Result<Description, String> {
+        Err("this input has no other streams".to_string())
+    }
+
+    /// The modes the device advertises, listed when the stream doesn't meet --min-resolution.
+    /// Empty for sources that take whatever size they're given.
+    fn modes(&self) -> Vec<Descriptor> {
+        Vec::new()
+    }
+
+    /// Exposure and gain the latest frame was captured with, as far as the source can tell.
+    /// Nothing for sources without a device to ask.
+    fn metadata(&self) -> FrameMetadata {
+        FrameMetadata::default()
+    }
+}
+
+
+/// Capture settings of a frame, as reported by the device, in its own units: V4L2's absolute
+/// exposure counts 100µs, and gain is whatever the sensor's scale is. A device that manages them
+/// itself reports what its auto exposure picked, read when the frame was dequeued.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameMetadata {
+    pub exposure: Option<f64>,
+    pub gain: Option<f64>,
+}
+
+
+impl FrameMetadata {
+
+    pub fn is_empty(&self) -> bool {
+        self.exposure.is_none() && self.gain.is_none()
+    }
+
+    /// The values there are, e.g. `{"exposure": 333, "gain": 12}`.
+    pub fn to_object(&self) -> json::Object {
+        let object = json::Object::new();
+        let object = match self.exposure {
+            Some(exposure) => object.field("exposure", exposure),
+            None => object,
+        };
+        match self.gain {
+            Some(gain) => object.field("gain", gain),
+            None => object,
+        }
+    }
+
+    /// "exposure 333, gain 12", empty without values.
+    pub fn text(&self) -> String {
+        let values: Vec<String> = [("exposure", self.exposure), ("gain", self.gain)].into_iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{name} {value}")))
+            .collect();
+        values.join(", ")
+    }
+}
+
+
+/// What consumers need to tell instances and their sources apart: reported in device_selected and
+/// source_changed, at /status and, with --events-include-source, in every start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceInfo {
+    pub uri: String,
+    pub product: String,
+    pub name: String,           // --name, or the product.
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: String,
+    pub interval: Duration,     // Negotiated by the stream.
+    pub capture_interval: Duration, // Requested, frames come at the longer of both.
+}
+
+
+impl SourceInfo {
+
+    pub fn new(description: &Description, stream: &Descriptor, name: Option<&str>, capture_interval: Duration) -> Self {
+        Self {
+            uri: description.uri.clone(),
+            product: description.product.clone(),
+            name: name.unwrap_or(&description.product).to_string(),
+            width: stream.width,
+            height: stream.height,
+            pixel_format: stream.pixfmt.to_string(),
+            interval: stream.interval,
+            capture_interval,
+        }
+    }
+
+    /// Frames per second actually captured.
+    pub fn fps(&self) -> f64 {
+        1.0 / self.interval.max(self.capture_interval).as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Whether frames still come in the size and layout the pipeline was set up for.
+    pub fn same_mode(&self, other: &SourceInfo) -> bool {
+        (self.width, self.height, &self.pixel_format) == (other.width, other.height, &other.pixel_format)
+    }
+
+    pub fn to_object(&self) -> json::Object {
+        json::Object::new()
+            .field("uri", self.uri.as_str())
+            .field("product", self.product.as_str())
+            .field("name", self.name.as_str())
+            .field("width", self.width as u64)
+            .field("height", self.height as u64)
+            .field("pixel_format", self.pixel_format.as_str())
+            .field("fps", self.fps())
+    }
+
+    /// "640x480 YUYV at 5 fps from /dev/video0 (name)".
+    pub fn text(&self) -> String {
+        format!(
+            "{}x{} {} at {:.1} fps from {} ({})",
+            self.width, self.height, self.pixel_format, self.fps(), self.uri, self.name
+        )
+    }
+}
+
+
+/// The size the stream must have, from --min-resolution and --require-exact-resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolutionRequirement {
+    pub min: Option<(u32, u32)>,
+    pub exact: Option<(u32, u32)>,  // The requested --width and --height.
+}
+
+
+impl ResolutionRequirement {
+
+    /// Checks the size the stream was negotiated at, then the size of its first frame, which
+    /// tells whether the driver went for another mode than the one it reported. That size is
+    /// told from the frame's length and the modes of the same pixel format the device
+    /// advertises, `pixels` being the pixels the frame holds.
+    pub fn check(&self, stream: &Descriptor, pixels: usize, modes: &[Descriptor]) -> Result<(), String> {
+        self.check_size(stream.width, stream.height)?;
+        if pixels == stream.width as usize * stream.height as usize {
+            return Ok(());
+        }
+        let delivered = modes.iter()
+            .find(|mode| mode.pixfmt == stream.pixfmt && mode.width as usize * mode.height as usize == pixels);
+        match delivered {
+            Some(mode) => Err(format!(
+                "the stream was negotiated at {}x{} but delivers {}x{} frames", stream.width, stream.height, mode.width, mode.height
+            )),
+            None => Err(format!(
+                "the stream was negotiated at {}x{} but delivers frames of {pixels} pixels", stream.width, stream.height
+            )),
+        }
+    }
+
+    fn check_size(&self, width: u32, height: u32) -> Result<(), String> {
+        if let Some((exact_width, exact_height)) = self.exact {
+            if (width, height) != (exact_width, exact_height) {
+                return Err(format!("the stream is {width}x{height}, not the {exact_width}x{exact_height} --require-exact-resolution asks for"));
+            }
+        }
+        if let Some((min_width, min_height)) = self.min {
+            if width < min_width || height < min_height {
+                return Err(format!("the stream is {width}x{height}, below --min-resolution {min_width}x{min_height}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl FrameSource for Camera<'_, '_> {
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        Camera::next_frame(self).map(Some)
+    }
+
+    fn skip_frame(&mut self) -> Result<bool, String> {
+        Camera::skip_frame(self).map(|()| true)
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        Camera::buffer_bytes(self)
+    }
+
+    fn stream(&self) -> Option<&Descriptor> {
+        Some(&self.descriptor)
+    }
+
+    fn modes(&self) -> Vec<Descriptor> {
+        self.modes.clone()
+    }
+
+    fn metadata(&self) -> FrameMetadata {
+        Camera::metadata(self)
+    }
+
+    fn switch(&mut self, uri: &str) -> Result<Description, String> {
+        Camera::switch(self, uri).map_err(|err| match err {
+            OpenError::NoDevice => format!("no device {uri}"),
+            err => err.to_string(),
+        })?;
+        Ok(self.description.clone())
+    }
+
+    fn can_release(&self) -> bool {
+        true
+    }
+
+    fn release(&mut self) -> Result<(), String> {
+        Camera::release(self);
+        Ok(())
+    }
+
+    // Only after a release: a camera that failed stays lost, see `Camera::reopen`.
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.reopen().map_err(|err| err.to_string())
+    }
+}
+
+
+/// Pixel layouts accepted from raw video input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    Rgb24,
+    Bgr24,
+    Rgba,
+    Bgra,
+    Gray,
+    Yuv420p,
+}
+
+
+impl RawFormat {
+
+    pub const ALL: [RawFormat; 6] = [RawFormat::Rgb24, RawFormat::Bgr24, RawFormat::Rgba, RawFormat::Bgra, RawFormat::Gray, RawFormat::Yuv420p];
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "rgb24" => Ok(RawFormat::Rgb24),
+            "bgr24" => Ok(RawFormat::Bgr24),
+            "rgba" => Ok(RawFormat::Rgba),
+            "bgra" => Ok(RawFormat::Bgra),
+            "gray" => Ok(RawFormat::Gray),
+            "yuv420p" => Ok(RawFormat::Yuv420p),
+            _ => Err(format!("Invalid input format '{text}', use rgb24, bgr24, rgba, bgra, gray or yuv420p")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RawFormat::Rgb24 => "rgb24",
+            RawFormat::Bgr24 => "bgr24",
+            RawFormat::Rgba => "rgba",
+            RawFormat::Bgra => "bgra",
+            RawFormat::Gray => "gray",
+            RawFormat::Yuv420p => "yuv420p",
+        }
+    }
+
+    /// Bytes of one frame on the input.
+    pub fn frame_len(&self, width: usize, height: usize) -> usize {
+        match self {
+            RawFormat::Rgb24 | RawFormat::Bgr24 => width * height * 3,
+            RawFormat::Rgba | RawFormat::Bgra => width * height * 4,
+            RawFormat::Gray => width * height,
+            // Full size luma plane, then two quarter size chroma planes.
+            RawFormat::Yuv420p => width * height + 2 * width.div_ceil(2) * height.div_ceil(2),
+        }
+    }
+
+    /// The format frames are handed to the detector in. Planar YUV is reduced to its luma plane,
+    /// which is all the diff needs.
+    pub fn pixel_format(&self) -> PixelFormat {
+        match self {
+            RawFormat::Rgb24 => PixelFormat::Rgb(24),
+            RawFormat::Bgr24 => PixelFormat::Bgr(24),
+            RawFormat::Rgba => PixelFormat::Rgb(32),
+            RawFormat::Bgra => PixelFormat::Bgr(32),
+            RawFormat::Gray | RawFormat::Yuv420p => PixelFormat::Gray(8),
+        }
+    }
+}
+
+
+/// Reads fixed size raw video frames from a pipe, e.g. the output of ffmpeg or libcamera-vid on stdin.
+pub struct RawVideoSource<R: Read> {
+    reader: R,
+    format: RawFormat,
+    width: usize,
+    height: usize,
+    frame: Vec<u8>,
+    frames_read: u64,
+}
+
+
+impl<R: Read> RawVideoSource<R> {
+
+    pub fn new(reader: R, format: RawFormat, width: usize, height: usize) -> Self {
+        let frame = vec![0; format.frame_len(width, height)];
+        Self { reader, format, width, height, frame, frames_read: 0 }
+    }
+
+    pub fn frames_read(&self) -> u64 {
+        self.frames_read
+    }
+
+    // Fills the frame buffer, tolerating short reads. Returns how many bytes were read before EOF.
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < self.frame.len() {
+            match self.reader.read(&mut self.frame[filled ..]) {
+                Ok(0) => break,
+                Ok(count) => filled += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+
+impl<R: Read> FrameSource for RawVideoSource<R> {
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        let filled = self.fill().map_err(|err| format!("failed to read input: {err}"))?;
+        if filled < self.frame.len() {
+            // A partial first frame means the size or format doesn't match what's being sent.
+            if self.frames_read == 0 && filled > 0 {
+                return Err(format!(
+                    "input ended after {filled} bytes, less than one {}x{} {} frame of {} bytes. Check --input-size and --input-format",
+                    self.width, self.height, self.format.name(), self.frame.len()
+                ));
+            }
+            return Ok(None);
+        }
+        self.frames_read += 1;
+        let frame_len = match self.format {
+            RawFormat::Yuv420p => self.width * self.height,
+            _ => self.frame.len(),
+        };
+        Ok(Some(&self.frame[.. frame_len]))
+    }
+
+    fn buffer_bytes(&self) -> usize {
+        self.frame.capacity()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands over at most 5 bytes a read, like a pipe does under load.
+    struct Trickle<'d>(&'d [u8]);
+
+
+    impl Read for Trickle<'_> {
+
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let count = buf.len().min(self.0.len()).min(5);
+            buf[.. count].copy_from_slice(&self.0[.. count]);
+            self.0 = &self.0[count ..];
+            Ok(count)
+        }
+    }
+
+
+    #[test]
+    fn frames_are_read_whole_from_short_reads() {
+        let input: Vec<u8> = (0 .. 24).collect();
+        let mut source = RawVideoSource::new(Trickle(&input), RawFormat::Rgb24, 2, 2);
+        assert_eq!(source.next_frame().unwrap(), Some(&input[.. 12]));
+        assert_eq!(source.next_frame().unwrap(), Some(&input[12 ..]));
+        assert_eq!(source.next_frame().unwrap(), None);
+        assert_eq!(source.frames_read(), 2);
+    }
+
+
+    #[test]
+    fn a_partial_first_frame_is_a_size_mismatch() {
+        let mut source = RawVideoSource::new(&[0; 10][..], RawFormat::Rgb24, 2, 2);
+        assert!(source.next_frame().unwrap_err().contains("--input-size"));
+        // Later on it's just the input ending halfway through a frame.
+        let mut source = RawVideoSource::new(&[0; 18][..], RawFormat::Rgb24, 2, 2);
+        assert!(source.next_frame().unwrap().is_some());
+        assert_eq!(source.next_frame().unwrap(), None);
+    }
+
+
+    #[test]
+    fn planar_yuv_is_handed_over_as_its_luma_plane() {
+        // 3 by 3: 9 luma bytes, then two 2 by 2 chroma planes.
+        assert_eq!(RawFormat::Yuv420p.frame_len(3, 3), 9 + 8);
+        let input: Vec<u8> = (0 .. 17).collect();
+        let mut source = RawVideoSource::new(&input[..], RawFormat::Yuv420p, 3, 3);
+        assert_eq!(source.next_frame().unwrap(), Some(&input[.. 9]));
+        assert_eq!(RawFormat::Yuv420p.pixel_format(), PixelFormat::Gray(8));
+    }
+
+
+    #[test]
+    fn formats_are_parsed_by_name() {
+        for format in RawFormat::ALL {
+            assert_eq!(RawFormat::parse(format.name()), Ok(format));
+        }
+        assert!(RawFormat::parse("nv12").is_err());
+    }
+
+
+    fn mode(width: u32, height: u32) -> Descriptor {
+        Descriptor { width, height, pixfmt: PixelFormat::Custom("YUYV".to_string()), interval: Duration::from_millis(200) }
+    }
+
+
+    fn requirement(min: Option<(u32, u32)>, exact: Option<(u32, u32)>) -> ResolutionRequirement {
+        ResolutionRequirement { min, exact }
+    }
+
+
+    #[test]
+    fn an_exact_resolution_is_met_only_by_that_size() {
+        let exact = requirement(None, Some((1280, 720)));
+        assert_eq!(exact.check(&mode(1280, 720), 1280 * 720, &[]), Ok(()));
+        assert_eq!(
+            exact.check(&mode(1920, 1080), 1920 * 1080, &[]),
+            Err("the stream is 1920x1080, not the 1280x720 --require-exact-resolution asks for".to_string())
+        );
+    }
+
+
+    #[test]
+    fn a_minimum_resolution_is_met_by_anything_as_large() {
+        let min = requirement(Some((640, 480)), None);
+        assert_eq!(min.check(&mode(640, 480), 640 * 480, &[]), Ok(()));
+        assert_eq!(min.check(&mode(1280, 720), 1280 * 720, &[]), Ok(()));
+        // Both sides count, a wide but short stream is too small.
+        assert_eq!(
+            min.check(&mode(800, 400), 800 * 400, &[]),
+            Err("the stream is 800x400, below --min-resolution 640x480".to_string())
+        );
+        assert_eq!(requirement(None, None).check(&mode(160, 120), 160 * 120, &[]), Ok(()));
+    }
+
+
+    #[test]
+    fn frames_of_another_size_than_negotiated_are_unmet() {
+        let min = requirement(Some((640, 480)), None);
+        let modes = [mode(320, 240), mode(640, 480), mode(1280, 720)];
+        assert_eq!(
+            min.check(&mode(1280, 720), 320 * 240, &modes),
+            Err("the stream was negotiated at 1280x720 but delivers 320x240 frames".to_string())
+        );
+        assert_eq!(
+            min.check(&mode(1280, 720), 1000, &modes),
+            Err("the stream was negotiated at 1280x720 but delivers frames of 1000 pixels".to_string())
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::VecDeque,
+    fs::{ self, OpenOptions },
+    io::Write,
+    path::PathBuf,
+};
+
+use crate::exit_report::fnv1a;
+
+
+/// Records that couldn't be delivered, kept on disk in order until they are. Each record is a
+/// list of text fields.
+///
+/// The file has one record per line: the FNV-1a hash of the rest of the line (16 hex digits), a
+/// space, then the fields separated by tabs, with backslashes, tabs and newlines escaped. Lines
+/// whose hash doesn't match, e.g. the last one after a crash in the middle of writing it, are
+/// skipped when the file is opened. Records are appended as they come, the file is only rewritten
+/// when records leave it.
+pub struct Spool {
+    path: PathBuf,
+    limit: usize,                           // Bytes of the file.
+    records: VecDeque<(Vec<String>, usize)>,// With the length of their line.
+    bytes: usize,
+}
+
+
+impl Spool {
+
+    /// Loads the records left by a previous run, if the file exists. Also returns the number of
+    /// records that were dropped, corrupt or beyond a limit lowered since.
+    pub fn open(path: PathBuf, limit: usize) -> Result<(Self, usize), String> {
+        let mut spool = Self { path, limit, records: VecDeque::new(), bytes: 0 };
+        let text = match fs::read(&spool.path) {
+            Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("can't read the spool {}: {err}", spool.path.display())),
+        };
+        let mut skipped = 0;
+        for line in text.split_inclusive('\n') {
+            match decode(line) {
+                Some(fields) => {
+                    spool.bytes += line.len();
+                    spool.records.push_back((fields, line.len()));
+                }
+                None => skipped += 1,
+            }
+        }
+        let evicted = spool.evict(0);
+        // Appending after a torn line would corrupt the next record as well.
+        if skipped > 0 || evicted > 0 {
+            spool.rewrite()?;
+        }
+        Ok((spool, skipped + evicted))
+    }
+
+    /// Appends a record, evicting the oldest ones if the file would grow beyond its limit.
+    /// Returns the number of records evicted.
+    pub fn push(&mut self, fields: &[String]) -> Result<usize, String> {
+        let line = encode(fields);
+        if line.len() > self.limit {
+            return Err(format!("record of {} bytes doesn't fit in the spool", line.len()));
+        }
+        let evicted = self.evict(line.len());
+        self.bytes += line.len();
+        self.records.push_back((fields.to_vec(), line.len()));
+        if evicted > 0 {
+            self.rewrite()?;
+            return Ok(evicted);
+        }
+        OpenOptions::new().create(true).append(true).open(&self.path)
+            .and_then(|mut file| {
+                file.write_all(line.as_bytes())?;
+                file.sync_data()
+            })
+            .map_err(|err| format!("can't write the spool {}: {err}", self.path.display()))?;
+        Ok(0)
+    }
+
+    /// The oldest record.
+    pub fn front(&self) -> Option<&[String]> {
+        self.records.front().map(|(fields, _)| fields.as_slice())
+    }
+
+    /// Removes the oldest record, once delivered.
+    pub fn pop(&mut self) -> Result<(), String> {
+        if let Some((_, len)) = self.records.pop_front() {
+            self.bytes -= len;
+            self.rewrite()?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    // Makes room for `len` more bytes, oldest first.
+    fn evict(&mut self, len: usize) -> usize {
+        let mut evicted = 0;
+        while self.bytes + len > self.limit {
+            let Some((_, old)) = self.records.pop_front() else { break };
+            self.bytes -= old;
+            evicted += 1;
+        }
+        evicted
+    }
+
+    // Renamed into place, a crash never loses the records that were there.
+    fn rewrite(&self) -> Result<(), String> {
+        let text: String = self.records.iter().map(|(fields, _)| encode(fields)).collect();
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, text)
+            .and_then(|()| fs::File::open(&temp_path)?.sync_data())
+            .and_then(|()| fs::rename(&temp_path, &self.path))
+            .map_err(|err| format!("can't write the spool {}: {err}", self.path.display()))
+    }
+}
+
+
+fn encode(fields: &[String]) -> String {
+    let body = fields.iter()
+        .map(|field| field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n"))
+        .collect::<Vec<_>>()
+        .join("\t");
+    format!("{:016x} {body}\n", fnv1a(body.as_bytes()))
+}
+
+
+fn decode(line: &str) -> Option<Vec<String>> {
+    let (hash, body) = line.strip_suffix('\n')?.split_once(' ')?;
+    if u64::from_str_radix(hash, 16).ok()? != fnv1a(body.as_bytes()) {
+        return None;
+    }
+    body.split('\t')
+        .map(|field| {
+            let mut text = String::with_capacity(field.len());
+            let mut chars = field.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    text.push(c);
+                    continue;
+                }
+                text.push(match chars.next()? {
+                    't' => '\t',
+                    'n' => '\n',
+                    other => other,
+                });
+            }
+            Some(text)
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ env, process };
+
+
+    fn record(text: &str) -> Vec<String> {
+        vec![text.to_string(), "with\ta tab\nand a newline\\".to_string()]
+    }
+
+
+    fn spool_path(test: &str) -> PathBuf {
+        env::temp_dir().join(format!("motion-detect-spool-{test}-{}", process::id()))
+    }
+
+
+    #[test]
+    fn records_come_back_in_order_after_reopening() {
+        let path = spool_path("order");
+        let _ = fs::remove_file(&path);
+        let (mut spool, _) = Spool::open(path.clone(), 1 << 16).unwrap();
+        for text in ["first", "second", "third"] {
+            assert_eq!(spool.push(&record(text)).unwrap(), 0);
+        }
+        spool.pop().unwrap();
+        let (mut spool, dropped) = Spool::open(path.clone(), 1 << 16).unwrap();
+        assert_eq!((spool.len(), dropped), (2, 0));
+        assert_eq!(spool.front(), Some(record("second").as_slice()));
+        spool.pop().unwrap();
+        assert_eq!(spool.front(), Some(record("third").as_slice()));
+        let _ = fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn the_oldest_records_make_room_for_a_new_one() {
+        let path = spool_path("evict");
+        let _ = fs::remove_file(&path);
+        let line = encode(&record("first")).len();
+        let (mut spool, _) = Spool::open(path.clone(), line * 2).unwrap();
+        spool.push(&record("first")).unwrap();
+        spool.push(&record("other")).unwrap();
+        assert_eq!(spool.push(&record("third")).unwrap(), 1);
+        assert_eq!(spool.front(), Some(record("other").as_slice()));
+        assert!(spool.push(&["x".repeat(line * 2)]).is_err());
+        assert_eq!(Spool::open(path.clone(), line * 2).unwrap().0.len(), 2);
+        let _ = fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn damaged_and_torn_lines_are_skipped() {
+        let path = spool_path("damaged");
+        let text = [encode(&record("first")), encode(&record("second")).replace("second", "sec0nd"), encode(&record("third"))].concat();
+        fs::write(&path, &text[.. text.len() - 3]).unwrap();
+        let (spool, dropped) = Spool::open(path.clone(), 1 << 16).unwrap();
+        assert_eq!((spool.len(), dropped), (1, 2));
+        assert_eq!(spool.front(), Some(record("first").as_slice()));
+        // Rewritten without them, so appending goes after a whole line.
+        assert_eq!(fs::read_to_string(&path).unwrap(), encode(&record("first")));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::json::Object;
+
+/// What the detector is doing before it's ready, in the order it does it. Each phase may be
+/// reported several times, e.g. once per device probed, and phases that don't apply are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Starting,       // Before the source is opened.
+    Probing,        // A device is looked at for video streams, or the input opened.
+    Negotiating,    // A stream is started in a pixel format.
+    WarmingUp,      // The camera settles, see --warm-up.
+    Calibrating,    // Thumbnails are learned from before the baseline, e.g. pixels flickering.
+    Baseline,       // The first reference is captured, again if the scene moved meanwhile.
+}
+
+
+impl Phase {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Starting => "starting",
+            Phase::Probing => "probing",
+            Phase::Negotiating => "negotiating",
+            Phase::WarmingUp => "warming_up",
+            Phase::Calibrating => "calibrating",
+            Phase::Baseline => "baseline",
+        }
+    }
+}
+
+
+/// A step of the startup, published as a startup_progress message and served at /status until the
+/// detector is ready.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub phase: Phase,
+    pub detail: String,         // The device, pixel format or attempt, empty if there's nothing to say.
+    pub percent: Option<f32>,   // Of the phase, where it has a known end.
+}
+
+
+impl Progress {
+
+    pub fn new(phase: Phase, detail: impl Into<String>) -> Self {
+        Self { phase, detail: detail.into(), percent: None }
+    }
+
+    pub fn with_percent(mut self, done: f64, total: f64) -> Self {
+        let percent = if total > 0.0 { (done / total * 100.0).clamp(0.0, 100.0) } else { 100.0 };
+        self.percent = Some(percent as f32);
+        self
+    }
+
+    pub fn to_object(&self) -> Object {
+        Object::new()
+            .field("phase", self.phase.name())
+            .field("detail", self.detail.as_str())
+            .field("percent", self.percent)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn a_percentage_stays_within_its_phase() {
+        assert_eq!(Progress::new(Phase::WarmingUp, "").with_percent(5.0, 20.0).percent, Some(25.0));
+        assert_eq!(Progress::new(Phase::WarmingUp, "").with_percent(30.0, 20.0).percent, Some(100.0));
+        // A phase with nothing to do is done.
+        assert_eq!(Progress::new(Phase::Calibrating, "").with_percent(0.0, 0.0).percent, Some(100.0));
+    }
+
+
+    #[test]
+    fn a_step_without_a_known_end_has_no_percentage() {
+        let progress = Progress::new(Phase::Negotiating, "YUYV");
+        assert_eq!(progress.to_object().finish(), r#"{"phase":"negotiating","detail":"YUYV","percent":null}"#);
+    }
+}
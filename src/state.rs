@@ -0,0 +1,264 @@
+use std::{
+    fs, io,
+    path::{ Path, PathBuf },
+    time::{ Duration, UNIX_EPOCH },
+};
+
+use crate::{ activity::ActivityHistogram, noise::NoiseMap, profile::SceneProfile, quiet::HeldMovement, thumbnail::Thumbnail };
+
+// Bump whenever the layout below changes, older files are then ignored.
+const MAGIC: &[u8; 4] = b"MDST";
+const FORMAT_VERSION: u32 = 6;
+
+
+/// What the detector learned during a run, saved on clean shutdown so the next start can skip
+/// most of the warm-up. Only restored if the capture configuration is the same.
+///
+/// Layout (little endian): magic, format version, capture width, capture height, downsample,
+/// thumbnail width, thumbnail height (all u32), algorithm name (u8 length + bytes),
+/// reference thumbnail pixels (u32 length + bytes, 3 or 1 per pixel), then a u8 flag followed,
+/// if set, by the noise map: sample count, value count (u32) and one f32 variance per pixel, then
+/// the movements held for the quiet hours digest: count (u32), and for each its id, start (unix
+/// milliseconds) and duration (milliseconds, u64::MAX while in progress, all u64), snapshot count
+/// (u16) and paths (u16 length + UTF-8 bytes each). Last come the streams of a --stream-rotation:
+/// count (u32), and for each its name (u8 length + bytes), then its reference and noise map as
+/// above, the same size as the main one. Then the scenes saved by `scene save`: count (u32), and for
+/// each its name (u8 length + bytes), thumbnail width and height (u32), then its reference and noise
+/// map as above, at that size. The activity histogram ends it: the time it started (unix
+/// milliseconds, u64), then 7 times 24 f64 seconds, Monday first.
+pub struct SavedState {
+    pub capture_width: u32,
+    pub capture_height: u32,
+    pub downsample: u32,
+    pub algorithm: String,
+    pub reference: Thumbnail,
+    pub noise_map: Option<NoiseMap>,    // Only kept by the adaptive algorithm, same size as the reference.
+    pub held: Vec<HeldMovement>,        // Restored whatever the capture configuration.
+    pub streams: Vec<StreamState>,      // Of a --stream-rotation, the main reference being the current stream's.
+    pub scenes: Vec<SceneProfile>,      // Checked against the thumbnail size one by one, see profile::SceneProfiles::restore.
+    pub activity: ActivityHistogram,    // Restored whatever the capture configuration too.
+}
+
+
+/// What the detector learned on one stream of a --stream-rotation.
+pub struct StreamState {
+    pub name: String,
+    pub reference: Thumbnail,
+    pub noise_map: Option<NoiseMap>,
+}
+
+
+impl SavedState {
+
+    /// True if the state was saved with the same capture configuration.
+    pub fn matches(&self, capture_width: u32, capture_height: u32, downsample: u32, algorithm: &str) -> bool {
+        self.capture_width == capture_width
+            && self.capture_height == capture_height
+            && self.downsample == downsample
+            && self.algorithm == algorithm
+    }
+
+    /// Writes the state to a temporary file first, so a crash never leaves a half written state behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = Vec::with_capacity(64 + self.reference.pixels.len());
+        data.extend_from_slice(MAGIC);
+        for value in [
+            FORMAT_VERSION,
+            self.capture_width,
+            self.capture_height,
+            self.downsample,
+            self.reference.width as u32,
+            self.reference.height as u32,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let algorithm = &self.algorithm.as_bytes()[.. self.algorithm.len().min(255)];
+        data.push(algorithm.len() as u8);
+        data.extend_from_slice(algorithm);
+        write_learned(&mut data, &self.reference, &self.noise_map);
+        data.extend_from_slice(&(self.held.len() as u32).to_le_bytes());
+        for movement in &self.held {
+            let start = movement.start.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+            let duration = movement.duration.map_or(u64::MAX, |duration| duration.as_millis() as u64);
+            for value in [movement.id, start, duration] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            let snapshots = &movement.snapshots[.. movement.snapshots.len().min(u16::MAX as usize)];
+            data.extend_from_slice(&(snapshots.len() as u16).to_le_bytes());
+            for path in snapshots {
+                let path = path.to_string_lossy();
+                let path = &path.as_bytes()[.. path.len().min(u16::MAX as usize)];
+                data.extend_from_slice(&(path.len() as u16).to_le_bytes());
+                data.extend_from_slice(path);
+            }
+        }
+        data.extend_from_slice(&(self.streams.len() as u32).to_le_bytes());
+        for stream in &self.streams {
+            let name = &stream.name.as_bytes()[.. stream.name.len().min(255)];
+            data.push(name.len() as u8);
+            data.extend_from_slice(name);
+            write_learned(&mut data, &stream.reference, &stream.noise_map);
+        }
+        data.extend_from_slice(&(self.scenes.len() as u32).to_le_bytes());
+        for scene in &self.scenes {
+            let name = &scene.name.as_bytes()[.. scene.name.len().min(255)];
+            data.push(name.len() as u8);
+            data.extend_from_slice(name);
+            data.extend_from_slice(&(scene.reference.width as u32).to_le_bytes());
+            data.extend_from_slice(&(scene.reference.height as u32).to_le_bytes());
+            write_learned(&mut data, &scene.reference, &scene.noise_map);
+        }
+        let since = self.activity.since.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        data.extend_from_slice(&since.to_le_bytes());
+        for seconds in self.activity.seconds.iter().flatten() {
+            data.extend_from_slice(&seconds.to_le_bytes());
+        }
+
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, path)
+    }
+
+    /// Reads a state file, any problem with it is reported as a message rather than a panic.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        let mut reader = Reader { data: &data, position: 0 };
+
+        if reader.bytes(4)? != MAGIC {
+            return Err("not a motion-detect state file".to_string());
+        }
+        let version = reader.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("unsupported state format version {version}"));
+        }
+        let capture_width = reader.u32()?;
+        let capture_height = reader.u32()?;
+        let downsample = reader.u32()?;
+        let thumb_width = reader.u32()? as usize;
+        let thumb_height = reader.u32()? as usize;
+        let algorithm_length = reader.bytes(1)?[0] as usize;
+        let algorithm = String::from_utf8(reader.bytes(algorithm_length)?.to_vec())
+            .map_err(|_| "invalid algorithm name".to_string())?;
+        let (reference, noise_map) = reader.learned(thumb_width, thumb_height)?;
+
+        let mut held = Vec::new();
+        for _ in 0 .. reader.u32()? {
+            let id = reader.u64()?;
+            let start = UNIX_EPOCH + Duration::from_millis(reader.u64()?);
+            let duration = Some(reader.u64()?).filter(|duration| *duration != u64::MAX).map(Duration::from_millis);
+            let mut snapshots = Vec::new();
+            for _ in 0 .. reader.u16()? {
+                let length = reader.u16()? as usize;
+                let path = String::from_utf8(reader.bytes(length)?.to_vec()).map_err(|_| "invalid snapshot path".to_string())?;
+                snapshots.push(PathBuf::from(path));
+            }
+            held.push(HeldMovement { id, start, duration, snapshots });
+        }
+
+        let mut streams = Vec::new();
+        for _ in 0 .. reader.u32()? {
+            let name_length = reader.bytes(1)?[0] as usize;
+            let name = String::from_utf8(reader.bytes(name_length)?.to_vec()).map_err(|_| "invalid stream name".to_string())?;
+            let (reference, noise_map) = reader.learned(thumb_width, thumb_height)?;
+            streams.push(StreamState { name, reference, noise_map });
+        }
+
+        let mut scenes = Vec::new();
+        for _ in 0 .. reader.u32()? {
+            let name_length = reader.bytes(1)?[0] as usize;
+            let name = String::from_utf8(reader.bytes(name_length)?.to_vec()).map_err(|_| "invalid scene name".to_string())?;
+            let (width, height) = (reader.u32()? as usize, reader.u32()? as usize);
+            let (reference, noise_map) = reader.learned(width, height)?;
+            scenes.push(SceneProfile { name, reference, noise_map });
+        }
+
+        let mut activity = ActivityHistogram::new(UNIX_EPOCH + Duration::from_millis(reader.u64()?));
+        for seconds in activity.seconds.iter_mut().flatten() {
+            *seconds = f64::from_bits(reader.u64()?);
+            if !seconds.is_finite() || *seconds < 0.0 {
+                return Err("invalid activity histogram".to_string());
+            }
+        }
+
+        Ok(Self { capture_width, capture_height, downsample, algorithm, reference, noise_map, held, streams, scenes, activity })
+    }
+}
+
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+
+// A reference thumbnail and the noise map, if any, learned along with it.
+fn write_learned(data: &mut Vec<u8>, reference: &Thumbnail, noise_map: &Option<NoiseMap>) {
+    data.extend_from_slice(&(reference.pixels.len() as u32).to_le_bytes());
+    data.extend_from_slice(&reference.pixels);
+    match noise_map {
+        Some(noise_map) => {
+            data.push(1);
+            data.extend_from_slice(&noise_map.samples.to_le_bytes());
+            data.extend_from_slice(&(noise_map.variance.len() as u32).to_le_bytes());
+            for variance in &noise_map.variance {
+                data.extend_from_slice(&variance.to_le_bytes());
+            }
+        }
+        None => data.push(0),
+    }
+}
+
+
+impl<'a> Reader<'a> {
+
+    // As written by `write_learned`, for a thumbnail of the given size.
+    fn learned(&mut self, thumb_width: usize, thumb_height: usize) -> Result<(Thumbnail, Option<NoiseMap>), String> {
+        let pixel_length = self.u32()? as usize;
+        // RGB or luma, depending on the camera's pixel format.
+        let channels = match pixel_length.checked_div(thumb_width * thumb_height) {
+            Some(channels @ (1 | 3)) if pixel_length == thumb_width * thumb_height * channels => channels,
+            _ => return Err("reference thumbnail size doesn't match its dimensions".to_string()),
+        };
+        let mut reference = Thumbnail::with_channels(thumb_width, thumb_height, channels);
+        reference.pixels.copy_from_slice(self.bytes(pixel_length)?);
+
+        let noise_map = match self.bytes(1)?[0] {
+            0 => None,
+            _ => {
+                let mut noise_map = NoiseMap::new(thumb_width, thumb_height);
+                noise_map.samples = self.u32()?;
+                if self.u32()? as usize != noise_map.variance.len() {
+                    return Err("noise map size doesn't match the reference thumbnail".to_string());
+                }
+                for variance in noise_map.variance.iter_mut() {
+                    *variance = f32::from_bits(self.u32()?);
+                }
+                Some(noise_map)
+            }
+        };
+        Ok((reference, noise_map))
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.data.len())
+            .ok_or("state file is truncated")?;
+        let bytes = &self.data[self.position .. end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("Eight bytes")))
+    }
+}
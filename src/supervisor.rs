@@ -0,0 +1,114 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::VecDeque,
+    panic::{ self, AssertUnwindSafe },
+    time::{ Duration, Instant },
+};
+
+/// What to do after a panic was caught.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    Skip,       // Drop the frame and rebuild the detector.
+    Restart,    // Too many lately, also restart the source.
+    Exit,       // Still panicking, leave it to the process supervisor.
+}
+
+
+/// Counts panics caught in per-frame processing, escalating when they come too often.
+pub struct PanicSupervisor {
+    restart_after: usize,
+    exit_after: usize,
+    window: Duration,
+    recent: VecDeque<Instant>,
+    total: u64,
+}
+
+
+impl PanicSupervisor {
+
+    /// * `restart_after` - Panics within `window` that restart the source.
+    /// * `exit_after` - Panics within `window` that end the process.
+    pub fn new(restart_after: usize, exit_after: usize, window: Duration) -> Self {
+        Self { restart_after, exit_after, window, recent: VecDeque::new(), total: 0 }
+    }
+
+    /// Records a panic caught at `now`.
+    pub fn record(&mut self, now: Instant) -> PanicAction {
+        self.total += 1;
+        self.recent.push_back(now);
+        while self.recent.front().is_some_and(|time| now.duration_since(*time) > self.window) {
+            self.recent.pop_front();
+        }
+        match self.recent.len() {
+            count if count >= self.exit_after => PanicAction::Exit,
+            count if count >= self.restart_after => PanicAction::Restart,
+            _ => PanicAction::Skip,
+        }
+    }
+
+    /// Panics caught since launch.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+
+thread_local! {
+    static GUARDED: Cell<bool> = const { Cell::new(false) };
+}
+
+
+/// Runs `f`, turning a panic into an error carrying its message. Whatever `f` touched must be
+/// rebuilt after an error rather than trusted.
+pub fn guard<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    let outer = GUARDED.replace(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(payload.as_ref()));
+    GUARDED.set(outer);
+    result
+}
+
+
+/// True within `guard`, where a panic is caught rather than ending the thread.
+pub fn guarded() -> bool {
+    GUARDED.get()
+}
+
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_panic_is_an_error_with_its_message() {
+        assert_eq!(guard(|| 7), Ok(7));
+        let result: Result<(), _> = guard(|| panic!("bad frame {}", 3));
+        assert_eq!(result, Err("bad frame 3".to_string()));
+        assert!(!guarded());
+        assert_eq!(guard(guarded), Ok(true));
+    }
+
+
+    #[test]
+    fn panics_coming_too_often_escalate() {
+        let mut supervisor = PanicSupervisor::new(2, 3, Duration::from_secs(10));
+        let start = Instant::now();
+        assert_eq!(supervisor.record(start), PanicAction::Skip);
+        assert_eq!(supervisor.record(start + Duration::from_secs(1)), PanicAction::Restart);
+        assert_eq!(supervisor.record(start + Duration::from_secs(2)), PanicAction::Exit);
+        // Past the window only the latest ones count.
+        assert_eq!(supervisor.record(start + Duration::from_secs(30)), PanicAction::Skip);
+        assert_eq!(supervisor.total(), 4);
+    }
+}
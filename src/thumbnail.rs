@@ -0,0 +1,697 @@
+use std::{ fs, path::Path, time::Instant };
+
+/// Byte layout of the full frames handed to the downsampler. Color thumbnails are always RGB,
+/// whatever the channel order and padding of the frames they're made from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelLayout {
+    Rgb,
+    Bgr,
+    Rgba,   // The fourth byte is ignored, alpha or padding.
+    Bgra,
+    Gray,   // 8 bit luma.
+}
+
+
+impl PixelLayout {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "rgb" => Ok(PixelLayout::Rgb),
+            "bgr" => Ok(PixelLayout::Bgr),
+            "rgba" => Ok(PixelLayout::Rgba),
+            "bgra" => Ok(PixelLayout::Bgra),
+            "gray" => Ok(PixelLayout::Gray),
+            _ => Err(format!("Invalid pixel layout '{text}', use rgb, bgr, rgba, bgra or gray")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelLayout::Rgb => "rgb",
+            PixelLayout::Bgr => "bgr",
+            PixelLayout::Rgba => "rgba",
+            PixelLayout::Bgra => "bgra",
+            PixelLayout::Gray => "gray",
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelLayout::Gray => 1,
+            PixelLayout::Rgb | PixelLayout::Bgr => 3,
+            PixelLayout::Rgba | PixelLayout::Bgra => 4,
+        }
+    }
+
+    /// Channels of the thumbnails made from frames in this layout.
+    pub fn channels(&self) -> usize {
+        if *self == PixelLayout::Gray { 1 } else { 3 }
+    }
+
+    /// Appends `frame` to `converted` as RGB, or as is for luma.
+    pub fn extend_rgb(&self, frame: &[u8], converted: &mut Vec<u8>) {
+        match self {
+            PixelLayout::Rgb | PixelLayout::Gray => converted.extend_from_slice(frame),
+            PixelLayout::Bgr => extend_rgb::<3, 2, 0>(frame, converted),
+            PixelLayout::Rgba => extend_rgb::<4, 0, 2>(frame, converted),
+            PixelLayout::Bgra => extend_rgb::<4, 2, 0>(frame, converted),
+        }
+    }
+}
+
+
+// Pixels of BYTES bytes with red at byte RED and blue at byte BLUE, green always being byte 1.
+fn extend_rgb<const BYTES: usize, const RED: usize, const BLUE: usize>(frame: &[u8], converted: &mut Vec<u8>) {
+    converted.extend(frame.chunks_exact(BYTES).flat_map(|pixel| [pixel[RED], pixel[1], pixel[BLUE]]));
+}
+
+
+/// How interlaced frames are made progressive before downsampling, so the comb between two fields
+/// captured apart isn't taken for motion. Either one field's lines stand in for the other's too,
+/// halving the vertical resolution, or both lines of each pair take their average. Frames keep
+/// their size, and the thumbnails theirs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deinterlace {
+    DiscardBottom,  // Keeps the top field, the even lines counting from 0.
+    DiscardTop,     // Keeps the bottom field, the odd lines.
+    /// Each pair of lines takes their average. The means of the thumbnails only change with it at
+    /// odd --downsample factors: at even ones, each block already averages whole pairs of lines.
+    /// What it does change there is the snapshots, and the extremes of --thumb-stats mean-minmax.
+    Average,
+}
+
+
+impl Deinterlace {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "discard-bottom" => Ok(Deinterlace::DiscardBottom),
+            "discard-top" => Ok(Deinterlace::DiscardTop),
+            "average" => Ok(Deinterlace::Average),
+            _ => Err(format!("Invalid deinterlace mode '{text}', use discard-bottom, discard-top or average")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Deinterlace::DiscardBottom => "discard-bottom",
+            Deinterlace::DiscardTop => "discard-top",
+            Deinterlace::Average => "average",
+        }
+    }
+
+    /// Writes the progressive version of `frame`, rows of `row_bytes` bytes, to `progressive`. The
+    /// last line of an odd height has no pair: it's kept as it is, unless its field is discarded,
+    /// then the line above stands in.
+    pub fn apply(&self, frame: &[u8], row_bytes: usize, progressive: &mut Vec<u8>) {
+        progressive.clear();
+        progressive.extend_from_slice(frame);
+        if row_bytes == 0 {
+            return;
+        }
+        let rows = frame.len() / row_bytes;
+        for top in (0 .. rows).step_by(2) {
+            let lines = &mut progressive[top * row_bytes .. (top + 2).min(rows) * row_bytes];
+            if lines.len() == row_bytes {
+                if *self == Deinterlace::DiscardTop && top > 0 {
+                    progressive.copy_within((top - 1) * row_bytes .. top * row_bytes, top * row_bytes);
+                }
+                break;
+            }
+            let (upper, lower) = lines.split_at_mut(row_bytes);
+            match self {
+                Deinterlace::DiscardBottom => lower.copy_from_slice(upper),
+                Deinterlace::DiscardTop => upper.copy_from_slice(lower),
+                Deinterlace::Average => for (upper, lower) in upper.iter_mut().zip(lower.iter_mut()) {
+                    *upper = (*upper as u16 + *lower as u16).div_ceil(2) as u8;
+                    *lower = *upper;
+                },
+            }
+        }
+    }
+}
+
+
+/// What the downsampler keeps of each block of frame pixels: its mean alone, or its darkest and
+/// brightest values along with it, for --thumb-stats. A small bright spot barely moves the mean of
+/// its block, but moves its maximum all the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbStats {
+    Mean,
+    MeanMinMax,
+}
+
+
+impl ThumbStats {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "mean" => Ok(ThumbStats::Mean),
+            "mean-minmax" => Ok(ThumbStats::MeanMinMax),
+            _ => Err(format!("Invalid thumbnail statistics '{text}', use mean or mean-minmax")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThumbStats::Mean => "mean",
+            ThumbStats::MeanMinMax => "mean-minmax",
+        }
+    }
+}
+
+
+/// A downsampled frame, either RGB with 3 bytes per pixel or luma with 1 byte per pixel.
+#[derive(Clone)]
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub pixels: Vec<u8>,
+    pub extremes: Vec<u8>,  // Empty, or for each pixel the minimum then the maximum of each channel.
+}
+
+
+impl Thumbnail {
+
+    /// An RGB thumbnail.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::with_channels(width, height, 3)
+    }
+
+    pub fn with_channels(width: usize, height: usize, channels: usize) -> Self {
+        Self { width, height, channels, pixels: vec![0; width * height * channels], extremes: Vec::new() }
+    }
+
+    /// Also keeps the extremes of each block when downsampling, with ThumbStats::MeanMinMax.
+    pub fn with_stats(mut self, stats: ThumbStats) -> Self {
+        self.extremes = match stats {
+            ThumbStats::Mean => Vec::new(),
+            ThumbStats::MeanMinMax => vec![0; self.pixels.len() * 2],
+        };
+        self
+    }
+
+    /// Number of pixels (not bytes).
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// True if both thumbnails have the same dimensions and channels.
+    pub fn same_shape(&self, other: &Thumbnail) -> bool {
+        self.width == other.width && self.height == other.height && self.channels == other.channels
+    }
+
+    /// Bytes of the pixel buffers.
+    pub fn buffer_bytes(&self) -> usize {
+        self.pixels.capacity() + self.extremes.capacity()
+    }
+
+    /// The channel values of the pixel at index (y * width + x).
+    pub fn pixel(&self, index: usize) -> &[u8] {
+        &self.pixels[index * self.channels .. (index + 1) * self.channels]
+    }
+
+    /// The minimum then the maximum of each channel over the block of the pixel at index, if kept.
+    pub fn extremes(&self, index: usize) -> Option<&[u8]> {
+        self.extremes.get(index * self.channels * 2 .. (index + 1) * self.channels * 2)
+    }
+
+    /// As a binary PPM (P6) image, or PGM (P5) for luma, with a header of single newlines.
+    pub fn to_pnm(&self) -> Vec<u8> {
+        let magic = if self.channels == 1 { "P5" } else { "P6" };
+        let mut data = format!("{magic}\n{} {}\n255\n", self.width, self.height).into_bytes();
+        data.extend_from_slice(&self.pixels);
+        data
+    }
+
+    /// Reads a binary PPM or PGM image with 8-bit samples, as `to_pnm` writes them: a header of
+    /// single spaces or newlines, no comments.
+    pub fn read_pnm(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        let invalid = |reason: &str| format!("invalid image {}: {reason}", path.display());
+        // Four fields, each followed by one whitespace byte.
+        let mut fields = Vec::new();
+        let mut position = 0;
+        while fields.len() < 4 {
+            let start = position;
+            while data.get(position).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                position += 1;
+            }
+            if start == position || position >= data.len() {
+                return Err(invalid("truncated header"));
+            }
+            fields.push(String::from_utf8_lossy(&data[start .. position]).into_owned());
+            position += 1;
+        }
+        let channels = match fields[0].as_str() {
+            "P5" => 1,
+            "P6" => 3,
+            _ => return Err(invalid("not a binary PGM or PPM")),
+        };
+        let number = |field: &str| field.parse::<usize>().map_err(|_| invalid("bad header"));
+        let (width, height) = (number(&fields[1])?, number(&fields[2])?);
+        if width == 0 || height == 0 || fields[3] != "255" {
+            return Err(invalid("only 8-bit images of at least one pixel are supported"));
+        }
+        let pixels = data.get(position .. position + width * height * channels).ok_or_else(|| invalid("truncated pixels"))?;
+        Ok(Self { width, height, channels, pixels: pixels.to_vec(), extremes: Vec::new() })
+    }
+
+    /// Resizes a full frame in the given layout, which must have the thumbnail's channels. Each
+    /// layout gets its own copy of the loop, with the extremes or without, so there is no per pixel
+    /// branching.
+    pub fn downsample(&mut self, frame: &[u8], frame_width: usize, factor: usize, layout: PixelLayout) {
+        match self.extremes.is_empty() {
+            true => self.downsample_layout::<false>(frame, frame_width, factor, layout),
+            false => self.downsample_layout::<true>(frame, frame_width, factor, layout),
+        }
+    }
+
+    fn downsample_layout<const EXTREMES: bool>(&mut self, frame: &[u8], frame_width: usize, factor: usize, layout: PixelLayout) {
+        match layout {
+            PixelLayout::Rgb => self.downsample_color::<3, 0, 2, EXTREMES>(frame, frame_width, factor),
+            PixelLayout::Bgr => self.downsample_color::<3, 2, 0, EXTREMES>(frame, frame_width, factor),
+            PixelLayout::Rgba => self.downsample_color::<4, 0, 2, EXTREMES>(frame, frame_width, factor),
+            PixelLayout::Bgra => self.downsample_color::<4, 2, 0, EXTREMES>(frame, frame_width, factor),
+            PixelLayout::Gray => self.downsample_gray::<EXTREMES>(frame, frame_width, factor),
+        }
+    }
+
+    /// Resizes a full RGB frame into this thumbnail by averaging blocks of factor x factor pixels.
+    /// The thumbnail must already have the frame size divided by the factor.
+    pub fn downsample_rgb(&mut self, frame: &[u8], frame_width: usize, factor: usize) {
+        self.downsample(frame, frame_width, factor, PixelLayout::Rgb);
+    }
+
+    // As downsample_rgb, for pixels of BYTES bytes with red at byte RED and blue at byte BLUE,
+    // keeping the extremes of each block in the same pass if EXTREMES.
+    fn downsample_color<const BYTES: usize, const RED: usize, const BLUE: usize, const EXTREMES: bool>(&mut self, frame: &[u8], frame_width: usize, factor: usize) {
+        let sample_count = (factor * factor) as u32;
+        for thumb_y in 0 .. self.height {
+            for thumb_x in 0 .. self.width {
+                let source_x = thumb_x * factor;
+                let source_y = thumb_y * factor;
+                let mut resized_pixel:[u32; 3] = [0, 0, 0];
+                let (mut low, mut high) = ([u8::MAX; 3], [0u8; 3]);
+                for y in 0 .. factor {
+                    for x in 0 .. factor {
+                        let sub_pixel_index = (((source_y + y) * frame_width) + (source_x + x)) * BYTES;
+                        let sub_pixel = [frame[sub_pixel_index+RED], frame[sub_pixel_index+1], frame[sub_pixel_index+BLUE]];
+                        // Accumulate RGB values
+                        for channel in 0 .. 3 {
+                            resized_pixel[channel] += sub_pixel[channel] as u32;
+                            if EXTREMES {
+                                low[channel] = low[channel].min(sub_pixel[channel]);
+                                high[channel] = high[channel].max(sub_pixel[channel]);
+                            }
+                        }
+                    }
+                }
+
+                // Averages RGB value and assigns it to thumbnail
+                let dest_index = ((thumb_y * self.width) + thumb_x) * 3;
+                self.pixels[dest_index] = (resized_pixel[0] / sample_count).min(255) as u8;
+                self.pixels[dest_index+1] = (resized_pixel[1] / sample_count).min(255) as u8;
+                self.pixels[dest_index+2] = (resized_pixel[2] / sample_count).min(255) as u8;
+                if EXTREMES {
+                    self.extremes[dest_index * 2 .. dest_index * 2 + 3].copy_from_slice(&low);
+                    self.extremes[dest_index * 2 + 3 .. dest_index * 2 + 6].copy_from_slice(&high);
+                }
+            }
+        }
+    }
+
+    /// Resizes a full single channel (8 bit grayscale) frame into this luma thumbnail by averaging
+    /// blocks of factor x factor pixels.
+    pub fn downsample_luma(&mut self, frame: &[u8], frame_width: usize, factor: usize) {
+        self.downsample(frame, frame_width, factor, PixelLayout::Gray);
+    }
+
+    // As downsample_luma, keeping the extremes of each block in the same pass if EXTREMES.
+    fn downsample_gray<const EXTREMES: bool>(&mut self, frame: &[u8], frame_width: usize, factor: usize) {
+        let sample_count = (factor * factor) as u32;
+        for thumb_y in 0 .. self.height {
+            for thumb_x in 0 .. self.width {
+                let source_x = thumb_x * factor;
+                let source_y = thumb_y * factor;
+                let mut sum = 0u32;
+                let (mut low, mut high) = (u8::MAX, 0u8);
+                for y in 0 .. factor {
+                    let row = &frame[(source_y + y) * frame_width + source_x ..][.. factor];
+                    sum += row.iter().map(|value| *value as u32).sum::<u32>();
+                    if EXTREMES {
+                        low = row.iter().fold(low, |low, value| low.min(*value));
+                        high = row.iter().fold(high, |high, value| high.max(*value));
+                    }
+                }
+                let dest_index = thumb_y * self.width + thumb_x;
+                self.pixels[dest_index] = (sum / sample_count).min(255) as u8;
+                if EXTREMES {
+                    self.extremes[dest_index * 2] = low;
+                    self.extremes[dest_index * 2 + 1] = high;
+                }
+            }
+        }
+    }
+}
+
+
+/// Averages groups of consecutive thumbnails to beat down sensor noise in low light, at the cost of
+/// dividing the detection rate by the group size. A group size of 1 passes thumbnails straight through.
+pub struct TemporalAverage {
+    group_size: usize,
+    sums: Vec<u32>,
+    count: usize,
+    middle_time: Option<Instant>,
+    averaged: Thumbnail,
+}
+
+
+impl TemporalAverage {
+
+    pub fn new(group_size: usize) -> Self {
+        Self { group_size: group_size.max(1), sums: Vec::new(), count: 0, middle_time: None, averaged: Thumbnail::new(0, 0) }
+    }
+
+    /// Adds a thumbnail captured at `time`. Once a group is complete, returns the averaged thumbnail
+    /// along with the capture time of the group's middle frame.
+    pub fn push<'a>(&'a mut self, thumb: &'a Thumbnail, time: Instant) -> Option<(&'a Thumbnail, Instant)> {
+        if self.group_size == 1 {
+            return Some((thumb, time));
+        }
+        if !self.averaged.same_shape(thumb) || self.averaged.extremes.len() != thumb.extremes.len() {
+            self.averaged = Thumbnail::with_channels(thumb.width, thumb.height, thumb.channels);
+            self.averaged.extremes = vec![0; thumb.extremes.len()];
+            self.sums = vec![0; thumb.pixels.len() + thumb.extremes.len()];
+            self.count = 0;
+        }
+
+        // Block extremes are averaged like the means, after them.
+        for (sum, value) in self.sums.iter_mut().zip(thumb.pixels.iter().chain(&thumb.extremes)) {
+            *sum += *value as u32;
+        }
+        if self.count == self.group_size / 2 {
+            self.middle_time = Some(time);
+        }
+        self.count += 1;
+        if self.count < self.group_size {
+            return None;
+        }
+
+        // Group complete, divide and start over.
+        let group_size = self.group_size as u32;
+        let averaged = &mut self.averaged;
+        for (value, sum) in averaged.pixels.iter_mut().chain(averaged.extremes.iter_mut()).zip(self.sums.iter_mut()) {
+            *value = (*sum / group_size) as u8;
+            *sum = 0;
+        }
+        self.count = 0;
+        Some((&self.averaged, self.middle_time.take().unwrap_or(time)))
+    }
+
+    /// Bytes of the sums and the averaged thumbnail.
+    pub fn buffer_bytes(&self) -> usize {
+        self.sums.capacity() * std::mem::size_of::<u32>() + self.averaged.buffer_bytes()
+    }
+}
+
+
+/// Which thumbnail a new one is compared against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferencePolicy {
+    /// The one before, so only what changed since counts.
+    EveryFrame,
+    /// The latest that moved: still frames don't replace it, so slow changes add up against it.
+    OnMotion,
+}
+
+
+/// A thumbnail being compared and the one it's compared against, in two buffers taking turns, so
+/// neither is reallocated and the current one is never its own reference. Each frame is written
+/// into the current buffer, compared, then `advance` makes it the previous one or not, as the
+/// policy says.
+///
+/// ```
+/// use motion_detect::thumbnail::{ ReferencePolicy, ThumbPair };
+///
+/// let mut pair = ThumbPair::new(4, 3, 1, ReferencePolicy::OnMotion);
+/// for value in [10, 90, 95] {
+///     pair.write_current(|pixels| pixels.fill(value));
+///     let moved = pair.previous().is_some_and(|previous| {
+///         pair.current().pixels.iter().zip(&previous.pixels).any(|(now, before)| now.abs_diff(*before) > 20)
+///     });
+///     pair.advance(moved);
+/// }
+/// // 90 moved against 10 and replaced it, 95 didn't against 90.
+/// assert_eq!(pair.previous().unwrap().pixels, [90; 12]);
+/// ```
+pub struct ThumbPair {
+    thumbs: [Thumbnail; 2],
+    current: usize,         // The other one is the previous one, once there is one.
+    has_previous: bool,
+    policy: ReferencePolicy,
+}
+
+
+impl ThumbPair {
+
+    pub fn new(width: usize, height: usize, channels: usize, policy: ReferencePolicy) -> Self {
+        let thumb = Thumbnail::with_channels(width, height, channels);
+        Self { thumbs: [thumb.clone(), thumb], current: 0, has_previous: false, policy }
+    }
+
+    pub fn policy(&self) -> ReferencePolicy {
+        self.policy
+    }
+
+    pub fn current(&self) -> &Thumbnail {
+        &self.thumbs[self.current]
+    }
+
+    /// What the current thumbnail is compared against, none before the first `advance`.
+    pub fn previous(&self) -> Option<&Thumbnail> {
+        self.has_previous.then(|| &self.thumbs[1 - self.current])
+    }
+
+    /// E.g. to fill in what a restored previous thumbnail lacks.
+    pub fn previous_mut(&mut self) -> Option<&mut Thumbnail> {
+        self.has_previous.then(|| &mut self.thumbs[1 - self.current])
+    }
+
+    /// Lets `write` fill the pixels of the current thumbnail.
+    pub fn write_current(&mut self, write: impl FnOnce(&mut [u8])) {
+        write(&mut self.thumbs[self.current].pixels);
+    }
+
+    /// Copies a thumbnail made elsewhere into the current one, its block extremes too, taking its
+    /// shape if it has another one.
+    pub fn set_current(&mut self, thumb: &Thumbnail) {
+        let current = &mut self.thumbs[self.current];
+        if current.same_shape(thumb) {
+            current.pixels.copy_from_slice(&thumb.pixels);
+            current.extremes.clone_from(&thumb.extremes);
+        } else {
+            *current = thumb.clone();
+        }
+    }
+
+    /// Compares the next thumbnails against `thumb`, e.g. a saved reference.
+    pub fn set_previous(&mut self, thumb: Thumbnail) {
+        self.thumbs[1 - self.current] = thumb;
+        self.has_previous = true;
+    }
+
+    /// The current thumbnail becomes the previous one, the next is written over the one before.
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+        self.has_previous = true;
+    }
+
+    /// Done with the current thumbnail, which `moved` or not: swaps if the policy keeps it as the
+    /// reference, always for the first one.
+    pub fn advance(&mut self, moved: bool) {
+        if moved || self.policy == ReferencePolicy::EveryFrame || !self.has_previous {
+            self.swap();
+        }
+    }
+
+    /// Bytes of both thumbnails.
+    pub fn buffer_bytes(&self) -> usize {
+        self.thumbs.iter().map(Thumbnail::buffer_bytes).sum()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsampling_averages_blocks() {
+        // A 4 by 2 frame, of two 2 by 2 blocks: one of 0 and 100 in red, one of 255 in blue.
+        let mut frame = Vec::new();
+        for _ in 0 .. 2 {
+            frame.extend([0, 0, 0, 100, 0, 0, 0, 0, 255, 0, 0, 255]);
+        }
+        let mut thumb = Thumbnail::new(2, 1);
+        thumb.downsample_rgb(&frame, 4, 2);
+        assert_eq!(thumb.pixels, [50, 0, 0, 0, 0, 255]);
+    }
+
+
+    #[test]
+    fn block_extremes_are_kept_in_the_same_pass() {
+        let mut frame = Vec::new();
+        for _ in 0 .. 2 {
+            frame.extend([0, 0, 0, 100, 0, 0, 0, 0, 255, 0, 0, 255]);
+        }
+        let mut thumb = Thumbnail::new(2, 1).with_stats(ThumbStats::MeanMinMax);
+        thumb.downsample_rgb(&frame, 4, 2);
+        assert_eq!(thumb.pixels, [50, 0, 0, 0, 0, 255]);
+        assert_eq!(thumb.extremes(0), Some([0, 0, 0, 100, 0, 0].as_slice()));
+        assert_eq!(thumb.extremes(1), Some([0, 0, 255, 0, 0, 255].as_slice()));
+        assert_eq!(Thumbnail::new(2, 1).extremes(0), None);
+    }
+
+
+    #[test]
+    fn every_layout_downsamples_to_rgb() {
+        let (red, green, blue) = (10, 20, 30);
+        let pixel = |layout: PixelLayout| match layout {
+            PixelLayout::Rgb => vec![red, green, blue],
+            PixelLayout::Bgr => vec![blue, green, red],
+            PixelLayout::Rgba => vec![red, green, blue, 255],
+            PixelLayout::Bgra => vec![blue, green, red, 255],
+            PixelLayout::Gray => vec![green],
+        };
+        for layout in [PixelLayout::Rgb, PixelLayout::Bgr, PixelLayout::Rgba, PixelLayout::Bgra] {
+            let frame = pixel(layout).repeat(4);
+            let mut thumb = Thumbnail::new(1, 1);
+            thumb.downsample(&frame, 2, 2, layout);
+            assert_eq!(thumb.pixels, [red, green, blue], "{}", layout.name());
+        }
+    }
+
+
+    #[test]
+    fn gray_frames_downsample_to_luma() {
+        let frame = [0, 100, 200, 200, 50, 150, 200, 200];
+        let mut thumb = Thumbnail::with_channels(2, 1, 1);
+        thumb.downsample(&frame, 4, 2, PixelLayout::Gray);
+        assert_eq!(thumb.pixels, [75, 200]);
+    }
+
+
+    #[test]
+    fn pnm_images_read_back() {
+        let mut thumb = Thumbnail::new(3, 2);
+        thumb.pixels.iter_mut().enumerate().for_each(|(index, value)| *value = index as u8 * 10);
+        let path = std::env::temp_dir().join(format!("thumbnail_{}.ppm", std::process::id()));
+        fs::write(&path, thumb.to_pnm()).unwrap();
+        let read = Thumbnail::read_pnm(&path);
+        let _ = fs::remove_file(&path);
+        let read = read.unwrap();
+        assert!(read.same_shape(&thumb));
+        assert_eq!(read.pixels, thumb.pixels);
+    }
+
+
+    #[test]
+    fn a_pair_swaps_its_buffers_without_copying() {
+        let mut pair = ThumbPair::new(2, 2, 1, ReferencePolicy::EveryFrame);
+        assert!(pair.previous().is_none());
+        pair.write_current(|pixels| pixels.fill(1));
+        let first = pair.current().pixels.as_ptr();
+        pair.advance(false);
+        assert_eq!(pair.previous().map(|previous| previous.pixels.as_ptr()), Some(first));
+        pair.write_current(|pixels| pixels.fill(2));
+        assert_ne!(pair.current().pixels.as_ptr(), first);
+        pair.swap();
+        // The one before last is written over.
+        assert_eq!(pair.current().pixels.as_ptr(), first);
+        assert_eq!(pair.previous().unwrap().pixels, [2; 4]);
+    }
+
+
+    #[test]
+    fn the_policy_says_which_thumbnails_become_the_reference() {
+        for (policy, reference) in [(ReferencePolicy::EveryFrame, 3), (ReferencePolicy::OnMotion, 2)] {
+            let mut pair = ThumbPair::new(2, 2, 1, policy);
+            // The first is the reference whether it moved or not.
+            for (value, moved) in [(1, false), (2, true), (3, false)] {
+                pair.write_current(|pixels| pixels.fill(value));
+                pair.advance(moved);
+            }
+            assert_eq!(pair.previous().unwrap().pixels, [reference; 4], "{policy:?}");
+        }
+    }
+
+
+    #[test]
+    fn a_pair_takes_the_shape_of_what_is_set() {
+        let mut pair = ThumbPair::new(0, 0, 3, ReferencePolicy::OnMotion);
+        let mut thumb = Thumbnail::new(2, 1).with_stats(ThumbStats::MeanMinMax);
+        thumb.pixels.fill(7);
+        pair.set_current(&thumb);
+        assert!(pair.current().same_shape(&thumb));
+        assert_eq!((pair.current().pixels.len(), pair.current().extremes.len()), (6, 12));
+        pair.set_previous(Thumbnail::new(4, 4));
+        assert_eq!(pair.previous().map(|previous| previous.width), Some(4));
+        assert_eq!(pair.current().pixels, [7; 6]);
+    }
+
+
+    // 8 by 8 luma, an edge moving right between the fields: lit from column 0 to 4 on the even
+    // lines, from 2 to 6 on the odd ones captured later.
+    fn combed() -> Vec<u8> {
+        (0 .. 8).flat_map(|row| (0 .. 8).map(move |column| {
+            let left = if row % 2 == 0 { 0 } else { 2 };
+            if (left .. left + 4).contains(&column) { 200 } else { 0 }
+        })).collect()
+    }
+
+
+    #[test]
+    fn deinterlacing_removes_the_comb() {
+        let even = [200, 200, 200, 200, 0, 0, 0, 0];
+        let odd = [0, 0, 200, 200, 200, 200, 0, 0];
+        let blended = [100, 100, 200, 200, 100, 100, 0, 0];
+        for (mode, line) in [(Deinterlace::DiscardBottom, even), (Deinterlace::DiscardTop, odd), (Deinterlace::Average, blended)] {
+            let mut progressive = Vec::new();
+            mode.apply(&combed(), 8, &mut progressive);
+            assert_eq!(progressive.len(), 64);
+            // Every line is the same, nothing changes from one to the next.
+            assert!(progressive.chunks_exact(8).all(|row| row == line), "{mode:?}: {progressive:?}");
+        }
+    }
+
+
+    #[test]
+    fn an_odd_last_line_keeps_to_the_field_left() {
+        let frame = &combed()[.. 7 * 8];
+        let mut progressive = Vec::new();
+        Deinterlace::DiscardTop.apply(frame, 8, &mut progressive);
+        assert_eq!(&progressive[6 * 8 ..], &progressive[5 * 8 .. 6 * 8]);
+        Deinterlace::Average.apply(frame, 8, &mut progressive);
+        assert_eq!(&progressive[6 * 8 ..], &frame[6 * 8 ..]);
+    }
+
+
+    #[test]
+    fn averaging_lines_leaves_thumbnails_at_even_factors_as_they_were() {
+        let mut averaged = Vec::new();
+        Deinterlace::Average.apply(&combed(), 8, &mut averaged);
+        let thumbnail = |frame: &[u8], factor| {
+            let mut thumb = Thumbnail::with_channels(8 / factor, 8 / factor, 1);
+            thumb.downsample_luma(frame, 8, factor);
+            thumb.pixels
+        };
+        assert_eq!(thumbnail(&averaged, 2), thumbnail(&combed(), 2));
+        assert_ne!(thumbnail(&averaged, 1), thumbnail(&combed(), 1));
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    path::PathBuf,
+    time::{ Duration, Instant, SystemTime },
+};
+
+use crate::clock::UtcTime;
+
+/// Decides when to save a timelapse frame: rarely while nothing moves, often while a movement is
+/// active. Frames are named by their UTC capture time, so they sort in capture order.
+pub struct Timelapse {
+    directory: PathBuf,
+    idle_interval: Duration,
+    active_interval: Duration,
+    daily: bool,
+    last_saved: Option<Instant>,
+}
+
+
+impl Timelapse {
+
+    /// * `daily` - Saves each day's frames in a "YYYY-MM-DD" subdirectory.
+    pub fn new(directory: PathBuf, idle_interval: Duration, active_interval: Duration, daily: bool) -> Self {
+        Self { directory, idle_interval, active_interval, daily, last_saved: None }
+    }
+
+    /// True if the frame captured at `now` should be saved. The first frame always is, and a
+    /// movement starting switches to the active rate right away.
+    pub fn due(&mut self, now: Instant, motion_active: bool) -> bool {
+        let interval = if motion_active { self.active_interval } else { self.idle_interval };
+        let due = self.last_saved.is_none_or(|last| now.saturating_duration_since(last) >= interval);
+        if due {
+            self.last_saved = Some(now);
+        }
+        due
+    }
+
+    /// Where the frame captured at `time` goes, without extension.
+    pub fn path(&self, time: SystemTime) -> PathBuf {
+        let utc = UtcTime::from(time);
+        let mut path = self.directory.clone();
+        if self.daily {
+            path.push(format!("{:04}-{:02}-{:02}", utc.year, utc.month, utc.day));
+        }
+        path.push(format!(
+            "{:04}{:02}{:02}-{:02}{:02}{:02}",
+            utc.year, utc.month, utc.day, utc.hour, utc.minute, utc.second
+        ));
+        path
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use crate::json;
+
+// Weight of the latest frame in the rolling averages, as in the CPU budget.
+const SMOOTHING: f64 = 0.1;
+
+
+/// Steps of the per-frame pipeline, timed separately to tell where the time goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Capture,    // Waiting for the source, including its format conversion.
+    Downsample, // Thumbnail and snapshot copy of the frame.
+    Diff,       // The diff strategy.
+    Output,     // Events, snapshots, status and the other sinks.
+}
+
+
+impl Stage {
+
+    pub const ALL: [Stage; 4] = [Stage::Capture, Stage::Downsample, Stage::Diff, Stage::Output];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Downsample => "downsample",
+            Stage::Diff => "diff",
+            Stage::Output => "output",
+        }
+    }
+}
+
+
+/// Time spent in one stage: the latest frame, the rolling average and the maximum since the
+/// last `reset_max`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTiming {
+    pub latest: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
+
+/// Per-stage timings of the detection pipeline. Recording is plain arithmetic, nothing is
+/// formatted unless asked for, so it is always on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineTiming {
+    stages: [StageTiming; 4],
+    frames: u64,
+    skipped: f64,       // Share of the latest frame's pixels the diff left uncompared...
+    skipped_mean: f64,  // ...its rolling average...
+    early_exits: u64,   // ...and the frames it stopped early on.
+}
+
+
+impl PipelineTiming {
+
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        let timing = &mut self.stages[stage as usize];
+        timing.latest = elapsed;
+        timing.mean = if self.frames == 0 {
+            elapsed
+        } else {
+            timing.mean.mul_f64(1.0 - SMOOTHING) + elapsed.mul_f64(SMOOTHING)
+        };
+        timing.max = timing.max.max(elapsed);
+    }
+
+    /// What the diff skipped of a frame by exiting early, out of `pixels`.
+    pub fn record_skipped(&mut self, skipped: usize, pixels: usize) {
+        self.skipped = skipped as f64 / pixels.max(1) as f64;
+        self.skipped_mean = if self.frames == 0 {
+            self.skipped
+        } else {
+            self.skipped_mean * (1.0 - SMOOTHING) + self.skipped * SMOOTHING
+        };
+        self.early_exits += (skipped > 0) as u64;
+    }
+
+    /// Marks the end of a frame, the first frame starts the averages.
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn stage(&self, stage: Stage) -> StageTiming {
+        self.stages[stage as usize]
+    }
+
+    /// Starts a new window for the maxima, e.g. after they were reported.
+    pub fn reset_max(&mut self) {
+        self.stages.iter_mut().for_each(|timing| timing.max = Duration::ZERO);
+    }
+
+    /// The latest frame's breakdown, e.g. "timing: capture 48.2ms, downsample 1.1ms, diff 0.3ms, output 0.1ms",
+    /// with "diff 0.1ms (75% skipped)" when the diff exited early.
+    pub fn frame_line(&self) -> String {
+        let stages: Vec<String> = Stage::ALL.iter()
+            .map(|stage| match *stage == Stage::Diff && self.skipped > 0.0 {
+                true => format!("{} {:.1?} ({:.0}% skipped)", stage.name(), self.stage(*stage).latest, self.skipped * 100.0),
+                false => format!("{} {:.1?}", stage.name(), self.stage(*stage).latest),
+            })
+            .collect();
+        format!("timing: {}", stages.join(", "))
+    }
+
+    /// Rolling averages and maxima, e.g. "timing: capture 48.2ms (max 61.0ms), downsample ...",
+    /// with "diff 0.2ms (max 0.4ms, 40% skipped, 12 early exits)" once the diff exited early.
+    pub fn summary_line(&self) -> String {
+        let stages: Vec<String> = Stage::ALL.iter()
+            .map(|stage| {
+                let timing = self.stage(*stage);
+                match *stage == Stage::Diff && self.early_exits > 0 {
+                    true => format!("{} {:.1?} (max {:.1?}, {:.0}% skipped, {} early exits)",
+                        stage.name(), timing.mean, timing.max, self.skipped_mean * 100.0, self.early_exits),
+                    false => format!("{} {:.1?} (max {:.1?})", stage.name(), timing.mean, timing.max),
+                }
+            })
+            .collect();
+        format!("timing: {} over {} frames", stages.join(", "), self.frames)
+    }
+
+    /// Milliseconds per stage, as `{"capture": {"mean_ms": 48.215, "max_ms": 61.002}, ...}`. Once
+    /// the diff exited early, it also has the rolling share of pixels skipped and the frames cut short,
+    /// as `"diff": {..., "skipped_percent": 40.2, "early_exits": 12}`.
+    pub fn to_object(&self) -> json::Object {
+        Stage::ALL.iter().fold(json::Object::new(), |object, stage| {
+            let timing = self.stage(*stage);
+            let times = json::Object::new()
+                .field("mean_ms", timing.mean.as_secs_f64() * 1000.0)
+                .field("max_ms", timing.max.as_secs_f64() * 1000.0);
+            object.field(stage.name(), match *stage == Stage::Diff && self.early_exits > 0 {
+                true => times.field("skipped_percent", self.skipped_mean * 100.0).field("early_exits", self.early_exits),
+                false => times,
+            })
+        })
+    }
+}
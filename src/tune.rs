@@ -0,0 +1,314 @@
+use std::{
+    collections::VecDeque,
+    io::{ Read, Write },
+    sync::mpsc,
+    time::{ Duration, Instant },
+};
+use eye::hal::PlatformContext;
+
+use crate::{
+    camera::{ self, Camera },
+    diff::{ self, Blur, DiffStrategy, FlickerRejection, Masked, Normalize },
+    output::{ Format, Output },
+    settings::Settings,
+    signals,
+    thumbnail::Thumbnail,
+};
+
+// Exit codes, as for self-test.
+const EXIT_DEVICE: i32 = 19;        // No such device
+const EXIT_CAPTURE: i32 = 5;        // I/O error
+
+// Frames the false positive rate is estimated over.
+const STATIC_WINDOW: Duration = Duration::from_secs(30);
+
+// Characters of the changed pixels bar, which spans twice the image threshold.
+const BAR_WIDTH: usize = 40;
+
+const MAX_BLUR: usize = 10;
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    use std::os::raw::c_int;
+
+    pub const ICANON: u32 = 0o2;
+    pub const ECHO: u32 = 0o10;
+    pub const VTIME: usize = 5;
+    pub const VMIN: usize = 6;
+    pub const TCSANOW: c_int = 0;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: u32,
+        pub c_oflag: u32,
+        pub c_cflag: u32,
+        pub c_lflag: u32,
+        pub c_line: u8,
+        pub c_cc: [u8; 32],
+        pub c_ispeed: u32,
+        pub c_ospeed: u32,
+    }
+
+    extern "C" {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+    }
+}
+
+
+/// The terminal in single keypress mode without echo, restored when dropped. Signals still work,
+/// so Ctrl+C ends the session cleanly.
+struct RawTerminal {
+    #[cfg(target_os = "linux")]
+    saved: ffi::Termios,
+}
+
+
+impl RawTerminal {
+
+    /// None if stdin isn't a terminal, keys then need Enter.
+    #[cfg(target_os = "linux")]
+    fn enter() -> Option<Self> {
+        let mut saved = std::mem::MaybeUninit::<ffi::Termios>::uninit();
+        if unsafe { ffi::tcgetattr(0, saved.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let saved = unsafe { saved.assume_init() };
+        let mut raw = saved;
+        raw.c_lflag &= !(ffi::ICANON | ffi::ECHO);
+        raw.c_cc[ffi::VMIN] = 1;
+        raw.c_cc[ffi::VTIME] = 0;
+        match unsafe { ffi::tcsetattr(0, ffi::TCSANOW, &raw) } {
+            0 => Some(Self { saved }),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn enter() -> Option<Self> {
+        None
+    }
+}
+
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        unsafe { ffi::tcsetattr(0, ffi::TCSANOW, &self.saved); }
+    }
+}
+
+
+/// The values being tuned, in the units of their command line options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Values {
+    pixel_threshold: f32,   // Percent.
+    image_threshold: f32,   // Percent.
+    blur: usize,
+    luma: bool,             // Compare luma only, even for RGB frames.
+}
+
+
+impl Values {
+
+    /// Applies a key, returns false for keys that don't change anything.
+    fn apply(&mut self, key: char) -> bool {
+        let before = *self;
+        match key {
+            'a' => self.pixel_threshold = (self.pixel_threshold + 1.0).min(100.0),
+            'z' => self.pixel_threshold = (self.pixel_threshold - 1.0).max(0.0),
+            's' => self.image_threshold = (self.image_threshold + 1.0).min(100.0),
+            'x' => self.image_threshold = (self.image_threshold - 1.0).max(0.0),
+            'd' => self.blur = (self.blur + 1).min(MAX_BLUR),
+            'c' => self.blur = self.blur.saturating_sub(1),
+            'l' => self.luma = !self.luma,
+            _ => {}
+        }
+        *self != before
+    }
+
+    fn flags(&self) -> String {
+        format!("--pixel-threshold {} --image-threshold {} --blur {}", self.pixel_threshold, self.image_threshold, self.blur)
+    }
+}
+
+
+/// Frames of the last STATIC_WINDOW and whether each crossed the image threshold. The scene is
+/// presumed static while tuning, so every crossing would have been a false start.
+#[derive(Default)]
+struct FalsePositives {
+    frames: VecDeque<(Instant, bool)>,
+}
+
+
+impl FalsePositives {
+
+    fn push(&mut self, now: Instant, above: bool) {
+        self.frames.push_back((now, above));
+        while self.frames.front().is_some_and(|(time, _)| now.duration_since(*time) > STATIC_WINDOW) {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Percentage of frames above the threshold, and how long they were measured for.
+    fn rate(&self, now: Instant) -> (f32, Duration) {
+        let above = self.frames.iter().filter(|(_, above)| *above).count();
+        let span = self.frames.front().map(|(time, _)| now.duration_since(*time)).unwrap_or_default();
+        (above as f32 * 100.0 / self.frames.len().max(1) as f32, span)
+    }
+}
+
+
+/// Shows the changed pixels of the live camera while thresholds are adjusted with single keys,
+/// then prints the final values as options. Returns the process exit code.
+pub fn run(settings: &Settings) -> i32 {
+    let output = Output::new(Format::Text);
+    let ctx = PlatformContext::default();
+    let mut camera = match Camera::open(&ctx, settings.capture_width, settings.capture_height, settings.frame_capture_interval, &output) {
+        Ok(camera) => camera,
+        Err(err) => {
+            println!("\nError, {err}");
+            return EXIT_DEVICE;
+        }
+    };
+    if let Some(layout) = settings.force_input_layout {
+        camera.force_layout(layout);
+    }
+    println!("Warming up for {:.1?}...", settings.camera_warm_up);
+    std::thread::sleep(settings.camera_warm_up);
+
+    let frame_width = camera.descriptor.width as usize;
+    let layout = camera::layout(&camera.descriptor.pixfmt, settings.force_input_layout);
+    let channels = layout.channels();
+    let mut thumb = Thumbnail::with_channels(frame_width / settings.downsample, camera.descriptor.height as usize / settings.downsample, channels)
+        .with_stats(settings.thumb_stats);
+    let mut progressive = Vec::new();
+    let mut luma_thumb = Thumbnail::with_channels(thumb.width, thumb.height, 1);
+    let mut values = Values {
+        pixel_threshold: settings.pixel_threshold,
+        image_threshold: settings.image_threshold,
+        blur: settings.blur,
+        luma: channels == 1,
+    };
+    let mut strategy = build_strategy(settings, &values, thumb.len());
+    let mut false_positives = FalsePositives::default();
+
+    // Keys are read on their own thread, so the bar keeps moving between them.
+    signals::install_shutdown_handler();
+    let terminal = RawTerminal::enter();
+    let (key_sender, keys) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8; 1];
+        while let Ok(1) = std::io::stdin().read(&mut byte) {
+            if key_sender.send(byte[0] as char).is_err() {
+                break;
+            }
+        }
+    });
+    println!("Keep the scene still. a/z pixel threshold, s/x image threshold, d/c blur, l luma, q quits{}",
+        if terminal.is_some() { "" } else { " (each followed by Enter)" });
+
+    let mut exit_code = 0;
+    'session: while !signals::shutdown_requested() {
+        while let Ok(key) = keys.try_recv() {
+            match key.to_ascii_lowercase() {
+                'q' | '\x1b' | '\x04' => break 'session,
+                // Frames of the other channel layout can't be compared.
+                'l' if channels == 1 => {}
+                key => if values.apply(key) {
+                    strategy = build_strategy(settings, &values, thumb.len());
+                    false_positives = FalsePositives::default();
+                }
+            }
+        }
+
+        let frame = match camera.next_frame() {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("\nError, {err}");
+                exit_code = EXIT_CAPTURE;
+                break;
+            }
+        };
+        let now = Instant::now();
+        let frame = match settings.deinterlace {
+            Some(mode) => {
+                mode.apply(frame, frame_width * layout.bytes_per_pixel(), &mut progressive);
+                progressive.as_slice()
+            }
+            None => frame,
+        };
+        thumb.downsample(frame, frame_width, settings.downsample, layout);
+        let compared = if values.luma && channels == 3 {
+            to_luma(&thumb, &mut luma_thumb);
+            &luma_thumb
+        } else {
+            &thumb
+        };
+        let score = strategy.process(compared).score;
+        false_positives.push(now, score > values.image_threshold);
+
+        let (rate, span) = false_positives.rate(now);
+        print!("\r\x1b[2K{} {:5.1}%  pixel {}%  image {}%  blur {}  luma {}  false positives {:.1}% of {}s",
+            bar(score, values.image_threshold), score,
+            values.pixel_threshold, values.image_threshold, values.blur,
+            if values.luma { "on" } else { "off" },
+            rate, span.as_secs());
+        let _ = std::io::stdout().flush();
+    }
+    drop(terminal);
+
+    println!("\n\nFinal values:\n    {}", values.flags());
+    if values.luma && channels == 3 {
+        println!("Luma was compared, the detector compares RGB for this camera.");
+    }
+    exit_code
+}
+
+
+// The strategy the detector would use with these values, starting from a fresh reference.
+fn build_strategy(settings: &Settings, values: &Values, thumb_len: usize) -> Box<dyn DiffStrategy> {
+    let pixel_threshold = ((values.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    let pixel_count_threshold = (thumb_len as f32 * values.image_threshold / 100.0) as i32;
+    let mut strategy = diff::from_name(&settings.algorithm, pixel_threshold, pixel_count_threshold, settings.adaptive_threshold(), settings.edge_level(), settings.channels)
+        .expect("Algorithm names are validated with the settings");
+    if let Some(mode) = settings.normalize {
+        strategy = Box::new(Normalize::new(strategy, mode));
+    }
+    if settings.flicker_rejection {
+        strategy = Box::new(FlickerRejection::new(strategy, pixel_threshold));
+    }
+    if values.blur > 0 {
+        strategy = Box::new(Blur::new(strategy, values.blur));
+    }
+    if let Some(mask) = &settings.mask {
+        strategy = Box::new(Masked::new(strategy, mask.clone()));
+    }
+    strategy
+}
+
+
+// BT.601 luma, in fixed point.
+fn to_luma(rgb: &Thumbnail, luma: &mut Thumbnail) {
+    for (value, pixel) in luma.pixels.iter_mut().zip(rgb.pixels.chunks_exact(3)) {
+        *value = ((pixel[0] as u32 * 77 + pixel[1] as u32 * 150 + pixel[2] as u32 * 29) >> 8) as u8;
+    }
+}
+
+
+// "[#####     |          ]", the threshold in the middle, '>' past the end.
+fn bar(score: f32, image_threshold: f32) -> String {
+    let full_scale = (image_threshold * 2.0).max(1.0);
+    let filled = ((score / full_scale * BAR_WIDTH as f32) as usize).min(BAR_WIDTH);
+    let mut bar: String = (0 .. BAR_WIDTH).map(|column| match column {
+        _ if column < filled => '#',
+        _ if column == BAR_WIDTH / 2 => '|',
+        _ => ' ',
+    }).collect();
+    if score > full_scale {
+        bar.pop();
+        bar.push('>');
+    }
+    format!("[{bar}]")
+}
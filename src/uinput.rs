@@ -0,0 +1,120 @@
+use std::{
+    fs::{ File, OpenOptions },
+    io::{ self, Write },
+    os::{ fd::AsRawFd, raw::c_long },
+};
+
+use crate::settings::InputEvent;
+
+const DEVICE_PATH: &str = "/dev/uinput";
+const DEVICE_NAME: &[u8] = b"motion-detect";
+
+mod ffi {
+    use std::os::raw::{ c_int, c_ulong };
+
+    // ioctl requests from linux/uinput.h, _IOW('U', nr, int) for the SET ones.
+    pub const UI_DEV_CREATE: c_ulong = 0x5501;
+    pub const UI_DEV_DESTROY: c_ulong = 0x5502;
+    pub const UI_SET_EVBIT: c_ulong = 0x4004_5564;
+    pub const UI_SET_KEYBIT: c_ulong = 0x4004_5565;
+    pub const UI_SET_SWBIT: c_ulong = 0x4004_556d;
+
+    // Event types and codes from linux/input-event-codes.h
+    pub const EV_SYN: u16 = 0x00;
+    pub const EV_KEY: u16 = 0x01;
+    pub const EV_SW: u16 = 0x05;
+    pub const SYN_REPORT: u16 = 0;
+    pub const BUS_VIRTUAL: u16 = 0x06;
+
+    extern "C" {
+        pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+}
+
+
+/// A virtual input device that looks like a presence sensor to software listening to input
+/// devices: the configured key is held, or the switch is on, while a movement is active.
+/// The kernel removes the device when it's dropped, or when the process exits.
+pub struct VirtualDevice {
+    file: File,
+    event: InputEvent,
+}
+
+
+impl VirtualDevice {
+
+    pub fn create(event: InputEvent) -> Result<Self, String> {
+        let file = OpenOptions::new().write(true).open(DEVICE_PATH).map_err(|err| match err.kind() {
+            io::ErrorKind::PermissionDenied => format!(
+                "no permission to open {DEVICE_PATH}, add a udev rule giving this user access to it or run as root"
+            ),
+            io::ErrorKind::NotFound => format!("{DEVICE_PATH} doesn't exist, is the uinput kernel module loaded?"),
+            _ => format!("can't open {DEVICE_PATH}: {err}"),
+        })?;
+
+        let (event_type, set_code_bit) = match event {
+            InputEvent::Key(_) => (ffi::EV_KEY, ffi::UI_SET_KEYBIT),
+            InputEvent::Switch(_) => (ffi::EV_SW, ffi::UI_SET_SWBIT),
+        };
+        let fd = file.as_raw_fd();
+        ioctl(fd, ffi::UI_SET_EVBIT, event_type as i32)?;
+        ioctl(fd, set_code_bit, event.code() as i32)?;
+
+        // Legacy struct uinput_user_dev: name, input_id, ff_effects_max, then the four absolute axis arrays.
+        let mut setup = Vec::with_capacity(1116);
+        setup.extend_from_slice(DEVICE_NAME);
+        setup.resize(80, 0);
+        for value in [ffi::BUS_VIRTUAL, 0, 0, 1] {
+            setup.extend_from_slice(&value.to_ne_bytes());
+        }
+        setup.resize(1116, 0);
+        (&file).write_all(&setup).map_err(|err| format!("can't set up the virtual device: {err}"))?;
+        ioctl(fd, ffi::UI_DEV_CREATE, 0)?;
+
+        Ok(Self { file, event })
+    }
+
+    pub fn motion_started(&mut self) -> io::Result<()> {
+        self.emit(1)
+    }
+
+    pub fn motion_stopped(&mut self) -> io::Result<()> {
+        self.emit(0)
+    }
+
+    fn emit(&mut self, value: i32) -> io::Result<()> {
+        let event_type = match self.event {
+            InputEvent::Key(_) => ffi::EV_KEY,
+            InputEvent::Switch(_) => ffi::EV_SW,
+        };
+        let mut data = Vec::with_capacity(48);
+        write_event(&mut data, event_type, self.event.code(), value);
+        write_event(&mut data, ffi::EV_SYN, ffi::SYN_REPORT, 0);
+        self.file.write_all(&data)
+    }
+}
+
+
+impl Drop for VirtualDevice {
+    fn drop(&mut self) {
+        let _ = ioctl(self.file.as_raw_fd(), ffi::UI_DEV_DESTROY, 0);
+    }
+}
+
+
+// struct input_event. The kernel stamps events itself, so the timeval stays zero.
+fn write_event(data: &mut Vec<u8>, event_type: u16, code: u16, value: i32) {
+    data.extend_from_slice(&(0 as c_long).to_ne_bytes());
+    data.extend_from_slice(&(0 as c_long).to_ne_bytes());
+    data.extend_from_slice(&event_type.to_ne_bytes());
+    data.extend_from_slice(&code.to_ne_bytes());
+    data.extend_from_slice(&value.to_ne_bytes());
+}
+
+
+fn ioctl(fd: i32, request: std::os::raw::c_ulong, argument: i32) -> Result<(), String> {
+    match unsafe { ffi::ioctl(fd, request, argument) } {
+        -1 => Err(format!("uinput ioctl failed: {}", io::Error::last_os_error())),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,459 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{ Path, PathBuf },
+    process::{ Command, Stdio },
+    sync::{ atomic::{ AtomicBool, Ordering }, mpsc::{ self, Receiver, TryRecvError }, Arc },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use crate::{ json, motion::MotionEvent };
+
+// How often a running command is checked for its exit.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+// Movements already decided that are kept, for the events they still have to come.
+const KEPT_DECIDED: usize = 2;
+
+
+/// What becomes of a movement the command gave no verdict on, timed out or crashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutAction {
+    Accept,     // Reported as if confirmed.
+    Reject,     // Suppressed as if rejected.
+}
+
+
+impl TimeoutAction {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "accept" => Ok(TimeoutAction::Accept),
+            "reject" => Ok(TimeoutAction::Reject),
+            _ => Err(format!("Invalid verify timeout action '{text}', use accept or reject")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeoutAction::Accept => "accept",
+            TimeoutAction::Reject => "reject",
+        }
+    }
+}
+
+
+/// How the verification of a movement ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Confirmed,  // The command exited with 0.
+    Rejected,   // With any other code up to 125.
+    TimedOut,   // Killed at the timeout.
+    Failed,     // Couldn't run, or killed by a signal. Codes from 126 are those of the shell for it.
+}
+
+
+impl Verdict {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Confirmed => "confirmed",
+            Verdict::Rejected => "rejected",
+            Verdict::TimedOut => "timed_out",
+            Verdict::Failed => "failed",
+        }
+    }
+}
+
+
+/// Verification counters, since launch.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyStats {
+    pub confirmed: u64,
+    pub rejected: u64,
+    pub timed_out: u64,
+    pub failed: u64,
+    pub suppressed: u64,        // Movements not reported, rejected or per the timeout action.
+}
+
+
+impl VerifyStats {
+
+    pub fn to_object(&self) -> json::Object {
+        json::Object::new()
+            .field("confirmed_total", self.confirmed)
+            .field("rejected_total", self.rejected)
+            .field("timed_out_total", self.timed_out)
+            .field("failed_total", self.failed)
+            .field("suppressed_total", self.suppressed)
+    }
+}
+
+
+// The command's answer, from its thread.
+struct Outcome {
+    verdict: Verdict,
+    warning: Option<String>,
+}
+
+
+enum Check {
+    Running { outcome: Receiver<Outcome>, abandon: Arc<AtomicBool>, frame: PathBuf },
+    // None if the movement was cancelled before its verdict.
+    Decided { verdict: Option<Verdict>, reported: bool },
+}
+
+
+struct Movement {
+    ids: Vec<u64>,              // The movement, then those continuing it past the maximum duration.
+    provisional: bool,          // Its Provisional was reported before the verdict.
+    check: Check,
+}
+
+
+struct Queued {
+    event: MotionEvent,
+    movement: Option<u64>,      // Reported once this movement is decided, right away if None.
+    verdict: Option<Verdict>,
+}
+
+
+/// Hands each candidate movement to an external command, a person or object detector for
+/// instance, and only reports the movements it confirms.
+///
+/// On the first event of a movement, its Provisional or, without confirmation frames, its
+/// Start, the latest full frame is written to a temporary file and `sh -c` runs the command with
+/// the file's path as its last argument, and MOTION_ID in its environment. Exit code 0 confirms
+/// the movement, any other below 126 rejects it. Past the timeout the command is killed, and the
+/// timeout action decides, as it does when the command can't run or crashes. The command runs on
+/// a thread of its own: meanwhile the movement is tracked as usual but its Start and Stop are
+/// held, with the times they had, and any later event behind them to keep their order. A
+/// Provisional is reported right away, and a rejection reported as its cancel.
+pub struct Verifier {
+    command: String,
+    timeout: Duration,
+    timeout_action: TimeoutAction,
+    movements: Vec<Movement>,
+    queue: VecDeque<Queued>,
+    stats: VerifyStats,
+    warnings: Vec<String>,
+}
+
+
+impl Verifier {
+
+    pub fn new(command: String, timeout: Duration, timeout_action: TimeoutAction) -> Self {
+        Self {
+            command,
+            timeout,
+            timeout_action,
+            movements: Vec::new(),
+            queue: VecDeque::new(),
+            stats: VerifyStats::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Takes the events of the tracker, called for every frame, and returns those to report now
+    /// with the verdict of their movement's Start. `snapshot` saves the latest full frame at a
+    /// path, the image extension added, for the command to check.
+    pub fn pass(&mut self, events: Vec<MotionEvent>, mut snapshot: impl FnMut(PathBuf) -> Result<PathBuf, String>) -> Vec<(MotionEvent, Option<Verdict>)> {
+        for event in events {
+            self.hold(event, &mut snapshot);
+        }
+        self.collect();
+        self.release()
+    }
+
+    pub fn stats(&self) -> VerifyStats {
+        self.stats
+    }
+
+    /// Kills the commands still running on shutdown, their movements are never reported, and
+    /// removes their frames.
+    pub fn finish(self) {
+        for movement in self.movements {
+            if let Check::Running { abandon, frame, .. } = movement.check {
+                abandon.store(true, Ordering::Relaxed);
+                let _ = fs::remove_file(frame);
+            }
+        }
+    }
+
+    /// What went wrong running the command since the last call, to print as warnings.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn hold(&mut self, event: MotionEvent, snapshot: &mut impl FnMut(PathBuf) -> Result<PathBuf, String>) {
+        let movement = match event {
+            MotionEvent::Provisional { id } => {
+                self.begin(id, true, snapshot);
+                None
+            }
+            // A continuation is decided along with the movement it continues.
+            MotionEvent::Start { id, continued_from, .. } => {
+                match continued_from.and_then(|previous| self.index(previous)) {
+                    Some(index) => self.movements[index].ids.push(id),
+                    None if self.index(id).is_none() => self.begin(id, false, snapshot),
+                    None => {}
+                }
+                Some(id)
+            }
+            MotionEvent::ProvisionalCancel { id } => {
+                // Nothing to verify anymore, the cancel follows the Provisional already reported.
+                if let Some(movement) = self.index(id).map(|index| &mut self.movements[index]) {
+                    if let Check::Running { abandon, .. } = &movement.check {
+                        abandon.store(true, Ordering::Relaxed);
+                        movement.check = Check::Decided { verdict: None, reported: true };
+                    }
+                }
+                Some(id)
+            }
+            MotionEvent::Stop { id, .. } => Some(id),
+        };
+        self.queue.push_back(Queued { event, movement, verdict: None });
+    }
+
+    // Saves the frame and starts the command for a new movement.
+    fn begin(&mut self, id: u64, provisional: bool, snapshot: &mut impl FnMut(PathBuf) -> Result<PathBuf, String>) {
+        let path = std::env::temp_dir().join(format!("motion-detect-verify-{}-{id}", std::process::id()));
+        let check = match snapshot(path) {
+            Ok(path) => {
+                let (sender, outcome) = mpsc::channel();
+                let abandon = Arc::new(AtomicBool::new(false));
+                let (command, timeout, thread_abandon, frame) = (self.command.clone(), self.timeout, abandon.clone(), path.clone());
+                thread::spawn(move || {
+                    let result = verify(&command, id, &path, timeout, &thread_abandon);
+                    let _ = fs::remove_file(&path);
+                    let _ = sender.send(result);
+                });
+                Check::Running { outcome, abandon, frame }
+            }
+            Err(err) => self.decide(Outcome {
+                verdict: Verdict::Failed,
+                warning: Some(format!("can't save the frame of movement {id} to verify: {err}")),
+            }),
+        };
+        self.movements.push(Movement { ids: vec![id], provisional, check });
+    }
+
+    // Counts a verdict and decides whether the movement is reported.
+    fn decide(&mut self, outcome: Outcome) -> Check {
+        let reported = match outcome.verdict {
+            Verdict::Confirmed => true,
+            Verdict::Rejected => false,
+            Verdict::TimedOut | Verdict::Failed => self.timeout_action == TimeoutAction::Accept,
+        };
+        let counter = match outcome.verdict {
+            Verdict::Confirmed => &mut self.stats.confirmed,
+            Verdict::Rejected => &mut self.stats.rejected,
+            Verdict::TimedOut => &mut self.stats.timed_out,
+            Verdict::Failed => &mut self.stats.failed,
+        };
+        *counter += 1;
+        self.stats.suppressed += !reported as u64;
+        self.warnings.extend(outcome.warning);
+        Check::Decided { verdict: Some(outcome.verdict), reported }
+    }
+
+    // Picks up the verdicts that came. A rejected movement whose Provisional was reported is
+    // cancelled, in place of its first held event or after the others.
+    fn collect(&mut self) {
+        for index in 0 .. self.movements.len() {
+            let Check::Running { outcome, .. } = &self.movements[index].check else {
+                continue;
+            };
+            let outcome = match outcome.try_recv() {
+                Ok(outcome) => outcome,
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => Outcome {
+                    verdict: Verdict::Failed,
+                    warning: Some(String::from("the verify command's thread panicked")),
+                },
+            };
+            let check = self.decide(outcome);
+            let movement = &mut self.movements[index];
+            movement.check = check;
+            if let (Check::Decided { verdict, reported: false }, true) = (&movement.check, movement.provisional) {
+                let id = movement.ids[0];
+                let cancel = Queued { event: MotionEvent::ProvisionalCancel { id }, movement: None, verdict: *verdict };
+                match self.queue.iter_mut().find(|queued| queued.movement.is_some_and(|queued| movement.ids.contains(&queued))) {
+                    Some(first) => *first = cancel,
+                    None => self.queue.push_back(cancel),
+                }
+            }
+        }
+    }
+
+    // Returns the events from the front of the queue up to the first of a movement being verified.
+    fn release(&mut self) -> Vec<(MotionEvent, Option<Verdict>)> {
+        let mut released = Vec::new();
+        while let Some(queued) = self.queue.front() {
+            let decided = match queued.movement.and_then(|id| self.index(id)) {
+                Some(index) => match self.movements[index].check {
+                    Check::Running { .. } => break,
+                    Check::Decided { verdict, reported } => Some((verdict, reported)),
+                },
+                None => None,
+            };
+            let queued = self.queue.pop_front().unwrap();
+            match (decided, queued.event) {
+                (None, event) => released.push((event, queued.verdict)),
+                (Some((_, false)), _) => {}
+                (Some((verdict, true)), event @ MotionEvent::Start { continued_from: None, .. }) => released.push((event, verdict)),
+                (Some((_, true)), event) => released.push((event, None)),
+            }
+        }
+        // Still running, still queued for, or among the latest.
+        let queue = &self.queue;
+        let keep_from = self.movements.len().saturating_sub(KEPT_DECIDED);
+        let mut index = 0;
+        self.movements.retain(|movement| {
+            index += 1;
+            index > keep_from
+                || matches!(movement.check, Check::Running { .. })
+                || queue.iter().any(|queued| queued.movement.is_some_and(|id| movement.ids.contains(&id)))
+        });
+        released
+    }
+
+    fn index(&self, id: u64) -> Option<usize> {
+        self.movements.iter().position(|movement| movement.ids.contains(&id))
+    }
+}
+
+
+// Runs the command on a saved frame until it exits, times out or the movement is abandoned.
+fn verify(command: &str, id: u64, path: &Path, timeout: Duration, abandon: &AtomicBool) -> Outcome {
+    let child = Command::new("sh")
+        .args(["-c", &format!("{command} \"$@\""), "sh"])
+        .arg(path)
+        .env("MOTION_ID", id.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => return Outcome { verdict: Verdict::Failed, warning: Some(format!("can't run the verify command: {err}")) },
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return match status.code() {
+                Some(0) => Outcome { verdict: Verdict::Confirmed, warning: None },
+                // The shell's own, it couldn't run the command or the command was killed by a signal.
+                Some(126 ..) | None => Outcome { verdict: Verdict::Failed, warning: Some(format!("the verify command of movement {id} failed: {status}")) },
+                Some(_) => Outcome { verdict: Verdict::Rejected, warning: None },
+            },
+            Ok(None) if abandon.load(Ordering::Relaxed) || Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Outcome {
+                    verdict: Verdict::TimedOut,
+                    warning: Some(format!("the verify command of movement {id} timed out after {timeout:.1?}")),
+                };
+            }
+            Ok(None) => thread::sleep(WAIT_INTERVAL),
+            Err(err) => {
+                let _ = child.kill();
+                return Outcome { verdict: Verdict::Failed, warning: Some(format!("can't wait for the verify command: {err}")) };
+            }
+        }
+    }
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::motion::{ MotionStats, StopReason };
+
+    // Saves an image of a single black pixel where the verifier asks.
+    fn save(path: PathBuf) -> Result<PathBuf, String> {
+        let path = path.with_extension("ppm");
+        fs::write(&path, b"P6\n1 1\n255\n\0\0\0").map(|()| path).map_err(|err| err.to_string())
+    }
+
+
+    // A movement's Start at `origin`, and its Stop a second later.
+    fn movement(id: u64, origin: Instant) -> Vec<MotionEvent> {
+        vec![
+            MotionEvent::Start { id, continued_from: None, provisional: false, pre_existing: false, at: origin, between: None },
+            MotionEvent::Stop { id, reason: StopReason::Tail, duration: Duration::from_secs(1), at: origin + Duration::from_secs(1), stats: MotionStats::default(), between: None },
+        ]
+    }
+
+
+    // Passes the events, then empty frames until no command is running anymore.
+    fn pass(verifier: &mut Verifier, events: Vec<MotionEvent>) -> Vec<(MotionEvent, Option<Verdict>)> {
+        let mut released = verifier.pass(events, save);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while verifier.movements.iter().any(|movement| matches!(movement.check, Check::Running { .. })) {
+            assert!(Instant::now() < deadline, "The command never ended");
+            thread::sleep(WAIT_INTERVAL);
+            released.extend(verifier.pass(Vec::new(), save));
+        }
+        released
+    }
+
+
+    #[test]
+    fn a_confirmed_movement_is_reported_with_its_original_times() {
+        // The command is given the frame, and the movement's id.
+        let command = "sh -c 'sleep 0.2; test -f \"$1\" && test \"$MOTION_ID\" = 1' stub";
+        let mut verifier = Verifier::new(command.to_string(), Duration::from_secs(5), TimeoutAction::Reject);
+        let origin = Instant::now();
+        assert!(verifier.pass(movement(1, origin), save).is_empty(), "Held until the verdict");
+        let released = pass(&mut verifier, Vec::new());
+        assert!(matches!(released[..], [
+            (MotionEvent::Start { id: 1, at, .. }, Some(Verdict::Confirmed)),
+            (MotionEvent::Stop { id: 1, .. }, None),
+        ] if at == origin), "{released:?}");
+        assert_eq!((verifier.stats().confirmed, verifier.stats().suppressed), (1, 0));
+        // The frame is removed once checked.
+        assert!(!std::env::temp_dir().join(format!("motion-detect-verify-{}-1.ppm", std::process::id())).exists());
+    }
+
+
+    #[test]
+    fn a_rejected_movement_is_suppressed_or_cancelled() {
+        let command = "sh -c 'sleep 0.2; exit 3' stub";
+        let mut verifier = Verifier::new(command.to_string(), Duration::from_secs(5), TimeoutAction::Accept);
+        assert!(pass(&mut verifier, movement(2, Instant::now())).is_empty());
+        // A Provisional already reported is cancelled.
+        let mut events = vec![MotionEvent::Provisional { id: 3 }];
+        events.extend(movement(3, Instant::now()));
+        let released = pass(&mut verifier, events);
+        assert!(matches!(released[..], [
+            (MotionEvent::Provisional { id: 3 }, None),
+            (MotionEvent::ProvisionalCancel { id: 3 }, Some(Verdict::Rejected)),
+        ]), "{released:?}");
+        assert_eq!((verifier.stats().rejected, verifier.stats().suppressed), (2, 2));
+        assert!(verifier.take_warnings().is_empty());
+    }
+
+
+    #[test]
+    fn a_command_past_the_timeout_is_killed_and_the_action_decides() {
+        for (id, action, reported) in [(4, TimeoutAction::Reject, 0), (5, TimeoutAction::Accept, 2)] {
+            let mut verifier = Verifier::new(String::from("sh -c 'sleep 5' stub"), Duration::from_millis(200), action);
+            let started = Instant::now();
+            let released = pass(&mut verifier, movement(id, Instant::now()));
+            assert!(started.elapsed() < Duration::from_secs(2), "Killed at the timeout");
+            assert_eq!(released.len(), reported, "{released:?}");
+            assert_eq!(verifier.stats().timed_out, 1);
+            assert_eq!(verifier.take_warnings(), [format!("the verify command of movement {id} timed out after 200.0ms")]);
+        }
+    }
+
+
+    #[test]
+    fn a_crashed_command_is_a_failure_the_action_decides() {
+        let mut verifier = Verifier::new(String::from("sh -c 'kill -9 $$' stub"), Duration::from_secs(5), TimeoutAction::Accept);
+        let released = pass(&mut verifier, movement(6, Instant::now()));
+        assert!(matches!(released[..], [(MotionEvent::Start { id: 6, .. }, Some(Verdict::Failed)), (MotionEvent::Stop { .. }, None)]), "{released:?}");
+        assert_eq!((verifier.stats().failed, verifier.stats().suppressed), (1, 0));
+        assert_eq!(verifier.take_warnings().len(), 1);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    collections::VecDeque,
+    io::{ self, Read, Write },
+    net::{ TcpListener, TcpStream },
+    sync::{ Arc, Condvar, Mutex, atomic::{ AtomicU64, Ordering } },
+    thread,
+};
+#[cfg(unix)]
+use std::{ os::unix::net::{ UnixListener, UnixStream }, path::PathBuf };
+
+use crate::bus::{ Event, Sink, SinkError };
+
+// ZMTP 3.0 with the NULL mechanism, what libzmq and its bindings speak to any ZeroMQ socket. Later
+// versions fall back to it, subscriptions then arrive as messages rather than commands.
+const SIGNATURE: [u8; 10] = [0xff, 0, 0, 0, 0, 0, 0, 0, 1, 0x7f];
+const VERSION: [u8; 2] = [3, 0];
+const GREETING_LEN: usize = 64;
+
+// Frame flags.
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+// Nothing a subscriber sends is larger: commands and subscriptions to topics...
+const MAX_SUBSCRIBER_FRAME: u64 = 64 * 1024;
+
+// ...nor any message of a publisher.
+const MAX_PUBLISHER_FRAME: u64 = 16 * 1024 * 1024;
+
+
+/// Where the publisher binds or a subscriber connects: "tcp://*:5556", "tcp://127.0.0.1:5556" or,
+/// on unix, "ipc:///run/motion-detect.sock".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),        // host:port, "*" being any address.
+    #[cfg(unix)]
+    Ipc(PathBuf),
+}
+
+
+impl Endpoint {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if let Some(address) = text.strip_prefix("tcp://") {
+            let (host, port) = address.rsplit_once(':').ok_or_else(|| format!("Invalid endpoint '{text}', expected tcp://host:port"))?;
+            port.parse::<u16>().map_err(|_| format!("Invalid port in endpoint '{text}'"))?;
+            let host = if host == "*" { "0.0.0.0" } else { host };
+            return Ok(Endpoint::Tcp(format!("{host}:{port}")));
+        }
+        #[cfg(unix)]
+        if let Some(path) = text.strip_prefix("ipc://").filter(|path| !path.is_empty()) {
+            return Ok(Endpoint::Ipc(PathBuf::from(path)));
+        }
+        Err(format!("Invalid endpoint '{text}', use tcp://host:port or ipc://path"))
+    }
+}
+
+
+/// A stream a peer is connected over.
+trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+
+impl Connection for TcpStream {
+
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+}
+
+
+/// A subscriber connected to the publisher: its subscriptions, and the messages waiting for it.
+struct Peer {
+    subscriptions: Mutex<Vec<Vec<u8>>>,     // Topic prefixes, repeated as often as subscribed.
+    queue: Mutex<PeerQueue>,
+    wake: Condvar,
+}
+
+
+#[derive(Default)]
+struct PeerQueue {
+    messages: VecDeque<Arc<(Vec<u8>, Vec<u8>)>>,   // Topic and payload.
+    closed: bool,
+}
+
+
+struct Shared {
+    peers: Mutex<Vec<Arc<Peer>>>,
+    high_water_mark: usize,
+    dropped: AtomicU64,
+}
+
+
+/// A ZeroMQ PUB socket, without libzmq: each message is a topic frame then a JSON payload frame,
+/// topics being "motion.<instance>.<type>" so subscribers filter by prefix, e.g. "motion.garden."
+/// for every event of one instance. Like libzmq's PUB, it filters by the subscribers'
+/// subscriptions before sending, and never blocks: each subscriber has a queue of up to the high
+/// water mark of messages, which a thread of its own writes out, and messages for a subscriber
+/// whose queue is full are dropped and counted.
+#[derive(Clone)]
+pub struct Publisher {
+    instance: String,
+    shared: Arc<Shared>,
+}
+
+
+impl Publisher {
+
+    /// Binds the endpoint. Failing that is a configuration error, reported right away. For ipc,
+    /// a file left at the path by a previous run is replaced.
+    pub fn bind(endpoint: &str, instance: &str, high_water_mark: usize) -> Result<Self, String> {
+        let shared = Arc::new(Shared { peers: Mutex::new(Vec::new()), high_water_mark, dropped: AtomicU64::new(0) });
+        match Endpoint::parse(endpoint)? {
+            Endpoint::Tcp(address) => {
+                let listener = TcpListener::bind(&address).map_err(|err| format!("Can't bind {endpoint}: {err}"))?;
+                let accept_shared = shared.clone();
+                thread::spawn(move || for stream in listener.incoming().flatten() {
+                    let _ = stream.set_nodelay(true);
+                    accept(stream, accept_shared.clone());
+                });
+            }
+            #[cfg(unix)]
+            Endpoint::Ipc(path) => {
+                if std::fs::symlink_metadata(&path).is_ok_and(|metadata| {
+                    use std::os::unix::fs::FileTypeExt;
+                    metadata.file_type().is_socket()
+                }) {
+                    let _ = std::fs::remove_file(&path);
+                }
+                let listener = UnixListener::bind(&path).map_err(|err| format!("Can't bind {endpoint}: {err}"))?;
+                let accept_shared = shared.clone();
+                thread::spawn(move || for stream in listener.incoming().flatten() {
+                    accept(stream, accept_shared.clone());
+                });
+            }
+        }
+        // Dots separate the topic's parts, whatever the instance is named.
+        let instance = instance.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' }).collect();
+        Ok(Self { instance, shared })
+    }
+
+    /// The topic of a message of the given type.
+    pub fn topic(&self, event_type: &str) -> String {
+        format!("motion.{}.{event_type}", self.instance)
+    }
+
+    /// Queues a JSON message for every subscriber of its topic. Its type is read from its "type"
+    /// field, which every message starts with.
+    pub fn publish(&self, json: &str) {
+        let event_type = json.strip_prefix("{\"type\":\"")
+            .and_then(|rest| rest.split_once('"'))
+            .map_or("event", |(event_type, _)| event_type);
+        let topic = self.topic(event_type).into_bytes();
+        let peers = self.shared.peers.lock().unwrap();
+        let mut message = None;
+        for peer in peers.iter() {
+            if !peer.subscriptions.lock().unwrap().iter().any(|prefix| topic.starts_with(prefix)) {
+                continue;
+            }
+            let mut queue = peer.queue.lock().unwrap();
+            if queue.messages.len() >= self.shared.high_water_mark {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let message = message.get_or_insert_with(|| Arc::new((topic.clone(), json.as_bytes().to_vec())));
+            queue.messages.push_back(message.clone());
+            peer.wake.notify_one();
+        }
+    }
+
+    /// Messages dropped because a subscriber's queue was at the high water mark.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Connected subscribers.
+    pub fn subscribers(&self) -> usize {
+        self.shared.peers.lock().unwrap().len()
+    }
+}
+
+
+/// Every message of the event stream goes to the subscribers, except events held for quiet hours.
+impl Sink for Publisher {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::Message { json, .. } | Event::Motion { json, notify: true, .. } => self.publish(json),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+
+// Each connection gets a thread for the handshake and what the peer sends, and one writing out
+// its queue once it's a subscriber.
+fn accept<S: Connection>(stream: S, shared: Arc<Shared>) {
+    thread::spawn(move || {
+        let _ = serve(stream, shared);
+    });
+}
+
+
+fn serve<S: Connection>(mut stream: S, shared: Arc<Shared>) -> io::Result<()> {
+    handshake(&mut stream, "PUB", &["SUB", "XSUB"])?;
+    let peer = Arc::new(Peer { subscriptions: Mutex::new(Vec::new()), queue: Mutex::default(), wake: Condvar::new() });
+    shared.peers.lock().unwrap().push(peer.clone());
+
+    let mut writer = stream.try_clone()?;
+    let writer_peer = peer.clone();
+    thread::spawn(move || {
+        loop {
+            let message = {
+                let mut queue = writer_peer.queue.lock().unwrap();
+                loop {
+                    if queue.closed {
+                        return;
+                    }
+                    if let Some(message) = queue.messages.pop_front() {
+                        break message;
+                    }
+                    queue = writer_peer.wake.wait(queue).unwrap();
+                }
+            };
+            let (topic, payload) = &*message;
+            if write_frame(&mut writer, MORE, topic).and_then(|()| write_frame(&mut writer, 0, payload)).is_err() {
+                return;
+            }
+        }
+    });
+
+    // Subscriptions, until the peer goes away.
+    let result = loop {
+        let (flags, body) = match read_frame(&mut stream, MAX_SUBSCRIBER_FRAME) {
+            Ok(frame) => frame,
+            Err(err) => break Err(err),
+        };
+        let change = if flags & COMMAND != 0 {
+            match command(&body) {
+                Some(("SUBSCRIBE", topic)) => Some((true, topic)),
+                Some(("CANCEL", topic)) => Some((false, topic)),
+                _ => None,
+            }
+        } else {
+            match body.split_first() {
+                Some((1, topic)) => Some((true, topic)),
+                Some((0, topic)) => Some((false, topic)),
+                _ => None,
+            }
+        };
+        let mut subscriptions = peer.subscriptions.lock().unwrap();
+        match change {
+            Some((true, topic)) => subscriptions.push(topic.to_vec()),
+            Some((false, topic)) => if let Some(index) = subscriptions.iter().position(|prefix| prefix == topic) {
+                subscriptions.remove(index);
+            },
+            None => {}
+        }
+    };
+    shared.peers.lock().unwrap().retain(|other| !Arc::ptr_eq(other, &peer));
+    peer.queue.lock().unwrap().closed = true;
+    peer.wake.notify_one();
+    result
+}
+
+
+/// A ZeroMQ SUB socket connected to a publisher, for consumers written in Rust and for checking
+/// what `Publisher` sends.
+pub struct Subscriber {
+    stream: Box<dyn ReadWrite>,
+}
+
+
+trait ReadWrite: Read + Write + Send {}
+impl<S: Read + Write + Send> ReadWrite for S {}
+
+
+impl Subscriber {
+
+    pub fn connect(endpoint: &str) -> Result<Self, String> {
+        let mut stream: Box<dyn ReadWrite> = match Endpoint::parse(endpoint)? {
+            Endpoint::Tcp(address) => Box::new(TcpStream::connect(address.replace("0.0.0.0", "127.0.0.1")).map_err(|err| format!("Can't connect to {endpoint}: {err}"))?),
+            #[cfg(unix)]
+            Endpoint::Ipc(path) => Box::new(UnixStream::connect(path).map_err(|err| format!("Can't connect to {endpoint}: {err}"))?),
+        };
+        handshake(&mut stream, "SUB", &["PUB", "XPUB"]).map_err(|err| format!("Handshake with {endpoint} failed: {err}"))?;
+        Ok(Self { stream })
+    }
+
+    /// Receives the messages whose topic starts with `prefix`, all of them for an empty one.
+    pub fn subscribe(&mut self, prefix: &str) -> Result<(), String> {
+        let mut body = vec![1];
+        body.extend_from_slice(prefix.as_bytes());
+        write_frame(&mut self.stream, 0, &body).map_err(|err| err.to_string())
+    }
+
+    /// Waits for the next message, returns its topic and payload.
+    pub fn receive(&mut self) -> Result<(String, String), String> {
+        let mut parts = Vec::new();
+        loop {
+            let (flags, body) = read_frame(&mut self.stream, MAX_PUBLISHER_FRAME).map_err(|err| err.to_string())?;
+            if flags & COMMAND != 0 {
+                continue;
+            }
+            parts.push(String::from_utf8_lossy(&body).into_owned());
+            if flags & MORE == 0 {
+                break;
+            }
+        }
+        let payload = parts.pop().unwrap_or_default();
+        Ok((parts.into_iter().next().unwrap_or_default(), payload))
+    }
+}
+
+
+// Greetings both ways, then READY commands naming the socket types.
+fn handshake(stream: &mut (impl Read + Write), socket_type: &str, peer_types: &[&str]) -> io::Result<()> {
+    let mut greeting = [0u8; GREETING_LEN];
+    greeting[.. 10].copy_from_slice(&SIGNATURE);
+    greeting[10 .. 12].copy_from_slice(&VERSION);
+    greeting[12 .. 16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+    let mut peer = [0u8; GREETING_LEN];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 || &peer[12 .. 16] != b"NULL" || peer[16 .. 32].iter().any(|byte| *byte != 0) {
+        return Err(invalid("not a ZMTP 3 peer with the NULL mechanism"));
+    }
+
+    let mut ready = vec![5];
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    ready.extend_from_slice(socket_type.as_bytes());
+    write_frame(stream, COMMAND, &ready)?;
+
+    let (flags, body) = read_frame(stream, MAX_SUBSCRIBER_FRAME)?;
+    let Some(("READY", mut properties)) = command(&body).filter(|_| flags & COMMAND != 0) else {
+        return Err(invalid("expected a READY command"));
+    };
+    // Name length, name, value length (big endian u32) and value, as often as there are properties.
+    while let Some((&name_len, rest)) = properties.split_first() {
+        let name = rest.get(.. name_len as usize).ok_or_else(|| invalid("truncated property"))?;
+        let rest = &rest[name_len as usize ..];
+        let value_len = rest.get(.. 4).map(|bytes| u32::from_be_bytes(bytes.try_into().expect("4 bytes")) as usize).ok_or_else(|| invalid("truncated property"))?;
+        let value = rest.get(4 .. 4 + value_len).ok_or_else(|| invalid("truncated property"))?;
+        if name.eq_ignore_ascii_case(b"Socket-Type") && !peer_types.iter().any(|peer_type| peer_type.as_bytes() == value) {
+            return Err(invalid(&format!("a {} socket can't talk to {socket_type}", String::from_utf8_lossy(value))));
+        }
+        properties = &rest[4 + value_len ..];
+    }
+    stream.flush()
+}
+
+
+fn write_frame(stream: &mut impl Write, flags: u8, body: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    if body.len() > 255 {
+        frame.push(flags | LONG);
+        frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        frame.push(flags);
+        frame.push(body.len() as u8);
+    }
+    frame.extend_from_slice(body);
+    stream.write_all(&frame)
+}
+
+
+fn read_frame(stream: &mut impl Read, limit: u64) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8];
+    stream.read_exact(&mut flags)?;
+    let size = if flags[0] & LONG != 0 {
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size)?;
+        u64::from_be_bytes(size)
+    } else {
+        let mut size = [0u8];
+        stream.read_exact(&mut size)?;
+        size[0] as u64
+    };
+    if size > limit {
+        return Err(invalid(&format!("frame of {size} bytes")));
+    }
+    let mut body = vec![0; size as usize];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+
+// The name and the data of a command frame's body.
+fn command(body: &[u8]) -> Option<(&str, &[u8])> {
+    let (&name_len, rest) = body.split_first()?;
+    let name = std::str::from_utf8(rest.get(.. name_len as usize)?).ok()?;
+    Some((name, &rest[name_len as usize ..]))
+}
+
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
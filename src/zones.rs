@@ -0,0 +1,569 @@
+use std::{ path::PathBuf, time::SystemTime };
+
+use crate::{ clock::TimeSource, json, schedule::Schedule };
+
+/// A named rectangle or polygon of the frame, stored as fractions of the frame size so it doesn't
+/// depend on the capture resolution or the downsample factor.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub name: String,
+    pub x: f32,                             // The rectangle, or the bounding box of the polygon.
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub polygon: Option<Vec<(f32, f32)>>,   // Vertices, in order. Never crosses itself.
+    pub snapshot: Option<ZoneSnapshot>,     // Saved when a movement starts in the zone.
+    pub schedule: Option<Schedule>,         // Armed only within, from --zone-schedule.
+}
+
+
+/// How a zone's own snapshot is taken.
+#[derive(Debug, Clone, Default)]
+pub struct ZoneSnapshot {
+    pub crop: bool,                 // Only the zone's rectangle, otherwise the whole frame.
+    pub padding: f32,               // Margin kept around the crop, as a fraction of the zone size.
+    pub directory: Option<PathBuf>, // Defaults to --snapshot-dir.
+    pub template: Option<String>,   // Defaults to --snapshot-template.
+}
+
+
+impl Zone {
+
+    /// Parses "name:x,y,width,height" with coordinates in percent of the frame, e.g. "door:0,0,30,100",
+    /// or "name:polygon=x1,y1,x2,y2,x3,y3..." with at least three vertices, e.g.
+    /// "driveway:polygon=0,100,45,40,60,40,35,100". Either can be followed by snapshot options:
+    /// ":crop" (to the polygon's bounding box), ":padding=<percent>", ":dir=<path>" and
+    /// ":template=<template>".
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid zone '{text}', expected name:x,y,width,height or name:polygon=x1,y1,x2,y2,x3,y3... in percent");
+        let mut parts = text.split(':');
+        let (Some(name), Some(rect)) = (parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let mut snapshot = None;
+        for option in parts {
+            let options: &mut ZoneSnapshot = snapshot.get_or_insert_with(|| ZoneSnapshot { padding: 0.1, ..Default::default() });
+            match option.split_once('=') {
+                None if option == "crop" => options.crop = true,
+                Some(("padding", padding)) => {
+                    let padding: f32 = padding.parse().ok().filter(|padding: &f32| *padding >= 0.0)
+                        .ok_or(format!("Invalid padding '{padding}' in zone '{text}'"))?;
+                    options.padding = padding / 100.0;
+                }
+                Some(("dir", directory)) => options.directory = Some(PathBuf::from(directory)),
+                Some(("template", template)) => options.template = Some(template.to_string()),
+                _ => return Err(format!("Invalid zone option '{option}' in '{text}', use crop, padding=<percent>, dir=<path> or template=<template>")),
+            }
+        }
+        let (polygon, coordinates) = match rect.strip_prefix("polygon=") {
+            Some(vertices) => (true, vertices),
+            None => (false, rect),
+        };
+        let values: Vec<f32> = coordinates.split(',')
+            .map(|value| value.trim().parse::<f32>().map(|value| value / 100.0))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        if polygon {
+            let vertices: Vec<(f32, f32)> = values.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect();
+            if values.len() != vertices.len() * 2 || vertices.len() < 3 {
+                return Err(format!("Invalid polygon in zone '{text}', expected at least three x,y vertices"));
+            }
+            if crosses_itself(&vertices) {
+                return Err(format!("Polygon of zone '{text}' crosses itself"));
+            }
+            if area(&vertices) == 0.0 {
+                return Err(format!("Polygon of zone '{text}' has no area"));
+            }
+            let (left, top) = vertices.iter().fold((f32::MAX, f32::MAX), |(x, y), vertex| (x.min(vertex.0), y.min(vertex.1)));
+            let (right, bottom) = vertices.iter().fold((f32::MIN, f32::MIN), |(x, y), vertex| (x.max(vertex.0), y.max(vertex.1)));
+            return Ok(Self {
+                name: name.to_string(),
+                x: left,
+                y: top,
+                width: right - left,
+                height: bottom - top,
+                polygon: Some(vertices),
+                snapshot,
+                schedule: None,
+            });
+        }
+        let [x, y, width, height] = values[..] else {
+            return Err(invalid());
+        };
+        if width <= 0.0 || height <= 0.0 {
+            return Err(invalid());
+        }
+        Ok(Self { name: name.to_string(), x, y, width, height, polygon: None, snapshot, schedule: None })
+    }
+
+    /// The zone in the --zone syntax it was parsed from.
+    pub fn spec(&self) -> String {
+        let mut spec = match &self.polygon {
+            Some(vertices) => {
+                let coordinates: Vec<String> = vertices.iter().map(|(x, y)| format!("{},{}", x * 100.0, y * 100.0)).collect();
+                format!("{}:polygon={}", self.name, coordinates.join(","))
+            }
+            None => format!("{}:{},{},{},{}", self.name, self.x * 100.0, self.y * 100.0, self.width * 100.0, self.height * 100.0),
+        };
+        if let Some(snapshot) = &self.snapshot {
+            if snapshot.crop {
+                spec.push_str(":crop");
+            }
+            spec.push_str(&format!(":padding={}", snapshot.padding * 100.0));
+            if let Some(directory) = &snapshot.directory {
+                spec.push_str(&format!(":dir={}", directory.display()));
+            }
+            if let Some(template) = &snapshot.template {
+                spec.push_str(&format!(":template={template}"));
+            }
+        }
+        spec
+    }
+
+    /// True if the point, in fractions of the frame size, is inside the zone. Polygons use the
+    /// even-odd rule.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let in_box = x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height;
+        match &self.polygon {
+            Some(vertices) => in_box && in_polygon(vertices, x, y),
+            None => in_box,
+        }
+    }
+
+    /// The outline, in fractions of the frame size: the polygon's vertices or the rectangle's corners.
+    pub fn outline(&self) -> Vec<(f32, f32)> {
+        match &self.polygon {
+            Some(vertices) => vertices.clone(),
+            None => vec![
+                (self.x, self.y),
+                (self.x + self.width, self.y),
+                (self.x + self.width, self.y + self.height),
+                (self.x, self.y + self.height),
+            ],
+        }
+    }
+
+    /// The zone on a grid of the given size, row by row: a pixel belongs to it when its centre is inside.
+    pub fn rasterize(&self, width: usize, height: usize) -> Vec<bool> {
+        (0 .. width * height)
+            .map(|index| self.contains(((index % width) as f32 + 0.5) / width as f32, ((index / width) as f32 + 0.5) / height as f32))
+            .collect()
+    }
+
+    /// The zone as left, top, right and bottom pixel coordinates (right and bottom excluded) in an
+    /// image of the given size, grown by `padding` times the zone size on each side.
+    pub fn pixel_rect(&self, width: usize, height: usize, padding: f32) -> (usize, usize, usize, usize) {
+        let scale = |value: f32, size: usize| ((value * size as f32).round().max(0.0) as usize).min(size);
+        let (pad_x, pad_y) = (self.width * padding, self.height * padding);
+        (
+            scale(self.x - pad_x, width),
+            scale(self.y - pad_y, height),
+            scale(self.x + self.width + pad_x, width),
+            scale(self.y + self.height + pad_y, height),
+        )
+    }
+}
+
+
+/// Every zone rasterized onto the thumbnail grid, so the pixels of a zone are looked up rather than
+/// tested against its shape. Rasterized again only when the thumbnail size changes.
+#[derive(Debug, Clone, Default)]
+pub struct ZoneMasks {
+    width: usize,
+    height: usize,
+    masks: Vec<Vec<bool>>,  // In the order of the zones.
+}
+
+
+impl ZoneMasks {
+
+    /// Rasterizes the zones for a thumbnail size, unless they already are.
+    pub fn fit(&mut self, zones: &[Zone], width: usize, height: usize) {
+        if (self.width, self.height) == (width, height) && self.masks.len() == zones.len() {
+            return;
+        }
+        *self = Self { width, height, masks: zones.iter().map(|zone| zone.rasterize(width, height)).collect() };
+    }
+
+    /// The fraction of a zone's pixels that changed in a diff mask of the fitted size.
+    pub fn activity(&self, zone: usize, mask: &[u8]) -> f32 {
+        let Some(zone_mask) = self.masks.get(zone) else {
+            return 0.0;
+        };
+        let (area, changed) = zone_mask.iter().zip(mask)
+            .filter(|(inside, _)| **inside)
+            .fold((0usize, 0usize), |(area, changed), (_, pixel)| (area + 1, changed + (*pixel != 0) as usize));
+        if area == 0 {
+            return 0.0;
+        }
+        changed as f32 / area as f32
+    }
+
+    /// Changed pixels of a diff mask of the fitted size that belong to disarmed zones only, in
+    /// the order of the zones. A pixel also in an armed zone still counts.
+    pub fn silenced(&self, mask: &[u8], armed: &[bool]) -> i32 {
+        let (disarmed, armed): (Vec<_>, Vec<_>) = self.masks.iter().zip(armed).partition(|(_, armed)| !**armed);
+        if disarmed.is_empty() {
+            return 0;
+        }
+        mask.iter().enumerate()
+            .filter(|(index, changed)| {
+                **changed != 0
+                    && disarmed.iter().any(|(zone_mask, _)| zone_mask[*index])
+                    && !armed.iter().any(|(zone_mask, _)| zone_mask[*index])
+            })
+            .count() as i32
+    }
+}
+
+
+/// Why a zone was armed or disarmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmingCause {
+    Schedule,
+    Control,    // An arm or disarm command naming the zone.
+}
+
+
+impl ArmingCause {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArmingCause::Schedule => "schedule",
+            ArmingCause::Control => "control",
+        }
+    }
+}
+
+
+/// A zone was armed or disarmed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArmingChange {
+    pub zone: String,
+    pub armed: bool,
+    pub cause: ArmingCause,
+}
+
+
+// The arming of one zone.
+struct ZoneArm {
+    name: String,
+    schedule: Option<Schedule>,
+    armed: bool,
+    held_until: Option<Option<SystemTime>>, // Set by a command, until the schedule changes next or for good.
+}
+
+
+/// Which zones are armed. Changes in disarmed zones take no part in starting or sustaining
+/// movements, while the heatmap and the context still see them. A zone with a schedule is armed
+/// within its windows, one without is armed until disarmed. An arm or disarm command naming the
+/// zone holds until the schedule's next transition. The global arm and disarm override every zone.
+pub struct ZoneArming {
+    zones: Vec<ZoneArm>,
+}
+
+
+impl ZoneArming {
+
+    pub fn new(zones: &[Zone], now: SystemTime) -> Self {
+        let zones = zones.iter()
+            .map(|zone| ZoneArm {
+                name: zone.name.clone(),
+                armed: zone.schedule.as_ref().is_none_or(|schedule| schedule.contains(now)),
+                schedule: zone.schedule.clone(),
+                held_until: None,
+            })
+            .collect();
+        Self { zones }
+    }
+
+    /// Follows the schedules, returns the zones that were armed or disarmed by them.
+    pub fn update(&mut self, now: SystemTime) -> Vec<ArmingChange> {
+        let mut changes = Vec::new();
+        for zone in &mut self.zones {
+            if let Some(Some(until)) = zone.held_until {
+                if now >= until {
+                    zone.held_until = None;
+                }
+            }
+            let Some(schedule) = zone.schedule.as_ref().filter(|_| zone.held_until.is_none()) else {
+                continue;
+            };
+            let armed = schedule.contains(now);
+            if armed != zone.armed {
+                zone.armed = armed;
+                changes.push(ArmingChange { zone: zone.name.clone(), armed, cause: ArmingCause::Schedule });
+            }
+        }
+        changes
+    }
+
+    /// Arms or disarms the zone by command. Returns the change, None if it already was.
+    pub fn set(&mut self, name: &str, armed: bool, now: SystemTime) -> Result<Option<ArmingChange>, String> {
+        let zone = self.zones.iter_mut().find(|zone| zone.name == name).ok_or_else(|| format!("no zone named '{name}'"))?;
+        zone.held_until = Some(zone.schedule.as_ref().and_then(|schedule| schedule.next_change(now)));
+        if zone.armed == armed {
+            return Ok(None);
+        }
+        zone.armed = armed;
+        Ok(Some(ArmingChange { zone: name.to_string(), armed, cause: ArmingCause::Control }))
+    }
+
+    /// Whether each zone is armed on its own, in the order of the zones.
+    pub fn armed(&self) -> Vec<bool> {
+        self.zones.iter().map(|zone| zone.armed).collect()
+    }
+
+    /// Each zone's name, arming and next scheduled transition, for GET /status. `armed` is the
+    /// global arming.
+    pub fn to_objects(&self, armed: bool, now: SystemTime) -> Vec<json::Object> {
+        self.zones.iter()
+            .map(|zone| {
+                let next = match zone.held_until {
+                    Some(until) => until,
+                    None => zone.schedule.as_ref().and_then(|schedule| schedule.next_change(now)),
+                };
+                json::Object::new()
+                    .field("name", zone.name.as_str())
+                    .field("armed", armed && zone.armed)
+                    .field("zone_armed", zone.armed)
+                    .field("schedule", zone.schedule.as_ref().map(|schedule| schedule.to_string()))
+                    .field("held", zone.held_until.is_some())
+                    .field("next_transition", next.map(json::unix_time))
+            })
+            .collect()
+    }
+}
+
+
+// Twice the signed area, by the shoelace formula.
+fn area(vertices: &[(f32, f32)]) -> f32 {
+    edges(vertices).map(|((x1, y1), (x2, y2))| x1 * y2 - x2 * y1).sum()
+}
+
+
+// Each edge once, the last one closing the polygon.
+fn edges(vertices: &[(f32, f32)]) -> impl Iterator<Item = ((f32, f32), (f32, f32))> + '_ {
+    vertices.iter().copied().zip(vertices.iter().copied().cycle().skip(1))
+}
+
+
+// Even-odd rule: a ray to the right crosses the outline an odd number of times from inside.
+fn in_polygon(vertices: &[(f32, f32)], x: f32, y: f32) -> bool {
+    edges(vertices)
+        .filter(|((x1, y1), (x2, y2))| (*y1 > y) != (*y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1))
+        .count() % 2 == 1
+}
+
+
+// Whether two edges that don't share a vertex touch, the O(n²) check is fine for hand drawn zones.
+fn crosses_itself(vertices: &[(f32, f32)]) -> bool {
+    let edges: Vec<_> = edges(vertices).collect();
+    let count = edges.len();
+    (0 .. count).any(|i| (i + 2 .. count)
+        .filter(|j| !(i == 0 && *j == count - 1))
+        .any(|j| segments_touch(edges[i], edges[j])))
+}
+
+
+fn segments_touch((a, b): ((f32, f32), (f32, f32)), (c, d): ((f32, f32), (f32, f32))) -> bool {
+    let orientation = |p: (f32, f32), q: (f32, f32), r: (f32, f32)| {
+        let cross = (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0);
+        (cross > 0.0) as i32 - (cross < 0.0) as i32
+    };
+    let within = |p: (f32, f32), q: (f32, f32), r: (f32, f32)| r.0 >= p.0.min(q.0) && r.0 <= p.0.max(q.0) && r.1 >= p.1.min(q.1) && r.1 <= p.1.max(q.1);
+    let (o1, o2, o3, o4) = (orientation(a, b, c), orientation(a, b, d), orientation(c, d, a), orientation(c, d, b));
+    (o1 != o2 && o3 != o4)
+        || (o1 == 0 && within(a, b, c))
+        || (o2 == 0 && within(a, b, d))
+        || (o3 == 0 && within(c, d, a))
+        || (o4 == 0 && within(c, d, b))
+}
+
+
+/// Centre of the changed pixels in a diff mask, in fractions of the thumbnail size.
+/// None if nothing changed.
+pub fn centroid(mask: &[u8], width: usize) -> Option<(f32, f32)> {
+    if width == 0 {
+        return None;
+    }
+    let height = mask.len() / width;
+    let (mut sum_x, mut sum_y, mut count) = (0usize, 0usize, 0usize);
+    for (index, changed) in mask.iter().enumerate() {
+        if *changed != 0 {
+            sum_x += index % width;
+            sum_y += index / width;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    // Pixel centres, so a single changed pixel in the last column doesn't land on the edge.
+    let x = (sum_x as f32 / count as f32 + 0.5) / width as f32;
+    let y = (sum_y as f32 / count as f32 + 0.5) / height as f32;
+    Some((x, y))
+}
+
+
+/// The centroid of a movement settled in a different zone. None means outside of every zone.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+
+impl Transition {
+    pub fn to_json(&self, id: u64, time: SystemTime, source: TimeSource) -> String {
+        json::Object::new()
+            .field("type", "zone_transition")
+            .field("id", id)
+            .field("from", self.from.as_deref())
+            .field("to", self.to.as_deref())
+            .field("time", json::unix_time(time))
+            .field("time_source", source.name())
+            .finish()
+    }
+
+    /// The line printed in text mode.
+    pub fn text(&self) -> String {
+        format!("zone {} -> {}", self.from.as_deref().unwrap_or("none"), self.to.as_deref().unwrap_or("none"))
+    }
+}
+
+
+/// Follows which zone the centroid of the active movement is in, reporting changes once the
+/// centroid stayed in the new zone for a number of frames.
+pub struct ZoneTracker {
+    zones: Vec<Zone>,
+    debounce: u32,
+    current: Option<usize>,
+    candidate: Option<usize>,
+    candidate_frames: u32,
+    visited: Vec<usize>,
+}
+
+
+impl ZoneTracker {
+
+    pub fn new(zones: Vec<Zone>, debounce: u32) -> Self {
+        Self { zones, debounce: debounce.max(1), current: None, candidate: None, candidate_frames: 0, visited: Vec::new() }
+    }
+
+    /// Starts following a new movement from the zone its centroid is in.
+    pub fn begin(&mut self, centroid: Option<(f32, f32)>) {
+        self.current = centroid.and_then(|point| self.zone_at(point));
+        self.candidate = None;
+        self.candidate_frames = 0;
+        self.visited.clear();
+        self.visited.extend(self.current);
+    }
+
+    /// Follows the centroid of the active movement. Frames without changed pixels don't count.
+    pub fn update(&mut self, centroid: Option<(f32, f32)>) -> Option<Transition> {
+        let zone = self.zone_at(centroid?);
+        if zone == self.current {
+            self.candidate = None;
+            return None;
+        }
+        if zone != self.candidate {
+            self.candidate = zone;
+            self.candidate_frames = 0;
+        }
+        self.candidate_frames += 1;
+        if self.candidate_frames < self.debounce {
+            return None;
+        }
+
+        let from = self.current;
+        self.current = zone;
+        self.candidate = None;
+        self.visited.extend(zone);
+        Some(Transition { from: from.map(|index| self.name(index)), to: zone.map(|index| self.name(index)) })
+    }
+
+    /// Names of the zones the movement went through, in order.
+    pub fn visited(&self) -> Vec<String> {
+        self.visited.iter().map(|index| self.name(*index)).collect()
+    }
+
+    // Overlapping zones resolve to the first one given.
+    fn zone_at(&self, (x, y): (f32, f32)) -> Option<usize> {
+        self.zones.iter().position(|zone| zone.contains(x, y))
+    }
+
+    fn name(&self, index: usize) -> String {
+        self.zones[index].name.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 4 by 4 mask drawn as rows of '#' and '.'.
+    fn grid(rows: [&str; 4]) -> Vec<bool> {
+        rows.concat().chars().map(|pixel| pixel == '#').collect()
+    }
+
+
+    #[test]
+    fn a_triangle_covers_the_pixels_whose_centre_it_holds() {
+        let zone = Zone::parse("corner:polygon=0,0,100,0,0,50").unwrap();
+        assert_eq!((zone.x, zone.y, zone.width, zone.height), (0.0, 0.0, 1.0, 0.5));
+        assert_eq!(zone.rasterize(4, 4), grid([
+            "###.",
+            "#...",
+            "....",
+            "....",
+        ]));
+    }
+
+
+    #[test]
+    fn a_concave_polygon_leaves_out_its_notch() {
+        let zone = Zone::parse("u:polygon=0,0,25,0,25,75,75,75,75,0,100,0,100,100,0,100").unwrap();
+        assert_eq!(zone.rasterize(4, 4), grid([
+            "#..#",
+            "#..#",
+            "#..#",
+            "####",
+        ]));
+    }
+
+
+    #[test]
+    fn polygons_need_an_area_and_no_crossing() {
+        assert!(Zone::parse("line:polygon=0,0,100,100").is_err());
+        assert!(Zone::parse("odd:polygon=0,0,100,0,100").is_err());
+        assert!(Zone::parse("flat:polygon=0,0,50,50,100,100").unwrap_err().contains("no area"));
+        assert!(Zone::parse("bowtie:polygon=0,0,100,100,100,0,0,100").unwrap_err().contains("crosses itself"));
+    }
+
+
+    #[test]
+    fn zones_read_back_from_their_spec() {
+        for spec in ["door:0,0,30,100", "driveway:polygon=0,100,45,40,60,40,35,100:crop:padding=10"] {
+            let zone = Zone::parse(spec).unwrap();
+            let again = Zone::parse(&zone.spec()).unwrap();
+            assert_eq!(again.spec(), zone.spec());
+            assert_eq!(again.rasterize(8, 8), zone.rasterize(8, 8), "{spec}");
+        }
+    }
+
+
+    #[test]
+    fn zone_activity_is_the_share_of_its_pixels_that_changed() {
+        let zones = [Zone::parse("left:0,0,50,100").unwrap(), Zone::parse("u:polygon=0,0,25,0,25,75,75,75,75,0,100,0,100,100,0,100").unwrap()];
+        let mut masks = ZoneMasks::default();
+        masks.fit(&zones, 4, 4);
+        // The top row changed: half of the left zone's top row, two of the u's ten pixels.
+        let mask: Vec<u8> = (0 .. 16).map(|index| (index < 4) as u8).collect();
+        assert_eq!(masks.activity(0, &mask), 0.25);
+        assert_eq!(masks.activity(1, &mask), 0.2);
+    }
+}
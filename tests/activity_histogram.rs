@@ -0,0 +1,81 @@
+//! Movements added to an `ActivityHistogram` in Central European time, TZ set before anything
+//! asks for local time in this process: split over the hours they span, past midnight into the
+//! next weekday, and over the hour skipped as daylight saving time begins and the one repeated as
+//! it ends, every second counted once.
+
+#![cfg(unix)]
+
+use std::{
+    env,
+    sync::Once,
+    time::{ Duration, UNIX_EPOCH },
+};
+
+use motion_detect::activity::ActivityHistogram;
+
+// Summer time from the last Sunday of March at 2:00 to the last Sunday of October at 3:00, as in
+// the zone files, without needing them.
+const ZONE: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+const MONDAY: usize = 0;
+const WEDNESDAY: usize = 2;
+const SUNDAY: usize = 6;
+const MINUTE: u64 = 60;
+
+
+fn histogram() -> ActivityHistogram {
+    static ZONE_SET: Once = Once::new();
+    ZONE_SET.call_once(|| env::set_var("TZ", ZONE));
+    ActivityHistogram::new(UNIX_EPOCH)
+}
+
+
+// A histogram of the movement starting `utc` seconds after the epoch and lasting `minutes`.
+fn movement(utc: u64, minutes: u64) -> ActivityHistogram {
+    let mut histogram = histogram();
+    histogram.add(UNIX_EPOCH + Duration::from_secs(utc), Duration::from_secs(minutes * MINUTE));
+    histogram
+}
+
+
+// The hours of `weekday` with any motion, and their seconds.
+fn hours(histogram: &ActivityHistogram, weekday: usize) -> Vec<(usize, f64)> {
+    histogram.seconds[weekday].iter().enumerate().filter(|(_, seconds)| **seconds > 0.0).map(|(hour, seconds)| (hour, *seconds)).collect()
+}
+
+
+#[test]
+fn a_movement_is_split_over_the_hours_it_spans() {
+    // Wednesday the 14th of January 2026 from 10:50 to 11:20, 9:50 UTC.
+    let histogram = movement(1_768_384_200, 30);
+    assert_eq!(hours(&histogram, WEDNESDAY), [(10, 600.0), (11, 1200.0)]);
+    assert_eq!(histogram.total(), 1800.0);
+}
+
+
+#[test]
+fn a_movement_past_midnight_goes_on_in_the_next_weekday() {
+    // Sunday the 18th of January 2026 from 23:40 to Monday 0:20, the week starting over.
+    let histogram = movement(1_768_776_000, 40);
+    assert_eq!(hours(&histogram, SUNDAY), [(23, 1200.0)]);
+    assert_eq!(hours(&histogram, MONDAY), [(0, 1200.0)]);
+    assert_eq!(histogram.total(), 2400.0);
+}
+
+
+#[test]
+fn the_hour_skipped_as_summer_time_begins_gets_nothing() {
+    // Sunday the 29th of March 2026 from 1:30, two hours to 4:30: 2:00 is 3:00 already.
+    let histogram = movement(1_774_744_200, 120);
+    assert_eq!(hours(&histogram, SUNDAY), [(1, 1800.0), (3, 3600.0), (4, 1800.0)]);
+    assert_eq!(histogram.total(), 7200.0);
+}
+
+
+#[test]
+fn the_hour_repeated_as_summer_time_ends_gets_both() {
+    // Sunday the 25th of October 2026 from 1:30, two hours to 2:30 the second time.
+    let histogram = movement(1_792_884_600, 120);
+    assert_eq!(hours(&histogram, SUNDAY), [(1, 1800.0), (2, 5400.0)]);
+    assert_eq!(histogram.total(), 7200.0);
+}
@@ -0,0 +1,99 @@
+//! motion-detect with `--print-interval 1s` on raw video piped from a synthetic scene, a bar
+//! crossing it every second and a half: each "activity" record adds up the start and stop records
+//! printed since the one before, the last one covering what's left when the input ends.
+
+use std::{
+    io::{ BufRead, BufReader, Write },
+    process::{ Command, Stdio },
+    thread,
+    time::Duration,
+};
+
+use motion_detect::json::{ self, Scalar };
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const FPS: u64 = 20;
+const FRAMES: u64 = 140;
+
+
+// Gray and still for the first second, then a bright bar somewhere else on the first frames of
+// every 30.
+fn frame(index: u64) -> Vec<u8> {
+    let mut frame = vec![90; WIDTH * HEIGHT * 3];
+    if index >= FPS && index % 30 < 6 {
+        let left = (index as usize * 16) % WIDTH;
+        for y in 0 .. HEIGHT {
+            for x in left .. (left + 16).min(WIDTH) {
+                frame[(y * WIDTH + x) * 3 .. (y * WIDTH + x) * 3 + 3].fill(250);
+            }
+        }
+    }
+    frame
+}
+
+
+fn number(fields: &[(String, Scalar)], name: &str) -> f64 {
+    match fields.iter().find(|(field, _)| field == name) {
+        Some((_, Scalar::Number(number))) => *number,
+        other => panic!("{name} is {other:?}"),
+    }
+}
+
+
+// To the millisecond, as the records have it.
+fn millis(seconds: f64) -> u64 {
+    (seconds * 1000.0).round() as u64
+}
+
+
+#[test]
+fn each_activity_record_sums_up_the_events_before_it() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_motion-detect"))
+        .args(["--input", "stdin", "--input-size", &format!("{WIDTH}x{HEIGHT}"), "--input-fps", &FPS.to_string()])
+        .args(["--warm-up", "0s", "--motion-tail", "0.3s", "--print-interval", "1s", "--format", "json"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || {
+        for index in 0 .. FRAMES {
+            if stdin.write_all(&frame(index)).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(1000 / FPS));
+        }
+    });
+
+    // Movements, stops and milliseconds of motion since the latest report.
+    let (mut movements, mut stopped, mut motion) = (0, 0, 0);
+    let (mut reports, mut events) = (0, 0);
+    for line in BufReader::new(child.stdout.take().unwrap()).lines().map_while(Result::ok) {
+        let Ok(fields) = json::parse_flat(&line) else { continue };
+        let kind = fields.iter().find(|(field, _)| field == "type").map(|(_, kind)| kind.clone());
+        match kind {
+            Some(Scalar::String(kind)) if kind == "start" => movements += 1,
+            Some(Scalar::String(kind)) if kind == "stop" => {
+                stopped += 1;
+                motion += millis(number(&fields, "duration"));
+            }
+            Some(Scalar::String(kind)) if kind == "activity" => {
+                let report = (number(&fields, "movements") as u64, number(&fields, "stopped") as u64, millis(number(&fields, "motion")));
+                assert_eq!(report, (movements, stopped, motion), "report {reports}: {line}");
+                reports += 1;
+                events += movements + stopped;
+                (movements, stopped, motion) = (0, 0, 0);
+            }
+            _ => {}
+        }
+    }
+    writer.join().unwrap();
+    assert!(child.wait().unwrap().success());
+
+    // Nothing after the last report, which the end of the input printed.
+    assert_eq!((movements, stopped, motion), (0, 0, 0));
+    assert!(reports >= 5, "{reports} reports");
+    assert!(events >= 8, "{events} events");
+}
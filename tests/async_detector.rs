@@ -0,0 +1,188 @@
+//! The tokio facade: an `AsyncMotionDetector` on a synthetic source, its events taken from the
+//! stream while it's paused, resumed and given thresholds, and its worker let go of when the
+//! source ends, the handle is dropped or shut down.
+
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::{
+    sync::{ atomic::{ AtomicBool, Ordering }, Arc },
+    time::Duration,
+};
+
+use common::{ Scene, Shape, HEIGHT, WIDTH };
+use motion_detect::{
+    async_detector::AsyncMotionDetector,
+    control::{ ControlCommand, Setting },
+    detector::MotionError,
+    motion::MotionEvent,
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::PixelLayout,
+};
+use tokio::time::{ self, Instant };
+
+// A frame every 5ms, the bar sweeping for 10 frames in every 40.
+const PACE: Duration = Duration::from_millis(5);
+const PERIOD: u64 = 40;
+const MOVING: std::ops::Range<u64> = 20 .. 30;
+
+
+/// The scene at its pace, telling when it's dropped.
+struct Paced {
+    scene: Scene,
+    dropped: Arc<AtomicBool>,
+}
+
+
+impl FrameSource for Paced {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        std::thread::sleep(PACE);
+        self.scene.next_frame()
+    }
+}
+
+
+impl Drop for Paced {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+
+// A detector on `frames` of the sweeps, and whether its source was dropped.
+fn spawn(frames: u64) -> (AsyncMotionDetector, Arc<AtomicBool>) {
+    let shapes = (0 .. frames.min(100 * PERIOD))
+        .filter(|frame| MOVING.contains(&(frame % PERIOD)))
+        .flat_map(|frame| Shape::sweep(frame .. frame + 1))
+        .collect();
+    let dropped = Arc::new(AtomicBool::new(false));
+    let source = Paced { scene: Scene::new(frames, shapes), dropped: dropped.clone() };
+    (AsyncMotionDetector::spawn(common::settings(), WIDTH, HEIGHT, PixelLayout::Rgb, move || Ok(source)), dropped)
+}
+
+
+/// What came out of the stream while watching.
+#[derive(Debug, Default)]
+struct Watched {
+    events: Vec<String>,
+    error: Option<MotionError>,
+    ended: bool,
+}
+
+
+// Takes events for up to `within`, or until `stops` of them were stops.
+async fn watch(detector: &mut AsyncMotionDetector, within: Duration, stops: usize) -> Watched {
+    let deadline = Instant::now() + within;
+    let mut watched = Watched::default();
+    while watched.events.iter().filter(|event| event.starts_with("stop")).count() < stops {
+        tokio::select! {
+            event = detector.next_event() => match event {
+                Some(Ok(MotionEvent::Stop { reason, .. })) => watched.events.push(format!("stop {}", reason.name())),
+                Some(Ok(event)) => watched.events.push(event.text().to_string()),
+                Some(Err(err)) => watched.error = Some(err),
+                None => {
+                    watched.ended = true;
+                    break;
+                }
+            },
+            _ = time::sleep_until(deadline) => break,
+        }
+    }
+    watched
+}
+
+
+// Whether `dropped` is set within a second.
+async fn released(dropped: &AtomicBool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !dropped.load(Ordering::Relaxed) && Instant::now() < deadline {
+        time::sleep(PACE).await;
+    }
+    dropped.load(Ordering::Relaxed)
+}
+
+
+const MOVEMENT: [&str; 2] = ["start", "stop tail"];
+
+
+#[tokio::test]
+async fn movements_come_out_of_the_stream() {
+    let (mut detector, _) = spawn(u64::MAX);
+    let watched = watch(&mut detector, Duration::from_secs(5), 2).await;
+    assert_eq!(watched.events, [MOVEMENT, MOVEMENT].concat());
+}
+
+
+#[tokio::test]
+async fn nothing_comes_while_paused_and_movements_again_once_resumed() {
+    let (mut detector, _) = spawn(u64::MAX);
+    watch(&mut detector, Duration::from_secs(5), 1).await;
+    // Each command follows a stop, well before the next movement.
+    assert!(detector.pause().await.unwrap().paused);
+    assert_eq!(watch(&mut detector, Duration::from_secs(1), 1).await.events, Vec::<String>::new());
+    assert!(!detector.resume().await.unwrap().paused);
+    assert_eq!(watch(&mut detector, Duration::from_secs(5), 1).await.events, MOVEMENT);
+}
+
+
+#[tokio::test]
+async fn thresholds_set_apply_until_reverted() {
+    let (mut detector, _) = spawn(u64::MAX);
+    watch(&mut detector, Duration::from_secs(5), 1).await;
+    // The bar covers a quarter of the image.
+    let raised = detector.set_thresholds(vec![(Setting::ImageThreshold, 50.0)]).await.unwrap();
+    assert_eq!(raised.image_threshold, 50.0);
+    assert_eq!(watch(&mut detector, Duration::from_secs(1), 1).await.events, Vec::<String>::new());
+    let reverted = detector.send(ControlCommand::Revert).await.unwrap();
+    assert_eq!(reverted.image_threshold, Settings::default().image_threshold);
+    detector.reset_baseline().await.unwrap();
+    assert_eq!(watch(&mut detector, Duration::from_secs(5), 1).await.events, MOVEMENT);
+}
+
+
+#[tokio::test]
+async fn refused_commands_are_answered_as_such() {
+    let (detector, _) = spawn(u64::MAX);
+    assert!(detector.set_thresholds(vec![(Setting::ImageThreshold, 150.0)]).await.is_err());
+    assert!(detector.send(ControlCommand::Arm).await.is_err());
+}
+
+
+#[tokio::test]
+async fn the_end_of_the_source_stops_the_movement_and_ends_the_stream() {
+    // Ends halfway through the second movement.
+    let (mut detector, dropped) = spawn(PERIOD + MOVING.start + 5);
+    let watched = watch(&mut detector, Duration::from_secs(5), usize::MAX).await;
+    assert_eq!(watched.events, ["start", "stop tail", "start", "stop end_of_input"]);
+    assert_eq!(watched.error, Some(MotionError::EndOfInput));
+    assert!(watched.ended);
+    assert!(released(&dropped).await);
+}
+
+
+#[tokio::test]
+async fn dropping_the_handle_lets_go_of_the_source() {
+    let (detector, dropped) = spawn(u64::MAX);
+    drop(detector);
+    assert!(released(&dropped).await);
+}
+
+
+#[tokio::test]
+async fn a_shutdown_returns_once_the_source_is_let_go() {
+    let (detector, dropped) = spawn(u64::MAX);
+    detector.shutdown().await;
+    assert!(dropped.load(Ordering::Relaxed));
+}
+
+
+#[tokio::test]
+async fn a_source_that_cannot_be_opened_ends_the_stream_with_its_reason() {
+    let mut missing = AsyncMotionDetector::spawn(Settings::default(), WIDTH, HEIGHT, PixelLayout::Rgb, || Err::<Paced, _>("no camera".to_string()));
+    let watched = watch(&mut missing, Duration::from_secs(1), 1).await;
+    assert_eq!(watched.error, Some(MotionError::Source("no camera".to_string())));
+    assert!(watched.ended);
+}
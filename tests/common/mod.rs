@@ -0,0 +1,114 @@
+//! Synthetic scenes for the integration tests: a flat background with rectangles drawn on the
+//! frames they're on, fed to a `MotionDetector` at one interval per frame.
+
+#![allow(dead_code)]
+
+use std::{ ops::Range, time::Duration };
+
+use motion_detect::{
+    detector::MotionDetector,
+    motion::MotionEvent,
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::PixelLayout,
+};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 48;
+pub const INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// A rectangle of `shade` from `left`, `top` to `right`, `bottom` (excluded), in frame pixels,
+/// drawn on `frames`.
+#[derive(Debug, Clone)]
+pub struct Shape {
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub shade: u8,
+    pub frames: Range<u64>,
+}
+
+
+impl Shape {
+
+    pub fn new((left, top): (usize, usize), (right, bottom): (usize, usize), shade: u8, frames: Range<u64>) -> Self {
+        Self { left, top, right, bottom, shade, frames }
+    }
+
+    /// A bar the full height of the frame, 16 pixels wide, moving 4 pixels a frame.
+    pub fn sweep(frames: Range<u64>) -> Vec<Self> {
+        frames.map(|frame| {
+            let left = (frame as usize * 4) % (WIDTH - 16);
+            Self::new((left, 0), (left + 16, HEIGHT), 250, frame .. frame + 1)
+        }).collect()
+    }
+}
+
+
+/// RGB frames of WIDTH by HEIGHT, `frames` of them.
+pub struct Scene {
+    pub frame: Vec<u8>,
+    pub index: u64,
+    pub frames: u64,
+    pub background: u8,
+    pub shapes: Vec<Shape>,
+}
+
+
+impl Scene {
+
+    pub fn new(frames: u64, shapes: Vec<Shape>) -> Self {
+        Self { frame: Vec::new(), index: 0, frames, background: 90, shapes }
+    }
+}
+
+
+impl FrameSource for Scene {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        if self.index >= self.frames {
+            return Ok(None);
+        }
+        self.frame.clear();
+        self.frame.resize(WIDTH * HEIGHT * 3, self.background);
+        for shape in self.shapes.iter().filter(|shape| shape.frames.contains(&self.index)) {
+            for y in shape.top .. shape.bottom {
+                self.frame[(y * WIDTH + shape.left) * 3 .. (y * WIDTH + shape.right) * 3].fill(shape.shade);
+            }
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+}
+
+
+/// A 100ms capture interval, a 1s warm-up and a 500ms tail, on thumbnails a quarter of the size.
+pub fn settings() -> Settings {
+    Settings {
+        frame_capture_interval: INTERVAL,
+        camera_warm_up: Duration::from_secs(1),
+        motion_tail_length: Duration::from_millis(500),
+        downsample: 4,
+        ..Settings::default()
+    }
+}
+
+
+/// Every event of a run to the end of the scene, with the frame that caused it.
+pub fn run(settings: &Settings, scene: &mut Scene) -> Vec<(u64, MotionEvent)> {
+    let mut detector = MotionDetector::new(settings, WIDTH, HEIGHT, PixelLayout::Rgb);
+    let mut events = Vec::new();
+    while let Ok(frame_events) = detector.next_events(scene) {
+        events.extend(frame_events.into_iter().map(|event| (detector.frames() - 1, event)));
+    }
+    events.extend(detector.finish().map(|event| (detector.frames(), event)));
+    events
+}
+
+
+/// The frames movements started on.
+pub fn starts(events: &[(u64, MotionEvent)]) -> Vec<u64> {
+    events.iter().filter(|(_, event)| matches!(event, MotionEvent::Start { .. })).map(|(frame, _)| *frame).collect()
+}
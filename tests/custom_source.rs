@@ -0,0 +1,92 @@
+//! The scene of examples/custom_source.rs behind a `FrameSource` of its own: a ball crossing an
+//! empty room twice, a minute apart, is two movements, each starting as the ball comes in and
+//! stopping a tail after it's gone.
+
+use std::time::{ Duration, Instant };
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    motion::{ MotionEvent, MotionTracker },
+    source::FrameSource,
+    thumbnail::{ PixelLayout, Thumbnail },
+};
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+const INTERVAL: Duration = Duration::from_millis(100);
+const DOWNSAMPLE: usize = 8;
+const TAIL: Duration = Duration::from_secs(1);
+const RADIUS: i64 = 32;
+const SPEED: i64 = 64;
+const CROSSINGS: [(u64, u64); 2] = [(50, 8), (650, 8)];
+const FRAMES: u64 = 800;
+
+
+struct BallScene {
+    frame: Vec<u8>,
+    index: u64,
+}
+
+
+impl FrameSource for BallScene {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        if self.index == FRAMES {
+            return Ok(None);
+        }
+        let ball = CROSSINGS.iter()
+            .find(|(first, length)| (*first .. first + length).contains(&self.index))
+            .map(|(first, _)| ((self.index - first) as i64 * SPEED, HEIGHT as i64 / 2));
+        for (index, pixel) in self.frame.chunks_exact_mut(3).enumerate() {
+            let (x, y) = ((index % WIDTH) as i64, (index / WIDTH) as i64);
+            let inside = ball.is_some_and(|(cx, cy)| (x - cx).pow(2) + (y - cy).pow(2) <= RADIUS.pow(2));
+            pixel.fill(match (inside, y > HEIGHT as i64 * 2 / 3) {
+                (true, _) => 230,
+                (false, true) => 120,
+                (false, false) => 80,
+            });
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+}
+
+
+#[test]
+fn each_crossing_of_the_ball_is_a_movement() {
+    let mut source = BallScene { frame: vec![0; WIDTH * HEIGHT * 3], index: 0 };
+    let mut thumb = Thumbnail::new(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE);
+    let start_count = (thumb.len() as f32 * 0.02) as i32;
+    let mut strategy = FrameDiff::new(25, start_count);
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let mut events = Vec::new();
+    let mut frames = 0;
+    while let Some(frame) = source.next_frame().unwrap() {
+        thumb.downsample(frame, WIDTH, DOWNSAMPLE, PixelLayout::Rgb);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        events.extend(motion.update(result.changed_pixels, result.score, start + INTERVAL * frames));
+        frames += 1;
+    }
+    events.extend(motion.finish(start + INTERVAL * frames));
+
+    // In frames from the first one.
+    let frame = |at: Instant| (at.duration_since(start).as_millis() / INTERVAL.as_millis()) as u64;
+    let starts: Vec<u64> = events.iter().filter_map(|event| match event {
+        MotionEvent::Start { at, .. } => Some(frame(*at)),
+        _ => None,
+    }).collect();
+    let stops: Vec<Duration> = events.iter().filter_map(|event| match event {
+        MotionEvent::Stop { duration, .. } => Some(*duration),
+        _ => None,
+    }).collect();
+    assert_eq!(starts, CROSSINGS.map(|(first, _)| first), "{events:?}");
+    assert_eq!(stops.len(), CROSSINGS.len(), "{events:?}");
+    for duration in stops {
+        // The crossing and the tail, give or take the frame the ball leaves on.
+        assert!(duration >= TAIL + INTERVAL * 6 && duration <= TAIL + INTERVAL * 9, "{duration:?}");
+    }
+}
@@ -0,0 +1,136 @@
+//! A `FrameDiff` with an early exit decides like one that compares every pixel.
+//!
+//! Random scenes of random sizes, channels and thresholds, some with adaptive thresholds, get a
+//! few dozen frames each: a noisy background with a random share of it replaced. After every
+//! frame, both diffs must agree on whether more than the update count changed, must have the same
+//! reference and noise, and, for frames that stayed under it, the same count and mask.
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    noise::AdaptiveThreshold,
+    thumbnail::Thumbnail,
+};
+
+const SCENES: u32 = 150;
+const FRAMES: u32 = 40;
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+// The background with a little noise, and a random rectangle of it replaced by random values.
+fn frame(background: &Thumbnail, random: &mut Random) -> Thumbnail {
+    let mut thumb = background.clone();
+    for value in &mut thumb.pixels {
+        *value = value.saturating_add(random.below(4) as u8);
+    }
+    // Mostly small, so that about as many frames stay quiet as move.
+    let (width, height) = (random.below(thumb.width as u64), random.below(thumb.height as u64));
+    let (width, height) = (1 + random.below(1 + width) as usize, 1 + random.below(1 + height) as usize);
+    let (left, top) = (random.below((thumb.width - width + 1) as u64) as usize, random.below((thumb.height - height + 1) as u64) as usize);
+    for y in top .. top + height {
+        for x in left .. left + width {
+            let index = (y * thumb.width + x) * thumb.channels;
+            for value in &mut thumb.pixels[index .. index + thumb.channels] {
+                *value = random.below(256) as u8;
+            }
+        }
+    }
+    thumb
+}
+
+
+// Compares a random scene frame by frame, returns what differed first, or how many frames moved
+// and how many of them exited early.
+fn scene(random: &mut Random) -> Result<(u32, u32), String> {
+    let (width, height) = (4 + random.below(120) as usize, 4 + random.below(90) as usize);
+    let channels = [1, 3][random.below(2) as usize];
+    let pixel_threshold = 5 + random.below(60) as i32;
+    let update_count = ((width * height) as u64 * (1 + random.below(20)) / 100) as i32;
+    let mut full = FrameDiff::new(pixel_threshold, update_count);
+    let mut early = FrameDiff::new(pixel_threshold, update_count).with_early_exit();
+    if random.below(2) == 0 {
+        let adaptive = AdaptiveThreshold::from_percent(3.0, 2.0, 25.0);
+        full = full.with_adaptive_threshold(adaptive);
+        early = early.with_adaptive_threshold(adaptive);
+    }
+    let mut background = Thumbnail::with_channels(width, height, channels);
+    background.pixels.iter_mut().for_each(|value| *value = random.below(200) as u8);
+
+    let (mut exits, mut moving) = (0, 0);
+    for index in 0 .. FRAMES {
+        let thumb = frame(&background, random);
+        let active = random.below(4) == 0;
+        full.set_motion_active(active);
+        early.set_motion_active(active);
+        let (full_count, full_mask) = {
+            let result = full.process(&thumb);
+            (result.changed_pixels, result.mask.to_vec())
+        };
+        let (early_count, early_mask, skipped) = {
+            let result = early.process(&thumb);
+            (result.changed_pixels, result.mask.to_vec(), result.skipped_pixels)
+        };
+        let context = format!("{width}x{height}, {channels} channels, update count {update_count}, frame {index}");
+        if (full_count > update_count) != (early_count > update_count) {
+            return Err(format!("{context}: {full_count} changed in full, {early_count} with the early exit"));
+        }
+        if full_count <= update_count && (full_count != early_count || full_mask != early_mask || skipped > 0) {
+            return Err(format!("{context}: a quiet frame wasn't compared in full"));
+        }
+        if full.reference().map(|reference| &reference.pixels) != early.reference().map(|reference| &reference.pixels) {
+            return Err(format!("{context}: the references differ"));
+        }
+        if full.noise_map().map(|noise| (noise.samples, &noise.variance)) != early.noise_map().map(|noise| (noise.samples, &noise.variance)) {
+            return Err(format!("{context}: the learned noise differs"));
+        }
+        moving += (full_count > update_count) as u32;
+        exits += (skipped > 0) as u32;
+    }
+    Ok((moving, exits))
+}
+
+
+
+#[test]
+fn the_early_exit_decides_like_the_full_comparison() {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let (mut moving, mut exits) = (0, 0);
+    for _ in 0 .. SCENES {
+        let (scene_moving, scene_exits) = scene(&mut random).unwrap_or_else(|err| panic!("The early exit decided differently, {err}"));
+        (moving, exits) = (moving + scene_moving, exits + scene_exits);
+    }
+    // Both kinds of frames were seen, and the exit was taken.
+    assert!(moving > SCENES * FRAMES / 10 && moving < SCENES * FRAMES * 9 / 10, "{moving} moving frames");
+    assert!(exits > 0);
+}
+
+
+#[test]
+fn a_frame_moving_everywhere_is_cut_short() {
+    let mut frames = [Thumbnail::with_channels(160, 120, 3), Thumbnail::with_channels(160, 120, 3)];
+    frames[0].pixels.fill(40);
+    frames[1].pixels.fill(200);
+    let mut early = FrameDiff::new(25, 160 * 120 / 50).with_early_exit();
+    early.process(&frames[0]);
+    let result = early.process(&frames[1]);
+    assert!(result.skipped_pixels > 160 * 120 / 2, "{} skipped", result.skipped_pixels);
+    assert_eq!(result.mask[result.mask.len() - 1], 0);
+    // Without it, all of it is compared.
+    let mut full = FrameDiff::new(25, 160 * 120 / 50);
+    full.process(&frames[0]);
+    assert_eq!(full.process(&frames[1]).changed_pixels, 160 * 120);
+}
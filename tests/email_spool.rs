@@ -0,0 +1,141 @@
+//! The email spool against an SMTP server written out here: down at first, nothing listening on
+//! its port, so the emails of a run are spooled, then up for the next run, which delivers what
+//! the spool kept, oldest first and marked as delayed. Between the two, the spool file is damaged
+//! and its limit lowered.
+
+#![cfg(feature = "smtp")]
+
+use std::{
+    env, fs,
+    io::{ BufRead, BufReader, Write },
+    net::TcpListener,
+    path::Path,
+    process,
+    sync::{ Arc, Mutex },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use motion_detect::{
+    email::Mailer,
+    identity::EventIdentity,
+    settings::{ Settings, SmtpTls },
+};
+
+const EMAILS: u64 = 5;
+const KEPT: usize = 3;
+
+
+fn settings(port: u16, spool: &Path, spool_size: usize) -> Settings {
+    Settings {
+        smtp_server: Some(format!("127.0.0.1:{port}")),
+        smtp_tls: SmtpTls::None,
+        smtp_from: Some("cabin@example.com".to_string()),
+        smtp_to: vec!["me@example.com".to_string()],
+        email_cooldown: Duration::ZERO,
+        email_spool: Some(spool.to_path_buf()),
+        email_spool_size: spool_size,
+        ..Settings::default()
+    }
+}
+
+
+// Takes every message it's sent, one SMTP session per connection, answering each command with
+// the reply that lets it through.
+fn serve(listener: TcpListener, messages: Arc<Mutex<Vec<String>>>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let _ = writer.write_all(b"220 mock ESMTP\r\n");
+        let mut line = String::new();
+        while reader.read_line(&mut line).is_ok_and(|read| read > 0) {
+            let command = line.trim_end().to_ascii_uppercase();
+            line.clear();
+            let reply: &[u8] = if command.starts_with("DATA") {
+                let _ = writer.write_all(b"354 go ahead\r\n");
+                let mut message = String::new();
+                while reader.read_line(&mut line).is_ok_and(|read| read > 0) && line != ".\r\n" {
+                    message.push_str(&line);
+                    line.clear();
+                }
+                line.clear();
+                messages.lock().unwrap().push(message);
+                b"250 queued\r\n"
+            } else if command.starts_with("QUIT") {
+                let _ = writer.write_all(b"221 bye\r\n");
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            let _ = writer.write_all(reply);
+        }
+    }
+}
+
+
+// A port nothing listens on, for now.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+
+fn identity(id: u64) -> EventIdentity {
+    EventIdentity { installation: "trail".to_string(), sequence: id, id }
+}
+
+
+// The lines of the spool file.
+fn lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path).unwrap().split_inclusive('\n').map(String::from).collect()
+}
+
+
+#[test]
+fn spooled_emails_are_delivered_in_order_and_delayed_once_the_server_is_up() {
+    let spool = env::temp_dir().join(format!("motion-detect-email-spool-{}", process::id()));
+    let _ = fs::remove_file(&spool);
+    let port = free_port();
+
+    // The server is down, the emails are tried once and spooled as the mailer finishes.
+    let mut mailer = Mailer::new(&settings(port, &spool, 1 << 20), "trail".to_string()).unwrap();
+    for id in 1 ..= EMAILS {
+        mailer.motion_started(&identity(id), Instant::now(), &[]).unwrap();
+    }
+    assert_eq!(mailer.finish(Duration::from_secs(10)), EMAILS);
+    let spooled = lines(&spool);
+    assert_eq!(spooled.len(), EMAILS as usize);
+
+    // A record damaged in the middle, one torn at the end, and room left for KEPT of them.
+    let mut damaged = spooled.clone();
+    damaged[2] = damaged[2].replacen("start", "stort", 1);
+    damaged.push(spooled[0][.. 20].to_string());
+    fs::write(&spool, damaged.concat()).unwrap();
+    let limit = spooled[.. KEPT].iter().map(String::len).sum();
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    thread::spawn({
+        let messages = messages.clone();
+        move || serve(listener, messages)
+    });
+    let mailer = Mailer::new(&settings(port, &spool, limit), "trail".to_string()).unwrap();
+    // The spool is replayed as the mailer starts.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while mailer.spooled() > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!((mailer.spooled(), mailer.sent()), (0, KEPT as u64));
+    assert_eq!(mailer.finish(Duration::from_secs(5)), 0);
+    assert!(lines(&spool).is_empty());
+    let _ = fs::remove_file(&spool);
+
+    // The damaged third is dropped, and the first doesn't fit anymore.
+    let messages = messages.lock().unwrap();
+    assert_eq!(messages.len(), KEPT);
+    for (message, id) in messages.iter().zip([2, 4, 5]) {
+        assert!(message.contains("Subject: [delayed] Motion detected: trail"), "{message}");
+        assert!(message.contains(&format!("Movement {id} started on trail.")), "{message}");
+        assert!(message.contains("Delayed, this email was due at"), "{message}");
+    }
+}
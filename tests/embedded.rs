@@ -0,0 +1,73 @@
+//! The host loop of examples/embedded_no_events.rs: a `DiffStrategy` polled for a score every
+//! tick, with no tracker or events, in front of a noisy grayscale sensor watching a door. The
+//! score stays low while the door is shut and rises while it swings open.
+
+use std::ops::Range;
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    thumbnail::{ PixelLayout, Thumbnail },
+};
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 96;
+const DOWNSAMPLE: usize = 4;
+const TICKS: u32 = 300;
+const SWING: Range<u32> = 120 .. 150;
+const DOOR_WIDTH: usize = 48;
+const INDICATOR: f32 = 2.0;
+const NOISE: u8 = 6;
+
+
+struct Sensor {
+    frame: Vec<u8>,
+    noise: u64,
+}
+
+
+impl Sensor {
+
+    fn capture(&mut self, tick: u32) -> &[u8] {
+        let open = match tick {
+            _ if tick < SWING.start => 0,
+            _ if tick >= SWING.end => DOOR_WIDTH,
+            _ => DOOR_WIDTH * (tick - SWING.start) as usize / SWING.len(),
+        };
+        for (index, pixel) in self.frame.iter_mut().enumerate() {
+            let (x, y) = (index % WIDTH, index / WIDTH);
+            let base = if x >= 40 && x < 40 + open && y >= 16 { 20 } else { 140 };
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 7;
+            self.noise ^= self.noise << 17;
+            *pixel = base + (self.noise % NOISE as u64) as u8;
+        }
+        &self.frame
+    }
+}
+
+
+#[test]
+fn the_score_is_high_only_while_the_door_swings() {
+    let mut sensor = Sensor { frame: vec![0; WIDTH * HEIGHT], noise: 0x2545f491 };
+    let mut thumb = Thumbnail::with_channels(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE, 1);
+    let mut strategy = FrameDiff::new(20, -1);
+
+    // The ticks the indicator went on and off at, and the highest score while shut.
+    let mut indicator = Vec::new();
+    let mut quiet_peak = 0.0f32;
+    for tick in 0 .. TICKS {
+        thumb.downsample(sensor.capture(tick), WIDTH, DOWNSAMPLE, PixelLayout::Gray);
+        let score = strategy.process(&thumb).score;
+        if indicator.len() % 2 != (score > INDICATOR) as usize {
+            indicator.push(tick);
+        }
+        // The first frame after the swing still differs from the last one of it.
+        if !SWING.contains(&tick) && tick != SWING.end {
+            quiet_peak = quiet_peak.max(score);
+        }
+    }
+    assert!(quiet_peak <= INDICATOR, "{quiet_peak}% while shut");
+    // On once, while it swung, and off again once it's open.
+    assert_eq!(indicator.len(), 2, "{indicator:?}");
+    assert!(SWING.contains(&indicator[0]) && indicator[1] > SWING.start && indicator[1] <= SWING.end + 1, "{indicator:?}");
+}
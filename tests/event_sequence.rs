@@ -0,0 +1,109 @@
+//! Kills a process handing out event identities, as motion-detect does, and starts it again, over
+//! and over, on the same state directory: the installation id must outlive every run, and the
+//! sequence must only go up, never repeating a number and skipping at most a block.
+//!
+//! The process killed is this test binary again, running `hand_out_identities_until_killed`
+//! on the directory it's given. Each run is killed with SIGKILL after a random number of
+//! identities, wherever it is: printing, or replacing the sequence file.
+
+use std::{
+    env, fs,
+    io::{ BufRead, BufReader, Write },
+    path::Path,
+    process::{ self, Command, Stdio },
+};
+
+use motion_detect::identity::{ Identity, SEQUENCE_BLOCK };
+
+const RUNS: u32 = 40;
+// The most events a run reads before killing the process, a few blocks.
+const EVENTS: u64 = 300;
+// Where the process killed hands out identities, unset for the test itself.
+const DIR_VAR: &str = "MOTION_DETECT_EVENT_SEQUENCE_DIR";
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+// The process killed: "identity installation sequence" per event, until it is. Among the test
+// harness's own lines, hence the prefix.
+#[test]
+fn hand_out_identities_until_killed() {
+    let Some(dir) = env::var_os(DIR_VAR) else { return };
+    let mut identity = Identity::open(Path::new(&dir)).unwrap();
+    let mut stdout = std::io::stdout();
+    for id in 1 .. {
+        let event = identity.next(id);
+        if writeln!(stdout, "identity {} {}", event.installation, event.sequence).and_then(|()| stdout.flush()).is_err() {
+            return;
+        }
+        assert!(identity.take_warnings().is_empty());
+    }
+}
+
+
+fn parse(line: &str) -> Option<(String, u64)> {
+    let mut fields = line.strip_prefix("identity ")?.split(' ');
+    Some((fields.next()?.to_string(), fields.next()?.parse().ok()?))
+}
+
+
+// Starts a run, reads `count` events and kills it, returns every event it printed.
+fn run(dir: &Path, count: u64) -> Vec<(String, u64)> {
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["hand_out_identities_until_killed", "--exact", "--nocapture"])
+        .env(DIR_VAR, dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut events = Vec::new();
+    while let Some(Ok(line)) = lines.next() {
+        events.extend(parse(&line));
+        if events.len() as u64 == count {
+            child.kill().unwrap();
+            break;
+        }
+    }
+    // What it printed before it died counts as handed out too.
+    events.extend(lines.map_while(Result::ok).filter_map(|line| parse(&line)));
+    child.wait().unwrap();
+    events
+}
+
+
+#[test]
+fn the_sequence_survives_being_killed_anywhere() {
+    if env::var_os(DIR_VAR).is_some() {
+        return;
+    }
+    let dir = env::temp_dir().join(format!("motion-detect-event-sequence-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut random = Random(0x9e37_79b9_7f4a_7c15);
+    let mut installation: Option<String> = None;
+    let mut last: Option<u64> = None;
+    for index in 0 .. RUNS {
+        for (run_installation, sequence) in run(&dir, 1 + random.below(EVENTS)) {
+            assert_eq!(installation.get_or_insert_with(|| run_installation.clone()), &run_installation, "Run {index}");
+            if let Some(last) = last {
+                assert!(sequence > last && sequence - last <= SEQUENCE_BLOCK, "Run {index}: sequence {sequence} after {last}");
+            }
+            last = Some(sequence);
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+    assert!(last.is_some_and(|last| last > RUNS as u64), "Identities were handed out: {last:?}");
+}
@@ -0,0 +1,98 @@
+//! The motion-detect binary writes its exit report on every way out, fed raw video on stdin.
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{ Path, PathBuf },
+    process::{ Child, Command, Stdio },
+    thread,
+    time::Duration,
+};
+
+const FRAME_LEN: usize = 32 * 24;
+
+
+// A directory of its own for each test, holding the report and the binary's state.
+fn directory(test: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("exit_report_{test}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+
+fn spawn(directory: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_motion-detect"))
+        .args(["--input", "stdin", "--input-size", "32x24", "--input-format", "gray", "--input-fps", "50", "--warm-up", "100ms"])
+        .arg("--exit-report")
+        .arg(directory.join("report.json"))
+        .args(args)
+        .env("XDG_STATE_HOME", directory)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+
+// Waits for the binary to end, returns its exit code and its report.
+fn finish(mut child: Child, directory: &Path) -> (Option<i32>, String) {
+    let code = child.wait().unwrap().code();
+    let report = fs::read_to_string(directory.join("report.json")).unwrap_or_default();
+    let _ = fs::remove_dir_all(directory);
+    (code, report)
+}
+
+
+#[test]
+fn the_end_of_the_input_is_reported() {
+    let directory = directory("end_of_input");
+    let mut child = spawn(&directory, &[]);
+    child.stdin.take().unwrap().write_all(&vec![90; FRAME_LEN * 20]).unwrap();
+    let (code, report) = finish(child, &directory);
+    assert_eq!(code, Some(0));
+    assert!(report.contains(r#""reason":"end_of_input","code":0"#), "{report}");
+}
+
+
+#[test]
+fn a_broken_input_is_a_lost_camera() {
+    let directory = directory("camera_lost");
+    let mut child = spawn(&directory, &[]);
+    child.stdin.take().unwrap().write_all(&[90; FRAME_LEN / 2]).unwrap();
+    let (code, report) = finish(child, &directory);
+    assert_eq!(code, Some(5));
+    assert!(report.contains(r#""reason":"camera_lost","code":5"#), "{report}");
+    assert!(report.contains("Warning, camera lost: input ended after"), "{report}");
+}
+
+
+#[test]
+fn a_fatal_error_is_reported_with_its_message() {
+    let directory = directory("error");
+    let child = spawn(&directory, &["--http-token", "secret"]);
+    let (code, report) = finish(child, &directory);
+    assert_eq!(code, Some(22));
+    assert!(report.contains(r#""reason":"error","code":22"#), "{report}");
+    assert!(report.contains("--http-token requires --http"), "{report}");
+}
+
+
+#[cfg(unix)]
+#[test]
+fn a_termination_signal_is_reported() {
+    let directory = directory("signal");
+    let mut child = spawn(&directory, &[]);
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(&vec![90; FRAME_LEN * 10]).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    let killed = Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    // The input stays open until the binary is gone, only the signal ends the run.
+    let _ = stdin.write_all(&vec![90; FRAME_LEN]);
+    let (code, report) = finish(child, &directory);
+    drop(stdin);
+    assert_eq!(code, Some(0));
+    assert!(report.contains(r#""reason":"signal","code":0"#), "{report}");
+}
@@ -0,0 +1,153 @@
+//! A camera whose auto exposure jumps: the exposure it reports doubles at once and the picture
+//! overshoots, everything brighter for two frames, before it settles back. A ball crosses the
+//! room later on, or during the jump. Like motion-detect, the runs follow the camera's metadata
+//! with `ExposureCompensation` and compare with the pixel threshold it raises.
+
+use std::time::{ Duration, Instant };
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff },
+    exposure::ExposureCompensation,
+    motion::{ MotionEvent, MotionTracker },
+    source::{ FrameMetadata, FrameSource },
+    thumbnail::{ PixelLayout, Thumbnail },
+};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 120;
+const INTERVAL: Duration = Duration::from_millis(100);
+const DOWNSAMPLE: usize = 8;
+const PIXEL_THRESHOLD: i32 = 25;
+const IMAGE_THRESHOLD: f32 = 0.02;
+const TAIL: Duration = Duration::from_secs(1);
+const FRAMES: u64 = 300;
+
+// The room, and how much brighter it gets while the exposure overshoots.
+const BACKGROUND: u8 = 90;
+const OVERSHOOT: u8 = 40;
+const BALL: u8 = 230;
+
+// The frame the exposure jumps at, and the frames the picture overshoots for.
+const JUMP: u64 = 100;
+const OVERSHOOT_FRAMES: u64 = 2;
+
+// As --exposure-jump 25 and --exposure-raise 10 make it.
+const EXPOSURE_JUMP: f64 = 0.25;
+const EXPOSURE_RAISE: i32 = 25;
+
+// The ball, in frame pixels and frame pixels per frame, and for how many frames it crosses.
+const RADIUS: i64 = 16;
+const SPEED: i64 = 32;
+const CROSSING: u64 = 6;
+
+
+/// A gray room, brighter for a moment after the exposure jumped, with a ball crossing it from
+/// `crossing` on. Reports its exposure, or nothing without `metadata`.
+struct AutoExposureCamera {
+    metadata: bool,
+    crossing: u64,
+    frame: Vec<u8>,
+    index: u64,
+}
+
+
+impl AutoExposureCamera {
+
+    fn new(metadata: bool, crossing: u64) -> Self {
+        Self { metadata, crossing, frame: vec![0; WIDTH * HEIGHT], index: 0 }
+    }
+
+    // The ball's center, None while it's out of the room.
+    fn ball(&self) -> Option<(i64, i64)> {
+        (self.crossing .. self.crossing + CROSSING).contains(&self.index)
+            .then(|| ((self.index - self.crossing) as i64 * SPEED, HEIGHT as i64 / 2))
+    }
+}
+
+
+impl FrameSource for AutoExposureCamera {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        if self.index == FRAMES {
+            return Ok(None);
+        }
+        let background = match (JUMP .. JUMP + OVERSHOOT_FRAMES).contains(&self.index) {
+            true => BACKGROUND + OVERSHOOT,
+            false => BACKGROUND,
+        };
+        let ball = self.ball();
+        for (index, pixel) in self.frame.iter_mut().enumerate() {
+            let (x, y) = ((index % WIDTH) as i64, (index / WIDTH) as i64);
+            let inside = ball.is_some_and(|(cx, cy)| (x - cx).pow(2) + (y - cy).pow(2) <= RADIUS.pow(2));
+            *pixel = if inside { BALL } else { background };
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+
+    // In 100µs, as V4L2 reports it: 10ms, then 20ms from the jump on.
+    fn metadata(&self) -> FrameMetadata {
+        let exposure = if self.index > JUMP { 200.0 } else { 100.0 };
+        match self.metadata {
+            true => FrameMetadata { exposure: Some(exposure), gain: Some(0.0) },
+            false => FrameMetadata::default(),
+        }
+    }
+}
+
+
+// The frames movements started at, over a run of the camera.
+fn starts(mut camera: AutoExposureCamera) -> Vec<u64> {
+    let mut thumb = Thumbnail::with_channels(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE, 1);
+    let start_count = (thumb.len() as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy = FrameDiff::new(PIXEL_THRESHOLD, start_count);
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+    let mut exposure = ExposureCompensation::new(EXPOSURE_JUMP, EXPOSURE_RAISE);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let mut events = Vec::new();
+    let mut frames = 0;
+    while let Some(frame) = camera.next_frame().unwrap() {
+        thumb.downsample(frame, WIDTH, DOWNSAMPLE, PixelLayout::Gray);
+        exposure.update(camera.metadata());
+        strategy.raise_pixel_threshold(exposure.raise());
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        events.extend(motion.update(result.changed_pixels, result.score, start + INTERVAL * frames as u32));
+        frames += 1;
+    }
+    events.extend(motion.finish(start + INTERVAL * frames as u32));
+    events.iter()
+        .filter_map(|event| match event {
+            MotionEvent::Start { at, .. } => Some((at.duration_since(start).as_millis() / INTERVAL.as_millis()) as u64),
+            _ => None,
+        })
+        .collect()
+}
+
+
+// Asserts there's a start within each crossing, and no other.
+fn assert_crossings(starts: &[u64], expected: &[u64]) {
+    let matches = starts.len() == expected.len()
+        && starts.iter().zip(expected).all(|(start, first)| (*first .. first + CROSSING).contains(start));
+    assert!(matches, "Expected movements from frames {expected:?}, got them at {starts:?}");
+}
+
+
+#[test]
+fn without_metadata_the_overshoot_is_a_movement() {
+    assert_crossings(&starts(AutoExposureCamera::new(false, 200)), &[JUMP, 200]);
+}
+
+
+#[test]
+fn with_metadata_only_the_ball_is() {
+    assert_crossings(&starts(AutoExposureCamera::new(true, 200)), &[200]);
+}
+
+
+#[test]
+fn a_ball_crossing_during_the_jump_is_still_seen() {
+    assert_crossings(&starts(AutoExposureCamera::new(true, JUMP)), &[JUMP]);
+}
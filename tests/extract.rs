@@ -0,0 +1,90 @@
+//! Event contexts saved as --capture-context-on-event does, by a `ContextCapture`, once of RGB
+//! thumbnails and once of smaller luma ones as with another --downsample, then written out as
+//! images by the extract command: scaled up with nearest neighbor, the changed pixels tinted red.
+
+use std::{
+    env, fs,
+    path::{ Path, PathBuf },
+    process::{ self, Command },
+    time::{ Duration, UNIX_EPOCH },
+};
+
+use motion_detect::{ context::ContextCapture, json, thumbnail::Thumbnail };
+
+const SCALE: usize = 4;
+
+
+fn directory(test: &str, name: &str) -> PathBuf {
+    env::temp_dir().join(format!("motion-detect-extract-{test}-{name}-{}", process::id()))
+}
+
+
+// A gray thumbnail, and one with the pixel at (1, 1) lit, the one the mask has changed.
+fn save(contexts: &Path, id: u64, width: usize, height: usize, channels: usize) {
+    let mut capture = ContextCapture::new(contexts.to_path_buf());
+    let mut previous = Thumbnail::with_channels(width, height, channels);
+    previous.pixels.fill(100);
+    capture.compared(&previous, false);
+    let mut current = previous.clone();
+    let lit = width + 1;
+    current.pixels[lit * channels .. (lit + 1) * channels].fill(200);
+    let mut mask = vec![0; width * height];
+    mask[lit] = 1;
+    let sidecar = json::Object::new().field("changed_pixels", 1u64).field("start_pixels", 0u64);
+    capture.save(id, UNIX_EPOCH + Duration::from_secs(1_714_570_000 + id), &current, &mask, sidecar, "{}").unwrap();
+}
+
+
+fn extract(contexts: &Path, output: &Path) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_motion-detect"))
+        .args(["extract", contexts.to_str().unwrap(), "--output", output.to_str().unwrap(), "--downsample", &SCALE.to_string()])
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+
+fn pixel(image: &Thumbnail, x: usize, y: usize) -> &[u8] {
+    &image.pixels[(y * image.width + x) * 3 ..][.. 3]
+}
+
+
+#[test]
+fn each_context_is_written_scaled_up_with_its_changes_tinted() {
+    let (contexts, output) = (directory("scaled", "contexts"), directory("scaled", "images"));
+    let _ = fs::remove_dir_all(&contexts);
+    let _ = fs::remove_dir_all(&output);
+    save(&contexts, 1, 8, 6, 3);
+    save(&contexts, 2, 4, 3, 1);
+    assert_eq!(extract(&contexts, &output), Some(0));
+
+    for (id, width, height) in [(1, 8, 6), (2, 4, 3)] {
+        let name = format!("{}-{id}", 1_714_570_000 + id);
+        let previous = Thumbnail::read_pnm(&output.join(format!("{name}-previous.ppm"))).unwrap();
+        let current = Thumbnail::read_pnm(&output.join(format!("{name}-current.ppm"))).unwrap();
+        for image in [&previous, &current] {
+            assert_eq!((image.width, image.height, image.channels), (width * SCALE, height * SCALE, 3));
+        }
+        // The lit pixel is a block of SCALE by SCALE, tinted, and nothing else is.
+        for (x, y) in [(SCALE, SCALE), (SCALE * 2 - 1, SCALE * 2 - 1)] {
+            assert_eq!(pixel(&previous, x, y), [100; 3]);
+            assert_eq!(pixel(&current, x, y), [227, 100, 100]);
+        }
+        for (x, y) in [(SCALE - 1, SCALE), (SCALE * 2, SCALE), (0, 0)] {
+            assert_eq!(pixel(&current, x, y), [100; 3]);
+        }
+    }
+    let _ = fs::remove_dir_all(&contexts);
+    let _ = fs::remove_dir_all(&output);
+}
+
+
+#[test]
+fn a_directory_without_contexts_is_refused() {
+    let (contexts, output) = (directory("empty", "contexts"), directory("empty", "images"));
+    fs::create_dir_all(&contexts).unwrap();
+    assert_eq!(extract(&contexts, &output), Some(22));
+    assert!(!output.exists());
+    let _ = fs::remove_dir_all(&contexts);
+}
@@ -0,0 +1,182 @@
+//! A camera that comes back from each reconnect in another pixel format: RGB3 first, then YUYV,
+//! handed over as luma like `Camera` does, then BGRA. A ball crosses the room once per session.
+//! Like motion-detect, the run follows the format with `StreamFormat` after every reconnect and
+//! starts the detector over when it changed. Time is the scene's own, one interval per frame.
+
+use std::time::{ Duration, Instant };
+
+use eye::hal::{ format::PixelFormat, stream::Descriptor };
+
+use motion_detect::{
+    camera::StreamFormat,
+    diff::{ DiffStrategy, FrameDiff },
+    motion::{ MotionEvent, MotionTracker },
+    source::FrameSource,
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 120;
+const INTERVAL: Duration = Duration::from_millis(100);
+const DOWNSAMPLE: usize = 8;
+const PIXEL_THRESHOLD: i32 = 25;
+const IMAGE_THRESHOLD: f32 = 0.02;
+const TAIL: Duration = Duration::from_secs(1);
+
+// The ball, in frame pixels and frame pixels per frame.
+const RADIUS: i64 = 16;
+const SPEED: i64 = 32;
+
+// Frames of each session, and the one the ball starts crossing at and for how long.
+const SESSION_FRAMES: u64 = 200;
+const CROSSING: (u64, u64) = (80, 6);
+
+
+/// A gray room with a lighter floor and the ball while it crosses, in the pixel format of the
+/// current session. Each session ends with the stream dying, until the last one.
+struct SwitchingCamera {
+    sessions: Vec<PixelFormat>,
+    session: usize,
+    descriptor: Descriptor,
+    frame: Vec<u8>,
+    index: u64,     // Frame of the session.
+}
+
+
+impl SwitchingCamera {
+
+    fn new(sessions: Vec<PixelFormat>) -> Self {
+        let descriptor = Descriptor { width: WIDTH as u32, height: HEIGHT as u32, interval: INTERVAL, pixfmt: sessions[0].clone() };
+        Self { sessions, session: 0, descriptor, frame: Vec::new(), index: 0 }
+    }
+
+    // The ball's center, None while it's out of the room.
+    fn ball(&self) -> Option<(i64, i64)> {
+        let (first, length) = CROSSING;
+        (first .. first + length).contains(&self.index)
+            .then(|| ((self.index - first) as i64 * SPEED, HEIGHT as i64 / 2))
+    }
+}
+
+
+impl FrameSource for SwitchingCamera {
+
+    fn next_frame(&mut self) -> Result<Option<&[u8]>, String> {
+        if self.index == SESSION_FRAMES {
+            return match self.session + 1 == self.sessions.len() {
+                true => Ok(None),
+                false => Err(String::from("stream is dead")),
+            };
+        }
+        let ball = self.ball();
+        // Luma for YUYV, as `Camera` converts it, R, G, B for RGB3 and B, G, R, X for BGRA.
+        let bytes = match &self.descriptor.pixfmt {
+            PixelFormat::Custom(fourcc) if fourcc == "YUYV" => 1,
+            PixelFormat::Bgr(32) => 4,
+            _ => 3,
+        };
+        self.frame.resize(WIDTH * HEIGHT * bytes, 0);
+        for (index, pixel) in self.frame.chunks_exact_mut(bytes).enumerate() {
+            let (x, y) = ((index % WIDTH) as i64, (index / WIDTH) as i64);
+            let inside = ball.is_some_and(|(cx, cy)| (x - cx).pow(2) + (y - cy).pow(2) <= RADIUS.pow(2));
+            pixel.fill(match (inside, y > HEIGHT as i64 * 2 / 3) {
+                (true, _) => 230,
+                (false, true) => 120,
+                (false, false) => 80,
+            });
+            if bytes == 4 {
+                pixel[3] = 0;
+            }
+        }
+        self.index += 1;
+        Ok(Some(&self.frame))
+    }
+
+    fn can_reconnect(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.session += 1;
+        self.index = 0;
+        self.descriptor.pixfmt = self.sessions.get(self.session).cloned().ok_or("no more sessions")?;
+        Ok(())
+    }
+
+    fn stream(&self) -> Option<&Descriptor> {
+        Some(&self.descriptor)
+    }
+}
+
+
+fn detector(format: &StreamFormat) -> (Thumbnail, FrameDiff) {
+    let thumb = Thumbnail::with_channels(WIDTH / DOWNSAMPLE, HEIGHT / DOWNSAMPLE, format.layout.channels());
+    let start_count = (thumb.len() as f32 * IMAGE_THRESHOLD) as i32;
+    (thumb, FrameDiff::new(PIXEL_THRESHOLD, start_count))
+}
+
+
+// The frames movements started on, with the frame each session started at.
+fn run(sessions: Vec<PixelFormat>) -> (Vec<u64>, usize, Vec<u64>) {
+    let mut source = SwitchingCamera::new(sessions);
+    let mut format = StreamFormat::new(source.stream().unwrap(), None);
+    let (mut thumb, mut strategy) = detector(&format);
+    let start_count = (thumb.len() as f32 * IMAGE_THRESHOLD) as i32;
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let mut events = Vec::new();
+    let mut frames = 0;
+    let mut session_starts = vec![0];
+    loop {
+        let frame = match source.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(reason) => {
+                source.reconnect().unwrap_or_else(|err| panic!("Can't reconnect after {reason}: {err}"));
+                if format.follow(source.stream().unwrap(), None).is_some() {
+                    (thumb, strategy) = detector(&format);
+                }
+                session_starts.push(frames);
+                continue;
+            }
+        };
+        thumb.downsample(frame, WIDTH, DOWNSAMPLE, format.layout);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        events.extend(motion.update(result.changed_pixels, result.score, start + INTERVAL * frames as u32));
+        frames += 1;
+    }
+    events.extend(motion.finish(start + INTERVAL * frames as u32));
+
+    let starts = events.iter()
+        .filter_map(|event| match event {
+            MotionEvent::Start { at, .. } => Some((at.duration_since(start).as_millis() / INTERVAL.as_millis()) as u64),
+            _ => None,
+        })
+        .collect();
+    let stops = events.iter().filter(|event| matches!(event, MotionEvent::Stop { .. })).count();
+    (starts, stops, session_starts)
+}
+
+
+#[test]
+fn each_session_is_one_movement_whatever_its_format() {
+    let sessions = vec![PixelFormat::Rgb(24), PixelFormat::Custom(String::from("YUYV")), PixelFormat::Bgr(32)];
+    let (starts, stops, session_starts) = run(sessions.clone());
+    assert_eq!(session_starts, [0, SESSION_FRAMES, 2 * SESSION_FRAMES]);
+    // Each movement starts on a frame of its session's crossing, none at a switch.
+    assert_eq!(starts.len(), sessions.len(), "{starts:?}");
+    for (frame, first) in starts.iter().zip(&session_starts) {
+        assert!((first + CROSSING.0 .. first + CROSSING.0 + CROSSING.1).contains(frame), "{starts:?}");
+    }
+    assert_eq!(stops, sessions.len());
+}
+
+
+#[test]
+fn a_reconnect_in_the_same_format_keeps_the_detector() {
+    let (starts, stops, _) = run(vec![PixelFormat::Rgb(24); 2]);
+    assert_eq!((starts.len(), stops), (2, 2));
+}
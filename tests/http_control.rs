@@ -0,0 +1,124 @@
+//! POST /control/... of the HTTP server, answered by a detector reading synthetic frames, as the
+//! binary's main loop answers them between frames.
+
+mod common;
+
+use std::{
+    io::{ Read, Write },
+    net::TcpStream,
+    sync::{ atomic::{ AtomicBool, Ordering }, Arc },
+    thread::{ self, JoinHandle },
+    time::Duration,
+};
+
+use common::{ Scene, HEIGHT, WIDTH };
+use motion_detect::{
+    control::{ self, ControlState },
+    detector::MotionDetector,
+    http::HttpServer,
+    thumbnail::PixelLayout,
+};
+
+const TOKEN: &str = "s3cret";
+
+
+// A server with control enabled, and the detector taking its commands until it's told to stop.
+struct Setup {
+    server: HttpServer,
+    stop: Arc<AtomicBool>,
+    detector: Option<JoinHandle<ControlState>>,
+}
+
+
+impl Setup {
+
+    fn new() -> Self {
+        let settings = common::settings();
+        let (sender, requests) = control::channel(settings.control_bounds);
+        let server = HttpServer::start("127.0.0.1:0").unwrap();
+        server.enable_control(TOKEN.to_string(), sender);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let detector = thread::spawn(move || {
+            let mut detector = MotionDetector::new(&settings, WIDTH, HEIGHT, PixelLayout::Rgb);
+            let mut scene = Scene::new(u64::MAX, Vec::new());
+            while !stopped.load(Ordering::Relaxed) {
+                detector.next_events(&mut scene).unwrap();
+                for request in requests.try_iter() {
+                    let state = detector.apply(&request.command).unwrap_or_else(|_| detector.state());
+                    request.reply(state);
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            detector.state()
+        });
+        Self { server, stop, detector: Some(detector) }
+    }
+
+    // Sends a request and reads the answer until the server closes the connection.
+    fn post(&self, command: &str, authorization: Option<&str>, body: &str) -> String {
+        let authorization = authorization.map(|token| format!("Authorization: Bearer {token}\r\n")).unwrap_or_default();
+        self.exchange(&format!(
+            "POST /control/{command} HTTP/1.1\r\nHost: test\r\n{authorization}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        ))
+    }
+
+    fn exchange(&self, request: &str) -> String {
+        let mut stream = TcpStream::connect(self.server.local_addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut answer = String::new();
+        let _ = stream.read_to_string(&mut answer);
+        answer
+    }
+
+    // The detector's state once it stopped.
+    fn finish(mut self) -> ControlState {
+        self.stop.store(true, Ordering::Relaxed);
+        self.detector.take().unwrap().join().unwrap()
+    }
+}
+
+
+#[test]
+fn commands_are_applied_by_the_detector_and_answered_with_its_state() {
+    let setup = Setup::new();
+    let answer = setup.post("pause", Some(TOKEN), "");
+    assert!(answer.starts_with("HTTP/1.1 200 OK"), "{answer}");
+    assert!(answer.contains(r#""type":"control","paused":true"#), "{answer}");
+    let answer = setup.post("set", Some(TOKEN), r#"{"pixel_threshold": 8, "image_threshold": 15}"#);
+    assert!(answer.contains(r#""pixel_threshold":8.000,"image_threshold":15.000"#), "{answer}");
+    assert!(setup.post("resume", Some(TOKEN), "").contains(r#""paused":false"#));
+    let state = setup.finish();
+    assert_eq!((state.paused, state.pixel_threshold, state.image_threshold), (false, 8.0, 15.0));
+}
+
+
+#[test]
+fn requests_without_the_token_change_nothing() {
+    let setup = Setup::new();
+    assert!(setup.post("pause", None, "").starts_with("HTTP/1.1 401 Unauthorized"));
+    assert!(setup.post("pause", Some("guess"), "").starts_with("HTTP/1.1 401 Unauthorized"));
+    assert!(setup.post("set", Some("s3cre"), r#"{"pixel_threshold": 8}"#).starts_with("HTTP/1.1 401 Unauthorized"));
+    let state = setup.finish();
+    assert!(!state.paused);
+    assert_eq!(state.pixel_threshold, common::settings().pixel_threshold);
+}
+
+
+#[test]
+fn malformed_requests_are_refused_before_reaching_the_detector() {
+    let setup = Setup::new();
+    let refused = [
+        (setup.post("set", Some(TOKEN), r#"{"pixel_threshold": "high"}"#), "400 Bad Request"),
+        (setup.post("set", Some(TOKEN), "pixel_threshold=8"), "400 Bad Request"),
+        (setup.post("set", Some(TOKEN), r#"{"frame_rate": 8}"#), "400 Bad Request"),
+        (setup.post("explode", Some(TOKEN), ""), "404 Not Found"),
+        (setup.exchange(&format!("GET /control/pause HTTP/1.1\r\nAuthorization: Bearer {TOKEN}\r\n\r\n")), "405 Method Not Allowed"),
+    ];
+    for (answer, status) in &refused {
+        assert!(answer.starts_with(&format!("HTTP/1.1 {status}")), "{status}: {answer}");
+    }
+    let state = setup.finish();
+    assert_eq!((state.paused, state.pixel_threshold), (false, common::settings().pixel_threshold));
+}
@@ -0,0 +1,103 @@
+//! Masks drawn by the make-mask command and loaded back as --mask does: the bar of a timestamp
+//! overlay along the top and a tree in the bottom right corner ignored, or only a doorway watched,
+//! behind a diff in which every pixel changes.
+
+use std::{ env, fs, path::PathBuf, process::{ self, Command } };
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff, Masked },
+    mask::MaskImage,
+    settings::Settings,
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 60;
+const BACKGROUND: u8 = 90;
+
+// The band is 800 pixels, the tree 400.
+const IGNORED: [&str; 4] = ["--ignore", "0,0,80,10", "--ignore", "60,40,20,20"];
+
+
+fn output(test: &str) -> PathBuf {
+    env::temp_dir().join(format!("motion-detect-make-mask-{test}-{}.pgm", process::id()))
+}
+
+
+fn settings(args: &[&str]) -> Settings {
+    Settings::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+}
+
+
+// The mask make-mask draws for its arguments, without writing it.
+fn drawn(args: &[&str]) -> MaskImage {
+    let settings = settings(&[&["make-mask", "--output", "unused.pgm"], args].concat());
+    let (width, height) = settings.mask_grid();
+    MaskImage::from_rects(width, height, &settings.mask_rects).unwrap()
+}
+
+
+// The changed pixels and their mask when every pixel changes against a plain gray reference.
+fn changed_everywhere(mask: MaskImage) -> (i32, Vec<u8>) {
+    let mut strategy = Masked::new(Box::new(FrameDiff::new(10, 0)), mask);
+    let mut thumb = Thumbnail::with_channels(WIDTH, HEIGHT, 1);
+    thumb.pixels.fill(BACKGROUND);
+    strategy.process(&thumb);
+    thumb.pixels.fill(BACKGROUND + 100);
+    let result = strategy.process(&thumb);
+    (result.changed_pixels, result.mask.to_vec())
+}
+
+
+#[test]
+fn a_mask_written_by_make_mask_loads_back_and_hides_what_it_ignores() {
+    let path = output("ignored");
+    let status = Command::new(env!("CARGO_BIN_EXE_motion-detect"))
+        .args([&["make-mask", "--size", "80x60"], &IGNORED[..], &["--output", path.to_str().unwrap()]].concat())
+        .output()
+        .unwrap();
+    assert!(status.status.success(), "{}", String::from_utf8_lossy(&status.stdout));
+    let loaded = settings(&["--mask", path.to_str().unwrap()]).mask;
+    let _ = fs::remove_file(&path);
+    let loaded = loaded.expect("The mask was loaded");
+    assert_eq!(loaded, drawn(&[&["--size", "80x60"], &IGNORED[..]].concat()));
+
+    let (changed, mask) = changed_everywhere(loaded.clone());
+    assert_eq!(changed, 4800 - 1200);
+    assert!((0 .. WIDTH * HEIGHT).all(|index| mask[index] == 0 || loaded.weights[index] != 0));
+}
+
+
+#[test]
+fn percentages_draw_the_same_rectangles_as_pixels() {
+    let percent = drawn(&["--size", "80x60", "--ignore", "0%,0%,100%,16.67%", "--ignore", "75%,66.67%,25%,33.33%"]);
+    assert_eq!(percent, drawn(&[&["--size", "80x60"], &IGNORED[..]].concat()));
+}
+
+
+#[test]
+fn the_grid_follows_the_capture_size_and_downsample() {
+    let settings = settings(&["make-mask", "--input-size", "1280x720", "--downsample", "16", "--ignore", "0,0,1,1", "--output", "unused.pgm"]);
+    assert_eq!(settings.mask_grid(), (80, 45));
+}
+
+
+#[test]
+fn watch_ignores_everything_else() {
+    // The doorway is 800 pixels.
+    let watched = drawn(&["--size", "80x60", "--watch", "30,20,20,40"]);
+    assert_eq!(watched.coverage(), 1.0 - 800.0 / 4800.0);
+    assert_eq!(changed_everywhere(watched).0, 800);
+}
+
+
+#[test]
+fn a_rectangle_off_the_grid_is_refused() {
+    let path = output("refused");
+    let status = Command::new(env!("CARGO_BIN_EXE_motion-detect"))
+        .args(["make-mask", "--size", "80x60", "--ignore", "90,0,10,10", "--output", path.to_str().unwrap()])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(22));
+    assert!(!path.exists());
+}
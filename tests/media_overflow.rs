@@ -0,0 +1,145 @@
+//! A burst of ten movements on a card too slow for their snapshots: a `MediaQueue` of 3 jobs in
+//! front of a sink that only takes a job once it's let go, or that takes 50ms a job, with each
+//! --media-overflow policy. Then a snapshot of a `Snapshots` queued while the next frames come in.
+
+use std::{
+    env, fs, process,
+    sync::{ mpsc, Arc, Mutex },
+    thread,
+    time::{ Duration, Instant, SystemTime },
+};
+
+use motion_detect::{
+    media::{ MediaJob, MediaQueue },
+    settings::MediaOverflow,
+    snapshot::Snapshots,
+    thumbnail::PixelLayout,
+    zones::ZoneMasks,
+};
+
+const CAPACITY: usize = 3;
+const BURST: u64 = 10;
+const FRAME_BYTES: usize = 64 * 48 * 3;
+const SLOW_WRITE: Duration = Duration::from_millis(50);
+
+
+/// What a burst came to: the movements written in order, those skipped, how long the longest
+/// push took, and what the queue counted.
+struct Burst {
+    written: Vec<u64>,
+    skipped: Vec<u64>,
+    longest_push: Duration,
+    dropped: u64,
+}
+
+
+// Pushes the burst, the first job held by the sink until the rest were pushed with `held`, or
+// every job taking SLOW_WRITE otherwise.
+fn burst(overflow: MediaOverflow, wait: Duration, held: bool) -> Burst {
+    let queue = MediaQueue::new(CAPACITY, overflow, wait);
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let (release, gate) = mpsc::channel::<()>();
+    let mut gate = Some(gate);
+    let mut longest_push = Duration::ZERO;
+    for id in 1 ..= BURST {
+        let written = written.clone();
+        let gate = if held { gate.take() } else { None };
+        let job = MediaJob::new(Some(id), Vec::new(), Arc::new(vec![0; FRAME_BYTES]), move |_frame| {
+            match gate {
+                Some(gate) => {
+                    let _ = gate.recv();
+                }
+                None => thread::sleep(SLOW_WRITE),
+            }
+            written.lock().unwrap().push(id);
+            Vec::new()
+        });
+        let pushed_at = Instant::now();
+        queue.push(job);
+        longest_push = longest_push.max(pushed_at.elapsed());
+        // The sink has the first job before the next comes, so the queue fills after it.
+        while id == 1 && queue.queued_bytes() > 0 {
+            thread::yield_now();
+        }
+    }
+    drop(release);
+    let skipped = (1 ..= BURST).filter(|id| queue.take_skipped(*id)).collect();
+    let dropped = queue.dropped();
+    queue.finish(Duration::from_secs(5));
+    let written = written.lock().unwrap().clone();
+    Burst { written, skipped, longest_push, dropped }
+}
+
+
+/// Whether a snapshot queued behind a held sink is of the frame it was saved on, once two more
+/// frames came in, and what the queue held meanwhile.
+fn shared_frame() -> (bool, usize) {
+    let directory = env::temp_dir().join(format!("motion-detect-media-overflow-{}", process::id()));
+    let mut snapshots = Snapshots::new(Some(directory.clone()), "{id}-{zone}", true, 64, 48, 3);
+    let queue = MediaQueue::new(CAPACITY, MediaOverflow::DropNewest, Duration::ZERO);
+    let (release, gate) = mpsc::channel::<()>();
+    queue.push(MediaJob::new(None, Vec::new(), Arc::new(vec![0]), move |_frame| {
+        let _ = gate.recv();
+        Vec::new()
+    }));
+    while queue.queued_bytes() > 0 {
+        thread::yield_now();
+    }
+    snapshots.capture(&[200; FRAME_BYTES], PixelLayout::Rgb);
+    let job = snapshots.save(1, SystemTime::now(), &[], &ZoneMasks::default(), &[], 8).expect("A frame was captured");
+    let file = job.files[0].path.clone();
+    queue.push(job);
+    snapshots.capture(&[10; FRAME_BYTES], PixelLayout::Rgb);
+    snapshots.capture(&[20; FRAME_BYTES], PixelLayout::Rgb);
+    let held = queue.queued_bytes();
+    drop(release);
+    queue.finish(Duration::from_secs(5));
+    let image = fs::read(&file).unwrap_or_default();
+    let _ = fs::remove_dir_all(&directory);
+    (image.len() > FRAME_BYTES && image[image.len() - FRAME_BYTES ..].iter().all(|byte| *byte == 200), held)
+}
+
+
+#[test]
+fn drop_newest_writes_the_first_jobs_and_drops_the_rest() {
+    let burst = burst(MediaOverflow::DropNewest, Duration::ZERO, true);
+    assert_eq!(burst.written, [1, 2, 3, 4]);
+    assert_eq!(burst.skipped, [5, 6, 7, 8, 9, 10]);
+    assert_eq!(burst.dropped, 6);
+}
+
+
+#[test]
+fn drop_oldest_writes_the_first_and_the_last_jobs() {
+    // The first was with the sink already.
+    let burst = burst(MediaOverflow::DropOldest, Duration::ZERO, true);
+    assert_eq!(burst.written, [1, 8, 9, 10]);
+    assert_eq!(burst.skipped, [2, 3, 4, 5, 6, 7]);
+    assert_eq!(burst.dropped, 6);
+}
+
+
+#[test]
+fn block_writes_every_job_of_a_sink_that_keeps_up() {
+    let burst = burst(MediaOverflow::Block, Duration::from_secs(2), false);
+    assert_eq!(burst.written, (1 ..= BURST).collect::<Vec<_>>());
+    assert_eq!((burst.dropped, burst.skipped), (0, Vec::new()));
+    assert!(burst.longest_push >= SLOW_WRITE / 2, "{:?}", burst.longest_push);
+}
+
+
+#[test]
+fn block_waits_no_longer_than_the_media_wait() {
+    let burst = burst(MediaOverflow::Block, Duration::from_millis(20), true);
+    assert_eq!(burst.written, [1, 2, 3, 4]);
+    assert_eq!(burst.dropped, 6);
+    assert!((Duration::from_millis(20) .. Duration::from_millis(500)).contains(&burst.longest_push), "{:?}", burst.longest_push);
+}
+
+
+#[test]
+fn a_queued_snapshot_is_of_its_own_frame_held_by_the_queue_alone() {
+    let (own_frame, held) = shared_frame();
+    assert!(own_frame);
+    assert_eq!(held, FRAME_BYTES);
+}
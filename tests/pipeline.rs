@@ -0,0 +1,192 @@
+//! Synthetic frames through the whole detection pipeline, locking in what the default
+//! `FrameDiff` and the `MotionTracker` make of them.
+
+mod common;
+
+use std::time::{ Duration, Instant };
+
+use common::{ Scene, Shape, HEIGHT, INTERVAL, WIDTH };
+use motion_detect::{
+    diff::{ self, DiffStrategy, FrameDiff },
+    motion::{ MotionEvent, MotionTracker, StopReason },
+    settings::Settings,
+    source::FrameSource,
+    thumbnail::{ PixelLayout, ThumbStats, Thumbnail },
+};
+
+// The 10s long scene, the first 10 frames warming up, then the baseline.
+const FRAMES: u64 = 100;
+const WARM_UP: u64 = 10;
+
+
+// A box covering a quarter of the picture, half of it across and down, on `frames`.
+fn quarter_box(frames: std::ops::Range<u64>) -> Shape {
+    Shape::new((8, 12), (40, 36), 200, frames)
+}
+
+
+// An LED panel on a quarter of the picture, between lit and dark every other frame all along.
+fn flickering_panel() -> Vec<Shape> {
+    (0 .. FRAMES).step_by(2).map(|frame| quarter_box(frame .. frame + 1)).collect()
+}
+
+
+// A 2 by 2 dot crossing the frame, 3 pixels a frame.
+fn dot(frames: std::ops::Range<u64>) -> Vec<Shape> {
+    let first = frames.start;
+    frames.map(|frame| {
+        let left = (frame - first) as usize * 3 % (WIDTH - 2);
+        Shape::new((left, 20), (left + 2, 22), 250, frame .. frame + 1)
+    }).collect()
+}
+
+
+// What an event says apart from its times, with the frame that caused it.
+#[derive(Debug, PartialEq)]
+enum Seen {
+    Start { frame: u64, id: u64, pre_existing: bool },
+    Stop { frame: u64, id: u64, reason: StopReason, duration: Duration, peak: f32, frames: u32 },
+    Other { frame: u64, id: u64 },
+}
+
+
+fn seen(events: &[(u64, MotionEvent)]) -> Vec<Seen> {
+    events.iter().map(|(frame, event)| match *event {
+        MotionEvent::Start { id, pre_existing, .. } => Seen::Start { frame: *frame, id, pre_existing },
+        MotionEvent::Stop { id, reason, duration, stats, .. } => Seen::Stop { frame: *frame, id, reason, duration, peak: stats.peak, frames: stats.frames },
+        event => Seen::Other { frame: *frame, id: event.id() },
+    }).collect()
+}
+
+
+// The loop motion-detect ran before strategies could be picked: each frame after the warm-up
+// downsampled, compared by a `FrameDiff` and fed to a `MotionTracker`, at the default thresholds.
+fn frame_diff_loop(scene: &mut Scene) -> Vec<(u64, MotionEvent)> {
+    let settings = common::settings();
+    let mut thumb = Thumbnail::new(WIDTH / settings.downsample, HEIGHT / settings.downsample);
+    let start_count = (thumb.len() as f32 * settings.image_threshold / 100.0) as i32;
+    let pixel_threshold = (settings.pixel_threshold * 255.0 / 100.0) as i32;
+    let mut strategy = FrameDiff::new(pixel_threshold, start_count);
+    let mut motion = MotionTracker::new(settings.motion_tail_length, start_count, start_count).with_frame_interval(INTERVAL);
+    let origin = Instant::now();
+    let mut events = Vec::new();
+    let mut frame = 0;
+    while let Some(pixels) = scene.next_frame().unwrap() {
+        let now = origin + INTERVAL * frame as u32;
+        if frame == WARM_UP {
+            motion.ready(now);
+        }
+        if frame >= WARM_UP {
+            thumb.downsample(pixels, WIDTH, settings.downsample, PixelLayout::Rgb);
+            strategy.set_motion_active(motion.is_active());
+            let result = strategy.process(&thumb);
+            events.extend(motion.update(result.changed_pixels, result.score, now).into_iter().map(|event| (frame, event)));
+        }
+        frame += 1;
+    }
+    events.extend(motion.finish(origin + INTERVAL * (frame - 1) as u32).map(|event| (frame, event)));
+    events
+}
+
+
+#[test]
+fn a_still_scene_is_no_movement() {
+    assert!(common::run(&common::settings(), &mut Scene::new(FRAMES, Vec::new())).is_empty());
+}
+
+
+#[test]
+fn a_box_put_down_then_taken_away_is_two_movements() {
+    let events = common::run(&common::settings(), &mut Scene::new(FRAMES, vec![quarter_box(30 .. 60)]));
+    // The box is part of the reference from its first frame on, so each movement is one frame
+    // long and lasts the tail, ending with the first frame past it.
+    let stop = |frame, id| Seen::Stop { frame, id, reason: StopReason::Tail, duration: Duration::from_millis(500), peak: 25.0, frames: 7 };
+    assert_eq!(seen(&events), [
+        Seen::Start { frame: 30, id: 1, pre_existing: false },
+        stop(36, 1),
+        Seen::Start { frame: 60, id: 2, pre_existing: false },
+        stop(66, 2),
+    ]);
+}
+
+
+#[test]
+fn a_sweeping_bar_is_one_movement_while_it_moves() {
+    let events = common::run(&common::settings(), &mut Scene::new(FRAMES, Shape::sweep(40 .. 50)));
+    assert_eq!(common::starts(&events), [40]);
+    let Some((frame, MotionEvent::Stop { duration, reason, .. })) = events.last() else {
+        panic!("The movement stopped: {events:?}");
+    };
+    // The bar is gone with frame 50, which still moves against the reference, the tail follows.
+    assert_eq!((*frame, *reason, *duration), (56, StopReason::Tail, Duration::from_millis(1500)));
+}
+
+
+#[test]
+fn a_movement_at_the_end_of_the_input_stops_with_it() {
+    let events = common::run(&common::settings(), &mut Scene::new(FRAMES, Shape::sweep(95 .. 100)));
+    assert_eq!(common::starts(&events), [95]);
+    assert!(matches!(events.last(), Some((_, MotionEvent::Stop { reason: StopReason::EndOfInput, .. }))), "{events:?}");
+}
+
+
+#[test]
+fn the_default_strategy_reproduces_the_frame_diff_loop() {
+    let shapes = || [vec![quarter_box(20 .. 35)], Shape::sweep(45 .. 52), Shape::sweep(70 .. 90)].concat();
+    let detector = common::run(&common::settings(), &mut Scene::new(FRAMES, shapes()));
+    let frame_diff = frame_diff_loop(&mut Scene::new(FRAMES, shapes()));
+    assert!(!detector.is_empty());
+    assert_eq!(seen(&detector), seen(&frame_diff));
+}
+
+
+#[test]
+fn strategies_are_made_by_name() {
+    for name in diff::STRATEGY_NAMES {
+        let strategy = diff::from_name(name, 25, 38, common::settings().adaptive_threshold(), 64, diff::Channels::Rgb);
+        assert!(strategy.is_some(), "{name}");
+    }
+    assert!(diff::from_name("optical-flow", 25, 38, common::settings().adaptive_threshold(), 64, diff::Channels::Rgb).is_none());
+}
+
+
+#[test]
+fn a_decorator_wraps_any_strategy() {
+    // Blurred, a one pixel speck spreads below the pixel threshold, unblurred it is a change.
+    let mut plain: Box<dyn DiffStrategy> = Box::new(FrameDiff::new(25, 0));
+    let mut blurred: Box<dyn DiffStrategy> = Box::new(diff::Blur::new(Box::new(FrameDiff::new(25, 0)), 1));
+    let mut thumb = Thumbnail::new(8, 8);
+    thumb.pixels.fill(100);
+    for strategy in [&mut plain, &mut blurred] {
+        strategy.process(&thumb);
+    }
+    thumb.pixels[27 * 3 .. 28 * 3].fill(160);
+    assert_eq!(plain.process(&thumb).changed_pixels, 1);
+    assert_eq!(blurred.process(&thumb).changed_pixels, 0);
+}
+
+
+#[test]
+fn flicker_rejection_ignores_a_flickering_panel_but_not_what_moves_in_front_of_it() {
+    let settings = common::settings();
+    assert!(!common::run(&settings, &mut Scene::new(FRAMES, flickering_panel())).is_empty());
+    let settings = Settings { flicker_rejection: true, ..settings };
+    let events = common::run(&settings, &mut Scene::new(FRAMES, flickering_panel()));
+    assert!(events.is_empty(), "{events:?}");
+    // The bar crossing the panel, drawn over it, starts a movement the frame it comes in. The
+    // pixels it leaves count again until they're seen flickering again.
+    let events = common::run(&settings, &mut Scene::new(FRAMES, [flickering_panel(), Shape::sweep(60 .. 70)].concat()));
+    assert_eq!(common::starts(&events).first(), Some(&60), "{events:?}");
+}
+
+
+#[test]
+fn block_extremes_catch_a_dot_the_means_average_away() {
+    // 8 by 8 blocks, in which the dot moves the mean by 10 at most, any changed pixel a movement.
+    let settings = Settings { downsample: 8, image_threshold: 0.0, ..common::settings() };
+    let events = common::run(&settings, &mut Scene::new(FRAMES, dot(40 .. 60)));
+    assert!(events.is_empty(), "{events:?}");
+    let settings = Settings { thumb_stats: ThumbStats::MeanMinMax, ..settings };
+    let events = common::run(&settings, &mut Scene::new(FRAMES, dot(40 .. 60)));
+    assert_eq!(common::starts(&events), [40]);
+}
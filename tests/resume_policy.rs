@@ -0,0 +1,90 @@
+//! A `MotionDetector` paused while a box is put down in the gray scene, and resumed after, with
+//! each --resume-policy. The box was never seen arriving: with rebaseline it's part of the fresh
+//! baseline, with report-delta it's reported once as a `ResumeDelta`. Neither starts a movement.
+
+mod common;
+
+use common::{ Scene, Shape, INTERVAL };
+use motion_detect::{
+    control::ControlCommand,
+    detector::MotionDetector,
+    motion::MotionEvent,
+    resume::{ ResumeCause, ResumeDelta },
+    scene::BoundingBox,
+    settings::{ ResumePolicy, Settings },
+    thumbnail::PixelLayout,
+};
+
+const FRAMES: u64 = 120;
+
+// The frames the detector is paused and resumed on, the one the box is put down on in between,
+// and those the bar sweeps on later.
+const PAUSE: u64 = 20;
+const BOX: u64 = 30;
+const RESUME: u64 = 50;
+const SWEEP: std::ops::Range<u64> = 80 .. 90;
+
+
+/// What a run saw: the frames movements started on, and the delta reported on resuming.
+struct Run {
+    starts: Vec<u64>,
+    delta: Option<ResumeDelta>,
+}
+
+
+fn run(policy: ResumePolicy, with_box: bool) -> Run {
+    let settings = Settings { resume_policy: policy, ..common::settings() };
+    let mut detector = MotionDetector::new(&settings, common::WIDTH, common::HEIGHT, PixelLayout::Rgb);
+    // The box a quarter of the picture, half of it across and down, the bar drawn over it.
+    let the_box = Shape::new((8, 12), (40, 36), 200, BOX .. FRAMES);
+    let shapes = with_box.then_some(the_box).into_iter().chain(Shape::sweep(SWEEP)).collect();
+    let mut source = Scene::new(FRAMES, shapes);
+    let mut run = Run { starts: Vec::new(), delta: None };
+    loop {
+        // Applied between frames, the next one read is the first paused or resumed.
+        match detector.frames() {
+            PAUSE => detector.apply(&ControlCommand::Pause).unwrap(),
+            RESUME => detector.apply(&ControlCommand::Resume).unwrap(),
+            _ => detector.state(),
+        };
+        let Ok(events) = detector.next_events(&mut source) else {
+            break;
+        };
+        if events.iter().any(|event| matches!(event, MotionEvent::Start { .. })) {
+            run.starts.push(detector.frames() - 1);
+        }
+        if let Some(delta) = detector.take_resume_delta() {
+            assert!(run.delta.replace(delta).is_none(), "A single delta");
+        }
+    }
+    run
+}
+
+
+#[test]
+fn rebaseline_takes_in_what_changed_silently() {
+    let run = run(ResumePolicy::Rebaseline, true);
+    // Only the bar starts a movement, on its first frame.
+    assert_eq!(run.starts, [SWEEP.start]);
+    assert_eq!(run.delta, None);
+}
+
+
+#[test]
+fn report_delta_reports_what_changed_once_instead_of_a_movement() {
+    let run = run(ResumePolicy::ReportDelta, true);
+    assert_eq!(run.starts, [SWEEP.start]);
+    let delta = run.delta.expect("The box was reported");
+    assert_eq!(delta.cause, ResumeCause::Resume);
+    assert_eq!(delta.fraction, 0.25);
+    assert_eq!(delta.bbox, BoundingBox { x: 12.5, y: 25.0, width: 50.0, height: 50.0 });
+    assert_eq!(delta.away, INTERVAL * (RESUME - PAUSE) as u32);
+}
+
+
+#[test]
+fn report_delta_reports_nothing_when_nothing_changed() {
+    let run = run(ResumePolicy::ReportDelta, false);
+    assert_eq!(run.starts, [SWEEP.start]);
+    assert_eq!(run.delta, None);
+}
@@ -0,0 +1,271 @@
+//! A PTZ camera going between two presets, a kitchen and a door, each a textured scene of its own
+//! with a little sensor noise, panning from one to the other over a few frames. Like motion-detect
+//! with `scene save` and `scene activate` control commands, `SceneProfiles` keeps what the
+//! adaptive detector learned on each, and takes it back when the camera is sent there.
+
+use std::{ env, fs, process, time::{ Duration, Instant, SystemTime } };
+
+use motion_detect::{
+    activity::ActivityHistogram,
+    diff::{ self, Channels, DiffStrategy },
+    motion::{ MotionEvent, MotionTracker, StopReason },
+    profile::SceneProfiles,
+    settings::Settings,
+    state::SavedState,
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 60;
+const INTERVAL: Duration = Duration::from_millis(100);
+const PIXEL_THRESHOLD: i32 = 25;
+const IMAGE_THRESHOLD: f32 = 0.02;
+const TAIL: Duration = Duration::from_millis(500);
+const FRAMES: u64 = 350;
+const BLOCK: usize = 4;
+// As --scene-settle 1s, longer than a pan.
+const SETTLE: Duration = Duration::from_secs(1);
+const PAN_FRAMES: u64 = 5;
+
+// Where the camera is sent: the door at first without a scene to activate, both are saved once
+// the camera had a look, then back to the kitchen and again to the door, each time activated.
+const SAVE_KITCHEN: u64 = 30;
+const TO_DOOR: u64 = 50;
+const SAVE_DOOR: u64 = 100;
+const BACK_TO_KITCHEN: u64 = 150;
+const AGAIN_TO_DOOR: u64 = 250;
+
+// The ball crossing the door, in thumbnail pixels and pixels per frame.
+const CROSSING: u64 = 300;
+const CROSSING_FRAMES: u64 = 10;
+const RADIUS: i64 = 6;
+const SPEED: i64 = 8;
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+/// Everything the camera can point at, blocks of random shades, the kitchen on the left and the
+/// door on the right.
+struct Panorama {
+    width: usize,
+    pixels: Vec<u8>,
+}
+
+
+impl Panorama {
+
+    fn new(random: &mut Random) -> Self {
+        let width = 2 * WIDTH;
+        let blocks: Vec<u8> = (0 .. width.div_ceil(BLOCK) * HEIGHT.div_ceil(BLOCK)).map(|_| 40 + random.below(160) as u8).collect();
+        let mut pixels = vec![0; width * HEIGHT];
+        for y in 0 .. HEIGHT {
+            for x in 0 .. width {
+                pixels[y * width + x] = blocks[(y / BLOCK) * width.div_ceil(BLOCK) + x / BLOCK];
+            }
+        }
+        Self { width, pixels }
+    }
+
+    // The window `left` pixels from the left, with noise and the ball if it's there.
+    fn thumbnail(&self, left: usize, ball: Option<(i64, i64)>, random: &mut Random) -> Thumbnail {
+        let mut thumb = Thumbnail::with_channels(WIDTH, HEIGHT, 1);
+        for y in 0 .. HEIGHT {
+            for x in 0 .. WIDTH {
+                let inside = ball.is_some_and(|(cx, cy)| (x as i64 - cx).pow(2) + (y as i64 - cy).pow(2) <= RADIUS.pow(2));
+                thumb.pixels[y * WIDTH + x] = match inside {
+                    true => 250,
+                    false => self.pixels[y * self.width + left + x].saturating_add(random.below(4) as u8),
+                };
+            }
+        }
+        thumb
+    }
+}
+
+
+// Where the camera points on a frame: the kitchen at 0, the door at WIDTH, in between while panning.
+fn left(index: u64) -> usize {
+    let pan = |from: usize, to: usize, start: u64| {
+        let done = (index - start).min(PAN_FRAMES) as usize;
+        (from * (PAN_FRAMES as usize - done) + to * done) / PAN_FRAMES as usize
+    };
+    if index >= AGAIN_TO_DOOR {
+        pan(0, WIDTH, AGAIN_TO_DOOR)
+    } else if index >= BACK_TO_KITCHEN {
+        pan(WIDTH, 0, BACK_TO_KITCHEN)
+    } else if index >= TO_DOOR {
+        pan(0, WIDTH, TO_DOOR)
+    } else {
+        0
+    }
+}
+
+
+fn detector() -> Box<dyn DiffStrategy> {
+    let start_count = ((WIDTH * HEIGHT) as f32 * IMAGE_THRESHOLD) as i32;
+    diff::from_name("adaptive", PIXEL_THRESHOLD, start_count, Settings::default().adaptive_threshold(), 0, Channels::Rgb)
+        .expect("adaptive is a strategy")
+}
+
+
+/// What a run saw: the frames movements started at, and how many stopped for a scene change.
+struct Run {
+    starts: Vec<u64>,
+    scene_changes: usize,
+    profiles: SceneProfiles,
+}
+
+
+// Goes between the presets, saving and activating the scenes if asked to, settling with `settle`,
+// and activating the door once more on frame `again` if given.
+fn run(scenes: bool, settle: Option<Duration>, again: Option<u64>) -> Run {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let panorama = Panorama::new(&mut random);
+    let start_count = ((WIDTH * HEIGHT) as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy = detector();
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+    let mut profiles = SceneProfiles::new(settle);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let (mut events, mut scene_changed) = (Vec::new(), false);
+    for index in 0 .. FRAMES {
+        let now = start + INTERVAL * index as u32;
+        let ball = (CROSSING .. CROSSING + CROSSING_FRAMES).contains(&index)
+            .then(|| ((index - CROSSING) as i64 * SPEED, HEIGHT as i64 / 2));
+        let thumb = panorama.thumbnail(left(index), ball, &mut random);
+        let command = match index {
+            SAVE_KITCHEN => Some(("save", "kitchen")),
+            SAVE_DOOR => Some(("save", "door")),
+            BACK_TO_KITCHEN => Some(("activate", "kitchen")),
+            AGAIN_TO_DOOR => Some(("activate", "door")),
+            _ if again == Some(index) => Some(("activate", "door")),
+            _ => None,
+        };
+        match command.filter(|_| scenes) {
+            Some(("save", scene)) => profiles.save(scene, strategy.as_ref()).expect("There's a reference"),
+            Some((_, scene)) => {
+                let mut activated = detector();
+                profiles.activate(scene, &thumb, activated.as_mut(), now).expect("The scene was saved");
+                strategy = activated;
+                scene_changed = true;
+            }
+            None => {}
+        }
+        if profiles.settling(now) {
+            continue;
+        }
+        if std::mem::take(&mut scene_changed) {
+            events.extend(motion.interrupt(StopReason::SceneChange));
+        }
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        events.extend(motion.update(result.changed_pixels, result.score, now));
+    }
+    events.extend(motion.finish(start + INTERVAL * FRAMES as u32));
+    let starts = events.iter()
+        .filter_map(|event| match event {
+            MotionEvent::Start { at, .. } => Some((at.duration_since(start).as_millis() / INTERVAL.as_millis()) as u64),
+            _ => None,
+        })
+        .collect();
+    let scene_changes = events.iter().filter(|event| matches!(event, MotionEvent::Stop { reason: StopReason::SceneChange, .. })).count();
+    Run { starts, scene_changes, profiles }
+}
+
+
+// The movements started from frame `from` to frame `to`, excluded.
+fn starts_between(run: &Run, from: u64, to: u64) -> usize {
+    run.starts.iter().filter(|start| (from .. to).contains(*start)).count()
+}
+
+
+fn assert_crossing_seen(run: &Run) {
+    assert_eq!(starts_between(run, CROSSING, CROSSING + CROSSING_FRAMES), 1, "{:?}", run.starts);
+}
+
+
+#[test]
+fn without_scenes_every_pan_is_a_movement() {
+    let without = run(false, None, None);
+    assert_eq!(starts_between(&without, BACK_TO_KITCHEN, CROSSING), 2, "{:?}", without.starts);
+    assert_crossing_seen(&without);
+}
+
+
+#[test]
+fn scenes_activated_without_settling_still_see_the_pans() {
+    let unsettled = run(true, None, None);
+    assert_eq!(starts_between(&unsettled, BACK_TO_KITCHEN, CROSSING), 2, "{:?}", unsettled.starts);
+    assert_crossing_seen(&unsettled);
+}
+
+
+#[test]
+fn settled_scenes_take_the_pans_but_not_the_ball() {
+    let settled = run(true, Some(SETTLE), None);
+    // The first pan has no scene to activate yet.
+    assert_eq!(starts_between(&settled, TO_DOOR, TO_DOOR + PAN_FRAMES), 1, "{:?}", settled.starts);
+    assert_eq!(starts_between(&settled, BACK_TO_KITCHEN, CROSSING), 0, "{:?}", settled.starts);
+    assert_crossing_seen(&settled);
+}
+
+
+#[test]
+fn an_activation_during_a_movement_stops_it() {
+    let interrupted = run(true, Some(SETTLE), Some(CROSSING + 3));
+    assert_eq!(interrupted.scene_changes, 1);
+    assert_crossing_seen(&interrupted);
+}
+
+
+#[test]
+fn scenes_survive_a_state_file_for_thumbnails_of_their_size() {
+    let settled = run(true, Some(SETTLE), None);
+    let saved = settled.profiles.profiles();
+    assert!(saved.iter().all(|profile| profile.noise_map.is_some()), "The adaptive detector learned the noise");
+    let state = SavedState {
+        capture_width: WIDTH as u32,
+        capture_height: HEIGHT as u32,
+        downsample: 1,
+        algorithm: "adaptive blur=0".to_string(),
+        reference: saved[0].reference.clone(),
+        noise_map: None,
+        held: Vec::new(),
+        streams: Vec::new(),
+        scenes: saved.to_vec(),
+        activity: ActivityHistogram::new(SystemTime::now()),
+    };
+    let path = env::temp_dir().join(format!("motion-detect-scene-presets-{}.state", process::id()));
+    state.save(&path).unwrap();
+    let loaded = SavedState::load(&path);
+    let _ = fs::remove_file(&path);
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.scenes.len(), saved.len());
+    for (loaded, saved) in loaded.scenes.iter().zip(saved) {
+        assert_eq!(loaded.name, saved.name);
+        assert_eq!(loaded.reference.pixels, saved.reference.pixels);
+        assert_eq!(loaded.noise_map.as_ref().map(|noise_map| &noise_map.variance), saved.noise_map.as_ref().map(|noise_map| &noise_map.variance));
+    }
+
+    let mut kept = SceneProfiles::new(None);
+    assert!(kept.restore(loaded.scenes.clone(), &Thumbnail::with_channels(WIDTH, HEIGHT, 1)).is_empty());
+    assert_eq!(kept.profiles().len(), 2);
+    let mut smaller = SceneProfiles::new(None);
+    assert_eq!(smaller.restore(loaded.scenes, &Thumbnail::with_channels(WIDTH / 2, HEIGHT / 2, 1)).len(), 2);
+    assert!(smaller.profiles().is_empty());
+}
@@ -0,0 +1,94 @@
+//! `Config` and `Event` through serde, which the `serde` feature derives for them: what serde
+//! writes reads back the same, and agrees with the records the binary writes.
+
+#![cfg(feature = "serde")]
+
+use std::time::{ Duration, Instant, UNIX_EPOCH };
+
+use motion_detect::{
+    clock::TimeSource,
+    contract::{ Config, Event, EventKind, SCHEMA_VERSION },
+    motion::{ MotionEvent, StopReason },
+    settings::Settings,
+};
+use serde_json::Value;
+
+
+// One record of each kind, at times and values the binary's JSON writes exactly.
+fn events() -> Vec<Event> {
+    let event = |kind, time| Event { kind, time, time_source: TimeSource::Driver, schema_version: SCHEMA_VERSION };
+    vec![
+        event(EventKind::Provisional { id: 1 }, 1_700_000_000.1),
+        event(EventKind::Start { id: 1, continued_from: None, provisional: true, pre_existing: false }, 1_700_000_000.1),
+        event(EventKind::Stop { id: 1, reason: StopReason::MaxDuration, duration: 60.0, peak: 31.25, mean: 12.5, frames_above_threshold: 18 }, 1_700_000_060.1),
+        event(EventKind::Start { id: 2, continued_from: Some(1), provisional: false, pre_existing: false }, 1_700_000_060.1),
+        event(EventKind::ProvisionalCancel { id: 3 }, 1_700_000_100.0),
+    ]
+}
+
+
+#[test]
+fn a_config_reads_back_the_same() {
+    let config = Config { image_threshold: 2.5, max_event_duration: Some(60.0), algorithm: "edges".to_string(), ..Config::default() };
+    let text = serde_json::to_string(&config).unwrap();
+    assert_eq!(serde_json::from_str::<Config>(&text).unwrap(), config);
+}
+
+
+#[test]
+fn a_config_file_names_only_what_it_changes() {
+    let config: Config = serde_json::from_str(r#"{"pixel_threshold": 8, "motion_tail_length": 0.5}"#).unwrap();
+    assert_eq!(config, Config { pixel_threshold: 8.0, motion_tail_length: 0.5, ..Config::default() });
+    let mut settings = Settings::default();
+    config.apply(&mut settings).unwrap();
+    assert_eq!((settings.pixel_threshold, settings.motion_tail_length), (8.0, Duration::from_millis(500)));
+}
+
+
+#[test]
+fn a_misspelled_config_field_is_refused() {
+    let err = serde_json::from_str::<Config>(r#"{"image_treshold": 2}"#).unwrap_err();
+    assert!(err.to_string().contains("unknown field `image_treshold`"), "{err}");
+}
+
+
+#[test]
+fn every_kind_of_event_reads_back_the_same() {
+    for event in events() {
+        let text = serde_json::to_string(&event).unwrap();
+        assert_eq!(serde_json::from_str::<Event>(&text).unwrap(), event, "{text}");
+    }
+}
+
+
+#[test]
+fn serde_writes_the_fields_the_binary_does() {
+    for event in events() {
+        let written: Value = serde_json::from_str(&event.to_object().finish()).unwrap();
+        assert_eq!(serde_json::to_value(&event).unwrap(), written);
+    }
+}
+
+
+#[test]
+fn a_record_the_binary_wrote_reads_back_as_it_was_made() {
+    let start = MotionEvent::Start { id: 4, continued_from: None, provisional: false, pre_existing: true, at: Instant::now(), between: None };
+    let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+    let record = start.to_object(time, TimeSource::Arrival).field("installation", "5b9c2f4e-0d3a-4c8e-9f1a-2b7d6e8c4a10").finish();
+    assert_eq!(serde_json::from_str::<Event>(&record).unwrap(), Event::new(start, time, TimeSource::Arrival));
+}
+
+
+#[test]
+fn records_of_the_previous_version_still_read() {
+    let records: Vec<Event> = include_str!("fixtures/events-v1.jsonl").lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert!(records.iter().all(|record| record.schema_version == 1));
+    let kinds: Vec<_> = records.iter().map(|record| record.kind.clone()).collect();
+    assert_eq!(kinds, [
+        EventKind::Provisional { id: 1 },
+        EventKind::Start { id: 1, continued_from: None, provisional: true, pre_existing: false },
+        EventKind::Stop { id: 1, reason: StopReason::Tail, duration: 2.5, peak: 31.25, mean: 12.5, frames_above_threshold: 18 },
+        EventKind::ProvisionalCancel { id: 2 },
+    ]);
+    assert_eq!((records[3].time, records[3].time_source), (1_700_000_010.0, TimeSource::Arrival));
+}
@@ -0,0 +1,188 @@
+//! A bar swept across the gray scene twice in front of a `MotionDetector`, the snapshots of each
+//! movement saved through a `MediaQueue` with their sidecars, as motion-detect does, while the
+//! start and stop records go out on an `EventBus` to a sink keeping them.
+
+mod common;
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant, SystemTime },
+};
+
+use common::{ Scene, Shape, HEIGHT, WIDTH };
+use motion_detect::{
+    bus::{ Event, EventBus, Sink, SinkError },
+    clock::TimeSource,
+    detector::MotionDetector,
+    identity::Identity,
+    json::{ self, Scalar },
+    media::{ MediaFile, MediaJob, MediaQueue },
+    motion::MotionEvent,
+    settings::MediaOverflow,
+    sidecar::Sidecar,
+    snapshot::Snapshots,
+    thumbnail::PixelLayout,
+    zones::ZoneMasks,
+};
+
+const FRAMES: u64 = 80;
+const SWEEPS: [std::ops::Range<u64>; 2] = [20 .. 30, 50 .. 60];
+const CONFIG_HASH: u64 = 0x0123_4567_89ab_cdef;
+
+
+/// Keeps the JSON of the motion records it's given.
+struct Records(Arc<Mutex<Vec<String>>>);
+
+
+impl Sink for Records {
+
+    fn deliver(&mut self, event: &Event) -> Result<(), SinkError> {
+        if let Event::Motion { json, .. } = event {
+            self.0.lock().unwrap().push(json.clone());
+        }
+        Ok(())
+    }
+}
+
+
+type Fields = Vec<(String, Scalar)>;
+
+
+/// What a run left: the movements' images, the sidecars found next to them, the records the bus
+/// delivered, and the files left half written.
+struct Run {
+    movements: usize,
+    files: Vec<MediaFile>,
+    images: usize,
+    sidecars: Vec<Fields>,
+    records: Vec<Fields>,
+    leftovers: usize,
+}
+
+
+fn run(test: &str, with_sidecars: bool) -> Run {
+    let directory = env::temp_dir().join(format!("motion-detect-sidecar-{test}-{}", process::id()));
+    let mut detector = MotionDetector::new(&common::settings(), WIDTH, HEIGHT, PixelLayout::Rgb);
+    let mut source = Scene::new(FRAMES, SWEEPS.into_iter().flat_map(Shape::sweep).collect());
+    let mut snapshots = Snapshots::new(Some(directory.clone()), "{id}-{zone}", true, WIDTH, HEIGHT, 3);
+    let media = MediaQueue::new(4, MediaOverflow::Block, Duration::from_secs(5));
+    let mut identity = Identity::ephemeral();
+    let bus = EventBus::new();
+    let records = Arc::new(Mutex::new(Vec::new()));
+    bus.register("records", Records(records.clone()), false);
+
+    // Wall clock times of the movements, from when the detector was made.
+    let (origin, epoch) = (Instant::now(), SystemTime::now());
+    let mut started = Vec::new();
+    while let Ok(events) = detector.next_events(&mut source) {
+        snapshots.capture(&source.frame, PixelLayout::Rgb);
+        for event in events {
+            let (id, at) = match event {
+                MotionEvent::Start { id, at, .. } | MotionEvent::Stop { id, at, .. } => (id, at),
+                _ => continue,
+            };
+            let time = epoch + at.saturating_duration_since(origin);
+            let event_identity = identity.next(id);
+            let record = event.to_object(time, TimeSource::Arrival)
+                .field("installation", event_identity.installation.as_str())
+                .field("sequence", event_identity.sequence);
+            match event {
+                MotionEvent::Start { .. } => {
+                    let job = snapshots.save(id, time, &[], &ZoneMasks::default(), &[], WIDTH / 4).unwrap();
+                    let sidecar = Sidecar::started("scene", CONFIG_HASH, time, event_identity.clone(), time, 0.0, None);
+                    started.push((id, job.files.clone(), sidecar.clone()));
+                    media.push(job.with_sidecar(with_sidecars.then_some(sidecar)));
+                }
+                MotionEvent::Stop { duration, stats, .. } if with_sidecars => {
+                    if let Some((_, files, mut sidecar)) = started.iter().find(|(started, ..)| *started == id).cloned() {
+                        sidecar.stopped(time, duration, stats.peak);
+                        media.push(MediaJob::sidecars(Some(id), files, sidecar));
+                    }
+                }
+                _ => {}
+            }
+            bus.publish(Event::Motion { event, identity: event_identity, at, notify: true, text: String::new(), json: record.finish() });
+        }
+    }
+    media.finish(Duration::from_secs(5));
+    bus.drain(Duration::from_secs(5));
+
+    let files: Vec<_> = started.iter().flat_map(|(_, files, _)| files.clone()).collect();
+    let sidecars = files.iter()
+        .filter_map(|file| fs::read_to_string(Sidecar::path(&file.path)).ok())
+        .map(|text| json::parse_flat(&text).expect("A flat sidecar, its movement having no bounding box"))
+        .collect();
+    let records = records.lock().unwrap().iter().map(|record| json::parse_flat(record).unwrap()).collect();
+    let leftovers = fs::read_dir(&directory).map_or(0, |entries| {
+        entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path: &PathBuf| path.extension().is_some_and(|extension| extension == "part")).count()
+    });
+    let images = files.iter().filter(|file| file.path.exists()).count();
+    let _ = fs::remove_dir_all(&directory);
+    Run { movements: started.len(), files, images, sidecars, records, leftovers }
+}
+
+
+fn field<'f>(fields: &'f [(String, Scalar)], name: &str) -> Option<&'f Scalar> {
+    fields.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+}
+
+
+// The record of type `kind` of the movement a sidecar is of.
+fn record_of<'r>(run: &'r Run, sidecar: &[(String, Scalar)], kind: &str) -> &'r [(String, Scalar)] {
+    let id = field(sidecar, "id").expect("A sidecar of a movement");
+    run.records.iter()
+        .find(|record| field(record, "type") == Some(&Scalar::String(kind.to_string())) && field(record, "id") == Some(id))
+        .unwrap_or_else(|| panic!("A {kind} record for {id:?}"))
+}
+
+
+// Asserts the sidecar's fields are the record's, named in pairs, e.g. its start for the start
+// record's time.
+fn assert_same(sidecar: &[(String, Scalar)], record: &[(String, Scalar)], names: &[(&str, &str)]) {
+    for (in_sidecar, in_record) in names {
+        assert_eq!(field(sidecar, in_sidecar), field(record, in_record), "{in_sidecar} against the record's {in_record}");
+        assert!(field(sidecar, in_sidecar).is_some(), "{in_sidecar}");
+    }
+}
+
+
+#[test]
+fn every_snapshot_has_a_whole_sidecar() {
+    let run = run("whole", true);
+    assert_eq!(run.movements, SWEEPS.len());
+    assert!(!run.files.is_empty());
+    assert_eq!(run.images, run.files.len());
+    assert_eq!(run.sidecars.len(), run.files.len());
+    assert_eq!(run.leftovers, 0);
+}
+
+
+#[test]
+fn a_sidecar_tells_what_the_start_record_does() {
+    let run = run("start", true);
+    for sidecar in &run.sidecars {
+        let names = [("id", "id"), ("installation", "installation"), ("sequence", "sequence"), ("start", "time")];
+        assert_same(sidecar, record_of(&run, sidecar, "start"), &names);
+    }
+}
+
+
+#[test]
+fn a_sidecar_tells_what_the_stop_record_does() {
+    let run = run("stop", true);
+    for sidecar in &run.sidecars {
+        assert_same(sidecar, record_of(&run, sidecar, "stop"), &[("stop", "time"), ("duration", "duration"), ("peak", "peak")]);
+    }
+}
+
+
+#[test]
+fn without_sidecars_only_the_images_are_written() {
+    // As with --no-sidecar.
+    let run = run("none", false);
+    assert_eq!(run.images, run.files.len());
+    assert!(run.sidecars.is_empty());
+}
@@ -0,0 +1,150 @@
+//! motion-detect started as systemd starts a socket activated service: a listener bound here and
+//! passed as descriptor 3, with LISTEN_FDS, LISTEN_FDNAMES and LISTEN_PID telling it so. It serves
+//! GET /config on that listener, without --http, and leaves alone sockets meant for another
+//! process or called something else than "http".
+
+#![cfg(target_os = "linux")]
+
+use std::{
+    fs::File,
+    io::{ self, BufRead, BufReader, Read, Write },
+    net::{ TcpListener, TcpStream },
+    os::{ fd::AsRawFd, unix::process::CommandExt },
+    process::{ Child, Command, Stdio },
+    sync::{ Arc, Mutex },
+    thread,
+    time::{ Duration, Instant },
+};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+
+const F_SETFD: i32 = 2;
+
+extern "C" {
+    fn dup2(old: i32, new: i32) -> i32;
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+}
+
+
+/// A motion-detect run, and the lines it printed so far.
+struct Run {
+    child: Child,
+    printed: Arc<Mutex<Vec<String>>>,
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+
+// Starts motion-detect on a gray scene piped to it, with `passed` as descriptor 3 and the
+// variables of socket activation for `name`. LISTEN_PID is `pid`, "$$" for the process itself,
+// which is still the shell's once it execs.
+fn spawn(passed: &impl AsRawFd, name: &str, pid: &str, args: &[&str]) -> Run {
+    let fd = passed.as_raw_fd();
+    let mut command = Command::new("sh");
+    command.args(["-c", &format!("LISTEN_PID={pid} exec \"$0\" \"$@\""), env!("CARGO_BIN_EXE_motion-detect")])
+        .args(["--input", "stdin", "--input-size", &format!("{WIDTH}x{HEIGHT}"), "--input-fps", "10"])
+        .args(["--warm-up", "0s"])
+        .args(args)
+        .env("LISTEN_FDS", "1")
+        .env("LISTEN_FDNAMES", name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    // Between fork and exec, where only async-signal-safe calls are allowed, as these are. The
+    // copy isn't close-on-exec, the descriptor itself is if it's already 3.
+    unsafe {
+        command.pre_exec(move || match if fd == 3 { fcntl(3, F_SETFD, 0) } else { dup2(fd, 3) - 3 } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || while stdin.write_all(&[90; WIDTH * HEIGHT * 3]).is_ok() {});
+    let printed = Arc::new(Mutex::new(Vec::new()));
+    let stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn({
+        let printed = printed.clone();
+        move || BufReader::new(stdout).lines().map_while(Result::ok).for_each(|line| printed.lock().unwrap().push(line))
+    });
+    Run { child, printed, reader: Some(reader) }
+}
+
+
+// The body of GET /config on `port` once it's served, empty if it isn't within a few seconds.
+fn config(port: u16) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+            let _ = write!(stream, "GET /config HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            if response.starts_with("HTTP/1.1 200") {
+                return response.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default();
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    String::new()
+}
+
+
+fn listener() -> (TcpListener, u16) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (listener, port)
+}
+
+
+#[test]
+fn the_passed_listener_serves_the_config() {
+    let (passed, port) = listener();
+    let run = spawn(&passed, "http", "$$", &[]);
+    let config = config(port);
+    assert!(config.contains(&format!("\"http\":\"127.0.0.1:{port}\"")), "{config}");
+    assert!(!run.printed.lock().unwrap().iter().any(|line| line.contains("passed socket")));
+}
+
+
+#[test]
+fn a_socket_passed_to_another_process_is_left_alone() {
+    // Inherited from a socket activated parent, say. The server is the one --http asks for.
+    let (passed, _) = listener();
+    let (_, port) = listener();
+    let run = spawn(&passed, "http", "1", &["--http", &format!("127.0.0.1:{port}")]);
+    let config = config(port);
+    assert!(config.contains(&format!("\"http\":\"127.0.0.1:{port}\"")), "{config}");
+    assert!(!run.printed.lock().unwrap().iter().any(|line| line.contains("passed socket")));
+}
+
+
+#[test]
+fn a_socket_called_something_else_is_ignored_with_a_warning() {
+    let (passed, _) = listener();
+    let (_, port) = listener();
+    let run = spawn(&passed, "metrics", "$$", &["--http", &format!("127.0.0.1:{port}")]);
+    let config = config(port);
+    assert!(config.contains(&format!("\"http\":\"127.0.0.1:{port}\"")), "{config}");
+    let warning = "Warning, ignoring passed socket metrics (fd 3), only \"http\" is used";
+    assert!(run.printed.lock().unwrap().iter().any(|line| line == warning), "{:?}", run.printed.lock().unwrap());
+}
+
+
+#[test]
+fn a_passed_descriptor_that_isnt_a_listener_is_refused() {
+    let file = File::open("/dev/null").unwrap();
+    let mut run = spawn(&file, "http", "$$", &[]);
+    assert_eq!(run.child.wait().unwrap().code(), Some(22));
+    run.reader.take().unwrap().join().unwrap();
+    let printed = run.printed.lock().unwrap().join("\n");
+    assert!(printed.contains("Error, passed socket 'http' (fd 3) isn't a socket"), "{printed}");
+}
@@ -0,0 +1,175 @@
+//! A camera on a pole swaying in the wind: thumbnails of a textured scene, shifted by a pixel this
+//! way or that every few frames, with a little sensor noise. Like motion-detect with --stabilize,
+//! the diff runs behind `Stabilize`, and movements are followed with a `MotionTracker`.
+
+use std::time::{ Duration, Instant };
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff, Stabilize },
+    motion::{ MotionEvent, MotionTracker },
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 60;
+const CHANNELS: usize = 3;
+const INTERVAL: Duration = Duration::from_millis(100);
+const PIXEL_THRESHOLD: i32 = 25;
+const IMAGE_THRESHOLD: f32 = 0.02;
+const TAIL: Duration = Duration::from_secs(1);
+const FRAMES: u64 = 300;
+// As --stabilize-max-shift 1.
+const MAX_SHIFT: usize = 1;
+
+// The scene is a larger texture of blocks, the thumbnail a window of it.
+const MARGIN: usize = 4;
+const BLOCK: usize = 3;
+
+// The ball, in thumbnail pixels and pixels per frame, and the frame it starts crossing at.
+const RADIUS: i64 = 6;
+const SPEED: i64 = 8;
+const CROSSING: u64 = 150;
+const CROSSING_FRAMES: u64 = 10;
+
+// The frame the camera is bumped at, two pixels to the right for good.
+const BUMP: u64 = 200;
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+/// The scene beyond the thumbnail on every side, blocks of random colors.
+struct Scene {
+    width: usize,
+    pixels: Vec<u8>,
+}
+
+
+impl Scene {
+
+    fn new(random: &mut Random) -> Self {
+        let (width, height) = (WIDTH + 2 * MARGIN, HEIGHT + 2 * MARGIN);
+        let blocks: Vec<u8> = (0 .. width.div_ceil(BLOCK) * height.div_ceil(BLOCK) * CHANNELS).map(|_| 40 + random.below(160) as u8).collect();
+        let mut pixels = vec![0; width * height * CHANNELS];
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let block = (y / BLOCK) * width.div_ceil(BLOCK) + x / BLOCK;
+                for channel in 0 .. CHANNELS {
+                    pixels[(y * width + x) * CHANNELS + channel] = blocks[block * CHANNELS + channel];
+                }
+            }
+        }
+        Self { width, pixels }
+    }
+
+    // The window at `offset` from the middle, with noise and the ball if it's there.
+    fn thumbnail(&self, offset: (i64, i64), ball: Option<(i64, i64)>, random: &mut Random) -> Thumbnail {
+        let mut thumb = Thumbnail::with_channels(WIDTH, HEIGHT, CHANNELS);
+        for y in 0 .. HEIGHT {
+            for x in 0 .. WIDTH {
+                let (scene_x, scene_y) = ((x as i64 + MARGIN as i64 + offset.0) as usize, (y as i64 + MARGIN as i64 + offset.1) as usize);
+                let inside = ball.is_some_and(|(cx, cy)| (x as i64 - cx).pow(2) + (y as i64 - cy).pow(2) <= RADIUS.pow(2));
+                for channel in 0 .. CHANNELS {
+                    let value = match inside {
+                        true => 250,
+                        false => self.pixels[(scene_y * self.width + scene_x) * CHANNELS + channel].saturating_add(random.below(4) as u8),
+                    };
+                    thumb.pixels[(y * WIDTH + x) * CHANNELS + channel] = value;
+                }
+            }
+        }
+        thumb
+    }
+}
+
+
+/// What a run saw: the frames movements started at, and the shifts reported beyond the max.
+struct Run {
+    starts: Vec<u64>,
+    beyond: Vec<u64>,
+}
+
+
+// Sways by a pixel every few frames, with the ball crossing and the bump if asked for.
+fn run(stabilize: bool, ball: bool, bump: bool) -> Run {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let scene = Scene::new(&mut random);
+    let start_count = ((WIDTH * HEIGHT) as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy: Box<dyn DiffStrategy> = Box::new(FrameDiff::new(PIXEL_THRESHOLD, start_count));
+    if stabilize {
+        strategy = Box::new(Stabilize::new(strategy, MAX_SHIFT));
+    }
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let mut events = Vec::new();
+    let mut beyond = Vec::new();
+    for index in 0 .. FRAMES {
+        // Still for a while, then over to one side or the other.
+        let sway = match (index / 3) % 4 {
+            0 | 2 => (0, 0),
+            1 => (1, 0),
+            _ => (-1, (index % 2) as i64),
+        };
+        let offset = match bump && index >= BUMP {
+            true => (sway.0 + 2, sway.1),
+            false => sway,
+        };
+        let ball = (ball && (CROSSING .. CROSSING + CROSSING_FRAMES).contains(&index))
+            .then(|| ((index - CROSSING) as i64 * SPEED, HEIGHT as i64 / 2));
+        let thumb = scene.thumbnail(offset, ball, &mut random);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        if result.shift.is_some_and(|shift| !shift.compensated) {
+            beyond.push(index);
+        }
+        events.extend(motion.update(result.changed_pixels, result.score, start + INTERVAL * index as u32));
+    }
+    events.extend(motion.finish(start + INTERVAL * FRAMES as u32));
+    let starts = events.iter()
+        .filter_map(|event| match event {
+            MotionEvent::Start { at, .. } => Some((at.duration_since(start).as_millis() / INTERVAL.as_millis()) as u64),
+            _ => None,
+        })
+        .collect();
+    Run { starts, beyond }
+}
+
+
+#[test]
+fn the_sway_starts_movements_unless_stabilized() {
+    let swaying = run(false, false, false);
+    assert!(!swaying.starts.is_empty());
+    let stabilized = run(true, false, false);
+    assert!(stabilized.starts.is_empty() && stabilized.beyond.is_empty(), "{:?} {:?}", stabilized.starts, stabilized.beyond);
+}
+
+
+#[test]
+fn a_ball_crossing_the_swaying_scene_is_still_seen() {
+    let crossing = run(true, true, false);
+    assert_eq!(crossing.starts.len(), 1, "{:?}", crossing.starts);
+    assert!((CROSSING .. CROSSING + CROSSING_FRAMES).contains(&crossing.starts[0]), "{:?}", crossing.starts);
+}
+
+
+#[test]
+fn a_bump_beyond_the_max_is_compared_as_it_came() {
+    let bumped = run(true, false, true);
+    assert_eq!(bumped.beyond.first(), Some(&BUMP));
+    assert!(bumped.starts.first().is_some_and(|start| *start >= BUMP), "{:?}", bumped.starts);
+}
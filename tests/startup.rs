@@ -0,0 +1,48 @@
+//! Movements around the time the detector becomes ready: already in progress, or over before.
+
+mod common;
+
+use common::{ Scene, Shape };
+use motion_detect::motion::MotionEvent;
+
+// The first 10 frames warm up, the 11th is the baseline.
+const FRAMES: u64 = 60;
+const BASELINE: u64 = 10;
+
+
+// A box covering a quarter of the picture, there on every other frame of `frames`, so each frame
+// differs from the one before.
+fn blinking(frames: std::ops::Range<u64>) -> Vec<Shape> {
+    frames.step_by(2).map(|frame| Shape::new((8, 12), (40, 36), 200, frame .. frame + 1)).collect()
+}
+
+
+#[test]
+fn motion_over_during_the_warm_up_is_no_movement() {
+    let events = common::run(&common::settings(), &mut Scene::new(FRAMES, blinking(0 .. BASELINE - 2)));
+    assert!(events.is_empty(), "{events:?}");
+}
+
+
+#[test]
+fn motion_beginning_after_the_baseline_is_not_pre_existing() {
+    let events = common::run(&common::settings(), &mut Scene::new(FRAMES, Shape::sweep(BASELINE + 5 .. 30)));
+    assert_eq!(common::starts(&events), [BASELINE + 5]);
+    assert!(matches!(events[0], (_, MotionEvent::Start { pre_existing: false, .. })));
+}
+
+
+#[test]
+fn motion_in_progress_at_startup_starts_at_the_ready_time() {
+    let events = common::run(&common::settings(), &mut Scene::new(FRAMES, blinking(0 .. 30)));
+    let Some((frame, MotionEvent::Start { pre_existing, .. })) = events.first() else {
+        panic!("A movement started: {events:?}");
+    };
+    assert_eq!((*frame, *pre_existing), (BASELINE + 1, true));
+    let Some((_, MotionEvent::Stop { duration, .. })) = events.get(1) else {
+        panic!("The movement stopped: {events:?}");
+    };
+    // From the ready time rather than the first frame compared, to the last frame that differs,
+    // the one without the box after its last time, plus the tail.
+    assert_eq!(duration.as_millis(), (29 - BASELINE as u128) * 100 + 500);
+}
@@ -0,0 +1,142 @@
+//! Starts motion-detect on raw video piped from a synthetic scene and follows its startup: the
+//! startup_progress messages it prints before "ready", and what GET /status says meanwhile.
+
+use std::{
+    env, fs,
+    io::{ BufRead, BufReader, Read, Write },
+    net::{ TcpListener, TcpStream },
+    path::Path,
+    process::{ Child, Command, Stdio },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use motion_detect::json::{ self, Scalar };
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const BACKGROUND: u8 = 90;
+const WARM_UP: &str = "2.5s";
+// When the first /status is asked for, well within the warm-up.
+const STATUS_AFTER: Duration = Duration::from_millis(1200);
+const PHASES: [&str; 5] = ["probing", "negotiating", "warming_up", "calibrating", "baseline"];
+
+
+/// What a run printed before it was ready, and what /status said during the warm-up and after.
+struct Run {
+    progress: Vec<(String, String, Option<f64>)>,   // Phase, detail and percent.
+    during: String,
+    after: String,
+}
+
+
+// A gray frame, or with `moving` a bright bar somewhere else on each frame.
+fn frame(index: u64, moving: bool) -> Vec<u8> {
+    let mut frame = vec![BACKGROUND; WIDTH * HEIGHT * 3];
+    if moving {
+        let left = (index as usize * 16) % WIDTH;
+        for y in 0 .. HEIGHT {
+            for x in left .. (left + 16).min(WIDTH) {
+                frame[(y * WIDTH + x) * 3 .. (y * WIDTH + x) * 3 + 3].fill(250);
+            }
+        }
+    }
+    frame
+}
+
+
+// The body of GET `path`, empty if the server can't be reached.
+fn get(port: u16, path: &str) -> String {
+    let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) else { return String::new() };
+    let _ = write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    response.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default()
+}
+
+
+fn spawn(port: u16, state_dir: &Path) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_motion-detect"))
+        .args(["--input", "stdin", "--input-size", &format!("{WIDTH}x{HEIGHT}"), "--input-fps", "10"])
+        .args(["--warm-up", WARM_UP, "--flicker-rejection", "--format", "json"])
+        .args(["--http", &format!("127.0.0.1:{port}"), "--state-dir", &state_dir.to_string_lossy()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+
+// Runs motion-detect until it's ready, on a scene moving for its first frames if `moving`.
+fn run(test: &str, moving: bool) -> Run {
+    let port = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).unwrap().port();
+    let state_dir = env::temp_dir().join(format!("motion-detect-startup-phases-{test}-{}", std::process::id()));
+    let mut child = spawn(port, &state_dir);
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || {
+        // Moving for a few baseline attempts, still well before they run out.
+        for index in 0 .. {
+            if stdin.write_all(&frame(index, moving && index < 6)).is_err() {
+                return;
+            }
+        }
+    });
+    let begin = Instant::now();
+    let during = thread::spawn(move || {
+        thread::sleep(STATUS_AFTER.saturating_sub(begin.elapsed()));
+        get(port, "/status")
+    });
+
+    let mut progress = Vec::new();
+    for line in BufReader::new(child.stdout.take().unwrap()).lines().map_while(Result::ok) {
+        if line.starts_with("{\"type\":\"ready\"") {
+            break;
+        }
+        if !line.starts_with("{\"type\":\"startup_progress\"") {
+            continue;
+        }
+        let fields = json::parse_flat(&line).expect("Progress is flat JSON");
+        let field = |name: &str| fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.clone());
+        let (Some(Scalar::String(phase)), Some(Scalar::String(detail))) = (field("phase"), field("detail")) else { continue };
+        let percent = match field("percent") {
+            Some(Scalar::Number(percent)) => Some(percent),
+            _ => None,
+        };
+        progress.push((phase, detail, percent));
+    }
+    let after = get(port, "/status");
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = fs::remove_dir_all(&state_dir);
+    Run { progress, during: during.join().unwrap_or_default(), after }
+}
+
+
+// Asserts the phases came in order, each percentage went up to 100, and /status followed. Returns
+// the baseline attempts.
+fn assert_phases(run: &Run) -> usize {
+    let mut phases: Vec<&str> = run.progress.iter().map(|(phase, _, _)| phase.as_str()).collect();
+    phases.dedup();
+    assert_eq!(phases, PHASES, "{:?}", run.progress);
+    for phase in ["warming_up", "calibrating"] {
+        let percents: Vec<f64> = run.progress.iter().filter(|(other, _, _)| other == phase).filter_map(|(_, _, percent)| *percent).collect();
+        assert!(percents.windows(2).all(|pair| pair[0] <= pair[1]), "{phase} went back: {percents:?}");
+        assert_eq!(percents.last(), Some(&100.0), "{phase}: {percents:?}");
+    }
+    assert!(run.during.contains("\"startup\":{\"phase\":\"warming_up\""), "/status during the warm-up: {}", run.during);
+    assert!(run.after.contains("\"startup\":null"), "/status once ready: {}", run.after);
+    run.progress.iter().filter(|(phase, detail, _)| phase == "baseline" && detail.starts_with("attempt")).count()
+}
+
+
+#[test]
+fn a_still_scene_goes_through_every_phase_once() {
+    assert_eq!(assert_phases(&run("still", false)), 1);
+}
+
+
+#[test]
+fn moving_while_capturing_the_baseline_captures_it_again() {
+    assert!(assert_phases(&run("moving", true)) > 1);
+}
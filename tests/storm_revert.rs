@@ -0,0 +1,176 @@
+//! A fat-fingered threshold change on a remote box: leaves fluttering in a corner of the scene now
+//! and then, too small to start a movement, until a `set` command lowers the image threshold to
+//! almost nothing and every flutter becomes one. Like motion-detect with --storm-movements, a
+//! `StormGuard` watches the movements after each change and takes it back once they're a storm.
+
+use std::time::{ Duration, Instant };
+
+use motion_detect::{
+    control::{ ControlBounds, ControlCommand, ControlState, Setting, StormGuard },
+    diff::{ DiffStrategy, FrameDiff },
+    motion::{ MotionEvent, MotionTracker },
+    settings::Settings,
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 60;
+const INTERVAL: Duration = Duration::from_millis(100);
+const TAIL: Duration = Duration::from_millis(200);
+const FRAMES: u64 = 600;
+const BACKGROUND: u8 = 90;
+
+// A flutter every few frames, a small blob somewhere in the corner, for a frame.
+const FLUTTER_EVERY: u64 = 5;
+const FLUTTER_RADIUS: i64 = 2;
+
+// As --storm-movements 5 --storm-window 30s.
+const STORM_MOVEMENTS: usize = 5;
+const STORM_WINDOW: Duration = Duration::from_secs(30);
+
+// The frame the command comes at.
+const CHANGE: u64 = 100;
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+// The gray scene, with a flutter in the top left corner on every FLUTTER_EVERY-th frame.
+fn thumbnail(index: u64, random: &mut Random) -> Thumbnail {
+    let mut thumb = Thumbnail::with_channels(WIDTH, HEIGHT, 1);
+    thumb.pixels.fill(BACKGROUND);
+    if index.is_multiple_of(FLUTTER_EVERY) {
+        let (cx, cy) = (4 + random.below(WIDTH as u64 / 4) as i64, 4 + random.below(HEIGHT as u64 / 4) as i64);
+        for y in 0 .. HEIGHT {
+            for x in 0 .. WIDTH {
+                if (x as i64 - cx).pow(2) + (y as i64 - cy).pow(2) <= FLUTTER_RADIUS.pow(2) {
+                    thumb.pixels[y * WIDTH + x] = 200;
+                }
+            }
+        }
+    }
+    thumb
+}
+
+
+// The diff and the changed pixels that start and sustain a movement, for the control state's thresholds.
+fn detector(control: &ControlState) -> (FrameDiff, i32, i32) {
+    let pixels = (WIDTH * HEIGHT) as f32;
+    let start_count = (pixels * control.image_threshold / 100.0) as i32;
+    let sustain_count = (pixels * control.sustain_percent() / 100.0) as i32;
+    let pixel_level = ((control.pixel_threshold * (255.0 / 100.0)) as i32).clamp(0, 255);
+    (FrameDiff::new(pixel_level, start_count), start_count, sustain_count)
+}
+
+
+/// What a run saw: the frames movements started at and the storm reverted, if any.
+struct Run {
+    starts: Vec<u64>,
+    reverted_at: Option<u64>,
+    control: ControlState,
+}
+
+
+// Runs the scene with `command` applied at frame CHANGE, and `later` at frame `later.1` if given.
+fn run(command: &str, later: Option<(&str, u64)>) -> Run {
+    let settings = Settings { image_threshold: 1.0, ..Settings::default() };
+    let mut control = ControlState::new(&settings);
+    let mut guard = StormGuard::new(STORM_MOVEMENTS, STORM_WINDOW);
+    let (mut strategy, start_count, sustain_count) = detector(&control);
+    let mut motion = MotionTracker::new(TAIL, start_count, sustain_count).with_frame_interval(INTERVAL);
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let (mut starts, mut reverted_at) = (Vec::new(), None);
+    for index in 0 .. FRAMES {
+        let now = start + INTERVAL * index as u32;
+        let commands = [(command, CHANGE)].into_iter().chain(later);
+        let mut rebaseline = false;
+        for (line, _) in commands.filter(|(_, at)| *at == index) {
+            let command = ControlCommand::parse(line).expect("The command is valid");
+            let before = control;
+            rebaseline |= control.apply(&command);
+            match command {
+                ControlCommand::Revert => guard.cancel(),
+                _ if !control.changes(&before).is_empty() => guard.changed(before.thresholds(), now),
+                _ => {}
+            }
+        }
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumbnail(index, &mut random));
+        for event in motion.update(result.changed_pixels, result.score, now) {
+            if let MotionEvent::Start { .. } = event {
+                starts.push(index);
+                if let Some(storm) = guard.movement(now) {
+                    control.restore(storm.before);
+                    reverted_at = Some(index);
+                    rebaseline = true;
+                }
+            }
+        }
+        if rebaseline {
+            let (start_count, sustain_count);
+            (strategy, start_count, sustain_count) = detector(&control);
+            motion.set_thresholds(start_count, sustain_count);
+        }
+    }
+    Run { starts, reverted_at, control }
+}
+
+
+// The movements that started from the change on.
+fn after_change(run: &Run) -> usize {
+    run.starts.iter().filter(|start| **start >= CHANGE).count()
+}
+
+
+#[test]
+fn a_change_to_almost_nothing_is_reverted_once_its_movements_are_a_storm() {
+    let storm = run("set image_threshold=0.1", None);
+    assert!(storm.reverted_at.is_some(), "{:?}", storm.starts);
+    assert_eq!(after_change(&storm), STORM_MOVEMENTS);
+    assert_eq!(storm.control.image_threshold, 1.0);
+}
+
+
+#[test]
+fn a_milder_change_is_kept_after_its_window() {
+    let mild = run("adjust image_threshold=-0.5", None);
+    assert!(mild.starts.is_empty() && mild.reverted_at.is_none(), "{:?}", mild.starts);
+    assert_eq!(mild.control.image_threshold, 0.5);
+}
+
+
+#[test]
+fn a_revert_command_goes_back_to_the_startup_thresholds() {
+    let reverted = run("set image_threshold=0.5", Some(("revert", CHANGE + 50)));
+    assert!(reverted.starts.is_empty(), "{:?}", reverted.starts);
+    assert_eq!(reverted.control.image_threshold, 1.0);
+}
+
+
+#[test]
+fn adjustments_stay_within_the_bounds_and_sets_beyond_them_are_refused() {
+    let mut settings = Settings { image_threshold: 1.0, ..Settings::default() };
+    let (setting, min, max) = ControlBounds::parse("image_threshold=0.5-50").unwrap();
+    settings.control_bounds.set(setting, min, max);
+    let mut control = ControlState::new(&settings);
+    control.apply(&ControlCommand::parse("adjust image_threshold -10").unwrap());
+    assert_eq!(control.image_threshold, min);
+    assert!(settings.control_bounds.check(&ControlCommand::parse("set image_threshold=0").unwrap()).is_err());
+    assert_eq!(control.bounds.range(Setting::ImageThreshold), (min, max));
+}
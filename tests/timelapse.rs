@@ -0,0 +1,126 @@
+//! A trail camera taking a frame a minute, `--capture-interval 60s`: a tent is pitched between two
+//! frames and stays, later a hiker shows on a single frame. With `--tail-frames 1` and
+//! `--warm-up-frames 2`, each is one movement stopped by the next frame without change, and its
+//! events tell the two frames the change came between. The detector times the frames by the
+//! interval, so an hour takes no time.
+
+mod common;
+
+use std::time::{ Duration, Instant };
+
+use common::{ Scene, Shape, HEIGHT, WIDTH };
+use motion_detect::{
+    detector::MotionDetector,
+    motion::{ MotionEvent, StopReason },
+    settings::Settings,
+    thumbnail::PixelLayout,
+};
+
+const INTERVAL: Duration = Duration::from_secs(60);
+const FRAMES: u64 = 40;
+
+// The frame the tent is pitched on, and those a hiker is on, the first during the warm-up.
+const TENT: u64 = 10;
+const HIKERS: [u64; 2] = [1, 25];
+
+
+// The trail, the tent and a hiker a quarter of the picture each.
+fn trail() -> Scene {
+    let mut shapes = vec![Shape::new((4, 16), (28, HEIGHT), 40, TENT .. FRAMES)];
+    shapes.extend(HIKERS.map(|frame| Shape::new((40, 0), (56, HEIGHT), 230, frame .. frame + 1)));
+    Scene::new(FRAMES, shapes)
+}
+
+
+fn settings(tail_frames: Option<u32>) -> Settings {
+    Settings {
+        frame_capture_interval: INTERVAL,
+        tail_frames,
+        warm_up_frames: Some(2),
+        downsample: 4,
+        ..Settings::default()
+    }
+}
+
+
+/// A movement as its events told it, in frames from the first one.
+#[derive(Debug, PartialEq)]
+struct Movement {
+    started: u64,           // The frame the start came with.
+    start_between: Option<(u64, u64)>,
+    stopped: u64,
+    stop_between: Option<(u64, u64)>,
+    duration: Duration,
+}
+
+
+// Every movement of the trail, with the frames of the event times. Frame 0 is the first, captured
+// one interval before the frame the first event came with.
+fn movements(settings: &Settings) -> Vec<Movement> {
+    let mut detector = MotionDetector::new(settings, WIDTH, HEIGHT, PixelLayout::Rgb);
+    let mut source = trail();
+    let mut events = Vec::new();
+    while let Ok(found) = detector.next_events(&mut source) {
+        events.extend(found.into_iter().map(|event| (detector.frames() - 1, event)));
+    }
+    events.extend(detector.finish().map(|event| (detector.frames() - 1, event)));
+
+    // Times are relative to the first start, on frame `origin`.
+    let Some((origin, at)) = events.iter().find_map(|(frame, event)| match event {
+        MotionEvent::Start { at, .. } => Some((*frame, *at)),
+        _ => None,
+    }) else {
+        return Vec::new();
+    };
+    let frame = |time: Instant| {
+        let offset = time.saturating_duration_since(at).as_secs() as i64 - at.saturating_duration_since(time).as_secs() as i64;
+        (origin as i64 + offset / INTERVAL.as_secs() as i64) as u64
+    };
+    let mut movements = Vec::new();
+    for (index, event) in &events {
+        match event {
+            MotionEvent::Start { between, .. } => movements.push(Movement {
+                started: *index,
+                start_between: between.map(|(before, after)| (frame(before), frame(after))),
+                stopped: 0,
+                stop_between: None,
+                duration: Duration::ZERO,
+            }),
+            MotionEvent::Stop { reason: StopReason::Tail, between, duration, .. } => {
+                let movement = movements.last_mut().expect("A stop follows its start");
+                movement.stopped = *index;
+                movement.stop_between = between.map(|(before, after)| (frame(before), frame(after)));
+                movement.duration = *duration;
+            }
+            _ => {}
+        }
+    }
+    movements
+}
+
+
+#[test]
+fn a_change_between_two_frames_is_a_movement_of_one_frame() {
+    let movements = movements(&settings(Some(1)));
+    // The hiker on the warm-up is dropped with it, the tent stays and is a change once.
+    let tent = Movement { started: 10, start_between: Some((9, 10)), stopped: 11, stop_between: Some((10, 11)), duration: INTERVAL };
+    // A hiker changes the frame as they show and as they leave.
+    let hiker = Movement { started: 25, start_between: Some((24, 25)), stopped: 27, stop_between: Some((26, 27)), duration: INTERVAL * 2 };
+    assert_eq!(movements, [tent, hiker]);
+}
+
+
+#[test]
+fn a_motion_tail_in_wall_time_ends_before_the_next_frame() {
+    let movements = movements(&settings(None));
+    assert_eq!(movements.len(), 2, "{movements:?}");
+    assert_eq!(movements[0].duration, Settings::default().motion_tail_length);
+}
+
+
+#[test]
+fn the_wall_time_defaults_are_warned_about_at_a_minute() {
+    let wall_time = Settings { frame_capture_interval: INTERVAL, ..Settings::default() };
+    assert_eq!(wall_time.interval_warnings().len(), 2);
+    assert!(settings(Some(1)).interval_warnings().is_empty());
+}
@@ -0,0 +1,123 @@
+//! The blocking waits of a `MotionDetector`: `wait_for_motion` until a bar sweeps across the gray
+//! scene, `wait_for_still` until it has been gone for a while, with a timeout, the end of the
+//! input and a `CancelToken` ending them.
+
+mod common;
+
+use std::{ ops::Range, thread, time::Duration };
+
+use common::{ Scene, Shape, HEIGHT, WIDTH };
+use motion_detect::{
+    detector::{ MotionDetector, MotionError },
+    motion::MotionEvent,
+    settings::Settings,
+    thumbnail::PixelLayout,
+};
+
+// The frames a bar sweeps on, and the one frame it flashes on.
+const MOVING: [Range<u64>; 4] = [2 .. 6, 30 .. 40, 60 .. 70, 120 .. 130];
+const BLIP: u64 = 20;
+const END: u64 = 200;
+
+
+// A bar the full height of the frame, 16 pixels wide, somewhere else on each of `frames`.
+fn bar(frames: Range<u64>) -> Vec<Shape> {
+    frames.map(|frame| {
+        let left = (frame as usize * 16) % WIDTH;
+        Shape::new((left, 0), ((left + 16).min(WIDTH), HEIGHT), 250, frame .. frame + 1)
+    }).collect()
+}
+
+
+fn script() -> Scene {
+    let moving = MOVING.into_iter().chain(Some(BLIP .. BLIP + 1)).flat_map(bar).collect();
+    Scene::new(END, moving)
+}
+
+
+// Movements confirmed on their third frame.
+fn detector() -> MotionDetector {
+    let settings = Settings { confirm_frames: 3, ..common::settings() };
+    MotionDetector::new(&settings, WIDTH, HEIGHT, PixelLayout::Rgb)
+}
+
+
+fn started(result: &Result<Option<MotionEvent>, MotionError>) -> bool {
+    matches!(result, Ok(Some(MotionEvent::Start { .. })))
+}
+
+
+#[test]
+fn a_wait_skips_the_warm_up_and_a_blip() {
+    let (mut detector, mut source) = (detector(), script());
+    // The movement during the warm-up isn't waited for, and the blip changes two frames, one as the
+    // bar shows and one as it goes, one short of the confirmation.
+    let first = detector.wait_for_motion(&mut source, None);
+    assert!(started(&first), "{first:?}");
+    assert_eq!(detector.frames(), MOVING[1].start + 3);
+}
+
+
+#[test]
+fn each_wait_returns_the_next_movement() {
+    let (mut detector, mut source) = (detector(), script());
+    detector.wait_for_motion(&mut source, None).unwrap();
+    let second = detector.wait_for_motion(&mut source, None);
+    assert!(started(&second), "{second:?}");
+    assert_eq!(detector.frames(), MOVING[2].start + 3);
+}
+
+
+#[test]
+fn a_wait_for_still_returns_once_nothing_moved_for_its_duration() {
+    let (mut detector, mut source) = (detector(), script());
+    for _ in 0 .. 2 {
+        detector.wait_for_motion(&mut source, None).unwrap();
+    }
+    detector.wait_for_still(&mut source, Duration::from_secs(2)).unwrap();
+    // The frame the bar left on after the second movement, a tail and 2s.
+    let still_from = MOVING[2].end + 5 + 20;
+    assert!((still_from ..= still_from + 1).contains(&detector.frames()), "{}", detector.frames());
+    assert!(!detector.is_moving());
+}
+
+
+#[test]
+fn a_timeout_ends_a_wait_and_the_next_one_goes_on() {
+    let (mut detector, mut source) = (detector(), script());
+    for _ in 0 .. 2 {
+        detector.wait_for_motion(&mut source, None).unwrap();
+    }
+    detector.wait_for_still(&mut source, Duration::from_secs(2)).unwrap();
+    let still = detector.frames();
+    assert_eq!(detector.wait_for_motion(&mut source, Some(Duration::from_secs(1))), Ok(None));
+    assert_eq!(detector.frames(), still + 10);
+    let third = detector.wait_for_motion(&mut source, None);
+    assert!(started(&third), "{third:?}");
+    assert_eq!(detector.frames(), MOVING[3].start + 3);
+}
+
+
+#[test]
+fn a_wait_ends_with_the_input() {
+    let (mut detector, mut source) = (detector(), script());
+    for _ in 0 .. MOVING.len() - 1 {
+        detector.wait_for_motion(&mut source, None).unwrap();
+    }
+    assert_eq!(detector.wait_for_motion(&mut source, None), Err(MotionError::EndOfInput));
+}
+
+
+#[test]
+fn a_cancel_ends_that_wait_alone() {
+    let mut source = Scene::new(u64::MAX, Vec::new());
+    let mut detector = detector();
+    let token = detector.cancel_token();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        token.cancel();
+    });
+    assert_eq!(detector.wait_for_motion(&mut source, None), Err(MotionError::Cancelled));
+    canceller.join().unwrap();
+    assert_eq!(detector.wait_for_motion(&mut source, Some(Duration::from_secs(1))), Ok(None));
+}
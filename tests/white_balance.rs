@@ -0,0 +1,158 @@
+//! A camera whose auto white balance hunts: thumbnails of a textured scene that turn bluer for a
+//! while, +15 blue and -10 red all over, with a little sensor noise. As with --wb-compensation,
+//! the diff runs behind `WhiteBalance`, and movements are followed with a `MotionTracker`.
+
+use std::{ ops::Range, time::{ Duration, Instant } };
+
+use motion_detect::{
+    diff::{ DiffStrategy, FrameDiff, WhiteBalance },
+    motion::{ MotionEvent, MotionTracker },
+    thumbnail::Thumbnail,
+};
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 60;
+const CHANNELS: usize = 3;
+const INTERVAL: Duration = Duration::from_millis(100);
+const PIXEL_THRESHOLD: i32 = 10;
+const IMAGE_THRESHOLD: f32 = 0.02;
+const TAIL: Duration = Duration::from_secs(1);
+const FRAMES: u64 = 200;
+const BLOCK: usize = 5;
+// As --wb-compensation 2.
+const TOLERANCE: f32 = 2.0;
+
+// The cast, red, green and blue, and the frames it's on.
+const CAST: [i32; 3] = [-10, 0, 15];
+const CASTS: [Range<u64>; 2] = [40 .. 80, 120 .. 160];
+
+// The object, as thumbnail pixels across and down, a sixteenth of the picture, and its frames.
+const OBJECT: (Range<usize>, Range<usize>) = (20 .. 40, 15 .. 30);
+const OBJECT_FRAMES: Range<u64> = 60 .. 70;
+
+
+/// A fixed sequence, the same on every run.
+struct Random(u64);
+
+
+impl Random {
+
+    fn below(&mut self, bound: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+
+/// Blocks of random colors, away from black and white so a cast doesn't clip.
+fn scene(random: &mut Random) -> Vec<u8> {
+    let blocks: Vec<u8> = (0 .. WIDTH.div_ceil(BLOCK) * HEIGHT.div_ceil(BLOCK) * CHANNELS).map(|_| 40 + random.below(160) as u8).collect();
+    let mut pixels = vec![0; WIDTH * HEIGHT * CHANNELS];
+    for y in 0 .. HEIGHT {
+        for x in 0 .. WIDTH {
+            let block = (y / BLOCK) * WIDTH.div_ceil(BLOCK) + x / BLOCK;
+            for channel in 0 .. CHANNELS {
+                pixels[(y * WIDTH + x) * CHANNELS + channel] = blocks[block * CHANNELS + channel];
+            }
+        }
+    }
+    pixels
+}
+
+
+// The scene with noise, the cast all over if it's on, and the object's tint where it is.
+fn thumbnail(scene: &[u8], cast: bool, object: bool, random: &mut Random) -> Thumbnail {
+    let mut thumb = Thumbnail::with_channels(WIDTH, HEIGHT, CHANNELS);
+    for y in 0 .. HEIGHT {
+        for x in 0 .. WIDTH {
+            let tinted = (cast as i32) + (object && OBJECT.0.contains(&x) && OBJECT.1.contains(&y)) as i32;
+            let index = (y * WIDTH + x) * CHANNELS;
+            for ((pixel, value), cast) in thumb.pixels[index .. index + CHANNELS].iter_mut().zip(&scene[index ..]).zip(CAST) {
+                *pixel = (*value as i32 + random.below(4) as i32 + cast * tinted).clamp(0, 255) as u8;
+            }
+        }
+    }
+    thumb
+}
+
+
+/// What a run saw: the frames movements started at, and those casts were compensated on.
+struct Run {
+    starts: Vec<u64>,
+    compensated: Vec<u64>,
+    casts: Vec<[i32; 3]>,
+}
+
+
+fn run(compensate: bool, cast: bool, object: bool) -> Run {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let scene = scene(&mut random);
+    let start_count = ((WIDTH * HEIGHT) as f32 * IMAGE_THRESHOLD) as i32;
+    let mut strategy: Box<dyn DiffStrategy> = Box::new(FrameDiff::new(PIXEL_THRESHOLD, start_count));
+    if compensate {
+        strategy = Box::new(WhiteBalance::new(strategy, TOLERANCE));
+    }
+    let mut motion = MotionTracker::new(TAIL, start_count, start_count / 2).with_frame_interval(INTERVAL);
+
+    let start = Instant::now();
+    motion.ready(start);
+    let mut events = Vec::new();
+    let mut run = Run { starts: Vec::new(), compensated: Vec::new(), casts: Vec::new() };
+    for index in 0 .. FRAMES {
+        let cast = cast && CASTS.iter().any(|frames| frames.contains(&index));
+        let thumb = thumbnail(&scene, cast, object && OBJECT_FRAMES.contains(&index), &mut random);
+        strategy.set_motion_active(motion.is_active());
+        let result = strategy.process(&thumb);
+        if let Some(cast) = result.cast.filter(|cast| cast.compensated) {
+            run.compensated.push(index);
+            run.casts.push(cast.shifts);
+        }
+        events.extend(motion.update(result.changed_pixels, result.score, start + INTERVAL * index as u32));
+    }
+    events.extend(motion.finish(start + INTERVAL * FRAMES as u32));
+    run.starts = events.iter()
+        .filter_map(|event| match event {
+            MotionEvent::Start { at, .. } => Some((at.duration_since(start).as_millis() / INTERVAL.as_millis()) as u64),
+            _ => None,
+        })
+        .collect();
+    run
+}
+
+
+#[test]
+fn without_compensation_the_cast_starts_movements() {
+    assert!(!run(false, true, false).starts.is_empty());
+}
+
+
+#[test]
+fn with_compensation_the_cast_is_taken_out_as_applied() {
+    let run = run(true, true, false);
+    assert_eq!(run.starts, Vec::<u64>::new());
+    assert!(!run.compensated.is_empty());
+    // Give or take the noise and a little clipping.
+    for shifts in run.casts {
+        assert!(shifts.iter().zip(CAST).all(|(shift, cast)| shift.abs_diff(cast) <= 1), "{shifts:?}");
+    }
+}
+
+
+#[test]
+fn an_object_of_the_same_tint_is_still_seen() {
+    let run = run(true, false, true);
+    assert_eq!(run.starts.len(), 1, "{:?}", run.starts);
+    assert!(OBJECT_FRAMES.contains(&run.starts[0]));
+    assert!(run.compensated.is_empty(), "{:?}", run.compensated);
+}
+
+
+#[test]
+fn an_object_is_still_seen_while_the_scene_is_cast() {
+    let run = run(true, true, true);
+    assert_eq!(run.starts.len(), 1, "{:?}", run.starts);
+    assert!(OBJECT_FRAMES.contains(&run.starts[0]));
+}
@@ -0,0 +1,99 @@
+//! The hot path allocates nothing per frame: once a `MotionDetector` on the synthetic source is
+//! warmed up, reading, downsampling and comparing frames, still or in a movement, leaves the
+//! allocation count of its thread where it was. Alone in its binary, so the counting allocator
+//! counts nothing else.
+
+mod common;
+
+use std::{
+    alloc::{ GlobalAlloc, Layout, System },
+    cell::Cell,
+};
+
+use common::{ Scene, Shape, HEIGHT, WIDTH };
+use motion_detect::{ detector::MotionDetector, thumbnail::PixelLayout };
+
+
+/// The system allocator, counting the allocations of the threads that ask for it.
+struct Counting;
+
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+
+unsafe impl GlobalAlloc for Counting {
+
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+
+fn count() {
+    // Ignored once the thread's locals are gone, at its exit.
+    let _ = COUNTING.try_with(|counting| {
+        if counting.get() {
+            let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        }
+    });
+}
+
+
+// The allocations `run` makes on this thread.
+fn allocations(mut run: impl FnMut()) -> u64 {
+    ALLOCATIONS.with(|allocations| allocations.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    run();
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATIONS.with(Cell::get)
+}
+
+
+// `frames` more frames, none of them telling an event.
+fn frames<'a>(detector: &'a mut MotionDetector, scene: &'a mut Scene, frames: u64) -> impl FnMut() + 'a {
+    move || for _ in 0 .. frames {
+        let events = detector.next_events(scene).unwrap();
+        assert!(events.is_empty(), "{events:?}");
+    }
+}
+
+
+#[test]
+fn frames_after_the_warm_up_allocate_nothing() {
+    // Still until frame 40, then a bar sweeping until the end, the movement started by frame 50.
+    let mut scene = Scene::new(200, Shape::sweep(40 .. 200));
+    let mut detector = MotionDetector::new(&common::settings(), WIDTH, HEIGHT, PixelLayout::Rgb);
+    // What the counting would see.
+    assert_eq!(allocations(|| drop(std::hint::black_box(vec![0u8; WIDTH]))), 1);
+    for _ in 0 .. 20 {
+        detector.next_events(&mut scene).unwrap();
+    }
+    assert_eq!(allocations(frames(&mut detector, &mut scene, 20)), 0, "still frames");
+    for _ in 0 .. 10 {
+        detector.next_events(&mut scene).unwrap();
+    }
+    assert!(detector.is_moving());
+    assert_eq!(allocations(frames(&mut detector, &mut scene, 100)), 0, "frames of a movement");
+}
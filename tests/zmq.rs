@@ -0,0 +1,137 @@
+//! The ZeroMQ publisher over ipc, against a SUB peer written out here byte by byte, so the
+//! greeting and the framing are checked against ZMTP 3.0 rather than against our own `Subscriber`.
+
+#![cfg(all(feature = "zmq", unix))]
+
+use std::{
+    env, fs,
+    io::{ Read, Write },
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process,
+    sync::{ Arc, atomic::{ AtomicBool, Ordering } },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use motion_detect::zmq::{ Publisher, Subscriber };
+
+
+fn socket(test: &str) -> PathBuf {
+    env::temp_dir().join(format!("motion-detect-zmq-{test}-{}.sock", process::id()))
+}
+
+
+// A frame's flags and body, short frames only, as everything here is.
+fn read_frame(stream: &mut UnixStream) -> (u8, Vec<u8>) {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(head[0] & 0x02, 0, "a short frame");
+    let mut body = vec![0; head[1] as usize];
+    stream.read_exact(&mut body).unwrap();
+    (head[0], body)
+}
+
+
+fn write_frame(stream: &mut UnixStream, flags: u8, body: &[u8]) {
+    stream.write_all(&[flags, body.len() as u8]).unwrap();
+    stream.write_all(body).unwrap();
+}
+
+
+// Publishes until a message arrives, the subscription reaching the publisher after the handshake.
+fn publish_until_received(publisher: &Publisher, stream: &mut UnixStream, messages: &[&str]) -> (u8, Vec<u8>) {
+    stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        assert!(Instant::now() < deadline, "Nothing was received");
+        for message in messages {
+            publisher.publish(message);
+        }
+        let mut flags = [0u8];
+        if stream.read_exact(&mut flags).is_ok() {
+            stream.set_read_timeout(None).unwrap();
+            let mut size = [0u8];
+            stream.read_exact(&mut size).unwrap();
+            let mut body = vec![0; size[0] as usize];
+            stream.read_exact(&mut body).unwrap();
+            return (flags[0], body);
+        }
+    }
+}
+
+
+#[test]
+fn a_hand_written_sub_peer_gets_the_topic_then_the_payload() {
+    let path = socket("handshake");
+    let publisher = Publisher::bind(&format!("ipc://{}", path.display()), "front door", 16).unwrap();
+    let mut stream = UnixStream::connect(&path).unwrap();
+
+    // The publisher's greeting: the signature, version 3.0, the NULL mechanism, not a server.
+    let mut greeting = [0u8; 64];
+    stream.read_exact(&mut greeting).unwrap();
+    assert_eq!(greeting[.. 10], [0xff, 0, 0, 0, 0, 0, 0, 0, 1, 0x7f]);
+    assert_eq!(greeting[10 .. 12], [3, 0]);
+    assert_eq!(&greeting[12 .. 32], b"NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+    assert_eq!(greeting[32], 0);
+    assert!(greeting[33 ..].iter().all(|byte| *byte == 0));
+    let mut ours = [0u8; 64];
+    ours[.. 10].copy_from_slice(&[0xff, 0, 0, 0, 0, 0, 0, 0, 1, 0x7f]);
+    ours[10 .. 12].copy_from_slice(&[3, 0]);
+    ours[12 .. 16].copy_from_slice(b"NULL");
+    stream.write_all(&ours).unwrap();
+
+    // Its READY command names a PUB socket.
+    let (flags, ready) = read_frame(&mut stream);
+    assert_eq!(flags, 0x04);
+    assert_eq!(ready, b"\x05READY\x0bSocket-Type\0\0\0\x03PUB");
+    write_frame(&mut stream, 0x04, b"\x05READY\x0bSocket-Type\0\0\0\x03SUB");
+
+    // A ZMTP 3.0 subscription is a message starting with 1, to the start events only.
+    write_frame(&mut stream, 0, b"\x01motion.front-door.start");
+    let messages = ["{\"type\":\"stop\",\"id\":1}", "{\"type\":\"start\",\"id\":2}"];
+    let (flags, topic) = publish_until_received(&publisher, &mut stream, &messages);
+    assert_eq!((flags, topic.as_slice()), (0x01, b"motion.front-door.start".as_slice()));
+    let (flags, payload) = read_frame(&mut stream);
+    assert_eq!((flags, payload.as_slice()), (0, messages[1].as_bytes()));
+    drop(stream);
+    let _ = fs::remove_file(&path);
+}
+
+
+#[test]
+fn a_peer_of_another_protocol_is_hung_up_on() {
+    let path = socket("refused");
+    let publisher = Publisher::bind(&format!("ipc://{}", path.display()), "garden", 16).unwrap();
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream.write_all(&[b'G'; 64]).unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received.len(), 64, "Only the greeting was sent");
+    assert_eq!(publisher.subscribers(), 0);
+    let _ = fs::remove_file(&path);
+}
+
+
+#[test]
+fn the_subscriber_receives_what_is_published() {
+    let path = socket("subscriber");
+    let endpoint = format!("ipc://{}", path.display());
+    let publisher = Publisher::bind(&endpoint, "garden", 16).unwrap();
+    let mut subscriber = Subscriber::connect(&endpoint).unwrap();
+    subscriber.subscribe("motion.garden.").unwrap();
+    // Published over and over from another thread, until the subscription has reached the publisher.
+    let received = Arc::new(AtomicBool::new(false));
+    let publishing = thread::spawn({
+        let received = received.clone();
+        move || while !received.load(Ordering::Relaxed) {
+            publisher.publish("{\"type\":\"heartbeat\",\"frames\":1}");
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+    let message = subscriber.receive().unwrap();
+    received.store(true, Ordering::Relaxed);
+    publishing.join().unwrap();
+    assert_eq!(message, ("motion.garden.heartbeat".to_string(), "{\"type\":\"heartbeat\",\"frames\":1}".to_string()));
+    let _ = fs::remove_file(&path);
+}